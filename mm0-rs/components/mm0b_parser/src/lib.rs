@@ -90,6 +90,8 @@ pub mod cmd {
 
   /// `STMT_AXIOM = 0x02`, starts an `axiom` declaration
   pub const STMT_AXIOM: u8 = 0x02;
+  /// `STMT_INPUT_STRING = 0x03`, starts an `input string` statement
+  pub const STMT_INPUT_STRING: u8 = 0x03;
   /// `STMT_SORT = 0x04`, starts a `sort` declaration
   pub const STMT_SORT: u8 = 0x04;
   /// `STMT_TERM = 0x05`, starts a `term` declaration
@@ -203,6 +205,9 @@ pub enum StmtCmd {
     /// Is this not `pub theorem`?
     local: bool,
   },
+  /// An input statement. Equivalent to `input string: ...`. This is followed by a
+  /// proof sequence that constructs the expressions whose concatenation should equal the input.
+  InputString,
 }
 
 // IMO breaking this out is preferred to making the id fields Option<A> in StmtCmd
@@ -245,6 +250,9 @@ pub enum NumdStmtCmd {
     /// Is this not `pub theorem`?
     local: bool,
   },
+  /// An input statement. Equivalent to `input string: ...`. This is followed by a
+  /// proof sequence that constructs the expressions whose concatenation should equal the input.
+  InputString,
 }
 
 impl StmtCmd {
@@ -252,7 +260,7 @@ impl StmtCmd {
   #[must_use]
   pub fn is_local(self) -> bool {
     match self {
-      Self::Sort | Self::Axiom => false,
+      Self::Sort | Self::Axiom | Self::InputString => false,
       Self::TermDef { local } | Self::Thm { local } => local,
     }
   }
@@ -263,7 +271,7 @@ impl NumdStmtCmd {
   #[must_use]
   pub fn is_local(self) -> bool {
     match self {
-      Self::Sort { .. } | Self::Axiom { .. } => false,
+      Self::Sort { .. } | Self::Axiom { .. } | Self::InputString => false,
       Self::TermDef { local, .. } | Self::Thm { local, .. } => local,
     }
   }
@@ -279,6 +287,7 @@ impl TryFrom<u8> for StmtCmd {
       cmd::STMT_LOCAL_DEF => StmtCmd::TermDef { local: true },
       cmd::STMT_THM => StmtCmd::Thm { local: false },
       cmd::STMT_LOCAL_THM => StmtCmd::Thm { local: true },
+      cmd::STMT_INPUT_STRING => StmtCmd::InputString,
       _ => return Err(ParseError::StmtCmdConv(cmd)),
     })
   }
@@ -837,11 +837,12 @@ impl<'a, X: HasSymbolNames<'a>> MmbFile<'a, X> {
   /// the [`StmtCmd`] every time.
   #[must_use]
   pub fn stmt_index(&self, stmt: NumdStmtCmd) -> Option<NameEntryRef<'a>> {
-    use crate::NumdStmtCmd::{Axiom, Sort, TermDef, Thm};
+    use crate::NumdStmtCmd::{Axiom, InputString, Sort, TermDef, Thm};
     match stmt {
       Sort { sort_id } => self.sort_index(sort_id),
       Axiom { thm_id } | Thm { thm_id, .. } => self.thm_index(thm_id),
       TermDef { term_id, .. } => self.term_index(term_id),
+      InputString => None,
     }
   }
 
@@ -995,9 +996,9 @@ impl<'a, X: HasVarNames<'a>> MmbFile<'a, X> {
   /// the [`StmtCmd`] every time.
   #[must_use]
   pub fn stmt_vars(&self, stmt: NumdStmtCmd) -> VarListRef<'a> {
-    use crate::NumdStmtCmd::{Axiom, Sort, TermDef, Thm};
+    use crate::NumdStmtCmd::{Axiom, InputString, Sort, TermDef, Thm};
     match stmt {
-      Sort { .. } => VarListRef::new(self.buf),
+      Sort { .. } | InputString => VarListRef::new(self.buf),
       Axiom { thm_id } | Thm { thm_id, .. } => self.thm_vars(thm_id),
       TermDef { term_id, .. } => self.term_vars(term_id),
     }
@@ -1022,9 +1023,9 @@ impl<'a, X: HasHypNames<'a>> MmbFile<'a, X> {
   /// the [`StmtCmd`] every time.
   #[must_use]
   pub fn stmt_hyps(&self, stmt: NumdStmtCmd) -> HypListRef<'a> {
-    use crate::NumdStmtCmd::{Axiom, Sort, TermDef, Thm};
+    use crate::NumdStmtCmd::{Axiom, InputString, Sort, TermDef, Thm};
     match stmt {
-      Sort { .. } | TermDef { .. } => HypListRef::new(self.buf),
+      Sort { .. } | TermDef { .. } | InputString => HypListRef::new(self.buf),
       Axiom { thm_id } | Thm { thm_id, .. } => self.thm_hyps(thm_id),
     }
   }
@@ -1164,6 +1165,7 @@ impl<'a> Iterator for DeclIter<'a> {
             self.next_thm_id += 1;
             out
           }
+          StmtCmd::InputString => NumdStmtCmd::InputString,
         };
         Some(Ok((cmd, proof_iter)))
       }
@@ -294,6 +294,13 @@ impl<W: Reopen> Mm0Writer<W> {
     self.add_thm_core(if local { STMT_LOCAL_THM } else { STMT_THM }, name, args)
   }
 
+  /// Begin construction of a new `input string` statement.
+  /// The returned `InputBuilder` contains a reference to the proof stream, where the
+  /// expressions to be matched against the input should be inserted.
+  pub fn add_input_string(&mut self) -> InputBuilder<'_, W> {
+    InputBuilder(StmtBuilder::new(self, STMT_INPUT_STRING))
+  }
+
   /// This function consumes the `Mm0Writer` instance and actually writes the MMB data to the given
   /// writer, given a function `reopen` which reads the data just written to `proof`.
   pub fn finish(self, w: &mut impl Write) -> io::Result<()> {
@@ -452,3 +459,21 @@ impl<'a, W: Reopen> ThmBuilder<'a, W> {
     Ok(self.1)
   }
 }
+
+/// An unfinished `input string` statement. The `proof` stream should be used to write the
+/// expressions whose concatenation is matched against the input.
+#[derive(Debug)]
+#[must_use = "discarding an InputBuilder will result in a corrupted file"]
+pub struct InputBuilder<'a, W>(StmtBuilder<'a, W>);
+
+impl<'a, W: Reopen> InputBuilder<'a, W> {
+  /// A reference to the proof stream for this statement. Use [`ProofCmd::write_to`] to add
+  /// commands to this stream. Do not add an `END` command at the end; [`finish`] will handle that.
+  pub fn proof(&mut self) -> &mut (impl Write + 'a) { self.0.proof() }
+
+  /// Finish the proof stream for this statement.
+  pub fn finish(mut self) -> io::Result<()> {
+    self.proof().write_u8(0)?;
+    self.0.finish()
+  }
+}
@@ -1,4 +1,4 @@
-use mm0b_parser::{BareMmbFile, ParseError};
+use mm0b_parser::{BareMmbFile, Mm0Writer, NumdStmtCmd, ParseError};
 use std::fs::OpenOptions;
 use std::io::Read;
 use std::path::PathBuf;
@@ -28,3 +28,14 @@ fn peano0() {
   assert!(!mmb_bytes.is_empty());
   assert!(BareMmbFile::parse(mmb_bytes.as_slice()).is_ok());
 }
+
+#[test]
+fn input_string_stmt() {
+  let mut w = Mm0Writer::new(vec![]);
+  w.add_input_string().finish().unwrap();
+  let mut mmb_bytes = vec![];
+  w.finish(&mut mmb_bytes).unwrap();
+  let mmb = BareMmbFile::parse(&mmb_bytes).unwrap();
+  let stmts = mmb.proof().map(|r| r.unwrap().0).collect::<Vec<_>>();
+  assert!(matches!(*stmts, [NumdStmtCmd::InputString]));
+}
@@ -0,0 +1,1360 @@
+//! AArch64-specific parts of the compiler.
+//!
+//! This backend targets 64-bit ARM Linux. It uses the same lowering pass as the x86 backend
+//! (see [`Lower`]), with the same operand types, but the instructions selected here are
+//! three-address, so memory operands are loaded into registers first.
+//!
+//! After register allocation, each [`Inst`] is expanded into one or more 4-byte instructions.
+//! Operations that do not fit in a single ARM instruction, such as addressing modes with large
+//! offsets or comparisons of 8 and 16 bit values, use the scratch registers `x16` and `x17`,
+//! which are reserved for this purpose and never allocated.
+//!
+//! Proof generation is only supported for the x86 backend.
+
+use std::collections::HashMap;
+use std::fmt::{Debug, Display};
+
+use arrayvec::ArrayVec;
+use once_cell::sync::Lazy;
+use regalloc2::{MachineEnv, Operand, ProgPoint};
+
+use crate::build_vcode::{LowerErr, VCodeCtx, build_vcode};
+use crate::codegen::InstSink;
+use crate::linker::ConstData;
+use crate::mir_opt::storage::Allocations;
+use crate::regalloc::{ApplyRegalloc, BlockBuilder, PCode, PCodeBuilder, get_clobbers};
+use crate::types::{classify as cl, mir, IdxVec, Size,
+  vcode::{BlockId, ProcId, ProcAbi, InstId, VReg, IsReg, Inst as VInst, VCode}};
+use crate::{Entity, Symbol};
+use super::{AMode, Arch, Binop, CC, Cmp, ExtMode, Flags, Lower, MachInst, Offset, PAMode, PReg,
+  RegMem, RegMemImm, ShiftIndex, ShiftKind, SysCall, Unop};
+
+const X0: PReg = PReg::new(0);
+const X8: PReg = PReg::new(8);
+/// The first scratch register (`ip0`), used in expansions of instructions after register
+/// allocation.
+const X16: PReg = PReg::new(16);
+/// The second scratch register (`ip1`).
+const X17: PReg = PReg::new(17);
+/// The link register.
+const LR: PReg = PReg::new(30);
+/// The stack pointer. Register number 31 denotes either `sp` or the zero register,
+/// depending on the instruction.
+pub(crate) const SP: PReg = PReg::new(31);
+/// The zero register, see [`SP`].
+const ZR: PReg = PReg::new(31);
+
+const fn regs<const N: usize>(start: usize) -> [PReg; N] {
+  let mut out = [X0; N];
+  let mut i = 0;
+  while i < N { out[i] = PReg::new(start + i); i += 1 }
+  out
+}
+
+pub(crate) const RET_AND_ARG_REGS: [PReg; 8] = regs(0);
+pub(crate) const SYSCALL_ARG_REGS: (PReg, [PReg; 6]) = (X8, regs(0));
+pub(crate) const CALLER_SAVED: [PReg; 16] = regs(0);
+pub(crate) const CALLEE_SAVED: [PReg; 10] = regs(19);
+
+pub(crate) fn callee_saved() -> impl DoubleEndedIterator<Item=PReg> + Clone {
+  CALLEE_SAVED.iter().copied()
+}
+pub(crate) fn caller_saved() -> impl DoubleEndedIterator<Item=PReg> + Clone {
+  CALLER_SAVED.iter().copied()
+}
+
+pub(crate) static MACHINE_ENV: Lazy<MachineEnv> = Lazy::new(|| MachineEnv {
+  preferred_regs_by_class: [CALLER_SAVED.map(|r| r.0).into(), vec![]],
+  non_preferred_regs_by_class: [CALLEE_SAVED.map(|r| r.0).into(), vec![]],
+  fixed_stack_slots: vec![],
+});
+
+/// The `AT_FDCWD` argument to `openat`, which makes it behave like `open`.
+const AT_FDCWD: u64 = (-100_i64).cast_unsigned();
+
+/// Prints a register as `xN` or `wN` depending on the size, with register 31 printed as `sp`.
+struct R(PReg, Size);
+
+impl Display for R {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match (self.0.index(), self.1) {
+      (31, Size::S64) => write!(f, "sp"),
+      (31, _) => write!(f, "wsp"),
+      (n, Size::S64) => write!(f, "x{n}"),
+      (n, _) => write!(f, "w{n}"),
+    }
+  }
+}
+
+/// Prints a register as `xN` or `wN` depending on the size, with register 31 printed as `zr`.
+struct Z(PReg, Size);
+
+impl Display for Z {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match (self.0.index(), self.1) {
+      (31, Size::S64) => write!(f, "xzr"),
+      (31, _) => write!(f, "wzr"),
+      _ => R(self.0, self.1).fmt(f),
+    }
+  }
+}
+
+/// The size of the register used for an operation of size `sz`:
+/// operations on 8, 16 and 32 bit values all use 32 bit registers.
+fn reg_size(sz: Size) -> Size { if sz == Size::S64 { Size::S64 } else { Size::S32 } }
+
+/// The ARM condition code corresponding to an x86 condition code. Because ARM sets the carry
+/// flag on subtraction when there is *no* borrow, the unsigned comparisons are swapped.
+fn cond(cc: CC) -> u32 {
+  match cc {
+    CC::Z => 0,   // eq
+    CC::NZ => 1,  // ne
+    CC::NB => 2,  // hs
+    CC::B => 3,   // lo
+    CC::S => 4,   // mi
+    CC::NS => 5,  // pl
+    CC::O => 6,   // vs
+    CC::NO => 7,  // vc
+    CC::NBE => 8, // hi
+    CC::BE => 9,  // ls
+    CC::NL => 10, // ge
+    CC::L => 11,  // lt
+    CC::NLE => 12, // gt
+    CC::LE => 13, // le
+  }
+}
+
+/// An ARM64 instruction, before register allocation.
+#[derive(Debug)]
+pub(crate) enum Inst {
+  /// A pseudo-instruction that falls through to the next block, which must be `dst`.
+  Fallthrough { dst: BlockId },
+  /// A pseudo-instruction declaring the location of a block parameter.
+  BlockParam { var: mir::VarId, val: RegMem },
+  /// `dst <- (add|sub|and|orr|eor).sz src1, src2`
+  AluRRR { sz: Size, op: Binop, dst: VReg, src1: VReg, src2: VReg },
+  /// `dst <- (add|sub).sz src, #imm` for `imm < 4096`
+  AluRRImm { sz: Size, op: Binop, dst: VReg, src: VReg, imm: u16 },
+  /// `dst <- (mvn|neg).sz src`
+  Unop { sz: Size, op: Unop, dst: VReg, src: VReg },
+  /// `dst <- mul.sz src1, src2`
+  Mul { sz: Size, dst: VReg, src1: VReg, src2: VReg },
+  /// `dst <- imm.sz src`, using a sequence of `movz` and `movk`.
+  Imm { sz: Size, dst: VReg, src: u64 },
+  /// `dst <- mov src`
+  MovRR { dst: VReg, src: VReg },
+  /// `dst <- mov src`, where `src` is a physical register.
+  MovPR { dst: VReg, src: PReg },
+  /// `dst <- (s|u)xt src`
+  Extend { signed: bool, ext_mode: ExtMode, dst: VReg, src: VReg },
+  /// `dst <- ldr.sz [addr]`, zero extending to 64 bits.
+  Load { sz: Size, dst: VReg, addr: AMode },
+  /// `[addr] <- str.sz src`
+  Store { sz: Size, addr: AMode, src: VReg },
+  /// `dst <- &addr`
+  Lea { dst: VReg, addr: AMode },
+  /// `dst <- (lsl|lsr|asr).sz src, #num_bits`
+  ShiftImm { sz: Size, kind: ShiftKind, dst: VReg, src: VReg, num_bits: u8 },
+  /// `dst <- (lsl|lsr|asr).sz src, src2`
+  ShiftRR { sz: Size, kind: ShiftKind, dst: VReg, src: VReg, src2: VReg },
+  /// `cmp.sz src1, src2`
+  Cmp { sz: Size, src1: VReg, src2: VReg },
+  /// `cmp.sz src, #imm` for `imm < 4096`. For 8 and 16 bit comparisons, `imm` must be 0.
+  CmpImm { sz: Size, src: VReg, imm: u16 },
+  /// `dst <- cset cc`
+  CSet { cc: CC, dst: VReg },
+  /// `dst <- csel.sz cc, tru, fal`
+  CSel { sz: Size, cc: CC, dst: VReg, tru: VReg, fal: VReg },
+  /// `bl f`. If `clobbers` is `None` then the function does not return.
+  CallKnown { f: ProcId, operands: Box<[Operand]>, clobbers: Option<Box<[PReg]>> },
+  /// `svc #0`
+  SysCall { f: SysCall, operands: Box<[Operand]> },
+  /// The function epilogue and `ret`.
+  Epilogue { params: Box<[Operand]> },
+  /// `b dst`, passing the block parameters `params`.
+  JmpKnown { dst: BlockId, params: Box<[regalloc2::VReg]> },
+  /// `b.cc taken; b not_taken`
+  JmpCond { cc: CC, taken: BlockId, not_taken: BlockId },
+  /// Assert that `cc` holds, and fall through to `dst`.
+  Assert { cc: CC, dst: BlockId },
+  /// `udf #0`
+  Udf,
+}
+
+impl AMode {
+  fn collect_operands_arm(&self, args: &mut Vec<Operand>) {
+    if self.base.is_valid() { args.push(Operand::reg_use(self.base.0)) }
+    if let Some(si) = &self.si { args.push(Operand::reg_use(si.index.0)) }
+  }
+}
+
+impl VInst for Inst {
+  fn is_call(&self) -> bool {
+    matches!(self, Inst::CallKnown {..} | Inst::SysCall {..})
+  }
+
+  fn is_ret(&self) -> bool {
+    matches!(self, Inst::Epilogue {..} | Inst::SysCall { f: SysCall::Exit, .. })
+  }
+
+  fn is_branch(&self) -> bool {
+    matches!(self, Inst::JmpCond {..} | Inst::JmpKnown {..})
+  }
+
+  fn branch_blockparams(&self, _: usize) -> &[regalloc2::VReg] {
+    match self {
+      Inst::JmpKnown { params, .. } => params,
+      _ => &[]
+    }
+  }
+
+  fn is_move(&self) -> Option<(Operand, Operand)> {
+    if let Inst::MovRR { dst, src } = *self {
+      Some((Operand::reg_use(src.0), Operand::reg_def(dst.0)))
+    } else { None }
+  }
+
+  fn collect_operands(&self, args: &mut Vec<Operand>) {
+    match *self {
+      Inst::BlockParam { ref val, .. } => match val {
+        RegMem::Reg(r) => args.push(Operand::reg_use(r.0)),
+        RegMem::Mem(a) => a.collect_operands_arm(args),
+      },
+      Inst::Imm { dst, .. } |
+      Inst::CSet { dst, .. } => args.push(Operand::reg_def(dst.0)),
+      Inst::AluRRImm { dst, src, .. } |
+      Inst::Unop { dst, src, .. } |
+      Inst::Extend { dst, src, .. } |
+      Inst::ShiftImm { dst, src, .. } => {
+        args.push(Operand::reg_use(src.0));
+        args.push(Operand::reg_def(dst.0));
+      }
+      Inst::AluRRR { dst, src1, src2, .. } |
+      Inst::Mul { dst, src1, src2, .. } |
+      Inst::ShiftRR { dst, src: src1, src2, .. } |
+      Inst::CSel { dst, tru: src1, fal: src2, .. } => {
+        args.push(Operand::reg_use(src1.0));
+        args.push(Operand::reg_use(src2.0));
+        args.push(Operand::reg_def(dst.0));
+      }
+      Inst::MovPR { dst, src } => args.push(Operand::reg_fixed_def(dst.0, src.0)),
+      Inst::Load { dst, ref addr, .. } |
+      Inst::Lea { dst, ref addr } => {
+        addr.collect_operands_arm(args);
+        args.push(Operand::reg_def(dst.0));
+      }
+      Inst::Store { ref addr, src, .. } => {
+        args.push(Operand::reg_use(src.0));
+        addr.collect_operands_arm(args);
+      }
+      Inst::Cmp { src1, src2, .. } => {
+        args.push(Operand::reg_use(src1.0));
+        args.push(Operand::reg_use(src2.0));
+      }
+      Inst::CmpImm { src, .. } => args.push(Operand::reg_use(src.0)),
+      Inst::CallKnown { operands: ref params, .. } |
+      Inst::SysCall { operands: ref params, .. } |
+      Inst::Epilogue { ref params } => args.extend_from_slice(params),
+      // moves are handled specially by regalloc, we don't need operands
+      Inst::MovRR { .. } |
+      // Jumps have blockparams but no operands
+      Inst::JmpKnown { .. } |
+      // Other instructions that have no operands
+      Inst::Fallthrough { .. } |
+      Inst::JmpCond { .. } |
+      Inst::Assert { .. } |
+      Inst::Udf => {}
+    }
+  }
+
+  fn clobbers(&self) -> &[PReg] {
+    match self {
+      Inst::CallKnown { clobbers: Some(cl), .. } => cl,
+      _ => &[],
+    }
+  }
+}
+
+impl VCode<Inst> {
+  fn emit_reg(&mut self, sz: Size, src: RegMemImm) -> VReg {
+    match src {
+      RegMemImm::Reg(r) => r,
+      RegMemImm::Mem(a) => self.emit_load(sz, a),
+      RegMemImm::Imm(i) => self.emit_imm(sz, i),
+    }
+  }
+}
+
+impl Lower for VCode<Inst> {
+  const ARG_REGS: &'static [PReg] = &RET_AND_ARG_REGS;
+  const SYSCALL_REGS: (PReg, PReg, &'static [PReg]) = (X8, X0, &SYSCALL_ARG_REGS.1);
+
+  fn syscall_args(f: SysCall, args: &mut ArrayVec<(RegMemImm<u64>, cl::Operand), 6>) -> u32 {
+    const CV: cl::Operand = cl::Operand::Const(cl::Const::Value);
+    match f {
+      SysCall::Open => { args.insert(0, (AT_FDCWD.into(), CV)); 56 } // openat
+      SysCall::Read => 63,
+      SysCall::Write => 64,
+      SysCall::Close => 57,
+      SysCall::FStat => 80,
+      SysCall::LSeek => 62,
+      SysCall::MMap => 222,
+      SysCall::MUnmap => 215,
+      SysCall::Brk => 214,
+      SysCall::Pipe => { args.push((0.into(), CV)); 59 } // pipe2
+      // Note: unlike dup2, this fails if the two file descriptors are equal
+      SysCall::Dup2 => { args.push((0.into(), CV)); 24 } // dup3
+      SysCall::Exit => 93,
+      SysCall::ClockGetTime => 113,
+      SysCall::GetRandom => 278,
+    }
+  }
+
+  fn emit_block_param(&mut self, var: mir::VarId, val: RegMem) {
+    self.emit(Inst::BlockParam { var, val });
+  }
+
+  fn emit_mov(&mut self, dst: VReg, src: VReg) { self.emit(Inst::MovRR { dst, src }); }
+
+  fn emit_mov_preg(&mut self, dst: VReg, src: PReg) { self.emit(Inst::MovPR { dst, src }); }
+
+  fn emit_imm(&mut self, sz: Size, src: impl Into<u64>) -> VReg {
+    let dst = self.fresh_vreg();
+    self.emit(Inst::Imm { sz, dst, src: src.into() });
+    dst
+  }
+
+  fn emit_load(&mut self, sz: Size, addr: AMode) -> VReg {
+    let dst = self.fresh_vreg();
+    self.emit(Inst::Load { sz, dst, addr });
+    dst
+  }
+
+  fn emit_lea(&mut self, _: Size, addr: AMode) -> VReg {
+    let dst = self.fresh_vreg();
+    self.emit(Inst::Lea { dst, addr });
+    dst
+  }
+
+  fn emit_extend(&mut self, signed: bool, ext_mode: ExtMode, src: RegMem) -> VReg {
+    let src = match src {
+      RegMem::Reg(r) => r,
+      RegMem::Mem(a) => {
+        let r = self.emit_load(ext_mode.src(), a);
+        // loads are already zero-extending
+        if !signed { return r }
+        r
+      }
+    };
+    let dst = self.fresh_vreg();
+    self.emit(Inst::Extend { signed, ext_mode, dst, src });
+    dst
+  }
+
+  fn emit_binop(&mut self, sz: Size, op: Binop, src1: VReg, src2: impl Into<RegMemImm>) -> VReg {
+    assert!(!matches!(op, Binop::Adc | Binop::Sbb), "unsupported binop {op}");
+    let dst = self.fresh_vreg();
+    match src2.into() {
+      RegMemImm::Imm(i) if matches!(op, Binop::Add | Binop::Sub) && i < 4096 => {
+        #[allow(clippy::cast_possible_truncation)]
+        self.emit(Inst::AluRRImm { sz, op, dst, src: src1, imm: i as u16 });
+      }
+      src2 => {
+        let src2 = self.emit_reg(sz, src2);
+        self.emit(Inst::AluRRR { sz, op, dst, src1, src2 });
+      }
+    }
+    dst
+  }
+
+  fn emit_unop(&mut self, sz: Size, op: Unop, src: VReg) -> VReg {
+    match op {
+      Unop::Inc => self.emit_binop(sz, Binop::Add, src, 1),
+      Unop::Dec => self.emit_binop(sz, Binop::Sub, src, 1),
+      Unop::Not | Unop::Neg => {
+        let dst = self.fresh_vreg();
+        self.emit(Inst::Unop { sz, op, dst, src });
+        dst
+      }
+    }
+  }
+
+  fn emit_mul(&mut self, sz: Size, src1: VReg, src2: impl Into<RegMem>) -> VReg {
+    let src2 = self.emit_reg(sz, src2.into().into());
+    let dst = self.fresh_vreg();
+    self.emit(Inst::Mul { sz, dst, src1, src2 });
+    dst
+  }
+
+  fn emit_shift(&mut self, sz: Size, kind: ShiftKind, src1: VReg, src2: Result<u8, VReg>) -> VReg {
+    let dst = self.fresh_vreg();
+    self.emit(match src2 {
+      Ok(num_bits) => Inst::ShiftImm { sz, kind, dst, src: src1, num_bits },
+      Err(src2) => Inst::ShiftRR { sz, kind, dst, src: src1, src2 },
+    });
+    dst
+  }
+
+  fn emit_cmp(&mut self, sz: Size, op: Cmp, cc: CC, src1: VReg, src2: impl Into<RegMemImm>
+  ) -> Flags<'_, Self> {
+    let (src1, src2) = match op {
+      Cmp::Cmp => (src1, src2.into()),
+      // `tst` sets the carry flag differently from x86 `test`, so we use `and` and `cmp` instead
+      Cmp::Test => (self.emit_binop(sz, Binop::And, src1, src2), RegMemImm::Imm(0)),
+    };
+    match src2 {
+      RegMemImm::Imm(i) if i < 4096 && (matches!(sz, Size::S32 | Size::S64) || i == 0) => {
+        #[allow(clippy::cast_possible_truncation)]
+        self.emit(Inst::CmpImm { sz, src: src1, imm: i as u16 });
+      }
+      src2 => {
+        let src2 = self.emit_reg(sz, src2);
+        self.emit(Inst::Cmp { sz, src1, src2 });
+      }
+    }
+    Flags::new(self, cc)
+  }
+
+  fn emit_copy(&mut self, sz: Size, dst: RegMem, src: impl Into<RegMemImm<u64>>) -> cl::Copy {
+    fn copy(code: &mut VCode<Inst>, sz: Size, dst: RegMem, src: RegMemImm<u64>) -> cl::Copy {
+      match (dst, src) {
+        (RegMem::Reg(dst), RegMemImm::Reg(src)) => code.emit(Inst::MovRR { dst, src }),
+        (RegMem::Reg(dst), RegMemImm::Mem(addr)) => code.emit(Inst::Load { sz, dst, addr }),
+        (RegMem::Reg(dst), RegMemImm::Imm(src)) => code.emit(Inst::Imm { sz, dst, src }),
+        (RegMem::Mem(addr), RegMemImm::Reg(src)) => code.emit(Inst::Store { sz, addr, src }),
+        _ => {
+          let temp = code.fresh_vreg();
+          copy(code, sz, temp.into(), src);
+          copy(code, sz, dst, temp.into());
+          return cl::Copy::Two
+        }
+      };
+      cl::Copy::One
+    }
+    copy(self, sz, dst, src.into())
+  }
+
+  fn emit_setcc(&mut self, cc: CC) -> VReg {
+    let dst = self.fresh_vreg();
+    self.emit(Inst::CSet { cc, dst });
+    dst
+  }
+
+  fn emit_cmov(&mut self, sz: Size, cc: CC, tru: RegMem, fal: VReg) -> VReg {
+    let tru = self.emit_reg(sz, tru.into());
+    let dst = self.fresh_vreg();
+    self.emit(Inst::CSel { sz, cc, dst, tru, fal });
+    dst
+  }
+
+  fn emit_assert(&mut self, cc: CC, dst: BlockId) -> InstId {
+    self.emit(Inst::Assert { cc, dst })
+  }
+
+  fn emit_branch(&mut self, cc: CC, tru: BlockId, fal: BlockId) -> InstId {
+    self.emit(Inst::JmpCond { cc, taken: tru, not_taken: fal })
+  }
+
+  fn emit_jump(&mut self, dst: BlockId, params: Box<[regalloc2::VReg]>) -> InstId {
+    self.emit(Inst::JmpKnown { dst, params })
+  }
+
+  fn emit_fallthrough(&mut self, dst: BlockId) -> InstId {
+    self.emit(Inst::Fallthrough { dst })
+  }
+
+  fn emit_call(&mut self, f: ProcId, operands: Box<[Operand]>, clobbers: Option<Box<[PReg]>>) {
+    self.emit(Inst::CallKnown { f, operands, clobbers });
+  }
+
+  fn emit_syscall(&mut self, f: SysCall, operands: Box<[Operand]>) {
+    self.emit(Inst::SysCall { f, operands });
+  }
+
+  fn emit_epilogue(&mut self, params: Box<[Operand]>) { self.emit(Inst::Epilogue { params }); }
+
+  fn emit_trap(&mut self) { self.emit(Inst::Udf); }
+
+  fn patch_branch(&mut self, from: BlockId, inst: InstId) {
+    macro_rules! patch {($dst:expr) => {{ *$dst = self.block_map[&mir::BlockId($dst.0)]; *$dst }}}
+    match &mut self.insts[inst] {
+      Inst::Fallthrough { dst } |
+      Inst::Assert { dst, .. } |
+      Inst::JmpKnown { dst, .. } => {
+        let dst = patch!(dst);
+        self.add_edge(from, dst)
+      }
+      Inst::JmpCond { taken, not_taken, .. } => {
+        let (bl1, bl2) = (patch!(taken), patch!(not_taken));
+        self.add_edge(from, bl1);
+        self.add_edge(from, bl2);
+      }
+      _ => unreachable!(),
+    }
+  }
+}
+
+/// An ARM64 instruction, after register allocation. Except for the pseudo-instructions
+/// (`Fallthrough`, `MovId`, `Assert` and `StackFar`), these correspond to single 4-byte
+/// instructions.
+///
+/// Register number 31 is interpreted as either `sp` or the zero register, according to the
+/// ARM encoding of the instruction; this is noted where `sp` is permitted.
+#[derive(Copy, Clone)]
+pub enum PInst {
+  /// Jump to the given block ID. This is required to be the immediately following instruction,
+  /// so no code need be emitted.
+  Fallthrough {
+    /// The target block.
+    dst: BlockId,
+  },
+  /// An eliminated identity move instruction.
+  MovId,
+  /// `dst <- (add|sub|and|orr|eor).sz src1, src2 lsl #shift` (shifted register form)
+  AluRRR {
+    /// The operation.
+    op: Binop,
+    /// The operation size (8, 16 and 32 bit operations use 32 bit registers).
+    sz: Size,
+    /// The destination register.
+    dst: PReg,
+    /// The first source register (31 is the zero register).
+    src1: PReg,
+    /// The second source register, which is shifted left by `shift` bits.
+    src2: PReg,
+    /// The shift amount.
+    shift: u8,
+  },
+  /// `dst <- (add|sub).sz src, #imm` (`dst` and `src` may be `sp`)
+  AluRRImm {
+    /// The operation.
+    op: Binop,
+    /// The operation size.
+    sz: Size,
+    /// The destination register.
+    dst: PReg,
+    /// The source register.
+    src: PReg,
+    /// The immediate, less than 4096.
+    imm: u16,
+  },
+  /// `dst <- add.64 src1, src2, uxtx #shift` (extended register form, `dst` and `src1` may be
+  /// `sp`)
+  AddExt {
+    /// The destination register.
+    dst: PReg,
+    /// The first source register.
+    src1: PReg,
+    /// The second source register, which is shifted left by `shift` bits.
+    src2: PReg,
+    /// The shift amount, at most 4.
+    shift: u8,
+  },
+  /// `dst <- (mvn|neg).sz src`
+  Unop {
+    /// The operation, `Not` or `Neg`.
+    op: Unop,
+    /// The operation size.
+    sz: Size,
+    /// The destination register.
+    dst: PReg,
+    /// The source register.
+    src: PReg,
+  },
+  /// `dst <- mul.sz src1, src2`
+  Mul {
+    /// The operation size.
+    sz: Size,
+    /// The destination register.
+    dst: PReg,
+    /// The first source register.
+    src1: PReg,
+    /// The second source register.
+    src2: PReg,
+  },
+  /// `dst <- movz.sz #imm, lsl #(16 * hw)` (if `keep` is false) or
+  /// `dst <- movk.sz #imm, lsl #(16 * hw)` (if `keep` is true)
+  MovImm {
+    /// The operation size.
+    sz: Size,
+    /// If true, this is `movk`, which keeps the other bits of `dst`.
+    keep: bool,
+    /// The destination register.
+    dst: PReg,
+    /// The 16 bit immediate.
+    imm: u16,
+    /// Which 16 bit chunk of `dst` is written.
+    hw: u8,
+  },
+  /// `dst <- movz.64 #lo16(addr)` (if `hi` is false) or
+  /// `dst <- movk.64 #hi16(addr), lsl #16` (if `hi` is true),
+  /// where `addr` is the address of a global or constant, determined during code generation.
+  MovAddr {
+    /// The destination register.
+    dst: PReg,
+    /// The global or constant offset.
+    off: Offset,
+    /// True if this is the instruction for the high half of the address.
+    hi: bool,
+  },
+  /// `dst <- mov.sz src`
+  MovRR {
+    /// The operation size.
+    sz: Size,
+    /// The destination register.
+    dst: PReg,
+    /// The source register.
+    src: PReg,
+  },
+  /// `dst <- (ubfm|sbfm).sz src, #immr, #imms`, the bitfield move instructions,
+  /// used for extensions and shifts by an immediate.
+  Bfm {
+    /// True for `sbfm`, false for `ubfm`.
+    signed: bool,
+    /// The operation size.
+    sz: Size,
+    /// The destination register.
+    dst: PReg,
+    /// The source register.
+    src: PReg,
+    /// The rotate amount.
+    immr: u8,
+    /// The index of the most significant bit of the field.
+    imms: u8,
+  },
+  /// `dst <- (lslv|lsrv|asrv).sz src1, src2`
+  ShiftRR {
+    /// The kind of shift.
+    kind: ShiftKind,
+    /// The operation size.
+    sz: Size,
+    /// The destination register.
+    dst: PReg,
+    /// The value to shift.
+    src1: PReg,
+    /// The shift amount.
+    src2: PReg,
+  },
+  /// `dst <- ldr.sz [base, #(off * sz)]`, zero extending (`base` may be `sp`)
+  Load {
+    /// True if this is a load from a register allocator spill slot.
+    spill: bool,
+    /// The size of the load.
+    sz: Size,
+    /// The destination register.
+    dst: PReg,
+    /// The base register.
+    base: PReg,
+    /// The offset, in multiples of the access size.
+    off: u16,
+  },
+  /// `dst <- ldr.sz [base, index]`, zero extending (`base` may be `sp`)
+  LoadRR {
+    /// The size of the load.
+    sz: Size,
+    /// The destination register.
+    dst: PReg,
+    /// The base register.
+    base: PReg,
+    /// The index register.
+    index: PReg,
+  },
+  /// `[base, #(off * sz)] <- str.sz src` (`base` may be `sp`)
+  Store {
+    /// True if this is a store to a register allocator spill slot.
+    spill: bool,
+    /// The size of the store.
+    sz: Size,
+    /// The source register.
+    src: PReg,
+    /// The base register.
+    base: PReg,
+    /// The offset, in multiples of the access size.
+    off: u16,
+  },
+  /// `[base, index] <- str.sz src` (`base` may be `sp`)
+  StoreRR {
+    /// The size of the store.
+    sz: Size,
+    /// The source register.
+    src: PReg,
+    /// The base register.
+    base: PReg,
+    /// The index register.
+    index: PReg,
+  },
+  /// `cmp.sz src1, src2 lsl #shift`
+  Cmp {
+    /// The operation size.
+    sz: Size,
+    /// The first operand.
+    src1: PReg,
+    /// The second operand, which is shifted left by `shift` bits.
+    src2: PReg,
+    /// The shift amount.
+    shift: u8,
+  },
+  /// `cmp.sz src, #imm`
+  CmpImm {
+    /// The operation size.
+    sz: Size,
+    /// The first operand.
+    src: PReg,
+    /// The immediate, less than 4096.
+    imm: u16,
+  },
+  /// `dst <- cset cc`
+  CSet {
+    /// The condition code.
+    cc: CC,
+    /// The destination register.
+    dst: PReg,
+  },
+  /// `dst <- csel.sz cc, tru, fal`
+  CSel {
+    /// The operation size.
+    sz: Size,
+    /// The condition code.
+    cc: CC,
+    /// The destination register.
+    dst: PReg,
+    /// The value if the condition holds.
+    tru: PReg,
+    /// The value if the condition does not hold.
+    fal: PReg,
+  },
+  /// `bl f`
+  CallKnown {
+    /// The function to call.
+    f: ProcId,
+  },
+  /// `svc #0`
+  SysCall,
+  /// `ret`
+  Ret,
+  /// `b dst`
+  JmpKnown {
+    /// The target block.
+    dst: BlockId,
+  },
+  /// `b.cc dst`
+  JmpCond {
+    /// The condition code.
+    cc: CC,
+    /// The target block.
+    dst: BlockId,
+  },
+  /// Assert that the condition holds, and fall through to `dst`: `b.cc +8; udf #0`
+  Assert {
+    /// The condition code.
+    cc: CC,
+    /// The next block.
+    dst: BlockId,
+  },
+  /// A 64 bit load or store at `[sp, #off]`, where `off` is too large for the immediate field:
+  /// `x16 <- movz #lo16(off); x16 <- movk #hi16(off), lsl #16; ldr/str reg, [sp, x16]`
+  StackFar {
+    /// True for a load, false for a store.
+    load: bool,
+    /// True if this is an access to a register allocator spill slot.
+    spill: bool,
+    /// The register which is loaded or stored.
+    reg: PReg,
+    /// The byte offset from `sp`.
+    off: u32,
+  },
+  /// `udf #0`
+  Udf,
+}
+
+impl Debug for PInst {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match *self {
+      Self::Fallthrough { dst } => write!(f, "fallthrough -> vb{}", dst.0),
+      Self::MovId => write!(f, "mov_id"),
+      Self::AluRRR { op, sz, dst, src1, src2, shift } => {
+        let sz = reg_size(sz);
+        let op = match op { Binop::Or => "orr", Binop::Xor => "eor", _ => return write!(f,
+          "{} <- {op:?} {}, {}, lsl #{shift}", R(dst, sz), Z(src1, sz), Z(src2, sz)) };
+        write!(f, "{} <- {op} {}, {}, lsl #{shift}", R(dst, sz), Z(src1, sz), Z(src2, sz))
+      }
+      Self::AluRRImm { op, sz, dst, src, imm } =>
+        write!(f, "{} <- {op:?} {}, #{imm}", R(dst, reg_size(sz)), R(src, reg_size(sz))),
+      Self::AddExt { dst, src1, src2, shift } =>
+        write!(f, "{} <- add {}, {}, uxtx #{shift}", R(dst, Size::S64), R(src1, Size::S64),
+          Z(src2, Size::S64)),
+      Self::Unop { op, sz, dst, src } => {
+        let op = if op == Unop::Not { "mvn" } else { "neg" };
+        write!(f, "{} <- {op} {}", Z(dst, reg_size(sz)), Z(src, reg_size(sz)))
+      }
+      Self::Mul { sz, dst, src1, src2 } => {
+        let sz = reg_size(sz);
+        write!(f, "{} <- mul {}, {}", Z(dst, sz), Z(src1, sz), Z(src2, sz))
+      }
+      Self::MovImm { sz, keep, dst, imm, hw } => {
+        let op = if keep { "movk" } else { "movz" };
+        write!(f, "{} <- {op} #{imm:#x}, lsl #{}", Z(dst, reg_size(sz)), 16 * hw)
+      }
+      Self::MovAddr { dst, off, hi: false } => write!(f, "{} <- movz lo16({off:?})", Z(dst, Size::S64)),
+      Self::MovAddr { dst, off, hi: true } => write!(f, "{} <- movk hi16({off:?})", Z(dst, Size::S64)),
+      Self::MovRR { sz, dst, src } =>
+        write!(f, "{} <- mov {}", Z(dst, reg_size(sz)), Z(src, reg_size(sz))),
+      Self::Bfm { signed, sz, dst, src, immr, imms } => {
+        let op = if signed { "sbfm" } else { "ubfm" };
+        write!(f, "{} <- {op} {}, #{immr}, #{imms}", Z(dst, reg_size(sz)), Z(src, reg_size(sz)))
+      }
+      Self::ShiftRR { kind, sz, dst, src1, src2 } => {
+        let sz = reg_size(sz);
+        write!(f, "{} <- {kind:?} {}, {}", Z(dst, sz), Z(src1, sz), Z(src2, sz))
+      }
+      Self::Load { spill, sz, dst, base, off } => {
+        let off = u32::from(off) * u32::from(sz.bytes().expect("size"));
+        write!(f, "{} <- ldr.{} [{}, #{off}]", Z(dst, reg_size(sz)), sz.bits0(), R(base, Size::S64))?;
+        if spill { write!(f, " (spill)")? }
+        Ok(())
+      }
+      Self::LoadRR { sz, dst, base, index } => write!(f, "{} <- ldr.{} [{}, {}]",
+        Z(dst, reg_size(sz)), sz.bits0(), R(base, Size::S64), Z(index, Size::S64)),
+      Self::Store { spill, sz, src, base, off } => {
+        let off = u32::from(off) * u32::from(sz.bytes().expect("size"));
+        write!(f, "[{}, #{off}] <- str.{} {}", R(base, Size::S64), sz.bits0(), Z(src, reg_size(sz)))?;
+        if spill { write!(f, " (spill)")? }
+        Ok(())
+      }
+      Self::StoreRR { sz, src, base, index } => write!(f, "[{}, {}] <- str.{} {}",
+        R(base, Size::S64), Z(index, Size::S64), sz.bits0(), Z(src, reg_size(sz))),
+      Self::Cmp { sz, src1, src2, shift } => {
+        let sz = reg_size(sz);
+        write!(f, "cmp {}, {}, lsl #{shift}", Z(src1, sz), Z(src2, sz))
+      }
+      Self::CmpImm { sz, src, imm } => write!(f, "cmp {}, #{imm}", R(src, reg_size(sz))),
+      Self::CSet { cc, dst } => write!(f, "{} <- set{cc}", Z(dst, Size::S32)),
+      Self::CSel { sz, cc, dst, tru, fal } => {
+        let sz = reg_size(sz);
+        write!(f, "{} <- sel{cc} {}, {}", Z(dst, sz), Z(tru, sz), Z(fal, sz))
+      }
+      Self::CallKnown { f: func } => write!(f, "bl {func:?}"),
+      Self::SysCall => write!(f, "svc"),
+      Self::Ret => write!(f, "ret"),
+      Self::JmpKnown { dst } => write!(f, "b -> vb{}", dst.0),
+      Self::JmpCond { cc, dst } => write!(f, "b{cc} -> vb{}", dst.0),
+      Self::Assert { cc, dst } => write!(f, "assert{cc} -> vb{}", dst.0),
+      Self::StackFar { load, spill, reg, off } => {
+        if load {
+          write!(f, "{} <- ldr.64 [sp, #{off}] (far)", Z(reg, Size::S64))?
+        } else {
+          write!(f, "[sp, #{off}] <- str.64 {} (far)", Z(reg, Size::S64))?
+        }
+        if spill { write!(f, " (spill)")? }
+        Ok(())
+      }
+      Self::Udf => write!(f, "udf"),
+    }
+  }
+}
+
+/// The offset part of a memory operand: an unsigned immediate scaled by the access size,
+/// or an index register.
+#[derive(Clone, Copy)]
+enum MemOff {
+  Imm(u16),
+  Reg(PReg),
+}
+
+impl PCodeBuilder<PInst> {
+  /// Push a sequence of `movz`/`movk` instructions setting `dst` to `val`.
+  fn push_imm(&mut self, sz: Size, dst: PReg, val: u64) {
+    let sz = reg_size(sz);
+    let val = if sz == Size::S64 { val } else { val & u64::from(u32::MAX) };
+    if val == 0 { self.push(PInst::MovImm { sz, keep: false, dst, imm: 0, hw: 0 }); return }
+    let chunks = if sz == Size::S64 { 4 } else { 2 };
+    let mut keep = false;
+    for hw in 0..chunks {
+      #[allow(clippy::cast_possible_truncation)]
+      let imm = (val >> (16 * hw)) as u16;
+      if imm != 0 {
+        self.push(PInst::MovImm { sz, keep, dst, imm, hw });
+        keep = true;
+      }
+    }
+  }
+
+  /// Lower an address to a base register and an offset that can be used by a load or store of
+  /// size `sz`, using the scratch registers for intermediate computations.
+  #[allow(clippy::integer_division)]
+  fn push_addr(&mut self, a: &PAMode, sz: Size) -> (PReg, MemOff) {
+    let bytes = u32::from(sz.bytes().expect("unbounded"));
+    let (mut base, n) = match a.off {
+      Offset::Real(n) => (a.base, n),
+      Offset::Spill(..) => unreachable!("removed by regalloc"),
+      off @ (Offset::Global(..) | Offset::Const(_)) => {
+        self.push(PInst::MovAddr { dst: X16, off, hi: false });
+        self.push(PInst::MovAddr { dst: X16, off, hi: true });
+        if a.base.is_valid() {
+          self.push(PInst::AddExt { dst: X16, src1: X16, src2: a.base, shift: 0 });
+        }
+        (X16, 0)
+      }
+    };
+    if let Some(ShiftIndex { index, shift }) = a.si {
+      if base.is_valid() {
+        self.push(PInst::AddExt { dst: X16, src1: base, src2: index, shift });
+      } else {
+        self.push(PInst::AluRRR {
+          op: Binop::Add, sz: Size::S64, dst: X16, src1: ZR, src2: index, shift
+        });
+      }
+      base = X16;
+    }
+    // As on x86, the displacement of an address is sign extended
+    let n64 = i64::from(n.cast_signed()).cast_unsigned();
+    if !base.is_valid() {
+      self.push_imm(Size::S64, X16, n64);
+      return (X16, MemOff::Imm(0))
+    }
+    match u16::try_from(n / bytes) {
+      Ok(off) if n % bytes == 0 && off < 4096 => (base, MemOff::Imm(off)),
+      _ => {
+        self.push_imm(Size::S64, X17, n64);
+        (base, MemOff::Reg(X17))
+      }
+    }
+  }
+
+  fn push_load(&mut self, sz: Size, dst: PReg, a: &PAMode) {
+    match self.push_addr(a, sz) {
+      (base, MemOff::Imm(off)) => self.push(PInst::Load { spill: false, sz, dst, base, off }),
+      (base, MemOff::Reg(index)) => self.push(PInst::LoadRR { sz, dst, base, index }),
+    };
+  }
+
+  fn push_store(&mut self, sz: Size, src: PReg, a: &PAMode) {
+    match self.push_addr(a, sz) {
+      (base, MemOff::Imm(off)) => self.push(PInst::Store { spill: false, sz, src, base, off }),
+      (base, MemOff::Reg(index)) => self.push(PInst::StoreRR { sz, src, base, index }),
+    };
+  }
+
+  fn push_lea(&mut self, dst: PReg, a: &PAMode) {
+    match self.push_addr(a, Size::S8) {
+      (src, MemOff::Imm(imm)) =>
+        self.push(PInst::AluRRImm { op: Binop::Add, sz: Size::S64, dst, src, imm }),
+      (src1, MemOff::Reg(src2)) => self.push(PInst::AddExt { dst, src1, src2, shift: 0 }),
+    };
+  }
+
+  /// Push `sp <- sp op n`, in multiple steps if `n` does not fit in an immediate.
+  fn push_sp_adjust(&mut self, op: Binop, mut n: u32) {
+    while n != 0 {
+      let imm = n.min(0xff0);
+      #[allow(clippy::cast_possible_truncation)]
+      self.push(PInst::AluRRImm { op, sz: Size::S64, dst: SP, src: SP, imm: imm as u16 });
+      n -= imm;
+    }
+  }
+
+  fn push_prologue(&mut self, stack_size: u32, saved_off: u32, saved_regs: &[PReg]) {
+    self.push_sp_adjust(Binop::Sub, stack_size);
+    for (off, &src) in (saved_off..).step_by(8).zip(saved_regs) {
+      self.push(stack_access(false, false, src, off));
+    }
+  }
+
+  fn push_epilogue(&mut self, stack_size: u32, saved_off: u32, saved_regs: &[PReg]) {
+    for (off, &dst) in (saved_off..).step_by(8).zip(saved_regs) {
+      self.push(stack_access(true, false, dst, off));
+    }
+    self.push_sp_adjust(Binop::Add, stack_size);
+    self.push(PInst::Ret);
+  }
+}
+
+/// A 64 bit load (if `load` is true) or store of `reg` at byte offset `off` from `sp`. Offsets
+/// which do not fit in the scaled immediate field are loaded into `x16`.
+#[allow(clippy::integer_division)]
+fn stack_access(load: bool, spill: bool, reg: PReg, off: u32) -> PInst {
+  assert!(off % 8 == 0, "misaligned stack slot");
+  match u16::try_from(off / 8) {
+    Ok(off) if off < 4096 => if load {
+      PInst::Load { spill, sz: Size::S64, dst: reg, base: SP, off }
+    } else {
+      PInst::Store { spill, sz: Size::S64, src: reg, base: SP, off }
+    },
+    _ => PInst::StackFar { load, spill, reg, off },
+  }
+}
+
+impl VCode<Inst> {
+  #[allow(clippy::similar_names)]
+  pub(crate) fn regalloc(mut self) -> (ProcAbi, Box<PCode<PInst>>) {
+    let out = self.do_regalloc(&MACHINE_ENV);
+    let clobbers = get_clobbers(&self, &out);
+    let mut saved_regs = callee_saved().filter(move |&r| clobbers.get(r)).collect::<Vec<_>>();
+    if self.insts.0.iter().any(|inst| matches!(inst, Inst::CallKnown {..})) {
+      saved_regs.push(LR)
+    }
+    self.abi.clobbers = caller_saved().filter(|&r| clobbers.get(r)).collect();
+    let mut edits = out.edits.into_iter().peekable();
+    for _ in 0..out.num_spillslots { self.fresh_spill(8); }
+    // The stack frame consists of (from low addresses to high addresses):
+    // the outgoing arguments, the spill slots (the register allocator's slots first,
+    // including the ones just allocated), the saved registers, and padding to 16 bytes.
+    // The incoming arguments are immediately above the frame.
+    let (stack_size, saved_off);
+    let mut ar = if let [_incoming, outgoing, ref spills @ ..] = *self.spills.0 {
+      let mut spill_map = vec![0; self.spills.len()];
+      let regspill_off = (outgoing + 7) & !7;
+      let mut sp_off = regspill_off;
+      for (&n, off) in spills.iter().zip(&mut spill_map[2..]).rev() {
+        *off = sp_off;
+        sp_off += (n + 7) & !7;
+      }
+      saved_off = sp_off;
+      sp_off += u32::try_from(saved_regs.len() * 8).expect("overflow");
+      stack_size = (sp_off + 15) & !15;
+      spill_map[0] = stack_size;
+      ApplyRegalloc::new(out.allocs, out.inst_alloc_offsets, regspill_off, spill_map.into(), SP)
+    } else { unreachable!() };
+    let mut code = PCodeBuilder::new(self.block_map, self.trace, stack_size);
+    let mut bb = BlockBuilder::new(&self.blocks.0);
+    code.push_prologue(stack_size, saved_off, &saved_regs);
+    for (i, inst) in self.insts.enum_iter() {
+      ar.next_inst();
+      if bb.next == i {
+        bb.finish_block(&mut code);
+        code.block_params.push_new();
+        let len = code.len;
+        code.block_addr.push(len);
+      }
+      code.apply_edits(&mut edits, &mut ar, ProgPoint::before(i));
+      match *inst {
+        Inst::Fallthrough { dst } => {
+          assert!(self.blocks[dst].1 == i.next());
+          code.push(PInst::Fallthrough { dst });
+        }
+        Inst::BlockParam { var, ref val } => {
+          code.block_params.extend_last((var, ar.rm(val)))
+        }
+        Inst::AluRRR { sz, op, .. } => {
+          let (src1, src2, dst) = (ar.reg(), ar.reg(), ar.reg());
+          code.push(PInst::AluRRR { op, sz, dst, src1, src2, shift: 0 });
+        }
+        Inst::AluRRImm { sz, op, imm, .. } => {
+          let (src, dst) = (ar.reg(), ar.reg());
+          code.push(PInst::AluRRImm { op, sz, dst, src, imm });
+        }
+        Inst::Unop { sz, op, .. } => {
+          let (src, dst) = (ar.reg(), ar.reg());
+          code.push(PInst::Unop { op, sz, dst, src });
+        }
+        Inst::Mul { sz, .. } => {
+          let (src1, src2, dst) = (ar.reg(), ar.reg(), ar.reg());
+          code.push(PInst::Mul { sz, dst, src1, src2 });
+        }
+        Inst::Imm { sz, src, .. } => code.push_imm(sz, ar.reg(), src),
+        Inst::MovRR { .. } => { code.push(PInst::MovId); }
+        Inst::MovPR { .. } => { ar.next(); code.push(PInst::MovId); }
+        Inst::Extend { signed, ext_mode, .. } => {
+          let (src, dst) = (ar.reg(), ar.reg());
+          let bits = ext_mode.src().bits0();
+          code.push(match (signed, ext_mode) {
+            (false, ExtMode::LQ) => PInst::MovRR { sz: Size::S32, dst, src },
+            (false, _) =>
+              PInst::Bfm { signed, sz: Size::S32, dst, src, immr: 0, imms: bits - 1 },
+            (true, _) =>
+              PInst::Bfm { signed, sz: ext_mode.dst(), dst, src, immr: 0, imms: bits - 1 },
+          });
+        }
+        Inst::Load { sz, ref addr, .. } => {
+          let (addr, dst) = (ar.mem(addr), ar.reg());
+          code.push_load(sz, dst, &addr);
+        }
+        Inst::Store { sz, ref addr, .. } => {
+          let (src, addr) = (ar.reg(), ar.mem(addr));
+          code.push_store(sz, src, &addr);
+        }
+        Inst::Lea { ref addr, .. } => {
+          let (addr, dst) = (ar.mem(addr), ar.reg());
+          code.push_lea(dst, &addr);
+        }
+        Inst::ShiftImm { sz, kind, num_bits, .. } => {
+          let (src, dst) = (ar.reg(), ar.reg());
+          let bits = sz.bits0();
+          code.push(match (kind, sz) {
+            (ShiftKind::Shl, _) => {
+              let w = reg_size(sz).bits0();
+              PInst::Bfm { signed: false, sz, dst, src,
+                immr: (w - num_bits) % w, imms: w - 1 - num_bits }
+            }
+            // For 8 and 16 bit values, this also discards the high bits
+            (_, _) => PInst::Bfm {
+              signed: matches!(kind, ShiftKind::ShrA), sz, dst, src, immr: num_bits, imms: bits - 1
+            },
+          });
+        }
+        Inst::ShiftRR { sz, kind, .. } => {
+          let (mut src1, src2, dst) = (ar.reg(), ar.reg(), ar.reg());
+          if matches!((kind, sz), (ShiftKind::ShrL | ShiftKind::ShrA, Size::S8 | Size::S16)) {
+            let signed = matches!(kind, ShiftKind::ShrA);
+            let imms = sz.bits0() - 1;
+            code.push(PInst::Bfm { signed, sz: Size::S32, dst: X16, src: src1, immr: 0, imms });
+            src1 = X16;
+          }
+          code.push(PInst::ShiftRR { kind, sz, dst, src1, src2 });
+        }
+        Inst::Cmp { sz, .. } => {
+          let (mut src1, src2) = (ar.reg(), ar.reg());
+          // 8 and 16 bit comparisons are done on the top bits of a 32 bit register
+          let shift = 32 - sz.bits0().min(32);
+          if shift != 0 {
+            code.push(PInst::Bfm { signed: false, sz: Size::S32, dst: X16, src: src1,
+              immr: 32 - shift, imms: 31 - shift });
+            src1 = X16;
+          }
+          code.push(PInst::Cmp { sz, src1, src2, shift });
+        }
+        Inst::CmpImm { sz, imm, .. } => {
+          let mut src = ar.reg();
+          let shift = 32 - sz.bits0().min(32);
+          if shift != 0 {
+            assert!(imm == 0);
+            code.push(PInst::Bfm { signed: false, sz: Size::S32, dst: X16, src,
+              immr: 32 - shift, imms: 31 - shift });
+            src = X16;
+          }
+          code.push(PInst::CmpImm { sz, src, imm });
+        }
+        Inst::CSet { cc, .. } => { code.push(PInst::CSet { cc, dst: ar.reg() }); }
+        Inst::CSel { sz, cc, .. } => {
+          let (tru, fal, dst) = (ar.reg(), ar.reg(), ar.reg());
+          code.push(PInst::CSel { sz, cc, dst, tru, fal });
+        }
+        Inst::CallKnown { f, ref operands, .. } => {
+          for _ in &**operands { ar.next(); }
+          code.push(PInst::CallKnown { f });
+        }
+        Inst::SysCall { ref operands, .. } => {
+          for _ in &**operands { ar.next(); }
+          code.push(PInst::SysCall);
+        }
+        Inst::Epilogue { ref params } => {
+          for _ in &**params { ar.next(); }
+          code.push_epilogue(stack_size, saved_off, &saved_regs)
+        }
+        Inst::JmpKnown { dst, .. } =>
+          if self.blocks[dst].1 == i.next() {
+            code.push(PInst::Fallthrough { dst });
+          } else {
+            code.push(PInst::JmpKnown { dst });
+          },
+        Inst::JmpCond { cc, taken, not_taken } =>
+          if self.blocks[not_taken].1 == i.next() {
+            code.push(PInst::JmpCond { cc, dst: taken });
+            code.push(PInst::Fallthrough { dst: not_taken });
+          } else if self.blocks[taken].1 == i.next() {
+            code.push(PInst::JmpCond { cc: cc.invert(), dst: not_taken });
+            code.push(PInst::Fallthrough { dst: taken });
+          } else {
+            code.push(PInst::JmpCond { cc, dst: taken });
+            code.push(PInst::JmpKnown { dst: not_taken });
+          },
+        Inst::Assert { cc, dst } => {
+          assert!(self.blocks[dst].1 == i.next());
+          code.push(PInst::Assert { cc, dst });
+        }
+        Inst::Udf => { code.push(PInst::Udf); }
+      }
+      code.apply_edits(&mut edits, &mut ar, ProgPoint::after(i));
+    }
+    bb.finish_block(&mut code);
+    (self.abi, code.finish(saved_regs))
+  }
+}
+
+/// The `sf` bit of an instruction, selecting between 32 and 64 bit operation.
+fn sf(sz: Size) -> u32 { u32::from(sz == Size::S64) << 31 }
+
+/// The `size` field of a load or store instruction.
+fn ldst_size(sz: Size) -> u32 {
+  match sz {
+    Size::S8 => 0,
+    Size::S16 => 1,
+    Size::S32 => 2,
+    Size::S64 => 3,
+    Size::Inf => unreachable!(),
+  }
+}
+
+/// Encode a register operand.
+fn enc(r: PReg) -> u32 { u32::from(r.index()) }
+
+impl PInst {
+  /// The length of the instruction in bytes.
+  #[allow(clippy::len_without_is_empty)]
+  #[must_use] pub fn len(&self) -> u8 {
+    match self {
+      PInst::Fallthrough { .. } | PInst::MovId => 0,
+      PInst::Assert { .. } => 8,
+      PInst::StackFar { .. } => 12,
+      _ => 4,
+    }
+  }
+
+  /// The encoded instruction, as a 32-bit word.
+  fn encode(&self, buf: &InstSink<'_, PInst>) -> u32 {
+    #[allow(clippy::cast_sign_loss)]
+    fn rel(n: i32, bits: u32) -> u32 {
+      debug_assert!(n % 4 == 0);
+      ((n >> 2) as u32) & ((1 << bits) - 1)
+    }
+    match *self {
+      PInst::Fallthrough { .. } | PInst::MovId | PInst::Assert { .. } | PInst::StackFar { .. } =>
+        unreachable!(),
+      PInst::AluRRR { op, sz, dst, src1, src2, shift } => {
+        let opc = match op {
+          Binop::Add => 0x0b00_0000,
+          Binop::Sub => 0x4b00_0000,
+          Binop::And => 0x0a00_0000,
+          Binop::Or => 0x2a00_0000,
+          Binop::Xor => 0x4a00_0000,
+          Binop::Adc | Binop::Sbb => unreachable!(),
+        };
+        sf(sz) | opc | enc(src2) << 16 | u32::from(shift) << 10 | enc(src1) << 5 | enc(dst)
+      }
+      PInst::AluRRImm { op, sz, dst, src, imm } => {
+        let opc = if op == Binop::Sub { 0x5100_0000 } else { 0x1100_0000 };
+        sf(sz) | opc | u32::from(imm) << 10 | enc(src) << 5 | enc(dst)
+      }
+      PInst::AddExt { dst, src1, src2, shift } =>
+        // add (extended register), option = uxtx
+        0x8b20_6000 | enc(src2) << 16 | u32::from(shift) << 10 | enc(src1) << 5 | enc(dst),
+      PInst::Unop { op, sz, dst, src } => {
+        // mvn = orn dst, zr, src; neg = sub dst, zr, src
+        let opc = if op == Unop::Not { 0x2a20_03e0 } else { 0x4b00_03e0 };
+        sf(sz) | opc | enc(src) << 16 | enc(dst)
+      }
+      PInst::Mul { sz, dst, src1, src2 } =>
+        // madd dst, src1, src2, zr
+        sf(sz) | 0x1b00_7c00 | enc(src2) << 16 | enc(src1) << 5 | enc(dst),
+      PInst::MovImm { sz, keep, dst, imm, hw } => {
+        let opc = if keep { 0x7280_0000 } else { 0x5280_0000 };
+        sf(sz) | opc | u32::from(hw) << 21 | u32::from(imm) << 5 | enc(dst)
+      }
+      PInst::MovAddr { dst, off, hi } => {
+        let addr = match off {
+          Offset::Global(id, n) => buf.global_start + buf[id] + n,
+          Offset::Const(n) => buf.rodata_start + n,
+          Offset::Real(_) | Offset::Spill(..) => unreachable!(),
+        };
+        if hi {
+          0xf2a0_0000 | (addr >> 16) << 5 | enc(dst)
+        } else {
+          0xd280_0000 | (addr & 0xffff) << 5 | enc(dst)
+        }
+      }
+      PInst::MovRR { sz, dst, src } =>
+        // orr dst, zr, src
+        sf(sz) | 0x2a00_03e0 | enc(src) << 16 | enc(dst),
+      PInst::Bfm { signed, sz, dst, src, immr, imms } => {
+        let opc = if signed { 0x1300_0000 } else { 0x5300_0000 };
+        let n = u32::from(sz == Size::S64) << 22;
+        sf(sz) | opc | n | u32::from(immr) << 16 | u32::from(imms) << 10 | enc(src) << 5 | enc(dst)
+      }
+      PInst::ShiftRR { kind, sz, dst, src1, src2 } => {
+        let opc = match kind {
+          ShiftKind::Shl => 0x1ac0_2000,
+          ShiftKind::ShrL => 0x1ac0_2400,
+          ShiftKind::ShrA => 0x1ac0_2800,
+        };
+        sf(reg_size(sz)) | opc | enc(src2) << 16 | enc(src1) << 5 | enc(dst)
+      }
+      PInst::Load { sz, dst, base, off, .. } =>
+        ldst_size(sz) << 30 | 0x3940_0000 | u32::from(off) << 10 | enc(base) << 5 | enc(dst),
+      PInst::LoadRR { sz, dst, base, index } =>
+        ldst_size(sz) << 30 | 0x3860_6800 | enc(index) << 16 | enc(base) << 5 | enc(dst),
+      PInst::Store { sz, src, base, off, .. } =>
+        ldst_size(sz) << 30 | 0x3900_0000 | u32::from(off) << 10 | enc(base) << 5 | enc(src),
+      PInst::StoreRR { sz, src, base, index } =>
+        ldst_size(sz) << 30 | 0x3820_6800 | enc(index) << 16 | enc(base) << 5 | enc(src),
+      PInst::Cmp { sz, src1, src2, shift } =>
+        // subs zr, src1, src2, lsl #shift
+        sf(sz) | 0x6b00_001f | enc(src2) << 16 | u32::from(shift) << 10 | enc(src1) << 5,
+      PInst::CmpImm { sz, src, imm } =>
+        // subs zr, src, #imm
+        sf(sz) | 0x7100_001f | u32::from(imm) << 10 | enc(src) << 5,
+      PInst::CSet { cc, dst } =>
+        // csinc dst, zr, zr, !cc
+        0x1a9f_07e0 | cond(cc.invert()) << 12 | enc(dst),
+      PInst::CSel { sz, cc, dst, tru, fal } =>
+        sf(sz) | 0x1a80_0000 | enc(fal) << 16 | cond(cc) << 12 | enc(tru) << 5 | enc(dst),
+      PInst::CallKnown { f } => 0x9400_0000 | rel(buf.rip_relative_proc(f), 26),
+      PInst::SysCall => 0xd400_0001,
+      PInst::Ret => 0xd65f_03c0,
+      PInst::JmpKnown { dst } => 0x1400_0000 | rel(buf.rip_relative_block(dst), 26),
+      PInst::JmpCond { cc, dst } =>
+        0x5400_0000 | rel(buf.rip_relative_block(dst), 19) << 5 | cond(cc),
+      PInst::Udf => 0,
+    }
+  }
+}
+
+impl MachInst for PInst {
+  const ELF_MACHINE: u16 = 0xb7;
+
+  fn len(&self) -> u32 { PInst::len(self).into() }
+  fn mov(dst: PReg, src: PReg) -> Self { PInst::MovRR { sz: Size::S64, dst, src } }
+  fn spill_store(dst: PAMode, src: PReg) -> Self {
+    let Offset::Real(off) = dst.off else { unreachable!() };
+    debug_assert!(dst.base == SP);
+    stack_access(false, true, src, off)
+  }
+  fn spill_load(dst: PReg, src: PAMode) -> Self {
+    let Offset::Real(off) = src.off else { unreachable!() };
+    debug_assert!(src.base == SP);
+    stack_access(true, true, dst, off)
+  }
+
+  fn write(&self, buf: &mut InstSink<'_, Self>) {
+    match *self {
+      PInst::Fallthrough { .. } | PInst::MovId => {}
+      PInst::Assert { cc, .. } => {
+        buf.push_u32(0x5400_0000 | 2 << 5 | cond(cc)); // b.cc +8
+        buf.push_u32(0); // udf #0
+      }
+      PInst::StackFar { load, reg, off, .. } => {
+        #[allow(clippy::cast_possible_truncation)]
+        let [lo, hi] = [off as u16, (off >> 16) as u16];
+        let set_lo = PInst::MovImm { sz: Size::S64, keep: false, dst: X16, imm: lo, hw: 0 };
+        let set_hi = PInst::MovImm { sz: Size::S64, keep: true, dst: X16, imm: hi, hw: 1 };
+        let ldst = if load {
+          PInst::LoadRR { sz: Size::S64, dst: reg, base: SP, index: X16 }
+        } else {
+          PInst::StoreRR { sz: Size::S64, src: reg, base: SP, index: X16 }
+        };
+        for inst in [set_lo, set_hi, ldst] { buf.push_u32(inst.encode(buf)) }
+      }
+      _ => { let insn = self.encode(buf); buf.push_u32(insn) }
+    }
+    buf.update_rip(self.len());
+  }
+}
+
+/// The `AArch64` (64-bit ARM) target.
+#[derive(Debug)]
+pub(crate) struct Arm64;
+
+impl Arch for Arm64 {
+  type PInst = PInst;
+
+  fn lower(
+    names: &HashMap<Symbol, Entity>,
+    func_mono: &HashMap<Symbol, ProcId>,
+    funcs: &IdxVec<ProcId, ProcAbi>,
+    consts: &ConstData,
+    cfg: &mir::Cfg,
+    allocs: &Allocations,
+    ctx: VCodeCtx<'_>,
+  ) -> Result<(ProcAbi, Box<PCode<PInst>>), LowerErr> {
+    Ok(build_vcode::<Inst>(names, func_mono, funcs, consts, cfg, allocs, ctx)?.regalloc())
+  }
+}
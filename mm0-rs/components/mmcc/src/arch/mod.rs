@@ -1,5 +1,197 @@
 //! Architecture-specific parts of the compiler.
+//!
+//! The x86-64 backend is the primary target, and its types are re-exported at the top level of
+//! this module. The [`arm64`] backend reuses the operand types ([`AMode`], [`RegMem`], [`CC`]
+//! and so on) but has its own instructions, register conventions and encoding.
+//! Each backend plugs into the shared lowering pass, register allocator and ELF writer
+//! through the `Lower`, `MachInst` and `Arch` traits defined here.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+use arrayvec::ArrayVec;
+use regalloc2::Operand;
+
+use crate::build_vcode::{LowerErr, VCodeCtx};
+use crate::codegen::InstSink;
+use crate::linker::ConstData;
+use crate::mir_opt::storage::Allocations;
+use crate::regalloc::PCode;
+use crate::types::{classify as cl, mir, IdxVec, Size};
+use crate::types::vcode::{BlockId, InstId, ProcAbi, ProcId, VReg};
+use crate::{Entity, Symbol};
 
-// We only support x86 at the moment.
 mod x86;
 pub use x86::*;
+pub mod arm64;
+
+/// A set of physical registers, represented as a bitset.
+#[derive(Copy, Clone, Default)]
+pub(crate) struct PRegSet(u32);
+impl PRegSet {
+  #[inline] pub(crate) fn insert(&mut self, r: PReg) { self.0 |= 1 << r.index() }
+  #[inline] pub(crate) fn get(self, r: PReg) -> bool { self.0 & (1 << r.index()) != 0 }
+  #[inline] pub(crate) fn remove(&mut self, r: PReg) { self.0 &= !(1 << r.index()) }
+}
+
+/// A compilation target.
+pub(crate) trait Arch {
+  /// The type of instructions after register allocation.
+  type PInst: MachInst;
+
+  /// Lower a function to `VCode` and run register allocation on it.
+  #[allow(clippy::type_complexity)]
+  fn lower(
+    names: &HashMap<Symbol, Entity>,
+    func_mono: &HashMap<Symbol, ProcId>,
+    funcs: &IdxVec<ProcId, ProcAbi>,
+    consts: &ConstData,
+    cfg: &mir::Cfg,
+    allocs: &Allocations,
+    ctx: VCodeCtx<'_>,
+  ) -> Result<(ProcAbi, Box<PCode<Self::PInst>>), LowerErr>;
+}
+
+/// A physical instruction, the output of register allocation.
+pub(crate) trait MachInst: Clone + Debug + Sized {
+  /// The value of the `e_machine` field in the ELF header.
+  const ELF_MACHINE: u16;
+
+  /// The length of the instruction in bytes.
+  fn len(&self) -> u32;
+
+  /// If this is a jump which has a short form, returns the target block.
+  fn is_jump(&self) -> Option<BlockId> { None }
+
+  /// Replace a jump (as returned by [`MachInst::is_jump`]) with its short form.
+  fn shorten(&mut self) {}
+
+  /// A register to register move, inserted by the register allocator.
+  fn mov(dst: PReg, src: PReg) -> Self;
+
+  /// A store to a register allocator spill slot.
+  fn spill_store(dst: PAMode, src: PReg) -> Self;
+
+  /// A load from a register allocator spill slot.
+  fn spill_load(dst: PReg, src: PAMode) -> Self;
+
+  /// Encode the instruction into the sink.
+  fn write(&self, buf: &mut InstSink<'_, Self>);
+}
+
+/// The instruction selection interface used by the lowering pass.
+///
+/// This is implemented for `VCode<I>` where `I` is the instruction type of the target.
+/// The operations here are x86-flavored: they take an `op` from the x86 instruction set
+/// and operands which may be in memory, and targets which do not support these directly
+/// are expected to legalize them (usually by loading into a fresh register first).
+pub(crate) trait Lower: Sized {
+  /// The registers used for passing function arguments and return values, in order.
+  const ARG_REGS: &'static [PReg];
+
+  /// The registers used for a system call: the register containing the system call number,
+  /// the register holding the return value, and the argument registers.
+  const SYSCALL_REGS: (PReg, PReg, &'static [PReg]);
+
+  /// Adjust the arguments to a system call for this target, and return the system call number.
+  /// The arguments are initially given according to the x86-64 Linux calling convention.
+  fn syscall_args(f: SysCall, args: &mut ArrayVec<(RegMemImm<u64>, cl::Operand), 6>) -> u32;
+
+  /// Emit a pseudo-instruction declaring the location of a block parameter.
+  fn emit_block_param(&mut self, var: mir::VarId, val: RegMem);
+
+  /// Emit a register to register move.
+  fn emit_mov(&mut self, dst: VReg, src: VReg);
+
+  /// Emit a move out of a physical register (used in the prologue).
+  fn emit_mov_preg(&mut self, dst: VReg, src: PReg);
+
+  /// Emit `dst <- src`, returning the fresh register `dst`.
+  fn emit_imm(&mut self, sz: Size, src: impl Into<u64>) -> VReg;
+
+  /// Emit a zero-extending load of size `sz` from `addr`.
+  fn emit_load(&mut self, sz: Size, addr: AMode) -> VReg;
+
+  /// Emit an address computation `dst <- &addr`.
+  fn emit_lea(&mut self, sz: Size, addr: AMode) -> VReg;
+
+  /// Emit a zero or sign extension of `src` according to `ext_mode`.
+  fn emit_extend(&mut self, signed: bool, ext_mode: ExtMode, src: RegMem) -> VReg;
+
+  /// Emit `dst <- src1 op src2`.
+  fn emit_binop(&mut self, sz: Size, op: Binop, src1: VReg, src2: impl Into<RegMemImm>) -> VReg;
+
+  /// Emit `dst <- op src`.
+  fn emit_unop(&mut self, sz: Size, op: Unop, src: VReg) -> VReg;
+
+  /// Emit `dst <- src1 * src2`, keeping the low `sz` bits of the product.
+  fn emit_mul(&mut self, sz: Size, src1: VReg, src2: impl Into<RegMem>) -> VReg;
+
+  /// Emit a shift by an immediate (`Ok`) or a register (`Err`).
+  /// The shift amount must be less than the number of bits in `sz`.
+  fn emit_shift(&mut self, sz: Size, kind: ShiftKind, src1: VReg, src2: Result<u8, VReg>) -> VReg;
+
+  /// Emit a comparison, returning a handle to the flags which can be used to
+  /// consume the result with condition code `cc`.
+  fn emit_cmp(&mut self, sz: Size, op: Cmp, cc: CC, src1: VReg, src2: impl Into<RegMemImm>
+  ) -> Flags<'_, Self>;
+
+  /// Emit a move of `sz` bits from `src` to `dst`.
+  #[must_use]
+  fn emit_copy(&mut self, sz: Size, dst: RegMem, src: impl Into<RegMemImm<u64>>) -> cl::Copy;
+
+  /// Emit `dst <- cc ? 1 : 0` (see [`Flags::into_reg`]).
+  fn emit_setcc(&mut self, cc: CC) -> VReg;
+
+  /// Emit `dst <- cc ? tru : fal` (see [`Flags::select`]).
+  fn emit_cmov(&mut self, sz: Size, cc: CC, tru: RegMem, fal: VReg) -> VReg;
+
+  /// Emit an assertion that `cc` holds, falling through to `dst` (see [`Flags::assert`]).
+  fn emit_assert(&mut self, cc: CC, dst: BlockId) -> InstId;
+
+  /// Emit a conditional branch on `cc` (see [`Flags::branch`]).
+  fn emit_branch(&mut self, cc: CC, tru: BlockId, fal: BlockId) -> InstId;
+
+  /// Emit an unconditional jump to `dst`, passing the block parameters `params`.
+  fn emit_jump(&mut self, dst: BlockId, params: Box<[regalloc2::VReg]>) -> InstId;
+
+  /// Emit a pseudo-instruction that ends the block and falls through to `dst`.
+  fn emit_fallthrough(&mut self, dst: BlockId) -> InstId;
+
+  /// Emit a call to a known function. If `clobbers` is `None` the call does not return.
+  fn emit_call(&mut self, f: ProcId, operands: Box<[Operand]>, clobbers: Option<Box<[PReg]>>);
+
+  /// Emit a system call, with operands constrained to the registers in [`Lower::SYSCALL_REGS`].
+  fn emit_syscall(&mut self, f: SysCall, operands: Box<[Operand]>);
+
+  /// Emit the function epilogue and return.
+  fn emit_epilogue(&mut self, params: Box<[Operand]>);
+
+  /// Emit an instruction that causes the program to crash.
+  fn emit_trap(&mut self);
+
+  /// Resolve the MIR block targets of the branch instruction `inst` to `VCode` blocks,
+  /// and add the corresponding edges to the CFG.
+  fn patch_branch(&mut self, from: BlockId, inst: InstId);
+}
+
+/// A handle to the result of a comparison, which must be consumed by one of the methods
+/// on this type.
+#[must_use]
+pub(crate) struct Flags<'a, C>(&'a mut C, CC);
+
+impl<'a, C: Lower> Flags<'a, C> {
+  pub(crate) fn new(code: &'a mut C, cc: CC) -> Self { Self(code, cc) }
+
+  pub(crate) fn into_reg(self) -> VReg { self.0.emit_setcc(self.1) }
+
+  pub(crate) fn select(self, sz: Size, tru: impl Into<RegMem>, fal: VReg) -> VReg {
+    self.0.emit_cmov(sz, self.1, tru.into(), fal)
+  }
+
+  pub(crate) fn assert(self, dst: BlockId) -> InstId { self.0.emit_assert(self.1, dst) }
+
+  pub(crate) fn branch(self, tru: BlockId, fal: BlockId) -> InstId {
+    self.0.emit_branch(self.1, tru, fal)
+  }
+}
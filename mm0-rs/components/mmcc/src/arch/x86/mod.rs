@@ -1,14 +1,22 @@
 //! x86-specific parts of the compiler.
 
+use std::collections::HashMap;
 use std::fmt::{Debug, Display};
 
+use arrayvec::ArrayVec;
 use num::Zero;
 use once_cell::sync::Lazy;
 use regalloc2::{MachineEnv, Operand};
 
+use crate::build_vcode::{LowerErr, VCodeCtx, build_vcode};
 use crate::codegen::InstSink;
-use crate::types::{classify as cl, mir, Size,
-  vcode::{BlockId, GlobalId, SpillId, ProcId, InstId, VReg, IsReg, Inst as VInst, VCode}};
+use crate::linker::ConstData;
+use crate::mir_opt::storage::Allocations;
+use crate::regalloc::PCode;
+use crate::types::{classify as cl, mir, IdxVec, Size,
+  vcode::{BlockId, GlobalId, SpillId, ProcId, ProcAbi, InstId, VReg, IsReg, Inst as VInst, VCode}};
+use crate::{Entity, Symbol};
+use super::{Arch, Flags, Lower, MachInst};
 
 /// A physical register. For x86, this is one of the 16 general purpose integer registers.
 #[repr(transparent)]
@@ -90,14 +98,6 @@ pub(crate) static MACHINE_ENV: Lazy<MachineEnv> = Lazy::new(|| MachineEnv {
   fixed_stack_slots: vec![],
 });
 
-#[derive(Copy, Clone, Default)]
-pub(crate) struct PRegSet(u16);
-impl PRegSet {
-  #[inline] pub(crate) fn insert(&mut self, r: PReg) { self.0 |= 1 << r.index() }
-  #[inline] pub(crate) fn get(self, r: PReg) -> bool { self.0 & (1 << r.index()) != 0 }
-  #[inline] pub(crate) fn remove(&mut self, r: PReg) { self.0 &= !(1 << r.index()) }
-}
-
 /// These indicate the form of a scalar shift/rotate: left, signed right, unsigned right.
 #[derive(Clone, Copy)]
 pub enum ShiftKind {
@@ -501,14 +501,12 @@ impl AMode {
     self.on_regs(|v| args.push(Operand::reg_use(v.0)))
   }
 
-  pub(crate) fn emit_load(&self, code: &mut VCode<Inst>, sz: Size) -> VReg {
-    let dst = code.fresh_vreg();
-    code.emit(Inst::load_mem(sz, dst, *self));
-    dst
+  pub(crate) fn emit_load(&self, code: &mut impl Lower, sz: Size) -> VReg {
+    code.emit_load(sz, *self)
   }
 
   pub(crate) fn add_scaled(&self,
-    code: &mut VCode<Inst>, sc: u64, reg: VReg
+    code: &mut impl Lower, sc: u64, reg: VReg
   ) -> (AMode, cl::AddScaled) {
     match (
       &self.si,
@@ -536,7 +534,7 @@ impl AMode {
       }
       (None, _, _) => {
         let sc = code.emit_imm(Size::S64, sc);
-        let mul = code.emit_mul(Size::S64, reg, sc).0;
+        let mul = code.emit_mul(Size::S64, reg, sc);
         let si = Some(ShiftIndex { shift: 0, index: mul });
         (AMode { off: self.off, base: self.base, si }, cl::AddScaled::Large)
       }
@@ -607,14 +605,15 @@ impl RegMem {
     self.on_regs(|v| args.push(Operand::reg_use(v.0)))
   }
 
-  pub(crate) fn into_reg(self, code: &mut VCode<Inst>, sz: Size) -> (VReg, cl::IntoReg) {
+  pub(crate) fn into_reg(self, code: &mut impl Lower, sz: Size) -> (VReg, cl::IntoReg) {
     match self {
       RegMem::Reg(r) => (r, cl::IntoReg(false)),
       RegMem::Mem(a) => (a.emit_load(code, sz), cl::IntoReg(true))
     }
   }
 
-  pub(crate) fn into_mem(self, code: &mut VCode<Inst>, sz: Size) -> (AMode, cl::IntoMem) {
+  pub(crate) fn into_mem<I>(self, code: &mut VCode<I>, sz: Size) -> (AMode, cl::IntoMem)
+  where VCode<I>: Lower {
     match self {
       RegMem::Reg(r) => {
         let a = AMode::spill(code.fresh_spill(sz.bytes().expect("large reg").into()));
@@ -625,7 +624,7 @@ impl RegMem {
     }
   }
 
-  pub(crate) fn emit_deref(&self, code: &mut VCode<Inst>, sz: Size) -> (AMode, cl::IntoReg) {
+  pub(crate) fn emit_deref(&self, code: &mut impl Lower, sz: Size) -> (AMode, cl::IntoReg) {
     let (reg, cl) = self.into_reg(code, sz);
     (AMode::reg(reg), cl)
   }
@@ -701,7 +700,7 @@ impl<N> RegMemImm<N> {
     }
   }
 
-  pub(crate) fn into_rm(self, code: &mut VCode<Inst>, sz: Size) -> (RegMem, cl::IntoRM)
+  pub(crate) fn into_rm(self, code: &mut impl Lower, sz: Size) -> (RegMem, cl::IntoRM)
   where N: Into<u64> {
     match self {
       RegMemImm::Reg(r) => (RegMem::Reg(r), cl::IntoRM(false)),
@@ -710,7 +709,7 @@ impl<N> RegMemImm<N> {
     }
   }
 
-  pub(crate) fn into_reg(self, code: &mut VCode<Inst>, sz: Size) -> (VReg, cl::IntoReg)
+  pub(crate) fn into_reg(self, code: &mut impl Lower, sz: Size) -> (VReg, cl::IntoReg)
   where N: Into<u64> {
     match self {
       RegMemImm::Reg(r) => (r, cl::IntoReg(false)),
@@ -719,8 +718,8 @@ impl<N> RegMemImm<N> {
     }
   }
 
-  pub(crate) fn into_mem(self, code: &mut VCode<Inst>, sz: Size) -> (AMode, cl::IntoMem)
-  where N: Into<u64> {
+  pub(crate) fn into_mem<I>(self, code: &mut VCode<I>, sz: Size) -> (AMode, cl::IntoMem)
+  where N: Into<u64>, VCode<I>: Lower {
     let (rm, cl) = self.into_rm(code, sz);
     let (a, cl2) = rm.into_mem(code, sz);
     (a, cl::IntoMem(cl.0 || cl2.0))
@@ -728,7 +727,7 @@ impl<N> RegMemImm<N> {
 }
 
 impl RegMemImm<u64> {
-  pub(crate) fn into_rmi_32(self, code: &mut VCode<Inst>) -> (RegMemImm, cl::IntoRMI32) {
+  pub(crate) fn into_rmi_32(self, code: &mut impl Lower) -> (RegMemImm, cl::IntoRMI32) {
     match self {
       RegMemImm::Reg(r) => (RegMemImm::Reg(r), cl::IntoRMI32(false)),
       RegMemImm::Mem(a) => (RegMemImm::Mem(a), cl::IntoRMI32(false)),
@@ -742,7 +741,7 @@ impl RegMemImm<u64> {
 
 /// The available set of kernel calls that can be made through the `syscall` instruction.
 #[derive(Clone, Copy, PartialEq, Eq)]
-#[repr(u8)]
+#[repr(u16)]
 pub enum SysCall {
   /// `fd <- open(filename, flags, 0)`. `flags` must be one of:
   /// * `O_RDONLY = 0`
@@ -752,14 +751,30 @@ pub enum SysCall {
   Read = 0,
   /// `nwrite <- write(fd, buf, count)`.
   Write = 1,
+  /// `err <- close(fd)`.
+  Close = 3,
   /// `err <- fstat(fd, statbuf)`.
   FStat = 5,
+  /// `off <- lseek(fd, off, whence)`.
+  LSeek = 8,
   /// `p <- mmap(0, len, prot, flags, fd, 0)`.
   /// * If `fd == u32::MAX` then `flags = MAP_PRIVATE + MAP_ANONYMOUS = 2 + 32 = 34`
   /// * If `fd != u32::MAX` then `flags = MAP_PRIVATE = 2`
   MMap = 9,
+  /// `err <- munmap(p, len)`.
+  MUnmap = 11,
+  /// `p <- brk(p)`.
+  Brk = 12,
+  /// `err <- pipe(fds)`.
+  Pipe = 22,
+  /// `fd <- dup2(oldfd, newfd)`.
+  Dup2 = 33,
   /// `! <- exit(exit_code)`.
   Exit = 0x3c,
+  /// `err <- clock_gettime(clock, tp)`.
+  ClockGetTime = 228,
+  /// `nread <- getrandom(buf, count, 0)`.
+  GetRandom = 318,
 }
 
 impl Debug for SysCall {
@@ -768,9 +783,17 @@ impl Debug for SysCall {
       Self::Open => write!(f, "open"),
       Self::Read => write!(f, "read"),
       Self::Write => write!(f, "write"),
+      Self::Close => write!(f, "close"),
       Self::FStat => write!(f, "fstat"),
+      Self::LSeek => write!(f, "lseek"),
       Self::MMap => write!(f, "mmap"),
+      Self::MUnmap => write!(f, "munmap"),
+      Self::Brk => write!(f, "brk"),
+      Self::Pipe => write!(f, "pipe"),
+      Self::Dup2 => write!(f, "dup2"),
       Self::Exit => write!(f, "exit"),
+      Self::ClockGetTime => write!(f, "clock_gettime"),
+      Self::GetRandom => write!(f, "getrandom"),
     }
   }
 }
@@ -1167,66 +1190,69 @@ impl Inst {
   }
 }
 
-#[must_use]
-pub(crate) struct Flags<'a>(&'a mut VCode<Inst>, CC);
+impl Lower for VCode<Inst> {
+  const ARG_REGS: &'static [PReg] = &RET_AND_ARG_REGS;
+  const SYSCALL_REGS: (PReg, PReg, &'static [PReg]) = (RAX, RAX, &SYSCALL_ARG_REGS.1);
 
-impl Flags<'_> {
-  pub(crate) fn into_reg(self) -> VReg {
-    let dst = self.0.fresh_vreg();
-    self.0.emit(Inst::SetCC { cc: self.1, dst });
-    dst
+  fn syscall_args(f: SysCall, _: &mut ArrayVec<(RegMemImm<u64>, cl::Operand), 6>) -> u32 {
+    f as u32
   }
 
-  pub(crate) fn select(self, sz: Size, tru: impl Into<RegMem>, fal: VReg) -> VReg {
-    let dst = self.0.fresh_vreg();
-    self.0.emit(Inst::CMov { sz, cc: self.1, dst, src1: fal, src2: tru.into() });
-    dst
+  fn emit_block_param(&mut self, var: mir::VarId, val: RegMem) {
+    self.emit(Inst::BlockParam { var, val });
   }
 
-  pub(crate) fn assert(self, dst: BlockId) -> InstId {
-    self.0.emit(Inst::Assert { cc: self.1, dst })
+  fn emit_mov(&mut self, dst: VReg, src: VReg) { self.emit(Inst::MovRR { dst, src }); }
+
+  fn emit_mov_preg(&mut self, dst: VReg, src: PReg) { self.emit(Inst::MovPR { dst, src }); }
+
+  fn emit_imm(&mut self, sz: Size, src: impl Into<u64>) -> VReg {
+    let dst = self.fresh_vreg();
+    self.emit(Inst::Imm { sz, dst, src: src.into() });
+    dst
   }
 
-  pub(crate) fn branch(self, tru: BlockId, fal: BlockId) -> InstId {
-    self.0.emit(Inst::JmpCond { cc: self.1, taken: tru, not_taken: fal })
+  fn emit_load(&mut self, sz: Size, addr: AMode) -> VReg {
+    let dst = self.fresh_vreg();
+    self.emit(Inst::load_mem(sz, dst, addr));
+    dst
   }
-}
 
-impl VCode<Inst> {
-  pub(crate) fn emit_lea(&mut self, sz: Size, addr: AMode) -> VReg {
+  fn emit_lea(&mut self, sz: Size, addr: AMode) -> VReg {
     let dst = self.fresh_vreg();
     self.emit(Inst::Lea { sz, dst, addr });
     dst
   }
 
-  pub(crate) fn emit_binop(&mut self, sz: Size, op: Binop, src1: VReg, src2: impl Into<RegMemImm>
-  ) -> VReg {
+  fn emit_extend(&mut self, signed: bool, ext_mode: ExtMode, src: RegMem) -> VReg {
     let dst = self.fresh_vreg();
-    self.emit(Inst::Binop { sz, op, dst, src1, src2: src2.into() });
+    self.emit(match signed {
+      true => Inst::MovsxRmR { ext_mode, dst, src },
+      false => Inst::MovzxRmR { ext_mode, dst, src },
+    });
     dst
   }
 
-  pub(crate) fn emit_unop(&mut self, sz: Size, op: Unop, src: VReg) -> VReg {
+  fn emit_binop(&mut self, sz: Size, op: Binop, src1: VReg, src2: impl Into<RegMemImm>) -> VReg {
     let dst = self.fresh_vreg();
-    self.emit(Inst::Unop { sz, op, dst, src });
+    self.emit(Inst::Binop { sz, op, dst, src1, src2: src2.into() });
     dst
   }
 
-  pub(crate) fn emit_imm(&mut self, sz: Size, src: impl Into<u64>) -> VReg {
+  fn emit_unop(&mut self, sz: Size, op: Unop, src: VReg) -> VReg {
     let dst = self.fresh_vreg();
-    self.emit(Inst::Imm { sz, dst, src: src.into() });
+    self.emit(Inst::Unop { sz, op, dst, src });
     dst
   }
 
-  pub(crate) fn emit_mul(&mut self, sz: Size, src1: VReg, src2: impl Into<RegMem>) -> (VReg, VReg) {
+  fn emit_mul(&mut self, sz: Size, src1: VReg, src2: impl Into<RegMem>) -> VReg {
     let dst_lo = self.fresh_vreg();
     let dst_hi = self.fresh_vreg();
     self.emit(Inst::Mul { sz, dst_lo, dst_hi, src1, src2: src2.into() });
-    (dst_lo, dst_hi)
+    dst_lo
   }
 
-  pub(crate) fn emit_shift(&mut self, sz: Size, kind: ShiftKind, src1: VReg, src2: Result<u8, VReg>
-  ) -> VReg {
+  fn emit_shift(&mut self, sz: Size, kind: ShiftKind, src1: VReg, src2: Result<u8, VReg>) -> VReg {
     let dst = self.fresh_vreg();
     self.emit(match src2 {
       Ok(num_bits) => Inst::ShiftImm { sz, kind, dst, src: src1, num_bits },
@@ -1235,14 +1261,13 @@ impl VCode<Inst> {
     dst
   }
 
-  pub(crate) fn emit_cmp(&mut self, sz: Size, op: Cmp, cc: CC, src1: VReg, src2: impl Into<RegMemImm>
-  ) -> Flags<'_> {
+  fn emit_cmp(&mut self, sz: Size, op: Cmp, cc: CC, src1: VReg, src2: impl Into<RegMemImm>
+  ) -> Flags<'_, Self> {
     self.emit(Inst::Cmp { sz, op, src1, src2: src2.into() });
-    Flags(self, cc)
+    Flags::new(self, cc)
   }
 
-  #[must_use]
-  #[inline] pub(crate) fn emit_copy(&mut self,
+  #[inline] fn emit_copy(&mut self,
     sz: Size, dst: RegMem, src: impl Into<RegMemImm<u64>>
   ) -> cl::Copy {
     fn copy(code: &mut VCode<Inst>, sz: Size, dst: RegMem, src: RegMemImm<u64>) -> cl::Copy {
@@ -1262,6 +1287,64 @@ impl VCode<Inst> {
     }
     copy(self, sz, dst, src.into())
   }
+
+  fn emit_setcc(&mut self, cc: CC) -> VReg {
+    let dst = self.fresh_vreg();
+    self.emit(Inst::SetCC { cc, dst });
+    dst
+  }
+
+  fn emit_cmov(&mut self, sz: Size, cc: CC, tru: RegMem, fal: VReg) -> VReg {
+    let dst = self.fresh_vreg();
+    self.emit(Inst::CMov { sz, cc, dst, src1: fal, src2: tru });
+    dst
+  }
+
+  fn emit_assert(&mut self, cc: CC, dst: BlockId) -> InstId {
+    self.emit(Inst::Assert { cc, dst })
+  }
+
+  fn emit_branch(&mut self, cc: CC, tru: BlockId, fal: BlockId) -> InstId {
+    self.emit(Inst::JmpCond { cc, taken: tru, not_taken: fal })
+  }
+
+  fn emit_jump(&mut self, dst: BlockId, params: Box<[regalloc2::VReg]>) -> InstId {
+    self.emit(Inst::JmpKnown { dst, params })
+  }
+
+  fn emit_fallthrough(&mut self, dst: BlockId) -> InstId {
+    self.emit(Inst::Fallthrough { dst })
+  }
+
+  fn emit_call(&mut self, f: ProcId, operands: Box<[Operand]>, clobbers: Option<Box<[PReg]>>) {
+    self.emit(Inst::CallKnown { f, operands, clobbers });
+  }
+
+  fn emit_syscall(&mut self, f: SysCall, operands: Box<[Operand]>) {
+    self.emit(Inst::SysCall { f, operands });
+  }
+
+  fn emit_epilogue(&mut self, params: Box<[Operand]>) { self.emit(Inst::Epilogue { params }); }
+
+  fn emit_trap(&mut self) { self.emit(Inst::Ud2); }
+
+  fn patch_branch(&mut self, from: BlockId, inst: InstId) {
+    macro_rules! patch {($dst:expr) => {{ *$dst = self.block_map[&mir::BlockId($dst.0)]; *$dst }}}
+    match &mut self.insts[inst] {
+      Inst::Fallthrough { dst } |
+      Inst::Assert { dst, .. } |
+      Inst::JmpKnown { dst, .. } => {
+        let dst = patch!(dst);
+        self.add_edge(from, dst)
+      }
+      Inst::JmpCond { taken, not_taken, .. } => {
+        let (bl1, bl2) = (patch!(taken), patch!(not_taken));
+        self.add_edge(from, bl1);
+        self.add_edge(from, bl2);
+      }
+      _ => unreachable!(),
+    }
+  }
 }
 
 /// A version of `ShiftIndex` post-register allocation.
@@ -1999,7 +2082,7 @@ impl PInst {
       match *off {
         Offset::Real(off) => off,
         Offset::Spill(..) => unreachable!("removed by regalloc"),
-        Offset::Global(id, n) => buf[id] + n,
+        Offset::Global(id, n) => buf.global_start + buf[id] + n,
         Offset::Const(n) => buf.rodata_start + n,
       }
     }
@@ -2227,3 +2310,37 @@ impl PInst {
     }
   }
 }
+
+impl MachInst for PInst {
+  const ELF_MACHINE: u16 = 0x3e;
+
+  fn len(&self) -> u32 { PInst::len(self).into() }
+  fn is_jump(&self) -> Option<BlockId> { PInst::is_jump(self) }
+  fn shorten(&mut self) { PInst::shorten(self) }
+  fn mov(dst: PReg, src: PReg) -> Self { PInst::MovRR { sz: Size::S64, dst, src } }
+  fn spill_store(dst: PAMode, src: PReg) -> Self {
+    PInst::Store { spill: true, sz: Size::S64, dst, src }
+  }
+  fn spill_load(dst: PReg, src: PAMode) -> Self { PInst::Load64 { spill: true, dst, src } }
+  fn write(&self, buf: &mut InstSink<'_>) { PInst::write(self, buf) }
+}
+
+/// The x86-64 target.
+#[derive(Debug)]
+pub(crate) struct X86;
+
+impl Arch for X86 {
+  type PInst = PInst;
+
+  fn lower(
+    names: &HashMap<Symbol, Entity>,
+    func_mono: &HashMap<Symbol, ProcId>,
+    funcs: &IdxVec<ProcId, ProcAbi>,
+    consts: &ConstData,
+    cfg: &mir::Cfg,
+    allocs: &Allocations,
+    ctx: VCodeCtx<'_>,
+  ) -> Result<(ProcAbi, Box<PCode>), LowerErr> {
+    Ok(build_vcode::<Inst>(names, func_mono, funcs, consts, cfg, allocs, ctx)?.regalloc())
+  }
+}
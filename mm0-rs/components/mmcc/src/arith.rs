@@ -0,0 +1,42 @@
+//! Proofs by linear integer arithmetic.
+//!
+//! The MIR for the `map` and `fold` combinators proves the bounds on the loop counter from the
+//! loop condition with a Farkas [`Certificate`]: a nonnegative combination of the hypotheses
+//! which normalizes to a negative constant when added to the negation of the goal. Checking a
+//! certificate only requires ring normalization and numeric evaluation, in the style of
+//! `norm_num`, but they are not yet translated into MM0 proofs, so the typechecker does not use
+//! them to discharge the bounds checks of `a[i]` and `a[i..i+l]`.
+
+#[cfg(feature = "memory")] use mm0_deepsize_derive::DeepSizeOf;
+
+/// A proof that a goal `t < n` (or `t <= n`) follows from a list of hypotheses by linear
+/// arithmetic.
+///
+/// Each hypothesis is broken into a list of facts `L >= 0` (see below), the negation
+/// of the goal is turned into one more fact `G >= 0`, and the certificate provides nonnegative
+/// multipliers such that `sum_i k_i L_i + k G` normalizes to a negative constant.
+///
+/// The facts of a hypothesis are, in order:
+/// * `a < b` gives `b - a - 1`, and `a <= b` gives `b - a`;
+/// * `a = b` gives `b - a` and `a - b`;
+/// * `!(a < b)` gives `a - b`, and `!(a <= b)` gives `a - b - 1`;
+/// * `p && q` (or `!(p || q)`) gives the facts of `p` followed by those of `q`;
+/// * a typing `x: T` for an integral type `T` gives `x - lo` and then `hi - x`, for those bounds
+///   of `T` which are finite.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "memory", derive(DeepSizeOf))]
+pub struct Certificate {
+  /// The used facts, as `(h, i, k)` where `h` is the index of the hypothesis, `i` is the index
+  /// of the fact in the list of facts of that hypothesis, and `k > 0` is the multiplier.
+  pub terms: Box<[(u32, u32, u64)]>,
+  /// The multiplier on the negated goal.
+  pub goal: u64,
+}
+
+impl std::fmt::Display for Certificate {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "(linarith {}", self.goal)?;
+    for &(h, i, k) in &*self.terms { write!(f, " {k}*h{h}.{i}")? }
+    write!(f, ")")
+  }
+}
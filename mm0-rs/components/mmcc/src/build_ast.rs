@@ -19,7 +19,7 @@ use crate::types::{Binop, Spanned, FieldName, VarId, IdxVec, ast::{self, LabelId
 
 #[derive(Debug)]
 enum Ctx {
-  Var(VarId),
+  Var(Symbol, VarId),
   Label(Symbol, LabelId),
 }
 
@@ -84,7 +84,7 @@ impl BuildAst {
   /// Push a variable with a given name to the context. (This is not exposed because we would like
   /// to prevent reuse of variables in new binding scopes.)
   fn push(&mut self, name: Symbol, v: VarId) {
-    self.ctx.push(Ctx::Var(v));
+    self.ctx.push(Ctx::Var(name, v));
     self.name_map.entry(name).or_default().push(v);
   }
 
@@ -161,8 +161,8 @@ impl BuildAst {
 
   fn pop(&mut self) {
     match self.ctx.pop().expect("stack underflow") {
-      Ctx::Var(v) => {
-        self.name_map.get_mut(&self.var_names[v].k).and_then(Vec::pop).expect("stack underflow");
+      Ctx::Var(name, _) => {
+        self.name_map.get_mut(&name).and_then(Vec::pop).expect("stack underflow");
       }
       Ctx::Label(name, _) => {
         self.label_map.get_mut(&name).and_then(Vec::pop).expect("stack underflow");
@@ -303,7 +303,7 @@ impl BuildAst {
     Self::add_origins(lhs, &mut |var| {
       if let Entry::Vacant(e) = split.entry(var) {
         if let Some(from) = self.ctx.iter().find_map(|v| match v {
-          &Ctx::Var(v) if v == var => Some(self.var_names[v].clone()),
+          &Ctx::Var(_, v) if v == var => Some(self.var_names[v].clone()),
           _ => None
         }) {
           e.insert((self.fresh_var(from.clone()), from.clone(), from));
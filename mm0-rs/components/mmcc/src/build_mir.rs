@@ -6,7 +6,7 @@ use std::collections::{HashMap, hash_map::Entry};
 use mm0_util::{u32_as_usize, FileSpan};
 use smallvec::SmallVec;
 use types::{IntTy, Size};
-use crate::{Idx, Symbol};
+use crate::{Idx, Symbol, arith::Certificate};
 use super::types;
 use types::{Spanned, VarId as HVarId, hir, ty, mir};
 use hir::GenId;
@@ -876,6 +876,7 @@ impl<'a, 'n> BuildMir<'a, 'n> {
       hir::ExprKind::Mm0Proof(p) => Constant::mm0_proof(self.tr(e.k.1.1), p).into(),
       hir::ExprKind::Block(bl) => self.rvalue_block(e.span, bl, Some(e.k.1))?,
       hir::ExprKind::While(while_) => self.rvalue_while(e.span, *while_)?,
+      hir::ExprKind::MapFold(mf) => self.rvalue_map_fold(e.span, e.k.1.1, *mf)?,
       hir::ExprKind::Assert { trivial: Some(false), .. } |
       hir::ExprKind::Unreachable(_) |
       hir::ExprKind::Jump(_, _, _, _) |
@@ -1010,7 +1011,8 @@ impl<'a, 'n> BuildMir<'a, 'n> {
           }
           hir::ExprKind::Mm0Proof(_) |
           hir::ExprKind::Block(_) |
-          hir::ExprKind::While {..} => { this.rvalue(e)?; }
+          hir::ExprKind::While {..} |
+          hir::ExprKind::MapFold(_) => { this.rvalue(e)?; }
           hir::ExprKind::Call(call) => match call.rk {
             hir::ReturnKind::Unreachable |
             hir::ReturnKind::Unit => this.expr_call(e.span, call, e.k.1.1, &[])?,
@@ -1636,6 +1638,242 @@ impl<'a, 'n> BuildMir<'a, 'n> {
     }
   }
 
+  /// Prove `goal` from the hypothesis `h: ty` by linear arithmetic, where the first fact of `h`
+  /// and the negated goal add up to a contradiction (see [`Certificate`]).
+  fn linarith_cast(&mut self, span: &'a FileSpan, h: Operand, ty: Ty, goal: Expr) -> VarId {
+    let goal = Rc::new(TyKind::Pure(goal));
+    let cert = Rc::new(Certificate { terms: Box::new([(0, 0, 1)]), goal: 1 });
+    let pf = Constant::linarith(Rc::new(TyKind::Imp(ty, goal.clone())), cert);
+    let v = self.fresh_var_span(span.clone());
+    let k = v.k;
+    self.push_stmt(Statement::Let(
+      LetKind::Let(v, Some(Rc::new(ExprKind::Unit))), false, goal.clone(),
+      RValue::Cast(CastKind::Subtype(pf.into()), h, goal)));
+    k
+  }
+
+  /// Introduce a ghost hypothesis `v: ty` proved by `rv`.
+  fn proof_let(&mut self, span: &'a FileSpan, ty: Ty, rv: RValue) -> VarId {
+    let v = self.fresh_var_span(span.clone());
+    let k = v.k;
+    self.push_stmt(Statement::Let(LetKind::Let(v, Some(Rc::new(ExprKind::Unit))), false, ty, rv));
+    k
+  }
+
+  fn rvalue_map_fold(&mut self,
+    span: &'a FileSpan, tgt: ty::Ty<'a>,
+    hir::MapFold {idx, hyp, n, len, acc, arrays, body}: hir::MapFold<'a>,
+  ) -> Block<RValue> {
+    // A map or fold is a bounded for loop, with an invariant `i <= n` on the loop counter
+    // that we prove automatically. We generate:
+    //
+    //   v_n := len
+    //   label_group([base])
+    //   jump base(i := 0, h_i := (linarith: 0 <= n), acc := init)
+    // base(i: u64, h_i: i <= n, acc: A):
+    //   v_cond := i < v_n
+    //   if v_cond {h. goto main(h)} else {h'. goto after(h')}
+    // main(h: i < n):
+    //   x_1 := a_1[i, h]; ...; x_k := a_k[i, h]
+    //   acc' := body
+    //   i' := i + 1
+    //   h_i' := (linarith h: i' <= n)
+    //   goto base(i', h_i', acc')
+    // after(h': !(i < n)):
+    //   pop_label_group
+    //   dest := acc
+    //
+    // For a map, the accumulator is the output array `out: (array ?T n)`, which starts out
+    // uninitialized, and instead of `acc' := body` we write the result to `out[i, h]`.
+    // The loop also carries the invariant `h_out: [out[0..i] : (array T i)]`, which says that
+    // the part of the array written so far is initialized:
+    //
+    //   jump base(i := 0, h_i := (linarith: 0 <= n), out := uninit, h_out := ([]: (array T 0)))
+    // base(i: u64, h_i: i <= n, out: (array ?T n), h_out: [out[0..i] : (array T i)]):
+    //   ...
+    // main(h: i < n):
+    //   x := body; h_x := typeof x
+    //   out'[i, h] := x
+    //   h_out' := (h_out, h_x): [out'[0..i'] : (array T i')]
+    //   ...
+    //   goto base(i', h_i', out', h_out')
+    // after(h': !(i < n)):
+    //   pop_label_group
+    //   h_n := (linarith h': n <= i)
+    //   dest := cast(out, (h_out, h_i, h_n): [out : (array T n)])
+    let arrays = arrays.into_vec().into_iter().map(|(x, ty, a)| {
+      let (copy, ty) = (ty.is_copy(), self.tr(ty));
+      let (pa, aty) = (a.k.1.0.map(|pa| self.tr(pa)), self.tr(a.ty()));
+      Ok((x, copy, ty, pa, aty, self.expr_place(a)?))
+    }).collect::<Block<Vec<_>>>()?;
+    let v_n = self.as_temp(*len)?;
+    let n = self.tr(n);
+    let t_u64 = Rc::new(TyKind::Int(IntTy::UInt(Size::S64)));
+    let (acc, acc_ty, init, elem) = match acc {
+      Some((x, init)) => {
+        let ty = self.tr(init.ty());
+        let init = self.operand(*init)?;
+        (Spanned { span: x.span.clone(), k: self.tr(x.k) }, ty, init, None)
+      }
+      None => {
+        let ty::TyKind::Array(ty, _) = tgt.k else { unreachable!() };
+        let elem = self.tr(ty);
+        let ty = Rc::new(TyKind::Array(Rc::new(TyKind::Uninit(elem.clone())), n.clone()));
+        let init = Constant::uninit_core(ty.clone()).into();
+        (self.fresh_var_span(span.clone()), ty, init, Some(elem))
+      }
+    };
+    let le = |e| Rc::new(ExprKind::Binop(types::Binop::Le, e, n.clone()));
+    let zero = Rc::new(ExprKind::Int(0.into()));
+    // `[out[0..len] : (array T len)]`, the invariant of a map
+    let inv = |elem: &Ty, out: VarId, len: Expr| Rc::new(TyKind::HasTy(
+      Rc::new(ExprKind::Slice(Rc::new(ExprKind::Var(out)), zero.clone(), len.clone())),
+      Rc::new(TyKind::Array(elem.clone(), len))));
+
+    //   jump base(i := 0, h_i := (linarith: 0 <= n), acc := init)
+    let h_n_s = self.fresh_var_span(span.clone());
+    let h_n = h_n_s.k;
+    let ty_n = Rc::new(TyKind::HasTy(Rc::new(ExprKind::Var(v_n)), t_u64.clone()));
+    self.push_stmt(Statement::Let(
+      LetKind::Let(h_n_s, Some(Rc::new(ExprKind::Unit))), false, ty_n.clone(),
+      RValue::Typeof(Operand::Copy(v_n.into()))));
+    let h_0 = self.linarith_cast(span, h_n.into(), ty_n, le(zero.clone()));
+    //   h_out := ([]: (array T 0))
+    let h_out_0 = elem.as_ref().map(|elem| {
+      let ty = Rc::new(TyKind::HasTy(Rc::new(ExprKind::Array(Box::new([]))),
+        Rc::new(TyKind::Array(elem.clone(), zero.clone()))));
+      self.proof_let(span, ty, RValue::List(Box::new([])))
+    });
+    let base_ctx = self.cur_ctx;
+    let base_len = self.cfg.ctxs.len(base_ctx);
+    let i = Spanned { span: idx.span.clone(), k: self.tr(idx.k) };
+    let e_i = Rc::new(ExprKind::Var(i.k));
+    let h_i = self.fresh_var_span(span.clone());
+    let ctx = self.cfg.ctxs.extend(base_ctx, i.clone(), true, (None, t_u64.clone()));
+    let ctx = self.cfg.ctxs.extend(ctx, h_i.clone(), false,
+      (Some(Rc::new(ExprKind::Unit)), Rc::new(TyKind::Pure(le(e_i.clone())))));
+    let mut head_ctx = self.cfg.ctxs.extend(ctx, acc.clone(), true, (None, acc_ty.clone()));
+    let h_out = elem.as_ref().map(|elem| {
+      let h_out = self.fresh_var_span(span.clone());
+      head_ctx = self.cfg.ctxs.extend(head_ctx, h_out.clone(), false,
+        (Some(Rc::new(ExprKind::Unit)), inv(elem, acc.k, e_i.clone())));
+      h_out.k
+    });
+    let head = self.cfg.new_block(head_ctx, base_len);
+    self.cur_block().stmts.push(Statement::LabelGroup(std::iter::once(head).collect(), base_ctx));
+    self.tree.push_group(std::iter::once(head).collect());
+    self.tree.push(head);
+    let mut args = vec![
+      (i.k, true, Constant::int(IntTy::UInt(Size::S64), 0.into()).into()),
+      (h_i.k, false, h_0.into()),
+      (acc.k, true, init),
+    ];
+    if let (Some(h_out), Some(h_out_0)) = (h_out, h_out_0) {
+      args.push((h_out, false, h_out_0.into()))
+    }
+    self.cur_block().terminate(Terminator::Jump(head, args.into(), None));
+    let gen = self.tr.cur_gen;
+    self.set((head, head_ctx, gen));
+
+    //   v_cond := i < v_n
+    //   if v_cond {h. goto main(h)} else {h'. goto after(h')}
+    let cond = Rc::new(ExprKind::Binop(types::Binop::Lt, e_i.clone(), n.clone()));
+    let v_cond_s = self.fresh_var_span(span.clone());
+    let v_cond = v_cond_s.k;
+    self.push_stmt(Statement::Let(
+      LetKind::Let(v_cond_s, Some(cond.clone())), true, Rc::new(TyKind::Bool),
+      RValue::Binop(Binop::Lt(IntTy::UInt(Size::S64)),
+        Operand::Copy(i.k.into()), Operand::Copy(v_n.into()))));
+    let vh = Spanned { span: hyp.span.clone(), k: self.tr(hyp.k) };
+    let test_ctx = self.cur_ctx;
+    let test_len = self.cfg.ctxs.len(test_ctx);
+    let t_cond = Rc::new(TyKind::Pure(cond));
+    let tru_ctx = self.cfg.ctxs.extend(test_ctx, vh.clone(), false,
+      (Some(Rc::new(ExprKind::Unit)), t_cond.clone()));
+    let tru = self.cfg.new_block(tru_ctx, test_len);
+    let fal_ctx = self.cfg.ctxs.extend(test_ctx, vh.clone(), false,
+      (Some(Rc::new(ExprKind::Unit)), Rc::new(TyKind::Not(t_cond.clone()))));
+    let fal = self.cfg.new_block(fal_ctx, test_len);
+    self.cur_block().terminate(
+      Terminator::If(test_ctx, v_cond.into(), [(vh.k, tru), (vh.k, fal)]));
+    self.cfg[fal].stmts.push(Statement::PopLabelGroup);
+
+    // The body of the loop. If it diverges then there is no back edge,
+    // but `after` is still reachable.
+    self.set((tru, tru_ctx, gen));
+    let _ = (|| -> Block<()> {
+      //   x_j := a_j[i, h]
+      for (x, copy, ty, pa, aty, place) in arrays {
+        let place = place.proj((aty, Projection::Index(i.k, vh.k)));
+        let x = Spanned { span: x.span.clone(), k: self.tr(x.k) };
+        let val = pa.map(|pa| Rc::new(ExprKind::Index(pa, e_i.clone())));
+        self.push_stmt(Statement::Let(LetKind::Let(x, val), true, ty,
+          RValue::Use(if copy { Operand::Copy(place) } else { Operand::Ref(place) })));
+      }
+      let e_i2 = Rc::new(ExprKind::Binop(types::Binop::Add,
+        e_i.clone(), Rc::new(ExprKind::Int(1.into()))));
+      let (acc2, h_out2) = if let (Some(elem), Some(h_out)) = (&elem, h_out) {
+        //   x := body; h_x := typeof x
+        let ty = self.tr(body.ty());
+        let x = self.as_temp(*body)?;
+        let ty_x = Rc::new(TyKind::HasTy(Rc::new(ExprKind::Var(x)), ty.clone()));
+        let h_x = self.proof_let(span, ty_x, RValue::Typeof(Operand::Copy(x.into())));
+        //   out'[i, h] := x
+        let out = Place::local(acc.k).proj((acc_ty.clone(), Projection::Index(i.k, vh.k)));
+        let to = self.fresh_var_span(span.clone());
+        let k = to.k;
+        self.push_stmt(Statement::Assign(out, ty, Operand::Move(x.into()), Box::new([Rename {
+          from: acc.k, to, rel: true, ety: (None, acc_ty.clone())
+        }])));
+        //   h_out' := (h_out, h_x): [out'[0..i'] : (array T i')]
+        let h_out2 = self.proof_let(span, inv(elem, k, e_i2.clone()),
+          RValue::List(Box::new([Operand::Copy(h_out.into()), Operand::Copy(h_x.into())])));
+        (k, Some(h_out2))
+      } else {
+        //   acc' := body
+        (self.as_temp(*body)?, None)
+      };
+      //   i' := i + 1
+      let i2_s = self.fresh_var_span(span.clone());
+      let i2 = i2_s.k;
+      self.push_stmt(Statement::Let(
+        LetKind::Let(i2_s, Some(e_i2.clone())), true, t_u64.clone(),
+        RValue::Binop(Binop::Add(IntTy::UInt(Size::S64)),
+          Operand::Copy(i.k.into()), Constant::int(IntTy::UInt(Size::S64), 1.into()).into())));
+      //   h_i' := (linarith h: i' <= n)
+      let h_i2 = self.linarith_cast(span, Operand::Copy(vh.k.into()), t_cond.clone(), le(e_i2));
+      //   goto base(i', h_i', acc')
+      let mut args = vec![
+        (i.k, true, Operand::Copy(i2.into())),
+        (h_i.k, false, h_i2.into()),
+        (acc.k, true, Operand::Move(acc2.into())),
+      ];
+      if let (Some(h_out), Some(h_out2)) = (h_out, h_out2) {
+        args.push((h_out, false, h_out2.into()))
+      }
+      self.cur_block().terminate(Terminator::Jump(head, args.into(), None));
+      Ok(())
+    })();
+
+    //   dest := acc
+    self.tree.pop();
+    self.tree.push(fal);
+    self.set((fal, fal_ctx, gen));
+    Ok(if let Some(h_out) = h_out {
+      //   h_n := (linarith h': n <= i)
+      let h_n = self.linarith_cast(span, Operand::Copy(vh.k.into()),
+        Rc::new(TyKind::Not(t_cond)), Rc::new(ExprKind::Binop(types::Binop::Le, n, e_i)));
+      //   dest := cast(out, (h_out, h_i, h_n): [out : (array T n)])
+      let tgt = self.tr(tgt);
+      let ty_h = Rc::new(TyKind::HasTy(Rc::new(ExprKind::Var(acc.k)), tgt.clone()));
+      let hs = [h_out, h_i.k, h_n].map(|h| Operand::Copy(h.into()));
+      let h = self.proof_let(span, ty_h, RValue::List(Box::new(hs)));
+      RValue::Cast(CastKind::Mem(Operand::Copy(h.into())), Operand::Move(acc.k.into()), tgt)
+    } else {
+      RValue::Use(Operand::Move(acc.k.into()))
+    })
+  }
+
   fn expr_call(&mut self, span: &'a FileSpan,
     hir::Call {f, side_effect: se, tys, args, variant, gen, rk}: hir::Call<'a>,
     tgt: ty::Ty<'a>,
@@ -1716,7 +1954,7 @@ impl<'a, 'n> BuildMir<'a, 'n> {
   ) -> Option<Symbol> {
     self.cfg.span = it.span.clone();
     match it.k {
-      hir::ItemKind::Proc { kind, name, tyargs, args, gen, outs, rets, variant, body } => {
+      hir::ItemKind::Proc { kind, inline, name, tyargs, args, gen, outs, rets, variant, body } => {
         fn tr_attr(attr: ty::ArgAttr) -> ArgAttr {
           let mut out = ArgAttr::empty();
           if attr.contains(ty::ArgAttr::NONDEP) { out |= ArgAttr::NONDEP }
@@ -1751,6 +1989,7 @@ impl<'a, 'n> BuildMir<'a, 'n> {
         mir.insert(name.k, Proc {
           kind,
           name: Spanned {span: name.span.clone(), k: name.k},
+          inline,
           tyargs,
           args: args2,
           outs,
@@ -5,7 +5,8 @@
 //! in [`crate::arch::Inst`] have one to one correspondence to instructions of
 //! the ISA, except that they use virtual registers instead of physical
 //! registers. So the main role of this pass is to translate MIR operations
-//! into sequences of x86 instructions.
+//! into sequences of machine instructions. The pass is generic over the target,
+//! which supplies instruction selection via the `Lower` trait.
 
 use std::collections::HashMap;
 use std::rc::Rc;
@@ -17,19 +18,17 @@ use regalloc2::Operand as ROperand;
 use crate::linker::ConstData;
 use crate::types::entity::{IntrinsicProc, ProcTc, ProcTy};
 use crate::{Symbol, Entity};
-use crate::arch::{AMode, Binop as VBinop, CC, Cmp, ExtMode, Inst, PReg, RegMem, RegMemImm,
-  RET_AND_ARG_REGS, SYSCALL_ARG_REGS, ShiftKind, SysCall, Unop as VUnop};
+use crate::arch::{AMode, Binop as VBinop, CC, Cmp, ExtMode, Lower, PReg, RegMem, RegMemImm,
+  ShiftKind, SysCall, Unop as VUnop};
 use crate::mir_opt::BitSet;
 use crate::mir_opt::storage::{Allocations, AllocId};
 use crate::types::{Idx, IdxVec, IntTy, Size, Spanned, classify as cl};
-use crate::types::vcode::{self, ArgAbi, BlockId as VBlockId,
-  ChunkVec, ConstRef, InstId, GlobalId, ProcAbi, ProcId, SpillId, VReg};
+use crate::types::vcode::{ArgAbi, BlockId as VBlockId,
+  ChunkVec, ConstRef, InstId, GlobalId, ProcAbi, ProcId, SpillId, VCode, VReg};
 
 #[allow(clippy::wildcard_imports)]
 use crate::types::mir::*;
 
-pub(crate) type VCode = vcode::VCode<Inst>;
-
 /// A very simple jump threading visitor. Start at an unvisited basic block, then follow forward
 /// edges to unvisited basic blocks as long as possible. Then start over somewhere else.
 /// This ordering is good for code placement since a jump or branch to the immediately following
@@ -152,14 +151,14 @@ impl<'a> From<&'a [Arg]> for VCodeCtx<'a> {
     fn from(v: &'a [Arg]) -> Self { Self::Proc(v) }
 }
 
-struct LowerCtx<'a> {
+struct LowerCtx<'a, I> {
   cfg: &'a Cfg,
   allocs: &'a Allocations,
   names: &'a HashMap<Symbol, Entity>,
   func_mono: &'a HashMap<Symbol, ProcId>,
   funcs: &'a IdxVec<ProcId, ProcAbi>,
   consts: &'a ConstData,
-  code: VCode,
+  code: VCode<I>,
   var_map: HashMap<AllocId, (RegMem, Size)>,
   ctx: TyCtx<'a>,
   unpatched: Vec<(VBlockId, InstId)>,
@@ -169,7 +168,7 @@ struct LowerCtx<'a> {
   can_return: bool,
 }
 
-impl<'a> LowerCtx<'a> {
+impl<'a, I> LowerCtx<'a, I> where VCode<I>: Lower {
   /// Create a new lowering context.
   fn new(
     names: &'a HashMap<Symbol, Entity>,
@@ -212,8 +211,6 @@ impl<'a> LowerCtx<'a> {
     }
   }
 
-  fn emit(&mut self, inst: Inst) -> InstId { self.code.emit(inst) }
-
   fn get_alloc(&mut self, a: AllocId) -> (&(RegMem, Size), u64) {
     assert_ne!(a, AllocId::ZERO);
     let m = self.allocs[a].m;
@@ -331,6 +328,7 @@ impl<'a> LowerCtx<'a> {
       ConstKind::Unit |
       ConstKind::ITrue |
       ConstKind::Mm0Proof(_) |
+      ConstKind::Linarith(_) |
       ConstKind::Contra(_, _) => unreachable!("unexpected ZST"),
       #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
       ConstKind::As(ref c) => {
@@ -441,11 +439,7 @@ impl<'a> LowerCtx<'a> {
     Ok((cl1, match ExtMode::new(sz, to.size()) {
       None => cl::As::Truncate(self.code.emit_copy(sz, dst, src)),
       Some(ext_mode) => {
-        let temp = self.code.fresh_vreg();
-        self.code.emit(match to.signed() {
-          true => Inst::MovsxRmR { ext_mode, dst: temp, src },
-          false => Inst::MovzxRmR { ext_mode, dst: temp, src },
-        });
+        let temp = self.code.emit_extend(to.signed(), ext_mode, src);
         cl::As::Extend(self.code.emit_copy(to.size(), dst, temp))
       }
     }))
@@ -463,6 +457,10 @@ impl<'a> LowerCtx<'a> {
     _tysize: u64, sz: Size, dst: RegMem, o: &Operand
   ) -> Result<cl::Move, GhostErr> {
     if sz == Size::Inf {
+      // An uninitialized value has no contents to copy
+      if matches!(o, Operand::Const(c) if matches!(c.k, ConstKind::Uninit)) {
+        return Ok(cl::Move::Uninit)
+      }
       unimplemented!("large copy");
     } else {
       let (src, cl1) = self.get_operand(o)?;
@@ -503,7 +501,7 @@ impl<'a> LowerCtx<'a> {
         let sz = ity.size(); assert_ne!(sz, Size::Inf);
         let (src1, cl1) = self.get_operand_reg(o1, sz)?;
         let (src2, cl2) = self.get_operand_rm(o2, sz)?;
-        let temp = self.code.emit_mul(sz, src1, src2).0;
+        let temp = self.code.emit_mul(sz, src1, src2);
         cl::RValue::Mul(cl1, cl2, self.code.emit_copy(sz, dst, temp))
       }
       RValue::Binop(Binop::Sub(ity), o1, o2) =>
@@ -563,7 +561,8 @@ impl<'a> LowerCtx<'a> {
           let (cl1, cl2) = self.build_as(dst, from, to, o)?;
           cl::RValue::Cast(cl1, cl2)
         } else {
-          unimplemented!("casting between non-integral types: {:?} -> {:?}", tyin, ty)
+          // Casts between non-integral types only change the type, not the representation
+          cl::RValue::Use(self.build_move(tysize, sz, dst, o)?)
         },
       RValue::List(os) => {
         let TyKind::Struct(args) = ty else { unreachable!() };
@@ -652,10 +651,10 @@ impl<'a> LowerCtx<'a> {
       self.code.trace.lists.push(cl);
     }
     assert!(params_it.peek().is_none());
-    self.unpatched.push((vbl, self.code.emit(Inst::JmpKnown {
-      dst: VBlockId(tgt.0),
-      params: params.iter().map(|v| v.0).collect()
-    })));
+    self.unpatched.push((vbl, self.code.emit_jump(
+      VBlockId(tgt.0),
+      params.iter().map(|v| v.0).collect()
+    )));
     Ok(cl::Terminator::Jump(args.len().try_into().expect("overflow")))
   }
 
@@ -684,15 +683,14 @@ impl<'a> LowerCtx<'a> {
           cl::Elem::Move(self.build_move(sz, Size::from_u64(sz), AMode::reg(dst).into(), o)?)
         }
         VRetAbi::BoxedMem { off, sz } => {
-          let ptr = self.code.fresh_vreg();
-          self.code.emit(Inst::load_mem(Size::S64, ptr, &incoming + off));
+          let ptr = self.code.emit_load(Size::S64, &incoming + off);
           let sz = sz.into();
           cl::Elem::Move(self.build_move(sz, Size::from_u64(sz), AMode::reg(ptr).into(), o)?)
         }
       };
       self.code.trace.lists.push(cl);
     }
-    self.code.emit(Inst::Epilogue { params: params.into() });
+    self.code.emit_epilogue(params.into());
     Ok(())
   }
 
@@ -784,11 +782,7 @@ impl<'a> LowerCtx<'a> {
           self.code.trace.lists.push(cl::Elem::RetArg(cl::IntoMem(cl)))
         }
       }
-      self.emit(Inst::CallKnown {
-        f,
-        operands: operands.into(),
-        clobbers: Some(fabi.clobbers.clone()),
-      });
+      self.code.emit_call(f, operands.into(), Some(fabi.clobbers.clone()));
       let mut ret_regs = ret_regs.into_iter();
       for (arg, &(vr, v)) in fabi.rets.iter().zip(rets) {
         if !vr { continue }
@@ -809,12 +803,10 @@ impl<'a> LowerCtx<'a> {
         self.code.trace.lists.push(cl)
       }
       for (sz, dst, a) in boxes { let _ = self.code.emit_copy(sz, dst.into(), a); }
-      self.unpatched.push((vbl, self.code.emit(Inst::Fallthrough {
-        dst: VBlockId(tgt.0),
-      })));
+      self.unpatched.push((vbl, self.code.emit_fallthrough(VBlockId(tgt.0))));
     } else {
       assert!(!fabi.reach);
-      self.emit(Inst::CallKnown { f, operands: operands.into(), clobbers: None });
+      self.code.emit_call(f, operands.into(), None);
     }
     Ok(cl::Terminator::Call(f))
   }
@@ -871,6 +863,42 @@ impl<'a> LowerCtx<'a> {
         ]);
         (SysCall::MMap, ret)
       }
+      (IntrinsicProc::MUnmap, &[ret], [(true, len), (_, _buf), (true, p)]) => {
+        rmis.extend([self.get_operand(p)?, self.get_operand(len)?]);
+        (SysCall::MUnmap, ret)
+      }
+      (IntrinsicProc::Brk, &[ret], [(true, addr)]) => {
+        rmis.push(self.get_operand(addr)?);
+        (SysCall::Brk, ret)
+      }
+      (IntrinsicProc::Close, &[ret], [(true, fd)]) => {
+        rmis.push(self.get_operand(fd)?);
+        (SysCall::Close, ret)
+      }
+      (IntrinsicProc::LSeek, &[ret], [(true, fd), (true, off), (true, whence)]) => {
+        rmis.extend([self.get_operand(fd)?, self.get_operand(off)?, self.get_operand(whence)?]);
+        (SysCall::LSeek, ret)
+      }
+      (IntrinsicProc::Pipe, &[(_, _fds_new), ret], [(_, _fds_old), (true, p)]) => {
+        rmis.push(self.get_operand(p)?);
+        (SysCall::Pipe, ret)
+      }
+      (IntrinsicProc::Dup2, &[ret], [(true, oldfd), (true, newfd)]) => {
+        rmis.extend([self.get_operand(oldfd)?, self.get_operand(newfd)?]);
+        (SysCall::Dup2, ret)
+      }
+      (IntrinsicProc::ClockGetTime, &[(_, _buf_new), ret],
+        [(true, clock), (_, _buf_old), (true, p)]
+      ) => {
+        rmis.extend([self.get_operand(clock)?, self.get_operand(p)?]);
+        (SysCall::ClockGetTime, ret)
+      }
+      (IntrinsicProc::GetRandom, &[(_, _buf_new), ret],
+        [(true, count), (_, _buf_old), (true, p)]
+      ) => {
+        rmis.extend([self.get_operand(p)?, self.get_operand(count)?, (0.into(), CV)]);
+        (SysCall::GetRandom, ret)
+      }
       e => panic!("intrinsic has the wrong number of arguments: {e:?}")
     };
     let vreg = self.code.fresh_vreg();
@@ -883,24 +911,26 @@ impl<'a> LowerCtx<'a> {
     } else {
       None
     };
-    self.unpatched.push((vbl, self.code.emit(Inst::Fallthrough { dst: VBlockId(tgt.0) })));
+    self.unpatched.push((vbl, self.code.emit_fallthrough(VBlockId(tgt.0))));
     Ok(cl::Terminator::Intrinsic(intrinsic, cl2))
   }
 
   fn build_syscall(&mut self, f: SysCall, args: &[(RegMemImm<u64>, cl::Operand)], dst: VReg) {
-    let (rax, ref argregs) = SYSCALL_ARG_REGS;
+    let (fnreg, retreg, argregs) = VCode::<I>::SYSCALL_REGS;
+    let mut args = args.iter().copied().collect();
+    let num = VCode::<I>::syscall_args(f, &mut args);
     debug_assert!(args.len() <= argregs.len());
     let fname = self.code.fresh_vreg();
-    let _ = self.code.emit_copy(Size::S32, fname.into(), u64::from(f as u8));
-    let mut params = vec![ROperand::reg_fixed_use(fname.0, rax.0)];
-    for ((arg, cl), &reg) in args.iter().zip(argregs) {
+    let _ = self.code.emit_copy(Size::S32, fname.into(), u64::from(num));
+    let mut params = vec![ROperand::reg_fixed_use(fname.0, fnreg.0)];
+    for ((arg, cl), &reg) in args.into_iter().zip(argregs) {
       let dst = self.code.fresh_vreg();
-      let _ = self.code.emit_copy(Size::S64, dst.into(), *arg);
+      let _ = self.code.emit_copy(Size::S64, dst.into(), arg);
       params.push(ROperand::reg_fixed_use(dst.0, reg.0));
-      self.code.trace.lists.push(cl::Elem::Operand(*cl))
+      self.code.trace.lists.push(cl::Elem::Operand(cl))
     }
-    if f.returns() { params.push(ROperand::reg_fixed_def(dst.0, rax.0)) }
-    self.code.emit(Inst::SysCall { f, operands: params.into() });
+    if f.returns() { params.push(ROperand::reg_fixed_def(dst.0, retreg.0)) }
+    self.code.emit_syscall(f, params.into());
   }
 
   fn build_terminator(&mut self,
@@ -909,9 +939,7 @@ impl<'a> LowerCtx<'a> {
     Ok(match *term {
       Terminator::Jump(tgt, ref args, _) => self.build_jump(vbl, block_args, tgt, args)?,
       Terminator::Jump1(_, tgt) => {
-        self.unpatched.push((vbl, self.code.emit(Inst::Fallthrough {
-          dst: VBlockId(tgt.0)
-        })));
+        self.unpatched.push((vbl, self.code.emit_fallthrough(VBlockId(tgt.0))));
         cl::Terminator::Jump1
       }
       Terminator::Return(_, ref args) => {
@@ -936,7 +964,7 @@ impl<'a> LowerCtx<'a> {
         cl::Terminator::Assert(cl1)
       }
       Terminator::Fail => {
-        self.code.emit(Inst::Ud2);
+        self.code.emit_trap();
         cl::Terminator::Fail
       }
       Terminator::Call { f, ref tys, ref args, reach, tgt, ref rets, .. } => {
@@ -996,7 +1024,7 @@ impl<'a> LowerCtx<'a> {
   }
 
   fn build_prologue(&mut self, bl: &'a BasicBlock, ctx: VCodeCtx<'_>) {
-    let mut arg_regs = RET_AND_ARG_REGS.iter();
+    let mut arg_regs = VCode::<I>::ARG_REGS.iter();
     let incoming = AMode::spill(SpillId::INCOMING);
     let mut off = 0_u32;
     let mut alloc = |sz| {
@@ -1016,7 +1044,7 @@ impl<'a> LowerCtx<'a> {
           (false, Some(&r)) => VRetAbi::Reg(r, sz),
           (true, Some(&r)) => {
             let ptr = self.code.fresh_vreg();
-            self.code.emit(Inst::MovPR { dst: ptr, src: r });
+            self.code.emit_mov_preg(ptr, r);
             VRetAbi::Boxed { reg: (ptr, r), sz: size.try_into().expect("overflow") }
           }
           (_, None) if size <= 8 => {
@@ -1036,13 +1064,13 @@ impl<'a> LowerCtx<'a> {
       match (dst, arg_regs.next()) {
         (RegMem::Reg(dst), Some(&r)) => {
           let src = self.code.fresh_vreg();
-          self.code.emit(Inst::MovPR { dst: src, src: r });
-          self.code.emit(Inst::MovRR { dst, src });
+          self.code.emit_mov_preg(src, r);
+          self.code.emit_mov(dst, src);
           ArgAbi::Reg(r, sz)
         },
         (RegMem::Mem(_), Some(&r)) => {
           let src = self.code.fresh_vreg();
-          self.code.emit(Inst::MovPR { dst: src, src: r });
+          self.code.emit_mov_preg(src, r);
           let size32 = size.try_into().expect("overflow");
           let cl = self.build_memcpy(size, sz, dst, AMode::reg(src));
           self.code.trace.lists.push(cl::Elem::ArgCopy(cl));
@@ -1128,7 +1156,7 @@ impl<'a> LowerCtx<'a> {
         let a = self.allocs.get(v.k);
         assert_ne!(a, AllocId::ZERO);
         let val = self.get_alloc(a).0.0;
-        self.code.emit(Inst::BlockParam { var: v.k, val });
+        self.code.emit_block_param(v.k, val);
       }
       self.build_block(block_args, bl, vblock).map_err(|err| match err {
         GhostErr::GhostVarUsed(v) => {
@@ -1141,25 +1169,9 @@ impl<'a> LowerCtx<'a> {
     })
   }
 
-  fn finish(self) -> VCode {
+  fn finish(self) -> VCode<I> {
     let LowerCtx { mut code, unpatched, abi_args, abi_rets, can_return, .. } = self;
-    macro_rules! patch {($dst:expr) => {{ *$dst = code.block_map[&BlockId($dst.0)]; *$dst }}}
-    for (vbl, inst) in unpatched {
-      match &mut code.insts[inst] {
-        Inst::Fallthrough { dst } |
-        Inst::Assert { dst, .. } |
-        Inst::JmpKnown { dst, .. } => {
-          let dst = patch!(dst);
-          code.add_edge(vbl, dst)
-        }
-        Inst::JmpCond { taken, not_taken, .. } => {
-          let (bl1, bl2) = (patch!(taken), patch!(not_taken));
-          code.add_edge(vbl, bl1);
-          code.add_edge(vbl, bl2);
-        }
-        _ => unreachable!(),
-      }
-    }
+    for (vbl, inst) in unpatched { code.patch_branch(vbl, inst) }
     code.abi.args = abi_args.into();
     code.abi.rets = abi_rets.iter().map(ArgAbi::from).collect();
     code.abi.reach = can_return;
@@ -1168,7 +1180,7 @@ impl<'a> LowerCtx<'a> {
   }
 }
 
-pub(crate) fn build_vcode(
+pub(crate) fn build_vcode<I>(
   names: &HashMap<Symbol, Entity>,
   func_mono: &HashMap<Symbol, ProcId>,
   funcs: &IdxVec<ProcId, ProcAbi>,
@@ -1176,7 +1188,7 @@ pub(crate) fn build_vcode(
   cfg: &Cfg,
   allocs: &Allocations,
   ctx: VCodeCtx<'_>,
-) -> Result<VCode, LowerErr> {
+) -> Result<VCode<I>, LowerErr> where VCode<I>: Lower {
   let mut lctx = LowerCtx::new(names, func_mono, funcs, consts, cfg, allocs, ctx);
   let block_args = lctx.build_block_args()?;
   lctx.build_blocks(&block_args, ctx)?;
@@ -0,0 +1,147 @@
+//! Reuse of compilation results for procedures that have not changed.
+//!
+//! When the same source is elaborated repeatedly (for example by the language server after every
+//! edit), most procedures are the same from one run to the next. Every procedure added to the
+//! compiler is stored here together with a key summarizing everything its compilation depended on:
+//! the item itself, and the global names it looked up during typechecking. Adding an item whose key
+//! matches the stored one reuses the previous declaration and MIR, and the linker does the same
+//! for the generated machine code. Source positions are not part of the key, so a procedure that
+//! has only moved in the file is also reused.
+
+use std::collections::{HashMap, hash_map::DefaultHasher};
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use crate::linker::ConstData;
+use crate::regalloc::PCode;
+use crate::types::{IdxVec, Spanned, VarId, ast, entity::Entity, mir, vcode::{ProcAbi, ProcId}};
+use crate::{Config, ItemContext, Symbol};
+
+/// An adapter that feeds formatted text to a hasher, so that data structures that implement
+/// `Debug` but not `Hash` can be hashed.
+struct HashWriter<'a, H>(&'a mut H);
+
+impl<H: Hasher> std::fmt::Write for HashWriter<'_, H> {
+  fn write_str(&mut self, s: &str) -> std::fmt::Result {
+    self.0.write(s.as_bytes());
+    Ok(())
+  }
+}
+
+/// Hash the `Debug` representation of `t`.
+pub(crate) fn hash_debug(h: &mut impl Hasher, t: &impl Debug) {
+  use std::fmt::Write;
+  write!(HashWriter(h), "{t:?}").expect("impossible")
+}
+
+/// Hash an item as passed to [`Compiler::add`](crate::Compiler::add). This does not include the
+/// dependencies of the item, which are only known after typechecking. The `Debug` output of
+/// [`Spanned`] leaves out the span, so the hash does not depend on where the item is in the file.
+pub(crate) fn hash_item<C: Config>(item: &ast::Item,
+  var_names: &IdxVec<VarId, Spanned<Symbol>>, ic: &impl ItemContext<C>
+) -> u64 {
+  let mut h = DefaultHasher::new();
+  hash_debug(&mut h, item);
+  hash_debug(&mut h, var_names);
+  ic.hash_item(&mut h);
+  h.finish()
+}
+
+/// The result of adding a procedure.
+#[derive(Debug)]
+struct CachedProc {
+  /// The hash of the item, from [`hash_item`].
+  item: u64,
+  /// The global names that were looked up while typechecking the item, in sorted order.
+  deps: Box<[Symbol]>,
+  /// The key of the procedure, from [`ItemCache::key`].
+  key: u64,
+  /// The declaration of the procedure.
+  entity: Entity,
+  /// The optimized MIR of the procedure, or `None` for an intrinsic.
+  mir: Option<mir::Proc>,
+}
+
+/// The machine code of a procedure, produced by the linker.
+#[derive(Debug)]
+pub(crate) struct CachedCode<I> {
+  /// A hash of the procedure key and the program-wide data used during lowering.
+  pub(crate) key: u64,
+  /// The calling convention of the procedure.
+  pub(crate) abi: ProcAbi,
+  /// The code of the procedure.
+  pub(crate) code: Box<PCode<I>>,
+}
+
+/// The machine code of procedures from previous calls to the linker, indexed by name.
+pub(crate) type CodeCache<I> = HashMap<Symbol, CachedCode<I>>;
+
+/// The stored results of compiling procedures, indexed by name. Only the most recent version of
+/// each procedure is kept.
+#[derive(Debug, Default)]
+pub(crate) struct ItemCache {
+  /// The typechecked procedures.
+  procs: HashMap<Symbol, CachedProc>,
+  /// The x86 machine code for procedures.
+  pub(crate) code: CodeCache<crate::arch::PInst>,
+}
+#[cfg(feature = "memory")]
+mm0_deepsize::deep_size_0!(ItemCache);
+
+impl ItemCache {
+  /// Compute the key of a procedure with hash `item` and dependencies `deps`. A dependency which
+  /// is a procedure added to the current compiler contributes its own key (from `keys`), because
+  /// its body may be inlined; other dependencies contribute their current declaration.
+  fn key(item: u64, deps: &[Symbol],
+    names: &HashMap<Symbol, Entity>, keys: &HashMap<Symbol, u64>
+  ) -> u64 {
+    let mut h = DefaultHasher::new();
+    item.hash(&mut h);
+    for dep in deps {
+      dep.hash(&mut h);
+      match keys.get(dep) {
+        Some(key) => key.hash(&mut h),
+        None => hash_debug(&mut h, &names.get(dep)),
+      }
+    }
+    h.finish()
+  }
+
+  /// Look up procedure `name` with item hash `item`, returning its key, declaration and MIR if
+  /// none of its dependencies have changed.
+  pub(crate) fn get(&self, name: Symbol, item: u64,
+    names: &HashMap<Symbol, Entity>, keys: &HashMap<Symbol, u64>
+  ) -> Option<(u64, &Entity, Option<&mir::Proc>)> {
+    let p = self.procs.get(&name).filter(|p| p.item == item)?;
+    (Self::key(item, &p.deps, names, keys) == p.key).then_some((p.key, &p.entity, p.mir.as_ref()))
+  }
+
+  /// Store the result of adding procedure `name`, whose declaration is now in `names`,
+  /// and return its key.
+  pub(crate) fn insert(&mut self, name: Symbol, item: u64, mut deps: Vec<Symbol>,
+    names: &HashMap<Symbol, Entity>, keys: &HashMap<Symbol, u64>, mir: Option<&mir::Proc>
+  ) -> u64 {
+    deps.sort_unstable();
+    deps.dedup();
+    let key = Self::key(item, &deps, names, keys);
+    let entity = names[&name].clone();
+    self.procs.insert(name, CachedProc {
+      item, deps: deps.into(), key, entity, mir: mir.cloned()
+    });
+    key
+  }
+}
+
+/// Compute the key for the code of a procedure with key `key`. Besides the MIR of the procedure,
+/// lowering depends on the numbering of all functions, the calling conventions of the functions
+/// that have been lowered so far, and the constant table.
+pub(crate) fn code_key(key: u64,
+  funcs: &IdxVec<ProcId, Symbol>, func_abi: &IdxVec<ProcId, ProcAbi>, consts: &ConstData
+) -> u64 {
+  let mut h = DefaultHasher::new();
+  key.hash(&mut h);
+  hash_debug(&mut h, funcs);
+  hash_debug(&mut h, func_abi);
+  for c in &consts.ordered { hash_debug(&mut h, &(c, consts[*c])) }
+  consts.rodata.hash(&mut h);
+  h.finish()
+}
@@ -0,0 +1,346 @@
+//! Optional debugging information for the generated ELF file.
+//!
+//! The proved ELF file has a single program header and no sections. For use with debuggers and
+//! profilers, [`LinkedCode::write_elf_debug`] appends a section header table, a `.symtab`
+//! with the procedure, constant and global names, and a DWARF `.debug_line` table mapping
+//! instruction addresses back to source lines. All of this data is placed after the end of the
+//! loaded segment, so the loaded program is byte-for-byte the same as the one produced by
+//! [`LinkedCode::write_elf`]; only the section header fields of the ELF header differ.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use byteorder::{LE, WriteBytesExt};
+use mm0_util::{FileRef, FileSpan};
+use crate::{LinkedCode, Symbol, TEXT_START, intern};
+use crate::codegen::{align_to, BSS_ALIGN, FUNCTION_ALIGN};
+use crate::proof::{AssemblyItem, Inst};
+use crate::types::{classify::{self, TraceIter}, mir, vcode::{ArgAbi, ProcAbi, ProcId}, IdxVec};
+
+/// The virtual address corresponding to file offset 0 in the loaded segment.
+const LOAD_BASE: u64 = TEXT_START as u64 - 0x78;
+
+/// Section indices in the section header table. These must agree with the order of the
+/// sections in [`LinkedCode::write_elf_debug`].
+const SHN_TEXT: u16 = 1;
+const SHN_RODATA: u16 = 2;
+const SHN_BSS: u16 = 3;
+const SHN_STRTAB: u16 = 5;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_NOBITS: u32 = 8;
+const SHF_WRITE: u64 = 1;
+const SHF_ALLOC: u64 = 2;
+const SHF_EXECINSTR: u64 = 4;
+
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+const DW_LNS_COPY: u8 = 1;
+const DW_LNS_ADVANCE_PC: u8 = 2;
+const DW_LNS_ADVANCE_LINE: u8 = 3;
+const DW_LNS_SET_FILE: u8 = 4;
+const DW_LNE_END_SEQUENCE: u8 = 1;
+const DW_LNE_SET_ADDRESS: u8 = 2;
+/// The number of standard opcodes, plus one (DWARF 3).
+const OPCODE_BASE: u8 = 13;
+const STANDARD_OPCODE_LENGTHS: [u8; OPCODE_BASE as usize - 1] = [0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1];
+
+fn write_uleb(w: &mut Vec<u8>, mut n: u64) {
+  loop {
+    #[allow(clippy::cast_possible_truncation)]
+    let byte = (n & 0x7f) as u8;
+    n >>= 7;
+    if n == 0 { return w.push(byte) }
+    w.push(byte | 0x80)
+  }
+}
+
+fn write_sleb(w: &mut Vec<u8>, mut n: i64) {
+  loop {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let byte = (n & 0x7f) as u8;
+    n >>= 7;
+    if (n == 0 && byte & 0x40 == 0) || (n == -1 && byte & 0x40 != 0) { return w.push(byte) }
+    w.push(byte | 0x80)
+  }
+}
+
+/// A string table section, which is a sequence of null-terminated strings.
+struct StrTab(Vec<u8>);
+
+impl StrTab {
+  fn new() -> Self { Self(vec![0]) }
+  fn add(&mut self, s: impl std::fmt::Display) -> u32 {
+    let n = self.0.len().try_into().expect("overflow");
+    write!(self.0, "{s}\0").expect("impossible");
+    n
+  }
+}
+
+/// A symbol table section, together with its string table.
+struct SymTab {
+  syms: Vec<u8>,
+  /// The number of symbols in the table, including the null symbol.
+  len: u32,
+  strtab: StrTab,
+}
+
+impl SymTab {
+  /// The size of an `Elf64_Sym` entry.
+  const ENTSIZE: usize = 24;
+
+  fn new() -> Self { Self { syms: vec![0; Self::ENTSIZE], len: 1, strtab: StrTab::new() } }
+
+  fn push(&mut self, name: Symbol, info: u8, shndx: u16, value: u64, size: u64) {
+    self.syms.write_u32::<LE>(self.strtab.add(name)).expect("impossible");
+    self.syms.push(info);
+    self.syms.push(0); // st_other
+    self.syms.write_u16::<LE>(shndx).expect("impossible");
+    self.syms.write_u64::<LE>(value).expect("impossible");
+    self.syms.write_u64::<LE>(size).expect("impossible");
+    self.len += 1;
+  }
+}
+
+/// Collects the source span for each instruction in a procedure.
+struct LineVisitor<'a> {
+  /// The virtual address of the current procedure.
+  start: u32,
+  /// The span of the statement being visited.
+  span: &'a FileSpan,
+  /// The list of `(addr, span)` rows, deduplicated so that consecutive rows have different spans.
+  rows: Vec<(u32, &'a FileSpan)>,
+}
+
+impl<'a> classify::Visitor<'a> for LineVisitor<'a> {
+  fn on_inst(&mut self, _: &TraceIter<'a>, _: bool, inst: &Inst<'a>) {
+    if inst.layout.len() != 0 && self.rows.last().is_none_or(|&(_, sp)| sp != self.span) {
+      self.rows.push((self.start + inst.start, self.span))
+    }
+  }
+
+  fn before_stmt(&mut self, _: &TraceIter<'a>,
+    stmt: &'a mir::Statement, _: &'a classify::Statement
+  ) {
+    match stmt {
+      mir::Statement::Let(mir::LetKind::Let(v, _) | mir::LetKind::Ptr([_, (v, _)]), ..) =>
+        self.span = &v.span,
+      mir::Statement::Assign(_, _, _, vars) =>
+        if let Some(r) = vars.first() { self.span = &r.to.span },
+      _ => {}
+    }
+  }
+
+  fn before_terminator(&mut self, _: &TraceIter<'a>,
+    _: &'a IdxVec<ProcId, ProcAbi>, _: Option<&'a [ArgAbi]>,
+    _: &'a mir::Terminator, _: &'a classify::Terminator
+  ) {}
+}
+
+/// Where the data for a section is located.
+enum SectionData<'a> {
+  /// The section is part of the loaded segment, at the given file offset.
+  Loaded(u64),
+  /// The section is not loaded, and the data will be appended to the end of the file.
+  File(&'a [u8]),
+}
+
+/// An entry in the section header table.
+struct Section<'a> {
+  name: &'static str,
+  ty: u32,
+  flags: u64,
+  addr: u64,
+  data: SectionData<'a>,
+  /// The size of the section, for `Loaded` sections.
+  size: u64,
+  link: u16,
+  info: u32,
+  align: u64,
+  entsize: u64,
+}
+
+/// A line number sequence for a single procedure.
+struct Sequence {
+  start: u32,
+  end: u32,
+  rows: Vec<(u32, FileSpan)>,
+}
+
+/// Build the `.debug_line` section (DWARF version 3) from the instruction spans.
+fn debug_line(seqs: &[Sequence], mut line: impl FnMut(&FileSpan) -> Option<u32>) -> Vec<u8> {
+  let mut files: HashMap<&FileRef, u64> = HashMap::new();
+  let mut file_names = vec![];
+  let mut program = vec![];
+  for seq in seqs {
+    let (mut cur_addr, mut cur_file, mut cur_line) = (seq.start, 1, 1);
+    program.extend_from_slice(&[0, 9, DW_LNE_SET_ADDRESS]);
+    program.extend_from_slice(&u64::from(seq.start).to_le_bytes());
+    for &(addr, ref span) in &seq.rows {
+      let Some(l) = line(span) else { continue };
+      let file = *files.entry(&span.file).or_insert_with(|| {
+        file_names.push(&span.file);
+        file_names.len() as u64
+      });
+      if file != cur_file {
+        program.push(DW_LNS_SET_FILE);
+        write_uleb(&mut program, file);
+        cur_file = file;
+      }
+      if l != cur_line {
+        program.push(DW_LNS_ADVANCE_LINE);
+        write_sleb(&mut program, i64::from(l) - i64::from(cur_line));
+        cur_line = l;
+      }
+      if addr != cur_addr {
+        program.push(DW_LNS_ADVANCE_PC);
+        write_uleb(&mut program, u64::from(addr - cur_addr));
+        cur_addr = addr;
+      }
+      program.push(DW_LNS_COPY);
+    }
+    if seq.end != cur_addr {
+      program.push(DW_LNS_ADVANCE_PC);
+      write_uleb(&mut program, u64::from(seq.end - cur_addr));
+    }
+    program.extend_from_slice(&[0, 1, DW_LNE_END_SEQUENCE]);
+  }
+
+  let mut header = vec![
+    1, // minimum_instruction_length
+    1, // default_is_stmt
+    (-5_i8).to_le_bytes()[0], // line_base
+    14, // line_range
+    OPCODE_BASE,
+  ];
+  header.extend_from_slice(&STANDARD_OPCODE_LENGTHS);
+  header.push(0); // include_directories (empty)
+  for file in file_names {
+    let name = file.path().to_string_lossy();
+    header.extend_from_slice(if name.is_empty() { b"?" } else { name.as_bytes() });
+    header.extend_from_slice(&[0, 0, 0, 0]); // null terminator, directory, mtime, length
+  }
+  header.push(0);
+
+  let mut out = vec![];
+  let unit_length = 2 + 4 + header.len() + program.len();
+  out.write_u32::<LE>(unit_length.try_into().expect("overflow")).expect("impossible");
+  out.write_u16::<LE>(3).expect("impossible"); // version
+  out.write_u32::<LE>(header.len().try_into().expect("overflow")).expect("impossible");
+  out.extend_from_slice(&header);
+  out.extend_from_slice(&program);
+  out
+}
+
+impl LinkedCode {
+  /// Write this code object as an ELF file, like [`write_elf`](Self::write_elf),
+  /// but with a section header table, symbol table and line number information appended
+  /// after the loaded segment, for use with debuggers and profilers.
+  ///
+  /// The `line` function is used to resolve a source span to a (1-based) line number.
+  /// Instructions whose spans cannot be resolved are attributed to the previous line.
+  #[allow(clippy::cast_lossless)]
+  pub fn write_elf_debug(&self,
+    w: &mut impl Write, line: impl FnMut(&FileSpan) -> Option<u32>
+  ) -> io::Result<()> {
+    let proof = self.proof();
+    let mut file = proof.to_vec();
+    let rodata_start = u64::from(TEXT_START + self.text_size);
+    let file_end = rodata_start + u64::try_from(self.consts.rodata.len()).expect("overflow");
+    let global_start = align_to::<BSS_ALIGN>(file_end);
+
+    let mut seqs = vec![];
+    let mut symtab = SymTab::new();
+    for item in proof.assembly() {
+      match item {
+        AssemblyItem::Proc(proc) => {
+          let mut v = LineVisitor { start: proc.start, span: &proc.cfg.span, rows: vec![] };
+          for bl in proc.assembly_blocks() { bl.visit(&mut v) }
+          let rows = v.rows.into_iter().map(|(addr, sp)| (addr, sp.clone())).collect();
+          let end = proc.start + proc.len_no_padding();
+          seqs.push(Sequence { start: proc.start, end, rows });
+          if let Some(name) = proc.name() {
+            symtab.push(name, (STB_LOCAL << 4) | STT_FUNC, SHN_TEXT,
+              proc.start.into(), proc.len_no_padding().into())
+          }
+        }
+        AssemblyItem::Const(c) => symtab.push(c.name, (STB_LOCAL << 4) | STT_OBJECT,
+          SHN_RODATA, c.start.into(), c.content.len() as u64),
+      }
+    }
+    for &(name, off, size) in &self.globals.0 {
+      symtab.push(name, (STB_LOCAL << 4) | STT_OBJECT, SHN_BSS,
+        global_start + u64::from(off), size.into())
+    }
+    let first_global = symtab.len;
+    symtab.push(intern("_start"), (STB_GLOBAL << 4) | STT_FUNC, SHN_TEXT,
+      TEXT_START.into(), self.init.1.len.into());
+    let debug_line = debug_line(&seqs, line);
+
+    let loaded = |addr: u64| SectionData::Loaded(addr - LOAD_BASE);
+    let sections = [
+      Section { name: ".text", ty: SHT_PROGBITS, flags: SHF_ALLOC | SHF_EXECINSTR,
+        addr: TEXT_START.into(), data: loaded(TEXT_START.into()), size: self.text_size.into(),
+        link: 0, info: 0, align: FUNCTION_ALIGN.into(), entsize: 0 },
+      Section { name: ".rodata", ty: SHT_PROGBITS, flags: SHF_ALLOC,
+        addr: rodata_start, data: loaded(rodata_start), size: file_end - rodata_start,
+        link: 0, info: 0, align: 1, entsize: 0 },
+      Section { name: ".bss", ty: SHT_NOBITS, flags: SHF_WRITE | SHF_ALLOC,
+        addr: global_start, data: loaded(file_end), size: self.global_size.into(),
+        link: 0, info: 0, align: BSS_ALIGN, entsize: 0 },
+      Section { name: ".symtab", ty: SHT_SYMTAB, flags: 0,
+        addr: 0, data: SectionData::File(&symtab.syms), size: 0,
+        link: SHN_STRTAB, info: first_global, align: 8, entsize: SymTab::ENTSIZE as u64 },
+      Section { name: ".strtab", ty: SHT_STRTAB, flags: 0,
+        addr: 0, data: SectionData::File(&symtab.strtab.0), size: 0,
+        link: 0, info: 0, align: 1, entsize: 0 },
+      Section { name: ".debug_line", ty: SHT_PROGBITS, flags: 0,
+        addr: 0, data: SectionData::File(&debug_line), size: 0,
+        link: 0, info: 0, align: 1, entsize: 0 },
+    ];
+    let mut shstrtab = StrTab::new();
+    let names = sections.iter().map(|sec| shstrtab.add(sec.name)).collect::<Vec<_>>();
+    let shstrtab_name = shstrtab.add(".shstrtab");
+
+    let mut shdrs = vec![0; 64];
+    let mut push_shdr = |file: &mut Vec<u8>, name: u32, sec: &Section<'_>| {
+      let (offset, size) = match sec.data {
+        SectionData::Loaded(offset) => (offset, sec.size),
+        SectionData::File(data) => {
+          file.resize(file.len().next_multiple_of(8), 0);
+          let offset = file.len() as u64;
+          file.extend_from_slice(data);
+          (offset, data.len() as u64)
+        }
+      };
+      shdrs.write_u32::<LE>(name).expect("impossible");
+      shdrs.write_u32::<LE>(sec.ty).expect("impossible");
+      shdrs.write_u64::<LE>(sec.flags).expect("impossible");
+      shdrs.write_u64::<LE>(sec.addr).expect("impossible");
+      shdrs.write_u64::<LE>(offset).expect("impossible");
+      shdrs.write_u64::<LE>(size).expect("impossible");
+      shdrs.write_u32::<LE>(sec.link.into()).expect("impossible");
+      shdrs.write_u32::<LE>(sec.info).expect("impossible");
+      shdrs.write_u64::<LE>(sec.align).expect("impossible");
+      shdrs.write_u64::<LE>(sec.entsize).expect("impossible");
+    };
+    for (&name, sec) in names.iter().zip(&sections) { push_shdr(&mut file, name, sec) }
+    push_shdr(&mut file, shstrtab_name, &Section { name: ".shstrtab", ty: SHT_STRTAB, flags: 0,
+      addr: 0, data: SectionData::File(&shstrtab.0), size: 0,
+      link: 0, info: 0, align: 1, entsize: 0 });
+
+    file.resize(file.len().next_multiple_of(8), 0);
+    let shoff = file.len() as u64;
+    // The null section, the listed sections, and `.shstrtab`
+    let shnum = u16::try_from(sections.len() + 2).expect("overflow");
+    file.extend_from_slice(&shdrs);
+    file[0x28..0x30].copy_from_slice(&shoff.to_le_bytes()); // e_shoff
+    file[0x3c..0x3e].copy_from_slice(&shnum.to_le_bytes()); // e_shnum
+    file[0x3e..0x40].copy_from_slice(&(shnum - 1).to_le_bytes()); // e_shstrndx
+    w.write_all(&file)
+  }
+}
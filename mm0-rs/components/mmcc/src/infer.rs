@@ -27,7 +27,7 @@ pub enum TypeError<'a> {
   /// Failed to pattern match type T with the given pattern of type U
   PatternMatch(Ty<'a>, Ty<'a>),
   /// Failed to relate type T to type U according to the relation
-  Relate(Ty<'a>, Ty<'a>, Relation),
+  Relate(Ty<'a>, Ty<'a>, Relation, Box<Explanation<'a>>),
   /// Expected a pure expression (for the operation at this span)
   ExpectedPure(&'a FileSpan),
   /// Expected a struct expression
@@ -65,12 +65,142 @@ pub enum TypeError<'a> {
   InvalidReturn,
   /// While loop mutates a value without marking it as `mut` in the loop header
   MissingMuts(Vec<VarId>),
+  /// Expected an array (as an argument to `map` or `fold`), got this type
+  ExpectedArray(Ty<'a>),
+  /// The arrays in a `map` or `fold` have different lengths
+  MapFoldLength(Expr<'a>, Expr<'a>),
+  /// The body of a `map` or `fold` mutates variables from outside the loop
+  MapFoldMuts(Vec<VarId>),
   /// A `(variant h)` clause was provided to a function or label that does not declare a variant
   UnexpectedVariant,
+  /// A variable of non-copy type was used after it was moved at the given span
+  UseAfterMove(VarId, &'a FileSpan),
+  /// A variable of non-copy type from outside a loop is moved in the loop body
+  MoveInLoop(VarId),
   /// More than one `main` function defined
   DoubleMain,
 }
 
+/// Additional information about a failed [`TypeError::Relate`].
+#[derive(Debug, Default)]
+pub struct Explanation<'a> {
+  /// If the target type is a proposition, the hypotheses that were in the context when
+  /// attempting to prove it.
+  pub hyps: Option<Vec<(VarId, Ty<'a>)>>,
+  /// Other places in the source relevant to the error.
+  pub related: Vec<(FileSpan, Related)>,
+}
+
+/// The reason a location is mentioned in a type error.
+#[derive(Copy, Clone, Debug)]
+pub enum Related {
+  /// The variable's type was last changed by the assignment at this span.
+  Assigned(VarId),
+  /// The hypothesis was introduced at this span.
+  Hyp(VarId),
+  /// The variable (often a hypothesis) was used by value, and hence moved, at this span.
+  Consumed(VarId),
+  /// The operation which needed a pure expression.
+  PureOperation,
+}
+
+impl<'a, C: DisplayCtx<'a>> CtxDisplay<C> for Related {
+  fn fmt(&self, ctx: &C, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match *self {
+      Related::Assigned(v) => write!(f, "{} was last assigned here", CtxPrint(ctx, &v)),
+      Related::Hyp(v) => write!(f, "hypothesis {} was introduced here", CtxPrint(ctx, &v)),
+      Related::Consumed(v) => write!(f, "{} was consumed here", CtxPrint(ctx, &v)),
+      Related::PureOperation => write!(f, "Needed for this operation"),
+    }
+  }
+}
+
+impl TypeError<'_> {
+  /// The other locations in the source that are relevant to this error.
+  #[must_use] pub fn related(&self) -> Vec<(FileSpan, Related)> {
+    match *self {
+      TypeError::ExpectedPure(sp) => vec![(sp.clone(), Related::PureOperation)],
+      TypeError::Relate(_, _, _, ref exp) => exp.related.clone(),
+      TypeError::UseAfterMove(v, sp) => vec![(sp.clone(), Related::Consumed(v))],
+      _ => vec![],
+    }
+  }
+}
+
+/// Returns true if `ty` is a proposition, that is, a type whose values carry no data
+/// and serve only as proofs.
+fn is_prop(ty: Ty<'_>) -> bool {
+  match ty.k {
+    TyKind::True | TyKind::False | TyKind::All(..) | TyKind::Imp(..) | TyKind::Wand(..) |
+    TyKind::Not(_) | TyKind::Pure(_) | TyKind::Heap(..) | TyKind::HasTy(..) => true,
+    TyKind::And(tys) | TyKind::Or(tys) => tys.iter().all(|&ty| is_prop(ty)),
+    TyKind::If(_, t1, t2) => is_prop(t1) && is_prop(t2),
+    TyKind::Ghost(ty) | TyKind::Moved(ty) => is_prop(ty),
+    _ => false,
+  }
+}
+
+/// The part of two types where they differ, as found by [`ty_diff`].
+enum TyDiff<'a> {
+  Ty(Ty<'a>, Ty<'a>),
+  Expr(Expr<'a>, Expr<'a>),
+}
+
+/// Find the smallest parts of `t1` and `t2` where they differ, when they have the same shape
+/// except for one position. Returns `None` if the types differ at the top level.
+fn ty_diff<'a>(t1: Ty<'a>, t2: Ty<'a>) -> Option<TyDiff<'a>> {
+  fn go<'a>(t1: Ty<'a>, t2: Ty<'a>) -> TyDiff<'a> {
+    let sub = match (&t1.k, &t2.k) {
+      (&TyKind::Array(a1, n1), &TyKind::Array(a2, n2)) |
+      (&TyKind::Sn(n1, a1), &TyKind::Sn(n2, a2)) if a1 == a2 => return TyDiff::Expr(n1, n2),
+      (&TyKind::Array(a1, n1), &TyKind::Array(a2, n2)) |
+      (&TyKind::Sn(n1, a1), &TyKind::Sn(n2, a2)) if n1 == n2 => Some((a1, a2)),
+      (&TyKind::Imp(a1, b1), &TyKind::Imp(a2, b2)) |
+      (&TyKind::Wand(a1, b1), &TyKind::Wand(a2, b2)) =>
+        if a1 == a2 { Some((b1, b2)) } else if b1 == b2 { Some((a1, a2)) } else { None },
+      (&TyKind::Own(a1), &TyKind::Own(a2)) |
+      (&TyKind::Not(a1), &TyKind::Not(a2)) |
+      (&TyKind::Ghost(a1), &TyKind::Ghost(a2)) |
+      (&TyKind::Uninit(a1), &TyKind::Uninit(a2)) |
+      (&TyKind::Moved(a1), &TyKind::Moved(a2)) => Some((a1, a2)),
+      (&TyKind::Shr(l1, a1), &TyKind::Shr(l2, a2)) |
+      (&TyKind::Ref(l1, a1), &TyKind::Ref(l2, a2)) if l1 == l2 => Some((a1, a2)),
+      (&TyKind::List(ts1), &TyKind::List(ts2)) |
+      (&TyKind::And(ts1), &TyKind::And(ts2)) |
+      (&TyKind::Or(ts1), &TyKind::Or(ts2)) if ts1.len() == ts2.len() => {
+        let mut it = ts1.iter().zip(ts2).filter(|(a1, a2)| a1 != a2);
+        match (it.next(), it.next()) {
+          (Some((&a1, &a2)), None) => Some((a1, a2)),
+          _ => None,
+        }
+      }
+      _ => None,
+    };
+    match sub {
+      Some((a1, a2)) if a1 != a2 => go(a1, a2),
+      _ => TyDiff::Ty(t1, t2),
+    }
+  }
+  match go(t1, t2) {
+    TyDiff::Ty(d1, _) if d1 == t1 => None,
+    d => Some(d),
+  }
+}
+
+impl<'a, C: DisplayCtx<'a>> CtxDisplay<C> for Explanation<'a> {
+  fn fmt(&self, ctx: &C, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self.hyps {
+      None => Ok(()),
+      Some(ref hyps) if hyps.is_empty() => write!(f, "\nThere are no hypotheses in context"),
+      Some(ref hyps) => {
+        write!(f, "\nHypotheses in context:")?;
+        for &(v, ty) in hyps { write!(f, "\n  {}: {}", CtxPrint(ctx, &v), CtxPrint(ctx, ty))? }
+        Ok(())
+      }
+    }
+  }
+}
+
 impl<'a, C: DisplayCtx<'a>> CtxDisplay<C> for TypeError<'a> {
   fn fmt(&self, ctx: &C, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     macro_rules! p {($e:expr) => {CtxPrint(ctx, $e)}}
@@ -79,14 +209,21 @@ impl<'a, C: DisplayCtx<'a>> CtxDisplay<C> for TypeError<'a> {
         "Failed to pattern match type\n  {}\n \
         with the given pattern of type\n  {}",
         p!(t1), p!(t2)),
-      TypeError::Relate(t1, t2, Relation::Equal) => write!(f,
-        "Type mismatch: type\n  {}\nis not equal to type\n  {}", p!(t1), p!(t2)),
-      TypeError::Relate(t1, t2, Relation::Subtype) => write!(f,
-        "Type mismatch: type\n  {}\nis not a subtype of\n  {}", p!(t1), p!(t2)),
-      TypeError::Relate(t1, t2, Relation::SubtypeEqSize) => write!(f,
-        "Type mismatch: type\n  {}\nis not a binary compatible subtype of\n  {}", p!(t1), p!(t2)),
-      TypeError::Relate(t1, t2, Relation::Coerce) => write!(f,
-        "Type mismatch: type\n  {}\nis not coercible to\n  {}", p!(t1), p!(t2)),
+      TypeError::Relate(t1, t2, rel, ref exp) => {
+        let rel = match rel {
+          Relation::Equal => "is not equal to type",
+          Relation::Subtype => "is not a subtype of",
+          Relation::SubtypeEqSize => "is not a binary compatible subtype of",
+          Relation::Coerce => "is not coercible to",
+        };
+        write!(f, "Type mismatch: type\n  {}\n{rel}\n  {}", p!(t1), p!(t2))?;
+        match ty_diff(t1, t2) {
+          Some(TyDiff::Ty(d1, d2)) => write!(f, "\nexpected: {}\n   found: {}", p!(d2), p!(d1))?,
+          Some(TyDiff::Expr(d1, d2)) => write!(f, "\nexpected: {}\n   found: {}", p!(d2), p!(d1))?,
+          None => {}
+        }
+        write!(f, "{}", p!(&**exp))
+      }
       TypeError::ExpectedPure(_) => write!(f, "Expected a pure expression"),
       TypeError::ExpectedStruct(t) => write!(f, "Expected a struct expression, got\n  {}", p!(t)),
       TypeError::ExpectedPtr => write!(f, "Expected a pointer expression"),
@@ -115,8 +252,21 @@ impl<'a, C: DisplayCtx<'a>> CtxDisplay<C> for TypeError<'a> {
       TypeError::MissingMuts(ref muts) => write!(f, "\
         While loop mutates a value without marking it as 'mut' in the loop header. \
         Try adding:\n  (mut {})", muts.iter().unique().map(|v| p!(v)).format(" ")),
+      TypeError::ExpectedArray(t) => write!(f,
+        "map/fold: expected an array, got\n  {}", p!(t)),
+      TypeError::MapFoldLength(n1, n2) => write!(f,
+        "map/fold: the arrays must all have the same length, but\n  {}\n!=\n  {}",
+        p!(n1), p!(n2)),
+      TypeError::MapFoldMuts(ref muts) => write!(f, "\
+        The body of a map/fold cannot mutate variables from outside the loop, \
+        but it modifies:\n  {}\n\
+        Note: use a fold to carry state between iterations",
+        muts.iter().unique().map(|v| p!(v)).format(" ")),
       TypeError::UnexpectedVariant => write!(f, "A (variant h) clause was provided \
         to a function or label that does not declare a variant"),
+      TypeError::UseAfterMove(v, _) => write!(f, "Use of moved value {}", p!(&v)),
+      TypeError::MoveInLoop(v) => write!(f,
+        "Value {} from outside the loop is moved in the loop body", p!(&v)),
       TypeError::DoubleMain => write!(f, "The `main` function has been defined more than once"),
     }
   }
@@ -1035,6 +1185,12 @@ pub struct DynContext<'a> {
   /// A (persistent) map from variables that have been mutated at least once,
   /// to their latest type and value.
   gen_vars: im::HashMap<VarId, (GenId, Expr<'a>, Ty<'a>)>,
+  /// A (persistent) map from variables that have been mutated at least once,
+  /// to the location of the most recent assignment.
+  updates: im::HashMap<VarId, &'a FileSpan>,
+  /// A (persistent) map from variables of non-copy type that have been used by value
+  /// (and not assigned since), to the location of the first such use.
+  consumed: im::HashMap<VarId, &'a FileSpan>,
   /// The logical context.
   context: Context<'a>,
   /// True if we have previously hit an `unreachable` or other diverging operation,
@@ -1193,6 +1349,8 @@ struct LabelData<'a> {
   ret: Option<Ty<'a>>,
   /// The dynamic contexts at `break` points that need to be merged into the block exit context.
   dcs: Vec<DynContext<'a>>,
+  /// The variables that were moved before a jump to this label group, and where.
+  moved: im::HashMap<VarId, &'a FileSpan>,
 }
 
 /// The assignments for metavariables.
@@ -1260,6 +1418,8 @@ pub struct InferCtx<'a, 'n> {
   labels: HashMap<VarId, LabelData<'a>>,
   /// The return type of the current function.
   returns: Option<&'a [Arg<'a>]>,
+  /// The global names that have been looked up, which the result of typechecking depends on.
+  pub(crate) deps: HashSet<Symbol>,
   /// True if an upstream error was detected.
   pub has_ast_errors: bool,
   /// The list of type errors collected so far.
@@ -1594,6 +1754,8 @@ impl<'a, 'n> InferCtx<'a, 'n> {
       mvars: Default::default(),
       dc: DynContext {
         gen_vars: Default::default(),
+        updates: Default::default(),
+        consumed: Default::default(),
         generation: GenId::ROOT,
         context: Context::ROOT,
         diverged: false,
@@ -1601,6 +1763,7 @@ impl<'a, 'n> InferCtx<'a, 'n> {
       generation_count: GenId::ROOT,
       labels: HashMap::new(),
       returns: None,
+      deps: HashSet::new(),
       has_ast_errors: false,
       errors: vec![],
     }
@@ -1686,6 +1849,16 @@ impl<'a, 'n> InferCtx<'a, 'n> {
   }
 
   fn merge(&mut self, span: &'a FileSpan, contexts: &mut [DynContext<'a>]) -> Vec<VarId> {
+    // A variable consumed on any path is reported as consumed after the merge
+    let consumed = contexts.iter().filter(|dc| !dc.diverged)
+      .fold(self.dc.consumed.clone(), |m, dc| m.union(dc.consumed.clone()));
+    let vars = self.merge_gen_vars(span, contexts);
+    self.dc.consumed = consumed;
+    vars
+  }
+
+  fn merge_gen_vars(&mut self, span: &'a FileSpan, contexts: &mut [DynContext<'a>]
+  ) -> Vec<VarId> {
     if contexts.iter().all(|dc| {
       self.dc.diverged &= dc.diverged;
       dc.generation == self.dc.generation
@@ -1710,22 +1883,23 @@ impl<'a, 'n> InferCtx<'a, 'n> {
       let (old_gen, old_e, old_ty) =
         self.dc.gen_vars.get(&v).copied().unwrap_or((c.gen, c.val, c.ty));
       let mut new_e = Some(old_e);
-      let mut modified = false;
+      let mut modified = None;
       for dc in &*contexts {
         if !dc.diverged {
           if let Some(&(gen, e, ty)) = dc.gen_vars.get(&v) {
             if gen != old_gen {
               new_e = new_e.filter(|new_e| self.equate_expr(new_e, e).is_ok());
               self.relate_ty(span, Some(e), ty, old_ty, Relation::Subtype).expect("todo");
-              modified = true;
+              modified = Some(dc.updates.get(&v).copied().unwrap_or(span));
             }
           }
         }
       }
-      if modified {
+      if let Some(sp) = modified {
         vars.insert(v);
         let new_e = new_e.unwrap_or_else(|| intern!(self, ExprKind::Var(v)));
         newdc.gen_vars.insert(v, (newdc.generation, new_e, old_ty));
+        newdc.updates.insert(v, sp);
       }
     }
     if vars.is_empty() { newdc.generation = self.dc.generation }
@@ -1861,6 +2035,7 @@ impl<'a, 'n> InferCtx<'a, 'n> {
       },
       ExprKind::Sizeof(ty) => self.whnf_sizeof(sp, Default::default(), ty),
       ExprKind::Call {f, tys, args: es} => {
+        self.deps.insert(f);
         let Some(Entity::Proc(ty)) = self.names.get(&f) else { unreachable!() };
         match ty.k.ty() {
           None => self.common.e_error,
@@ -1919,6 +2094,7 @@ impl<'a, 'n> InferCtx<'a, 'n> {
       TyKind::Error => return wty,
       TyKind::Ref(_, ty) => return wty.map(ty), // FIXME
       TyKind::User(f, tys, es) => {
+        self.deps.insert(f);
         let Some(Entity::Type(tc)) = self.names.get(&f) else { unreachable!() };
         match tc.k {
           TypeTc::ForwardDeclared => return self.common.t_error.into(),
@@ -2139,6 +2315,7 @@ impl<'a, 'n> InferCtx<'a, 'n> {
         return Err(())
       }
       (ExprKind::Const(c), _) => {
+        self.deps.insert(c);
         let Some(Entity::Const(tc)) = self.names.get(&c) else { unreachable!() };
         match tc.k {
           ConstTc::ForwardDeclared => {}
@@ -2182,7 +2359,9 @@ impl<'a, 'n> InferCtx<'a, 'n> {
       $(if from.$i != to.$i { return Err(()) })*
     }}}
     if from.ty == to.ty {
-      check!(uninit, ghost, moved);
+      check!(ghost, moved);
+      // `T` is a subtype of `?T`, by forgetting that the value is initialized
+      if from.uninit != to.uninit && (from.uninit || rel == Relation::Equal) { return Err(()) }
       return Ok(vec![])
     }
     match (from.ty.k, to.ty.k) {
@@ -2354,12 +2533,48 @@ impl<'a, 'n> InferCtx<'a, 'n> {
             return Err(vec![Coercion::TypedPure(to)])
           }
         }
-        self.errors.push(hir::Spanned {span, k: TypeError::Relate(from, to, rel)});
+        let exp = self.explain(span, pe, from, to);
+        self.errors.push(hir::Spanned {span, k: TypeError::Relate(from, to, rel, Box::new(exp))});
         Err(vec![Coercion::Error])
       }
     }
   }
 
+  /// Collect the information for reporting a failure to relate `from` to `to` at `span`: where
+  /// the variables involved were last assigned, the available hypotheses if `to` is a
+  /// proposition, and where any of these were consumed before `span`.
+  fn explain(&self,
+    span: &'a FileSpan, pe: Option<Expr<'a>>, from: Ty<'a>, to: Ty<'a>
+  ) -> Explanation<'a> {
+    let mut vars = vec![];
+    if let Some(pe) = pe { pe.on_vars(|v| vars.push(v)) }
+    from.on_vars(|v| vars.push(v));
+    to.on_vars(|v| vars.push(v));
+    let vars = vars.into_iter().unique().collect::<Vec<_>>();
+    let mut related = vars.iter()
+      .filter_map(|&v| Some(((*self.dc.updates.get(&v)?).clone(), Related::Assigned(v))))
+      .collect::<Vec<_>>();
+    let hyps = is_prop(to).then(|| {
+      let mut hyps = self.dc.context.into_iter()
+        .filter_map(|c| {
+          let ty = self.dc.get_var(c.var).2;
+          (is_prop(ty) && !matches!(ty.k, TyKind::True)).then_some((c.var, ty))
+        })
+        .collect::<Vec<_>>();
+      hyps.reverse();
+      for &(v, _) in &hyps { related.push((self.var_name(v).span.clone(), Related::Hyp(v))) }
+      hyps
+    });
+    let hyp_vars = hyps.iter().flatten().map(|&(v, _)| v);
+    related.extend(vars.iter().copied().chain(hyp_vars).unique()
+      .filter_map(|v| {
+        // The expression being checked may itself have consumed the variable
+        let &sp = self.dc.consumed.get(&v).filter(|&&sp| sp != span)?;
+        Some((sp.clone(), Related::Consumed(v)))
+      }));
+    Explanation { hyps, related }
+  }
+
   fn lower_tuple_pattern(&mut self, span: &'a FileSpan,
     pat: &'a ast::TuplePatternKind,
     expect_e: Option<Expr<'a>>,
@@ -2453,6 +2668,7 @@ impl<'a, 'n> InferCtx<'a, 'n> {
       }
       ExprKind::Unit => self.common.t_unit,
       ExprKind::Const(c) => {
+        self.deps.insert(c);
         let Some(Entity::Const(tc)) = self.names.get(&c) else { unreachable!() };
         match &tc.k {
           ConstTc::ForwardDeclared => return None,
@@ -2799,6 +3015,7 @@ impl<'a, 'n> InferCtx<'a, 'n> {
       ast::TypeKind::User(f, tys, es) => {
         let tys = tys.iter().map(|ty| self.lower_ty(ty, ExpectTy::Any)).collect::<Vec<_>>();
         let tys = self.alloc.alloc_slice_fill_iter(tys.into_iter());
+        self.deps.insert(*f);
         let Some(Entity::Type(tc)) = self.names.get(f) else { unreachable!() };
         let args = match &tc.k {
           TypeTc::ForwardDeclared => return self.common.t_error,
@@ -2900,7 +3117,10 @@ impl<'a, 'n> InferCtx<'a, 'n> {
           if let ast::TuplePatternKind::Name(_, name, _) = pat;
           if let Some(Entity::Global(tc)) = self.names.get_mut(name);
           if let GlobalTc::Checked(ty) = &tc.k;
-          then { Some(ty.clone().import_global(self)) }
+          then {
+            self.deps.insert(*name);
+            Some(ty.clone().import_global(self))
+          }
           else { None }
         };
         if attr.contains(ArgAttr::MUT) {
@@ -2993,6 +3213,106 @@ impl<'a, 'n> InferCtx<'a, 'n> {
     (arrty, self.coerce_expr((e_a, a), arrty))
   }
 
+  fn lower_map_fold(&mut self, span: &'a FileSpan,
+    ast::MapFold {idx, hyp, acc, arrays, body}: &'a ast::MapFold, expect: ExpectExpr<'a>
+  ) -> (hir::Expr<'a>, RExpr<'a>) {
+    let acc = acc.as_ref().map(|(x, init)| {
+      let (init, _) = match expect.to_ty() {
+        Some(ty) => self.check_expr(init, ty),
+        None => self.lower_expr(init, ExpectExpr::Any),
+      };
+      (x.as_ref(), Box::new(init))
+    });
+    let mut n = None;
+    let arrays = arrays.iter().map(|(x, a)| {
+      let (mut e_a, pe) = self.lower_expr(a, ExpectExpr::Any);
+      while let TyKind::Ref(_, aty2) = e_a.ty().k {
+        e_a = hir::Expr {span, k: (hir::ExprKind::Rval(Box::new(e_a)), (pe.ok(), aty2))};
+      }
+      let (ty, len) = match self.whnf_ty(&a.span, e_a.ty().into()).ty.k {
+        TyKind::Array(ty, len) => (ty, len),
+        TyKind::Error => (self.common.t_error, self.common.e_error),
+        _ => {
+          self.errors.push(hir::Spanned {span: &a.span, k: TypeError::ExpectedArray(e_a.ty())});
+          (self.common.t_error, self.common.e_error)
+        }
+      };
+      match n {
+        None => n = Some(len),
+        Some(n) => if self.equate_expr(n, len).is_err() {
+          self.errors.push(hir::Spanned {span: &a.span, k: TypeError::MapFoldLength(n, len)})
+        }
+      }
+      (x.as_ref(), ty, e_a, pe)
+    }).collect::<Vec<_>>();
+    let n = self.whnf_expr(span, n.expect("map/fold needs an array"));
+
+    // Compute the length of the arrays as a `u64`, for the loop bound
+    let t_u64 = self.common.t_uint(Size::S64);
+    let len = match n.k {
+      ExprKind::Int(k) if IntTy::UInt(Size::S64).contains(k) =>
+        hir::Spanned {span, k: (hir::ExprKind::Int(k), (Some(n), t_u64))},
+      _ => match self.eval_expr(span, n) {
+        Some(e) if e.ty() == t_u64 => e,
+        Some(e) => {
+          let ity = e.ty().as_int_ty().unwrap_or(IntTy::NAT);
+          let k = hir::ExprKind::Unop(hir::Unop::As(ity, IntTy::UInt(Size::S64)), Box::new(e));
+          hir::Spanned {span, k: (k, (Some(n), t_u64))}
+        }
+        None => {
+          let err = TypeError::UnsupportedSynthesis(Box::new(self.dc.clone()), n, self.common.nat());
+          self.errors.push(hir::Spanned {span, k: err});
+          hir::Spanned {span, k: (hir::ExprKind::Error, (Some(self.common.e_error), t_u64))}
+        }
+      }
+    };
+
+    // Bind `i: u64`, `h: i < n`, the accumulator, and `x_j := a_j[i]` in the body
+    let base = self.dc.clone();
+    let fresh = |this: &mut Self, v: Option<&'a Spanned<VarId>>| v.map_or_else(
+      || hir::Spanned {span, k: this.fresh_var(Spanned {span: span.clone(), k: Symbol::UNDER})},
+      Spanned::as_ref);
+    let idx = fresh(self, idx.as_ref());
+    let hyp = fresh(self, hyp.as_ref());
+    let ctx = self.new_context_next(idx.k, None, t_u64);
+    self.dc.context = ctx.into();
+    let e_i = intern!(self, ExprKind::Var(idx.k));
+    let ty = intern!(self, TyKind::Pure(intern!(self, ExprKind::Binop(Binop::Lt, e_i, n))));
+    let ctx = self.new_context_next(hyp.k, Some(self.common.e_unit), ty);
+    self.dc.context = ctx.into();
+    if let Some((x, ref init)) = acc {
+      let ctx = self.new_context_next(x.k, None, init.ty());
+      self.dc.context = ctx.into();
+    }
+    for &(x, ty, _, pe) in &arrays {
+      let val = pe.ok().map(|a| intern!(self, ExprKind::Index(a, e_i)));
+      let ctx = self.new_context_next(x.k, val, ty);
+      self.dc.context = ctx.into();
+    }
+    let tgt = match acc {
+      Some((_, ref init)) => init.ty(),
+      None => self.whnf_expect(span, expect)
+        .and_then(|ty| if let TyKind::Array(ty, _) = ty.k {Some(ty)} else {None})
+        .unwrap_or_else(|| self.new_ty_mvar(span)),
+    };
+    let (body, _) = self.check_expr(body, tgt);
+
+    // The loop cannot change anything outside the body
+    let muts = self.dc.gen_vars.iter().filter(|&(&v, &(gen, _, _))| {
+      base.context.find(v).is_some() &&
+      base.gen_vars.get(&v).is_none_or(|&(gen2, _, _)| gen != gen2)
+    }).map(|(&v, _)| v).collect::<Vec<_>>();
+    if !muts.is_empty() { self.errors.push(hir::Spanned {span, k: TypeError::MapFoldMuts(muts)}) }
+    self.dc = base;
+
+    let ty = if acc.is_some() { tgt } else { intern!(self, TyKind::Array(tgt, n)) };
+    let arrays = arrays.into_iter().map(|(x, ty, e_a, _)| (x, ty, e_a)).collect();
+    let k = hir::ExprKind::MapFold(Box::new(hir::MapFold {
+      idx, hyp, n, len: Box::new(len), acc, arrays, body: Box::new(body)
+    }));
+    (hir::Spanned {span, k: (k, (None, ty))}, Err(span))
+  }
+
   fn build_lens(mut origin: Place<'a>
   ) -> Option<(VarId, impl FnOnce(&mut InferCtx<'a, 'n>, Expr<'a>) -> Expr<'a>)> {
     enum Projection<'a> {
@@ -3040,6 +3360,7 @@ impl<'a, 'n> InferCtx<'a, 'n> {
   ) -> Option<(hir::Call<'a>, RExprTy<'a>)> {
     let tys = tys.iter().map(|ty| self.lower_ty(ty, ExpectTy::Any)).collect::<Vec<_>>();
     let tys = &*self.alloc.alloc_slice_fill_iter(tys.into_iter());
+    self.deps.insert(f);
     let Some(Entity::Proc(ty)) = self.names.get(&f) else { unreachable!() };
     let ty = ty.k.ty()?;
     let ProcTy {kind, tyargs, args, outs, rets, variant, ..} = ty.clone();
@@ -3215,10 +3536,21 @@ impl<'a, 'n> InferCtx<'a, 'n> {
 
       &ast::ExprKind::Var(v) => {
         let (gen, val, ty) = self.dc.get_var(v);
+        if !ty.is_copy() {
+          if let Some(&sp) = self.dc.consumed.get(&v) {
+            // The type may have been a metavariable when the variable was first used
+            if !self.whnf_ty(span, ty.into()).ty.is_copy() {
+              self.errors.push(hir::Spanned {span, k: TypeError::UseAfterMove(v, sp)})
+            }
+          } else {
+            self.dc.consumed.insert(v, span);
+          }
+        }
         ret![Var(v, gen), Ok(val), ty]
       }
 
       &ast::ExprKind::Const(c) => {
+        self.deps.insert(c);
         let Some(Entity::Const(tc)) = self.names.get(&c) else { unreachable!() };
         match &tc.k {
           ConstTc::ForwardDeclared => error!(),
@@ -3435,7 +3767,8 @@ impl<'a, 'n> InferCtx<'a, 'n> {
           TyKind::Error => error!(),
           _ => {
             let tgt = intern!(self, TyKind::RefSn(self.common.p_error));
-            error!(e2.span, Relate(ty, tgt, Relation::Equal))
+            error!(e2.span,
+              Relate(ty, tgt, Relation::Equal, Box::new(self.explain(e2.span, None, ty, tgt))))
           }
         }
       }
@@ -3480,7 +3813,8 @@ impl<'a, 'n> InferCtx<'a, 'n> {
               if let ExprKind::Int(n) = self.whnf_expr(span, n_tgt).k {
                 if let Ok(n) = n.try_into() { break n }
               }
-              error!(span, Relate(ty, tgt, Relation::Equal))
+              error!(span,
+                Relate(ty, tgt, Relation::Equal, Box::new(self.explain(span, None, ty, tgt))))
             };
             expect!(n_tgt);
             let mut pes = Ok(Vec::with_capacity(n_tgt));
@@ -3540,7 +3874,9 @@ impl<'a, 'n> InferCtx<'a, 'n> {
             let val = pes.into_iter().collect::<Result<Vec<_>, _>>()
               .map(|pes| intern!(self, ExprKind::List(
                 self.alloc.alloc_slice_fill_iter(pes.into_iter()))));
-            ret![List(ListKind::Struct, es), val, tgt]
+            // Keep the name of the type if this is the unfolding of a user struct
+            let ty = expect.to_ty().unwrap_or(tgt);
+            ret![List(ListKind::Struct, es), val, ty]
           }
           TyKind::Own(_) => unimplemented!("malloc"),
           TyKind::Shr(_, _) => unimplemented!("&T constructor"),
@@ -3729,6 +4065,8 @@ impl<'a, 'n> InferCtx<'a, 'n> {
           intern!(self, ExprKind::Var(v))
         };
         self.dc.gen_vars.insert(v, (newgen, val, ty)); // FIXME: ty is not correct here
+        self.dc.updates.insert(v, span);
+        self.dc.consumed.remove(&v);
         let v = lhs.map(|_| v);
         let e = hir::ExprKind::Assign {
           lhs: Box::new(lhs),
@@ -3836,6 +4174,7 @@ impl<'a, 'n> InferCtx<'a, 'n> {
             let e = intern!(self, ExprKind::Var(v));
             let ty = self.dc.get_var(v).2;
             self.dc.gen_vars.insert(v, (newgen, e, ty));
+            self.dc.updates.insert(v, span);
           }
         }
         let variant = self.check_variant(var.as_deref());
@@ -3846,6 +4185,7 @@ impl<'a, 'n> InferCtx<'a, 'n> {
           has_jump: false,
           ret: has_break.then_some(self.common.t_unit),
           dcs: vec![],
+          moved: Default::default(),
         });
         let (cond, pe) = self.check_expr(cond, self.common.t_bool);
         let mut after = self.dc.clone();
@@ -3880,7 +4220,7 @@ impl<'a, 'n> InferCtx<'a, 'n> {
           if crate::proof::VERIFY_TERMINATION { self.common.t_false }
           else { self.common.t_unit };
         let body = Box::new(self.check_block(span, body, ret_ty).0);
-        let LabelData {labels, dcs, ..} =
+        let LabelData {labels, dcs, moved, ..} =
           self.labels.remove(&label).expect("labels should be well scoped");
 
         // TODO: remove this when the typechecker is complete, this isn't needed for inference
@@ -3894,6 +4234,18 @@ impl<'a, 'n> InferCtx<'a, 'n> {
           error!(span, MissingMuts(missing().map(|(&v, _)| v).collect()))
         }
 
+        // A variable from outside the loop that is moved on the way back to the start
+        // would be moved again by the next iteration
+        let moved = (!self.dc.diverged).then_some(&self.dc.consumed).into_iter().chain([&moved])
+          .flat_map(im::HashMap::iter)
+          .filter(|&(v, _)| !base.consumed.contains_key(v) &&
+            base.context.into_iter().any(|c| c.var == *v))
+          .map(|(&v, &sp)| (v, sp))
+          .unique_by(|&(v, _)| v).collect::<Vec<_>>();
+        for (v, sp) in moved {
+          self.errors.push(hir::Spanned {span: sp, k: TypeError::MoveInLoop(v)})
+        }
+
         self.dc = after;
         let (_, variant) = labels.into_vec().into_iter().next().expect("while label");
         ret![
@@ -3904,6 +4256,8 @@ impl<'a, 'n> InferCtx<'a, 'n> {
           Ok(unit!()), ret]
       }
 
+      ast::ExprKind::MapFold(mf) => self.lower_map_fold(span, mf, expect),
+
       ast::ExprKind::Unreachable(h) => {
         let tgt = expect.to_ty().unwrap_or(self.common.t_false);
         let (h, _) = self.check_expr(h, self.common.t_false);
@@ -3920,6 +4274,10 @@ impl<'a, 'n> InferCtx<'a, 'n> {
         let (args, _, mut subst) = self.check_args(span, args, tgt, |x| x.k);
         let variant = self.check_variant_use(&mut subst, pf.as_deref(), variant);
         let tgt = expect.to_ty().unwrap_or(self.common.t_false);
+        if !self.dc.diverged {
+          let label_data = self.labels.get_mut(&lab).expect("well formed");
+          label_data.moved = mem::take(&mut label_data.moved).union(self.dc.consumed.clone());
+        }
         self.dc.diverged = true;
         ret![Jump(lab, i, args, variant), Ok(unit!()), tgt]
       }
@@ -3988,6 +4346,7 @@ impl<'a, 'n> InferCtx<'a, 'n> {
         (hir::ExprKind::Var(v, gen), ty)
       }
       ExprKind::Const(c) => {
+        self.deps.insert(c);
         let Some(Entity::Const(tc)) = self.names.get(&c) else { unreachable!() };
         match &tc.k {
           ConstTc::ForwardDeclared => error!(),
@@ -4057,9 +4416,13 @@ impl<'a, 'n> InferCtx<'a, 'n> {
       ExprKind::List(_) |
       ExprKind::Array(_) |
       ExprKind::Ref(_) |
+      ExprKind::Infer(_) => {
+        let e2 = self.whnf_expr(span, e);
+        if e != e2 { return self.eval_expr(span, e2) }
+        return None
+      }
       ExprKind::Mm0(_) |
-      ExprKind::Call {..} |
-      ExprKind::Infer(_) => return None,
+      ExprKind::Call {..} => return None,
       ExprKind::Error => error!(),
     };
     Some(hir::Spanned {span, k: (k, (Some(e), ty))})
@@ -4205,7 +4568,9 @@ impl<'a, 'n> InferCtx<'a, 'n> {
           TyKind::Error => error!(),
           _ => {
             let ty2 = TyKind::Array(self.new_ty_mvar(span), self.new_expr_mvar(span));
-            error!(span, Relate(arrty, intern!(self, ty2), Relation::Equal))
+            let ty2 = intern!(self, ty2);
+            let exp = Box::new(self.explain(span, None, arrty, ty2));
+            error!(span, Relate(arrty, ty2, Relation::Equal, exp))
           }
         };
         let (e_i, idx) = self.check_expr(idx, self.common.nat());
@@ -4232,7 +4597,9 @@ impl<'a, 'n> InferCtx<'a, 'n> {
           TyKind::Error => error!(),
           _ => {
             let ty2 = TyKind::Array(self.new_ty_mvar(span), self.new_expr_mvar(span));
-            error!(span, Relate(arrty, intern!(self, ty2), Relation::Equal))
+            let ty2 = intern!(self, ty2);
+            let exp = Box::new(self.explain(span, None, arrty, ty2));
+            error!(span, Relate(arrty, ty2, Relation::Equal, exp))
           }
         };
         let (e_i, idx) = self.check_expr(idx, self.common.nat());
@@ -4266,7 +4633,8 @@ impl<'a, 'n> InferCtx<'a, 'n> {
           TyKind::Error => error!(),
           _ => {
             let tgt = intern!(self, TyKind::RefSn(self.common.p_error));
-            error!(e2.span, Relate(ty, tgt, Relation::Equal))
+            error!(e2.span,
+              Relate(ty, tgt, Relation::Equal, Box::new(self.explain(e2.span, None, ty, tgt))))
           }
         }
       }
@@ -4281,7 +4649,14 @@ impl<'a, 'n> InferCtx<'a, 'n> {
         let ctx = self.dc.context;
         let lhs1 = self.lower_tuple_pattern(&lhs.span, &lhs.k, None, None).0;
         self.dc.context = ctx;
+        let consumed = self.dc.consumed.clone();
+        let view = matches!(rhs.k, ast::ExprKind::Var(_)) && lhs.k.as_single_name().is_none();
         let rhs = self.check_expr(rhs, lhs1.ctx.ty).0;
+        // Destructuring an owned pointer as `{((ghost buf) p) := o}` does not move `o`,
+        // so that it can still be passed to `free` afterwards
+        if view && matches!(self.whnf_ty(span, rhs.ty().into()).ty.k, TyKind::Own(_)) {
+          self.dc.consumed = consumed
+        }
         // lower the LHS again to unify the types better
         let lhs = self.lower_tuple_pattern(&lhs.span, &lhs.k, rhs.k.1.0, Some(rhs.k.1.1)).0;
         UnelabStmt::Let {lhs, rhs}
@@ -4303,7 +4678,7 @@ impl<'a, 'n> InferCtx<'a, 'n> {
         let data = LabelData {
           labels: labs2,
           has_jump: false, value: AgreeExpr::Unset,
-          ret: Some(tgt), dcs: vec![]
+          ret: Some(tgt), dcs: vec![], moved: Default::default(),
         };
         assert!(self.labels.insert(v, data).is_none());
         UnelabStmt::Label(v, todo.into())
@@ -4392,7 +4767,8 @@ impl<'a, 'n> InferCtx<'a, 'n> {
   pub fn lower_item(&mut self, Spanned {span, k: item}: &'a ast::Item) -> Option<hir::Item<'a>> {
     let item = match item {
       &ast::ItemKind::Proc {
-        intrinsic, kind, ref name, tyargs, ref args, ref outs, ref rets, ref variant, ref body
+        intrinsic, inline, kind, ref name, tyargs, ref args, ref outs, ref rets, ref variant,
+        ref body
       } => {
         let name = hir::Spanned {span: &name.span, k: name.k};
         let args2 = args.iter()
@@ -4421,7 +4797,10 @@ impl<'a, 'n> InferCtx<'a, 'n> {
           self.lower_tuple_pattern(&pat.span, &pat.k, None, None).0))));
         let rets = self.finish_args(rets2);
         let t_rets = self.args_to_ty_args(&rets);
-        self.returns = Some(t_rets);
+        // The `out` parameters are passed back implicitly, so only the remaining
+        // return values are given by `return` and the tail expression
+        let t_vals = &t_rets[outs.len()..];
+        self.returns = Some(t_vals);
         let ctx = self.dc.context;
         let variant = self.lower_variant(variant);
         let args = self.finish_args(args2);
@@ -4448,15 +4827,15 @@ impl<'a, 'n> InferCtx<'a, 'n> {
         }
         if intrinsic.is_some() { return None }
         self.dc.context = ctx;
-        let sigma = match *t_rets {
+        let sigma = match *t_vals {
           [] => self.common.t_unit,
           [arg] => arg.k.1.var().k.ty,
-          _ => intern!(self, TyKind::Struct(t_rets)),
+          _ => intern!(self, TyKind::Struct(t_vals)),
         };
         let mut body = self.check_block(span, body, sigma).0;
         let e = body.expr.take().map_or_else(|| hir::Spanned {span, k:
           (hir::ExprKind::Unit, (Some(self.common.e_unit), self.common.t_unit))}, |e| *e);
-        let (span, k) = match t_rets.len() {
+        let (span, k) = match t_vals.len() {
           0 => {
             body.stmts.push(e.map_into(hir::StmtKind::Expr));
             (span, hir::ExprKind::Return(vec![]))
@@ -4470,7 +4849,7 @@ impl<'a, 'n> InferCtx<'a, 'n> {
         };
         body.expr = Some(Box::new(hir::Spanned {span, k:
           (k, (Some(self.common.e_unit), self.common.t_false))}));
-        hir::ItemKind::Proc {kind, name, tyargs, args, gen, outs, rets, variant, body}
+        hir::ItemKind::Proc {kind, inline, name, tyargs, args, gen, outs, rets, variant, body}
       }
       ast::ItemKind::Global(intrinsic, lhs, rhs) => {
         if let Some(intrinsic) = intrinsic { match *intrinsic {} }
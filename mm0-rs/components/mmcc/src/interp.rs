@@ -0,0 +1,932 @@
+//! A reference interpreter for MIR.
+//!
+//! This executes the optimized MIR of a procedure on concrete inputs. The compiler only uses it
+//! to evaluate constant initializers in the linker, but it also gives an executable semantics to
+//! test the generated machine code against.
+//!
+//! The memory model is typed: every variable and every object behind a pointer lives in its own
+//! cell holding a [`Value`], and a pointer is a path into one of these cells. Pointers can be
+//! converted to integers (see [`PunKind::Ptr`]), in which case they are given addresses in a
+//! fake address space that respects the layout of the types, but is otherwise unrelated to the
+//! addresses used by the compiled program. Ghost variables are erased: they are never bound,
+//! and they evaluate to [`Value::Unit`]. System calls are modeled by a small kernel with a file
+//! descriptor table, in which standard input and output are buffers and pipes are queues. The
+//! file system is not modeled, and the clock and random number generator are deterministic.
+
+use std::collections::{HashMap, VecDeque};
+use num::{BigInt, Signed};
+use crate::{Compiler, Symbol};
+use crate::types::{IdxVec, IntTy, Size, global};
+use crate::types::entity::{ConstTc, Entity, IntrinsicProc, ProcTc, ProcTy};
+use crate::types::mir::{Arg, ArgAttr, Binop, BlockId, Cfg, ConstKind, Constant, ExprKind,
+  LetKind, ListKind, Operand, Place, Proc, Projection, PunKind, RValue, Statement, Terminator,
+  TyKind, Unop, VarId};
+
+/// The number of statements and terminators the interpreter will execute before giving up.
+pub const DEFAULT_FUEL: u64 = 1 << 24;
+
+/// The maximum depth of nested calls.
+const MAX_DEPTH: usize = 1 << 10;
+
+/// The largest array that will be materialized by an `uninit` constant.
+const MAX_ARRAY_LEN: usize = 1 << 24;
+
+/// The fake address of the first memory cell.
+const CELL_BASE: u64 = 0x1000_0000;
+
+/// The distance between the fake addresses of consecutive memory cells.
+const CELL_STRIDE: u64 = 1 << 20;
+
+/// The error code returned by system calls on a file descriptor that is not open.
+const EBADF: u32 = 9;
+/// The error code returned by `mmap` on a file that cannot be mapped.
+const ENODEV: u32 = 19;
+/// The error code returned by system calls on invalid arguments.
+const EINVAL: u32 = 22;
+/// The error code returned by `lseek` on a pipe.
+const ESPIPE: u32 = 29;
+
+/// The maximum number of open file descriptors.
+const MAX_FDS: usize = 1024;
+
+/// The initial (fake) value of the program break.
+const BRK_BASE: u64 = 0x0800_0000;
+
+/// An open file description in the interpreter.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum File {
+  /// Standard input, which reads from the input buffer.
+  Stdin,
+  /// Standard output, which appends to the output buffer.
+  Stdout,
+  /// Standard error, which discards everything written to it.
+  Stderr,
+  /// The read end of the pipe with the given index.
+  PipeRead(usize),
+  /// The write end of the pipe with the given index.
+  PipeWrite(usize),
+}
+
+mk_id! {
+  /// A memory cell in the interpreter.
+  MemId(Debug("m"))
+}
+
+/// A step in the path of a [`Ptr`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Step {
+  /// Select the `i`th element of a struct or array.
+  Elem(usize),
+  /// Select the subarray `a[i..i+n]`. This can only be the last step in a path.
+  Slice(usize, usize),
+}
+
+/// A pointer into the memory of the interpreter.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ptr {
+  /// The memory cell containing the target.
+  cell: MemId,
+  /// The path from the root of the cell to the target.
+  path: Vec<Step>,
+  /// The fake address of the target.
+  addr: u64,
+}
+
+impl Ptr {
+  fn new(cell: MemId) -> Self {
+    Self { cell, path: vec![], addr: CELL_BASE + u64::from(cell.0).wrapping_mul(CELL_STRIDE) }
+  }
+
+  /// The (fake) address of this pointer.
+  #[must_use] pub fn addr(&self) -> u64 { self.addr }
+
+  /// Select element `i` of the target, which is at offset `off` from the start of the target.
+  fn elem(mut self, i: usize, off: u64) -> Self {
+    self.addr = self.addr.wrapping_add(off);
+    match self.path.last_mut() {
+      Some(last @ &mut Step::Slice(start, _)) => *last = Step::Elem(start + i),
+      _ => self.path.push(Step::Elem(i)),
+    }
+    self
+  }
+
+  /// Select the subarray `a[i..i+n]` of the target, which is at offset `off`.
+  fn slice(mut self, i: usize, n: usize, off: u64) -> Self {
+    self.addr = self.addr.wrapping_add(off);
+    match self.path.last_mut() {
+      Some(Step::Slice(start, len)) => { *start += i; *len = n }
+      _ => self.path.push(Step::Slice(i, n)),
+    }
+    self
+  }
+}
+
+/// A value in the interpreter.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+  /// The unit value `()`. This is also the value of all ghost variables and proofs.
+  Unit,
+  /// An uninitialized value.
+  Uninit,
+  /// A boolean value.
+  Bool(bool),
+  /// An integer value.
+  Int(BigInt),
+  /// A struct or array value. Ghost fields of a struct hold [`Value::Unit`].
+  List(Vec<Value>),
+  /// A pointer. Pointers are converted to their (fake) addresses in the results of
+  /// [`Compiler::eval`].
+  Ptr(Ptr),
+}
+
+/// The result of running a procedure.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+  /// The procedure returned these values.
+  Return(Vec<Value>),
+  /// The program exited successfully.
+  Exit,
+  /// The program failed an assertion.
+  Fail,
+}
+
+/// An error that prevents the interpreter from producing an [`Outcome`].
+#[derive(Clone, Debug)]
+pub enum EvalError {
+  /// The named procedure or constant does not exist, or has not been compiled.
+  UnknownName(Symbol),
+  /// The program used an operation that the interpreter does not model.
+  Unsupported(String),
+  /// An operation was applied to a value of the wrong kind, for example arithmetic on an
+  /// uninitialized value or an out of bounds index, or the arguments to a procedure do not match
+  /// its type.
+  BadValue(String),
+  /// Execution reached a block that should be unreachable.
+  Unreachable,
+  /// The step limit was reached.
+  OutOfFuel,
+  /// The calls were nested too deeply.
+  StackOverflow,
+}
+
+impl std::fmt::Display for EvalError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      EvalError::UnknownName(s) => write!(f, "unknown procedure or constant '{s}'"),
+      EvalError::Unsupported(s) => write!(f, "unsupported operation: {s}"),
+      EvalError::BadValue(s) => write!(f, "bad value: {s}"),
+      EvalError::Unreachable => write!(f, "reached unreachable code"),
+      EvalError::OutOfFuel => write!(f, "step limit exceeded"),
+      EvalError::StackOverflow => write!(f, "stack overflow"),
+    }
+  }
+}
+
+type Result<T> = std::result::Result<T, EvalError>;
+
+fn bad<T>(msg: impl Into<String>) -> Result<T> { Err(EvalError::BadValue(msg.into())) }
+fn unsupported<T>(msg: impl Into<String>) -> Result<T> { Err(EvalError::Unsupported(msg.into())) }
+
+impl Value {
+  fn as_bool(&self) -> Result<bool> {
+    match *self { Value::Bool(b) => Ok(b), ref v => bad(format!("expected a bool, got {v:?}")) }
+  }
+
+  fn as_int(&self) -> Result<&BigInt> {
+    match self { Value::Int(n) => Ok(n), v => bad(format!("expected an integer, got {v:?}")) }
+  }
+
+  fn as_usize(&self) -> Result<usize> {
+    let n = self.as_int()?;
+    n.try_into().or_else(|_| bad(format!("{n} is not a valid index")))
+  }
+
+  fn as_list(&self) -> Result<&[Value]> {
+    match self { Value::List(vs) => Ok(vs), v => bad(format!("expected an array, got {v:?}")) }
+  }
+}
+
+/// Truncate an integer to the given type, wrapping around on overflow.
+fn wrap(ity: IntTy, n: &BigInt) -> Value {
+  if ity.size() == Size::Inf { return Value::Int(n.clone()) }
+  Value::Int(crate::types::Unop::As(ity).apply_int(n).expect("finite size").into_owned())
+}
+
+fn unop(op: Unop, v: &Value) -> Result<Value> {
+  Ok(match (op, v) {
+    (Unop::Not, &Value::Bool(b)) => Value::Bool(!b),
+    (Unop::Neg(ity), Value::Int(n)) => wrap(ity, &-n),
+    (Unop::BitNot(ity), Value::Int(n)) => wrap(ity, &!n),
+    (Unop::As(_, ity), Value::Int(n)) => wrap(ity, n),
+    _ => return bad(format!("cannot apply {op:?} to {v:?}")),
+  })
+}
+
+fn binop(op: Binop, a: &Value, b: &Value) -> Result<Value> {
+  use crate::types::Binop as B;
+  let (a, b) = match (op, a, b) {
+    (Binop::And, &Value::Bool(a), &Value::Bool(b)) => return Ok(Value::Bool(a && b)),
+    (Binop::Or, &Value::Bool(a), &Value::Bool(b)) => return Ok(Value::Bool(a || b)),
+    (_, Value::Int(a), Value::Int(b)) => (a, b),
+    _ => return bad(format!("cannot apply {op:?} to {a:?}, {b:?}")),
+  };
+  let (op, ity) = match op {
+    Binop::Lt(_) => return Ok(Value::Bool(a < b)),
+    Binop::Le(_) => return Ok(Value::Bool(a <= b)),
+    Binop::Eq(_) => return Ok(Value::Bool(a == b)),
+    Binop::Ne(_) => return Ok(Value::Bool(a != b)),
+    Binop::And | Binop::Or => return bad(format!("cannot apply {op:?} to {a}, {b}")),
+    Binop::Shl(ity) | Binop::Shr(ity) if *b > BigInt::from(128) => {
+      // Shifting a bounded integer by 128 has the same effect as any larger shift
+      if ity.size() == Size::Inf { return unsupported(format!("shift by {b}")) }
+      let b = BigInt::from(128);
+      return binop(op, &Value::Int(a.clone()), &Value::Int(b))
+    }
+    Binop::Add(ity) => (B::Add, ity),
+    Binop::Mul(ity) => (B::Mul, ity),
+    Binop::Sub(ity) => (B::Sub, ity),
+    Binop::Max(ity) => (B::Max, ity),
+    Binop::Min(ity) => (B::Min, ity),
+    Binop::BitAnd(ity) => (B::BitAnd, ity),
+    Binop::BitOr(ity) => (B::BitOr, ity),
+    Binop::BitXor(ity) => (B::BitXor, ity),
+    Binop::Shl(ity) => (B::Shl, ity),
+    Binop::Shr(ity) => (B::Shr, ity),
+  };
+  match op.apply_int_int(a, b) {
+    Some(n) => Ok(wrap(ity, &n)),
+    None => bad(format!("cannot apply {op} to {a}, {b}")),
+  }
+}
+
+/// Apply an operation from a pure expression, which acts on unbounded integers.
+fn pure_unop(op: crate::types::Unop, v: &Value) -> Result<Value> {
+  use crate::types::Unop as U;
+  match (op, v) {
+    (U::Not, &Value::Bool(b)) => Ok(Value::Bool(!b)),
+    (U::As(IntTy::UInt(Size::Inf)), Value::Int(n)) if !n.is_negative() => Ok(Value::Int(n.clone())),
+    (U::As(IntTy::UInt(Size::Inf)), _) => bad(format!("cannot apply {op} to {v:?}")),
+    (_, Value::Int(n)) => match op.apply_int(n) {
+      Some(n) => Ok(Value::Int(n.into_owned())),
+      None => bad(format!("cannot apply {op} to {n}")),
+    },
+    _ => bad(format!("cannot apply {op} to {v:?}")),
+  }
+}
+
+/// Apply an operation from a pure expression, which acts on unbounded integers.
+fn pure_binop(op: crate::types::Binop, a: &Value, b: &Value) -> Result<Value> {
+  use crate::types::BinopType;
+  match (op.ty(), a, b) {
+    (BinopType::BoolBoolBool, &Value::Bool(a), &Value::Bool(b)) =>
+      Ok(Value::Bool(op.apply_bool_bool(a, b))),
+    (BinopType::IntIntBool, Value::Int(a), Value::Int(b)) =>
+      Ok(Value::Bool(op.apply_int_bool(a, b))),
+    (BinopType::IntIntInt | BinopType::IntNatInt, Value::Int(a), Value::Int(b)) =>
+      match op.apply_int_int(a, b) {
+        Some(n) => Ok(Value::Int(n)),
+        None => bad(format!("cannot apply {op} to {a}, {b}")),
+      },
+    _ => bad(format!("cannot apply {op} to {a:?}, {b:?}")),
+  }
+}
+
+/// The local variables of a procedure, mapped to their locations.
+/// Ghost variables are not in the map.
+type Frame = HashMap<VarId, Ptr>;
+
+struct Interp<'a> {
+  names: &'a HashMap<Symbol, Entity>,
+  mir: &'a HashMap<Symbol, Proc>,
+  mem: IdxVec<MemId, Value>,
+  consts: HashMap<Symbol, Value>,
+  stdin: &'a [u8],
+  stdout: Vec<u8>,
+  /// The file descriptor table.
+  fds: Vec<Option<File>>,
+  /// The contents of each pipe that has been created.
+  pipes: Vec<VecDeque<u8>>,
+  /// The current program break.
+  brk: u64,
+  /// The state of the random number generator.
+  rng: u64,
+  fuel: u64,
+  depth: usize,
+}
+
+impl<'a> Interp<'a> {
+  fn new(
+    names: &'a HashMap<Symbol, Entity>,
+    mir: &'a HashMap<Symbol, Proc>,
+    stdin: &'a [u8],
+  ) -> Self {
+    Self {
+      names,
+      mir,
+      mem: Default::default(),
+      consts: Default::default(),
+      stdin,
+      stdout: vec![],
+      fds: vec![Some(File::Stdin), Some(File::Stdout), Some(File::Stderr)],
+      pipes: vec![],
+      brk: BRK_BASE,
+      rng: 0x2545_f491_4f6c_dd1d,
+      fuel: DEFAULT_FUEL,
+      depth: 0,
+    }
+  }
+
+  fn tick(&mut self) -> Result<()> {
+    self.fuel = self.fuel.checked_sub(1).ok_or(EvalError::OutOfFuel)?;
+    Ok(())
+  }
+
+  /// True if an argument of a procedure or struct is passed at runtime.
+  fn relevant(&self, arg: &Arg) -> bool {
+    !arg.attr.contains(ArgAttr::GHOST) && arg.ty.sizeof(self.names) != Some(0)
+  }
+
+  fn sizeof(&self, ty: &TyKind) -> Result<u64> {
+    match ty.sizeof(self.names) {
+      Some(n) => Ok(n),
+      None => unsupported(format!("sizeof {ty:?}")),
+    }
+  }
+
+  /// The offset of element `i` in a value of type `ty`, for computing fake addresses.
+  fn elem_offset(&self, ty: &TyKind, i: usize) -> u64 {
+    match ty {
+      TyKind::Struct(args) => args[..i].iter()
+        .filter(|arg| !arg.attr.contains(ArgAttr::GHOST))
+        .map(|arg| arg.ty.sizeof(self.names).unwrap_or(0))
+        .fold(0, u64::wrapping_add),
+      TyKind::Array(ty, _) => ty.sizeof(self.names).unwrap_or(0)
+        .wrapping_mul(i.try_into().expect("overflow")),
+      TyKind::Sn(_, ty) |
+      TyKind::Uninit(ty) |
+      TyKind::Moved(ty) |
+      TyKind::All(_, _, ty) => self.elem_offset(ty, i),
+      _ => 0,
+    }
+  }
+
+  fn alloc(&mut self, v: Value) -> Ptr { Ptr::new(self.mem.push(v)) }
+
+  fn bind(&mut self, frame: &mut Frame, v: VarId, val: Value) {
+    let p = self.alloc(val);
+    frame.insert(v, p);
+  }
+
+  fn load(&self, p: &Ptr) -> Result<Value> {
+    let mut v = &self.mem[p.cell];
+    for &step in &p.path {
+      let vs = match v {
+        Value::List(vs) => vs,
+        Value::Uninit => return Ok(Value::Uninit),
+        _ => return bad(format!("cannot project out of {v:?}")),
+      };
+      match step {
+        Step::Elem(i) => v = vs.get(i).map_or_else(|| bad("index out of bounds"), Ok)?,
+        Step::Slice(i, n) => return vs.get(i..i+n)
+          .map_or_else(|| bad("slice out of bounds"), |vs| Ok(Value::List(vs.to_vec()))),
+      }
+    }
+    Ok(v.clone())
+  }
+
+  fn store(&mut self, p: &Ptr, val: Value) -> Result<()> {
+    let mut v = &mut self.mem[p.cell];
+    for &step in &p.path {
+      let Value::List(vs) = v else { return bad(format!("cannot project out of {v:?}")) };
+      match step {
+        Step::Elem(i) => v = vs.get_mut(i).map_or_else(|| bad("index out of bounds"), Ok)?,
+        Step::Slice(i, n) => {
+          let Some(vs) = vs.get_mut(i..i+n) else { return bad("slice out of bounds") };
+          match val {
+            Value::List(new) if new.len() == n => vs.clone_from_slice(&new),
+            _ => return bad(format!("cannot store {val:?} into a slice of length {n}")),
+          }
+          return Ok(())
+        }
+      }
+    }
+    *v = val;
+    Ok(())
+  }
+
+  fn read(&self, frame: &Frame, v: VarId) -> Result<Value> {
+    match frame.get(&v) {
+      Some(p) => self.load(p),
+      None => Ok(Value::Unit),
+    }
+  }
+
+  /// Evaluate a place to a pointer, or `None` if the place is rooted in a ghost variable.
+  fn place(&self, frame: &Frame, p: &Place) -> Result<Option<Ptr>> {
+    let Some(mut ptr) = frame.get(&p.local).cloned() else { return Ok(None) };
+    for (ty, proj) in &p.proj {
+      ptr = match *proj {
+        Projection::Proj(ListKind::Struct | ListKind::Array, i) => {
+          let i = crate::u32_as_usize(i);
+          let off = self.elem_offset(ty, i);
+          ptr.elem(i, off)
+        }
+        Projection::Proj(ListKind::And | ListKind::Sn, _) => ptr,
+        Projection::Index(i, _) => {
+          let i = self.read(frame, i)?.as_usize()?;
+          let off = self.elem_offset(ty, i);
+          ptr.elem(i, off)
+        }
+        Projection::Slice(i, l, _) => {
+          let i = self.read(frame, i)?.as_usize()?;
+          let l = self.read(frame, l)?.as_usize()?;
+          let off = self.elem_offset(ty, i);
+          ptr.slice(i, l, off)
+        }
+        Projection::Deref => match self.load(&ptr)? {
+          Value::Ptr(p) => p,
+          v => return bad(format!("cannot dereference {v:?}")),
+        }
+      }
+    }
+    Ok(Some(ptr))
+  }
+
+  fn read_place(&self, frame: &Frame, p: &Place) -> Result<Value> {
+    match self.place(frame, p)? {
+      Some(p) => self.load(&p),
+      None => Ok(Value::Unit),
+    }
+  }
+
+  fn operand(&mut self, frame: &Frame, o: &Operand) -> Result<Value> {
+    match o {
+      Operand::Copy(p) | Operand::Move(p) | Operand::Ref(p) => self.read_place(frame, p),
+      Operand::Const(c) => self.constant(frame, c),
+    }
+  }
+
+  fn constant(&mut self, frame: &Frame, c: &Constant) -> Result<Value> {
+    Ok(match &c.k {
+      ConstKind::Unit | ConstKind::ITrue | ConstKind::Mm0Proof(_) | ConstKind::Linarith(_) |
+      ConstKind::Contra(..) => Value::Unit,
+      ConstKind::Bool | ConstKind::Int => match &c.ety.0 {
+        Some(e) => self.expr(frame, e)?,
+        None => return bad("missing constant value"),
+      },
+      ConstKind::Uninit => self.uninit(frame, &c.ety.1)?,
+      &ConstKind::Const(s) => self.global_const(s)?,
+      ConstKind::Sizeof => Value::Int(self.sizeof(c.ty_as_sizeof().1)?.into()),
+      ConstKind::As(c) => wrap(c.1, self.constant(frame, &c.0)?.as_int()?),
+    })
+  }
+
+  /// Construct an uninitialized value of the given type. Structs and arrays are built out of
+  /// their components, so that they can be initialized piecewise.
+  fn uninit(&mut self, frame: &Frame, ty: &TyKind) -> Result<Value> {
+    Ok(match ty {
+      TyKind::Sn(_, ty) |
+      TyKind::Uninit(ty) |
+      TyKind::Moved(ty) |
+      TyKind::All(_, _, ty) => return self.uninit(frame, ty),
+      TyKind::Struct(args) => Value::List(args.iter().map(|arg| if self.relevant(arg) {
+        self.uninit(frame, &arg.ty)
+      } else {
+        Ok(Value::Unit)
+      }).collect::<Result<_>>()?),
+      TyKind::Array(ty, n) => {
+        let n = self.expr(frame, n)?.as_usize()?;
+        if n > MAX_ARRAY_LEN { return unsupported(format!("array of length {n}")) }
+        Value::List(vec![self.uninit(frame, ty)?; n])
+      }
+      _ if ty.sizeof(self.names) == Some(0) => Value::Unit,
+      _ => Value::Uninit,
+    })
+  }
+
+  /// Evaluate a pure expression appearing in MIR, like the length of an array type.
+  fn expr(&mut self, frame: &Frame, e: &ExprKind) -> Result<Value> {
+    Ok(match e {
+      ExprKind::Unit => Value::Unit,
+      &ExprKind::Var(v) => self.read(frame, v)?,
+      &ExprKind::Const(s) => self.global_const(s)?,
+      &ExprKind::Bool(b) => Value::Bool(b),
+      ExprKind::Int(n) => Value::Int(n.clone()),
+      ExprKind::Unop(op, e) => pure_unop(*op, &self.expr(frame, e)?)?,
+      ExprKind::Binop(op, e1, e2) =>
+        pure_binop(*op, &self.expr(frame, e1)?, &self.expr(frame, e2)?)?,
+      ExprKind::Sizeof(ty) => Value::Int(self.sizeof(ty)?.into()),
+      _ => return unsupported(format!("expression {e:?}")),
+    })
+  }
+
+  fn global_const(&mut self, s: Symbol) -> Result<Value> {
+    if let Some(v) = self.consts.get(&s) { return Ok(v.clone()) }
+    let Some(Entity::Const(tc)) = self.names.get(&s) else { return Err(EvalError::UnknownName(s)) };
+    let ConstTc::Checked { whnf, .. } = &tc.k else { return Err(EvalError::UnknownName(s)) };
+    let v = self.global_expr(whnf)?;
+    self.consts.insert(s, v.clone());
+    Ok(v)
+  }
+
+  /// Evaluate the (closed) expression of a global constant.
+  fn global_expr(&mut self, e: &global::ExprKind) -> Result<Value> {
+    use global::ExprKind as E;
+    Ok(match e {
+      E::Unit => Value::Unit,
+      &E::Const(s) => self.global_const(s)?,
+      &E::Bool(b) => Value::Bool(b),
+      E::Int(n) => Value::Int(n.clone()),
+      E::Unop(op, e) => pure_unop(*op, &self.global_expr(e)?)?,
+      E::Binop(op, e1, e2) => pure_binop(*op, &self.global_expr(e1)?, &self.global_expr(e2)?)?,
+      E::Index(a, i) => {
+        let (a, i) = (self.global_expr(a)?, self.global_expr(i)?.as_usize()?);
+        a.as_list()?.get(i).map_or_else(|| bad("index out of bounds"), |v| Ok(v.clone()))?
+      }
+      E::Slice([a, i, l]) => {
+        let a = self.global_expr(a)?;
+        let (i, l) = (self.global_expr(i)?.as_usize()?, self.global_expr(l)?.as_usize()?);
+        a.as_list()?.get(i..i+l)
+          .map_or_else(|| bad("slice out of bounds"), |vs| Ok(Value::List(vs.to_vec())))?
+      }
+      E::Proj(a, i) => {
+        let a = self.global_expr(a)?;
+        a.as_list()?.get(crate::u32_as_usize(*i))
+          .map_or_else(|| bad("projection out of bounds"), |v| Ok(v.clone()))?
+      }
+      E::List(es) | E::Array(es) =>
+        Value::List(es.iter().map(|e| self.global_expr(e)).collect::<Result<_>>()?),
+      E::Call { f, tys, args } => {
+        if !tys.is_empty() { return unsupported("calls to generic functions") }
+        let args = args.iter().map(|e| self.global_expr(e)).collect::<Result<_>>()?;
+        match self.call(*f, args)? {
+          Outcome::Return(mut vals) if vals.len() == 1 => vals.pop().expect("nonempty"),
+          Outcome::Return(vals) => Value::List(vals),
+          o => return bad(format!("function {f} did not return: {o:?}")),
+        }
+      }
+      E::If { cond, then, els } =>
+        if self.global_expr(cond)?.as_bool()? { self.global_expr(then)? }
+        else { self.global_expr(els)? },
+      _ => return unsupported(format!("constant expression {e:?}")),
+    })
+  }
+
+  fn rvalue(&mut self, frame: &Frame, rv: &RValue) -> Result<Value> {
+    Ok(match rv {
+      RValue::Use(o) | RValue::Cast(_, o, _) => self.operand(frame, o)?,
+      RValue::Unop(op, o) => unop(*op, &self.operand(frame, o)?)?,
+      RValue::Binop(op, o1, o2) => binop(*op, &self.operand(frame, o1)?, &self.operand(frame, o2)?)?,
+      RValue::Eq(_, inverted, o1, o2) =>
+        Value::Bool((self.operand(frame, o1)? == self.operand(frame, o2)?) != *inverted),
+      RValue::Pun(PunKind::Ptr, p) => match self.read_place(frame, p)? {
+        Value::Ptr(p) => Value::Int(p.addr.into()),
+        v => return bad(format!("expected a pointer, got {v:?}")),
+      },
+      RValue::Pun(_, p) => self.read_place(frame, p)?,
+      RValue::List(os) | RValue::Array(os) =>
+        Value::List(os.iter().map(|o| self.operand(frame, o)).collect::<Result<_>>()?),
+      RValue::Ghost(_) | RValue::Typeof(_) => Value::Unit,
+      RValue::Borrow(p) => match self.place(frame, p)? {
+        Some(p) => Value::Ptr(p),
+        None => return bad(format!("cannot borrow ghost place {p:?}")),
+      },
+      RValue::Mm0(..) => return unsupported("MM0 expression in a relevant position"),
+    })
+  }
+
+  fn stmt(&mut self, frame: &mut Frame, stmt: &Statement) -> Result<()> {
+    match stmt {
+      Statement::Let(lk, r, _, rv) => if *r {
+        let val = self.rvalue(frame, rv)?;
+        let v = match lk { LetKind::Let(v, _) | LetKind::Ptr([_, (v, _)]) => v.k };
+        self.bind(frame, v, val)
+      }
+      Statement::Assign(lhs, _, rhs, renames) => if stmt.relevant() {
+        let val = self.operand(frame, rhs)?;
+        if let Some(p) = self.place(frame, lhs)? { self.store(&p, val)? }
+        for r in renames.iter().filter(|r| r.rel) {
+          if let Some(p) = frame.get(&r.from).cloned() { frame.insert(r.to.k, p); }
+        }
+      }
+      Statement::LabelGroup(..) | Statement::PopLabelGroup | Statement::DominatedBlock(..) => {}
+    }
+    Ok(())
+  }
+
+  fn run_cfg(&mut self, cfg: &Cfg, frame: &mut Frame) -> Result<Outcome> {
+    let mut id = BlockId::ENTRY;
+    loop {
+      let bl = &cfg[id];
+      for stmt in &bl.stmts {
+        self.tick()?;
+        self.stmt(frame, stmt)?;
+      }
+      self.tick()?;
+      id = match bl.terminator() {
+        Terminator::Jump(tgt, args, _) => {
+          let mut vals = vec![];
+          for &(v, r, ref o) in &**args {
+            if r { vals.push((v, self.operand(frame, o)?)) }
+          }
+          for (v, val) in vals { self.bind(frame, v, val) }
+          *tgt
+        }
+        &Terminator::Jump1(_, tgt) => tgt,
+        Terminator::Return(_, args) => {
+          // `out` parameters are ghost, and the caller does not receive them
+          let rets = args.iter().map(|(_, r, o)|
+            if *r { self.operand(frame, o) } else { Ok(Value::Unit) }).collect::<Result<_>>()?;
+          return Ok(Outcome::Return(rets))
+        }
+        Terminator::Unreachable(_) | Terminator::Dead => return Err(EvalError::Unreachable),
+        Terminator::If(_, cond, [(_, bl1), (_, bl2)]) =>
+          if self.operand(frame, cond)?.as_bool()? { *bl1 } else { *bl2 },
+        Terminator::Assert(cond, _, tgt) =>
+          if self.operand(frame, cond)?.as_bool()? { *tgt } else { return Ok(Outcome::Fail) },
+        Terminator::Fail => return Ok(Outcome::Fail),
+        Terminator::Call { f, tys, args, tgt, rets, .. } => {
+          if !tys.is_empty() { return unsupported("calls to generic functions") }
+          let args = args.iter().map(|(r, o)| if *r {
+            self.operand(frame, o)
+          } else {
+            Ok(Value::Unit)
+          }).collect::<Result<_>>()?;
+          match self.call(*f, args)? {
+            Outcome::Return(vals) => {
+              if vals.len() != rets.len() { return bad(format!("wrong number of returns from {f}")) }
+              for (&(r, v), val) in rets.iter().zip(vals) {
+                if r { self.bind(frame, v, val) }
+              }
+              *tgt
+            }
+            o => return Ok(o),
+          }
+        }
+        Terminator::Exit(_) => return Ok(Outcome::Exit),
+      }
+    }
+  }
+
+  fn run(&mut self, proc: &Proc, args: Vec<Value>) -> Result<Outcome> {
+    if proc.tyargs != 0 { return unsupported("generic procedures") }
+    if args.len() != proc.args.len() {
+      return bad(format!("wrong number of arguments to {}", proc.name.k))
+    }
+    if self.depth >= MAX_DEPTH { return Err(EvalError::StackOverflow) }
+    let mut frame = Frame::new();
+    for (arg, v) in proc.args.iter().zip(args) {
+      if !arg.attr.contains(ArgAttr::GHOST) { self.bind(&mut frame, arg.var, v) }
+    }
+    self.depth += 1;
+    let res = self.run_cfg(&proc.body, &mut frame);
+    self.depth -= 1;
+    res
+  }
+
+  fn call(&mut self, f: Symbol, args: Vec<Value>) -> Result<Outcome> {
+    if let Some(proc) = self.mir.get(&f) { return self.run(proc, args) }
+    if_chain! {
+      if let Some(Entity::Proc(tc)) = self.names.get(&f);
+      if let ProcTc::Typed(ty) = &tc.k;
+      if let Some(intrinsic) = ty.intrinsic;
+      then { return self.intrinsic(intrinsic, ty, &args).map(Outcome::Return) }
+    }
+    Err(EvalError::UnknownName(f))
+  }
+
+  /// Look up an open file descriptor.
+  fn file(&self, fd: &Value) -> Result<Option<File>> {
+    Ok(self.fds.get(fd.as_usize()?).copied().flatten())
+  }
+
+  /// Open a file at the lowest free file descriptor.
+  fn open(&mut self, file: File) -> u32 {
+    let fd = match self.fds.iter().position(Option::is_none) {
+      Some(fd) => { self.fds[fd] = Some(file); fd }
+      None => { self.fds.push(Some(file)); self.fds.len() - 1 }
+    };
+    fd.try_into().expect("overflow")
+  }
+
+  /// Store bytes into the array pointed to by `p`.
+  fn store_bytes(&mut self, p: &Ptr, buf: impl IntoIterator<Item=u8>) -> Result<()> {
+    for (i, b) in buf.into_iter().enumerate() {
+      self.store(&p.clone().elem(i, i.try_into().expect("overflow")), Value::Int(b.into()))?
+    }
+    Ok(())
+  }
+
+  /// Run a system call. The file system is not modeled, so opening files and `fstat` are not
+  /// supported. Reads from an empty pipe whose write end is still open would block forever, so
+  /// they are also not supported.
+  fn intrinsic(&mut self,
+    intrinsic: IntrinsicProc, ty: &ProcTy, args: &[Value],
+  ) -> Result<Vec<Value>> {
+    fn ret(n: u32) -> Vec<Value> { vec![Value::Int(n.into())] }
+    fn err(code: u32) -> Vec<Value> { ret(code.wrapping_neg()) }
+    fn ret64(n: u64) -> Vec<Value> { vec![Value::Int(n.into())] }
+    fn err64(code: u32) -> Vec<Value> { ret64(u64::from(code).wrapping_neg()) }
+    // The ghost output comes before the return value
+    fn with_ghost(mut v: Vec<Value>) -> Vec<Value> { v.insert(0, Value::Unit); v }
+    match (intrinsic, args) {
+      (IntrinsicProc::Read, [fd, count, _, Value::Ptr(p)]) => {
+        let count = count.as_usize()?;
+        let buf = match self.file(fd)? {
+          Some(File::Stdin) => {
+            let (buf, rest) = self.stdin.split_at(count.min(self.stdin.len()));
+            self.stdin = rest;
+            buf.to_vec()
+          }
+          Some(File::PipeRead(i)) => {
+            if self.pipes[i].is_empty() && self.fds.contains(&Some(File::PipeWrite(i))) {
+              return unsupported("read from an empty pipe")
+            }
+            let n = count.min(self.pipes[i].len());
+            self.pipes[i].drain(..n).collect()
+          }
+          _ => return Ok(err(EBADF)),
+        };
+        self.store_bytes(p, buf.iter().copied())?;
+        Ok(ret(buf.len().try_into().expect("overflow")))
+      }
+      (IntrinsicProc::Write, [fd, count, _, Value::Ptr(p)]) => {
+        let file = self.file(fd)?;
+        if !matches!(file, Some(File::Stdout | File::Stderr | File::PipeWrite(_))) {
+          return Ok(err(EBADF))
+        }
+        let n = count.as_usize()?;
+        let buf = self.load(p)?;
+        let Some(buf) = buf.as_list()?.get(..n) else { return bad("write out of bounds") };
+        let buf = buf.iter().map(|b| {
+          let b = b.as_int()?;
+          b.try_into().or_else(|_| bad(format!("{b} is not a byte")))
+        }).collect::<Result<Vec<u8>>>()?;
+        match file {
+          Some(File::Stdout) => self.stdout.extend(buf),
+          Some(File::PipeWrite(i)) => self.pipes[i].extend(buf),
+          _ => {}
+        }
+        Ok(ret(n.try_into().expect("overflow")))
+      }
+      (IntrinsicProc::Close, [fd]) => match self.fds.get_mut(fd.as_usize()?) {
+        Some(file @ Some(_)) => { *file = None; Ok(ret(0)) }
+        _ => Ok(err(EBADF)),
+      }
+      (IntrinsicProc::Dup2, [oldfd, newfd]) => {
+        let Some(file) = self.file(oldfd)? else { return Ok(err(EBADF)) };
+        let newfd = newfd.as_usize()?;
+        if newfd >= MAX_FDS { return Ok(err(EBADF)) }
+        if self.fds.len() <= newfd { self.fds.resize(newfd + 1, None) }
+        self.fds[newfd] = Some(file);
+        Ok(ret(newfd.try_into().expect("overflow")))
+      }
+      (IntrinsicProc::Pipe, [_, Value::Ptr(p)]) => {
+        let i = self.pipes.len();
+        self.pipes.push(VecDeque::new());
+        let r = self.open(File::PipeRead(i));
+        let w = self.open(File::PipeWrite(i));
+        self.store(p, Value::List(vec![Value::Int(r.into()), Value::Int(w.into())]))?;
+        Ok(with_ghost(ret(0)))
+      }
+      (IntrinsicProc::LSeek, [fd, _, _]) =>
+        Ok(err64(if self.file(fd)?.is_some() { ESPIPE } else { EBADF })),
+      (IntrinsicProc::Brk, [addr]) => {
+        // The memory above the break is not accessible, because integers cannot be converted
+        // back to pointers, so only the value of the break is tracked.
+        let addr = addr.as_int()?.try_into().unwrap_or(0);
+        if addr >= BRK_BASE { self.brk = addr }
+        Ok(ret64(self.brk))
+      }
+      (IntrinsicProc::MMap, [_, _, fd]) =>
+        Ok(err64(if self.file(fd)?.is_some() { ENODEV } else { EBADF })),
+      (IntrinsicProc::MMapAnon, [len, _]) => {
+        let len = len.as_usize()?;
+        if len == 0 { return Ok(err64(EINVAL)) }
+        if len > MAX_ARRAY_LEN { return unsupported(format!("mmap of {len} bytes")) }
+        let p = self.alloc(Value::List(vec![Value::Int(0.into()); len]));
+        // `mmap` can be declared to return either an address or an owned pointer
+        match ty.rets.last().map(|ret| &**ret.1.ty()) {
+          Some(global::TyKind::Own(_)) => Ok(vec![Value::Ptr(p)]),
+          _ => Ok(ret64(p.addr)),
+        }
+      }
+      (IntrinsicProc::MUnmap, [_, _, Value::Ptr(p)]) => {
+        if !p.path.is_empty() { return unsupported("munmap of part of an allocation") }
+        self.mem[p.cell] = Value::Uninit;
+        Ok(ret(0))
+      }
+      (IntrinsicProc::ClockGetTime, [clock, _, Value::Ptr(p)]) => {
+        // All clocks are stopped at time zero
+        if clock.as_usize()? > 11 { return Ok(with_ghost(err(EINVAL))) }
+        self.store(p, Value::List(vec![Value::Int(0.into()), Value::Int(0.into())]))?;
+        Ok(with_ghost(ret(0)))
+      }
+      (IntrinsicProc::GetRandom, [count, _, Value::Ptr(p)]) => {
+        // A fixed xorshift generator, so that runs are reproducible
+        let count = count.as_usize()?;
+        let buf = (0..count).map(|_| {
+          self.rng ^= self.rng << 13;
+          self.rng ^= self.rng >> 7;
+          self.rng ^= self.rng << 17;
+          self.rng.to_le_bytes()[0]
+        }).collect::<Vec<_>>();
+        self.store_bytes(p, buf)?;
+        Ok(with_ghost(ret(count.try_into().expect("overflow"))))
+      }
+      _ => unsupported(format!("system call {}", intrinsic.as_symbol())),
+    }
+  }
+
+  /// Convert a user-supplied value to the runtime representation of type `ty`. The user value
+  /// contains only the relevant fields of structs, and pointers are given as the value they
+  /// point to.
+  fn import(&mut self, ty: &TyKind, v: Value) -> Result<Value> {
+    Ok(match (ty, v) {
+      (TyKind::Sn(_, ty) | TyKind::Uninit(ty) | TyKind::Moved(ty) | TyKind::All(_, _, ty), v) =>
+        return self.import(ty, v),
+      (TyKind::Int(ity), Value::Int(n)) if ity.contains(&n) => Value::Int(n),
+      (TyKind::Bool, v @ Value::Bool(_)) => v,
+      (TyKind::Array(ty, n), Value::List(vs)) => {
+        if self.expr(&Frame::new(), n).ok().and_then(|n| n.as_usize().ok()) != Some(vs.len()) {
+          return bad(format!("array of length {} does not have type {ty:?}", vs.len()))
+        }
+        Value::List(vs.into_iter().map(|v| self.import(ty, v)).collect::<Result<_>>()?)
+      }
+      (TyKind::Struct(args), Value::List(vs)) => {
+        let mut vs = vs.into_iter();
+        let out = args.iter().map(|arg| if self.relevant(arg) {
+          let v = vs.next().map_or_else(|| bad("not enough fields in struct"), Ok)?;
+          self.import(&arg.ty, v)
+        } else {
+          Ok(Value::Unit)
+        }).collect::<Result<_>>()?;
+        if vs.next().is_some() { return bad("too many fields in struct") }
+        Value::List(out)
+      }
+      (TyKind::Own(ty) | TyKind::Shr(_, ty), v) => {
+        let v = self.import(ty, v)?;
+        Value::Ptr(self.alloc(v))
+      }
+      (TyKind::RefSn(_), v) => Value::Ptr(self.alloc(v)),
+      (_, v) if ty.sizeof(self.names) == Some(0) => { drop(v); Value::Unit }
+      (_, v) => return bad(format!("{v:?} does not have type {ty:?}")),
+    })
+  }
+
+  /// Convert a runtime value of type `ty` to the user representation, which drops
+  /// the ghost fields of structs and replaces pointers by their addresses.
+  fn export(&self, ty: &TyKind, v: Value) -> Value {
+    match (ty, v) {
+      (TyKind::Sn(_, ty) | TyKind::Uninit(ty) | TyKind::Moved(ty) | TyKind::All(_, _, ty), v) =>
+        self.export(ty, v),
+      (TyKind::Array(ty, _), Value::List(vs)) =>
+        Value::List(vs.into_iter().map(|v| self.export(ty, v)).collect()),
+      (TyKind::Struct(args), Value::List(vs)) => Value::List(args.iter().zip(vs)
+        .filter(|(arg, _)| self.relevant(arg))
+        .map(|(arg, v)| self.export(&arg.ty, v)).collect()),
+      (_, Value::Ptr(p)) => Value::Int(p.addr.into()),
+      (_, v) => v,
+    }
+  }
+}
+
+/// Evaluate the initializer of a global constant, for use in the linker.
+pub(crate) fn eval_global(
+  names: &HashMap<Symbol, Entity>,
+  mir: &HashMap<Symbol, Proc>,
+  e: &global::ExprKind,
+) -> Result<Value> {
+  Interp::new(names, mir, &[]).global_expr(e)
+}
+
+impl<C> Compiler<C> {
+  /// Run the function or procedure `f` in the MIR interpreter, with `stdin` as the contents of
+  /// standard input, and return the outcome and the data written to standard output.
+  ///
+  /// Only the relevant arguments are passed in `args`, and only the relevant fields of structs.
+  /// An argument of pointer type is given as the value it points to, which is placed in a fresh
+  /// memory cell. The returned values are likewise stripped of ghost values, and pointers are
+  /// replaced by their addresses. This must be called before [`finish`](Self::finish), which
+  /// consumes the compiled functions.
+  pub fn eval(&self, f: Symbol, args: Vec<Value>, stdin: &[u8]
+  ) -> Result<(Outcome, Vec<u8>)> {
+    let mut interp = Interp::new(&self.names, &self.mir, stdin);
+    let proc = self.mir.get(&f).ok_or(EvalError::UnknownName(f))?;
+    let mut args = args.into_iter();
+    let all_args = proc.args.iter().map(|arg| if interp.relevant(arg) {
+      let v = args.next().map_or_else(|| bad(format!("not enough arguments to {f}")), Ok)?;
+      interp.import(&arg.ty, v)
+    } else {
+      Ok(Value::Unit)
+    }).collect::<Result<_>>()?;
+    if args.next().is_some() { return bad(format!("too many arguments to {f}")) }
+    let out = match interp.run(proc, all_args)? {
+      Outcome::Return(vals) => Outcome::Return(proc.rets.iter().zip(vals)
+        .filter(|(ret, _)| interp.relevant(ret))
+        .map(|(ret, v)| interp.export(&ret.ty, v)).collect()),
+      o => o,
+    };
+    Ok((out, interp.stdout))
+  }
+}
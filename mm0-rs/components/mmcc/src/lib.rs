@@ -108,6 +108,8 @@
 pub mod types;
 pub mod build_ast;
 mod union_find;
+pub mod arith;
+mod cache;
 pub mod infer;
 mod nameck;
 mod build_mir;
@@ -118,9 +120,12 @@ pub mod arch;
 mod regalloc;
 mod linker;
 mod codegen;
+mod debuginfo;
+mod listing;
 pub mod proof;
+pub mod interp;
 
-use std::collections::HashMap;
+use std::{cell::RefCell, collections::HashMap, hash::Hasher, rc::Rc};
 use types::{entity::Entity, mir, Spanned};
 use bumpalo::Bump;
 use infer::TypeError;
@@ -153,6 +158,11 @@ pub trait ItemContext<C: Config + ?Sized> {
   /// Return a new lambda printer.
   fn print(&mut self) -> Self::Printer;
 
+  /// Feed any data that affects the meaning of the item but is not part of the AST, such as
+  /// the source text and the lambda expressions, to the hasher. This is used to decide whether
+  /// a procedure can be reused from a previous compilation.
+  fn hash_item(&self, _h: &mut impl Hasher) {}
+
   /// This function is called if errors are produced during typechecking.
   fn emit_type_errors<'a>(&mut self, _ctx: &mut C,
     _errs: Vec<hir::Spanned<'a, TypeError<'a>>>,
@@ -210,6 +220,14 @@ pub struct Compiler<C> {
   main: Option<Symbol>,
   /// If true, some items have not been generated correctly, so compilation cannot proceed.
   has_type_errors: bool,
+  /// The cache keys of the procedures that have been added.
+  keys: HashMap<Symbol, u64>,
+  /// The results of compiling procedures in earlier runs. This is shared between clones of the
+  /// compiler, so a compiler copied from another file can reuse the work of previous
+  /// elaborations of this one.
+  cache: Rc<RefCell<cache::ItemCache>>,
+  /// The number of procedures that were reused from `cache`.
+  cache_hits: usize,
 }
 
 impl<C: Default> Default for Compiler<C> {
@@ -225,6 +243,9 @@ impl<C> Compiler<C> {
       init: Default::default(),
       main: None,
       has_type_errors: false,
+      keys: Default::default(),
+      cache: Default::default(),
+      cache_hits: 0,
       config,
     })
   }
@@ -235,10 +256,22 @@ impl<C: Config> Compiler<C> {
   /// performing typehecking but not code generation.
   /// This should be called repeatedly to add all top level function items,
   /// finally calling [`finish`](Self::finish) to complete code generation.
+  ///
+  /// If a procedure is added again, and neither it nor the declarations it refers to have changed
+  /// since it was last compiled by this compiler (or a clone of it), the previous result is reused.
   pub fn add(&mut self, item: &ast::Item, var_names: IdxVec<VarId, Spanned<Symbol>>,
     mut ic: impl ItemContext<C>
   ) -> Result<(), C::Error> {
-    let Compiler {names, mir, init, main, has_type_errors, ..} = self;
+    let item_hash = cache::hash_item(item, &var_names, &ic);
+    let proc_name = if let ast::ItemKind::Proc {kind, ref name, ..} = item.k {
+      let main = kind == ast::ProcKind::Main;
+      if !(main && self.main.is_some()) && self.add_cached(name, &item.span, item_hash) {
+        if main { self.main = Some(name.k) }
+        return Ok(())
+      }
+      Some(name.k)
+    } else { None };
+    let Compiler {names, mir, init, main, has_type_errors, keys, cache, ..} = self;
     let hir_alloc = Bump::new();
     let mut ctx = infer::InferCtx::new(&hir_alloc, names, var_names);
     if let ast::ItemKind::Proc {kind: ast::ProcKind::Main, ref name, ..} = item.k {
@@ -249,6 +282,7 @@ impl<C: Config> Compiler<C> {
       }
     }
     let item = ctx.lower_item(item);
+    let mut deps = std::mem::take(&mut ctx.deps);
     let item_errors = ctx.has_ast_errors || !ctx.errors.is_empty();
     *has_type_errors |= item_errors;
     if !ctx.errors.is_empty() {
@@ -258,12 +292,49 @@ impl<C: Config> Compiler<C> {
     }
     if let Some(item) = item.filter(|_| !item_errors) {
       if let Some(n) = build_mir::BuildMir::new(Some(&mut ctx.mvars)).build_item(mir, init, item) {
-        mir.get_mut(&n).expect("missing").optimize(names);
+        let mut proc = mir.remove(&n).expect("missing");
+        proc.inline_calls(mir);
+        proc.optimize(names);
+        mir.insert(n, proc);
       }
     }
+    if let Some(name) = proc_name.filter(|_| !item_errors) {
+      deps.remove(&name);
+      let key = cache.borrow_mut().insert(name, item_hash, deps.into_iter().collect(),
+        names, keys, mir.get(&name));
+      keys.insert(name, key);
+    }
     Ok(())
   }
 
+  /// Add procedure `name` from the cache, if it is present with item hash `item` and none of its
+  /// dependencies have changed. Returns false if the procedure has to be compiled.
+  ///
+  /// The item may have moved since it was compiled, so the spans of the declaration and the
+  /// procedure are updated to `span`. (The spans inside the body are not, so they can point to
+  /// the old location of the item.)
+  fn add_cached(&mut self, name: &Spanned<Symbol>, span: &FileSpan, item: u64) -> bool {
+    let cache = self.cache.borrow();
+    let Some((key, entity, proc)) = cache.get(name.k, item, &self.names, &self.keys)
+    else { return false };
+    let mut entity = entity.clone();
+    if let Entity::Proc(p) = &mut entity { p.span = span.clone() }
+    self.names.insert(name.k, entity);
+    if let Some(proc) = proc {
+      let mut proc = proc.clone();
+      proc.name.span = name.span.clone();
+      proc.body.span = span.clone();
+      self.mir.insert(name.k, proc);
+    }
+    self.keys.insert(name.k, key);
+    self.cache_hits += 1;
+    true
+  }
+
+  /// The number of procedures that have been added from the cache instead of being compiled,
+  /// since this compiler was created.
+  pub fn cache_hits(&self) -> usize { self.cache_hits }
+
   /// If true, then `finish` will panic.
   pub fn has_type_errors(&self) -> bool { self.has_type_errors }
 
@@ -274,6 +345,7 @@ impl<C: Config> Compiler<C> {
     self.init = Default::default();
     self.main = None;
     self.has_type_errors = false;
+    self.keys = Default::default();
   }
 
   /// Once we are done adding functions, this function performs final linking to produce an
@@ -282,14 +354,29 @@ impl<C: Config> Compiler<C> {
   /// [`Compiler::config`], so it can be used to compile another program but the library functions
   /// must first be loaded in again.
   pub fn finish(&mut self) -> Result<Box<LinkedCode>, LinkerErr> {
+    let cache = self.cache.clone();
+    let mut cache = cache.borrow_mut();
+    self.finish_for::<arch::X86>(&mut cache.code)
+  }
+
+  /// Like [`Compiler::finish`], but produces code for the `AArch64` (64-bit ARM) target.
+  /// The result can be written out using [`LinkedCode::write_elf`], but proof generation and
+  /// debug information are only available for x86-64.
+  pub fn finish_arm64(&mut self) -> Result<Box<LinkedCode<arch::arm64::PInst>>, LinkerErr> {
+    self.finish_for::<arch::arm64::Arm64>(&mut Default::default())
+  }
+
+  fn finish_for<A: arch::Arch>(&mut self, code_cache: &mut cache::CodeCache<A::PInst>
+  ) -> Result<Box<LinkedCode<A::PInst>>, LinkerErr> {
     let names = std::mem::replace(&mut self.names, symbol::Interner::with(Self::make_names));
     let mir = std::mem::take(&mut self.mir);
+    let keys = std::mem::take(&mut self.keys);
     assert!(!self.has_type_errors);
     // eprintln!("{:#?}", mir);
     let (mut init, globals) = std::mem::take(&mut self.init).finish(&mir, self.main.take());
     init.optimize(&[]);
     let allocs = init.storage(&names);
-    LinkedCode::link(&names, mir, init, &allocs, &globals)
+    LinkedCode::link::<A>(&names, mir, init, &allocs, &globals, &keys, code_cache)
   }
 }
 
@@ -328,7 +415,7 @@ mod test {
   }
 
   #[test] fn trivial_ir() {
-    use crate::{LinkedCode, mir::*};
+    use crate::{LinkedCode, arch::X86, mir::*};
     let names = Default::default();
     let mut cfg = Cfg::default();
     let bl = cfg.new_block(CtxId::ROOT, 0);
@@ -338,7 +425,8 @@ mod test {
     // println!("after opt:\n{:#?}", cfg);
     let allocs = cfg.storage(&names);
     // println!("allocs = {:#?}", allocs);
-    let code = LinkedCode::link(&names, Default::default(), cfg, &allocs, &[]).unwrap();
+    let code = LinkedCode::link::<X86>(&names, Default::default(), cfg, &allocs, &[],
+      &Default::default(), &mut Default::default()).unwrap();
     println!("code = {code:#?}");
     // code.write_elf(&mut std::fs::File::create("trivial").unwrap());
     let mut out = Vec::new();
@@ -356,9 +444,52 @@ mod test {
     ");
   }
 
+  #[test] fn trivial_asm() {
+    use crate::{LinkedCode, arch::X86, mir::*};
+    let names = Default::default();
+    let mut cfg = Cfg::default();
+    let bl = cfg.new_block(CtxId::ROOT, 0);
+    cfg[bl].terminate(Terminator::Exit(Constant::unit().into()));
+    cfg.optimize(&[]);
+    let allocs = cfg.storage(&names);
+    let code = LinkedCode::link::<X86>(&names, Default::default(), cfg, &allocs, &[],
+      &Default::default(), &mut Default::default()).unwrap();
+    let mut out = Vec::new();
+    code.write_asm(&mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    // println!("{out}");
+    assert!(out.contains("00400078 <_start>:"));
+    assert!(out.contains("  00400078: b8 3c 00 00 00"));
+    assert!(out.contains("; exit"));
+  }
+
+  #[test] fn trivial_debug_elf() {
+    use crate::{LinkedCode, arch::X86, mir::*};
+    let names = Default::default();
+    let mut cfg = Cfg::default();
+    let bl = cfg.new_block(CtxId::ROOT, 0);
+    cfg[bl].terminate(Terminator::Exit(Constant::unit().into()));
+    cfg.optimize(&[]);
+    let allocs = cfg.storage(&names);
+    let code = LinkedCode::link::<X86>(&names, Default::default(), cfg, &allocs, &[],
+      &Default::default(), &mut Default::default()).unwrap();
+    let mut elf = Vec::new();
+    code.write_elf(&mut elf).unwrap();
+    let mut out = Vec::new();
+    code.write_elf_debug(&mut out, |_| Some(1)).unwrap();
+    // std::fs::write("trivial_debug", &out).unwrap();
+    // Only the section header fields of the ELF header change
+    assert_eq!(out[..0x28], elf[..0x28]);
+    assert_eq!(out[0x30..0x3c], elf[0x30..0x3c]);
+    assert_eq!(out[0x40..elf.len()], elf[0x40..]);
+    assert_eq!(u16::from_le_bytes([out[0x3c], out[0x3d]]), 8);
+    let shoff = u64::from_le_bytes(out[0x28..0x30].try_into().unwrap());
+    assert_eq!(shoff + 8 * 0x40, out.len() as u64);
+  }
+
   #[test] fn two_plus_two_ir() {
     use std::{collections::HashMap, rc::Rc};
-    use crate::{LinkedCode, types::IntTy, mir::*};
+    use crate::{LinkedCode, arch::X86, types::IntTy, mir::*};
 
     let names = HashMap::new();
     let mut fresh_var = VarId::default();
@@ -401,7 +532,8 @@ mod test {
     // println!("after opt:\n{:#?}", cfg);
     let allocs = cfg.storage(&names);
     // println!("allocs = {:#?}", allocs);
-    let code = LinkedCode::link(&names, mir, cfg, &allocs, &[]).unwrap();
+    let code = LinkedCode::link::<X86>(&names, mir, cfg, &allocs, &[],
+      &Default::default(), &mut Default::default()).unwrap();
     // println!("code = {:#?}", code);
     // code.write_elf(&mut File::create("two_plus_two_ir").unwrap());
     let mut out = Vec::new();
@@ -413,17 +545,242 @@ mod test {
       0000 0000 4000 3800 0100 4000 0000 0000\
       0100 0000 0700 0000 7800 0000 0000 0000\
       7800 4000 0000 0000 0000 0000 0000 0000\
-      2800 0000 0000 0000 2800 0000 0000 0000\
-      0000 2000 0000 0000 ba02 0000 00b9 0200\
-      0000 02d1 80fa 0441 0f94 c041 80f8 0075\
+      1800 0000 0000 0000 1800 0000 0000 0000\
+      0000 2000 0000 0000 b801 0000 003c 0075\
       020f 0bb8 3c00 0000 33ff 0f05 0000 0000\
     ");
   }
 
+  #[test] fn propagate_ir() {
+    use std::rc::Rc;
+    use crate::{types::IntTy, mir::*};
+
+    let mut fresh_var = VarId::default();
+    let u8 = IntTy::UInt(Size::S8);
+    let mut cfg = Cfg::default();
+
+    // let x = 2; let y = x; let a = y + 3; let b = x + 3; assert(a = b);
+    let bl1 = cfg.new_block(CtxId::ROOT, 0);
+    let mut push = |cfg: &mut Cfg, ty, rv| {
+      let x = fresh_var.fresh();
+      cfg[bl1].stmts.push(Statement::Let(
+        LetKind::Let(Spanned::dummy(x), None), true, Rc::new(ty), rv));
+      x
+    };
+    let x = push(&mut cfg, TyKind::Int(u8), Constant::int(u8, 2.into()).into());
+    let y = push(&mut cfg, TyKind::Int(u8), Operand::Copy(Place::local(x)).rv());
+    let lhs = push(&mut cfg, TyKind::Int(u8), RValue::Binop(Binop::Add(u8),
+      Operand::Copy(Place::local(y)), Constant::int(u8, 3.into()).into()));
+    let rhs = push(&mut cfg, TyKind::Int(u8), RValue::Binop(Binop::Add(u8),
+      Operand::Copy(Place::local(x)), Constant::int(u8, 3.into()).into()));
+    let eq = push(&mut cfg, TyKind::Bool, RValue::Binop(Binop::Eq(u8),
+      Operand::Copy(Place::local(lhs)), Operand::Copy(Place::local(rhs))));
+    let hyp = fresh_var.fresh();
+    let bl2ctx = cfg.ctxs.extend(CtxId::ROOT, Spanned::dummy(hyp), true, (None,
+      Rc::new(TyKind::Pure(Rc::new(ExprKind::Var(eq))))));
+    let bl2 = cfg.new_block(bl2ctx, 0);
+    cfg[bl1].terminate(Terminator::Assert(eq.into(), hyp, bl2));
+    cfg[bl2].terminate(Terminator::Exit(Constant::unit().into()));
+
+    cfg.optimize(&[]);
+    // println!("after opt:\n{:#?}", cfg);
+    // Everything is folded into the assert, and the lets are kept as ghosts
+    assert_eq!(cfg[bl1].stmts.len(), 5);
+    assert!(cfg[bl1].stmts.iter().all(|s| !s.relevant()));
+    let Some(Terminator::Assert(Operand::Const(c), _, _)) = &cfg[bl1].term else { panic!() };
+    assert!(matches!(c.ety.0.as_deref(), Some(ExprKind::Bool(true))));
+  }
+
+  #[test] fn dead_store() {
+    use std::rc::Rc;
+    use crate::{types::IntTy, mir::*};
+
+    let mut fresh_var = VarId::default();
+    let u8 = IntTy::UInt(Size::S8);
+    let ty = Rc::new(TyKind::Int(u8));
+    let mut cfg = Cfg::default();
+
+    // let x = 1; x <- 2; return x;
+    let bl = cfg.new_block(CtxId::ROOT, 0);
+    let (x, x2, ret) = (fresh_var.fresh(), fresh_var.fresh(), fresh_var.fresh());
+    cfg[bl].stmts.push(Statement::Let(LetKind::Let(Spanned::dummy(x), None), true, ty.clone(),
+      Constant::int(u8, 1.into()).into()));
+    cfg[bl].stmts.push(Statement::Assign(Place::local(x), ty.clone(),
+      Constant::int(u8, 2.into()).into(),
+      Box::new([Rename { from: x, to: Spanned::dummy(x2), rel: true, ety: (None, ty.clone()) }])));
+    cfg[bl].terminate(Terminator::Return(Box::new([]),
+      Box::new([(ret, true, Operand::Copy(Place::local(x2)))])));
+
+    cfg.optimize(&[Arg { attr: ArgAttr::empty(), var: ret, ty }]);
+    // The overwritten value of `x` is not needed, so the first let is ghost
+    assert_eq!(cfg[bl].stmts.len(), 2);
+    assert!(!cfg[bl].stmts[0].relevant());
+    assert!(matches!(cfg[bl].stmts[1], Statement::Let(..)));
+    let Some(Terminator::Return(_, args)) = &cfg[bl].term else { panic!() };
+    assert!(matches!(args[0].2, Operand::Const(_)));
+  }
+
+  #[test] fn propagate_exit() {
+    use std::rc::Rc;
+    use crate::{types::IntTy, mir::*};
+
+    let u8 = IntTy::UInt(Size::S8);
+    let mut cfg = Cfg::default();
+
+    // let x = 2; exit x;
+    let bl = cfg.new_block(CtxId::ROOT, 0);
+    let x = VarId::default().fresh();
+    cfg[bl].stmts.push(Statement::Let(LetKind::Let(Spanned::dummy(x), None), true,
+      Rc::new(TyKind::Int(u8)), Constant::int(u8, 2.into()).into()));
+    cfg[bl].terminate(Terminator::Exit(Operand::Copy(Place::local(x))));
+
+    cfg.propagate();
+    let Some(Terminator::Exit(Operand::Const(c))) = &cfg[bl].term else { panic!() };
+    assert!(matches!(c.ety.0.as_deref(), Some(ExprKind::Int(n)) if *n == 2.into()));
+  }
+
+  #[test] fn inline_call() {
+    use crate::types::mir::Terminator;
+    let mut compiler = Compiler::new(());
+    let add2 = intern("add2");
+
+    // inline proc add2(x: u8): u8 := (x + 2) as u8;
+    let mut fresh = VarId::default();
+    let x = fresh.fresh();
+    compiler.add(
+      &Spanned::dummy(ItemKind::Proc {
+        intrinsic: None,
+        inline: true,
+        kind: ProcKind::Proc,
+        name: Spanned::dummy(add2),
+        tyargs: 0,
+        args: Box::new([
+          Spanned::dummy((ArgAttr::empty(), ArgKind::Lam(TuplePatternKind::Typed(
+            Box::new(Spanned::dummy(TuplePatternKind::Name(false, intern("x"), x))),
+            Box::new(Spanned::dummy(TypeKind::UInt(Size::S8))),
+          )))),
+        ]),
+        outs: Box::new([]),
+        rets: Box::new([
+          Spanned::dummy(TuplePatternKind::Typed(
+            Box::new(Spanned::dummy(TuplePatternKind::Name(false, Symbol::UNDER, fresh.fresh()))),
+            Box::new(Spanned::dummy(TypeKind::UInt(Size::S8))),
+          ))
+        ]),
+        variant: None,
+        body: Block {
+          stmts: vec![],
+          expr: Some(Box::new(Spanned::dummy(ExprKind::As(
+            Box::new(Spanned::dummy(ExprKind::Binop(Binop::Add,
+              Box::new(Spanned::dummy(ExprKind::Var(x))),
+              Box::new(Spanned::dummy(ExprKind::Int(2.into())))
+            ))),
+            Box::new(Spanned::dummy(TypeKind::UInt(Size::S8)))
+          )))),
+        },
+      }),
+      Default::default(), ()).unwrap();
+
+    // main() { let y: u8 = add2(2); assert(y = 4); }
+    let mut fresh = VarId::default();
+    let y = fresh.fresh();
+    let main = intern("main");
+    compiler.add(
+      &Spanned::dummy(ItemKind::Proc {
+        intrinsic: None,
+        inline: false,
+        kind: ProcKind::Main,
+        name: Spanned::dummy(main),
+        tyargs: 0,
+        args: Box::new([]),
+        outs: Box::new([]),
+        rets: Box::new([]),
+        variant: None,
+        body: Block {
+          stmts: vec![
+            Spanned::dummy(StmtKind::Let {
+              lhs: Spanned::dummy(TuplePatternKind::Typed(
+                Box::new(Spanned::dummy(TuplePatternKind::Name(false, intern("y"), y))),
+                Box::new(Spanned::dummy(TypeKind::UInt(Size::S8))),
+              )),
+              rhs: Spanned::dummy(ExprKind::Call {
+                f: Spanned::dummy(add2),
+                tys: vec![],
+                args: vec![Spanned::dummy(ExprKind::Int(2.into()))],
+                variant: None,
+              })
+            }),
+            Spanned::dummy(StmtKind::Expr(ExprKind::Assert(
+              Box::new(Spanned::dummy(ExprKind::Binop(Binop::Eq,
+                Box::new(Spanned::dummy(ExprKind::Var(y))),
+                Box::new(Spanned::dummy(ExprKind::Int(4.into())))
+              )))
+            )))
+          ],
+          expr: None,
+        },
+      }),
+      Default::default(), ()).unwrap();
+    let cfg = &compiler.mir[&main].body;
+    // println!("main:\n{:#?}", cfg);
+    assert!(cfg.blocks().all(|(_, bl)| !matches!(bl.terminator(), Terminator::Call {..})));
+    compiler.finish().unwrap();
+  }
+
+  #[test] fn eval_proc() {
+    use crate::interp::{Outcome, Value};
+    let mut compiler = Compiler::new(());
+    let add2 = intern("add2");
+
+    // proc add2(x: u8): u8 := (x + 2) as u8;
+    let mut fresh = VarId::default();
+    let x = fresh.fresh();
+    compiler.add(
+      &Spanned::dummy(ItemKind::Proc {
+        intrinsic: None,
+        inline: false,
+        kind: ProcKind::Proc,
+        name: Spanned::dummy(add2),
+        tyargs: 0,
+        args: Box::new([
+          Spanned::dummy((ArgAttr::empty(), ArgKind::Lam(TuplePatternKind::Typed(
+            Box::new(Spanned::dummy(TuplePatternKind::Name(false, intern("x"), x))),
+            Box::new(Spanned::dummy(TypeKind::UInt(Size::S8))),
+          )))),
+        ]),
+        outs: Box::new([]),
+        rets: Box::new([
+          Spanned::dummy(TuplePatternKind::Typed(
+            Box::new(Spanned::dummy(TuplePatternKind::Name(false, Symbol::UNDER, fresh.fresh()))),
+            Box::new(Spanned::dummy(TypeKind::UInt(Size::S8))),
+          ))
+        ]),
+        variant: None,
+        body: Block {
+          stmts: vec![],
+          expr: Some(Box::new(Spanned::dummy(ExprKind::As(
+            Box::new(Spanned::dummy(ExprKind::Binop(Binop::Add,
+              Box::new(Spanned::dummy(ExprKind::Var(x))),
+              Box::new(Spanned::dummy(ExprKind::Int(2.into())))
+            ))),
+            Box::new(Spanned::dummy(TypeKind::UInt(Size::S8)))
+          )))),
+        },
+      }),
+      Default::default(), ()).unwrap();
+
+    let eval = |n: u8| compiler.eval(add2, vec![Value::Int(n.into())], &[]).unwrap();
+    assert_eq!(eval(3), (Outcome::Return(vec![Value::Int(5.into())]), vec![]));
+    assert_eq!(eval(255), (Outcome::Return(vec![Value::Int(1.into())]), vec![]));
+    assert!(compiler.eval(add2, vec![Value::Int(256.into())], &[]).is_err());
+    assert!(compiler.eval(add2, vec![], &[]).is_err());
+  }
+
   #[test] fn two_plus_two() {
     let mut compiler = Compiler::new(());
     let main = Spanned::dummy(ItemKind::Proc {
       intrinsic: None,
+      inline: false,
       kind: ProcKind::Main,
       name: Spanned::dummy(intern("main")),
       tyargs: 0,
@@ -460,11 +817,10 @@ mod test {
       0000 0000 4000 3800 0100 4000 0000 0000\
       0100 0000 0700 0000 7800 0000 0000 0000\
       7800 4000 0000 0000 0000 0000 0000 0000\
-      3800 0000 0000 0000 3800 0000 0000 0000\
+      2800 0000 0000 0000 2800 0000 0000 0000\
       0000 2000 0000 0000 e813 0000 00b8 3c00\
       0000 33ff 0f05 0000 0000 0000 0000 0000\
-      ba02 0000 00b9 0200 0000 02d1 b904 0000\
-      003a d10f 94c0 3c00 7502 0f0b c300 0000\
+      c300 0000 0000 0000 0000 0000 0000 0000\
     ");
   }
 
@@ -482,6 +838,7 @@ mod test {
     compiler.add(
       &Spanned::dummy(ItemKind::Proc {
         intrinsic: Some(IntrinsicProc::Write),
+        inline: false,
         kind: ProcKind::Proc,
         name: Spanned::dummy(write),
         tyargs: 0,
@@ -536,6 +893,7 @@ mod test {
     compiler.add(
       &Spanned::dummy(ItemKind::Proc {
         intrinsic: None,
+        inline: false,
         kind: ProcKind::Main,
         name: Spanned::dummy(intern("main")),
         tyargs: 0,
@@ -590,14 +948,14 @@ mod test {
       0000 33ff 0f05 0000 0000 0000 0000 0000\
       4883 ec0b b868 0000 0040 8804 24ba 6500\
       0000 4088 5424 0141 b96c 0000 0044 884c\
-      2402 bf6c 0000 0040 887c 2403 b96f 0000\
-      0040 884c 2404 41ba 2000 0000 4488 5424\
-      05be 7700 0000 4088 7424 0641 b86f 0000\
-      0044 8844 2407 b872 0000 0040 8844 2408\
-      ba6c 0000 0040 8854 2409 41b9 6400 0000\
-      4488 4c24 0a48 8d34 24b8 0100 0000 48c7\
-      c701 0000 0048 c7c2 0b00 0000 0f05 4883\
-      c40b c300 0000 0000 0000 0000 0000 0000\
+      2402 b86c 0000 0040 8844 2403 ba6f 0000\
+      0040 8854 2404 41b9 2000 0000 4488 4c24\
+      05b8 7700 0000 4088 4424 06ba 6f00 0000\
+      4088 5424 0741 b972 0000 0044 884c 2408\
+      b86c 0000 0040 8844 2409 ba64 0000 0040\
+      8854 240a 488d 3424 b901 0000 0048 c7c7\
+      0100 0000 48c7 c20b 0000 0048 8bc1 0f05\
+      4883 c40b c300 0000 0000 0000 0000 0000\
     ");
   }
 }
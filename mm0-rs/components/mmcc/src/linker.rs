@@ -2,7 +2,10 @@
 
 use std::collections::{HashMap, HashSet};
 
-use crate::build_vcode::{VCodeCtx, build_vcode};
+use crate::arch::{Arch, PInst};
+use crate::build_vcode::VCodeCtx;
+use crate::cache::{CachedCode, CodeCache, code_key};
+use crate::interp::{self, Value};
 use crate::codegen::FUNCTION_ALIGN;
 use crate::mir_opt::storage::{Allocations, AllocId};
 use crate::regalloc::PCode;
@@ -177,21 +180,25 @@ impl<'a> Collector<'a> {
       } else {
         None
       }
-      ExprKind::Var(_) |
       ExprKind::Unop(_, _) |
       ExprKind::Binop(_, _, _) |
       ExprKind::Index(_, _) |
       ExprKind::Slice(_) |
       ExprKind::Proj(_, _) |
+      ExprKind::Call { .. } |
+      ExprKind::If { .. } => match interp::eval_global(self.names, self.mir, e).ok()? {
+        Value::Bool(b) => self.eval_const(ty, &ExprKind::Bool(b)),
+        Value::Int(n) => self.eval_const(ty, &ExprKind::Int(n)),
+        _ => None,
+      }
+      ExprKind::Var(_) |
       ExprKind::UpdateIndex(_) |
       ExprKind::UpdateSlice(_) |
       ExprKind::UpdateProj(_, _, _) |
       ExprKind::Sizeof(_) |
       ExprKind::Ref(_) |
       ExprKind::Mm0(_) |
-      ExprKind::Call { .. } |
-      ExprKind::If { .. } |
-      ExprKind::Error => None, // TODO: const eval
+      ExprKind::Error => None,
     }
   }
 
@@ -216,16 +223,17 @@ pub const TEXT_START: u32 = 0x40_0078;
 
 //// A completed code object. This includes the list of instructions,
 /// and can be serialized to a list of bytes using the [`LinkedCode::write_elf`] method.
+/// The type parameter is the instruction type of the target, which defaults to x86-64.
 #[derive(Clone, Debug)]
-pub struct LinkedCode {
+pub struct LinkedCode<I = PInst> {
   pub(crate) mir: HashMap<Symbol, Proc>,
   pub(crate) consts: ConstData,
   pub(crate) globals: IdxVec<GlobalId, (Symbol, u32, u32)>,
   pub(crate) global_size: u32,
-  pub(crate) init: (Cfg, Box<PCode>),
+  pub(crate) init: (Cfg, Box<PCode<I>>),
   pub(crate) func_names: (HashMap<Symbol, ProcId>, IdxVec<ProcId, Symbol>),
   pub(crate) func_abi: IdxVec<ProcId, ProcAbi>,
-  pub(crate) funcs: IdxVec<ProcId, (u32, Box<PCode>)>,
+  pub(crate) funcs: IdxVec<ProcId, (u32, Box<PCode<I>>)>,
   pub(crate) postorder: Vec<ProcId>,
   pub(crate) text_size: u32,
 }
@@ -243,14 +251,19 @@ impl From<LowerErr> for LinkerErr {
   fn from(v: LowerErr) -> Self { Self::LowerErr(v) }
 }
 
-impl LinkedCode {
-  pub(crate) fn link(
+impl<I> LinkedCode<I> {
+  /// Lay out the program and compile all functions for the target `A`.
+  /// Procedures with an entry in `keys` reuse their code from `cache` if they were lowered before
+  /// in the same context, and store it there otherwise.
+  pub(crate) fn link<A: Arch<PInst = I>>(
     names: &HashMap<Symbol, Entity>,
     mir: HashMap<Symbol, Proc>,
     init: Cfg,
     allocs: &Allocations,
-    globals: &[(Symbol, bool, VarId, Ty)]
-  ) -> Result<Box<Self>, LinkerErr> {
+    globals: &[(Symbol, bool, VarId, Ty)],
+    keys: &HashMap<Symbol, u64>,
+    cache: &mut CodeCache<I>,
+  ) -> Result<Box<Self>, LinkerErr> where I: Clone {
     let mut coll = Collector::new(names, &mir);
     coll.collect_cfg(&init, &[]);
     let mut func_abi = IdxVec::from_default(coll.funcs.1.len());
@@ -258,11 +271,22 @@ impl LinkedCode {
     for &f in &coll.postorder {
       let sym = coll.funcs.1[f];
       if let Some(proc) = mir.get(&sym) {
-        let (abi, code) = build_vcode(
-          names, &coll.funcs.0, &func_abi, &coll.consts, &proc.body,
-          proc.allocs.as_deref().expect("optimized already"),
-          VCodeCtx::Proc(&proc.rets)
-        )?.regalloc();
+        let key = keys.get(&sym).map(|&k| code_key(k, &coll.funcs.1, &func_abi, &coll.consts));
+        let (abi, code) = match (key, cache.get(&sym)) {
+          (Some(key), Some(c)) if c.key == key => (c.abi.clone(), c.code.clone()),
+          _ => {
+            let (abi, code) = A::lower(
+              names, &coll.funcs.0, &func_abi, &coll.consts, &proc.body,
+              proc.allocs.as_deref().expect("optimized already"),
+              // `out` parameters are ghost, and are not part of the return ABI
+              VCodeCtx::Proc(&proc.rets[proc.outs.len()..])
+            )?;
+            if let Some(key) = key {
+              cache.insert(sym, CachedCode { key, abi: abi.clone(), code: code.clone() });
+            }
+            (abi, code)
+          }
+        };
         // println!("mir {} = {:#?}", sym, proc);
         // println!("abi {} = {:#?}", sym, abi);
         // println!("code {} = {:#?}", sym, code);
@@ -281,9 +305,9 @@ impl LinkedCode {
       global_size += size;
       Some((g, off, size))
     }).collect();
-    let init_code = build_vcode(
+    let init_code = A::lower(
       names, &coll.funcs.0, &func_abi, &coll.consts, &init, allocs, VCodeCtx::Start(globals)
-    )?.regalloc().1;
+    )?.1;
 
     let mut pos = (TEXT_START + init_code.len + FUNCTION_ALIGN - 1) & !(FUNCTION_ALIGN - 1);
     let funcs = func_code.0.into_iter().map(|code| {
@@ -0,0 +1,124 @@
+//! Produces a human-readable assembly listing of a linked program.
+//!
+//! The listing is driven by the same [`classify::Visitor`] machinery as the proof generator,
+//! so each instruction is annotated with the MIR statement or terminator that produced it.
+
+use std::fmt::Debug;
+use std::io::{self, Write};
+use crate::{LinkedCode, TEXT_START};
+use crate::codegen::{align_to, BSS_ALIGN};
+use crate::proof::{AssemblyItem, Inst, VBlock};
+use crate::types::{classify::{self, TraceIter}, mir, vcode::{ArgAbi, ProcAbi, ProcId}, IdxVec};
+use crate::arch::PReg;
+
+/// The number of instruction bytes shown on each line of the listing.
+const BYTES_PER_LINE: usize = 8;
+
+struct Listing<'w, W> {
+  w: &'w mut W,
+  /// The virtual address of the current procedure.
+  start: u32,
+  /// A comment describing the current source of instructions,
+  /// printed just before its first instruction.
+  header: Option<String>,
+  res: io::Result<()>,
+}
+
+impl<W: Write> Listing<'_, W> {
+  fn write(&mut self, args: std::fmt::Arguments<'_>) {
+    if self.res.is_ok() { self.res = self.w.write_fmt(args) }
+  }
+
+  fn set_header(&mut self, header: impl Debug) { self.header = Some(format!("{header:?}")) }
+
+  fn block(&mut self, bl: &VBlock<'_>) {
+    self.write(format_args!("vb{} ({:?}):\n", bl.id.0, bl.mir_id));
+    bl.visit(self);
+  }
+
+  fn bytes(&mut self, addr: u32, content: &[u8], comment: impl std::fmt::Display) {
+    if content.is_empty() {
+      return self.write(format_args!("  {addr:08x}:{:w$}  {comment}\n", "", w = 3 * BYTES_PER_LINE))
+    }
+    let mut addr = addr;
+    let mut comment = Some(comment);
+    for chunk in content.chunks(BYTES_PER_LINE) {
+      self.write(format_args!("  {addr:08x}:"));
+      for b in chunk { self.write(format_args!(" {b:02x}")) }
+      match comment.take() {
+        Some(c) => {
+          let pad = 3 * (BYTES_PER_LINE - chunk.len());
+          self.write(format_args!("{:pad$}  {c}\n", ""))
+        }
+        None => self.write(format_args!("\n")),
+      }
+      addr += u32::try_from(chunk.len()).expect("overflow");
+    }
+  }
+}
+
+impl<'a, W: Write> classify::Visitor<'a> for Listing<'_, W> {
+  fn on_inst(&mut self, _: &TraceIter<'a>, _: bool, inst: &Inst<'a>) {
+    if let Some(header) = self.header.take() { self.write(format_args!("    ; {header}\n")) }
+    self.bytes(self.start + inst.start, inst.content(), format_args!("{:?}", inst.inst))
+  }
+
+  fn before_prologue(&mut self, _: &TraceIter<'a>,
+    _: &'a [PReg], _: u32, _: &'a [ArgAbi], _: Option<&'a [ArgAbi]>
+  ) { self.header = Some("prologue".into()) }
+
+  fn before_stmt(&mut self, _: &TraceIter<'a>,
+    stmt: &'a mir::Statement, _: &'a classify::Statement
+  ) { self.set_header(stmt) }
+
+  fn before_terminator(&mut self, _: &TraceIter<'a>,
+    _: &'a IdxVec<ProcId, ProcAbi>, _: Option<&'a [ArgAbi]>,
+    term: &'a mir::Terminator, _: &'a classify::Terminator
+  ) { self.set_header(term) }
+
+  fn before_epilogue(&mut self, _: &TraceIter<'a>) { self.header = Some("epilogue".into()) }
+}
+
+impl LinkedCode {
+  /// Write an annotated assembly listing of this code object to a [`Write`] implementation.
+  /// This contains the same code as [`write_elf`](Self::write_elf), with each instruction
+  /// labeled by its address and the MIR statement it came from, followed by the
+  /// addresses of the read-only constants and the globals.
+  pub fn write_asm(&self, w: &mut impl Write) -> io::Result<()> {
+    let proof = self.proof();
+    let mut out = Listing { w, start: 0, header: None, res: Ok(()) };
+    out.write(format_args!("section .text\n"));
+    let mut rodata = false;
+    for item in proof.assembly() {
+      match item {
+        AssemblyItem::Proc(proc) => {
+          match proc.name() {
+            Some(name) => out.write(format_args!("\n{:08x} <{name}>:\n", proc.start)),
+            None => out.write(format_args!("\n{:08x} <_start>:\n", proc.start)),
+          }
+          out.start = proc.start;
+          for bl in proc.assembly_blocks() { out.block(&bl) }
+          let padding = proc.trailing_padding();
+          if !padding.is_empty() {
+            out.bytes(proc.start + proc.len_no_padding(), padding, "; padding")
+          }
+        }
+        AssemblyItem::Const(c) => {
+          if !std::mem::replace(&mut rodata, true) { out.write(format_args!("\nsection .rodata\n")) }
+          out.write(format_args!("\n{:08x} <{}>:\n", c.start, c.name));
+          out.bytes(c.start, c.content, "")
+        }
+      }
+    }
+    if !self.globals.is_empty() {
+      out.write(format_args!("\nsection .bss\n\n"));
+      let rodata_end = u64::from(TEXT_START + self.text_size) +
+        u64::try_from(self.consts.rodata.len()).expect("overflow");
+      let global_start = align_to::<BSS_ALIGN>(rodata_end);
+      for &(name, off, size) in &self.globals.0 {
+        out.write(format_args!("{:08x} <{name}>: {size} bytes\n", global_start + u64::from(off)))
+      }
+    }
+    out.res
+  }
+}
@@ -277,8 +277,10 @@ impl Cfg {
             let (LetKind::Let(v, _) | LetKind::Ptr([_, (v, _)])) = lk;
             if d.vars.contains(&v.k) { d.apply_rvalue(loc.block, rv) }
           }
-          Statement::Assign(_, _, rhs, vars) => {
-            let mut needed = false;
+          Statement::Assign(lhs, _, rhs, vars) => {
+            // A write through a pointer is needed even if the new value is only used in ghost
+            // code, because it is observable in memory.
+            let mut needed = lhs.is_deref();
             for v in &**vars {
               if v.rel && d.vars.contains(&v.to.k) {
                 needed = true;
@@ -287,6 +289,7 @@ impl Cfg {
             }
             if needed {
               d.active = OptBlockId::new(loc.block);
+              d.apply_place(lhs);
               d.apply_operand(rhs)
             }
           }
@@ -309,9 +312,9 @@ impl Cfg {
           Terminator::Jump1(_, _) |
           Terminator::Fail |
           Terminator::Exit(_) => {}
-          Terminator::Return(_, args) => {
+          Terminator::Return(outs, args) => {
             d.active = OptBlockId::new(id);
-            for ((_, vr, o), ret) in args.iter().zip(self.returns) {
+            for ((_, vr, o), ret) in args.iter().zip(&self.returns[outs.len()..]) {
               if *vr && !ret.attr.contains(ArgAttr::GHOST) { d.apply_operand(o) }
             }
           }
@@ -0,0 +1,295 @@
+//! The inlining pass, which replaces calls to small procedures by a copy of the callee's body.
+//!
+//! Inlining runs on the MIR of the caller before it is optimized, using the (already optimized)
+//! MIR of the callee. The variables, blocks and context buffers of the callee are renumbered to
+//! be fresh in the caller, and then:
+//!
+//! * The call is replaced by `let` statements binding the callee's arguments to the call
+//!   operands (so ghost arguments become ghost lets), followed by a jump to the callee's entry.
+//! * The callee's `return` is replaced by `let` statements binding the return variables of the
+//!   call, followed by a jump to the block after the call.
+//!
+//! The inlined blocks are ordinary blocks of the caller, so the proof generator proves them
+//! directly as part of the caller instead of going through the correctness lemma of the callee.
+
+use super::super::types::{ast::ProcKind, Idx, Spanned};
+#[allow(clippy::wildcard_imports)] use super::*;
+
+/// Callees with at most this many computationally relevant statements are inlined even when
+/// they are not marked `inline`.
+const INLINE_THRESHOLD: usize = 8;
+
+/// Renames the variables, blocks and contexts of a callee so that they are fresh in the caller.
+struct Relabel {
+  alpha: Alpha,
+  vars: u32,
+  blocks: u32,
+  bufs: u32,
+}
+
+impl Relabel {
+  fn var(&self, v: VarId) -> VarId { VarId(self.vars + v.0) }
+  fn svar(&self, v: &mut Spanned<VarId>) { v.k = self.var(v.k) }
+  fn block(&self, bl: BlockId) -> BlockId { BlockId(self.blocks + bl.0) }
+  fn ctx(&self, CtxId(buf, i): CtxId) -> CtxId { CtxId(CtxBufId(self.bufs + buf.0), i) }
+
+  fn ty(&mut self, ty: &mut Ty) { *ty = self.alpha.alpha(ty) }
+
+  fn ety(&mut self, (e, ty): &mut ExprTy) {
+    if let Some(e) = e { *e = self.alpha.alpha(e) }
+    self.ty(ty)
+  }
+
+  fn place(&mut self, p: &mut Place) {
+    p.local = self.var(p.local);
+    for (ty, proj) in &mut p.proj {
+      self.ty(ty);
+      match proj {
+        Projection::Index(i, h) => { *i = self.var(*i); *h = self.var(*h) }
+        Projection::Slice(i, l, h) => { *i = self.var(*i); *l = self.var(*l); *h = self.var(*h) }
+        Projection::Proj(..) | Projection::Deref => {}
+      }
+    }
+  }
+
+  fn constant(&mut self, c: &mut Constant) {
+    self.ety(&mut c.ety);
+    match &mut c.k {
+      ConstKind::Contra(bl, v) => { *bl = self.block(*bl); *v = self.var(*v) }
+      ConstKind::As(c) => self.constant(&mut c.0),
+      _ => {}
+    }
+  }
+
+  fn operand(&mut self, o: &mut Operand) {
+    match o {
+      Operand::Copy(p) | Operand::Move(p) | Operand::Ref(p) => self.place(p),
+      Operand::Const(c) => self.constant(c),
+    }
+  }
+
+  fn cast(&mut self, ck: &mut CastKind) {
+    match ck {
+      CastKind::Int | CastKind::Shr | CastKind::Wand(None) => {}
+      CastKind::Subtype(o) | CastKind::Wand(Some(o)) | CastKind::Mem(o) => self.operand(o),
+    }
+  }
+
+  fn rvalue(&mut self, rv: &mut RValue) {
+    match rv {
+      RValue::Use(o) | RValue::Unop(_, o) | RValue::Ghost(o) | RValue::Typeof(o) =>
+        self.operand(o),
+      RValue::Binop(_, o1, o2) => { self.operand(o1); self.operand(o2) }
+      RValue::Eq(ty, _, o1, o2) => { self.ty(ty); self.operand(o1); self.operand(o2) }
+      RValue::Pun(pk, p) => {
+        match pk {
+          PunKind::Sn(o) => if let Some(o) = o { self.operand(o) },
+          PunKind::And(os) => for o in os { self.operand(o) },
+          PunKind::Ptr => {}
+          PunKind::DropAs(ck) => self.cast(&mut ck.1),
+        }
+        self.place(p)
+      }
+      RValue::Cast(ck, o, ty) => { self.cast(ck); self.operand(o); self.ty(ty) }
+      RValue::List(os) | RValue::Array(os) | RValue::Mm0(_, os) =>
+        for o in &mut **os { self.operand(o) },
+      RValue::Borrow(p) => self.place(p),
+    }
+  }
+
+  fn stmt(&mut self, s: &mut Statement) {
+    match s {
+      Statement::Let(lk, _, ty, rv) => {
+        match lk {
+          LetKind::Let(v, e) => {
+            self.svar(v);
+            if let Some(e) = e { *e = self.alpha.alpha(e) }
+          }
+          LetKind::Ptr([(x, xt), (y, yt)]) => {
+            self.svar(x); self.ty(xt);
+            self.svar(y); self.ty(yt);
+          }
+        }
+        self.ty(ty);
+        self.rvalue(rv)
+      }
+      Statement::Assign(lhs, ty, rhs, vars) => {
+        self.place(lhs);
+        self.ty(ty);
+        self.operand(rhs);
+        for r in &mut **vars {
+          r.from = self.var(r.from);
+          self.svar(&mut r.to);
+          self.ety(&mut r.ety)
+        }
+      }
+      Statement::LabelGroup(bls, ctx) => {
+        for bl in bls { *bl = self.block(*bl) }
+        *ctx = self.ctx(*ctx)
+      }
+      Statement::PopLabelGroup => {}
+      Statement::DominatedBlock(bl, ctx) => { *bl = self.block(*bl); *ctx = self.ctx(*ctx) }
+    }
+  }
+
+  fn terminator(&mut self, term: &mut Terminator) {
+    match term {
+      Terminator::Jump(bl, args, variant) => {
+        *bl = self.block(*bl);
+        for (v, _, o) in &mut **args { *v = self.var(*v); self.operand(o) }
+        if let Some(o) = variant { self.operand(o) }
+      }
+      Terminator::Jump1(ctx, bl) => { *ctx = self.ctx(*ctx); *bl = self.block(*bl) }
+      Terminator::Return(outs, args) => {
+        for v in &mut **outs { *v = self.var(*v) }
+        for (v, _, o) in &mut **args { *v = self.var(*v); self.operand(o) }
+      }
+      Terminator::Unreachable(o) | Terminator::Exit(o) => self.operand(o),
+      Terminator::If(ctx, o, [(v1, bl1), (v2, bl2)]) => {
+        *ctx = self.ctx(*ctx);
+        self.operand(o);
+        *v1 = self.var(*v1); *bl1 = self.block(*bl1);
+        *v2 = self.var(*v2); *bl2 = self.block(*bl2);
+      }
+      Terminator::Assert(o, v, bl) => { self.operand(o); *v = self.var(*v); *bl = self.block(*bl) }
+      Terminator::Call { ctx, tys, args, tgt, rets, .. } => {
+        *ctx = self.ctx(*ctx);
+        for ty in &mut **tys { self.ty(ty) }
+        for (_, o) in &mut **args { self.operand(o) }
+        *tgt = self.block(*tgt);
+        for (_, v) in &mut **rets { *v = self.var(*v) }
+      }
+      Terminator::Fail | Terminator::Dead => {}
+    }
+  }
+}
+
+impl Proc {
+  /// If calls to this procedure can be inlined, returns the block containing its unique
+  /// `return`. We only inline procedures without type arguments or out parameters, whose body
+  /// contains no loops or join points (no `jump` terminators) so that the inlined blocks do not
+  /// need to be added to the block tree of the caller, and which are either marked `inline` or
+  /// small enough.
+  fn inline_target(&self) -> Option<BlockId> {
+    if self.kind == ProcKind::Main || self.tyargs != 0 || !self.outs.is_empty() { return None }
+    let cfg = &self.body;
+    if !cfg.ctxs.rev_iter(cfg[BlockId::ENTRY].ctx).map(|v| v.0.k)
+      .eq(self.args.iter().rev().map(|arg| arg.var)) { return None }
+    let mut ret = None;
+    let mut size = 0;
+    for (id, bl) in cfg.blocks() {
+      for s in &bl.stmts {
+        match s {
+          Statement::LabelGroup(..) | Statement::PopLabelGroup => return None,
+          _ => if s.relevant() { size += 1 }
+        }
+      }
+      match bl.terminator() {
+        Terminator::Return(..) if ret.is_some() => return None,
+        Terminator::Return(..) => ret = Some(id),
+        Terminator::Jump(..) | Terminator::Exit(_) => return None,
+        _ => {}
+      }
+    }
+    if !self.inline && size > INLINE_THRESHOLD { return None }
+    ret
+  }
+
+  /// Inline the calls in this procedure to procedures in `mir` that are marked `inline` or are
+  /// small enough. This should be called before the procedure is optimized.
+  pub(crate) fn inline_calls(&mut self, mir: &HashMap<Symbol, Proc>) {
+    for id in (0..self.body.blocks.len()).map(BlockId::from_usize) {
+      let bl = &self.body[id];
+      if bl.is_dead() { continue }
+      let Some(Terminator::Call { f, tys, args, reach: true, rets, .. }) = &bl.term
+      else { continue };
+      let Some(callee) = mir.get(f) else { continue };
+      if !tys.is_empty() || args.len() != callee.args.len() || rets.len() != callee.rets.len() {
+        continue
+      }
+      if let Some(ret) = callee.inline_target() { self.body.inline_call(id, callee, ret) }
+    }
+  }
+}
+
+impl Cfg {
+  /// Replace the call that terminates block `id` by the body of `callee`, whose unique `return`
+  /// is in block `ret`.
+  fn inline_call(&mut self, id: BlockId, callee: &Proc, ret: BlockId) {
+    let Some(Terminator::Call { ctx, args, tgt, rets, .. }) = self[id].term.take()
+    else { unreachable!() };
+    let base = u32::try_from(self.ctxs.len(ctx)).expect("overflow");
+    let mut r = Relabel {
+      alpha: Alpha::default(),
+      vars: self.max_var.0,
+      blocks: u32::try_from(self.blocks.len()).expect("overflow"),
+      bufs: u32::try_from(self.ctxs.num_buffers()).expect("overflow"),
+    };
+    for i in 0..callee.body.max_var.0 { r.alpha.push(VarId(i), r.var(VarId(i))) }
+    self.max_var = r.var(callee.body.max_var);
+
+    // If an argument is a constant or a variable, we can record its value in the context.
+    let mut arg_exprs = HashMap::new();
+    for (arg, (_, o)) in callee.args.iter().zip(&*args) {
+      let e = match o {
+        Operand::Const(c) => c.ety.0.clone(),
+        Operand::Copy(p) | Operand::Move(p) | Operand::Ref(p) if p.proj.is_empty() =>
+          Some(Rc::new(ExprKind::Var(p.local))),
+        _ => None,
+      };
+      if let Some(e) = e { arg_exprs.insert(r.var(arg.var), e); }
+    }
+
+    // The root context of the callee now extends the context of the call.
+    for i in 0..callee.body.ctxs.num_buffers() {
+      let buf = &callee.body.ctxs[CtxBufId::from_usize(i)];
+      let (parent, size) = if i == 0 { (ctx, base) } else { (r.ctx(buf.parent), buf.size + base) };
+      let vars = buf.vars.iter().map(|(v, rel, ety)| {
+        let (mut v, mut ety) = (v.clone(), ety.clone());
+        r.svar(&mut v);
+        r.ety(&mut ety);
+        if let Some(e) = arg_exprs.get(&v.k) { ety.0 = Some(e.clone()) }
+        (v, *rel, ety)
+      }).collect();
+      self.ctxs.push_buf(CtxBuf { parent, size, vars });
+    }
+
+    let tgt_ctx = self[tgt].ctx;
+    for (i, bl) in callee.body.blocks.enum_iter() {
+      let mut bl = bl.clone();
+      if !bl.is_dead() {
+        bl.ctx = r.ctx(bl.ctx);
+        bl.base += base;
+        bl.relevance = None;
+        for s in &mut bl.stmts { r.stmt(s) }
+        if i == ret {
+          let Some(Terminator::Return(_, vals)) = bl.term.take() else { unreachable!() };
+          for (v, rel, mut o) in vals.into_vec() {
+            let j = callee.rets.iter().position(|arg| arg.var == v).expect("unknown return");
+            let w = rets[j].1;
+            let (w, _, (e, ty)) = self.ctxs.rev_iter(tgt_ctx).find(|p| p.0.k == w)
+              .expect("return value not in context");
+            r.operand(&mut o);
+            bl.stmts.push(Statement::Let(
+              LetKind::Let(w.clone(), e.clone()), rel, ty.clone(), o.rv()));
+          }
+          bl.term = Some(Terminator::Jump1(tgt_ctx, tgt));
+        } else if let Some(term) = &mut bl.term {
+          r.terminator(term)
+        }
+      }
+      self.blocks.push(bl);
+    }
+
+    let entry = r.block(BlockId::ENTRY);
+    let entry_ctx = self[entry].ctx;
+    let mut params = self.ctxs.rev_iter(entry_ctx).take(args.len())
+      .map(|(v, _, (e, ty))| (v.clone(), e.clone(), ty.clone())).collect::<Vec<_>>();
+    params.reverse();
+    let bl = &mut self[id];
+    for ((v, e, ty), (rel, o)) in params.into_iter().zip(args.into_vec()) {
+      bl.stmts.push(Statement::Let(LetKind::Let(v, e), rel, ty, o.rv()));
+    }
+    bl.term = Some(Terminator::Jump1(entry_ctx, entry));
+  }
+}
@@ -11,7 +11,9 @@ pub(crate) use dominator::DominatorTree;
 
 pub(crate) mod dominator;
 pub(crate) mod ghost;
+pub(crate) mod inline;
 pub(crate) mod legalize;
+pub(crate) mod propagate;
 pub(crate) mod storage;
 
 /// A space-optimized `Option<BlockId>`.
@@ -547,7 +549,11 @@ impl Cfg {
     // eprintln!("ghost_analysis:\n{:#?}", self);
     self.legalize();
     // eprintln!("legalize:\n{:#?}", self);
-    // Do ghost analysis again because legalize produces dead values
+    self.eliminate_dead_stores();
+    // eprintln!("eliminate_dead_stores:\n{:#?}", self);
+    self.propagate();
+    // eprintln!("propagate:\n{:#?}", self);
+    // Do ghost analysis again because the passes above produce dead values
     self.do_ghost_analysis(&reachable, rets);
     // eprintln!("ghost_analysis 2:\n{:#?}", self);
   }
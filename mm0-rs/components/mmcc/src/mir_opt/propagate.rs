@@ -0,0 +1,431 @@
+//! The propagation pass, which performs constant folding, constant and copy propagation, and
+//! common subexpression elimination, as well as the dead store elimination pass.
+//!
+//! The propagation pass only rewrites computationally relevant operands, and only replaces a
+//! variable by a constant or another variable of the same integral or boolean type. Neither pass
+//! deletes statements: lets whose values are no longer needed after the rewrite are demoted to
+//! ghost by the following ghost analysis pass, so the proof generator still sees every binding
+//! together with its original pure expression.
+
+use std::collections::{HashMap, HashSet};
+use num::BigInt;
+use super::super::types::{self, IntTy, Size};
+#[allow(clippy::wildcard_imports)] use super::*;
+
+/// The types whose values this pass keeps track of.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Simple {
+  /// A fixed size integral type.
+  Int(IntTy),
+  /// The type `bool`.
+  Bool,
+}
+
+impl Simple {
+  fn from_ty(ty: &TyKind) -> Option<Self> {
+    match *ty {
+      TyKind::Bool => Some(Self::Bool),
+      TyKind::Int(ity) if ity.size() != Size::Inf => Some(Self::Int(ity)),
+      _ => None,
+    }
+  }
+}
+
+/// A known value, or the name of a variable holding an unknown value.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Value {
+  /// An integer constant of the given type.
+  Int(IntTy, BigInt),
+  /// A boolean constant.
+  Bool(bool),
+  /// The value of this (computationally relevant) variable.
+  Copy(VarId),
+}
+
+impl Value {
+  fn mentions(&self, f: &impl Fn(VarId) -> bool) -> bool {
+    matches!(*self, Value::Copy(v) if f(v))
+  }
+
+  fn to_operand(&self) -> Operand {
+    match *self {
+      Value::Int(ity, ref n) => Constant::int(ity, n.clone()).into(),
+      Value::Bool(b) => Constant::bool(b).into(),
+      Value::Copy(v) => Operand::Copy(v.into()),
+    }
+  }
+
+  /// Truncate an integer to the given (fixed size) type.
+  fn wrap(ity: IntTy, n: &BigInt) -> Option<Self> {
+    if ity.size() == Size::Inf { return None }
+    Some(Value::Int(ity, types::Unop::As(ity).apply_int(n)?.into_owned()))
+  }
+
+  fn unop(op: Unop, a: &Self) -> Option<Self> {
+    match (op, a) {
+      (Unop::Not, &Value::Bool(b)) => Some(Value::Bool(!b)),
+      (Unop::Neg(ity), Value::Int(_, n)) => Self::wrap(ity, &-n),
+      (Unop::BitNot(ity), Value::Int(_, n)) => Self::wrap(ity, &!n),
+      (Unop::As(_, ity), Value::Int(_, n)) => Self::wrap(ity, n),
+      _ => None,
+    }
+  }
+
+  fn binop(op: Binop, a: &Self, b: &Self) -> Option<Self> {
+    use types::Binop as B;
+    match (a, b) {
+      (&Value::Bool(a), &Value::Bool(b)) => match op {
+        Binop::And => Some(Value::Bool(a && b)),
+        Binop::Or => Some(Value::Bool(a || b)),
+        _ => None,
+      },
+      (Value::Int(_, a), Value::Int(_, b)) => {
+        let (op, ity) = match op {
+          Binop::Lt(_) => return Some(Value::Bool(a < b)),
+          Binop::Le(_) => return Some(Value::Bool(a <= b)),
+          Binop::Eq(_) => return Some(Value::Bool(a == b)),
+          Binop::Ne(_) => return Some(Value::Bool(a != b)),
+          Binop::And | Binop::Or => return None,
+          // Don't materialize huge intermediates; these shifts produce 0 or -1 anyway,
+          // but it's not worth the trouble.
+          Binop::Shl(_) | Binop::Shr(_) if *b > BigInt::from(128) => return None,
+          Binop::Add(ity) => (B::Add, ity),
+          Binop::Mul(ity) => (B::Mul, ity),
+          Binop::Sub(ity) => (B::Sub, ity),
+          Binop::Max(ity) => (B::Max, ity),
+          Binop::Min(ity) => (B::Min, ity),
+          Binop::BitAnd(ity) => (B::BitAnd, ity),
+          Binop::BitOr(ity) => (B::BitOr, ity),
+          Binop::BitXor(ity) => (B::BitXor, ity),
+          Binop::Shl(ity) => (B::Shl, ity),
+          Binop::Shr(ity) => (B::Shr, ity),
+        };
+        Self::wrap(ity, &op.apply_int_int(a, b)?)
+      }
+      _ => None,
+    }
+  }
+}
+
+/// A pure operation on known values, used as the key for common subexpression elimination.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Key {
+  Unop(Unop, Value),
+  Binop(Binop, Value, Value),
+}
+
+impl Key {
+  fn mentions(&self, f: &impl Fn(VarId) -> bool) -> bool {
+    match self {
+      Key::Unop(_, a) => a.mentions(f),
+      Key::Binop(_, a, b) => a.mentions(f) || b.mentions(f),
+    }
+  }
+}
+
+/// The facts known at a program point.
+#[derive(Clone, Debug, Default)]
+struct PropState {
+  /// The known values of variables. Every variable in the domain has a [`Simple`] type,
+  /// and `Copy` values point to variables of the same type.
+  vals: im::HashMap<VarId, Value>,
+  /// The available expressions, and a variable holding the value of each.
+  exprs: im::HashMap<Key, VarId>,
+}
+
+impl PropState {
+  /// Forget everything we know about the variables satisfying `f`.
+  fn kill(&mut self, f: impl Fn(VarId) -> bool) {
+    self.vals.retain(|&v, val| !f(v) && !val.mentions(&f));
+    self.exprs.retain(|k, &v| !f(v) && !k.mentions(&f));
+  }
+
+  /// Get the known value of an operand.
+  fn value(&self, o: &Operand) -> Option<Value> {
+    match o {
+      Operand::Copy(p) | Operand::Move(p) if p.proj.is_empty() => self.vals.get(&p.local).cloned(),
+      Operand::Const(c) => match (&c.k, c.ety.0.as_deref(), &*c.ety.1) {
+        (ConstKind::Int, Some(ExprKind::Int(n)), &TyKind::Int(ity)) =>
+          Some(Value::Int(ity, n.clone())),
+        (ConstKind::Bool, Some(&ExprKind::Bool(b)), _) => Some(Value::Bool(b)),
+        _ => None,
+      },
+      _ => None,
+    }
+  }
+
+  /// Get the value of an operand, or the variable it reads from if the value is unknown.
+  fn atom(&self, o: &Operand) -> Option<Value> {
+    self.value(o).or(match o {
+      Operand::Copy(p) | Operand::Move(p) if p.proj.is_empty() => Some(Value::Copy(p.local)),
+      _ => None,
+    })
+  }
+
+  /// Replace an operand by its known value. Returns true if the operand changed.
+  fn subst(&self, o: &mut Operand) -> bool {
+    if let Operand::Copy(p) | Operand::Move(p) = o {
+      if p.proj.is_empty() {
+        if let Some(val) = self.vals.get(&p.local) { *o = val.to_operand(); return true }
+      }
+    }
+    false
+  }
+
+  /// Replace all operands in an rvalue by their known values.
+  fn subst_rvalue(&self, rv: &mut RValue) -> bool {
+    match rv {
+      RValue::Use(o) |
+      RValue::Unop(_, o) => self.subst(o),
+      RValue::Binop(_, o1, o2) |
+      RValue::Eq(_, _, o1, o2) => self.subst(o1) | self.subst(o2),
+      RValue::List(os) |
+      RValue::Array(os) => os.iter_mut().fold(false, |changed, o| self.subst(o) | changed),
+      RValue::Pun(..) |
+      RValue::Cast(..) |
+      RValue::Ghost(_) |
+      RValue::Borrow(_) |
+      RValue::Mm0(..) |
+      RValue::Typeof(_) => false,
+    }
+  }
+}
+
+/// The type-level information needed by the transfer functions.
+struct Propagator {
+  /// The variables of [`Simple`] type.
+  simple: HashMap<VarId, Simple>,
+}
+
+impl Propagator {
+  fn new(cfg: &Cfg) -> Self {
+    let mut simple = HashMap::new();
+    for i in 0..cfg.ctxs.num_buffers() {
+      for (v, _, (_, ty)) in &cfg.ctxs[CtxBufId::from_usize(i)].vars {
+        if let Some(s) = Simple::from_ty(ty) { simple.insert(v.k, s); }
+      }
+    }
+    for (_, bl) in cfg.blocks() {
+      if bl.is_dead() { continue }
+      for stmt in &bl.stmts {
+        stmt.foreach_def(|v, _, _, ty| {
+          if let Some(s) = Simple::from_ty(ty) { simple.insert(v.k, s); }
+        })
+      }
+    }
+    Self { simple }
+  }
+
+  /// Cast a value to the given type, if it is known to fit.
+  fn cast(&self, val: Value, s: Simple) -> Option<Value> {
+    match (val, s) {
+      (Value::Int(_, n), Simple::Int(ity)) => ity.contains(&n).then_some(Value::Int(ity, n)),
+      (val @ Value::Bool(_), Simple::Bool) => Some(val),
+      (Value::Copy(v), s) => (self.simple.get(&v) == Some(&s)).then_some(Value::Copy(v)),
+      _ => None,
+    }
+  }
+
+  /// The transfer function for a statement. Returns the rewritten statement, if it changed.
+  fn apply_statement(&self, st: &mut PropState, stmt: &Statement) -> Option<Statement> {
+    match stmt {
+      Statement::Let(lk, r, ty, rv) => {
+        let v = match lk {
+          LetKind::Let(v, _) => v.k,
+          LetKind::Ptr([(x, _), (y, _)]) => {
+            let (x, y) = (x.k, y.k);
+            st.kill(|w| w == x || w == y);
+            return None
+          }
+        };
+        st.kill(|w| w == v);
+        if !r { return None }
+        let mut rv = rv.clone();
+        let mut changed = st.subst_rvalue(&mut rv);
+        let Some(s) = Simple::from_ty(ty) else {
+          return changed.then(|| Statement::Let(lk.clone(), true, ty.clone(), rv))
+        };
+        // `Ok(val)` if the value is known, `Err(key)` if it is a candidate for CSE
+        let res = match &rv {
+          RValue::Use(o) => st.atom(o).map(Ok),
+          RValue::Unop(op, o) =>
+            st.atom(o).map(|a| Value::unop(*op, &a).ok_or(Key::Unop(*op, a))),
+          RValue::Binop(op, o1, o2) => st.atom(o1).zip(st.atom(o2))
+            .map(|(a, b)| Value::binop(*op, &a, &b).ok_or(Key::Binop(*op, a, b))),
+          _ => None,
+        };
+        let val = match res {
+          Some(Ok(val)) => self.cast(val, s),
+          Some(Err(key)) => match st.exprs.get(&key) {
+            Some(&w) => self.cast(Value::Copy(w), s),
+            None => { st.exprs.insert(key, v); None }
+          }
+          None => None,
+        };
+        if let Some(val) = val {
+          // Uses have already been substituted
+          if !matches!(rv, RValue::Use(_)) {
+            rv = val.to_operand().rv();
+            changed = true;
+          }
+          st.vals.insert(v, val);
+        }
+        changed.then(|| Statement::Let(lk.clone(), true, ty.clone(), rv))
+      }
+      Statement::Assign(lhs, ty, rhs, vars) => {
+        let mut rhs = rhs.clone();
+        let changed = vars.iter().any(|v| v.rel) && st.subst(&mut rhs);
+        st.kill(|w| vars.iter().any(|v| v.from == w || v.to.k == w));
+        changed.then(|| Statement::Assign(lhs.clone(), ty.clone(), rhs, vars.clone()))
+      }
+      Statement::LabelGroup(..) | Statement::PopLabelGroup | Statement::DominatedBlock(..) => None,
+    }
+  }
+
+  /// Rewrite the computationally relevant operands of a terminator.
+  fn subst_terminator(st: &PropState, term: &mut Terminator) {
+    match term {
+      Terminator::Jump(_, args, _) |
+      Terminator::Return(_, args) =>
+        for (_, r, o) in &mut **args { if *r { st.subst(o); } },
+      Terminator::Call { args, .. } =>
+        for (r, o) in &mut **args { if *r { st.subst(o); } },
+      Terminator::Unreachable(o) |
+      Terminator::Exit(o) |
+      Terminator::If(_, o, _) |
+      Terminator::Assert(o, _, _) => { st.subst(o); }
+      Terminator::Jump1(..) |
+      Terminator::Fail |
+      Terminator::Dead => {}
+    }
+  }
+}
+
+/// The propagation state on entry to a block, or `None` if the block has not been visited yet.
+#[derive(Clone, Debug, Default)]
+struct PropDom(Option<PropState>);
+
+impl Domain for PropDom {
+  fn join(&mut self, other: &Self) -> bool {
+    let Some(other) = &other.0 else { return false };
+    let Some(this) = &mut self.0 else { self.0 = Some(other.clone()); return true };
+    let old = (this.vals.len(), this.exprs.len());
+    this.vals.retain(|v, val| other.vals.get(v) == Some(val));
+    this.exprs.retain(|k, v| other.exprs.get(k) == Some(v));
+    old != (this.vals.len(), this.exprs.len())
+  }
+}
+
+impl Cfg {
+  /// Run the propagation pass over the CFG, which folds constant expressions, replaces uses of
+  /// variables with known constant values or copies of other variables, and reuses previously
+  /// computed results of identical pure operations.
+  ///
+  /// Ghost analysis should be run after this pass to remove the computations that are no longer
+  /// needed.
+  pub fn propagate(&mut self) {
+    struct PropAnalysis<'a> {
+      prop: &'a Propagator,
+      cfg: &'a Cfg,
+      ctx_vars: HashMap<BlockId, HashSet<VarId>>,
+    }
+
+    impl Analysis for PropAnalysis<'_> {
+      type Dir = Forward;
+      type Doms = BlockVec<PropDom>;
+
+      fn bottom(&mut self, cfg: &Cfg) -> Self::Doms {
+        let mut doms = BlockVec::<PropDom>::from_default(cfg.blocks.len());
+        doms[BlockId::ENTRY] = PropDom(Some(Default::default()));
+        doms
+      }
+
+      fn apply_statement(&mut self, _: &Self::Doms,
+          _: Location, stmt: &Statement, d: &mut PropDom) {
+        if let Some(st) = &mut d.0 { self.prop.apply_statement(st, stmt); }
+      }
+
+      fn apply_terminator(&mut self, _: &Self::Doms,
+          _: BlockId, term: &Terminator, d: &mut PropDom) {
+        // A jump can rebind variables in the target, and drops any variables that are not in
+        // the target context. All other edges go to blocks with an extended context.
+        let (Some(st), &Terminator::Jump(tgt, ref args, _)) = (&mut d.0, term) else { return };
+        let cfg = self.cfg;
+        let vars = self.ctx_vars.entry(tgt)
+          .or_insert_with(|| cfg.ctxs.rev_iter(cfg[tgt].ctx).map(|p| p.0.k).collect());
+        st.kill(|v| !vars.contains(&v) || args.iter().any(|a| a.0 == v))
+      }
+
+      fn apply_trans_for_block(&mut self,
+          ds: &Self::Doms, id: BlockId, bl: &BasicBlock, d: &mut PropDom) {
+        if d.0.is_none() || bl.is_dead() { return }
+        self.do_apply_trans_for_block(ds, id, bl, d)
+      }
+    }
+
+    let prop = Propagator::new(self);
+    let mut analysis = PropAnalysis { prop: &prop, cfg: self, ctx_vars: HashMap::new() };
+    let result = analysis.iterate_to_fixpoint(self);
+    for (id, bl) in self.blocks.enum_iter_mut() {
+      let Some(mut st) = result[id].0.clone() else { continue };
+      if bl.is_dead() { continue }
+      for stmt in &mut bl.stmts {
+        if let Some(new) = prop.apply_statement(&mut st, stmt) { *stmt = new }
+      }
+      if let Some(term) = &mut bl.term { Propagator::subst_terminator(&st, term) }
+    }
+  }
+}
+
+/// A visitor which collects the variables whose address is taken in computationally relevant code.
+struct BorrowVisitor(HashSet<VarId>);
+
+impl Visitor for BorrowVisitor {
+  fn visit_operand(&mut self, o: &Operand) {
+    if let Operand::Ref(p) = o { self.0.insert(p.local); }
+  }
+
+  fn visit_rvalue(&mut self, rv: &RValue) {
+    match rv {
+      RValue::Borrow(p) => { self.0.insert(p.local); }
+      RValue::Use(o) |
+      RValue::Unop(_, o) |
+      RValue::Cast(_, o, _) => self.visit_operand(o),
+      RValue::Binop(_, o1, o2) |
+      RValue::Eq(_, _, o1, o2) => { self.visit_operand(o1); self.visit_operand(o2) }
+      RValue::List(os) |
+      RValue::Array(os) => for o in &**os { self.visit_operand(o) }
+      RValue::Pun(..) |
+      RValue::Ghost(_) |
+      RValue::Mm0(..) |
+      RValue::Typeof(_) => {}
+    }
+  }
+}
+
+impl Cfg {
+  /// Run the dead store elimination pass over the CFG.
+  ///
+  /// An assignment `x <- e` which overwrites all of `x` does not need the old value of `x`, but
+  /// since it writes to the storage of `x`, ghost analysis would otherwise keep the value that
+  /// was previously stored there alive. This pass replaces such assignments by `let x' := e`
+  /// with the same target variable and pure expression, so that the old value of `x` can be
+  /// demoted to ghost if it is not used elsewhere. Variables whose address is taken are left
+  /// alone, because a write to their storage is observable through the reference.
+  ///
+  /// Ghost analysis should be run after this pass to remove the stores that are no longer needed.
+  pub fn eliminate_dead_stores(&mut self) {
+    let mut borrows = BorrowVisitor(HashSet::new());
+    for (_, bl) in self.blocks() { borrows.visit_basic_block(bl) }
+    for (_, bl) in self.blocks.enum_iter_mut() {
+      if bl.is_dead() { continue }
+      for stmt in &mut bl.stmts {
+        let Statement::Assign(lhs, _, rhs, vars) = stmt else { continue };
+        let [Rename { from, to, rel, ety: (e, ty) }] = &**vars else { continue };
+        if !lhs.proj.is_empty() || *from != lhs.local || borrows.0.contains(from) { continue }
+        *stmt = Statement::Let(LetKind::Let(to.clone(), e.clone()), *rel, ty.clone(),
+          rhs.clone().rv());
+      }
+    }
+  }
+}
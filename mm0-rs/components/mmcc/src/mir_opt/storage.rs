@@ -191,7 +191,7 @@ impl TyKind {
             if m1.size > 0 {
               size = size.checked_add(m1.size)?;
               let large = m1.on_stack || !matches!(state, State::Start);
-              state = if large { State::One } else { State::Large };
+              state = if large { State::Large } else { State::One };
             }
           }
         }
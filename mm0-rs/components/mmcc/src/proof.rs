@@ -1,9 +1,11 @@
 //! The proof objects constructed by the compiler.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, hash_map::DefaultHasher};
+use std::hash::{Hash, Hasher};
 
+use crate::cache::hash_debug;
 use crate::regalloc::PCode;
-use crate::types::classify;
+use crate::types::{classify, ast::ProcKind};
 use crate::types::vcode::ProcAbi;
 use crate::{Idx, IdxVec, LinkedCode, Symbol, TEXT_START};
 use crate::codegen::FUNCTION_ALIGN;
@@ -42,6 +44,25 @@ impl LinkedCode {
 }
 
 impl<'a> ElfProof<'a> {
+  /// A hash of everything the correctness proof is generated from: the ELF file, and the MIR and
+  /// machine code of each procedure. Source positions are not included, so the proof of a program
+  /// that has only moved in the file can be reused.
+  #[must_use] pub fn key(&self) -> u64 {
+    let mut h = DefaultHasher::new();
+    self.file.hash(&mut h);
+    let code = self.code;
+    hash_debug(&mut h, &code.init);
+    for (f, sym) in code.func_names.1.enum_iter() {
+      hash_debug(&mut h, sym);
+      if let Some(p) = code.mir.get(sym) {
+        hash_debug(&mut h, &(p.kind, &p.args, &p.outs, &p.rets, &p.body))
+      }
+      hash_debug(&mut h, &code.funcs[f]);
+    }
+    hash_debug(&mut h, &(&code.globals, &code.func_abi));
+    h.finish()
+  }
+
   /// The ELF header.
   #[must_use] pub fn header(&self) -> &[u8] { &self[..0x40] }
 
@@ -68,6 +89,22 @@ impl<'a> ElfProof<'a> {
   /// The size of the BSS section (zeroed data following the read-only section).
   #[must_use] pub fn bss(&self) -> u64 { self.p_memsz() - self.p_filesz() }
 
+  /// The type of the value passed to `exit` at the end of the `start` routine, which is the
+  /// return type of `main`, or `()` if there is no `main` function. This is the exit
+  /// proposition, which holds on any successful run of the program. If the program never
+  /// exits, this is `F.`.
+  #[must_use] pub fn result_ty(&self) -> mir::Ty {
+    let cfg = &self.code.init.0;
+    cfg.blocks().find_map(|(_, bl)| match bl.term {
+      Some(mir::Terminator::Exit(ref o)) if !bl.is_dead() => Some(match o.place() {
+        Ok(p) => cfg.ctxs.rev_iter(bl.ctx).find(|v| v.0.k == p.local)
+          .expect("exit value not in context").2.1.clone(),
+        Err(c) => c.ety.1.clone(),
+      }),
+      _ => None,
+    }).unwrap_or_else(|| std::rc::Rc::new(mir::TyKind::False))
+  }
+
   /// The mapping from IDs to function names.
   #[must_use] pub fn func_names(&self) -> &'a IdxVec<ProcId, Symbol> { &self.code.func_names.1 }
 
@@ -299,6 +336,12 @@ impl<'a> Proc<'a> {
     self.id.map(|id| self.code.func_names.1[id])
   }
 
+  /// True if the procedure may have side effects, i.e. it is a `proc` rather than a `func`.
+  /// The init function is always considered side-effecting.
+  #[must_use] pub fn side_effect(&self) -> bool {
+    self.name().is_none_or(|f| self.code.mir[&f].kind != ProcKind::Func)
+  }
+
   /// The size of the procedure with padding omitted.
   #[must_use] pub fn len_no_padding(&self) -> u32 { self.proc.len }
 
@@ -10,21 +10,20 @@
 use std::collections::HashMap;
 
 use mm0_util::u32_as_usize;
-use regalloc2::{Allocation, Edit, Function, ProgPoint, SpillSlot};
+use regalloc2::{Allocation, Edit, Function, MachineEnv, ProgPoint, SpillSlot};
 
-use crate::arch::{AMode, Inst, callee_saved, caller_saved, MACHINE_ENV, Offset, PAMode, PInst,
-  PRegMem, PRegMemImm, PRegSet, PShiftIndex, RSP, PReg, RegMem, RegMemImm};
+use crate::arch::{AMode, Inst, callee_saved, caller_saved, MACHINE_ENV, MachInst, Offset, PAMode,
+  PInst, PRegMem, PRegMemImm, PRegSet, PShiftIndex, RSP, PReg, RegMem, RegMemImm};
 use crate::types::classify::Trace;
 use crate::types::{IdxVec, Size};
 use crate::types::mir;
 use crate::Idx;
-use crate::build_vcode::VCode;
-use crate::types::vcode::{self, IsReg, InstId, ProcAbi, SpillId, BlockId, ChunkVec};
+use crate::types::vcode::{self, IsReg, InstId, ProcAbi, SpillId, BlockId, ChunkVec, VCode};
 
-impl<I: vcode::Inst> vcode::VCode<I> {
-  fn do_regalloc(&self) -> regalloc2::Output {
+impl<I: vcode::Inst> VCode<I> {
+  pub(crate) fn do_regalloc(&self, env: &MachineEnv) -> regalloc2::Output {
     let opts = regalloc2::RegallocOptions { verbose_log: true };
-    regalloc2::run(self, &MACHINE_ENV, &opts).expect("fatal regalloc error")
+    regalloc2::run(self, env, &opts).expect("fatal regalloc error")
   }
 }
 
@@ -34,12 +33,15 @@ pub(crate) struct ApplyRegalloc {
   offset_iter: std::vec::IntoIter<u32>,
   regspill_off: u32,
   spill_map: IdxVec<SpillId, u32>,
+  /// The stack pointer register, which is the base register for spill slots.
+  sp: PReg,
 }
 
 impl ApplyRegalloc {
-  fn new(allocs: Vec<Allocation>, offsets: Vec<u32>,
+  pub(crate) fn new(allocs: Vec<Allocation>, offsets: Vec<u32>,
     regspill_off: u32,
     spill_map: IdxVec<SpillId, u32>,
+    sp: PReg,
   ) -> Self {
     Self {
       num_allocs: allocs.len(),
@@ -47,29 +49,30 @@ impl ApplyRegalloc {
       offset_iter: offsets.into_iter(),
       regspill_off,
       spill_map,
+      sp,
     }
   }
 
   fn spill(&self, n: SpillSlot) -> PAMode {
     let off = (self.regspill_off + u32::try_from(n.index()).expect("impossible") * 8).into();
-    PAMode { base: RSP, si: None, off }
+    PAMode { base: self.sp, si: None, off }
   }
 
-  fn next_inst(&mut self) {
+  pub(crate) fn next_inst(&mut self) {
     assert_eq!(u32_as_usize(self.offset_iter.next().expect("inst align")),
       self.num_allocs - self.alloc_iter.len());
   }
 
-  fn next(&mut self) -> Allocation {
+  pub(crate) fn next(&mut self) -> Allocation {
     self.alloc_iter.next().expect("allocation align")
   }
 
-  fn reg(&mut self) -> PReg {
+  pub(crate) fn reg(&mut self) -> PReg {
     PReg(self.next().as_reg().expect("expected a register"))
   }
-  fn mem(&mut self, a: &AMode) -> PAMode {
+  pub(crate) fn mem(&mut self, a: &AMode) -> PAMode {
     let (off, base) = match (a.off, a.base.is_valid()) {
-      (Offset::Spill(sp, n), false) => ((self.spill_map[sp] + n).into(), RSP),
+      (Offset::Spill(sp, n), false) => ((self.spill_map[sp] + n).into(), self.sp),
       (off, true) => (off, self.reg()),
       (off, false) => (off, PReg::invalid()),
     };
@@ -77,7 +80,7 @@ impl ApplyRegalloc {
     PAMode { off, base, si }
   }
 
-  fn rm(&mut self, rm: &RegMem) -> PRegMem {
+  pub(crate) fn rm(&mut self, rm: &RegMem) -> PRegMem {
     match rm {
       RegMem::Reg(_) => PRegMem::Reg(self.reg()),
       RegMem::Mem(a) => PRegMem::Mem(self.mem(a)),
@@ -99,8 +102,8 @@ mk_id! {
 }
 
 #[derive(Clone)]
-pub(crate) struct PCode {
-  pub(crate) insts: IdxVec<PInstId, PInst>,
+pub(crate) struct PCode<I = PInst> {
+  pub(crate) insts: IdxVec<PInstId, I>,
   pub(crate) block_map: HashMap<mir::BlockId, BlockId>,
   pub(crate) blocks: IdxVec<BlockId, (mir::BlockId, PInstId, PInstId)>,
   pub(crate) block_addr: IdxVec<BlockId, u32>,
@@ -111,14 +114,14 @@ pub(crate) struct PCode {
   pub(crate) len: u32,
 }
 
-impl PCode {
-  pub(crate) fn block_insts(&self, id: BlockId) -> &[PInst] {
+impl<I> PCode<I> {
+  pub(crate) fn block_insts(&self, id: BlockId) -> &[I] {
     let (_, inst_start, inst_end) = self.blocks[id];
     &self.insts[inst_start..inst_end]
   }
 }
 
-impl std::fmt::Debug for PCode {
+impl<I: std::fmt::Debug> std::fmt::Debug for PCode<I> {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     for (bl, &(_, start, end)) in self.blocks.enum_iter() {
       write!(f, "vb{}(", bl.index())?;
@@ -135,22 +138,40 @@ impl std::fmt::Debug for PCode {
 }
 
 #[derive(Debug)]
-struct PCodeBuilder {
-  code: Box<PCode>,
+pub(crate) struct PCodeBuilder<I = PInst> {
+  code: Box<PCode<I>>,
   fwd_jumps: Vec<(u32, PInstId)>,
 }
 
-impl std::ops::Deref for PCodeBuilder {
-  type Target = PCode;
+impl<I> std::ops::Deref for PCodeBuilder<I> {
+  type Target = PCode<I>;
   fn deref(&self) -> &Self::Target { &self.code }
 }
-impl std::ops::DerefMut for PCodeBuilder {
+impl<I> std::ops::DerefMut for PCodeBuilder<I> {
   fn deref_mut(&mut self) -> &mut Self::Target { &mut self.code }
 }
 
-impl PCodeBuilder {
-  fn push(&mut self, mut inst: PInst) -> PInstId {
-    self.len += u32::from(inst.len());
+impl<I: MachInst> PCodeBuilder<I> {
+  pub(crate) fn new(block_map: HashMap<mir::BlockId, BlockId>, trace: Trace, stack_size: u32
+  ) -> Self {
+    Self {
+      code: Box::new(PCode {
+        insts: IdxVec::new(),
+        block_map,
+        blocks: IdxVec::from(vec![]),
+        block_addr: IdxVec::from(vec![0]),
+        block_params: std::iter::once([]).collect(),
+        trace,
+        stack_size,
+        saved_regs: vec![],
+        len: 0,
+      }),
+      fwd_jumps: vec![],
+    }
+  }
+
+  pub(crate) fn push(&mut self, mut inst: I) -> PInstId {
+    self.len += inst.len();
     if let Some(dst) = inst.is_jump() {
       if let Some(&ub) = self.block_addr.get(dst) {
         if i8::try_from(self.len - ub).is_ok() { inst.shorten() }
@@ -165,36 +186,7 @@ impl PCodeBuilder {
     }
   }
 
-  fn push_prologue(&mut self, stack_size: u32, saved_regs: impl Iterator<Item=PReg>) {
-    for reg in saved_regs {
-      self.push(PInst::Push64 { src: PRegMemImm::Reg(reg) });
-    }
-    if stack_size != 0 {
-      self.push(PInst::Binop {
-        op: crate::arch::Binop::Sub,
-        sz: Size::S64,
-        dst: RSP,
-        src: PRegMemImm::Imm(stack_size)
-      });
-    }
-  }
-
-  fn push_epilogue(&mut self, stack_size: u32, saved_regs: impl DoubleEndedIterator<Item=PReg>) {
-    if stack_size != 0 {
-      self.push(PInst::Binop {
-        op: crate::arch::Binop::Add,
-        sz: Size::S64,
-        dst: RSP,
-        src: PRegMemImm::Imm(stack_size)
-      });
-    }
-    for dst in saved_regs.rev() {
-      self.push(PInst::Pop64 { dst });
-    }
-    self.push(PInst::Ret);
-  }
-
-  fn apply_edits(&mut self,
+  pub(crate) fn apply_edits(&mut self,
     edits: &mut std::iter::Peekable<impl Iterator<Item=(ProgPoint, Edit)>>,
     ar: &mut ApplyRegalloc,
     pt: ProgPoint
@@ -202,14 +194,14 @@ impl PCodeBuilder {
     while edits.peek().map_or(false, |p| p.0 == pt) {
       if let Some((_, Edit::Move { from, to, .. })) = edits.next() {
         match (from.as_reg().map(PReg), to.as_reg().map(PReg)) {
-          (Some(src), Some(dst)) => { self.push(PInst::MovRR { sz: Size::S64, dst, src }); }
+          (Some(src), Some(dst)) => { self.push(I::mov(dst, src)); }
           (Some(src), _) => {
             let dst = ar.spill(to.as_stack().expect("bad regalloc"));
-            self.push(PInst::Store { spill: true, sz: Size::S64, dst, src });
+            self.push(I::spill_store(dst, src));
           }
           (_, Some(dst)) => {
             let src = ar.spill(from.as_stack().expect("bad regalloc"));
-            self.push(PInst::Load64 { spill: true, dst, src });
+            self.push(I::spill_load(dst, src));
           }
           _ => panic!("bad regalloc")
         }
@@ -218,7 +210,7 @@ impl PCodeBuilder {
   }
 
   #[allow(clippy::unnecessary_box_returns)]
-  fn finish(self, saved_regs: Vec<PReg>) -> Box<PCode> {
+  pub(crate) fn finish(self, saved_regs: Vec<PReg>) -> Box<PCode<I>> {
     let Self {mut code, fwd_jumps, ..} = self;
     code.saved_regs = saved_regs;
     for (pos, i) in fwd_jumps {
@@ -237,18 +229,49 @@ impl PCodeBuilder {
         code.block_addr.push(code.len);
         if let Some(n) = iter.next() { cur = n.1 } else { break }
       }
-      code.len += u32::from(inst.len());
+      code.len += inst.len();
     }
     code
   }
 }
 
-struct BlockBuilder<'a> {
+impl PCodeBuilder {
+  fn push_prologue(&mut self, stack_size: u32, saved_regs: impl Iterator<Item=PReg>) {
+    for reg in saved_regs {
+      self.push(PInst::Push64 { src: PRegMemImm::Reg(reg) });
+    }
+    if stack_size != 0 {
+      self.push(PInst::Binop {
+        op: crate::arch::Binop::Sub,
+        sz: Size::S64,
+        dst: RSP,
+        src: PRegMemImm::Imm(stack_size)
+      });
+    }
+  }
+
+  fn push_epilogue(&mut self, stack_size: u32, saved_regs: impl DoubleEndedIterator<Item=PReg>) {
+    if stack_size != 0 {
+      self.push(PInst::Binop {
+        op: crate::arch::Binop::Add,
+        sz: Size::S64,
+        dst: RSP,
+        src: PRegMemImm::Imm(stack_size)
+      });
+    }
+    for dst in saved_regs.rev() {
+      self.push(PInst::Pop64 { dst });
+    }
+    self.push(PInst::Ret);
+  }
+}
+
+pub(crate) struct BlockBuilder<'a> {
   blocks: &'a [(mir::BlockId, InstId, InstId)],
   start: PInstId,
   cur: usize,
   next_id: mir::BlockId,
-  next: InstId,
+  pub(crate) next: InstId,
 }
 
 impl<'a> BlockBuilder<'a> {
@@ -259,7 +282,7 @@ impl<'a> BlockBuilder<'a> {
     }
   }
 
-  fn new(blocks: &'a [(mir::BlockId, InstId, InstId)]) -> Self {
+  pub(crate) fn new(blocks: &'a [(mir::BlockId, InstId, InstId)]) -> Self {
     let mut this = Self {
       blocks, start: PInstId(0), cur: 0, next_id: mir::BlockId(0), next: InstId(0)
     };
@@ -267,7 +290,7 @@ impl<'a> BlockBuilder<'a> {
     this
   }
 
-  fn finish_block(&mut self, code: &mut PCode) {
+  pub(crate) fn finish_block<I>(&mut self, code: &mut PCode<I>) {
     let end = PInstId::from_usize(code.insts.len());
     self.cur += 1;
     let id = self.next_id;
@@ -276,7 +299,7 @@ impl<'a> BlockBuilder<'a> {
   }
 }
 
-fn get_clobbers(vcode: &VCode, out: &regalloc2::Output) -> PRegSet {
+pub(crate) fn get_clobbers<I: vcode::Inst>(vcode: &VCode<I>, out: &regalloc2::Output) -> PRegSet {
   let mut result = PRegSet::default();
   for (_, edit) in &out.edits {
     let Edit::Move { to, .. } = *edit;
@@ -301,12 +324,12 @@ fn get_clobbers(vcode: &VCode, out: &regalloc2::Output) -> PRegSet {
   result
 }
 
-impl VCode {
+impl VCode<Inst> {
   #[allow(clippy::similar_names)]
   pub(crate) fn regalloc(mut self) -> (ProcAbi, Box<PCode>) {
     // drop(simplelog::SimpleLogger::init(simplelog::LevelFilter::Debug, simplelog::Config::default()));
     // eprintln!("{:#?}", self);
-    let out = self.do_regalloc();
+    let out = self.do_regalloc(&MACHINE_ENV);
     // eprintln!("{:#?}", out);
     let clobbers = get_clobbers(&self, &out);
     let saved_regs = callee_saved().filter(move |&r| clobbers.get(r)).collect::<Vec<_>>();
@@ -323,22 +346,9 @@ impl VCode {
       }
       stack_size_no_ret = rsp_off + u32::try_from(saved_regs.len() * 8).expect("overflow");
       spill_map[0] = stack_size_no_ret + 8;
-      ApplyRegalloc::new(out.allocs, out.inst_alloc_offsets, outgoing, spill_map.into())
+      ApplyRegalloc::new(out.allocs, out.inst_alloc_offsets, outgoing, spill_map.into(), RSP)
     } else { unreachable!() };
-    let mut code = PCodeBuilder {
-      code: Box::new(PCode {
-        insts: IdxVec::new(),
-        block_map: self.block_map,
-        blocks: IdxVec::from(vec![]),
-        block_addr: IdxVec::from(vec![0]),
-        block_params: std::iter::once([]).collect(),
-        trace: self.trace,
-        stack_size: stack_size_no_ret,
-        saved_regs: vec![],
-        len: 0,
-      }),
-      fwd_jumps: vec![],
-    };
+    let mut code = PCodeBuilder::new(self.block_map, self.trace, stack_size_no_ret);
     let mut bb = BlockBuilder::new(&self.blocks.0);
     code.push_prologue(stack_size_no_ret, saved_regs.iter().copied());
     // let mut last_let_start = Default::default();
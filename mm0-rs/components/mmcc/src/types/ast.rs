@@ -395,6 +395,24 @@ pub enum IfKind {
 pub struct LabelId(pub VarId, pub u16);
 #[cfg(feature = "memory")] mm0_deepsize::deep_size_0!(LabelId);
 
+/// A `(map ...)` or `(fold ...)` expression, which iterates over one or more arrays of the
+/// same length `n` in parallel.
+#[derive(Debug)]
+#[cfg_attr(feature = "memory", derive(DeepSizeOf))]
+pub struct MapFold {
+  /// The loop index `i`, if named.
+  pub idx: Option<Spanned<VarId>>,
+  /// A hypothesis `h: i < n` in the body, if named.
+  pub hyp: Option<Spanned<VarId>>,
+  /// For a `fold`, the accumulator and its initial value. This is `None` for `map`.
+  pub acc: Option<(Spanned<VarId>, Box<Expr>)>,
+  /// The arrays to iterate over, and the variables bound to their `i`th elements.
+  pub arrays: Box<[(Spanned<VarId>, Expr)]>,
+  /// The body of the loop. For `map` this is the `i`th element of the result,
+  /// and for `fold` it is the next value of the accumulator.
+  pub body: Box<Expr>,
+}
+
 /// An expression.
 pub type Expr = Spanned<ExprKind>;
 
@@ -519,6 +537,8 @@ pub enum ExprKind {
     /// not introduce a `hyp: !cond` assumption after the loop.
     has_break: bool,
   },
+  /// A `map` or `fold` over arrays.
+  MapFold(Box<MapFold>),
   /// `(unreachable h)` takes a proof of false and undoes the current code path.
   Unreachable(Box<Expr>),
   /// `(lab e1 ... en)` jumps to label `lab` with `e1 ... en` as arguments.
@@ -724,6 +744,19 @@ impl ExprKind {
         body.debug_indent(i+1, f)?;
         indent(i, f)?; write!(f, "}}")
       }
+      ExprKind::MapFold(mf) => {
+        write!(f, "{} ", if mf.acc.is_some() {"fold"} else {"map"})?;
+        if let Some(h) = &mf.hyp { write!(f, "{}: ", h.k)? }
+        if let Some(idx) = &mf.idx { write!(f, "{} ", idx.k)? }
+        writeln!(f, "(")?;
+        let acc = mf.acc.iter().map(|(x, e)| (x, &**e));
+        for (x, e) in acc.chain(mf.arrays.iter().map(|(x, e)| (x, e))) {
+          indent(i+1, f)?; write!(f, "{} := ", x.k)?; e.k.debug_indent(i+1, f)?; writeln!(f, ",")?;
+        }
+        indent(i, f)?; writeln!(f, ") {{")?;
+        indent(i+1, f)?; mf.body.k.debug_indent(i+1, f)?; writeln!(f)?;
+        indent(i, f)?; write!(f, "}}")
+      }
       ExprKind::Unreachable(e) => {
         write!(f, "unreachable ")?;
         e.k.debug_indent(i, f)
@@ -872,6 +905,9 @@ pub enum ItemKind {
     /// The compiler will ensure this matches an existing intrinsic, and intrinsics cannot be
     /// called until they are declared using an `intrinsic` declaration.
     intrinsic: Option<super::entity::IntrinsicProc>,
+    /// True if the procedure is marked `inline`, meaning that calls to it should always be
+    /// inlined at the call site (when possible), regardless of the size heuristic.
+    inline: bool,
     /// The type of declaration: `func`, `proc`, or `intrinsic`.
     kind: ProcKind,
     /// The name of the procedure.
@@ -65,6 +65,8 @@ pub enum Copy {
 pub enum Move {
   /// A small (<= 8 byte) move, implemented via a copy.
   Small(Operand, Copy),
+  /// A large move of an uninitialized value, which emits no code.
+  Uninit,
 }
 
 /// A `build_as` call.
@@ -417,9 +419,10 @@ mk_fold! { <'a>
   }
 
   fn before_move, after_move, do_move(self, it, o: &'a mir::Operand, cl: Move) {
-    let Move::Small(cl1, cl2) = cl;
-    self.do_operand(o, cl1, it);
-    self.do_copy(cl2, it);
+    if let Move::Small(cl1, cl2) = cl {
+      self.do_operand(o, cl1, it);
+      self.do_copy(cl2, it);
+    }
   }
 
   fn before_as, after_as, do_as(self, it, from: IntTy, to: IntTy, cl: As) {
@@ -454,7 +457,8 @@ mk_fold! { <'a>
   ) {
     match (cl, rv) {
       (RValue::Ghost, _) => {}
-      (&RValue::Use(cl), mir::RValue::Use(o)) => self.do_move(o, cl, it),
+      (&RValue::Use(cl), mir::RValue::Use(o) | mir::RValue::Cast(_, o, _)) =>
+        self.do_move(o, cl, it),
       (RValue::Unop(cl1, cl2), mir::RValue::Unop(_, o)) => {
         self.do_operand_reg(o, cl1, it);
         self.do_inst(it);
@@ -706,6 +710,19 @@ mk_fold! { <'a>
             self.do_syscall(MMap, &[None, Some(len), Some(prot), None, Some(fd), None], cl, it),
           (IntrinsicProc::MMapAnon, [len, prot]) =>
             self.do_syscall(MMap, &[None, Some(len), Some(prot), None, None, None], cl, it),
+          (IntrinsicProc::MUnmap, [len, _, p]) =>
+            self.do_syscall(MUnmap, &[Some(p), Some(len)], cl, it),
+          (IntrinsicProc::Brk, [addr]) => self.do_syscall(Brk, &[Some(addr)], cl, it),
+          (IntrinsicProc::Close, [fd]) => self.do_syscall(Close, &[Some(fd)], cl, it),
+          (IntrinsicProc::LSeek, [fd, off, whence]) =>
+            self.do_syscall(LSeek, &[Some(fd), Some(off), Some(whence)], cl, it),
+          (IntrinsicProc::Pipe, [_, p]) => self.do_syscall(Pipe, &[Some(p)], cl, it),
+          (IntrinsicProc::Dup2, [oldfd, newfd]) =>
+            self.do_syscall(Dup2, &[Some(oldfd), Some(newfd)], cl, it),
+          (IntrinsicProc::ClockGetTime, [clock, _, p]) =>
+            self.do_syscall(ClockGetTime, &[Some(clock), Some(p)], cl, it),
+          (IntrinsicProc::GetRandom, [count, _, p]) =>
+            self.do_syscall(GetRandom, &[Some(p), Some(count), None], cl, it),
           _ => unreachable!(),
         }
       }
@@ -301,6 +301,50 @@ make_prims! {
     /// intrinsic proc sys_mmap_anon(len: u64, prot: u32) -> u64;
     /// ```
     MMapAnon: "sys_mmap_anon",
+    /// Intrinsic for the [`munmap`](https://man7.org/linux/man-pages/man2/munmap.2.html)
+    /// system call. The buffer is consumed by the call.
+    /// ```text
+    /// intrinsic proc sys_munmap(len: u64, ghost buf: [u8; len], p: &sn buf) -> u32;
+    /// ```
+    MUnmap: "sys_munmap",
+    /// Intrinsic for the [`brk`](https://man7.org/linux/man-pages/man2/brk.2.html) system call.
+    /// ```text
+    /// intrinsic proc sys_brk(addr: u64) -> u64;
+    /// ```
+    Brk: "sys_brk",
+    /// Intrinsic for the [`close`](https://man7.org/linux/man-pages/man2/close.2.html) system call.
+    /// ```text
+    /// intrinsic proc sys_close(fd: u32) -> u32;
+    /// ```
+    Close: "sys_close",
+    /// Intrinsic for the [`lseek`](https://man7.org/linux/man-pages/man2/lseek.2.html) system call.
+    /// ```text
+    /// intrinsic proc sys_lseek(fd: u32, off: u64, whence: u32) -> u64;
+    /// ```
+    LSeek: "sys_lseek",
+    /// Intrinsic for the [`pipe`](https://man7.org/linux/man-pages/man2/pipe.2.html) system call.
+    /// ```text
+    /// intrinsic proc sys_pipe(ghost mut fds: [u32; 2], p: &sn fds) -> u32;
+    /// ```
+    Pipe: "sys_pipe",
+    /// Intrinsic for the [`dup2`](https://man7.org/linux/man-pages/man2/dup2.2.html) system call.
+    /// ```text
+    /// intrinsic proc sys_dup2(oldfd: u32, newfd: u32) -> u32;
+    /// ```
+    Dup2: "sys_dup2",
+    /// Intrinsic for the
+    /// [`clock_gettime`](https://man7.org/linux/man-pages/man2/clock_gettime.2.html)
+    /// system call.
+    /// ```text
+    /// intrinsic proc sys_clock_gettime(clock: u32, ghost mut buf: Timespec, p: &sn buf) -> u32;
+    /// ```
+    ClockGetTime: "sys_clock_gettime",
+    /// Intrinsic for the [`getrandom`](https://man7.org/linux/man-pages/man2/getrandom.2.html)
+    /// system call.
+    /// ```text
+    /// intrinsic proc sys_getrandom(count: u32, ghost mut buf: [u8; count], p: &sn buf) -> u32;
+    /// ```
+    GetRandom: "sys_getrandom",
   }
 
   /// Intrinsic global variables.
@@ -343,6 +387,14 @@ make_prims! {
     /// }
     /// ```
     Stat: "Stat",
+    /// The buffer filled by `clock_gettime`.
+    /// ```text
+    /// intrinsic struct Timespec {
+    ///   tv_sec: i64,
+    ///   tv_nsec: i64,
+    /// }
+    /// ```
+    Timespec: "Timespec",
   }
 }
 
@@ -284,6 +284,29 @@ pub struct While<'a> {
   pub trivial: Option<bool>,
 }
 
+/// A `map` or `fold` expression, which loops over one or more arrays of length `n` in parallel.
+///
+/// The return type is `(array T n)` for a `map` whose body has type `T`, and the type of the
+/// accumulator for a `fold`.
+#[derive(Debug)]
+#[cfg_attr(feature = "memory", derive(DeepSizeOf))]
+pub struct MapFold<'a> {
+  /// The loop index `i: u64`.
+  pub idx: Spanned<'a, VarId>,
+  /// The hypothesis `h: i < n` in the body.
+  pub hyp: Spanned<'a, VarId>,
+  /// The common length `n` of the arrays.
+  pub n: ty::Expr<'a>,
+  /// An expression which evaluates `n` as a `u64`.
+  pub len: Box<Expr<'a>>,
+  /// For a `fold`, the accumulator and its initial value.
+  pub acc: Option<(Spanned<'a, VarId>, Box<Expr<'a>>)>,
+  /// The arrays, with the variables bound to their elements and the element types.
+  pub arrays: Box<[(Spanned<'a, VarId>, ty::Ty<'a>, Expr<'a>)]>,
+  /// The body of the loop.
+  pub body: Box<Expr<'a>>,
+}
+
 /// Categorizes the way the returns of a call expression are packed.
 #[derive(Debug, Copy, Clone)]
 pub enum ReturnKind {
@@ -633,6 +656,8 @@ pub enum ExprKind<'a> {
   /// A while loop. If `cond` is a pure expression and there are no early `break`s inside the loop,
   /// then the return type is `!cond`; otherwise `()`.
   While(Box<While<'a>>),
+  /// A `map` or `fold` over arrays.
+  MapFold(Box<MapFold<'a>>),
   /// `(unreachable h)` takes a proof of false and undoes the current code path.
   Unreachable(Box<Expr<'a>>),
   /// `(lab e1 ... en)` jumps to label `lab` with `e1 ... en` as arguments.
@@ -840,6 +865,21 @@ impl ExprKind<'_> {
         w.body.debug_indent(i+1, f)?;
         indent(i, f)?; write!(f, "}}")
       }
+      ExprKind::MapFold(mf) => {
+        write!(f, "{} {:?}: {:?} < {:?} (", if mf.acc.is_some() {"fold"} else {"map"},
+          mf.hyp.k, mf.idx.k, mf.n)?;
+        if let Some((x, init)) = &mf.acc {
+          writeln!(f)?; indent(i+1, f)?; write!(f, "{:?} := ", x.k)?;
+          init.k.0.debug_indent(i+1, f)?; write!(f, ",")?;
+        }
+        for (x, _, e) in &*mf.arrays {
+          writeln!(f)?; indent(i+1, f)?; write!(f, "{:?} := ", x.k)?;
+          e.k.0.debug_indent(i+1, f)?; write!(f, ",")?;
+        }
+        writeln!(f, ") {{")?;
+        mf.body.k.0.debug_maybe_block(i+1, f)?;
+        indent(i, f)?; write!(f, "}}")
+      }
       ExprKind::Unreachable(e) => {
         write!(f, "unreachable ")?;
         e.k.0.debug_indent(i, f)
@@ -912,6 +952,8 @@ pub enum ItemKind<'a> {
   Proc {
     /// The type of declaration: `func`, `proc`, or `intrinsic`.
     kind: ProcKind,
+    /// True if calls to this procedure should always be inlined, if possible.
+    inline: bool,
     /// The name of the procedure.
     name: Spanned<'a, Symbol>,
     /// The number of type arguments
@@ -7,7 +7,7 @@ use mm0_util::FileSpan;
 use num::BigInt;
 use smallvec::SmallVec;
 #[cfg(feature = "memory")] use mm0_deepsize_derive::DeepSizeOf;
-use crate::{Symbol, mir_opt::storage::Allocations, u32_as_usize};
+use crate::{Symbol, arith::Certificate, mir_opt::storage::Allocations, u32_as_usize};
 use super::{IntTy, Size, ProofId, LambdaId, IdxVec, Spanned, ast::ProcKind, ast, global, hir,
   super::mir_opt::DominatorTree};
 pub use {ast::TyVarId, hir::{Unop, Binop}};
@@ -697,6 +697,9 @@ impl Contexts {
   #[allow(clippy::len_without_is_empty)]
   #[must_use] pub fn num_buffers(&self) -> usize { self.0.len() }
 
+  /// Add a new context buffer, which must extend a context that already exists.
+  pub fn push_buf(&mut self, buf: CtxBuf) -> CtxBufId { self.0.push(buf) }
+
   /// Given a context ID, retrieve a context buffer, ensuring that it can be directly extended by
   /// allocating a new context buffer if necessary.
   fn unshare(&mut self, id: &'_ mut CtxId) -> &mut CtxBuf {
@@ -1220,6 +1223,12 @@ impl Place {
   #[must_use] pub fn local(local: VarId) -> Self { Self {local, proj: vec![]} }
   /// Push a projection onto a place.
   #[must_use] pub fn proj(mut self, p: (Ty, Projection)) -> Self { self.proj.push(p); self }
+
+  /// Returns true if this place goes through a pointer, so that an assignment to it
+  /// is a write to memory rather than to a local variable.
+  #[must_use] pub fn is_deref(&self) -> bool {
+    self.proj.iter().any(|p| matches!(p.1, Projection::Deref))
+  }
 }
 
 impl From<VarId> for Place {
@@ -1290,6 +1299,11 @@ impl Constant {
     Self { ety: (Some(Rc::new(ExprKind::Unit)), ty), k: ConstKind::Mm0Proof(val) }
   }
 
+  /// Return a proof by linear arithmetic, see [`ConstKind::Linarith`].
+  #[must_use] pub fn linarith(ty: Ty, cert: Rc<Certificate>) -> Self {
+    Self { ety: (Some(Rc::new(ExprKind::Unit)), ty), k: ConstKind::Linarith(cert) }
+  }
+
   /// Return a proof by contradiction: the referenced block adds the negation of
   #[must_use] pub fn contra(ty: Ty, bl: BlockId, v: VarId) -> Self {
     Self { ety: (Some(Rc::new(ExprKind::Unit)), ty), k: ConstKind::Contra(bl, v) }
@@ -1349,6 +1363,9 @@ pub enum ConstKind {
   Sizeof,
   /// A proof embedded from MM0.
   Mm0Proof(ProofId),
+  /// A proof of `(h_1, ..., h_n) -> p` by linear arithmetic, where `p` is an inequality
+  /// and the certificate says how to combine the hypotheses to refute `!p`.
+  Linarith(Rc<Certificate>),
   /// A proof by contradiction: This has type `cond`, where the target block exists in a context
   /// extended by `v: !cond` and ends in a proof of contradiction.
   Contra(BlockId, VarId),
@@ -1367,6 +1384,7 @@ impl std::fmt::Debug for Constant {
       ConstKind::Const(s) => write!(f, "({s}: {:?})", self.ety.1),
       ConstKind::Sizeof => write!(f, "sizeof {:?}", self.ety.1),
       ConstKind::Mm0Proof(p) => p.fmt(f),
+      ConstKind::Linarith(cert) => write!(f, "{cert}"),
       ConstKind::Contra(_, _) => write!(f, "(contra: {:?})", self.ety.1),
       ConstKind::As(c) => write!(f, "({:?} as {:?})", c.0, c.1),
     }
@@ -1659,7 +1677,7 @@ impl Statement {
   #[must_use] pub fn relevant(&self) -> bool {
     match self {
       &Self::Let(_, r, _, _) => r,
-      Self::Assign(_, _, _, vars) => vars.iter().any(|v| v.rel),
+      Self::Assign(lhs, _, _, vars) => lhs.is_deref() || vars.iter().any(|v| v.rel),
       Self::LabelGroup(..) | Self::PopLabelGroup | Self::DominatedBlock(..) => false,
     }
   }
@@ -1870,7 +1888,7 @@ pub(crate) trait Visitor {
   fn visit_stmt(&mut self, s: &Statement) {
     match s {
       Statement::Assign(lhs, _, rhs, vars) => {
-        let mut needed = false;
+        let mut needed = lhs.is_deref();
         for r in &**vars {
           if r.rel { needed = true; self.visit_var(r.from) }
         }
@@ -2088,6 +2106,8 @@ pub struct Proc {
   pub kind: ProcKind,
   /// The name of the procedure.
   pub name: Spanned<Symbol>,
+  /// True if calls to this procedure should always be inlined, if possible.
+  pub inline: bool,
   /// The number of type arguments
   pub tyargs: u32,
   /// The arguments of the procedure.
@@ -0,0 +1,553 @@
+//! Differential tests for the code generator.
+//!
+//! Each test builds a small MMC program, runs `main` in the MIR interpreter, then links the
+//! program, writes it out as an ELF file and runs it on the host with a fixed standard input.
+//! The exit status and standard output of the binary must agree with the interpreter and with the
+//! expected result. Programs are only executed on x86-64 Linux; on other hosts only the
+//! interpreter half of the test runs. Each program is also compiled for AArch64, and each test
+//! has an ignored `arm64` variant which runs the AArch64 binary under `qemu-aarch64`
+//! (`cargo test -- --ignored`).
+
+use mmcc::{Compiler, Symbol, intern};
+use mmcc::interp::Outcome;
+use mmcc::types::{Binop, Size, Spanned, VarId, Idx};
+use mmcc::types::ast::{
+  Arg, ArgAttr, ArgKind, Block, Expr, ExprKind, IfKind, Item, ItemKind, MapFold, OutArg, Stmt,
+  StmtKind, TuplePattern, TuplePatternKind, Type, TypeKind};
+use mmcc::types::entity::IntrinsicProc;
+use mmcc::types::hir::ProcKind;
+
+fn bx<T>(k: T) -> Box<Spanned<T>> { Box::new(Spanned::dummy(k)) }
+fn int(n: impl Into<num::BigInt>) -> Expr { Spanned::dummy(ExprKind::Int(n.into())) }
+fn var(v: VarId) -> Expr { Spanned::dummy(ExprKind::Var(v)) }
+fn binop(op: Binop, a: Expr, b: Expr) -> Expr {
+  Spanned::dummy(ExprKind::Binop(op, Box::new(a), Box::new(b)))
+}
+fn cast(e: Expr, ty: Type) -> Expr { Spanned::dummy(ExprKind::As(Box::new(e), Box::new(ty))) }
+fn call(f: Symbol, args: Vec<Expr>) -> Expr {
+  Spanned::dummy(ExprKind::Call { f: Spanned::dummy(f), tys: vec![], args, variant: None })
+}
+
+fn uint(sz: Size) -> Type { Spanned::dummy(TypeKind::UInt(sz)) }
+fn array(ty: Type, n: Expr) -> Type {
+  Spanned::dummy(TypeKind::Array(Box::new(ty), Box::new(n)))
+}
+
+fn pat(name: &str, v: VarId, ty: Type) -> TuplePattern {
+  Spanned::dummy(TuplePatternKind::Typed(
+    bx(TuplePatternKind::Name(false, intern(name), v)), Box::new(ty)))
+}
+fn arg(attr: ArgAttr, name: &str, v: VarId, ty: Type) -> Arg {
+  Spanned::dummy((attr, ArgKind::Lam(pat(name, v, ty).k)))
+}
+fn let_(name: &str, v: VarId, ty: Type, rhs: Expr) -> Stmt {
+  Spanned::dummy(StmtKind::Let { lhs: pat(name, v, ty), rhs })
+}
+fn expr_stmt(e: Expr) -> Stmt { Spanned::dummy(StmtKind::Expr(e.k)) }
+fn assert(e: Expr) -> Stmt { expr_stmt(Spanned::dummy(ExprKind::Assert(Box::new(e)))) }
+
+fn proc(kind: ProcKind, intrinsic: Option<IntrinsicProc>, name: Symbol,
+  args: Vec<Arg>, rets: Vec<TuplePattern>, body: Block,
+) -> Item {
+  Spanned::dummy(ItemKind::Proc {
+    intrinsic,
+    inline: false,
+    kind,
+    name: Spanned::dummy(name),
+    tyargs: 0,
+    args: args.into(),
+    outs: Box::new([]),
+    rets: rets.into(),
+    variant: None,
+    body,
+  })
+}
+
+fn main_proc(stmts: Vec<Stmt>) -> Item {
+  proc(ProcKind::Main, None, intern("main"), vec![], vec![], Block { stmts, expr: None })
+}
+
+/// Declare `intrinsic proc name(fd: u32, count: u32, ghost mut buf: ref [u8; count],
+/// p: &sn buf): u32;` for the `read` and `write` system calls.
+fn add_syscall(compiler: &mut Compiler<()>, name: &str, intrinsic: IntrinsicProc) {
+  let mut fresh = VarId::default();
+  let [fd, count, buf, p, ret] = [(); 5].map(|_| fresh.fresh());
+  let buf_ty = Spanned::dummy(TypeKind::Ref(None, Box::new(array(uint(Size::S8), var(count)))));
+  compiler.add(&proc(ProcKind::Proc, Some(intrinsic), intern(name), vec![
+    arg(ArgAttr::empty(), "fd", fd, uint(Size::S32)),
+    arg(ArgAttr::empty(), "count", count, uint(Size::S32)),
+    arg(ArgAttr::GHOST | ArgAttr::MUT, "buf", buf, buf_ty),
+    arg(ArgAttr::empty(), "p", p, Spanned::dummy(TypeKind::RefSn(Box::new(var(buf))))),
+  ], vec![pat("_", ret, uint(Size::S32))], Block::default()), Default::default(), ()).unwrap();
+}
+
+/// Call `write(1, n, ref v, &v)` where `v: [u8; n]`.
+fn write_var(v: VarId, n: usize) -> Stmt {
+  expr_stmt(call(intern("write"), vec![int(1), int(n), var(v),
+    Spanned::dummy(ExprKind::Borrow(Box::new(var(v))))]))
+}
+
+/// The expected behavior of a test program.
+#[derive(Debug)]
+struct Expect<'a> {
+  /// True if the program should fail an assertion rather than exiting normally.
+  fail: bool,
+  /// The expected contents of standard output.
+  stdout: &'a [u8],
+}
+
+/// The architecture whose binary is run by a test.
+#[derive(Clone, Copy, Debug)]
+enum Target { X86, Arm64 }
+
+/// Define a test `name` with body `body`, which is run once for each [`Target`]: `name::x86`
+/// runs the x86-64 binary on the host, and the ignored test `name::arm64` runs the AArch64
+/// binary under `qemu-aarch64` (`cargo test -- --ignored`).
+macro_rules! differential_test {
+  (fn $name:ident($target:ident) $body:block) => {
+    mod $name {
+      use super::*;
+      fn run($target: Target) $body
+      #[test] fn x86() { run(Target::X86) }
+      #[test] #[ignore = "requires qemu-aarch64"] fn arm64() { run(Target::Arm64) }
+    }
+  }
+}
+
+/// Check that the interpreter and the program compiled for `target` both produce the expected
+/// result. The program is linked for both targets, so that code generation is tested even when
+/// the binary cannot be run.
+fn check(target: Target, name: &str, mut compiler: Compiler<()>, stdin: &[u8],
+  expect: &Expect<'_>,
+) {
+  let (outcome, stdout) = compiler.eval(intern("main"), vec![], stdin)
+    .unwrap_or_else(|e| panic!("{name}: interpreter error: {e}"));
+  assert_eq!(matches!(outcome, Outcome::Fail), expect.fail,
+    "{name}: interpreter returned {outcome:?}, expected {expect:?}");
+  assert_eq!(String::from_utf8_lossy(&stdout), String::from_utf8_lossy(expect.stdout),
+    "{name}: interpreter output differs");
+  let arm64 = compiler.clone().finish_arm64()
+    .unwrap_or_else(|e| panic!("{name}: arm64 linker error: {e:?}"));
+  let x86 = compiler.finish().unwrap_or_else(|e| panic!("{name}: linker error: {e:?}"));
+  let mut elf = Vec::new();
+  match target {
+    Target::X86 => {
+      x86.write_elf(&mut elf).unwrap();
+      run_elf(name, &elf, stdin, expect)
+    }
+    Target::Arm64 => {
+      arm64.write_elf(&mut elf).unwrap();
+      run_elf_qemu(&format!("{name}-arm64"), &elf, stdin, expect)
+    }
+  }
+}
+
+/// Run `cmd` on an ELF file, and check the result.
+#[cfg(target_os = "linux")]
+fn run_elf_with(name: &str, cmd: Option<&str>, elf: &[u8], stdin: &[u8], expect: &Expect<'_>) {
+  use std::io::Write;
+  use std::os::unix::fs::PermissionsExt;
+  use std::os::unix::process::ExitStatusExt;
+  use std::process::{Command, Stdio};
+  const SIGILL: i32 = 4;
+
+  let path = std::env::temp_dir().join(format!("mmcc-run-{name}-{}", std::process::id()));
+  std::fs::write(&path, elf).unwrap();
+  std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+  let mut cmd = match cmd {
+    Some(cmd) => { let mut c = Command::new(cmd); c.arg(&path); c }
+    None => Command::new(&path),
+  };
+  let mut child = cmd
+    .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::null())
+    .spawn().unwrap_or_else(|e| panic!("{name}: failed to run {}: {e}", path.display()));
+  // The program may exit without reading its input, so a broken pipe is not an error
+  drop(child.stdin.take().expect("piped").write_all(stdin));
+  let out = child.wait_with_output().unwrap();
+  std::fs::remove_file(&path).unwrap();
+  if expect.fail {
+    assert_eq!(out.status.signal(), Some(SIGILL), "{name}: expected an assertion failure");
+  } else {
+    assert_eq!(out.status.code(), Some(0), "{name}: expected a normal exit");
+  }
+  assert_eq!(String::from_utf8_lossy(&out.stdout), String::from_utf8_lossy(expect.stdout),
+    "{name}: program output differs");
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn run_elf(name: &str, elf: &[u8], stdin: &[u8], expect: &Expect<'_>) {
+  run_elf_with(name, None, elf, stdin, expect)
+}
+
+#[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+fn run_elf(_: &str, _: &[u8], _: &[u8], _: &Expect<'_>) {}
+
+#[cfg(target_os = "linux")]
+fn run_elf_qemu(name: &str, elf: &[u8], stdin: &[u8], expect: &Expect<'_>) {
+  const QEMU: &str = "qemu-aarch64";
+  let found = std::process::Command::new(QEMU).arg("--version")
+    .stdout(std::process::Stdio::null()).status().is_ok_and(|s| s.success());
+  assert!(found, "{name}: {QEMU} is required to run the arm64 tests");
+  run_elf_with(name, Some(QEMU), elf, stdin, expect)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn run_elf_qemu(name: &str, _: &[u8], _: &[u8], _: &Expect<'_>) {
+  panic!("{name}: the arm64 tests can only be run on linux")
+}
+
+const OK: Expect<'static> = Expect { fail: false, stdout: b"" };
+
+differential_test! { fn trivial(target) {
+  let mut compiler = Compiler::new(());
+  compiler.add(&main_proc(vec![]), Default::default(), ()).unwrap();
+  check(target, "trivial", compiler, b"", &OK);
+}}
+
+differential_test! { fn assert_ok(target) {
+  // main() { assert((2 + 2: u8) = 4); }
+  let mut compiler = Compiler::new(());
+  let sum = Spanned::dummy(ExprKind::Typed(Box::new(binop(Binop::Add, int(2), int(2))),
+    Box::new(uint(Size::S8))));
+  compiler.add(&main_proc(vec![assert(binop(Binop::Eq, sum, int(4)))]),
+    Default::default(), ()).unwrap();
+  check(target, "assert_ok", compiler, b"", &OK);
+}}
+
+differential_test! { fn assert_fail(target) {
+  // main() { let x: u8 = 200; assert((x + x) as u8 = x); }
+  let mut compiler = Compiler::new(());
+  let x = VarId::from_usize(0);
+  let sum = cast(binop(Binop::Add, var(x), var(x)), uint(Size::S8));
+  compiler.add(&main_proc(vec![
+    let_("x", x, uint(Size::S8), int(200)),
+    assert(binop(Binop::Eq, sum, var(x))),
+  ]), Default::default(), ()).unwrap();
+  check(target, "assert_fail", compiler, b"", &Expect { fail: true, stdout: b"" });
+}}
+
+differential_test! { fn hello_world(target) {
+  // main() { let hello: [u8; 12] = "hello world\n"; write(1, 12, ref hello, &hello); }
+  let mut compiler = Compiler::new(());
+  add_syscall(&mut compiler, "write", IntrinsicProc::Write);
+  let msg = b"hello world\n";
+  let v = VarId::from_usize(0);
+  compiler.add(&main_proc(vec![
+    let_("hello", v, array(uint(Size::S8), int(msg.len())),
+      Spanned::dummy(ExprKind::List(msg.iter().map(|&c| int(c)).collect()))),
+    write_var(v, msg.len()),
+  ]), Default::default(), ()).unwrap();
+  check(target, "hello_world", compiler, b"", &Expect { fail: false, stdout: msg });
+}}
+
+differential_test! { fn echo(target) {
+  // main() { let buf: ?[u8; 4] = uninit; read(0, 4, ref buf, &buf); write(1, 4, ref buf, &buf); }
+  let mut compiler = Compiler::new(());
+  add_syscall(&mut compiler, "read", IntrinsicProc::Read);
+  add_syscall(&mut compiler, "write", IntrinsicProc::Write);
+  let buf = VarId::from_usize(0);
+  compiler.add(&main_proc(vec![
+    let_("buf", buf, Spanned::dummy(TypeKind::Uninit(Box::new(array(uint(Size::S8), int(4))))),
+      Spanned::dummy(ExprKind::Uninit)),
+    expr_stmt(call(intern("read"), vec![int(0), int(4), var(buf),
+      Spanned::dummy(ExprKind::Borrow(Box::new(var(buf))))])),
+    write_var(buf, 4),
+  ]), Default::default(), ()).unwrap();
+  check(target, "echo", compiler, b"abcdefg", &Expect { fail: false, stdout: b"abcd" });
+}}
+
+differential_test! { fn echo_large(target) {
+  // main() { let buf: ?[u8; 16] = uninit; read(0, 16, ref buf, &buf); write(1, 16, ref buf, &buf); }
+  // `buf` does not fit in a register, so its initialization is a large move, which must not
+  // generate any code when the value is `uninit`.
+  let mut compiler = Compiler::new(());
+  add_syscall(&mut compiler, "read", IntrinsicProc::Read);
+  add_syscall(&mut compiler, "write", IntrinsicProc::Write);
+  let buf = VarId::from_usize(0);
+  compiler.add(&main_proc(vec![
+    let_("buf", buf, Spanned::dummy(TypeKind::Uninit(Box::new(array(uint(Size::S8), int(16))))),
+      Spanned::dummy(ExprKind::Uninit)),
+    expr_stmt(call(intern("read"), vec![int(0), int(16), var(buf),
+      Spanned::dummy(ExprKind::Borrow(Box::new(var(buf))))])),
+    write_var(buf, 16),
+  ]), Default::default(), ()).unwrap();
+  check(target, "echo_large", compiler, b"0123456789abcdefghij",
+    &Expect { fail: false, stdout: b"0123456789abcdef" });
+}}
+
+differential_test! { fn call_if(target) {
+  // proc larger(a: u8, b: u8): u8 := if a < b { b } else { a };
+  // main() {
+  //   let x: u8 = larger(3, 7); let y: u8 = larger(9, x);
+  //   let out: [u8; 2] = [(x + 48) as u8, (y + 48) as u8];
+  //   write(1, 2, ref out, &out);
+  // }
+  let mut compiler = Compiler::new(());
+  add_syscall(&mut compiler, "write", IntrinsicProc::Write);
+  let larger = intern("larger");
+  let mut fresh = VarId::default();
+  let [a, b, ret] = [(); 3].map(|_| fresh.fresh());
+  let body = Spanned::dummy(ExprKind::If {
+    ik: IfKind::If,
+    hyp: None,
+    cond: Box::new(binop(Binop::Lt, var(a), var(b))),
+    then: Box::new(var(b)),
+    els: Box::new(var(a)),
+  });
+  compiler.add(&proc(ProcKind::Proc, None, larger, vec![
+    arg(ArgAttr::empty(), "a", a, uint(Size::S8)),
+    arg(ArgAttr::empty(), "b", b, uint(Size::S8)),
+  ], vec![pat("_", ret, uint(Size::S8))], Block { stmts: vec![], expr: Some(Box::new(body)) }),
+    Default::default(), ()).unwrap();
+
+  let mut fresh = VarId::default();
+  let [x, y, out] = [(); 3].map(|_| fresh.fresh());
+  let digit = |v| cast(binop(Binop::Add, var(v), int(48)), uint(Size::S8));
+  compiler.add(&main_proc(vec![
+    let_("x", x, uint(Size::S8), call(larger, vec![int(3), int(7)])),
+    let_("y", y, uint(Size::S8), call(larger, vec![int(9), var(x)])),
+    let_("out", out, array(uint(Size::S8), int(2)),
+      Spanned::dummy(ExprKind::List(vec![digit(x), digit(y)]))),
+    write_var(out, 2),
+  ]), Default::default(), ()).unwrap();
+  check(target, "call_if", compiler, b"", &Expect { fail: false, stdout: b"79" });
+}}
+
+differential_test! { fn ghost_out_param(target) {
+  // proc inc(ghost mut g: ref u8, x: u8): u8 := (x + 1) as u8;
+  // main() {
+  //   let g: u8 = 0; let y: u8 = inc(g, 3);
+  //   let out: [u8; 1] = [(y + 48) as u8]; write(1, 1, ref out, &out);
+  // }
+  // The `mut` argument has an implicit `out` return, which is ghost and
+  // must not be part of the return ABI of `inc`
+  let mut compiler = Compiler::new(());
+  add_syscall(&mut compiler, "write", IntrinsicProc::Write);
+  let inc = intern("inc");
+  let mut fresh = VarId::default();
+  let [g, x, g_out, ret] = [(); 4].map(|_| fresh.fresh());
+  let mut item = proc(ProcKind::Proc, None, inc, vec![
+    arg(ArgAttr::GHOST | ArgAttr::MUT, "g", g,
+      Spanned::dummy(TypeKind::Ref(None, Box::new(uint(Size::S8))))),
+    arg(ArgAttr::empty(), "x", x, uint(Size::S8)),
+  ], vec![pat("_", ret, uint(Size::S8))], Block {
+    stmts: vec![],
+    expr: Some(Box::new(cast(binop(Binop::Add, var(x), int(1)), uint(Size::S8)))),
+  });
+  let ItemKind::Proc { outs, .. } = &mut item.k else { unreachable!() };
+  *outs = Box::new([OutArg { input: 0, name: Spanned::dummy(intern("g")), var: g_out, ty: None }]);
+  let names = ["g", "x", "g", "_"].into_iter().map(|s| Spanned::dummy(intern(s))).collect();
+  compiler.add(&item, names, ()).unwrap();
+
+  let mut fresh = VarId::default();
+  let [g, y, out] = [(); 3].map(|_| fresh.fresh());
+  compiler.add(&main_proc(vec![
+    let_("g", g, uint(Size::S8), int(0)),
+    let_("y", y, uint(Size::S8), call(inc, vec![var(g), int(3)])),
+    let_("out", out, array(uint(Size::S8), int(1)), Spanned::dummy(ExprKind::List(vec![
+      cast(binop(Binop::Add, var(y), int(48)), uint(Size::S8))]))),
+    write_var(out, 1),
+  ]), ["g", "y", "out"].into_iter().map(|s| Spanned::dummy(intern(s))).collect(), ()).unwrap();
+  check(target, "ghost_out_param", compiler, b"", &Expect { fail: false, stdout: b"4" });
+}}
+
+differential_test! { fn bounds_check(target) {
+  // proc get(k: u8): u8 := {
+  //   let msg: [u8; 4] = "abc\n";
+  //   if k < 3 { msg[k] } else { msg[3: u8] }
+  // };
+  // main() { let out: [u8; 3] = [get(0), get(2), get(9)]; write(1, 3, ref out, &out); }
+  let mut compiler = Compiler::new(());
+  add_syscall(&mut compiler, "write", IntrinsicProc::Write);
+  let get = intern("get");
+  let mut fresh = VarId::default();
+  let [k, msg, ret] = [(); 3].map(|_| fresh.fresh());
+  let index = |i: Expr| Spanned::dummy(ExprKind::Index(Box::new(var(msg)), Box::new(i), None));
+  let body = Spanned::dummy(ExprKind::If {
+    ik: IfKind::If,
+    hyp: None,
+    cond: Box::new(binop(Binop::Lt, var(k), int(3))),
+    then: Box::new(index(var(k))),
+    els: Box::new(index(Spanned::dummy(ExprKind::Typed(Box::new(int(3)), Box::new(uint(Size::S8)))))),
+  });
+  compiler.add(&proc(ProcKind::Proc, None, get, vec![
+    arg(ArgAttr::empty(), "k", k, uint(Size::S8)),
+  ], vec![pat("_", ret, uint(Size::S8))], Block {
+    stmts: vec![let_("msg", msg, array(uint(Size::S8), int(4)),
+      Spanned::dummy(ExprKind::List(b"abc\n".iter().map(|&c| int(c)).collect())))],
+    expr: Some(Box::new(body)),
+  }), ["k", "msg", "_"].into_iter().map(|s| Spanned::dummy(intern(s))).collect(), ()).unwrap();
+
+  let out = VarId::from_usize(0);
+  compiler.add(&main_proc(vec![
+    let_("out", out, array(uint(Size::S8), int(3)),
+      Spanned::dummy(ExprKind::List([0, 2, 9].into_iter().map(|n| call(get, vec![int(n)])).collect()))),
+    write_var(out, 3),
+  ]), Default::default(), ()).unwrap();
+  // Every index is in bounds by the `if` condition, but this is not proved automatically,
+  // so the accesses are still checked at runtime
+  assert!(format!("{compiler:?}").contains("assert "), "missing runtime bounds check");
+  check(target, "bounds_check", compiler, b"", &Expect { fail: false, stdout: b"ac\n" });
+}}
+
+differential_test! { fn map_fold(target) {
+  // main() {
+  //   let msg: [u8; 4] = "abc\n"; let key: [u8; 4] = [1, 1, 2, 0];
+  //   let out: [u8; 4] = map x in msg, k in key { (x + k) as u8 };
+  //   let sum: u8 = fold s = (0: u8), k in key { (s + k) as u8 };
+  //   let out2: [u8; 4] = map k in key { (k + sum + 48) as u8 };
+  //   write(1, 4, ref out, &out); write(1, 4, ref out2, &out2);
+  // }
+  let mut compiler = Compiler::new(());
+  add_syscall(&mut compiler, "write", IntrinsicProc::Write);
+  let names = ["msg", "key", "out", "sum", "out2", "x", "k", "s", "k", "k"];
+  let mut fresh = VarId::default();
+  let [msg, key, out, sum, out2, x, k1, s, k2, k3] = [(); 10].map(|_| fresh.fresh());
+  let u8_ = || uint(Size::S8);
+  let add = |a, b| cast(binop(Binop::Add, a, b), u8_());
+  let map_fold = |acc, arrays, body| Spanned::dummy(ExprKind::MapFold(Box::new(MapFold {
+    idx: None, hyp: None, acc, arrays, body: Box::new(body)
+  })));
+  let bytes = |s: &[u8]| Spanned::dummy(ExprKind::List(s.iter().map(|&c| int(c)).collect()));
+  compiler.add(&main_proc(vec![
+    let_("msg", msg, array(u8_(), int(4)), bytes(b"abc\n")),
+    let_("key", key, array(u8_(), int(4)), bytes(&[1, 1, 2, 0])),
+    let_("out", out, array(u8_(), int(4)), map_fold(None,
+      Box::new([(Spanned::dummy(x), var(msg)), (Spanned::dummy(k1), var(key))]),
+      add(var(x), var(k1)))),
+    let_("sum", sum, u8_(), map_fold(
+      Some((Spanned::dummy(s), Box::new(Spanned::dummy(
+        ExprKind::Typed(Box::new(int(0)), Box::new(u8_())))))),
+      Box::new([(Spanned::dummy(k2), var(key))]),
+      add(var(s), var(k2)))),
+    let_("out2", out2, array(u8_(), int(4)), map_fold(None,
+      Box::new([(Spanned::dummy(k3), var(key))]),
+      add(add(var(k3), var(sum)), int(48)))),
+    write_var(out, 4),
+    write_var(out2, 4),
+  // The variable names are needed here, because the typechecker introduces fresh variables
+  // for the loop indexes
+  ]), names.into_iter().map(|s| Spanned::dummy(intern(s))).collect(), ()).unwrap();
+  check(target, "map_fold", compiler, b"", &Expect { fail: false, stdout: b"bce\n5564" });
+}}
+
+differential_test! { fn recompile(target) {
+  // proc digit(): u8 := c;
+  // main() { let out: [u8; 1] = [digit()]; write(1, 1, ref out, &out); }
+  // Each program is compiled in a copy of the same compiler, so they share the item cache, and
+  // `main` has to be recompiled whenever `digit` changes.
+  let mut base = Compiler::new(());
+  add_syscall(&mut base, "write", IntrinsicProc::Write);
+  let digit = intern("digit");
+  // `digit` is placed at source position `at`
+  let program = |c: u8, at: usize| {
+    let mut compiler = base.clone();
+    let ret = VarId::from_usize(0);
+    let mut item = proc(ProcKind::Proc, None, digit, vec![], vec![pat("_", ret, uint(Size::S8))],
+      Block { stmts: vec![], expr: Some(Box::new(int(c))) });
+    item.span.span = (at..at + 20).into();
+    compiler.add(&item, Default::default(), ()).unwrap();
+    let out = VarId::from_usize(0);
+    compiler.add(&main_proc(vec![
+      let_("out", out, array(uint(Size::S8), int(1)),
+        Spanned::dummy(ExprKind::List(vec![call(digit, vec![])]))),
+      write_var(out, 1),
+    ]), Default::default(), ()).unwrap();
+    compiler
+  };
+  let run = |name, c: u8, at: usize, hits: usize| {
+    let compiler = program(c, at);
+    assert_eq!(compiler.cache_hits(), hits, "{name}: wrong number of cached procedures");
+    check(target, name, compiler, b"", &Expect { fail: false, stdout: &[c] });
+  };
+  run("recompile_1", b'1', 0, 0);
+  run("recompile_2", b'2', 0, 0);
+  // Both procedures are unchanged, so neither is typechecked again
+  run("recompile_2_cached", b'2', 0, 2);
+  // Moving a procedure does not change it
+  run("recompile_2_moved", b'2', 100, 2);
+  // Only the latest version of `digit` is kept
+  run("recompile_1_again", b'1', 100, 0);
+}}
+
+differential_test! { fn dup2_close(target) {
+  // intrinsic proc dup2(oldfd: u32, newfd: u32): u32;
+  // intrinsic proc close(fd: u32): u32;
+  // main() {
+  //   let hello: [u8; 6] = "hello\n";
+  //   dup2(1, 5); write(5, 6, ref hello, &hello); close(5);
+  //   let r: u32 = close(5); assert(r = -EBADF as u32);
+  // }
+  let mut compiler = Compiler::new(());
+  add_syscall(&mut compiler, "write", IntrinsicProc::Write);
+  let mut fresh = VarId::default();
+  let [oldfd, newfd, ret] = [(); 3].map(|_| fresh.fresh());
+  compiler.add(&proc(ProcKind::Proc, Some(IntrinsicProc::Dup2), intern("dup2"), vec![
+    arg(ArgAttr::empty(), "oldfd", oldfd, uint(Size::S32)),
+    arg(ArgAttr::empty(), "newfd", newfd, uint(Size::S32)),
+  ], vec![pat("_", ret, uint(Size::S32))], Block::default()), Default::default(), ()).unwrap();
+  let mut fresh = VarId::default();
+  let [fd, ret] = [(); 2].map(|_| fresh.fresh());
+  compiler.add(&proc(ProcKind::Proc, Some(IntrinsicProc::Close), intern("close"), vec![
+    arg(ArgAttr::empty(), "fd", fd, uint(Size::S32)),
+  ], vec![pat("_", ret, uint(Size::S32))], Block::default()), Default::default(), ()).unwrap();
+  let msg = b"hello\n";
+  let mut fresh = VarId::default();
+  let [hello, r] = [(); 2].map(|_| fresh.fresh());
+  let write = call(intern("write"), vec![int(5), int(msg.len()), var(hello),
+    Spanned::dummy(ExprKind::Borrow(Box::new(var(hello))))]);
+  compiler.add(&main_proc(vec![
+    let_("hello", hello, array(uint(Size::S8), int(msg.len())),
+      Spanned::dummy(ExprKind::List(msg.iter().map(|&c| int(c)).collect()))),
+    expr_stmt(call(intern("dup2"), vec![int(1), int(5)])),
+    expr_stmt(write),
+    expr_stmt(call(intern("close"), vec![int(5)])),
+    let_("r", r, uint(Size::S32), call(intern("close"), vec![int(5)])),
+    assert(binop(Binop::Eq, var(r), int(u32::MAX - 8))),
+  ]), Default::default(), ()).unwrap();
+  check(target, "dup2_close", compiler, b"", &Expect { fail: false, stdout: msg });
+}}
+
+differential_test! { fn large_frame(target) {
+  // proc larger(a: u8, b: u8): u8 := if a < b { b } else { a };
+  // main() {
+  //   let big: ?[u8; 40000] = uninit; read(0, 40000, ref big, &big);
+  //   let x: u8 = larger(3, 7); let out: [u8; 1] = [(x + 48) as u8];
+  //   write(1, 1, ref out, &out);
+  // }
+  // The saved link register is above `big` in the stack frame, at an offset too large for
+  // the immediate of an AArch64 load or store.
+  let mut compiler = Compiler::new(());
+  add_syscall(&mut compiler, "read", IntrinsicProc::Read);
+  add_syscall(&mut compiler, "write", IntrinsicProc::Write);
+  let larger = intern("larger");
+  let mut fresh = VarId::default();
+  let [a, b, ret] = [(); 3].map(|_| fresh.fresh());
+  let body = Spanned::dummy(ExprKind::If {
+    ik: IfKind::If,
+    hyp: None,
+    cond: Box::new(binop(Binop::Lt, var(a), var(b))),
+    then: Box::new(var(b)),
+    els: Box::new(var(a)),
+  });
+  compiler.add(&proc(ProcKind::Proc, None, larger, vec![
+    arg(ArgAttr::empty(), "a", a, uint(Size::S8)),
+    arg(ArgAttr::empty(), "b", b, uint(Size::S8)),
+  ], vec![pat("_", ret, uint(Size::S8))], Block { stmts: vec![], expr: Some(Box::new(body)) }),
+    Default::default(), ()).unwrap();
+
+  let n = 40000;
+  let mut fresh = VarId::default();
+  let [big, x, out] = [(); 3].map(|_| fresh.fresh());
+  compiler.add(&main_proc(vec![
+    let_("big", big, Spanned::dummy(TypeKind::Uninit(Box::new(array(uint(Size::S8), int(n))))),
+      Spanned::dummy(ExprKind::Uninit)),
+    expr_stmt(call(intern("read"), vec![int(0), int(n), var(big),
+      Spanned::dummy(ExprKind::Borrow(Box::new(var(big))))])),
+    let_("x", x, uint(Size::S8), call(larger, vec![int(3), int(7)])),
+    let_("out", out, array(uint(Size::S8), int(1)), Spanned::dummy(ExprKind::List(vec![
+      cast(binop(Binop::Add, var(x), int(48)), uint(Size::S8))]))),
+    write_var(out, 1),
+  ]), Default::default(), ()).unwrap();
+  check(target, "large_frame", compiler, b"abc", &Expect { fail: false, stdout: b"7" });
+}}
@@ -0,0 +1,156 @@
+//! Tests for the type errors reported by the typechecker.
+
+use mmcc::{Compiler, CtxPrint, FileSpan, ItemContext, intern};
+use mmcc::infer::TypeError;
+use mmcc::types::{Size, Spanned, VarId, Idx, hir};
+use mmcc::types::ast::{
+  Arg, ArgAttr, ArgKind, Block, Expr, ExprKind, Item, ItemKind, Stmt, StmtKind,
+  TuplePattern, TuplePatternKind, Type, TypeKind};
+use mmcc::types::hir::ProcKind;
+use mmcc::types::ast::{IfKind, LabelId};
+
+/// A span that identifies a location in the test program.
+fn at(n: usize) -> FileSpan { FileSpan { span: (n..n + 1).into(), ..FileSpan::default() } }
+fn uint(sz: Size) -> Type { Spanned::dummy(TypeKind::UInt(sz)) }
+fn own_u8() -> Type { Spanned::dummy(TypeKind::Own(Box::new(uint(Size::S8)))) }
+fn pat(name: &str, v: usize, ty: Type) -> TuplePattern {
+  let name = TuplePatternKind::Name(false, intern(name), VarId::from_usize(v));
+  Spanned::dummy(TuplePatternKind::Typed(Box::new(Spanned::dummy(name)), Box::new(ty)))
+}
+fn arg(name: &str, v: usize, ty: Type) -> Arg {
+  Spanned::dummy((ArgAttr::empty(), ArgKind::Lam(pat(name, v, ty).k)))
+}
+fn let_(sp: usize, name: &str, v: usize, ty: Type, rhs: Expr) -> Stmt {
+  Spanned { span: at(sp), k: StmtKind::Let { lhs: pat(name, v, ty), rhs } }
+}
+fn proc(name: &str, args: Vec<Arg>, stmts: Vec<Stmt>) -> Item {
+  Spanned::dummy(ItemKind::Proc {
+    intrinsic: None,
+    inline: false,
+    kind: ProcKind::Proc,
+    name: Spanned::dummy(intern(name)),
+    tyargs: 0,
+    args: args.into(),
+    outs: Box::new([]),
+    rets: Box::new([]),
+    variant: None,
+    body: Block { stmts, expr: None },
+  })
+}
+
+/// A type error as it would be shown to the user: the location and message, and the related
+/// locations with their messages.
+type Reported = (FileSpan, String, Vec<(FileSpan, String)>);
+
+/// Collects the errors from [`Compiler::add`].
+struct Errors<'a>(&'a mut Vec<Reported>);
+
+impl ItemContext<()> for Errors<'_> {
+  type Printer = ();
+  fn print(&mut self) {}
+
+  fn emit_type_errors<'a>(&mut self, _: &mut (),
+    errs: Vec<hir::Spanned<'a, TypeError<'a>>>,
+    pr: &impl mmcc::DisplayCtx<'a>,
+  ) -> Result<(), std::convert::Infallible> {
+    self.0.extend(errs.into_iter().map(|err| {
+      let related = err.k.related().into_iter()
+        .map(|(sp, r)| (sp, format!("{}", CtxPrint(pr, &r)))).collect();
+      (err.span.clone(), format!("{}", CtxPrint(pr, &err.k)), related)
+    }));
+    Ok(())
+  }
+}
+
+/// Typecheck `item`, whose variables are named `names`, and return the errors.
+fn typeck(compiler: &mut Compiler<()>, item: &Item, names: &[&str]) -> Vec<Reported> {
+  let mut errs = vec![];
+  compiler.add(item, names.iter().map(|s| Spanned::dummy(intern(s))).collect(), Errors(&mut errs))
+    .unwrap();
+  errs
+}
+
+#[test] fn related_spans() {
+  // proc take(x: own u8) {}
+  // proc test(x: own u8, k: u8) {
+  //   k <- 1;             -- 10
+  //   take(x);            -- 20
+  //   let a: bool = x;    -- 30: x was consumed at 20
+  //   let b: bool = k;    -- 40: k was assigned at 10
+  // }
+  let mut compiler = Compiler::new(());
+  let take = proc("take", vec![arg("x", 0, own_u8())], vec![]);
+  assert!(typeck(&mut compiler, &take, &["x"]).is_empty());
+  let bool_ty = || Spanned::dummy(TypeKind::Bool);
+  let [x, k, k_old] = [0, 1, 2].map(VarId::from_usize);
+  let test = proc("test", vec![arg("x", 0, own_u8()), arg("k", 1, uint(Size::S8))], vec![
+    Spanned { span: at(10), k: StmtKind::Expr(ExprKind::Assign {
+      lhs: Box::new(Spanned { span: at(10), k: ExprKind::Var(k) }),
+      rhs: Box::new(Spanned::dummy(ExprKind::Int(1.into()))),
+      oldmap: Box::new([(Spanned::dummy(k), Spanned::dummy(k_old))]),
+    }) },
+    Spanned { span: at(20), k: StmtKind::Expr(ExprKind::Call {
+      f: Spanned::dummy(intern("take")), tys: vec![], variant: None,
+      args: vec![Spanned { span: at(20), k: ExprKind::Var(x) }],
+    }) },
+    let_(30, "a", 3, bool_ty(), Spanned { span: at(30), k: ExprKind::Var(x) }),
+    let_(40, "b", 4, bool_ty(), Spanned { span: at(40), k: ExprKind::Var(k) }),
+  ]);
+  let errs = typeck(&mut compiler, &test, &["x", "k", "k_old", "a", "b"]);
+  let related = |sp: usize| {
+    let (_, _, rel) = errs.iter().find(|e| e.0 == at(sp))
+      .unwrap_or_else(|| panic!("no error at {sp}: {errs:#?}"));
+    rel.iter().map(|(sp, msg)| (sp.span.start, &**msg)).collect::<Vec<_>>()
+  };
+  assert_eq!(related(30), [(20, "x was consumed here")]);
+  assert_eq!(related(40), [(10, "k was last assigned here")]);
+}
+
+#[test] fn use_after_move() {
+  // proc take(x: own u8) {}
+  // proc test(x: own u8, y: own u8, z: own u8, b: bool) {
+  //   take(x);            -- 10
+  //   take(x);            -- 20: x was consumed at 10
+  //   if b { take(y) }    -- 30
+  //   take(y);            -- 40: y was consumed at 30
+  //   while b { take(z) } -- 50: z is moved again in the next iteration
+  // }
+  let mut compiler = Compiler::new(());
+  let take = proc("take", vec![arg("x", 0, own_u8())], vec![]);
+  assert!(typeck(&mut compiler, &take, &["x"]).is_empty());
+  let [x, y, z, b, label] = [0, 1, 2, 3, 4].map(VarId::from_usize);
+  let call = |sp: usize, v| Spanned { span: at(sp), k: ExprKind::Call {
+    f: Spanned::dummy(intern("take")), tys: vec![], variant: None,
+    args: vec![Spanned { span: at(sp), k: ExprKind::Var(v) }],
+  } };
+  let body = Block {
+    stmts: vec![Spanned { span: at(50), k: StmtKind::Expr(call(50, z).k) }],
+    expr: Some(Box::new(Spanned::dummy(ExprKind::Jump(LabelId(label, 0), vec![], None)))),
+  };
+  let cond = || Box::new(Spanned::dummy(ExprKind::Var(b)));
+  let test = proc("test", vec![
+    arg("x", 0, own_u8()), arg("y", 1, own_u8()), arg("z", 2, own_u8()),
+    arg("b", 3, Spanned::dummy(TypeKind::Bool)),
+  ], vec![
+    Spanned { span: at(10), k: StmtKind::Expr(call(10, x).k) },
+    Spanned { span: at(20), k: StmtKind::Expr(call(20, x).k) },
+    Spanned { span: at(30), k: StmtKind::Expr(ExprKind::If {
+      ik: IfKind::If, hyp: None, cond: cond(),
+      then: Box::new(call(30, y)),
+      els: Box::new(Spanned::dummy(ExprKind::Unit)),
+    }) },
+    Spanned { span: at(40), k: StmtKind::Expr(call(40, y).k) },
+    Spanned { span: at(50), k: StmtKind::Expr(ExprKind::While {
+      label, muts: Box::new([]), hyp: None, cond: cond(), var: None,
+      body: Box::new(body), has_break: false,
+    }) },
+  ]);
+  let errs = typeck(&mut compiler, &test, &["x", "y", "z", "b", "loop"]);
+  let errs = errs.iter().map(|(sp, msg, rel)| (sp.span.start, &**msg,
+    rel.iter().map(|(sp, msg)| (sp.span.start, &**msg)).collect::<Vec<_>>())).collect::<Vec<_>>();
+  assert_eq!(errs, [
+    (20, "Use of moved value x", vec![(10, "x was consumed here")]),
+    (40, "Use of moved value y", vec![(30, "y was consumed here")]),
+    (50, "Value z from outside the loop is moved in the loop body", vec![]),
+  ]);
+}
@@ -21,8 +21,10 @@ use annotate_snippets::{
 use once_cell::sync::Lazy;
 use typed_arena::Arena;
 use mm1_parser::{parse, ErrorLevel, ParseError};
-use crate::elab::{ElabError, ElabErrorKind, ElabResult, ElaborateBuilder};
-use crate::{ArcList, FileRef, FileSpan, FrozenEnv, LinedString, MutexExt, Position, Range, Span};
+use crate::elab::{DebugHook, ElabError, ElabErrorKind, ElabResult, ElaborateBuilder,
+  inout::OutputError};
+use crate::{ArcList, BoxError, FileRef, FileSpan, FrozenEnv, LinedString, MutexExt, Position,
+  Range, Span};
 use crate::mmb::import::elab as mmb_elab;
 use crate::mmu::import::elab as mmu_elab;
 use crate::mmb::export::Exporter as MmbExporter;
@@ -34,6 +36,9 @@ static POOL: Lazy<ThreadPool> = Lazy::new(|| ThreadPool::new().expect("could not
 static VFS: Lazy<Vfs> = Lazy::new(|| Vfs(Mutex::new(HashMap::new())));
 
 static QUIET: AtomicBool = AtomicBool::new(false);
+/// Set when stdout is reserved for a protocol (as in `mm0-rs debug`), in which case
+/// diagnostics are not printed, and must be read from the elaboration result instead.
+static SILENT: AtomicBool = AtomicBool::new(false);
 static MAX_EMITTED_ERROR: AtomicU8 = AtomicU8::new(0);
 
 /// The cached [`Environment`](crate::elab::Environment) representing a
@@ -338,6 +343,28 @@ fn log_msg(#[allow(unused_mut)] mut s: String) {
   println!("{s}")
 }
 
+/// Print a list of elaboration errors for the file `path` with contents `text`,
+/// and record the maximum error level for the final exit status.
+pub(crate) fn report_errors(path: &FileRef, text: &FileContents, errors: &[ElabError]) {
+  fn print(s: Snippet<'_>) {
+    if !SILENT.load(Ordering::Relaxed) { println!("{}\n", DisplayList::from(s)) }
+  }
+  let mut to_range = mk_to_range();
+  let mut level = 0;
+  if let FileContents::Ascii(text) = text {
+    for e in errors {
+      level = level.max(e.level as u8);
+      e.to_snippet(path, text, &mut to_range, print)
+    }
+  } else {
+    for e in errors {
+      level = level.max(e.level as u8);
+      e.to_snippet_no_source(path, e.pos, print)
+    }
+  }
+  MAX_EMITTED_ERROR.fetch_max(level, Ordering::Relaxed);
+}
+
 /// Elaborate a file for an [`Environment`](crate::elab::Environment) result.
 ///
 /// This is the main elaboration function, as an `async fn`. Given a `path`,
@@ -355,8 +382,13 @@ fn log_msg(#[allow(unused_mut)] mut s: String) {
 /// which will later be joined when the result is required.
 /// (**Note**: This can result in deadlock if the import graph has a cycle.)
 ///
+/// If `debug_hook` is set, it is attached to the lisp evaluator while elaborating this file
+/// (but not its imports).
+///
 /// [`Ast`]: crate::parser::Ast
-async fn elaborate(path: FileRef, rd: ArcList<FileRef>) -> io::Result<ElabResult<()>> {
+async fn elaborate(path: FileRef, rd: ArcList<FileRef>,
+  debug_hook: Option<DebugHook>,
+) -> io::Result<ElabResult<()>> {
   let (path, file) = VFS.get_or_insert(path)?;
   {
     let mut g = file.parsed.lock().await;
@@ -384,8 +416,10 @@ async fn elaborate(path: FileRef, rd: ArcList<FileRef>) -> io::Result<ElabResult
       let mut level = 0;
       for e in &ast.errors {
         level = level.max(e.level as u8);
-        to_snippet(e, &path, &ast.source,
-          |s| println!("{}", DisplayList::from(s)))
+        if !SILENT.load(Ordering::Relaxed) {
+          to_snippet(e, &path, &ast.source,
+            |s| println!("{}", DisplayList::from(s)))
+        }
       }
       MAX_EMITTED_ERROR.fetch_max(level, Ordering::Relaxed);
     }
@@ -412,27 +446,15 @@ async fn elaborate(path: FileRef, rd: ArcList<FileRef>) -> io::Result<ElabResult
           Ok(recv)
         },
         recv_goal: None,
+        debug_hook,
+        file_reader: None,
       }.elab();
     let (cyc, _, errors, env) = fut.await;
     (cyc, errors, env)
   };
   if !QUIET.load(Ordering::Relaxed) { log_msg(format!("elabbed {path}")) }
   let errors: Option<Arc<[_]>> = if errors.is_empty() { None } else {
-    fn print(s: Snippet<'_>) { println!("{}\n", DisplayList::from(s)) }
-    let mut to_range = mk_to_range();
-    let mut level = 0;
-    if let FileContents::Ascii(text) = &file.text {
-      for e in &errors {
-        level = level.max(e.level as u8);
-        e.to_snippet(&path, text, &mut to_range, print)
-      }
-    } else {
-      for e in &errors {
-        level = level.max(e.level as u8);
-        e.to_snippet_no_source(&path, e.pos, print)
-      }
-    }
-    MAX_EMITTED_ERROR.fetch_max(level, Ordering::Relaxed);
+    report_errors(&path, &file.text, &errors);
     Some(errors.into())
   };
   let res = match cyc {
@@ -460,7 +482,7 @@ async fn elaborate(path: FileRef, rd: ArcList<FileRef>) -> io::Result<ElabResult
 fn elaborate_and_send(path: FileRef, send: FSender<ElabResult<()>>, rd: ArcList<FileRef>) ->
   BoxFuture<'static, ()> {
   async {
-    if let Ok(env) = elaborate(path, rd).await {
+    if let Ok(env) = elaborate(path, rd, None).await {
       drop(send.send(env));
     }
   }.boxed()
@@ -470,13 +492,22 @@ fn elaborate_and_send(path: FileRef, send: FSender<ElabResult<()>>, rd: ArcList<
 /// file contents.
 pub(crate) fn elab_for_result(path: FileRef) -> io::Result<(FileContents, Option<FrozenEnv>)> {
   let (path, file) = VFS.get_or_insert(path)?;
-  let env = match block_on(elaborate(path, Default::default()))? {
+  let env = match block_on(elaborate(path, Default::default(), None))? {
     ElabResult::Ok(_, _, env) => Some(env),
     _ => None
   };
   Ok((file.text.clone(), env))
 }
 
+/// Elaborate a file with a lisp debugger attached, without printing any diagnostics,
+/// and return the elaboration result.
+#[cfg(feature = "server")]
+pub(crate) fn elab_for_debug(path: FileRef, hook: DebugHook) -> io::Result<ElabResult<()>> {
+  SILENT.store(true, Ordering::Relaxed);
+  QUIET.store(true, Ordering::Relaxed);
+  block_on(elaborate(path, Default::default(), Some(hook)))
+}
+
 /// Compile MM1 files into MMB
 #[allow(clippy::struct_excessive_bools)]
 #[derive(clap::Args, Debug)]
@@ -499,6 +530,13 @@ pub struct Args {
   /// Print 'output' commands to a file (use '-' to print to stdout)
   #[clap(short, long = "output", value_name = "FILE")]
   pub output_str: Option<std::ffi::OsString>,
+  /// Check 'input' commands against the contents of a file (usually the final .mm0 file)
+  #[clap(short, long = "input", value_name = "FILE")]
+  pub input_str: Option<std::ffi::OsString>,
+  /// Profile the elaboration, printing a summary and writing folded stacks
+  /// (for flamegraph tools) to a file
+  #[clap(long, value_name = "FILE")]
+  pub profile: Option<std::ffi::OsString>,
   /// Sets the input file (.mm1 or .mm0)
   pub input: String,
   /// Sets the output file (.mmb or .mmu)
@@ -516,22 +554,37 @@ impl Args {
   /// - `out.mmb` (or `out.mmu`) is the MMB file to generate, if the elaboration is
   ///   successful. The file extension is used to determine if we are outputting
   ///   binary. If this argument is omitted, the input is only elaborated.
+  /// - `--profile <out.folded>` records the time spent in each declaration, lisp procedure and
+  ///   `refine` call, prints the entries with the most self time, and writes the time spent in
+  ///   each stack of calls to `out.folded`, in a format that can be read by flamegraph tools.
   pub fn main(self) -> io::Result<()> {
     let path: FileRef = fs::canonicalize(self.input)?.into();
-    QUIET.store(self.quiet, Ordering::Relaxed);
+    set_quiet(self.quiet);
     let (file, env) = elab_for_result(path.clone())?;
+    if let Some(out) = self.profile {
+      let data = crate::elab::profile::take_profile();
+      data.write_folded(io::BufWriter::new(fs::File::create(out)?))?;
+      data.write_report(io::stdout(), 30)?;
+    }
     let env = env.unwrap_or_else(|| std::process::exit(1));
+    let report = |(fsp, e): (FileSpan, OutputError)| -> io::Result<()> {
+      let file = VFS.get_or_insert(fsp.file.clone())?.1;
+      if let Some(text) = file.text.try_ascii() {
+        ElabError::new_e(fsp.span, e).to_snippet(&fsp.file, text, &mut mk_to_range(),
+          |s| println!("{}\n", DisplayList::from(s)));
+      } else {
+        println!("error: {}: {}\n", fsp.file, BoxError::from(e));
+      }
+      std::process::exit(1);
+    };
+    if let Some(s) = self.input_str {
+      if let Err(e) = env.run_input(&fs::read(s)?) { report(e)? }
+    }
     if let Some(s) = self.output_str {
-      if let Err((fsp, e)) =
+      if let Err(e) =
         if s == "-" { env.run_output(io::stdout()) }
         else { env.run_output(fs::File::create(s)?) }
-      {
-        let e = ElabError::new_e(fsp.span, e);
-        let file = VFS.get_or_insert(fsp.file.clone())?.1;
-        e.to_snippet(&fsp.file, file.text.ascii(), &mut mk_to_range(),
-          |s| println!("{}\n", DisplayList::from(s)));
-        std::process::exit(1);
-      }
+      { report(e)? }
     }
     if !self.quiet {
       println!("{} sorts, {} term/def, {} ax/thm",
@@ -543,28 +596,51 @@ impl Args {
       if out.rsplit('.').next().map_or(false, |ext| ext.eq_ignore_ascii_case("mmu")) {
         env.export_mmu(w)?;
       } else {
-        let mut report = |lvl: ErrorLevel, err: &str| {
-          println!("{}\n", DisplayList::from(Snippet {
-            title: Some(Annotation {
-              label: Some(err),
-              id: None,
-              annotation_type: lvl.to_annotation_type(),
-            }),
-            footer: vec![],
-            slices: vec![],
-            opt: FormatOptions { color: true, ..Default::default() },
-          }));
-          MAX_EMITTED_ERROR.fetch_max(lvl as u8, Ordering::Relaxed);
-        };
-        let mut ex = MmbExporter::new(path, file.try_ascii().map(|fc| &**fc), &env, &mut report, w);
-        ex.run(!self.strip)?;
-        ex.finish()?;
+        export_mmb(path, file.try_ascii().map(|fc| &**fc), &env, !self.strip, w)?;
       }
     }
-    let max_error = if self.warn_as_error { ErrorLevel::Warning } else { ErrorLevel::Error };
-    if max_error as u8 <= MAX_EMITTED_ERROR.load(Ordering::Relaxed) {
-      std::process::exit(1);
-    }
+    exit_on_error(self.warn_as_error);
     Ok(())
   }
 }
+
+/// Write `env` as an MMB file to `w`, reporting any export errors.
+/// (`source` is the text of `path`, used for the debugging index.)
+pub(crate) fn export_mmb(path: FileRef, source: Option<&LinedString>,
+  env: &FrozenEnv, index: bool, w: impl io::Write + io::Seek,
+) -> io::Result<()> {
+  let mut report = |lvl: ErrorLevel, err: &str| {
+    println!("{}\n", DisplayList::from(Snippet {
+      title: Some(Annotation {
+        label: Some(err),
+        id: None,
+        annotation_type: lvl.to_annotation_type(),
+      }),
+      footer: vec![],
+      slices: vec![],
+      opt: FormatOptions { color: true, ..Default::default() },
+    }));
+    MAX_EMITTED_ERROR.fetch_max(lvl as u8, Ordering::Relaxed);
+  };
+  let mut ex = MmbExporter::new(path, source, env, &mut report, w);
+  ex.run(index)?;
+  ex.finish()
+}
+
+/// Load a file through the virtual file system, returning its canonical name and contents.
+pub(crate) fn load_file(path: FileRef) -> io::Result<(FileRef, FileContents)> {
+  let (path, file) = VFS.get_or_insert(path)?;
+  Ok((path, file.text.clone()))
+}
+
+/// Suppress (or enable) the `elab` progress messages.
+pub(crate) fn set_quiet(quiet: bool) { QUIET.store(quiet, Ordering::Relaxed) }
+
+/// Exit the process with status 1 if any error (or warning, if `warn_as_error`
+/// is set) has been reported so far.
+pub(crate) fn exit_on_error(warn_as_error: bool) {
+  let max_error = if warn_as_error { ErrorLevel::Warning } else { ErrorLevel::Error };
+  if max_error as u8 <= MAX_EMITTED_ERROR.load(Ordering::Relaxed) {
+    std::process::exit(1);
+  }
+}
@@ -0,0 +1,379 @@
+//! A step debugger for MM1 lisp, speaking the [Debug Adapter Protocol] over stdin and stdout.
+//!
+//! The debugger elaborates a single MM1 file, with a [`DebugHook`] attached to the lisp
+//! [`Evaluator`]. Evaluation pauses at breakpoints (set on source lines) and when stepping,
+//! and while paused the editor can inspect the lisp call stack, the local variables of each
+//! frame, the evaluator's internal stack, and the goals of the current proof.
+//!
+//! The evaluator only stops at "step points", which are mostly function applications, and
+//! only once for each line at each call depth, so stepping proceeds line by line.
+//! Local variables are nameless at run time, so they are shown as `x0`, `x1`, ...
+//! in order of binding.
+//!
+//! [Debug Adapter Protocol]: https://microsoft.github.io/debug-adapter-protocol/
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}};
+use std::sync::mpsc::{channel, Receiver};
+use serde_json::{json, Value};
+use crate::{ErrorLevel, FileRef, FileSpan, LinedString, MutexExt, Position, Span};
+use crate::compiler::{elab_for_debug, load_file};
+use crate::elab::{DebugHook, ElabResult, lisp::eval::Evaluator};
+
+/// The thread id of the (only) thread in the debuggee.
+const THREAD_ID: i64 = 1;
+/// The variables reference for the evaluator's stack.
+const STACK_REF: i64 = 1;
+/// The variables reference for the proof goals.
+const GOALS_REF: i64 = 2;
+/// The variables reference for the locals of stack frame `i` is `LOCALS_REF + i`.
+const LOCALS_REF: i64 = 3;
+
+/// A request from the client.
+#[derive(Debug)]
+struct Request {
+  seq: i64,
+  command: String,
+  arguments: Value,
+}
+
+/// Read the next request from the client, or `None` at end of input.
+/// Messages other than requests are skipped.
+fn read_request(r: &mut impl BufRead) -> io::Result<Option<Request>> {
+  loop {
+    let mut len = None;
+    let mut line = String::new();
+    loop {
+      line.clear();
+      if r.read_line(&mut line)? == 0 { return Ok(None) }
+      let line = line.trim_end();
+      if line.is_empty() {
+        if len.is_some() { break }
+      } else if let Some(n) = line.strip_prefix("Content-Length:") {
+        len = n.trim().parse::<usize>().ok()
+      }
+    }
+    let mut buf = vec![0; len.expect("impossible")];
+    r.read_exact(&mut buf)?;
+    let mut msg: Value = serde_json::from_slice(&buf)
+      .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if msg["type"] == "request" {
+      return Ok(Some(Request {
+        seq: msg["seq"].as_i64().unwrap_or_default(),
+        command: msg["command"].as_str().unwrap_or_default().into(),
+        arguments: msg["arguments"].take(),
+      }))
+    }
+  }
+}
+
+/// The sending half of the connection, shared by the main thread and the debuggee.
+#[derive(Debug, Default)]
+struct Output(Mutex<i64>);
+
+impl Output {
+  fn send(&self, mut msg: Value) {
+    let mut seq = self.0.ulock();
+    *seq += 1;
+    msg["seq"] = (*seq).into();
+    let msg = msg.to_string();
+    let mut out = io::stdout().lock();
+    drop(write!(out, "Content-Length: {}\r\n\r\n{msg}", msg.len()).and_then(|()| out.flush()))
+  }
+
+  fn respond(&self, req: &Request, body: Value) {
+    let mut msg = json!({
+      "type": "response", "request_seq": req.seq, "success": true, "command": req.command
+    });
+    msg["body"] = body;
+    self.send(msg)
+  }
+
+  fn fail(&self, req: &Request, message: &str) {
+    self.send(json!({
+      "type": "response", "request_seq": req.seq, "success": false,
+      "command": req.command, "message": message
+    }))
+  }
+
+  fn event(&self, event: &str, body: Value) {
+    let mut msg = json!({"type": "event", "event": event});
+    msg["body"] = body;
+    self.send(msg)
+  }
+}
+
+/// The debugger state that is shared between the main thread and the debuggee.
+#[derive(Debug, Default)]
+struct Shared {
+  /// The (zero-based) lines with breakpoints in each file.
+  breakpoints: Mutex<HashMap<FileRef, HashSet<u32>>>,
+  /// Set by a `pause` request, and cleared when the debuggee stops.
+  pause: AtomicBool,
+  /// True while the debuggee is stopped and answering requests.
+  stopped: AtomicBool,
+}
+
+/// The stepping mode of the debuggee.
+#[derive(Copy, Clone, Debug)]
+enum Step {
+  /// Run until a breakpoint is hit.
+  Run,
+  /// Stop at the next line.
+  In,
+  /// Stop at the next line at call depth `n`, or after returning from depth `n`.
+  Over(usize),
+  /// Stop at the first step point at call depth less than `n`.
+  Out(usize),
+}
+
+/// The state of the debuggee, owned by the [`DebugHook`].
+#[derive(Debug)]
+struct Session {
+  out: Arc<Output>,
+  shared: Arc<Shared>,
+  /// The requests that are forwarded to the debuggee while it is stopped.
+  recv: Receiver<Request>,
+  step: Step,
+  /// The last line visited at each call depth.
+  lines: Vec<Option<(FileRef, u32)>>,
+  /// The contents of the files we have needed positions for.
+  files: HashMap<FileRef, Option<Arc<LinedString>>>,
+}
+
+fn source(file: &FileRef) -> Value {
+  json!({"name": file.rel(), "path": file.path()})
+}
+
+impl Session {
+  fn position(&mut self, fsp: &FileSpan) -> Position {
+    let text = self.files.entry(fsp.file.clone()).or_insert_with(||
+      load_file(fsp.file.clone()).ok().and_then(|(_, text)| text.try_ascii().cloned()));
+    text.as_ref().map_or_else(Position::default, |text| text.to_pos(fsp.span.start))
+  }
+
+  fn hook(&mut self, ev: &Evaluator<'_>, sp: Span) {
+    let depth = ev.depth();
+    let file = ev.file().clone();
+    let line = self.position(&FileSpan {file: file.clone(), span: sp}).line;
+    self.lines.resize(depth + 1, None);
+    let here = Some((file, line));
+    let new_line = self.lines[depth] != here;
+    let reason = if self.shared.pause.swap(false, Ordering::Relaxed) {
+      Some("pause")
+    } else if match self.step {
+      Step::Run => false,
+      Step::In => new_line,
+      Step::Over(n) => depth < n || new_line && depth == n,
+      Step::Out(n) => depth < n,
+    } {
+      Some("step")
+    } else if new_line && here.as_ref().is_some_and(|(file, line)|
+      self.shared.breakpoints.ulock().get(file).is_some_and(|lines| lines.contains(line))) {
+      Some("breakpoint")
+    } else {
+      None
+    };
+    self.lines[depth] = here;
+    if let Some(reason) = reason { self.stop(ev, sp, reason) }
+  }
+
+  /// Stop the debuggee, and answer requests until it is resumed.
+  fn stop(&mut self, ev: &Evaluator<'_>, sp: Span, reason: &str) {
+    self.shared.stopped.store(true, Ordering::SeqCst);
+    self.out.event("stopped", json!({
+      "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true
+    }));
+    let frames = ev.frames(sp);
+    while let Ok(req) = self.recv.recv() {
+      let step = match &*req.command {
+        "stackTrace" => {
+          let stack_frames = frames.iter().enumerate().map(|(i, frame)| {
+            let pos = self.position(&frame.span);
+            json!({
+              "id": i, "name": frame.name, "source": source(&frame.span.file),
+              "line": pos.line + 1, "column": pos.character + 1
+            })
+          }).collect::<Vec<_>>();
+          self.out.respond(&req, json!({
+            "stackFrames": stack_frames, "totalFrames": frames.len()
+          }));
+          continue
+        }
+        "scopes" => {
+          let id = req.arguments["frameId"].as_i64().unwrap_or_default();
+          self.out.respond(&req, json!({"scopes": [
+            {"name": "Locals", "variablesReference": LOCALS_REF + id, "expensive": false},
+            {"name": "Stack", "variablesReference": STACK_REF, "expensive": false},
+            {"name": "Goals", "variablesReference": GOALS_REF, "expensive": false},
+          ]}));
+          continue
+        }
+        "variables" => {
+          let vars = match req.arguments["variablesReference"].as_i64().unwrap_or_default() {
+            STACK_REF => ev.print_stack().into_iter().enumerate()
+              .map(|(i, e)| (format!("[{i}]"), e)).collect(),
+            GOALS_REF => ev.stat_entries(),
+            n => usize::try_from(n - LOCALS_REF).ok().and_then(|i| frames.get(i))
+              .map(|frame| frame.locals.iter().enumerate()
+                .map(|(i, e)| (format!("x{i}"), ev.print(e).to_string())).collect())
+              .unwrap_or_default(),
+          };
+          let vars = vars.into_iter().map(|(name, value)| json!({
+            "name": name, "value": value, "variablesReference": 0
+          })).collect::<Vec<_>>();
+          self.out.respond(&req, json!({"variables": vars}));
+          continue
+        }
+        "continue" => Step::Run,
+        "next" => Step::Over(ev.depth()),
+        "stepIn" => Step::In,
+        "stepOut" => Step::Out(ev.depth()),
+        _ => { self.out.fail(&req, "unsupported request"); continue }
+      };
+      self.step = step;
+      self.shared.stopped.store(false, Ordering::SeqCst);
+      self.out.respond(&req, json!({"allThreadsContinued": true}));
+      return
+    }
+    self.shared.stopped.store(false, Ordering::SeqCst);
+  }
+}
+
+/// Elaborate `path` with the debugger attached, and report the result to the client.
+fn run(path: PathBuf, mut session: Session) {
+  let out = session.out.clone();
+  let path = FileRef::from(std::fs::canonicalize(&path).unwrap_or(path));
+  let res = elab_for_debug(path.clone(), DebugHook::new(move |ev, sp| session.hook(ev, sp)));
+  let mut exit_code = 0;
+  let output = |category: &str, msg: String| out.event("output", json!({
+    "category": category, "output": format!("{msg}\n")
+  }));
+  match res {
+    Ok(ElabResult::Ok((), errors, _)) => {
+      let text = load_file(path.clone()).ok().and_then(|(_, text)| text.try_ascii().cloned());
+      // parse errors are not part of the elaboration result, so we parse again to get them
+      let parse_errors = text.iter().flat_map(|text| mm1_parser::parse(text.clone(), None).1.errors);
+      let mut msgs = parse_errors.map(|e| (e.pos, e.level, e.msg.to_string()))
+        .chain(errors.iter().flat_map(|errs| &**errs).map(|e| (e.pos, e.level, e.kind.msg())))
+        .collect::<Vec<_>>();
+      msgs.sort_by_key(|e| e.0.start);
+      for (pos, level, msg) in msgs {
+        let Position {line, character} =
+          text.as_ref().map_or_else(Position::default, |text| text.to_pos(pos.start));
+        let category = if level == ErrorLevel::Info { "stdout" } else { "stderr" };
+        if level == ErrorLevel::Error { exit_code = 1 }
+        output(category, format!("{}:{}:{}: {level}: {msg}",
+          path.rel(), line + 1, character + 1));
+      }
+    }
+    Ok(ElabResult::Canceled) => exit_code = 1,
+    Ok(ElabResult::ImportCycle(cyc)) => {
+      use std::fmt::Write;
+      let mut s = format!("import cycle: {path}");
+      for p in &cyc { write!(s, " -> {p}").expect("writing to a string") }
+      output("stderr", s);
+      exit_code = 1;
+    }
+    Err(e) => { output("stderr", format!("error: {path}: {e}")); exit_code = 1 }
+  }
+  out.event("terminated", json!({}));
+  out.event("exited", json!({"exitCode": exit_code}));
+}
+
+/// MM1 lisp debugger, using the Debug Adapter Protocol
+#[derive(clap::Args, Clone, Copy, Debug)]
+pub struct Args {
+  /// Disable proof checking until (check-proofs #t)
+  #[clap(short, long)]
+  pub no_proofs: bool,
+  /// Warn on unnecessary parentheses
+  #[clap(long = "warn-unnecessary-parens")]
+  pub check_parens: bool,
+}
+
+impl Args {
+  /// Main entry point for `mm0-rs debug` subcommand.
+  ///
+  /// This function is not intended for interactive use, but instead communicates with an
+  /// editor using the [Debug Adapter Protocol] on stdin and stdout. The file to debug is
+  /// given by the `program` field of the `launch` request, and if `stopOnEntry` is set then
+  /// evaluation stops at the first step point.
+  ///
+  /// [Debug Adapter Protocol]: https://microsoft.github.io/debug-adapter-protocol/
+  pub fn main(self) -> io::Result<()> {
+    let out = Arc::new(Output::default());
+    let shared = Arc::new(Shared::default());
+    let (send, recv) = channel();
+    let mut recv = Some(recv);
+    let mut launch = None;
+    let mut configured = false;
+    let mut stdin = io::stdin().lock();
+    while let Some(req) = read_request(&mut stdin)? {
+      match &*req.command {
+        "initialize" => {
+          out.respond(&req, json!({"supportsConfigurationDoneRequest": true}));
+          out.event("initialized", json!({}));
+        }
+        "launch" => if recv.is_none() {
+          out.fail(&req, "already launched")
+        } else if let Some(program) = req.arguments["program"].as_str() {
+          let stop_on_entry = req.arguments["stopOnEntry"].as_bool().unwrap_or(false);
+          launch = Some((PathBuf::from(program), stop_on_entry));
+          out.respond(&req, Value::Null)
+        } else {
+          out.fail(&req, "missing 'program' argument")
+        },
+        "setBreakpoints" => {
+          let path = req.arguments["source"]["path"].as_str().unwrap_or_default();
+          let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.into());
+          let lines = req.arguments["breakpoints"].as_array().into_iter().flatten()
+            .filter_map(|bp| bp["line"].as_u64().and_then(|n| u32::try_from(n).ok()))
+            .collect::<Vec<_>>();
+          shared.breakpoints.ulock().insert(path.into(),
+            lines.iter().map(|&n| n.saturating_sub(1)).collect());
+          let bps = lines.iter().map(|n| json!({"verified": true, "line": n}))
+            .collect::<Vec<_>>();
+          out.respond(&req, json!({"breakpoints": bps}))
+        }
+        "setExceptionBreakpoints" => out.respond(&req, json!({})),
+        "configurationDone" => {
+          configured = true;
+          out.respond(&req, Value::Null)
+        }
+        "threads" => out.respond(&req, json!({"threads": [{"id": THREAD_ID, "name": "main"}]})),
+        "pause" => {
+          shared.pause.store(true, Ordering::Relaxed);
+          out.respond(&req, Value::Null)
+        }
+        "disconnect" | "terminate" => {
+          out.respond(&req, Value::Null);
+          break
+        }
+        "stackTrace" | "scopes" | "variables" | "continue" | "next" | "stepIn" | "stepOut" =>
+          if shared.stopped.load(Ordering::SeqCst) {
+            drop(send.send(req))
+          } else {
+            out.fail(&req, "the program is not stopped")
+          },
+        _ => out.fail(&req, "unsupported request"),
+      }
+      if configured {
+        if let Some((path, stop_on_entry)) = launch.take() {
+          let session = Session {
+            out: out.clone(),
+            shared: shared.clone(),
+            recv: recv.take().expect("launched twice"),
+            step: if stop_on_entry { Step::In } else { Step::Run },
+            lines: vec![],
+            files: HashMap::new(),
+          };
+          std::thread::spawn(move || run(path, session));
+        }
+      }
+    }
+    Ok(())
+  }
+}
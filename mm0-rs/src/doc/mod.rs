@@ -667,7 +667,8 @@ impl<'a, W: Write> BuildDoc<'a, W> {
       let fe = FormatEnv {source: self.source, env: &self.env};
       match *s {
         StmtTrace::Global(_) |
-        StmtTrace::OutputString(_) => {}
+        StmtTrace::OutputString(_) |
+        StmtTrace::InputString(_) => {}
         StmtTrace::Sort(a) => {
           let ad = &self.env.data[a];
           write!(file, "    <div id=\"")?;
@@ -13,8 +13,11 @@ pub mod lisp;
 pub mod math_parser;
 pub mod local_context;
 pub mod refine;
+pub mod simp;
+pub mod tauto;
 pub mod proof;
 pub mod inout;
+pub mod profile;
 pub mod verify;
 
 
@@ -228,7 +231,71 @@ impl std::fmt::Debug for GoalListener {
   }
 }
 
+/// A function that gets called by the lisp evaluator when a debugger is attached.
+///
+/// It is called at each step point (function applications and similar instructions),
+/// with the span of the instruction about to be executed, and may block in order to
+/// pause the evaluation.
+#[allow(clippy::type_complexity)]
+pub struct DebugHook(Box<dyn for<'a, 'b> FnMut(&'a lisp::eval::Evaluator<'b>, Span) + Send>);
+
+impl DebugHook {
+  /// Creates a new [`DebugHook`] from a callback.
+  pub fn new(f: impl for<'a, 'b> FnMut(&'a lisp::eval::Evaluator<'b>, Span) + Send + 'static) -> Self {
+    Self(Box::new(f))
+  }
+}
+
+impl std::fmt::Debug for DebugHook {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    "DebugHook".fmt(f)
+  }
+}
+
+/// A function that gets called by the `read-file` builtin to read a file.
+///
+/// It receives the resolved path. The server uses this to read through its virtual file
+/// system and to record the file as a dependency; if there is no reader the file is read
+/// from disk.
+#[allow(clippy::type_complexity)]
+pub struct FileReader(Box<dyn FnMut(&FileRef) -> std::io::Result<ArcString> + Send>);
+
+impl FileReader {
+  /// Creates a new [`FileReader`] from a callback.
+  pub fn new(f: impl FnMut(&FileRef) -> std::io::Result<ArcString> + Send + 'static) -> Self {
+    Self(Box::new(f))
+  }
+}
+
+impl std::fmt::Debug for FileReader {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    "FileReader".fmt(f)
+  }
+}
+
+/// The name of a statement, as recorded by the profiler.
+fn profile_name(ast: &Ast, stmt: &Stmt) -> String {
+  match &stmt.k {
+    StmtKind::Annot(_, s) | StmtKind::DocComment(_, s) => profile_name(ast, s),
+    StmtKind::Decl(d) => {
+      let kind = match d.k {
+        DeclKind::Term => "term",
+        DeclKind::Axiom => "axiom",
+        DeclKind::Thm => "theorem",
+        DeclKind::Def => "def",
+      };
+      format!("{kind} {}", String::from_utf8_lossy(ast.span(d.id)))
+    }
+    &StmtKind::Sort(id, _) => format!("sort {}", String::from_utf8_lossy(ast.span(id))),
+    StmtKind::Do(_) => format!("do (line {})", ast.source.to_pos(stmt.span.start).line + 1),
+    StmtKind::Import(..) => "import".into(),
+    StmtKind::Inout {..} => "input/output".into(),
+    _ => "notation".into(),
+  }
+}
+
 /// The persistent elaborator options (which can be set at the command line)
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Copy, Clone, Debug)]
 pub struct ElabOptions {
   /// True if we are checking proofs (otherwise we pretend every proof says `theorem foo = '?;`)
@@ -237,11 +304,13 @@ pub struct ElabOptions {
   pub check_parens: bool,
   /// If true, we will report a warning on declarations with unused variables.
   pub unused_vars: bool,
+  /// If true, we will record the time spent in each declaration and lisp procedure.
+  pub profile: bool,
 }
 
 impl Default for ElabOptions {
   fn default() -> Self {
-    Self { check_proofs: true, check_parens: false, unused_vars: true }
+    Self { check_proofs: true, check_parens: false, unused_vars: true, profile: false }
   }
 }
 
@@ -285,6 +354,12 @@ pub struct Elaborator {
   arena: lisp::LispArena,
   /// A listener for goal view events.
   recv_goal: Option<GoalListener>,
+  /// The lisp debugger, if one is attached.
+  debug_hook: Option<DebugHook>,
+  /// The function used to read files in `read-file`, if not reading from disk.
+  file_reader: Option<FileReader>,
+  /// The profiler, if profiling is enabled.
+  profiler: Option<profile::Profiler>,
 }
 
 impl Deref for Elaborator {
@@ -314,6 +389,7 @@ impl Elaborator {
       mm0_mode: bool, options: ElabOptions, cancel: Arc<AtomicBool>,
       recv_goal: Option<GoalListener>,
     ) -> Elaborator {
+    let profiler = options.profile.then(|| profile::Profiler::new(path.rel().into()));
     Elaborator {
       ast, path, cancel,
       errors: Vec::new(),
@@ -330,6 +406,9 @@ impl Elaborator {
       reporting: ReportMode::new(),
       arena: Default::default(),
       recv_goal,
+      debug_hook: None,
+      file_reader: None,
+      profiler,
     }
   }
 
@@ -348,6 +427,41 @@ impl Elaborator {
     self.env.spans.push(mem::take(&mut self.spans));
   }
 
+  /// Merge the environment `env` of an imported file into the current environment.
+  /// Conflicts are reported at `sp`, the span of the import statement.
+  pub(crate) fn import_env(&mut self, sp: Span, env: &FrozenEnv) {
+    let mut it = EnvMergeIter::new(&mut self.env, env, sp);
+    loop {
+      match it.next(&mut self.env, &mut self.errors) {
+        Err(e) => {self.report(e); break}
+        Ok(None) => break,
+        Ok(Some(mut merge)) => {
+          merge.val = self.apply_merge(sp,
+              merge.strat.as_deref(), merge.val.clone(), merge.new.val.clone())
+            .unwrap_or_else(|e| {self.report(e); merge.new.val.clone()});
+          merge.apply(&mut self.env);
+        }
+      }
+    }
+  }
+
+  /// Construct an elaborator for `ast` with the default options and run `f` on it,
+  /// outside the usual statement loop. This is used by front ends that build an
+  /// environment from something other than an MM1 file, such as `mm0-rs mmc`.
+  /// Returns the result of `f`, the reported errors, and the final environment.
+  pub(crate) fn with_elab<T>(ast: Arc<Ast>, path: FileRef,
+    f: impl FnOnce(&mut Elaborator) -> T
+  ) -> (T, Vec<ElabError>, FrozenEnv) {
+    let mut elab = Elaborator::new(ast, path, false, crate::get_options(), Arc::default(), None);
+    elab.arena.install_thread_local();
+    let t = f(&mut elab);
+    elab.push_spans();
+    if let Some(p) = elab.profiler.take() { p.finish() }
+    lisp::LispArena::uninstall_thread_local();
+    elab.arena.clear();
+    (t, elab.errors, FrozenEnv::new(elab.env))
+  }
+
   fn call_goal_listener(&mut self, stat: &str) {
     if let Some(mut listener) = self.recv_goal.take() {
       listener.0(self, stat);
@@ -682,6 +796,11 @@ pub struct ElaborateBuilder<'a, F> {
   /// to transfer an [`Environment`] containing the elaborated theorems, as well as any
   /// extra data `T`, which is collected and passed through the function.
   pub recv_goal: Option<GoalListener>,
+  /// A debugger to attach to the lisp evaluator, used by `mm0-rs debug`.
+  pub debug_hook: Option<DebugHook>,
+  /// A function to read files for the `read-file` builtin. If not provided, files are
+  /// read directly from disk.
+  pub file_reader: Option<FileReader>,
 }
 
 impl<'a, T: Send, F> ElaborateBuilder<'a, F>
@@ -766,19 +885,7 @@ where F: FnMut(FileRef) -> Result<Receiver<ElabResult<T>>, BoxError> {
                       }
                     }
                   }
-                  let mut it = EnvMergeIter::new(&mut elab.env, &env, *sp);
-                  loop {
-                    match it.next(&mut elab.env, &mut elab.errors) {
-                      Err(e) => {elab.report(e); break}
-                      Ok(None) => break,
-                      Ok(Some(mut merge)) => {
-                        merge.val = elab.apply_merge(*sp,
-                            merge.strat.as_deref(), merge.val.clone(), merge.new.val.clone())
-                          .unwrap_or_else(|e| {elab.report(e); merge.new.val.clone()});
-                        merge.apply(&mut elab.env);
-                      }
-                    }
-                  }
+                  elab.import_env(*sp, &env);
                 }
                 Ok(ElabResult::Canceled) => {
                   elab.report(ElabError::new_e(*sp, "canceled"));
@@ -800,7 +907,10 @@ where F: FnMut(FileRef) -> Result<Receiver<ElabResult<T>>, BoxError> {
           let ast = elab.ast.clone();
           while let Some(s) = ast.stmts.get(*idx) {
             if elab.cancel.load(Ordering::Relaxed) {break}
-            match elab.elab_stmt(String::new(), s, s.span) {
+            if let Some(p) = &mut elab.profiler { p.enter(profile_name(&elab.ast, s)) }
+            let res = elab.elab_stmt(String::new(), s, s.span);
+            if let Some(p) = &mut elab.profiler { p.exit_to(0) }
+            match res {
               Ok(ElabStmt::Ok) => {}
               Ok(ElabStmt::Import(sp)) => {
                 if let Some((file, recv)) = recv.remove(&sp) {
@@ -818,8 +928,9 @@ where F: FnMut(FileRef) -> Result<Receiver<ElabResult<T>>, BoxError> {
           break
         }
         lisp::LispArena::uninstall_thread_local();
-        let ElabFutureInner {elab: FrozenElaborator(elab), cyc, toks, ..} =
+        let ElabFutureInner {elab: FrozenElaborator(mut elab), cyc, toks, ..} =
           this.take().expect("impossible");
+        if let Some(p) = elab.profiler.take() { p.finish() }
         elab.arena.clear();
         Poll::Ready((cyc, toks, elab.errors, FrozenEnv::new(elab.env)))
       }
@@ -829,6 +940,8 @@ where F: FnMut(FileRef) -> Result<Receiver<ElabResult<T>>, BoxError> {
     let mut recv = HashMap::new();
     let mut elab = Elaborator::new(self.ast.clone(),
       self.path, self.mm0_mode, self.options, self.cancel, self.recv_goal);
+    elab.debug_hook = self.debug_hook;
+    elab.file_reader = self.file_reader;
     elab.arena.install_thread_local();
     for &(sp, ref f) in &self.ast.imports {
       (|| -> Result<_> {
@@ -4,7 +4,7 @@ use std::ops::Deref;
 use std::rc::Rc;
 use std::sync::Arc;
 use std::fmt::Write;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use super::{BoxError, ElabError, FrozenEnv, FrozenLispVal, spans::Spans, verify::VERIFY_ON_ADD};
 use crate::{ArcString, AtomId, AtomVec, DocComment, FileRef, FileSpan, HashMapExt, Modifiers,
   Prec, SortId, SortVec, Span, TermId, TermVec, ThmId, ThmVec,
@@ -67,7 +67,7 @@ impl Type {
 
 /// An [`ExprNode`] is interpreted inside a context containing the `[`[`Type`]`]`
 /// args, the `[ExprNode]` heap and the `[ExprNode]` store for subterms.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 #[cfg_attr(feature = "memory", derive(DeepSizeOf))]
 pub enum ExprNode {
   /// `Ref(n)` is a reference to heap element `n` (the first `args.len()` of them are the variables)
@@ -80,7 +80,7 @@ pub enum ExprNode {
 
 /// The `Expr` type stores expression dags using a local context of expression nodes
 /// and a final expression. See [`ExprNode`] for explanation of the variants.
-#[derive(Clone, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 #[cfg_attr(feature = "memory", derive(DeepSizeOf))]
 pub struct Expr {
   /// The heap, which is used for subexpressions that appear multiple times.
@@ -98,7 +98,7 @@ impl Expr {
 }
 
 /// The value of a term or def.
-#[derive(Clone, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 #[cfg_attr(feature = "memory", derive(DeepSizeOf))]
 pub enum TermKind {
   /// This is a `term`, which has no definition
@@ -308,8 +308,8 @@ pub struct Thm {
   pub kind: ThmKind,
 }
 
-/// An `output string` directive, which is anonymous and hence stored directly
-/// in the [`StmtTrace`] list.
+/// An `output string` or `input string` directive, which is anonymous and hence stored
+/// directly in the [`StmtTrace`] list.
 #[derive(Clone, Debug)]
 #[cfg_attr(feature = "memory", derive(DeepSizeOf))]
 pub struct OutputString {
@@ -319,7 +319,8 @@ pub struct OutputString {
   pub heap: Box<[ExprNode]>,
   /// The store of expressions used in the `exprs`.
   pub store: Box<[ExprNode]>,
-  /// The last `exprs` expressions in the store are the expressions to output.
+  /// The last `exprs` expressions in the store are the expressions to output
+  /// (or to match against the input).
   pub exprs: usize,
 }
 
@@ -335,7 +336,9 @@ pub enum StmtTrace {
   /// A global lisp declaration in a `do` block, i.e. `do { (def foo 1) };`
   Global(AtomId),
   /// An `output string` directive.
-  OutputString(Box<OutputString>)
+  OutputString(Box<OutputString>),
+  /// An `input string` directive.
+  InputString(Box<OutputString>),
 }
 
 /// A declaration is either a [`Term`] or a [`Thm`]. This is done because in MM1
@@ -637,6 +640,21 @@ pub enum OneOrMore<T> {
   More,
 }
 
+/// The lisp module declaration of a file, created by the `module` and `export` forms.
+/// It determines how the file's global lisp definitions are seen by files that import it.
+#[derive(Debug)]
+#[cfg_attr(feature = "memory", derive(DeepSizeOf))]
+pub struct Module {
+  /// The file containing the declaration. Global definitions made in this file are
+  /// private unless they are exported; definitions imported from elsewhere pass through.
+  pub file: FileRef,
+  /// The prefix set by `(module foo)`, if any. An exported definition `x` is imported
+  /// as `foo::x` instead of `x`.
+  pub prefix: Option<ArcString>,
+  /// The global definitions exported by `(export x ...)`.
+  pub exports: HashSet<AtomId>,
+}
+
 /// The main environment struct, containing all permanent data to be exported from an MM1 file.
 #[derive(Debug)]
 #[cfg_attr(feature = "memory", derive(DeepSizeOf))]
@@ -659,6 +677,9 @@ pub struct Environment {
   pub stmts: Vec<StmtTrace>,
   /// The list of spans that have been collected in the current statement.
   pub spans: Vec<Spans<ObjectKind>>,
+  /// The lisp module declaration of this file, if there is one. This is not merged
+  /// on import, since it only describes this file.
+  pub module: Option<Module>,
 }
 
 impl Environment {
@@ -682,6 +703,7 @@ impl Environment {
       thms: Default::default(),
       stmts: Default::default(),
       spans: Default::default(),
+      module: None,
     }
   }
 }
@@ -710,13 +732,17 @@ impl Delims {
 #[derive(Default, Debug)]
 pub struct Remapper {
   /// A mapping of foreign sorts into local sort IDs
-  sort: SortVec<SortId>,
+  pub(crate) sort: SortVec<SortId>,
   /// A mapping of foreign terms into local term IDs
-  term: TermVec<TermId>,
+  pub(crate) term: TermVec<TermId>,
   /// A mapping of foreign theorems into local theorem IDs
-  thm: ThmVec<ThmId>,
+  pub(crate) thm: ThmVec<ThmId>,
   /// A mapping of foreign atoms into local atom IDs
   pub(crate) atom: AtomVec<AtomId>,
+  /// A mapping of foreign atoms naming global lisp definitions that are renamed on import,
+  /// because they are private to a module or exported with a prefix. This overrides `atom`
+  /// for references to the definition (but not for uses of the atom as a value).
+  pub(crate) global: HashMap<AtomId, AtomId>,
   /// A mapping of foreign [`FrozenLispVal`]s into local [`LispVal`]s.
   /// It uses a pointer to the underlying allocation as an identifier so that
   /// we don't remap the same lisp values many times.
@@ -733,6 +759,13 @@ pub struct Remapper {
   pub(crate) refs: HashMap<*const FrozenLispRef, LispVal>,
 }
 
+impl Remapper {
+  /// Remap an atom used as the name of a global lisp definition.
+  pub(crate) fn global(&self, a: AtomId) -> AtomId {
+    self.global.get(&a).copied().unwrap_or(self.atom[a])
+  }
+}
+
 /// A trait for types that can be remapped.
 /// This is like [`Clone`] except it uses a `&mut R` as auxiliary state.
 pub trait Remap: Sized {
@@ -1399,6 +1432,7 @@ impl Environment {
       },
       StmtTrace::Global(_) => {}
       StmtTrace::OutputString(ref e) => self.stmts.push(StmtTrace::OutputString(e.remap(remap))),
+      StmtTrace::InputString(ref e) => self.stmts.push(StmtTrace::InputString(e.remap(remap))),
     }
     Ok(())
   }
@@ -1448,10 +1482,23 @@ pub struct AwaitingMerge<'a> {
 impl<'a> EnvMergeIter<'a> {
   /// Starts an environment merge operation.
   pub fn new(env: &mut Environment, other: &'a FrozenEnv, sp: Span) -> Self {
-    let remap = Remapper {
+    let mut remap = Remapper {
       atom: other.data().iter().map(|d| env.get_atom_arc(d.name().clone())).collect(),
       ..Default::default()
     };
+    if let Some(m) = other.module() {
+      for (a, d) in other.data().enum_iter() {
+        let Some((fsp, _)) = d.lisp().as_ref().and_then(|ld| ld.src().as_ref()) else { continue };
+        if fsp.file != m.file { continue }
+        // Private definitions get a name that cannot be written as a lisp identifier
+        let name = if !m.exports.contains(&a) {
+          format!("{}#{}", m.file.rel(), d.name())
+        } else if let Some(prefix) = &m.prefix {
+          format!("{prefix}::{}", d.name())
+        } else { continue };
+        remap.global.insert(a, env.get_atom_arc(name.into()));
+      }
+    }
     Self {remap, other, sp, it: other.stmts().iter()}
   }
 
@@ -1463,7 +1510,7 @@ impl<'a> EnvMergeIter<'a> {
     while let Some(s) = self.it.next() {
       if let StmtTrace::Global(a_old) = *s {
         let d = &self.other.data()[a_old];
-        let a = self.remap.atom[a_old];
+        let a = self.remap.global(a_old);
         env.stmts.push(StmtTrace::Global(a));
         let data = &mut env.data[a];
         let newlisp = d.lisp().as_ref().map(|v| v.remap(&mut self.remap));
@@ -38,7 +38,7 @@ use std::rc::Rc;
 use std::collections::{HashMap, hash_map::Entry};
 use num::BigInt;
 use crate::{mk_lisp_kind, ArcString, AtomData, AtomId, AtomVec, DeclKey, DocComment, Environment,
-  FileSpan, LinedString, LispData, LispKind, LispVal, MergeStrategy, MergeStrategyInner, ParserEnv, Sort,
+  FileSpan, LinedString, LispData, LispKind, LispVal, MergeStrategy, MergeStrategyInner, Module, ParserEnv, Sort,
   SortId, SortVec, Span, StmtTrace, Term, TermId, TermVec, Thm, ThmId, ThmVec,
   lisp::{print::FormatEnv, Annot, InferTarget, LispRef, LispWeak, Proc, Syntax}};
 use super::{ObjectKind, Remap, Remapper, Spans};
@@ -127,6 +127,11 @@ impl FrozenEnv {
     // Safety: `ParserEnv` does not have any `LispVal`s
     &unsafe { self.thaw() }.pe
   }
+  /// Accessor for [`Environment::module`]
+  #[must_use] pub fn module(&self) -> Option<&Module> {
+    // Safety: `Module` does not have any `LispVal`s
+    unsafe { self.thaw() }.module.as_ref()
+  }
 }
 
 /// A wrapper around an [`AtomData`] that is frozen.
@@ -423,6 +428,8 @@ impl Remap for FrozenLispKind {
       FrozenLispKind::Annot(sp, m) => LispVal::new(LispKind::Annot(sp.clone(), m.remap(r))),
       FrozenLispKind::Proc(f) => LispVal::proc(f.remap(r)),
       FrozenLispKind::AtomMap(m) => LispVal::new(LispKind::AtomMap(m.remap(r))),
+      FrozenLispKind::StrMap(m) => LispVal::new(LispKind::StrMap(m.remap(r))),
+      FrozenLispKind::Vector(v) => LispVal::new(LispKind::Vector(v.remap(r))),
       FrozenLispKind::Ref(m) => match r.refs.entry(m as *const _) {
         Entry::Occupied(e) => e.get().clone(),
         Entry::Vacant(e) => {
@@ -473,6 +480,7 @@ impl Remap for FrozenProc {
         }
       )),
       Proc::Dyn(c) => Proc::Dyn(c.remap(r)),
+      Proc::Macro(m) => Proc::Macro(m.remap(r)),
     }
   }
 }
@@ -331,7 +331,8 @@ impl Elaborator {
     Ok((*s, map))
   }
 
-  fn elab_output_string(&mut self, sp: Span, hs: &[SExpr]) -> EResult<()> {
+  /// Elaborate the arguments to an `output string` or `input string` command.
+  fn elab_string_exprs(&mut self, sp: Span, hs: &[SExpr]) -> EResult<Box<OutputString>> {
     let (sorts, _) = self.get_string_handler(sp)?;
     let fsp = self.fspan(sp);
     let mut es = Vec::with_capacity(hs.len());
@@ -353,9 +354,7 @@ impl Elaborator {
     let (mut ids, heap, mut store) = build(&de);
     let exprs = is.len();
     store.extend(is.into_iter().map(|i| ids[i].take()));
-    self.stmts.push(StmtTrace::OutputString(
-      Box::new(OutputString {span: fsp, heap, store: store.into(), exprs})));
-    Ok(())
+    Ok(Box::new(OutputString {span: fsp, heap, store: store.into(), exprs}))
   }
 
   /// Elaborate as if in an `output string` command, but from lisp. The input values
@@ -395,16 +394,27 @@ impl Elaborator {
   /// It is triggered only in "compile" mode, and by manual selection in server mode.
   pub fn elab_output(&mut self, sp: Span, kind: Span, hs: &[SExpr]) -> EResult<()> {
     match self.span(kind) {
-      b"string" => self.elab_output_string(sp, hs),
+      b"string" => {
+        let os = self.elab_string_exprs(sp, hs)?;
+        self.stmts.push(StmtTrace::OutputString(os));
+        Ok(())
+      }
       _ => Err(ElabError::new_e(kind, "unsupported output kind")),
     }
   }
 
-  /// Elaborate an `input` command. This is not implemented, as it needs to work with the
-  /// final MM0 file, which is not available. More design work is needed.
-  #[allow(clippy::unused_self)]
-  pub fn elab_input(&mut self, _: Span, kind: Span, _: &[SExpr]) -> EResult<()> {
-    Err(ElabError::new_e(kind, "unsupported input kind"))
+  /// Elaborate an `input` command. The input itself is not available during elaboration
+  /// (it is the final MM0 file, for the only supported kind `input string`), so this only
+  /// typechecks the command. The check is performed by [`FrozenEnv::run_input`].
+  pub fn elab_input(&mut self, sp: Span, kind: Span, hs: &[SExpr]) -> EResult<()> {
+    match self.span(kind) {
+      b"string" => {
+        let is = self.elab_string_exprs(sp, hs)?;
+        self.stmts.push(StmtTrace::InputString(is));
+        Ok(())
+      }
+      _ => Err(ElabError::new_e(kind, "unsupported input kind")),
+    }
   }
 }
 
@@ -431,4 +441,31 @@ impl FrozenEnv {
     }
     Ok(())
   }
+
+  /// Check all the `input` directives in the environment against `input`,
+  /// which should be the contents of the final MM0 file.
+  pub fn run_input(&self, input: &[u8]) -> Result<(), (FileSpan, OutputError)> {
+    // Safety: We only use this environment to read non-lisp data.
+    let env = unsafe { self.thaw() };
+    let mut handler = None;
+    for s in self.stmts() {
+      if let StmtTrace::InputString(is) = s {
+        let OutputString {span, heap, store, exprs} = &**is;
+        (|| -> Result<(), OutputError> {
+          let terms = &handler.get_or_insert(
+            env.new_string_handler().map_err(OutputError::String)?).1;
+          let mut w = StringWriter::<Vec<u8>>::default();
+          env.write_output_string(terms, &mut w, heap, store, &store[store.len() - exprs..])?;
+          if w.hex.is_some() { return Err("input string has an odd number of hex digits".into()) }
+          if w.w == input { return Ok(()) }
+          let i = w.w.iter().zip(input).position(|(a, b)| a != b)
+            .unwrap_or_else(|| w.w.len().min(input.len()));
+          Err(OutputError::String(format!(
+            "input string does not match the input: they differ at byte {i} \
+            (the string has length {}, the input has length {})", w.w.len(), input.len())))
+        })().map_err(|e| (span.clone(), e))?;
+      }
+    }
+    Ok(())
+  }
 }
@@ -120,6 +120,22 @@ str_enum! {
     ///   overwriting the originals but preserving any keys not in `new`.
     ///   * This can also be used as `(merge-map strat)` where `strat` is a subsidiary merge strategy.
     SetMergeStrategy: "set-merge-strategy",
+    /// `(export x ...)` exports the global definitions `x ...` from the current file,
+    /// making it a *module*. When a module is imported, only its exported definitions are
+    /// visible; the others are renamed so that they do not clash with the importer's names.
+    Export: "export",
+    /// `(module foo)` makes the current file a module (see `export`) with prefix `foo`.
+    /// Its exported definitions `x` are then imported as `foo::x`.
+    Module: "module",
+    /// `defmacro`: defines a new syntax form. `(defmacro (foo args) body)` defines a
+    /// procedural macro, which is called at compile time on the unevaluated arguments
+    /// and returns the code to compile in their place, and `(defmacro foo (syntax-rules ...))`
+    /// defines a pattern-based macro.
+    DefMacro: "defmacro",
+    /// `(syntax-rules (lits) [pat tmpl] ...)`: a list of pattern-based rewrite rules
+    /// for use in `defmacro`. Identifiers introduced by the templates are renamed
+    /// so that they cannot capture or be captured by the user's variables.
+    SyntaxRules: "syntax-rules",
   }
 }
 
@@ -315,6 +331,12 @@ macro_rules! mk_lisp_kind {
       /// A map from atoms to values. This can be used as a mutable map if it is behind a
       /// [`Ref`](Self::Ref).
       AtomMap(HashMap<AtomId, $val>),
+      /// A map from strings to values. Like [`AtomMap`](Self::AtomMap), this can be used
+      /// as a mutable map if it is behind a [`Ref`](Self::Ref).
+      StrMap(HashMap<ArcString, $val>),
+      /// An array of values with constant time indexing. This can be used as a
+      /// mutable vector if it is behind a [`Ref`](Self::Ref).
+      Vector(Vec<$val>),
       /// A mutable reference. This is the only way to have mutable values in
       /// client code.
       Ref($ref_),
@@ -724,6 +746,14 @@ impl LispKind {
   pub fn is_map(&self) -> bool {
     self.unwrapped(|e| matches!(e, LispKind::AtomMap(_)))
   }
+  /// Returns true if this value is a string map.
+  pub fn is_str_map(&self) -> bool {
+    self.unwrapped(|e| matches!(e, LispKind::StrMap(_)))
+  }
+  /// Returns true if this value is a vector.
+  pub fn is_vector(&self) -> bool {
+    self.unwrapped(|e| matches!(e, LispKind::Vector(_)))
+  }
   /// Returns true if this value is not `#undef` or a reference to `#undef`.
   pub fn is_def(&self) -> bool {
     self.unwrapped(|e| !matches!(e, LispKind::Undef))
@@ -885,7 +915,7 @@ impl PartialEq<LispKind> for LispKind {
           }
         }
       }
-      _ => false // Goal, Proc, MVar, AtomMap all have only reference equality
+      _ => false // Goal, Proc, MVar, AtomMap, StrMap, Vector all have only reference equality
     }))
   }
 }
@@ -984,7 +1014,34 @@ pub enum Proc {
   /// internal state here. See [`Compiler::call`].
   ///
   /// [`Compiler::call`]: crate::mmc::Compiler::call
-  Dyn(RefCell<Box<dyn LispProc>>) // TODO: use extern instead
+  Dyn(RefCell<Box<dyn LispProc>>), // TODO: use extern instead
+  /// A macro, created by `defmacro`. Macros are expanded by the lisp parser
+  /// when a global name bound to a macro is used in head position, so they
+  /// cannot be called at run time.
+  Macro(Macro),
+}
+
+/// A user-defined syntax form, created by `defmacro`.
+#[derive(Debug, EnvDebug)]
+#[cfg_attr(feature = "memory", derive(DeepSizeOf))]
+pub enum Macro {
+  /// A procedural macro `(defmacro (foo args) body)`. The procedure is called
+  /// with the arguments of the macro invocation as s-expressions, and returns
+  /// the code to compile in place of the invocation.
+  Proc(LispVal),
+  /// A pattern-based macro `(defmacro foo (syntax-rules (lits) [pat tmpl] ...))`.
+  Rules(SyntaxRules),
+}
+
+/// The rules of a `syntax-rules` macro.
+#[derive(Debug, EnvDebug)]
+#[cfg_attr(feature = "memory", derive(DeepSizeOf))]
+pub struct SyntaxRules {
+  /// The literals, which are atoms that match only themselves in a pattern.
+  pub lits: Box<[AtomId]>,
+  /// The `(pattern, template)` pairs, as quoted s-expressions. The head of each
+  /// pattern is the macro keyword, which is ignored when matching.
+  pub rules: Box<[(LispVal, LispVal)]>,
 }
 
 /// A procedure specification, which defines the number of arguments expected
@@ -1029,6 +1086,7 @@ impl Proc {
       Proc::MergeMap(_) => ProcSpec::Exact(2),
       Proc::RefineCallback => ProcSpec::AtLeast(1),
       Proc::Dyn(proc) => proc.borrow().spec(),
+      Proc::Macro(_) => ProcSpec::AtLeast(0),
     }
   }
 }
@@ -1115,8 +1173,8 @@ str_enum! {
     /// * Strings, atoms, `#t`, `#f`, `#undef` all perform structural comparison as expected
     ///   (`#t` is equal to `#t` but not equal to `#undef` or `"#t"` or `'#t`).
     /// * Two pairs are equal if their components are equal.
-    /// * Procedures (both builtins and `fn` declarations), `atom-map`s, `goal`s and `mvar`s
-    ///   have no structural equality; they compare equal only if they are pointer-equal.
+    /// * Procedures (both builtins and `fn` declarations), `atom-map`s, `str-map`s, vectors,
+    ///   `goal`s and `mvar`s have no structural equality; they compare equal only if they are pointer-equal.
     /// * Indirections are ignored; `(ref! 1)` is equal to `1`.
     /// * The comparison routine performs no cycle detection so equality on cyclic data structures can loop.
     /// * Like the numeric equality operator `=`, `==` can be used on more than two arguments,
@@ -1173,6 +1231,18 @@ str_enum! {
     /// (list->string '(98 97 114)) -- "bar"
     /// ```
     ListToString: "list->string",
+    /// `(read-file path)` returns the contents of the text file at `path` as a string.
+    /// It is an error if the file is not valid UTF-8.
+    /// The path is resolved relative to the current file, like `import`, and must not
+    /// leave the directory containing the current file. The server re-elaborates the
+    /// current file when a file read this way is changed.
+    ReadFile: "read-file",
+    /// `(write-file path s)` writes the string `s` to the file at `path`, replacing its
+    /// contents, and returns `#undef`. The path is resolved like in `read-file`.
+    WriteFile: "write-file",
+    /// `(file-exists? path)` is true if there is a file at `path`, resolved like in
+    /// `read-file`.
+    FileExists: "file-exists?",
     /// `(not e1 e2 e3)` returns `#f` if any argument is truthy, and `#t` otherwise.
     /// It is not short-circuiting.
     Not: "not",
@@ -1248,11 +1318,13 @@ str_enum! {
     IsAtomMap: "atom-map?",
     /// `(atom-map! [k1 v1] [k2 v2] ...)` creates a new mutable atom map, a key-value store.
     NewAtomMap: "atom-map!",
-    /// * `(lookup m k)` gets the value stored in the atom map `m` at `k`, or `#undef` if not present.
+    /// * `(lookup m k)` gets the value stored in the atom map or string map `m` at `k`,
+    ///   or `#undef` if not present.
     /// * `(lookup m k v)` will return `v` instead if the key is not present,
     ///   unless `v` is a procedure, in which case it will be called with no arguments on lookup failure.
     Lookup: "lookup",
-    /// * `(insert! m k v)` inserts the value `v` at key `k` in the mutable map `m`,
+    /// * `(insert! m k v)` inserts the value `v` at key `k` in the mutable atom map or
+    ///   string map `m`,
     ///   and returns `#undef`.
     /// * `(insert! m k)` "undefines" the value at key `k` in `m`, that is,
     ///   it erases whatever is there.
@@ -1269,6 +1341,42 @@ str_enum! {
     /// * `(merge-map f old new)` or `((merge-map f) old new)` will use
     ///   `(f oldval newval)` to resolve keys that are present in both maps.
     MergeMap: "merge-map",
+    /// `(str-map? m)` is true if the argument is a string map.
+    IsStrMap: "str-map?",
+    /// `(str-map! [k1 v1] [k2 v2] ...)` creates a new mutable string map, a key-value
+    /// store with string keys. Unlike an atom map, the keys are not interned as atoms.
+    /// It works with `lookup`, `insert!` and `insert` like an atom map.
+    NewStrMap: "str-map!",
+    /// `(map-len m)` returns the number of keys in the atom map or string map `m`.
+    MapLen: "map-len",
+    /// `(map->list m)` returns the list of `[k v]` pairs in the atom map or string map `m`,
+    /// in no particular order.
+    MapToList: "map->list",
+    /// `(vector? v)` is true if the argument is a vector.
+    IsVector: "vector?",
+    /// `(vector! e1 e2 ...)` creates a new mutable vector containing `e1 e2 ...`.
+    /// Vectors support constant time indexing and amortized constant time `vector-push!`.
+    NewVector: "vector!",
+    /// * `(make-vector! n v)` creates a new mutable vector containing `n` copies of `v`.
+    /// * `(make-vector! n)` creates a new mutable vector containing `n` copies of `#undef`.
+    MakeVector: "make-vector!",
+    /// `(vector-len v)` returns the number of elements in the vector `v`.
+    VectorLen: "vector-len",
+    /// `(vector-nth n v)` returns the `n`th element of the vector `v` (zero-indexed),
+    /// or `#undef` if out of range.
+    VectorNth: "vector-nth",
+    /// `(vector-set! v n e)` sets the `n`th element of the mutable vector `v` to `e`,
+    /// and returns `#undef`. It fails if `n` is out of range.
+    VectorSet: "vector-set!",
+    /// `(vector-push! v e)` adds `e` to the end of the mutable vector `v`, and returns `#undef`.
+    VectorPush: "vector-push!",
+    /// `(vector-pop! v)` removes the last element of the mutable vector `v` and returns it,
+    /// or returns `#undef` if `v` is empty.
+    VectorPop: "vector-pop!",
+    /// `(vector->list v)` returns the list of elements of the vector `v`.
+    VectorToList: "vector->list",
+    /// `(list->vector l)` creates a new mutable vector containing the elements of the list `l`.
+    ListToVector: "list->vector",
     /// `(set-timeout n)` sets the timeout for running individual theorems and
     /// `do` blocks to `n` milliseconds. The default is 5 seconds.
     SetTimeout: "set-timeout",
@@ -1419,6 +1527,36 @@ str_enum! {
     /// by `refine` when elaborating a term with too many arguments, and is expected to be
     /// overridden by user code to provide a more useful behavior.
     RefineExtraArgs: "refine-extra-args",
+    /// `(simp cfg e)` rewrites the expression `e` to normal form, and returns `(e2 p)`
+    /// where `e2` is the normal form and `p` is a proof pre-expression for `R e e2`,
+    /// where `R` is the relation on the sort of `e`. `cfg` is an atom map with keys:
+    ///
+    /// * `rels`: the list of equality relations, at most one for each sort
+    /// * `imp`: an implication term; if given, theorems of the form `A -> B` are also
+    ///   accepted in place of `A > B`
+    /// * `lemmas`: a list (or atom map) of theorems `R lhs rhs` used to rewrite left to right
+    /// * `unfold`: a list (or atom map) of definitions to unfold
+    /// * `max-steps`: the maximum number of rewrites to perform (default 10000)
+    ///
+    /// The reflexivity, transitivity and congruence theorems for the relations
+    /// are found in the environment by the shape of their statements.
+    Simp: "simp",
+    /// `(norm-num cfg e)` evaluates numeral arithmetic in the hexadecimal encoding of
+    /// `peano_hex.mm1`. If `e` is a goal `a = b`, `a < b`, `a <= b` or `a != b` it returns
+    /// a proof for it, and otherwise it returns `(e2 p)` where `e2` is the numeral `e`
+    /// evaluates to and `p` is a proof of `e = e2`. `cfg` is an atom map that can rename
+    /// the terms and theorems used by each step, which default to the names in
+    /// `peano_hex.mm1`. This uses the numeral arithmetic of the MMC proof generator.
+    #[cfg(feature = "mmc")]
+    NormNum: "norm-num",
+    /// `(tauto cfg e)` proves the formula `e` by a classical tableau search, returning
+    /// a proof pre-expression for it. The search handles the connectives `->`, `~`,
+    /// `/\`, `\/`, `<->`, `T.`, `F.`, `A.` and `E.`, where quantifiers are only
+    /// instantiated at their own bound variable. `cfg` is an atom map that can rename
+    /// the connectives and the theorems used to build the proof (the defaults are the
+    /// names in `peano.mm1`), and set the `max-steps` option (default 100000).
+    /// The search also stops when the timeout set by `set-timeout` expires.
+    Tauto: "tauto",
     /// `(eval-string e1 e2 ...)` takes as input zero or more expressions which are elaborated
     /// as type `string`, and then evaluates them to an actual lisp string. This has the same
     /// effect as the top level command `output string: e1 e2 ...;` but this command is only
@@ -1661,6 +1799,66 @@ impl Remap for LispVal {
   }
 }
 
+impl Remap for Macro {
+  type Target = Self;
+  fn remap(&self, r: &mut Remapper) -> Self {
+    match self {
+      Macro::Proc(f) => Macro::Proc(f.remap(r)),
+      Macro::Rules(m) => {
+        // The template is compiled in the importing file, so references to the module's
+        // private or prefixed definitions have to be renamed the same way their definitions are.
+        let globals = r.global.iter().map(|(&a, &b)| (r.atom[a], b)).collect::<HashMap<_, _>>();
+        Macro::Rules(SyntaxRules {
+          lits: m.lits.remap(r),
+          rules: m.rules.iter().map(|(pat, tmpl)| {
+            let (pat, tmpl) = (pat.remap(r), tmpl.remap(r));
+            if globals.is_empty() { return (pat, tmpl) }
+            let mut globals = globals.clone();
+            remove_atoms(&pat, &mut globals);
+            let tmpl = rename_globals(&tmpl, &globals, false);
+            (pat, tmpl)
+          }).collect(),
+        })
+      }
+    }
+  }
+}
+
+/// Remove the atoms appearing in a `syntax-rules` pattern from `globals`, because
+/// in the template they refer to pattern variables rather than global definitions.
+fn remove_atoms(pat: &LispVal, globals: &mut HashMap<AtomId, AtomId>) {
+  pat.unwrapped(|e| match e {
+    LispKind::Atom(a) => { globals.remove(a); }
+    LispKind::List(es) => for e in &**es { remove_atoms(e, globals) },
+    LispKind::DottedList(es, r) => for e in es.iter().chain(Some(r)) { remove_atoms(e, globals) },
+    _ => {}
+  })
+}
+
+/// Rename the atoms in the `syntax-rules` template `t` according to `globals`, except in
+/// quoted positions (when `quote` is true, until the next `unquote`). Spans are preserved.
+fn rename_globals(t: &LispVal, globals: &HashMap<AtomId, AtomId>, quote: bool) -> LispVal {
+  let list = |es: &[LispVal]| {
+    let quote = match es.first().map(|e| e.unwrapped(|e| match *e {
+      LispKind::Syntax(s) => Some(s), _ => None })) {
+      Some(Some(Syntax::Quote)) => true,
+      Some(Some(Syntax::Unquote)) => false,
+      _ => quote,
+    };
+    (quote, es.iter().map(|e| rename_globals(e, globals, quote)).collect::<Box<[_]>>())
+  };
+  match &**t {
+    LispKind::Atom(a) if !quote => globals.get(a).map_or_else(|| t.clone(), |&b| LispVal::atom(b)),
+    LispKind::Annot(Annot::Span(fsp), e) => rename_globals(e, globals, quote).span(fsp.clone()),
+    LispKind::List(es) => LispVal::list(list(es).1),
+    LispKind::DottedList(es, r) => {
+      let (quote, es) = list(es);
+      LispVal::dotted_list(es, rename_globals(r, globals, quote))
+    }
+    _ => t.clone(),
+  }
+}
+
 impl Remap for InferTarget {
   type Target = Self;
   fn remap(&self, r: &mut Remapper) -> Self {
@@ -1676,7 +1874,7 @@ impl Remap for ProcPos {
   type Target = Self;
   fn remap(&self, r: &mut Remapper) -> Self {
     match self {
-      ProcPos::Named(fsp, sp, a) => ProcPos::Named(fsp.clone(), *sp, a.remap(r)),
+      ProcPos::Named(fsp, sp, a) => ProcPos::Named(fsp.clone(), *sp, r.global(*a)),
       ProcPos::Unnamed(fsp) => ProcPos::Unnamed(fsp.clone()),
       &ProcPos::Builtin(p) => ProcPos::Builtin(p),
     }
@@ -5,24 +5,26 @@
 //! and timeout.
 
 use std::collections::{hash_map::Entry, HashMap};
-use std::mem;
+use std::{fs, io, mem};
 use std::ops::{Deref, DerefMut};
+use std::path::PathBuf;
 use std::sync::atomic::Ordering;
 use std::time::Duration;
 use instant::Instant;
 use num::{BigInt, Signed, ToPrimitive, Zero};
 use crate::{ast::SExpr, ArcString, AtomData, AtomId, BoxError, DeclKey, ElabError,
   Elaborator, Environment, ErrorLevel, FileRef, FileSpan, LispData,
-  MergeStrategy, MergeStrategyInner, ObjectKind, SliceExt, Span, StmtTrace,
+  MergeStrategy, MergeStrategyInner, Module, ObjectKind, SliceExt, Span, StmtTrace,
   TermKind, ThmKind, ThmId};
 use crate::elab::local_context::{try_get_span, try_get_span_from, AwaitingProof, InferSort};
 use crate::elab::{
+  profile::Profiler,
   refine::{RStack, RState, RefineResult},
-  ElabErrorKind, ReportMode, Result};
+  ElabErrorKind, FileReader, ReportMode, Result};
 use super::parser::{Ir, MVarPattern};
 use super::print::FormatEnv;
-use super::{Arc, BuiltinProc, Cell, InferTarget, LispKind, LispRef, LispVal, Modifiers, Proc,
-  ProcPos, ProcSpec, QExpr, Rc, RefCell, Uncons};
+use super::{Arc, BuiltinProc, Cell, InferTarget, LispKind, LispRef, LispVal, Macro, Modifiers,
+  Proc, ProcPos, ProcSpec, QExpr, Rc, RefCell, Uncons};
 
 #[derive(Debug)]
 struct MergeMapData {
@@ -133,41 +135,75 @@ impl LispKind {
     }
   }
 
-  fn make_map_mut<T>(&self, f: impl FnOnce(&mut HashMap<AtomId, LispVal>) -> T) -> (Option<T>, Option<LispVal>) {
+  fn make_coll_mut<C: Collection, T>(&self, f: impl FnOnce(&mut C) -> T) -> (Option<T>, Option<LispVal>) {
     match self {
-      LispKind::AtomMap(m) => {
-        let mut m = m.clone();
-        (Some(f(&mut m)), Some(LispVal::new(LispKind::AtomMap(m))))
-      }
-      LispKind::Annot(sp, e) => match e.make_map_mut(f) {
+      LispKind::Annot(sp, e) => match e.make_coll_mut(f) {
         (r, None) => (r, None),
         (r, Some(e)) => (r, Some(LispVal::new(LispKind::Annot(sp.clone(), e)))),
       },
       LispKind::Ref(m) => {
         let mut f = Some(f);
-        match m.try_get_mut(|e| e.as_map_mut(f.take().expect("impossible"))) {
+        match m.try_get_mut(|e| e.as_coll_mut(f.take().expect("impossible"))) {
           Some(r) => (r, None),
-          None => m.get(|e| e.make_map_mut(f.take().expect("impossible")))
+          None => m.get(|e| e.make_coll_mut(f.take().expect("impossible")))
+        }
+      }
+      e => match C::get(e) {
+        Some(m) => {
+          let mut m = m.clone();
+          (Some(f(&mut m)), Some(LispVal::new(m.into_lisp())))
         }
+        None => (None, None)
       }
-      _ => (None, None)
     }
   }
 }
 impl LispVal {
-  fn as_map_mut<T>(&mut self, f: impl FnOnce(&mut HashMap<AtomId, LispVal>) -> T) -> Option<T> {
+  fn as_coll_mut<C: Collection, T>(&mut self, f: impl FnOnce(&mut C) -> T) -> Option<T> {
     match self.get_mut() {
       None => {
-        let (r, new) = self.make_map_mut(f);
+        let (r, new) = self.make_coll_mut(f);
         if let Some(e) = new {*self = e}
         r
       }
-      Some(LispKind::AtomMap(m)) => Some(f(m)),
-      Some(LispKind::Annot(_, e)) => Self::as_map_mut(e, f),
-      Some(LispKind::Ref(m)) => m.get_mut(|e| Self::as_map_mut(e, f)),
-      Some(_) => None
+      Some(LispKind::Annot(_, e)) => Self::as_coll_mut(e, f),
+      Some(LispKind::Ref(m)) => m.get_mut(|e| Self::as_coll_mut(e, f)),
+      Some(e) => C::get_mut(e).map(f),
+    }
+  }
+
+  fn as_vector_mut<T>(&self, f: impl FnOnce(&mut Vec<LispVal>) -> SResult<T>) -> SResult<T> {
+    self.as_ref_mut(|r| r.as_coll_mut(f)).flatten().ok_or("expected a mutable vector")?
+  }
+}
+
+/// A lisp collection type ([`AtomMap`](LispKind::AtomMap), [`StrMap`](LispKind::StrMap)
+/// or [`Vector`](LispKind::Vector)). These are modified in place if they are behind a
+/// [`Ref`](LispKind::Ref) and not shared, and copied otherwise.
+trait Collection: Clone {
+  /// The name of this collection type, for error messages.
+  const NAME: &'static str;
+  fn get(e: &LispKind) -> Option<&Self>;
+  fn get_mut(e: &mut LispKind) -> Option<&mut Self>;
+  fn into_lisp(self) -> LispKind;
+}
+
+macro_rules! impl_collection {($($ty:ty => $kind:ident, $name:expr;)*) => {$(
+  impl Collection for $ty {
+    const NAME: &'static str = $name;
+    fn get(e: &LispKind) -> Option<&Self> {
+      if let LispKind::$kind(m) = e {Some(m)} else {None}
     }
+    fn get_mut(e: &mut LispKind) -> Option<&mut Self> {
+      if let LispKind::$kind(m) = e {Some(m)} else {None}
+    }
+    fn into_lisp(self) -> LispKind { LispKind::$kind(self) }
   }
+)*}}
+impl_collection! {
+  HashMap<AtomId, LispVal> => AtomMap, "an atom map";
+  HashMap<ArcString, LispVal> => StrMap, "a string map";
+  Vec<LispVal> => Vector, "a vector";
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -259,6 +295,48 @@ impl Elaborator {
     })
   }
 
+  /// Get the module declaration of the current file, making it a module if it is not one.
+  fn module_mut(&mut self) -> &mut Module {
+    let file = &self.path;
+    self.env.module.get_or_insert_with(||
+      Module {file: file.clone(), prefix: None, exports: Default::default()})
+  }
+
+  /// Resolves a path passed to a file I/O builtin relative to the current file, the same
+  /// way `import` does, and checks that it stays within the directory of the current file.
+  /// Returns `None` if the directory containing the target does not exist, and fails if the
+  /// target is a symlink that cannot be resolved.
+  fn sandbox_path(&self, f: &str) -> SResult<Option<PathBuf>> {
+    let dir = self.path.path().parent().ok_or("file I/O is not available in this file")?;
+    let root = dir.canonicalize().map_err(|e| format!("{}: {e}", dir.display()))?;
+    let path = dir.join(f);
+    let name = path.file_name().ok_or_else(|| format!("invalid file name: {f}"))?;
+    let Some(parent) = path.parent().and_then(|p| p.canonicalize().ok()) else { return Ok(None) };
+    let mut path = parent.join(name);
+    // follow symlinks, so that they cannot be used to escape the directory. If there is no
+    // entry at `path` then the canonical parent directory already determines where it goes.
+    match fs::symlink_metadata(&path) {
+      Ok(_) => path = path.canonicalize().map_err(|e| format!("{f}: cannot resolve path: {e}"))?,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+      Err(e) => return Err(format!("{f}: {e}")),
+    }
+    if !path.starts_with(&root) { return Err(format!("{f}: path is outside the project directory")) }
+    Ok(Some(path))
+  }
+
+  fn read_file(&mut self, f: &str) -> SResult<ArcString> {
+    let path = self.sandbox_path(f)?.ok_or_else(|| format!("{f}: file not found"))?;
+    match &mut self.file_reader {
+      Some(FileReader(read)) => read(&path.into()),
+      None => fs::read_to_string(path).map(|s| s.as_bytes().into()),
+    }.map_err(|e| format!("{f}: {e}"))
+  }
+
+  fn write_file(&self, f: &str, s: &[u8]) -> SResult<()> {
+    let path = self.sandbox_path(f)?.ok_or_else(|| format!("{f}: directory not found"))?;
+    fs::write(path, s).map_err(|e| format!("{f}: {e}"))
+  }
+
   fn with_int<T>(&self, e: &LispVal, f: impl FnOnce(&BigInt) -> SResult<T>) -> SResult<T> {
     e.unwrapped(|e| if let LispKind::Number(n) = e {f(n)} else {
       Err(format!("expected a integer, got {}", self.print(e)))
@@ -277,10 +355,47 @@ impl Elaborator {
     self.as_lref(e, |m| m.get_mut(f))
   }
 
-  fn as_map<T>(&self, e: &LispKind, f: impl FnOnce(&HashMap<AtomId, LispVal>) -> SResult<T>) -> SResult<T> {
+  fn as_coll<C: Collection, T>(&self, e: &LispKind, f: impl FnOnce(&C) -> SResult<T>) -> SResult<T> {
+    e.unwrapped(|e| match C::get(e) {
+      Some(m) => f(m),
+      None => Err(format!("not {}: {}", C::NAME, self.print(e)))
+    })
+  }
+
+  fn as_string_key(&self, e: &LispVal) -> Option<ArcString> {
     e.unwrapped(|e| match e {
-      LispKind::AtomMap(m) => f(m),
-      _ => Err(format!("not an atom map: {}", self.print(e)))
+      LispKind::String(s) => Some(s.clone()),
+      &LispKind::Atom(a) => Some(self.data[a].name.clone()),
+      _ => None
+    })
+  }
+
+  /// Inserts `v` at key `k` in the atom map or string map `m`, or erases `k` if `v` is
+  /// `None`. Returns `None` if `m` is not a map.
+  fn map_insert(&mut self, m: &mut LispVal, k: &LispVal, v: Option<LispVal>) -> Option<SResult<()>> {
+    fn insert<K: std::hash::Hash + Eq, E>(m: &mut HashMap<K, LispVal>, k: Result<K, E>, v: Option<LispVal>) -> Result<(), E> {
+      match v {
+        Some(v) => {m.insert(k?, v);}
+        None => {m.remove(&k?);}
+      }
+      Ok(())
+    }
+    if m.is_str_map() {
+      let k = self.as_string_key(k).ok_or_else(|| format!("expected a string, got {}", self.print(k)));
+      m.as_coll_mut(|m| insert::<ArcString, _>(m, k, v))
+    } else {
+      let k = self.as_string_atom(k).ok_or_else(|| format!("expected an atom, got {}", self.print(k)));
+      m.as_coll_mut(|m| insert::<AtomId, _>(m, k, v))
+    }
+  }
+
+  fn map_to_list(&self, e: &LispKind) -> SResult<Vec<LispVal>> {
+    e.unwrapped(|e| match e {
+      LispKind::AtomMap(m) =>
+        Ok(m.iter().map(|(&k, v)| LispVal::list(vec![LispVal::atom(k), v.clone()])).collect()),
+      LispKind::StrMap(m) =>
+        Ok(m.iter().map(|(k, v)| LispVal::list(vec![LispVal::string(k.clone()), v.clone()])).collect()),
+      _ => Err(format!("not a map: {}", self.print(e)))
     })
   }
 
@@ -321,6 +436,21 @@ impl Elaborator {
     s
   }
 
+  /// Returns the hypotheses and goals of the current proof context, as pairs of a label
+  /// (the hypothesis name, or `|-` for a goal) and the pretty printed statement.
+  pub fn stat_entries(&self) -> Vec<(String, String)> {
+    let mut out = vec![];
+    for (a, e, _) in &self.lc.proof_order {
+      out.push((self.print(a).to_string(), self.format_env().pp(e, 80).to_string()))
+    }
+    for e in &self.lc.goals {
+      e.unwrapped(|r| if let LispKind::Goal(e) = r {
+        out.push(("|-".into(), self.format_env().pp(e, 80).to_string()))
+      })
+    }
+    out
+  }
+
   fn head_err(&self, e: &LispKind) -> SResult<LispVal> {
     e.unwrapped(|e| match e {
       LispKind::List(es) if es.is_empty() => Err("evaluating 'hd ()'".into()),
@@ -508,6 +638,15 @@ pub struct Evaluator<'a> {
   /// each of which represent a context which awaiting a value from a sub-computation.
   stack: Vec<Stack>,
   call_stack: Vec<CallStack<'a>>,
+  /// The depth of the profiler stack when this evaluation started.
+  prof_depth: usize,
+}
+
+impl Drop for Evaluator<'_> {
+  fn drop(&mut self) {
+    // close the profiler frames of calls that were abandoned due to an error
+    if let Some(p) = &mut self.elab.profiler { p.exit_to(self.prof_depth) }
+  }
 }
 impl<'a> Deref for Evaluator<'a> {
   type Target = Elaborator;
@@ -517,6 +656,67 @@ impl<'a> DerefMut for Evaluator<'a> {
   fn deref_mut(&mut self) -> &mut Elaborator { self.elab }
 }
 
+fn proc_name(env: &Environment, pos: &ProcPos) -> String {
+  match *pos {
+    ProcPos::Named(_, _, a) => format!("({})", env.data[a].name),
+    ProcPos::Unnamed(_) => "[fn]".into(),
+    ProcPos::Builtin(p) => format!("({p})")
+  }
+}
+
+/// One frame of the lisp call stack, as presented to a debugger.
+#[derive(Debug)]
+pub struct DebugFrame<'b> {
+  /// The name of the procedure running in this frame, like `(foo)` or `[fn]`,
+  /// or `[top]` for the top level statement.
+  pub name: String,
+  /// The current location in this frame: the instruction being executed in the
+  /// innermost frame, and the call site in the others.
+  pub span: FileSpan,
+  /// The values of the local variables in this frame.
+  pub locals: &'b [LispVal],
+}
+
+impl Evaluator<'_> {
+  /// The number of procedure calls in progress.
+  #[must_use] pub fn depth(&self) -> usize { self.call_stack.len() }
+
+  /// The file containing the code currently being evaluated.
+  #[must_use] pub fn file(&self) -> &FileRef { &self.file }
+
+  /// The frames of the call stack, innermost first, where `sp` is the span
+  /// (in [`file`](Self::file)) of the instruction being executed.
+  #[must_use] pub fn frames(&self, sp: Span) -> Vec<DebugFrame<'_>> {
+    let name = |i: Option<usize>| i.map_or_else(|| "[top]".into(),
+      |i| proc_name(self, &self.call_stack[i].pos));
+    let mut frames = vec![DebugFrame {
+      name: name(self.call_stack.len().checked_sub(1)),
+      span: self.fspan(sp),
+      locals: &self.ctx,
+    }];
+    for (i, c) in self.call_stack.iter().enumerate().rev() {
+      frames.push(DebugFrame {
+        name: name(i.checked_sub(1)),
+        span: c.span.clone(),
+        locals: &c.parent_ctx,
+      })
+    }
+    frames
+  }
+
+  /// The entries of the evaluation stack, printed, from the top down.
+  #[must_use] pub fn print_stack(&self) -> Vec<String> {
+    self.stack.iter().rev().map(|e| self.print(e).to_string()).collect()
+  }
+
+  fn call_debug_hook(&mut self, sp: Span) {
+    if let Some(mut hook) = self.debug_hook.take() {
+      hook.0(self, sp);
+      self.debug_hook = Some(hook);
+    }
+  }
+}
+
 macro_rules! stack_match {
   (let $pat:pat = $e:expr) => {
     let $pat = $e else { panic!("stack type error") };
@@ -530,6 +730,7 @@ impl<'a> Evaluator<'a> {
   fn new(elab: &'a mut Elaborator, orig_span: Span, code: &'a [Ir]) -> Evaluator<'a> {
     // println!("new:\n{}", elab.print(&IrList(1, code)));
     let file = elab.path.clone();
+    let prof_depth = elab.profiler.as_ref().map_or(0, Profiler::depth);
     Evaluator {
       elab,
       ctx: vec![],
@@ -539,6 +740,7 @@ impl<'a> Evaluator<'a> {
       ip: 0,
       stack: vec![],
       call_stack: vec![],
+      prof_depth,
     }
   }
 
@@ -555,11 +757,7 @@ impl<'a> Evaluator<'a> {
     let mut old = sp.map(|(sp, good)| (self.fspan(sp), good, base));
     let mut info = vec![];
     for frame in self.call_stack.iter().rev() {
-      let x = match frame.pos {
-        ProcPos::Named(_, _, a) => format!("({})", self.data[a].name).into(),
-        ProcPos::Unnamed(_) => "[fn]".into(),
-        ProcPos::Builtin(p) => format!("({p})").into()
-      };
+      let x = proc_name(self, &frame.pos).into();
       if let Some((sp, good, base)) = old.take() {
         let (sp, osp) = if good {(sp, frame.span.clone())} else {(frame.span.clone(), sp)};
         info.push((osp, base));
@@ -624,7 +822,7 @@ impl<'a> Evaluator<'a> {
       LispKind::Undef => Ok(Some(old)),
       LispKind::AtomMap(newmap) => {
         if newmap.is_empty() { return Ok(Some(old)) }
-        let mut opt = Some(old.as_map_mut(mem::take).ok_or_else(||
+        let mut opt = Some(old.as_coll_mut(mem::take::<HashMap<AtomId, LispVal>>).ok_or_else(||
           self.err(Some((sp, false)), "merge-map: not an atom-map"))?);
         let oldmap = opt.as_mut().expect("impossible");
         let mut todo = vec![];
@@ -640,7 +838,7 @@ impl<'a> Evaluator<'a> {
         }
         if todo.is_empty() {
           Ok(Some({
-            if old.is_ref() && old.as_map_mut(|m| *m = opt.take().expect("impossible")).is_some() { old }
+            if old.is_ref() && old.as_coll_mut(|m| *m = opt.take().expect("impossible")).is_some() { old }
             else { LispVal::new(LispKind::AtomMap(opt.take().expect("impossible"))) }
           }))
         } else {
@@ -673,7 +871,7 @@ impl<'a> Evaluator<'a> {
       let Some(Stack::MergeMap(data)) = self.stack.pop() else { unreachable!() };
       let MergeMapData { mut old, map, .. } = *data;
       let mut opt = Some(map);
-      if !old.is_ref() || old.as_map_mut(|m| *m = opt.take().expect("impossible")).is_none() {
+      if !old.is_ref() || old.as_coll_mut(|m| *m = opt.take().expect("impossible")).is_none() {
         old = LispVal::new(LispKind::AtomMap(opt.take().expect("impossible")))
       }
       self.stack.push(old.into());
@@ -983,6 +1181,19 @@ make_builtins! { self, tail, sp1, sp2, args,
     }
     LispVal::string(out.into()).into()
   },
+  ReadFile: Exact(1) => {
+    let f = try1!(self.as_string(&args[0]));
+    LispVal::string(try1!(self.read_file(&String::from_utf8_lossy(&f)))).into()
+  },
+  WriteFile: Exact(2) => {
+    let (f, s) = (try1!(self.as_string(&args[0])), try1!(self.as_string(&args[1])));
+    try1!(self.write_file(&String::from_utf8_lossy(&f), &s));
+    Stack::Undef
+  },
+  FileExists: Exact(1) => {
+    let f = try1!(self.as_string(&args[0]));
+    try1!(self.sandbox_path(&String::from_utf8_lossy(&f))).is_some_and(|p| p.is_file()).into()
+  },
   Not: AtLeast(0) => (!args.iter().any(|e| e.truthy())).into(),
   And: AtLeast(0) => args.iter().all(|e| e.truthy()).into(),
   Or: AtLeast(0) => args.iter().any(|e| e.truthy()).into(),
@@ -1072,11 +1283,15 @@ make_builtins! { self, tail, sp1, sp2, args,
     LispVal::new_ref(LispVal::new(LispKind::AtomMap(m))).into()
   },
   Lookup: AtLeast(2) => {
-    match self.as_string_atom(&args[1]) {
+    let e = if args[0].is_str_map() {
+      self.as_string_key(&args[1]).map(|k| self.as_coll(&args[0], |m: &HashMap<ArcString, _>| Ok(m.get(&k).cloned())))
+    } else {
+      self.as_string_atom(&args[1]).map(|k| self.as_coll(&args[0], |m: &HashMap<AtomId, _>| Ok(m.get(&k).cloned())))
+    };
+    match e {
       None => Stack::Undef,
-      Some(k) => {
-        let e = try1!(self.as_map(&args[0], |m| Ok(m.get(&k).cloned())));
-        if let Some(e) = e {e} else {
+      Some(e) => {
+        if let Some(e) = try1!(e) {e} else {
           let v = args.get(2).cloned().unwrap_or_else(LispVal::undef);
           if v.is_proc() {
             let sp = v.fspan().map_or(sp2, |fsp| fsp.span);
@@ -1088,33 +1303,16 @@ make_builtins! { self, tail, sp1, sp2, args,
     }
   },
   Insert: AtLeast(2) => {
-    try1!(try1!(args[0].as_ref_mut(|r| {
-      r.as_map_mut(|m| -> SResult<_> {
-        let k = self.as_string_atom(&args[1])
-          .ok_or_else(|| format!("expected an atom, got {}", self.print(&args[1])))?;
-        match args.get(2) {
-          Some(v) => {m.insert(k, v.clone());}
-          None => {m.remove(&k);}
-        }
-        Ok(())
-      })
-    }).unwrap_or(None).ok_or("expected a mutable map")));
+    try1!(try1!(args[0].as_ref_mut(|r| self.map_insert(r, &args[1], args.get(2).cloned()))
+      .flatten().ok_or("expected a mutable map")));
     Stack::Undef
   },
   InsertNew: AtLeast(2) => {
     let mut it = args.into_iter();
     let mut m = it.next().unwrap();
     let k = it.next().unwrap();
-    let k = self.as_string_atom(&k)
-      .ok_or_else(|| format!("expected an atom, got {}", self.print(&k)));
-    try1!(try1!(m.as_map_mut(|m| -> SResult<_> {
-      match it.next() {
-        Some(v) => {m.insert(k?, v);}
-        None => {m.remove(&k?);}
-      }
-      Ok(())
-    }).ok_or("expected a map")));
-    Stack::Undef
+    try1!(try1!(self.map_insert(&mut m, &k, it.next()).ok_or("expected a map")));
+    m.into()
   },
   MergeMap: AtLeast(0) => {
     let mut it = args.into_iter();
@@ -1128,6 +1326,62 @@ make_builtins! { self, tail, sp1, sp2, args,
       } else { LispVal::proc(Proc::MergeMap(arg1.into_merge_strategy())) }
     } else { LispVal::proc(Proc::MergeMap(None)) }.into()
   },
+  IsStrMap: Exact(1) => args[0].is_str_map().into(),
+  NewStrMap: AtLeast(0) => {
+    let mut m = HashMap::new();
+    for e in args {
+      let mut u = Uncons::from(e);
+      let e = try1!(u.next().ok_or("invalid arguments"));
+      let k = try1!(self.as_string_key(&e)
+        .ok_or_else(|| format!("expected a string, got {}", self.print(&e))));
+      let ret = u.next();
+      if !u.exactly(0) {try1!(Err("invalid arguments"))}
+      if let Some(v) = ret {m.insert(k, v);} else {m.remove(&k);}
+    }
+    LispVal::new_ref(LispVal::new(LispKind::StrMap(m))).into()
+  },
+  MapLen: Exact(1) => LispVal::number(try1!(self.map_to_list(&args[0])).len().into()).into(),
+  MapToList: Exact(1) => LispVal::list(try1!(self.map_to_list(&args[0]))).into(),
+  IsVector: Exact(1) => args[0].is_vector().into(),
+  NewVector: AtLeast(0) => LispVal::new_ref(LispVal::new(LispKind::Vector(args))).into(),
+  MakeVector: AtLeast(1) => {
+    let n = try1!(args[0].as_int(BigInt::to_usize).flatten().ok_or("expected a number"));
+    let v = args.get(1).cloned().unwrap_or_else(LispVal::undef);
+    LispVal::new_ref(LispVal::new(LispKind::Vector(vec![v; n]))).into()
+  },
+  VectorLen: Exact(1) =>
+    LispVal::number(try1!(self.as_coll(&args[0], |v: &Vec<_>| Ok(v.len()))).into()).into(),
+  VectorNth: Exact(2) => {
+    let n = try1!(args[0].as_int(|n| n.to_usize().unwrap_or(usize::MAX))
+      .ok_or("expected a number"));
+    try1!(self.as_coll(&args[1], |v: &Vec<_>| Ok(v.get(n).cloned()))).unwrap_or_else(LispVal::undef).into()
+  },
+  VectorSet: Exact(3) => {
+    let n = try1!(args[1].as_int(|n| n.to_usize().unwrap_or(usize::MAX))
+      .ok_or("expected a number"));
+    try1!(args[0].as_vector_mut(|v| {
+      let len = v.len();
+      *v.get_mut(n).ok_or_else(|| format!("index {n} out of range for vector of length {len}"))? =
+        args[2].clone();
+      Ok(())
+    }));
+    Stack::Undef
+  },
+  VectorPush: Exact(2) => {
+    try1!(args[0].as_vector_mut(|v| {v.push(args[1].clone()); Ok(())}));
+    Stack::Undef
+  },
+  VectorPop: Exact(1) =>
+    try1!(args[0].as_vector_mut(|v| Ok(v.pop()))).unwrap_or_else(LispVal::undef).into(),
+  VectorToList: Exact(1) => LispVal::list(try1!(self.as_coll(&args[0], |v: &Vec<_>| Ok(v.clone())))).into(),
+  ListToVector: Exact(1) => {
+    let mut u = Uncons::new(args[0].clone());
+    let out = (&mut u).collect::<Vec<_>>();
+    if !u.is_empty() {
+      try1!(Err(format!("list->vector: not a list: {}", self.print(&args[0]))))
+    }
+    LispVal::new_ref(LispVal::new(LispKind::Vector(out))).into()
+  },
   SetTimeout: Exact(1) => {
     match try1!(args[0].as_int(BigInt::to_u64).ok_or("expected a number")) {
       None | Some(0) => {self.timeout = None; self.cur_timeout = None},
@@ -1358,6 +1612,10 @@ make_builtins! { self, tail, sp1, sp2, args,
     if args.len() > 2 {try1!(Err("too many arguments"))}
     args.into_iter().nth(1).unwrap().into()
   },
+  Simp: Exact(2) => try1!(self.simp(sp1, &args[0], &args[1])).into(),
+  #[cfg(feature = "mmc")]
+  NormNum: Exact(2) => try1!(self.norm_num(&args[0], &args[1])).into(),
+  Tauto: Exact(2) => try1!(self.tauto(&args[0], &args[1])).into(),
   EvalString: AtLeast(0) => {
     let fsp = self.fspan(sp1);
     let bytes = self.eval_string(&fsp, &args)?;
@@ -1416,6 +1674,12 @@ impl<'a> Evaluator<'a> {
     //   println!();
     // }
     if let Some(fsp) = pos.fspan() { self.file = fsp.file.clone() }
+    let replace = tail && !self.call_stack.is_empty();
+    let elab = &mut *self.elab;
+    if let Some(p) = &mut elab.profiler {
+      if replace { p.exit() }
+      p.enter(proc_name(&elab.env, &pos))
+    }
     if tail {
       if let Some(frame) = self.call_stack.last_mut() {
         self.code = code;
@@ -1438,6 +1702,7 @@ impl<'a> Evaluator<'a> {
 
   fn ret(&mut self) {
     let frame = self.call_stack.pop().expect("underflow");
+    if let Some(p) = &mut self.elab.profiler { p.exit() }
     self.file = frame.span.file;
     self.code = frame.parent_code;
     self.ctx = frame.parent_ctx;
@@ -1534,6 +1799,7 @@ impl<'a> Evaluator<'a> {
           let ret = c.borrow_mut().call(self, sp, args)?;
           self.stack.push(ret.into())
         }
+        Proc::Macro(_) => throw!(sp.1, "a macro cannot be called at run time"),
       }
       Ok(())
     })
@@ -1552,7 +1818,10 @@ impl<'a> Evaluator<'a> {
   fn call_refine(&mut self, tail: bool, state: RState) -> Result<()> {
     let val = self.stack.last_mut().expect("underflow");
     stack_match!(let Stack::Refine(sp, ref mut stack) = *val);
-    match self.elab.run_refine(self.orig.span, stack, state) {
+    if let Some(p) = &mut self.elab.profiler { p.enter("[refine]".into()) }
+    let res = self.elab.run_refine(self.orig.span, stack, state);
+    if let Some(p) = &mut self.elab.profiler { p.exit() }
+    match res {
       Err(e) => return Err(self.err(Some((e.pos, true)), e.kind.msg())),
       Ok(RefineResult::Ret(e)) => {
         self.elab.lc.clean_mvars();
@@ -1716,8 +1985,9 @@ impl<'a> Evaluator<'a> {
         Ir::Local(_) | Ir::Global(..) | Ir::Const(_) | Ir::List(..) | Ir::DottedList(_) |
         Ir::App(..) | Ir::BuiltinApp(..) | Ir::AppHead(_) | Ir::JumpUnless(_) | Ir::Jump(_) |
         Ir::ArityError(..) | Ir::FocusStart(_) | Ir::RefineGoal(_) | Ir::FocusFinish |
-        Ir::SetMergeStrategy(..) | Ir::LocalDef(_) | Ir::GlobalDef(..) | Ir::SetDoc(..) |
-        Ir::Lambda(..) | Ir::Branch(..) | Ir::TestPatternResume | Ir::BranchFail(_) |
+        Ir::SetMergeStrategy(..) | Ir::Export(_) | Ir::Module(..) | Ir::LocalDef(_) |
+        Ir::GlobalDef(..) | Ir::SetDoc(..) | Ir::MakeMacro(_) | Ir::Lambda(..) | Ir::Branch(..) | Ir::TestPatternResume |
+        Ir::BranchFail(_) |
         Ir::Map | Ir::Have | Ir::RefineResume | Ir::AddThm | Ir::MergeMap
         => panic!("unexpected in pattern mode"),
       };
@@ -1957,6 +2227,9 @@ impl<'a> Evaluator<'a> {
       //   }
       // }
       if let Some(ir) = self.code.get(self.ip) {
+        if self.debug_hook.is_some() {
+          if let Some(sp) = ir.step_span() { self.call_debug_hook(sp) }
+        }
         self.ip += 1;
         match *ir {
           Ir::Drop(n) => self.stack.truncate(self.stack.len() - n),
@@ -2012,12 +2285,29 @@ impl<'a> Evaluator<'a> {
             throw!(sp, format!("unknown definition '{}', cannot set merge strategy",
               self.print(&a)))
           }
+          Ir::Export(ref xs) => self.elab.module_mut().exports.extend(xs.iter().copied()),
+          Ir::Module(sp, a) => {
+            let name = self.elab.data[a].name.clone();
+            let m = self.elab.module_mut();
+            match &m.prefix {
+              Some(prefix) if *prefix != name =>
+                throw!(sp, format!("module prefix is already set to '{prefix}'")),
+              _ => m.prefix = Some(name),
+            }
+          }
           Ir::LocalDef(n) => {
             assert!(self.ctx.len() == n);
             let ret = self.pop_lisp();
             self.ctx.push(ret);
           }
           Ir::GlobalDef(sp1, sp2, a) => self.global_def(sp1, sp2, a)?,
+          Ir::MakeMacro(sp) => {
+            let f = self.pop_lisp();
+            let ret = if f.unwrapped(|e| matches!(e, LispKind::Proc(Proc::Macro(_)))) { f }
+            else if f.is_proc() { LispVal::proc(Proc::Macro(Macro::Proc(f))) }
+            else { throw!(sp, format!("defmacro: expected a procedure, got {}", self.print(&f))) };
+            self.stack.push(ret.into())
+          }
           Ir::SetDoc(ref doc, a) => if let Some(data) = &mut self.data[a].lisp {
             if data.val.is_def_strict() { data.doc = Some(doc.clone()) }
           }
@@ -6,11 +6,11 @@ use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
 use std::collections::HashMap;
 use num::{BigInt, ToPrimitive};
-use crate::ast::{SExpr, SExprKind, Atom};
+use crate::ast::{SExpr, SExprKind, Atom, Formula};
 use crate::ArcString;
 use super::super::{AtomId, Span, DocComment, Elaborator, ElabError, ObjectKind};
-use super::{BuiltinProc, FileSpan, LispKind, LispVal, PatternSyntax, Proc, ProcSpec,
-  Remap, Remapper, Syntax};
+use super::{Annot, BuiltinProc, FileSpan, LispKind, LispVal, Macro, PatternSyntax, Proc, ProcSpec,
+  Remap, Remapper, Syntax, SyntaxRules};
 use super::super::math_parser::{QExpr, QExprKind};
 use super::print::{FormatEnv, EnvDisplay};
 
@@ -83,6 +83,12 @@ pub enum Ir {
   /// The `(set-merge-strategy)` function, which is a macro because it directly binds
   /// to a global name. `[e] -> []`
   SetMergeStrategy(Span, AtomId),
+  /// The `(export xs)` syntax form, which adds `xs` to the exports of the current file.
+  /// Does not touch the stack.
+  Export(Box<[AtomId]>),
+  /// The `(module x)` syntax form, which sets the module prefix of the current file.
+  /// Does not touch the stack.
+  Module(Span, AtomId),
   /// The `(def x e)` syntax form, not at global scope. Get the argument from the stack,
   /// and extend the context with the result. `[e] -> []`, assert `n` is the context length.
   LocalDef(usize),
@@ -91,6 +97,9 @@ pub enum Ir {
   GlobalDef(Span, Span, AtomId),
   /// Set the doc comment for a variable. Does not touch the stack.
   SetDoc(DocComment, AtomId),
+  /// The `(defmacro x e)` syntax form. Turn the procedure on the stack into a macro,
+  /// before it is stored with `GlobalDef`. `[f] -> [macro(f)]`
+  MakeMacro(Span),
   /// The `(fn xs e)` syntax form. Create a closure from the current context, and return
   /// it, using the provided [`ProcSpec`] and code. It can later be called by the
   /// [`App`](Self::App) instruction.
@@ -212,6 +221,19 @@ impl Ir {
     if keep { Ir::DropAbove(n) } else { Ir::Drop(n) }
   }
 
+  /// If this instruction is a step point for the debugger, return its span.
+  /// These are the instructions that correspond to the evaluation of a user-visible
+  /// expression, mostly function applications.
+  pub(crate) fn step_span(&self) -> Option<Span> {
+    match *self {
+      Ir::App(_, ref sp, _) | Ir::BuiltinApp(_, _, ref sp, _) => Some(sp.0),
+      Ir::ArityError(sp, _) | Ir::AppHead(sp) | Ir::FocusStart(sp) |
+      Ir::GlobalDef(sp, _, _) | Ir::SetMergeStrategy(sp, _) | Ir::Module(sp, _) |
+      Ir::BranchFail(sp) => Some(sp),
+      _ => None,
+    }
+  }
+
   fn fmt_list(code: &[Ir], depth: usize, fe: FormatEnv<'_>, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     for (i, ir) in code.iter().enumerate() {
       for _ in 0..depth { f.write_str("  ")? }
@@ -248,9 +270,16 @@ impl Ir {
       Ir::RefineGoal(true) => write!(f, "refine"),
       Ir::FocusFinish => write!(f, "focus-finish"),
       Ir::SetMergeStrategy(_, a) => write!(f, "set-merge-strategy {}", fe.to(&a)),
+      Ir::Export(ref xs) => {
+        write!(f, "export")?;
+        for a in &**xs { write!(f, " {}", fe.to(a))? }
+        Ok(())
+      }
+      Ir::Module(_, a) => write!(f, "module {}", fe.to(&a)),
       Ir::LocalDef(n) => write!(f, "def x{n}"),
       Ir::GlobalDef(_, _, a) => write!(f, "def {}", fe.to(&a)),
       Ir::SetDoc(_, a) => write!(f, "set-doc _ {}", fe.to(&a)),
+      Ir::MakeMacro(_) => write!(f, "make-macro"),
       Ir::Lambda(n, ref args) => {
         write!(f, "lambda{} ", if n == u8::MAX {""} else {"-global"})?;
         match args.1 {
@@ -326,11 +355,11 @@ impl Remap for Ir {
   type Target = Self;
   fn remap(&self, r: &mut Remapper) -> Self {
     match self {
-      &Ir::Global(sp, a) => Ir::Global(sp, a.remap(r)),
+      &Ir::Global(sp, a) => Ir::Global(sp, r.global(a)),
       // Safety: The input Ir is already frozen
       Ir::Const(v) => Ir::Const(unsafe { v.freeze() }.remap(r)),
-      &Ir::SetMergeStrategy(sp, a) => Ir::SetMergeStrategy(sp, a.remap(r)),
-      &Ir::GlobalDef(sp, sp2, a) => Ir::GlobalDef(sp, sp2, a.remap(r)),
+      &Ir::SetMergeStrategy(sp, a) => Ir::SetMergeStrategy(sp, r.global(a)),
+      &Ir::GlobalDef(sp, sp2, a) => Ir::GlobalDef(sp, sp2, r.global(a)),
       &Ir::Lambda(name, ref args) => Ir::Lambda(name, Box::new((args.0, args.1, args.2.remap(r)))),
       &Ir::PatternQuoteAtom(a) => Ir::PatternQuoteAtom(a.remap(r)),
       &Ir::PatternQExprAtom(a) => Ir::PatternQExprAtom(a.remap(r)),
@@ -374,10 +403,17 @@ impl LocalCtx {
   }
 }
 
+/// The maximum number of nested macro expansions, to catch macros that expand to themselves.
+const MAX_EXPANSION_DEPTH: usize = 256;
+
 struct LispParser<'a> {
   elab: &'a mut Elaborator,
   ctx: LocalCtx,
   code: Vec<Ir>,
+  /// Maps the fresh atoms generated by `syntax-rules` expansion to the atoms they rename.
+  renames: HashMap<AtomId, AtomId>,
+  /// The number of macro expansions we are currently inside.
+  depth: usize,
 }
 impl<'a> Deref for LispParser<'a> {
   type Target = Elaborator;
@@ -387,12 +423,111 @@ impl<'a> DerefMut for LispParser<'a> {
   fn deref_mut(&mut self) -> &mut Elaborator { self.elab }
 }
 
-enum Item<'a> {
-  List(&'a [SExpr]),
-  DottedList(&'a [SExpr], &'a SExpr),
+/// A piece of lisp code to be compiled. This is usually an [`SExpr`] from the source,
+/// but the result of a macro expansion is a [`LispVal`], with span annotations
+/// on every subterm (see [`code_of`]).
+trait Code: Sized {
+  /// The location of the code in the current file.
+  fn span(&self) -> Span;
+  /// Get the shape of the code, parsing atoms into identifiers or keywords.
+  fn view<'c>(&'c self, p: &mut LispParser<'_>) -> CodeKind<'c, Self>;
+}
+
+/// The shape of a piece of [`Code`]. This mirrors [`SExprKind`].
+enum CodeKind<'c, E> {
+  /// An identifier, or a keyword.
+  Atom(Result<AtomId, Syntax>),
+  /// A proper list `(a b c)`.
+  List(&'c [E]),
+  /// A dotted list `(a b c . d)`.
+  DottedList(&'c [E], &'c E),
+  /// A number literal.
+  Number(BigInt),
+  /// A string literal.
+  String(ArcString),
+  /// A boolean literal.
+  Bool(bool),
+  /// The `#undef` literal.
+  Undef,
+  /// A doc comment on another expression.
+  DocComment(&'c str, &'c E),
+  /// A math formula.
+  Formula(Formula),
+  /// A value which is not an s-expression, spliced into the code by a macro.
+  /// It evaluates to itself.
+  Const(LispVal),
+}
+
+impl Code for SExpr {
+  fn span(&self) -> Span { self.span }
+  fn view<'c>(&'c self, p: &mut LispParser<'_>) -> CodeKind<'c, Self> {
+    match &self.k {
+      &SExprKind::Atom(a) => CodeKind::Atom(p.parse_ident_or_syntax(self.span, a)),
+      SExprKind::List(es) => CodeKind::List(es),
+      SExprKind::DottedList(es, e) => CodeKind::DottedList(es, e),
+      SExprKind::Number(n) => CodeKind::Number(n.clone().into()),
+      SExprKind::String(s) => CodeKind::String(s.clone()),
+      &SExprKind::Bool(b) => CodeKind::Bool(b),
+      SExprKind::Undef => CodeKind::Undef,
+      SExprKind::DocComment(doc, e) => CodeKind::DocComment(doc, e),
+      &SExprKind::Formula(f) => CodeKind::Formula(f),
+    }
+  }
+}
+
+impl Code for LispVal {
+  fn span(&self) -> Span {
+    if let LispKind::Annot(Annot::Span(fsp), _) = &**self { fsp.span } else { Span::default() }
+  }
+  fn view<'c>(&'c self, p: &mut LispParser<'_>) -> CodeKind<'c, Self> {
+    let mut e = &**self;
+    while let LispKind::Annot(_, e2) = e { e = e2 }
+    match e {
+      &LispKind::Atom(a) => CodeKind::Atom(match Syntax::from_bytes(&p.data[a].name) {
+        Some(s) => Err(s),
+        None => Ok(a),
+      }),
+      &LispKind::Syntax(s) => CodeKind::Atom(Err(s)),
+      LispKind::List(es) => CodeKind::List(es),
+      LispKind::DottedList(es, e) => CodeKind::DottedList(es, e),
+      LispKind::Number(n) => CodeKind::Number(n.clone()),
+      LispKind::String(s) => CodeKind::String(s.clone()),
+      &LispKind::Bool(b) => CodeKind::Bool(b),
+      LispKind::Undef => CodeKind::Undef,
+      _ => CodeKind::Const(self.clone()),
+    }
+  }
+}
+
+/// Prepare the result of a macro expansion for compilation, by flattening nested lists
+/// and putting a span annotation in the current file on every subterm. Subterms that come from the arguments of the
+/// macro keep their spans, and the rest use the span of their parent, or `fsp` at the root.
+fn code_of(fsp: &FileSpan, e: &LispVal) -> LispVal {
+  let fsp = match e.fspan() {
+    Some(fsp2) if fsp2.file == fsp.file => fsp2,
+    _ => fsp.clone(),
+  };
+  if is_list(e) {
+    let (es, r) = list_parts(e);
+    let es = es.iter().map(|e| code_of(&fsp, e)).collect::<Box<[_]>>();
+    return match r {
+      None => LispVal::list(es),
+      Some(r) => LispVal::dotted_list(es, code_of(&fsp, &r)),
+    }.span(fsp)
+  }
+  e.unwrapped(|k| match *k {
+    LispKind::Atom(a) => LispVal::atom(a),
+    LispKind::Syntax(s) => LispVal::syntax(s),
+    _ => e.clone(),
+  }).span(fsp)
+}
+
+enum Item<'a, E> {
+  List(&'a [E]),
+  DottedList(&'a [E], &'a E),
 }
 
-type Var<'a> = (Span, AtomId, Vec<Item<'a>>);
+type Var<'a, E> = (Span, AtomId, Vec<Item<'a, E>>);
 
 #[derive(Clone, Copy)]
 enum ExprsCtx {
@@ -425,7 +560,7 @@ impl ExprCtx {
 
 impl<'a> LispParser<'a> {
   fn new(elab: &'a mut Elaborator) -> Self {
-    Self { elab, ctx: LocalCtx::new(), code: vec![] }
+    Self { elab, ctx: LocalCtx::new(), code: vec![], renames: HashMap::new(), depth: 0 }
   }
 
   fn push_def(&mut self,
@@ -434,7 +569,7 @@ impl<'a> LispParser<'a> {
     if global && x != AtomId::UNDER {
       for (i, ir) in self.code.iter_mut().rev().enumerate() {
         match ir {
-          Ir::AssertScope(_) | Ir::EndScope(_) => {}
+          Ir::AssertScope(_) | Ir::EndScope(_) | Ir::MakeMacro(_) => {}
           Ir::Lambda(name, _) => {
             if let Ok(i) = i.try_into() { *name = i }
             break
@@ -464,22 +599,23 @@ impl<'a> LispParser<'a> {
     }
   }
 
-  fn def_var<'c>(&mut self, mut e: &'c SExpr) -> Result<Var<'c>, ElabError> {
+  fn def_var<'c, E: Code>(&mut self, mut e: &'c E) -> Result<Var<'c, E>, ElabError> {
     let mut stack = vec![];
     loop {
-      match &e.k {
-        &SExprKind::Atom(a) => break Ok((e.span, self.parse_atom(e.span, a)?, stack)),
-        SExprKind::List(xs) if !xs.is_empty() =>
+      match e.view(self) {
+        CodeKind::Atom(Ok(x)) => break Ok((e.span(), x, stack)),
+        CodeKind::Atom(Err(_)) => return Err(ElabError::new_e(e.span(), "keyword in invalid position")),
+        CodeKind::List(xs) if !xs.is_empty() =>
           {stack.push(Item::List(&xs[1..])); e = &xs[0]}
-        SExprKind::DottedList(xs, y) if !xs.is_empty() =>
+        CodeKind::DottedList(xs, y) if !xs.is_empty() =>
           {stack.push(Item::DottedList(&xs[1..], y)); e = &xs[0]}
-        _ => return Err(ElabError::new_e(e.span, "def: invalid spec"))
+        _ => return Err(ElabError::new_e(e.span(), "def: invalid spec"))
       }
     }
   }
 
-  fn def_ir(&mut self,
-    sp: Span, keep: bool, tail: bool, es: &[SExpr], stack: Vec<Item<'_>>
+  fn def_ir<E: Code>(&mut self,
+    sp: Span, keep: bool, tail: bool, es: &[E], stack: Vec<Item<'_, E>>
   ) -> Result<(), ElabError> {
     let lambdas = stack.len();
     if !keep && lambdas != 0 { return Ok(()) }
@@ -492,7 +628,7 @@ impl<'a> LispParser<'a> {
         Item::DottedList(xs, y) => {
           let xs = self.parse_idents(xs)?;
           self.ctx.push_list(&xs);
-          let y = self.parse_ident(y)?;
+          let y = self.parse_ident(*y)?;
           self.ctx.push(y);
         }
       }
@@ -523,7 +659,9 @@ impl<'a> LispParser<'a> {
     Ok(())
   }
 
-  fn def(&mut self, global: bool, tail: bool, e: &SExpr, es: &[SExpr]) -> Result<(Span, AtomId), ElabError> {
+  fn def<E: Code>(&mut self,
+    global: bool, tail: bool, e: &E, es: &[E]
+  ) -> Result<(Span, AtomId), ElabError> {
     let (sp, x, stack) = self.def_var(e)?;
     self.spans.insert(sp, if global {
       ObjectKind::Global(true, !stack.is_empty(), x)
@@ -604,26 +742,21 @@ impl<'a> LispParser<'a> {
     }
   }
 
-  fn parse_atom(&mut self, sp: Span, a: Atom) -> Result<AtomId, ElabError> {
-    self.parse_ident_or_syntax(sp, a).map_err(|_|
-      ElabError::new_e(sp, "keyword in invalid position"))
-  }
-
-  fn parse_ident_raw(&mut self, e: &SExpr) -> Result<AtomId, ElabError> {
-    if let SExprKind::Atom(a) = e.k {
-      self.parse_atom(e.span, a)
-    } else {
-      Err(ElabError::new_e(e.span, "expected an identifier"))
+  fn parse_ident_raw<E: Code>(&mut self, e: &E) -> Result<AtomId, ElabError> {
+    match e.view(self) {
+      CodeKind::Atom(Ok(x)) => Ok(x),
+      CodeKind::Atom(Err(_)) => Err(ElabError::new_e(e.span(), "keyword in invalid position")),
+      _ => Err(ElabError::new_e(e.span(), "expected an identifier"))
     }
   }
 
-  fn parse_ident(&mut self, e: &SExpr) -> Result<AtomId, ElabError> {
+  fn parse_ident<E: Code>(&mut self, e: &E) -> Result<AtomId, ElabError> {
     let x = self.parse_ident_raw(e)?;
-    self.spans.insert(e.span, ObjectKind::LispVar(true, false, x));
+    self.spans.insert(e.span(), ObjectKind::LispVar(true, false, x));
     Ok(x)
   }
 
-  fn parse_idents(&mut self, es: &[SExpr]) -> Result<Vec<AtomId>, ElabError> {
+  fn parse_idents<E: Code>(&mut self, es: &[E]) -> Result<Vec<AtomId>, ElabError> {
     let mut xs = vec![];
     for e in es {xs.push(self.parse_ident(e)?)}
     Ok(xs)
@@ -662,7 +795,7 @@ impl<'a> LispParser<'a> {
     Ok(())
   }
 
-  fn exprs(&mut self, ctx: ExprsCtx, es: &[SExpr]) -> Result<usize, ElabError> {
+  fn exprs<E: Code>(&mut self, ctx: ExprsCtx, es: &[E]) -> Result<usize, ElabError> {
     match ctx {
       ExprsCtx::Eval(keep, tail) => {
         if let [es @ .., last] = es {
@@ -702,24 +835,26 @@ impl<'a> LispParser<'a> {
     }
   }
 
-  fn let_var<'c>(&mut self, e: &'c SExpr) -> Result<(Var<'c>, &'c [SExpr]), ElabError> {
-    match &e.k {
-      SExprKind::List(es) if !es.is_empty() => {
+  fn let_var<'c, E: Code>(&mut self, e: &'c E) -> Result<(Var<'c, E>, &'c [E]), ElabError> {
+    match e.view(self) {
+      CodeKind::List(es) if !es.is_empty() => {
         let (sp, x, stk) = self.def_var(&es[0])?;
         self.spans.insert(sp, ObjectKind::LispVar(true, !stk.is_empty(), x));
         Ok(((sp, x, stk), &es[1..]))
       }
-      _ => Err(ElabError::new_e(e.span, "let: invalid spec"))
+      _ => Err(ElabError::new_e(e.span(), "let: invalid spec"))
     }
   }
 
-  fn let_(&mut self, mut rec: bool, keep: bool, tail: bool, es: &[SExpr]) -> Result<(), ElabError> {
+  fn let_<E: Code>(&mut self,
+    mut rec: bool, keep: bool, tail: bool, es: &[E]
+  ) -> Result<(), ElabError> {
     if es.is_empty() {
       if keep { self.code.push(Ir::Undef) }
       return Ok(())
     }
-    let SExprKind::List(ls) = &es[0].k else {
-      return Err(ElabError::new_e(es[0].span, "let: invalid spec"))
+    let CodeKind::List(ls) = es[0].view(self) else {
+      return Err(ElabError::new_e(es[0].span(), "let: invalid spec"))
     };
     rec &= !ls.is_empty();
     if rec {
@@ -756,8 +891,8 @@ impl<'a> LispParser<'a> {
     Ok(())
   }
 
-  fn finish_dotted_list_pattern(&mut self,
-    ctx: &mut LocalCtx, quote: bool, pfx: &[SExpr]
+  fn finish_dotted_list_pattern<E: Code>(&mut self,
+    ctx: &mut LocalCtx, quote: bool, pfx: &[E]
   ) -> Result<(), ElabError> {
     if !pfx.is_empty() {
       self.code.push(Ir::PatternDottedList(pfx.len()));
@@ -766,8 +901,8 @@ impl<'a> LispParser<'a> {
     Ok(())
   }
 
-  fn list_pattern(&mut self,
-    ctx: &mut LocalCtx, quote: bool, es: &[SExpr]
+  fn list_pattern<E: Code>(&mut self,
+    ctx: &mut LocalCtx, quote: bool, es: &[E]
   ) -> Result<(), ElabError> {
     let mut pfx = 0;
     macro_rules! finish {($e:expr) => {{
@@ -776,122 +911,124 @@ impl<'a> LispParser<'a> {
       return Ok(())
     }}}
     loop {
-      match es[pfx..] {
-        [] => {
-          self.code.push(Ir::PatternList(pfx, None));
-          for e in &es[..pfx] { self.pattern(ctx, quote, e)? }
-          return Ok(())
-        }
-        [SExpr {span, k: SExprKind::Atom(a)}, ref e] if quote =>
-          if self.ast.span_atom(span, a) == b"unquote" {
+      let [head, args @ ..] = &es[pfx..] else {
+        self.code.push(Ir::PatternList(pfx, None));
+        for e in &es[..pfx] { self.pattern(ctx, quote, e)? }
+        return Ok(())
+      };
+      if quote {
+        if let [e] = args {
+          if self.atom_name(head).as_deref() == Some(b"unquote") {
             finish!(self.pattern(ctx, false, e)?)
           }
-        _ if quote => {},
-        [ref head, ref args @ ..] => if let SExprKind::Atom(a) = head.k {
-          match self.ast.span_atom(head.span, a) {
-            b"quote" => {
-              self.spans.insert(head.span, ObjectKind::Syntax(Syntax::Quote));
-              if let [e] = args { finish!(self.pattern(ctx, true, e)?) }
-              return Err(ElabError::new_e(head.span, "expected one argument"))
-            }
-            b"mvar" => {
-              self.spans.insert(head.span, ObjectKind::PatternSyntax(PatternSyntax::MVar));
-              match args {
-                [] => finish!(self.code.push(Ir::PatternMVar(MVarPattern::Unknown))),
-                &[SExpr {span, k: SExprKind::Atom(a)}]
-                if matches!(self.ast.span_atom(span, a), b"___" | b"...") =>
-                  finish!(self.code.push(Ir::PatternMVar(MVarPattern::Any))),
-                [bd, s] => finish!({
-                  self.code.push(Ir::PatternMVar(MVarPattern::Simple));
-                  self.pattern(ctx, quote, bd)?;
-                  self.pattern(ctx, quote, s)?;
-                }),
-                _ => return Err(ElabError::new_e(head.span, "expected zero or two arguments")),
-              }
+        }
+      } else if let Some(name) = self.atom_name(head) {
+        let hsp = head.span();
+        match &*name {
+          b"quote" => {
+            self.spans.insert(hsp, ObjectKind::Syntax(Syntax::Quote));
+            if let [e] = args { finish!(self.pattern(ctx, true, e)?) }
+            return Err(ElabError::new_e(hsp, "expected one argument"))
+          }
+          b"mvar" => {
+            self.spans.insert(hsp, ObjectKind::PatternSyntax(PatternSyntax::MVar));
+            match args {
+              [] => finish!(self.code.push(Ir::PatternMVar(MVarPattern::Unknown))),
+              [e] if matches!(self.atom_name(e).as_deref(), Some(b"___" | b"...")) =>
+                finish!(self.code.push(Ir::PatternMVar(MVarPattern::Any))),
+              [bd, s] => finish!({
+                self.code.push(Ir::PatternMVar(MVarPattern::Simple));
+                self.pattern(ctx, quote, bd)?;
+                self.pattern(ctx, quote, s)?;
+              }),
+              _ => return Err(ElabError::new_e(hsp, "expected zero or two arguments")),
             }
-            b"goal" => {
-              self.spans.insert(head.span, ObjectKind::PatternSyntax(PatternSyntax::Goal));
-              if let [e] = args {
-                finish!({
-                  self.code.push(Ir::PatternGoal);
-                  self.pattern(ctx, quote, e)?
-                })
-              }
-              return Err(ElabError::new_e(head.span, "expected one argument"))
-            },
-            b"and" => finish!({
-              self.spans.insert(head.span, ObjectKind::PatternSyntax(PatternSyntax::And));
-              self.patterns_and(ctx, args)?
-            }),
-            b"or" => finish!({
-              self.spans.insert(head.span, ObjectKind::PatternSyntax(PatternSyntax::Or));
-              self.patterns_or(ctx, args)?
-            }),
-            b"not" => finish!({
-              self.spans.insert(head.span, ObjectKind::PatternSyntax(PatternSyntax::Not));
-              let patch = self.new_patch();
-              self.patterns_and(ctx, args)?;
-              self.finish_patch(patch, |ip| Ir::PatternTry(ip, ip + 1));
-              self.code.push(Ir::PatternResult(false))
-            }),
-            b"?" => {
-              self.spans.insert(head.span, ObjectKind::PatternSyntax(PatternSyntax::Test));
-              if let [test, tail @ ..] = args {
-                finish!({
-                  self.code.push(Ir::PatternTestPause);
-                  self.expr(ExprCtx::EVAL.mask_def(), test)?;
-                  if let Some(p) = self.pop_builtin() {
-                    self.code.push(Ir::BuiltinApp(false, p, Box::new((test.span, test.span)), 1));
-                  } else {
-                    self.code.push(Ir::AppHead(test.span));
-                  }
-                  self.code.push(Ir::TestPatternResume);
-                  self.patterns_and(ctx, tail)?
-                })
-              }
-              return Err(ElabError::new_e(head.span, "expected at least one argument"))
+          }
+          b"goal" => {
+            self.spans.insert(hsp, ObjectKind::PatternSyntax(PatternSyntax::Goal));
+            if let [e] = args {
+              finish!({
+                self.code.push(Ir::PatternGoal);
+                self.pattern(ctx, quote, e)?
+              })
             }
-            b"cons" => {
-              self.spans.insert(head.span, ObjectKind::PatternSyntax(PatternSyntax::Cons));
-              if let [es2 @ .., e] = args {
-                if es2.len() + pfx != 0 {
-                  self.code.push(Ir::PatternDottedList(es2.len() + pfx));
-                  for e in &es[..pfx] { self.pattern(ctx, quote, e)? }
-                  for e in es2 { self.pattern(ctx, quote, e)? }
+            return Err(ElabError::new_e(hsp, "expected one argument"))
+          },
+          b"and" => finish!({
+            self.spans.insert(hsp, ObjectKind::PatternSyntax(PatternSyntax::And));
+            self.patterns_and(ctx, args)?
+          }),
+          b"or" => finish!({
+            self.spans.insert(hsp, ObjectKind::PatternSyntax(PatternSyntax::Or));
+            self.patterns_or(ctx, args)?
+          }),
+          b"not" => finish!({
+            self.spans.insert(hsp, ObjectKind::PatternSyntax(PatternSyntax::Not));
+            let patch = self.new_patch();
+            self.patterns_and(ctx, args)?;
+            self.finish_patch(patch, |ip| Ir::PatternTry(ip, ip + 1));
+            self.code.push(Ir::PatternResult(false))
+          }),
+          b"?" => {
+            self.spans.insert(hsp, ObjectKind::PatternSyntax(PatternSyntax::Test));
+            if let [test, tail @ ..] = args {
+              finish!({
+                self.code.push(Ir::PatternTestPause);
+                self.expr(ExprCtx::EVAL.mask_def(), test)?;
+                let tsp = test.span();
+                if let Some(p) = self.pop_builtin() {
+                  self.code.push(Ir::BuiltinApp(false, p, Box::new((tsp, tsp)), 1));
+                } else {
+                  self.code.push(Ir::AppHead(tsp));
                 }
-                self.pattern(ctx, quote, e)?;
-                return Ok(())
-              }
-              return Err(ElabError::new_e(head.span, "expected at least one argument"))
+                self.code.push(Ir::TestPatternResume);
+                self.patterns_and(ctx, tail)?
+              })
             }
-            b"___" | b"..." => {
-              self.spans.insert(head.span, ObjectKind::PatternSyntax(PatternSyntax::Rest));
-              if args.is_empty() {
-                self.code.push(Ir::PatternList(pfx, Some(0)));
+            return Err(ElabError::new_e(hsp, "expected at least one argument"))
+          }
+          b"cons" => {
+            self.spans.insert(hsp, ObjectKind::PatternSyntax(PatternSyntax::Cons));
+            if let [es2 @ .., e] = args {
+              if es2.len() + pfx != 0 {
+                self.code.push(Ir::PatternDottedList(es2.len() + pfx));
                 for e in &es[..pfx] { self.pattern(ctx, quote, e)? }
-                return Ok(())
+                for e in es2 { self.pattern(ctx, quote, e)? }
               }
-              return Err(ElabError::new_e(head.span, "expected nothing after '...'"))
+              self.pattern(ctx, quote, e)?;
+              return Ok(())
             }
-            b"__" => {
-              self.spans.insert(head.span, ObjectKind::PatternSyntax(PatternSyntax::RestN));
-              if let [SExpr {span, k: SExprKind::Number(ref n)}] = *args {
+            return Err(ElabError::new_e(hsp, "expected at least one argument"))
+          }
+          b"___" | b"..." => {
+            self.spans.insert(hsp, ObjectKind::PatternSyntax(PatternSyntax::Rest));
+            if args.is_empty() {
+              self.code.push(Ir::PatternList(pfx, Some(0)));
+              for e in &es[..pfx] { self.pattern(ctx, quote, e)? }
+              return Ok(())
+            }
+            return Err(ElabError::new_e(hsp, "expected nothing after '...'"))
+          }
+          b"__" => {
+            self.spans.insert(hsp, ObjectKind::PatternSyntax(PatternSyntax::RestN));
+            if let [e] = args {
+              if let CodeKind::Number(n) = e.view(self) {
                 self.code.push(Ir::PatternList(pfx, Some(n.to_usize().ok_or_else(||
-                  ElabError::new_e(span, "number out of range"))?)));
+                  ElabError::new_e(e.span(), "number out of range"))?)));
                 for e in &es[..pfx] { self.pattern(ctx, quote, e)? }
                 return Ok(())
               }
-              return Err(ElabError::new_e(head.span, "expected number after '__'"))
             }
-            _ => {}
+            return Err(ElabError::new_e(hsp, "expected number after '__'"))
           }
+          _ => {}
         }
       }
       pfx += 1;
     }
   }
 
-  fn patterns_and(&mut self, ctx: &mut LocalCtx, args: &[SExpr]) -> Result<(), ElabError> {
+  fn patterns_and<E: Code>(&mut self, ctx: &mut LocalCtx, args: &[E]) -> Result<(), ElabError> {
     if let [args @ .., last] = args {
       for e in args {
         self.code.push(Ir::Dup);
@@ -904,7 +1041,7 @@ impl<'a> LispParser<'a> {
     }
   }
 
-  fn patterns_or(&mut self, ctx: &mut LocalCtx, args: &[SExpr]) -> Result<(), ElabError> {
+  fn patterns_or<E: Code>(&mut self, ctx: &mut LocalCtx, args: &[E]) -> Result<(), ElabError> {
     match args {
       [] => self.code.push(Ir::PatternResult(false)),
       [e] => self.pattern(ctx, false, e)?,
@@ -958,53 +1095,55 @@ impl<'a> LispParser<'a> {
     Ok(())
   }
 
-  fn pattern(&mut self, ctx: &mut LocalCtx, quote: bool, e: &SExpr) -> Result<(), ElabError> {
-    match &e.k {
-      &SExprKind::Atom(a) => if quote {
-        self.code.push(Ir::PatternQuoteAtom(
-          self.elab.env.get_atom(self.elab.ast.span_atom(e.span, a))))
+  fn pattern<E: Code>(&mut self, ctx: &mut LocalCtx, quote: bool, e: &E) -> Result<(), ElabError> {
+    match e.view(self) {
+      CodeKind::Atom(a) => if quote {
+        let x = match a {
+          Ok(x) => self.unrename(x),
+          Err(s) => self.elab.env.get_atom(s.to_byte_str()),
+        };
+        self.code.push(Ir::PatternQuoteAtom(x))
       } else {
-        let x = self.parse_atom(e.span, a)?;
+        let x = a.map_err(|_| ElabError::new_e(e.span(), "keyword in invalid position"))?;
         if x == AtomId::UNDER {
           self.code.push(Ir::PatternResult(true))
         } else {
-          self.spans.insert(e.span, ObjectKind::LispVar(true, false, x));
+          self.spans.insert(e.span(), ObjectKind::LispVar(true, false, x));
           self.code.push(Ir::PatternAtom(ctx.get_or_push(x)))
         }
       }
-      SExprKind::DottedList(es, e) => {
+      CodeKind::DottedList(es, e) => {
         self.code.push(Ir::PatternDottedList(es.len()));
         for e in es { self.pattern(ctx, quote, e)? }
         self.pattern(ctx, quote, e)?;
       }
-      SExprKind::Number(n) => self.code.push(Ir::PatternNumber(n.clone().into())),
-      SExprKind::String(s) => self.code.push(Ir::PatternString(s.clone())),
-      &SExprKind::Bool(b) => self.code.push(Ir::PatternBool(b)),
-      SExprKind::Undef => self.code.push(Ir::PatternUndef),
-      SExprKind::DocComment(_, e) => self.pattern(ctx, quote, e)?,
-      SExprKind::List(es) => self.list_pattern(ctx, quote, es)?,
-      &SExprKind::Formula(f) => {
+      CodeKind::Number(n) => self.code.push(Ir::PatternNumber(n)),
+      CodeKind::String(s) => self.code.push(Ir::PatternString(s)),
+      CodeKind::Bool(b) => self.code.push(Ir::PatternBool(b)),
+      CodeKind::Undef => self.code.push(Ir::PatternUndef),
+      CodeKind::DocComment(_, e) => self.pattern(ctx, quote, e)?,
+      CodeKind::List(es) => self.list_pattern(ctx, quote, es)?,
+      CodeKind::Formula(f) => {
         let q = self.parse_formula(f)?;
         self.qexpr_pattern(ctx, q)?
       }
+      CodeKind::Const(_) => return Err(ElabError::new_e(e.span(), "invalid pattern")),
     }
     Ok(())
   }
 
-  fn branch(&mut self, keep: bool, tail: bool, e: &SExpr) -> Result<usize, ElabError> {
+  fn branch<E: Code>(&mut self, keep: bool, tail: bool, e: &E) -> Result<usize, ElabError> {
     let patch1 = self.new_patch();
-    let (e, mut es) = match &e.k {
-      SExprKind::List(es) if !es.is_empty() => (&es[0], &es[1..]),
-      _ => return Err(ElabError::new_e(e.span, "match: improper syntax"))
+    let (e, mut es) = match e.view(self) {
+      CodeKind::List(es) if !es.is_empty() => (&es[0], &es[1..]),
+      _ => return Err(ElabError::new_e(e.span(), "match: improper syntax"))
     };
     let mut cont = AtomId::UNDER;
     if let Some(e2) = es.get(0) {
-      if let SExprKind::List(v) = &e2.k {
-        if let [SExpr {span, k: SExprKind::Atom(a)}, ref x] = **v {
-          if self.ast.span_atom(span, a) == b"=>" {
-            cont = self.parse_ident(x)?;
-            es = &es[1..];
-          }
+      if let CodeKind::List([head, x]) = e2.view(self) {
+        if self.atom_name(head).as_deref() == Some(b"=>") {
+          cont = self.parse_ident(x)?;
+          es = &es[1..];
         }
       }
     }
@@ -1021,7 +1160,7 @@ impl<'a> LispParser<'a> {
     Ok(patch3)
   }
 
-  fn match_(&mut self, keep: bool, tail: bool, sp: Span, es: &[SExpr]) -> Result<(), ElabError> {
+  fn match_<E: Code>(&mut self, keep: bool, tail: bool, sp: Span, es: &[E]) -> Result<(), ElabError> {
     let mut patches = vec![];
     for e in es { patches.push(self.branch(keep, tail, e)?) }
     self.code.push(Ir::BranchFail(sp));
@@ -1034,6 +1173,7 @@ impl<'a> LispParser<'a> {
       None => {
         if keep {
           // Preload the value, if it exists; else look it up at run time
+          let x = self.unrename(x);
           let data = &self.data[x];
           if let Some(data) = &data.lisp {
             let val = data.val.clone();
@@ -1053,102 +1193,112 @@ impl<'a> LispParser<'a> {
     }
   }
 
-  fn expr(&mut self, ctx: ExprCtx, e: &SExpr) -> Result<bool, ElabError> {
+  fn expr<E: Code>(&mut self, ctx: ExprCtx, e: &E) -> Result<bool, ElabError> {
     self.expr_doc(String::new(), ctx, e)
   }
 
-  fn expr_doc(&mut self, mut doc: String, ctx: ExprCtx, e: &SExpr) -> Result<bool, ElabError> {
+  fn expr_doc<E: Code>(&mut self, mut doc: String, ctx: ExprCtx, e: &E) -> Result<bool, ElabError> {
     macro_rules! span {($sp:expr, $e:expr) => {{$e.span(self.fspan($sp))}}}
     macro_rules! push_const {($e:expr) => {
       if ctx.keep { let e = $e; self.code.push(Ir::Const(e)) }
     }}
     let restore = self.ctx.len();
-    match &e.k {
-      &SExprKind::Atom(a) => if ctx.quote {
-        push_const!(span!(e.span,
-          match self.parse_ident_or_syntax(e.span, a) {
-            Ok(x) => LispVal::atom(x),
+    let esp = e.span();
+    match e.view(self) {
+      CodeKind::Atom(a) => if ctx.quote {
+        push_const!(span!(esp,
+          match a {
+            Ok(x) => LispVal::atom(self.unrename(x)),
             Err(s) => LispVal::syntax(s),
           }
         ))
       } else {
-        match self.parse_atom(e.span, a)? {
-          AtomId::UNDER => push_const!(span!(e.span, LispVal::atom(AtomId::UNDER))),
+        match a.map_err(|_| ElabError::new_e(esp, "keyword in invalid position"))? {
+          AtomId::UNDER => push_const!(span!(esp, LispVal::atom(AtomId::UNDER))),
           x => {
-            if self.eval_atom(ctx.keep, e.span, x) {
-              self.spans.insert(e.span, ObjectKind::LispVar(false, false, x));
+            if self.eval_atom(ctx.keep, esp, x) {
+              self.spans.insert(esp, ObjectKind::LispVar(false, false, x));
             } else {
-              self.spans.insert(e.span, ObjectKind::Global(false, false, x));
+              self.spans.insert(esp, ObjectKind::Global(false, false, x));
             }
           }
         }
       }
-      SExprKind::DottedList(es, e) => {
+      CodeKind::DottedList(es, e) => {
         if !ctx.quote {
-          return Err(ElabError::new_e(e.span, "cannot evaluate an improper list"))
+          return Err(ElabError::new_e(e.span(), "cannot evaluate an improper list"))
         }
         for e in es {
-          if let SExprKind::Atom(a) = es[0].k {
-            if Syntax::parse(self.ast.span(e.span), a) == Ok(Syntax::Unquote) {
-              return Err(ElabError::new_e(e.span, "cannot evaluate an improper list"))
-            }
+          if matches!(es[0].view(self), CodeKind::Atom(Err(Syntax::Unquote))) {
+            return Err(ElabError::new_e(e.span(), "cannot evaluate an improper list"))
           }
           self.expr(ExprCtx::EVAL.quote(true), e)?;
         }
         self.expr(ExprCtx::EVAL.quote(true), e)?;
-        self.dotted_list(e.span, es.len());
+        self.dotted_list(e.span(), es.len());
       }
-      SExprKind::Number(n) => push_const!(span!(e.span, LispVal::number(n.clone().into()))),
-      SExprKind::String(s) => push_const!(span!(e.span, LispVal::string(s.clone()))),
-      &SExprKind::Bool(b) => push_const!(span!(e.span, LispVal::bool(b))),
-      SExprKind::Undef => push_const!(span!(e.span, LispVal::undef())),
-      SExprKind::DocComment(doc2, e) => {
+      CodeKind::Number(n) => push_const!(span!(esp, LispVal::number(n))),
+      CodeKind::String(s) => push_const!(span!(esp, LispVal::string(s))),
+      CodeKind::Bool(b) => push_const!(span!(esp, LispVal::bool(b))),
+      CodeKind::Undef => push_const!(span!(esp, LispVal::undef())),
+      CodeKind::Const(v) => push_const!(v),
+      CodeKind::DocComment(doc2, e) => {
         // push an extra newline to separate multiple doc comments
         if !doc.is_empty() {doc.push('\n');}
         doc.push_str(doc2);
         return self.expr_doc(doc, ctx, e)
       }
-      SExprKind::List(es) if es.is_empty() => push_const!(span!(e.span, LispVal::nil())),
-      SExprKind::List(es) => if ctx.quote {
+      CodeKind::List([]) => push_const!(span!(esp, LispVal::nil())),
+      CodeKind::List(es) => if ctx.quote {
         let mut size = 0;
         let mut it = es.iter();
         loop {
           if let Some(arg) = it.next() {
-            if let SExprKind::Atom(a) = arg.k {
-              if Syntax::parse(self.ast.span(arg.span), a) == Ok(Syntax::Unquote) {
-                let r = it.next().ok_or_else(||
-                  ElabError::new_e(arg.span, "expected at least one argument"))?;
-                self.expr(ExprCtx::eval(ctx.keep), r)?;
-                if ctx.keep { self.dotted_list(e.span, size) }
-                break
-              }
+            if matches!(arg.view(self), CodeKind::Atom(Err(Syntax::Unquote))) {
+              let r = it.next().ok_or_else(||
+                ElabError::new_e(arg.span(), "expected at least one argument"))?;
+              self.expr(ExprCtx::eval(ctx.keep), r)?;
+              if ctx.keep { self.dotted_list(esp, size) }
+              break
             }
             size += 1;
             self.expr(ExprCtx::eval(ctx.keep).quote(true), arg)?;
           } else {
-            if ctx.keep { let fsp = self.fspan(e.span); self.list(fsp, size) }
+            if ctx.keep { let fsp = self.fspan(esp); self.list(fsp, size) }
             break
           }
         }
-      } else if let SExprKind::Atom(a) = es[0].k {
-        match self.parse_ident_or_syntax(es[0].span, a) {
-          Ok(AtomId::UNDER) => return Err(ElabError::new_e(es[0].span, "'_' is not a function")),
+      } else if let CodeKind::Atom(a) = es[0].view(self) {
+        let hsp = es[0].span();
+        match a {
+          Ok(AtomId::UNDER) => return Err(ElabError::new_e(hsp, "'_' is not a function")),
           Ok(x) => {
-            let mut local = self.eval_atom(true, es[0].span, x);
+            if self.ctx.get(x).is_none() {
+              if let Some(m) = self.get_macro(x) {
+                let e2 = self.expand(esp, &m, es)?;
+                let x = self.unrename(x);
+                self.spans.insert(hsp, ObjectKind::Global(false, true, x));
+                self.depth += 1;
+                let res = self.expr_doc(doc, ctx, &e2);
+                self.depth -= 1;
+                return res
+              }
+            }
+            let mut local = self.eval_atom(true, hsp, x);
             let p = self.pop_builtin();
             let n = self.exprs(ExprsCtx::App, &es[1..])?;
             if let Some(p) = p {
               local = false;
               let spec = p.spec();
               if spec.valid(n) {
-                self.code.push(Ir::BuiltinApp(ctx.tail, p, Box::new((e.span, es[0].span)), n));
+                self.code.push(Ir::BuiltinApp(ctx.tail, p, Box::new((esp, hsp)), n));
               } else {
-                self.code.push(Ir::ArityError(e.span, spec));
+                self.code.push(Ir::ArityError(esp, spec));
               }
             } else {
-              self.code.push(Ir::App(ctx.tail, Box::new((e.span, es[0].span)), n));
+              self.code.push(Ir::App(ctx.tail, Box::new((esp, hsp)), n));
             };
-            self.spans.insert(es[0].span, if local {
+            self.spans.insert(hsp, if local {
               ObjectKind::LispVar(false, true, x)
             } else {
               ObjectKind::Global(false, true, x)
@@ -1156,57 +1306,57 @@ impl<'a> LispParser<'a> {
             if !ctx.keep { self.code.push(Ir::Drop(1)) }
           }
           Err(stx) => {
-            self.spans.insert_if(Some(es[0].span), || ObjectKind::Syntax(stx));
+            self.spans.insert_if(Some(hsp), || ObjectKind::Syntax(stx));
             match stx {
               Syntax::Begin => { self.exprs(ExprsCtx::Eval(ctx.keep, ctx.tail), &es[1..])?; }
               Syntax::Define if es.len() < 2 => return Err(
-                ElabError::new_e(es[0].span, "expected at least one argument")),
+                ElabError::new_e(hsp, "expected at least one argument")),
               Syntax::Define => {
                 let (sp, x) = self.def(
                   !ctx.mask_def && ctx.global, ctx.tail && !ctx.keep, &es[1], &es[2..])?;
                 if x != AtomId::UNDER && !ctx.mask_def {
                   let doc = if doc.is_empty() {None} else {Some(doc.into())};
-                  self.push_def(ctx.global, e.span, sp, doc, x);
+                  self.push_def(ctx.global, esp, sp, doc, x);
                 }
                 if ctx.keep { self.code.push(Ir::Undef) }
                 if ctx.mask_def { self.restore(restore) }
                 return Ok(false)
               }
               Syntax::Lambda if es.len() < 2 => return Err(
-                ElabError::new_e(es[0].span, "expected at least one argument")),
+                ElabError::new_e(hsp, "expected at least one argument")),
               Syntax::Lambda => if ctx.keep {
                 let orig = std::mem::take(&mut self.code);
-                match &es[1].k {
-                  SExprKind::List(xs) => {
+                match es[1].view(self) {
+                  CodeKind::List(xs) => {
                     let xs = self.parse_idents(xs)?;
                     let n = self.ctx.push_list(&xs);
                     self.exprs(ExprsCtx::Eval(true, true), &es[2..])?;
                     let code = std::mem::replace(&mut self.code, orig).into();
-                    self.push_lambda(es[0].span, n, ProcSpec::Exact(xs.len()), code)
+                    self.push_lambda(hsp, n, ProcSpec::Exact(xs.len()), code)
                   }
-                  SExprKind::DottedList(xs, y) => {
+                  CodeKind::DottedList(xs, y) => {
                     let xs = self.parse_idents(xs)?;
                     let y = self.parse_ident(y)?;
                     let n = self.ctx.push_list(&xs);
                     self.ctx.push(y);
                     self.exprs(ExprsCtx::Eval(true, true), &es[2..])?;
                     let code = std::mem::replace(&mut self.code, orig).into();
-                    self.push_lambda(es[0].span, n, ProcSpec::AtLeast(xs.len()), code)
+                    self.push_lambda(hsp, n, ProcSpec::AtLeast(xs.len()), code)
                   }
                   _ => {
                     let x = self.parse_ident(&es[1])?;
                     let n = self.ctx.push(x);
                     self.exprs(ExprsCtx::Eval(true, true), &es[2..])?;
                     let code = std::mem::replace(&mut self.code, orig).into();
-                    self.push_lambda(es[0].span, n, ProcSpec::AtLeast(0), code)
+                    self.push_lambda(hsp, n, ProcSpec::AtLeast(0), code)
                   }
                 }
               }
               Syntax::Quote if es.len() < 2 => return Err(
-                ElabError::new_e(es[0].span, "expected at least one argument")),
+                ElabError::new_e(hsp, "expected at least one argument")),
               Syntax::Quote => { self.expr(ExprCtx::eval(ctx.keep).quote(true), &es[1])?; }
               Syntax::Unquote if es.len() < 2 => return Err(
-                ElabError::new_e(es[0].span, "expected at least one argument")),
+                ElabError::new_e(hsp, "expected at least one argument")),
               Syntax::Unquote => { self.expr(ExprCtx::eval(ctx.keep), &es[1])?; }
               Syntax::If if 3 <= es.len() && es.len() <= 4 => {
                 self.expr(ExprCtx::EVAL.mask_def(), &es[1])?;
@@ -1221,28 +1371,44 @@ impl<'a> LispParser<'a> {
                 self.finish_patch(patch2, Ir::Jump);
               }
               Syntax::If => return Err(
-                ElabError::new_e(es[0].span, "expected two or three arguments")),
+                ElabError::new_e(hsp, "expected two or three arguments")),
               Syntax::Focus => {
-                self.code.push(Ir::FocusStart(es[0].span));
+                self.code.push(Ir::FocusStart(hsp));
                 self.exprs(ExprsCtx::Focus, &es[1..])?;
               }
               Syntax::Let => self.let_(false, ctx.keep, ctx.tail, &es[1..])?,
               Syntax::Letrec => self.let_(true, ctx.keep, ctx.tail, &es[1..])?,
               Syntax::SetMergeStrategy if 2 <= es.len() && es.len() <= 3 => {
                 let a = self.parse_ident_raw(&es[1])?;
-                self.spans.insert(es[1].span, ObjectKind::Global(false, false, a));
+                self.spans.insert(es[1].span(), ObjectKind::Global(false, false, a));
                 if let Some(e) = es.get(2) { self.expr(ExprCtx::EVAL, e)?; }
                 else { self.code.push(Ir::Undef) }
-                self.code.push(Ir::SetMergeStrategy(es[0].span, a));
+                self.code.push(Ir::SetMergeStrategy(hsp, a));
                 if ctx.keep { self.code.push(Ir::Undef) }
               }
               Syntax::SetMergeStrategy => return Err(
-                ElabError::new_e(es[0].span, "expected one or two arguments")),
+                ElabError::new_e(hsp, "expected one or two arguments")),
+              Syntax::Export => {
+                let mut xs = Vec::with_capacity(es.len() - 1);
+                for e in &es[1..] {
+                  let a = self.parse_ident_raw(e)?;
+                  self.spans.insert(e.span(), ObjectKind::Global(false, false, a));
+                  xs.push(a);
+                }
+                self.code.push(Ir::Export(xs.into()));
+                if ctx.keep { self.code.push(Ir::Undef) }
+              }
+              Syntax::Module if es.len() == 2 => {
+                let a = self.parse_ident_raw(&es[1])?;
+                self.code.push(Ir::Module(hsp, a));
+                if ctx.keep { self.code.push(Ir::Undef) }
+              }
+              Syntax::Module => return Err(ElabError::new_e(hsp, "expected one argument")),
               Syntax::Match if es.len() < 2 => return Err(
-                ElabError::new_e(es[0].span, "expected at least one argument")),
+                ElabError::new_e(hsp, "expected at least one argument")),
               Syntax::Match => {
                 self.expr(ExprCtx::EVAL.mask_def(), &es[1])?;
-                self.match_(ctx.keep, ctx.tail, es[0].span, &es[2..])?
+                self.match_(ctx.keep, ctx.tail, hsp, &es[2..])?
               }
               Syntax::MatchFn | Syntax::MatchFns => {
                 let spec = match stx {
@@ -1253,9 +1419,30 @@ impl<'a> LispParser<'a> {
                 let i = self.ctx.push(AtomId::UNDER);
                 let orig = std::mem::take(&mut self.code);
                 self.code.push(Ir::Local(i));
-                self.match_(true, true, es[0].span, &es[1..])?;
+                self.match_(true, true, hsp, &es[1..])?;
                 let code = std::mem::replace(&mut self.code, orig).into();
-                self.push_lambda(es[0].span, i, spec, code);
+                self.push_lambda(hsp, i, spec, code);
+              }
+              Syntax::DefMacro if es.len() < 3 => return Err(
+                ElabError::new_e(hsp, "expected at least two arguments")),
+              Syntax::DefMacro if !ctx.global || ctx.mask_def => return Err(
+                ElabError::new_e(hsp, "defmacro: macros can only be defined at the top level")),
+              Syntax::DefMacro => {
+                let (sp, x) = self.def(true, false, &es[1], &es[2..])?;
+                if x == AtomId::UNDER {
+                  return Err(ElabError::new_e(sp, "defmacro: expected a name"))
+                }
+                self.code.push(Ir::MakeMacro(hsp));
+                let doc = if doc.is_empty() {None} else {Some(doc.into())};
+                self.push_def(true, esp, sp, doc, x);
+                if ctx.keep { self.code.push(Ir::Undef) }
+                return Ok(false)
+              }
+              Syntax::SyntaxRules if es.len() < 2 => return Err(
+                ElabError::new_e(hsp, "expected at least one argument")),
+              Syntax::SyntaxRules => {
+                let m = self.syntax_rules(&es[1], &es[2..])?;
+                push_const!(span!(esp, LispVal::proc(Proc::Macro(Macro::Rules(m)))))
               }
             }
           }
@@ -1263,10 +1450,10 @@ impl<'a> LispParser<'a> {
       } else {
         self.expr(ExprCtx::EVAL.mask_def(), &es[0])?;
         let n = self.exprs(ExprsCtx::App, &es[1..])?;
-        self.code.push(Ir::App(ctx.tail, Box::new((e.span, es[0].span)), n));
+        self.code.push(Ir::App(ctx.tail, Box::new((esp, es[0].span())), n));
         if !ctx.keep { self.code.push(Ir::Drop(1)) }
       },
-      &SExprKind::Formula(f) => {
+      CodeKind::Formula(f) => {
         let q = self.parse_formula(f)?;
         self.qexpr(ctx.keep, q)?
       }
@@ -1276,6 +1463,314 @@ impl<'a> LispParser<'a> {
   }
 }
 
+/// A binding of a pattern variable in a `syntax-rules` macro: either a single
+/// s-expression, or for a variable under an ellipsis `...`, one binding per repetition.
+#[derive(Clone)]
+enum Binding {
+  One(LispVal),
+  Many(Vec<Binding>),
+}
+
+/// Split a list into its elements and its tail, which is `None` for a proper list.
+/// A value that is not a list is returned as a tail with no elements.
+fn list_parts(e: &LispVal) -> (Vec<LispVal>, Option<LispVal>) {
+  let mut es = vec![];
+  let mut e = e.clone();
+  loop {
+    match e.unwrapped(|k| match k {
+      LispKind::List(xs) => { es.extend_from_slice(xs); Ok(None) }
+      LispKind::DottedList(xs, r) => { es.extend_from_slice(xs); Ok(Some(r.clone())) }
+      _ => Err(()),
+    }) {
+      Ok(None) => return (es, None),
+      Ok(Some(r)) => e = r,
+      Err(()) => return (es, Some(e)),
+    }
+  }
+}
+
+fn is_list(e: &LispVal) -> bool {
+  e.unwrapped(|k| matches!(k, LispKind::List(_) | LispKind::DottedList(..)))
+}
+
+impl LispParser<'_> {
+  /// Get the atom that `x` renames, if `x` is a fresh atom from a `syntax-rules` expansion.
+  fn unrename(&self, x: AtomId) -> AtomId { self.renames.get(&x).copied().unwrap_or(x) }
+
+  /// Make a fresh atom to rename `x` in a `syntax-rules` expansion. The name of the
+  /// new atom is not a valid identifier, so it cannot clash with the user's variables.
+  fn fresh_atom(&mut self, x: AtomId) -> AtomId {
+    let x = self.unrename(x);
+    let mut n = self.renames.len();
+    let name = loop {
+      let name = format!("{}#{n}", self.data[x].name);
+      if !self.atoms.contains_key(name.as_bytes()) { break name }
+      n += 1;
+    };
+    let y = self.get_atom(name.as_bytes());
+    self.renames.insert(y, x);
+    y
+  }
+
+  /// The name of an atom or keyword, looking through renaming.
+  fn atom_name<E: Code>(&mut self, e: &E) -> Option<ArcString> {
+    match e.view(self) {
+      CodeKind::Atom(Ok(x)) => Some(self.data[self.unrename(x)].name.clone()),
+      CodeKind::Atom(Err(s)) => Some(s.to_byte_str().into()),
+      _ => None
+    }
+  }
+
+  /// Get the value of the global definition `x`, if it is a macro.
+  fn get_macro(&self, x: AtomId) -> Option<LispVal> {
+    let val = &self.data[self.unrename(x)].lisp.as_ref()?.val;
+    val.unwrapped(|e| matches!(e, LispKind::Proc(Proc::Macro(_)))).then(|| val.clone())
+  }
+
+  /// Convert code to the s-expression it denotes, as if it were quoted, for passing
+  /// to a macro. Formulas become quotations of the parsed formula.
+  fn quote_code<E: Code>(&mut self, e: &E) -> Result<LispVal, ElabError> {
+    let fsp = self.fspan(e.span());
+    Ok(match e.view(self) {
+      CodeKind::Atom(Ok(x)) => LispVal::atom(x),
+      CodeKind::Atom(Err(s)) => LispVal::syntax(s),
+      CodeKind::List(es) =>
+        LispVal::list(es.iter().map(|e| self.quote_code(e)).collect::<Result<Box<[_]>, _>>()?),
+      CodeKind::DottedList(es, r) => LispVal::dotted_list(
+        es.iter().map(|e| self.quote_code(e)).collect::<Result<Box<[_]>, _>>()?,
+        self.quote_code(r)?),
+      CodeKind::Number(n) => LispVal::number(n),
+      CodeKind::String(s) => LispVal::string(s),
+      CodeKind::Bool(b) => LispVal::bool(b),
+      CodeKind::Undef => LispVal::undef(),
+      CodeKind::DocComment(_, e) => return self.quote_code(e),
+      CodeKind::Formula(f) => {
+        let q = self.parse_formula(f)?;
+        LispVal::list(vec![LispVal::syntax(Syntax::Quote), self.quote_qexpr(q)?])
+      }
+      CodeKind::Const(v) => return Ok(v),
+    }.span(fsp))
+  }
+
+  /// Convert a parsed formula to an s-expression, with `(unquote e)` for antiquotations.
+  fn quote_qexpr(&mut self, e: QExpr) -> Result<LispVal, ElabError> {
+    let fsp = self.fspan(e.span);
+    Ok(match e.k {
+      QExprKind::IdentApp(sp, es) => {
+        let head = LispVal::atom(self.elab.env.get_atom(self.ast.clone().span(sp)))
+          .span(self.fspan(sp));
+        if es.is_empty() { return Ok(head) }
+        let mut args = vec![head];
+        for e in es.into_vec() { args.push(self.quote_qexpr(e)?) }
+        LispVal::list(args)
+      }
+      QExprKind::App(sp, t, es) => {
+        let mut args = vec![LispVal::atom(self.terms[t].atom).span(self.fspan(sp))];
+        for e in es.into_vec() { args.push(self.quote_qexpr(e)?) }
+        LispVal::list(args)
+      }
+      QExprKind::Unquote(e) =>
+        LispVal::list(vec![LispVal::syntax(Syntax::Unquote), self.quote_code(&e)?]),
+    }.span(fsp))
+  }
+
+  /// Parse the body of a `(syntax-rules (lits) [pat tmpl] ...)` form.
+  fn syntax_rules<E: Code>(&mut self, lits: &E, rules: &[E]) -> Result<SyntaxRules, ElabError> {
+    let CodeKind::List(xs) = lits.view(self) else {
+      return Err(ElabError::new_e(lits.span(), "syntax-rules: expected a list of literals"))
+    };
+    let lits = xs.iter().map(|x| self.parse_ident_raw(x)).collect::<Result<Box<[_]>, _>>()?;
+    let mut out = vec![];
+    for rule in rules {
+      match rule.view(self) {
+        CodeKind::List([pat, tmpl])
+        if matches!(pat.view(self), CodeKind::List([_, ..]) | CodeKind::DottedList(..)) =>
+          out.push((self.quote_code(pat)?, self.quote_code(tmpl)?)),
+        _ => return Err(ElabError::new_e(rule.span(),
+          "syntax-rules: expected a rule [(_ pattern ...) template]")),
+      }
+    }
+    Ok(SyntaxRules { lits, rules: out.into() })
+  }
+
+  /// Expand the macro invocation `es` (whose head is bound to macro `m`),
+  /// returning the code to compile in its place.
+  fn expand<E: Code>(&mut self, sp: Span, m: &LispVal, es: &[E]) -> Result<LispVal, ElabError> {
+    if self.depth >= MAX_EXPANSION_DEPTH {
+      return Err(ElabError::new_e(sp, "macro expansion depth exceeded"))
+    }
+    let f = m.unwrapped(|m| match m {
+      LispKind::Proc(Proc::Macro(Macro::Proc(f))) => Some(f.clone()),
+      _ => None
+    });
+    let res = if let Some(f) = f {
+      let args = es[1..].iter().map(|e| self.quote_code(e)).collect::<Result<Vec<_>, _>>()?;
+      self.call_func(sp, &f, args)?
+    } else {
+      let form = es.iter().map(|e| self.quote_code(e)).collect::<Result<Vec<_>, _>>()?;
+      let ell = self.get_atom(b"...");
+      m.unwrapped(|m| {
+        let LispKind::Proc(Proc::Macro(Macro::Rules(rules))) = m else { unreachable!() };
+        for (pat, tmpl) in &*rules.rules {
+          let (ps, pt) = list_parts(pat);
+          let mut bs = HashMap::new();
+          if self.match_list(rules, ell, &ps[1..], pt.as_ref(), &form[1..], None, &mut bs) {
+            return self.instantiate(ell, tmpl, &bs, &mut HashMap::new())
+              .map_err(|msg| ElabError::new_e(sp, msg))
+          }
+        }
+        let e = LispVal::list(form.clone());
+        Err(ElabError::new_e(sp, format!("no syntax rule matches {}", self.print(&e))))
+      })?
+    };
+    Ok(code_of(&self.fspan(es[0].span()), &res))
+  }
+
+  /// Match input `e` against a `syntax-rules` pattern, adding to the bindings `bs`.
+  fn match_pat(&self, m: &SyntaxRules, ell: AtomId,
+    pat: &LispVal, e: &LispVal, bs: &mut HashMap<AtomId, Binding>
+  ) -> bool {
+    match pat.as_atom() {
+      Some(AtomId::UNDER) => true,
+      Some(a) if m.lits.contains(&a) => e.as_atom().is_some_and(|b| self.unrename(b) == a),
+      Some(a) => { bs.insert(a, Binding::One(e.clone())); true }
+      None if is_list(pat) => {
+        let (ps, pt) = list_parts(pat);
+        let (es, et) = list_parts(e);
+        self.match_list(m, ell, &ps, pt.as_ref(), &es, et.as_ref(), bs)
+      }
+      None => *pat == *e,
+    }
+  }
+
+  /// Match a list against a list pattern `(ps . pt)`, where `ps` may contain
+  /// one ellipsis `p ...` matching any number of elements.
+  #[allow(clippy::too_many_arguments)]
+  fn match_list(&self, m: &SyntaxRules, ell: AtomId,
+    ps: &[LispVal], pt: Option<&LispVal>, es: &[LispVal], et: Option<&LispVal>,
+    bs: &mut HashMap<AtomId, Binding>
+  ) -> bool {
+    let (pre, rep, post) = match ps.iter().position(|p| p.as_atom() == Some(ell)) {
+      Some(i) if i > 0 => (&ps[..i-1], Some(&ps[i-1]), &ps[i+1..]),
+      _ => (ps, None, &[][..]),
+    };
+    let fixed = pre.len() + post.len();
+    if es.len() < fixed { return false }
+    if pt.is_none() && (et.is_some() || rep.is_none() && es.len() != fixed) { return false }
+    for (p, e) in pre.iter().zip(es) {
+      if !self.match_pat(m, ell, p, e, bs) { return false }
+    }
+    let mut rest = &es[pre.len()..];
+    if let Some(rep) = rep {
+      let (reps, post_es) = rest.split_at(rest.len() - post.len());
+      let mut matches = vec![];
+      for e in reps {
+        let mut bs2 = HashMap::new();
+        if !self.match_pat(m, ell, rep, e, &mut bs2) { return false }
+        matches.push(bs2)
+      }
+      let mut vars = vec![];
+      pattern_vars(m, ell, rep, &mut vars);
+      for v in vars {
+        let many = matches.iter_mut().map(|bs2| bs2.remove(&v).expect("unbound variable")).collect();
+        bs.insert(v, Binding::Many(many));
+      }
+      for (p, e) in post.iter().zip(post_es) {
+        if !self.match_pat(m, ell, p, e, bs) { return false }
+      }
+      rest = &[];
+    }
+    let Some(pt) = pt else { return true };
+    let tail = match (rest, et) {
+      ([], None) => LispVal::nil(),
+      ([], Some(et)) => et.clone(),
+      (_, None) => LispVal::list(rest.to_vec()),
+      (_, Some(et)) => LispVal::dotted_list(rest.to_vec(), et.clone()),
+    };
+    self.match_pat(m, ell, pt, &tail, bs)
+  }
+
+  /// Instantiate a `syntax-rules` template with the bindings `bs`. Atoms in the
+  /// template that are not pattern variables are renamed to fresh atoms, recorded in `fresh`.
+  fn instantiate(&mut self, ell: AtomId, t: &LispVal,
+    bs: &HashMap<AtomId, Binding>, fresh: &mut HashMap<AtomId, AtomId>
+  ) -> Result<LispVal, String> {
+    let res = if let Some(a) = t.as_atom() {
+      match bs.get(&a) {
+        Some(Binding::One(e)) => return Ok(e.clone()),
+        Some(Binding::Many(_)) =>
+          return Err(format!("pattern variable '{}' must be followed by '...'", self.data[a].name)),
+        None if a == AtomId::UNDER => LispVal::atom(a),
+        None => LispVal::atom(match fresh.get(&a) {
+          Some(&b) => b,
+          None => { let b = self.fresh_atom(a); fresh.insert(a, b); b }
+        }),
+      }
+    } else if is_list(t) {
+      let (ts, tt) = list_parts(t);
+      match (&*ts, &tt) {
+        // `(... ...)` is an escaped ellipsis
+        ([t1, t2], None) if t1.as_atom() == Some(ell) && t2.as_atom() == Some(ell) =>
+          LispVal::atom(ell),
+        _ => {
+          let mut out = vec![];
+          let mut it = ts.iter().peekable();
+          while let Some(t) = it.next() {
+            if it.peek().and_then(|t| t.as_atom()) != Some(ell) {
+              out.push(self.instantiate(ell, t, bs, fresh)?);
+              continue
+            }
+            it.next();
+            let mut vars = vec![];
+            template_vars(t, bs, &mut vars);
+            let n = match vars.first().and_then(|v| bs.get(v)) {
+              Some(Binding::Many(es)) => es.len(),
+              _ => return Err("no pattern variables under '...' in template".into()),
+            };
+            let mut bs2 = bs.clone();
+            for i in 0..n {
+              for v in &vars {
+                let Some(Binding::Many(es)) = bs.get(v) else { unreachable!() };
+                let e = es.get(i).ok_or("pattern variables under '...' have different lengths")?;
+                bs2.insert(*v, e.clone());
+              }
+              out.push(self.instantiate(ell, t, &bs2, fresh)?);
+            }
+            if vars.iter().any(|v| matches!(bs.get(v), Some(Binding::Many(es)) if es.len() != n)) {
+              return Err("pattern variables under '...' have different lengths".into())
+            }
+          }
+          match tt {
+            None => LispVal::list(out),
+            Some(tt) => LispVal::dotted_list(out, self.instantiate(ell, &tt, bs, fresh)?),
+          }
+        }
+      }
+    } else { return Ok(t.clone()) };
+    Ok(match t.fspan() { Some(fsp) => res.span(fsp), None => res })
+  }
+}
+
+/// Collect the pattern variables in a `syntax-rules` pattern.
+fn pattern_vars(m: &SyntaxRules, ell: AtomId, pat: &LispVal, vars: &mut Vec<AtomId>) {
+  if let Some(a) = pat.as_atom() {
+    if a != AtomId::UNDER && a != ell && !m.lits.contains(&a) && !vars.contains(&a) { vars.push(a) }
+  } else if is_list(pat) {
+    let (ps, pt) = list_parts(pat);
+    for p in ps.iter().chain(&pt) { pattern_vars(m, ell, p, vars) }
+  }
+}
+
+/// Collect the variables in a `syntax-rules` template that are bound under an ellipsis.
+fn template_vars(t: &LispVal, bs: &HashMap<AtomId, Binding>, vars: &mut Vec<AtomId>) {
+  if let Some(a) = t.as_atom() {
+    if matches!(bs.get(&a), Some(Binding::Many(_))) && !vars.contains(&a) { vars.push(a) }
+  } else if is_list(t) {
+    let (ts, tt) = list_parts(t);
+    for t in ts.iter().chain(&tt) { template_vars(t, bs, vars) }
+  }
+}
+
 impl Elaborator {
   /// Parse a lisp `SExpr` from the surface syntax into an `IR` object suitable for evaluation.
   pub fn parse_lisp(&mut self, global: bool, e: &SExpr) -> Result<Vec<Ir>, ElabError> {
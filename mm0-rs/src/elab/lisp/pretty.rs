@@ -80,6 +80,8 @@ impl LispKind {
       LispKind::List(es) => es.is_empty(),
       LispKind::DottedList(..) |
       LispKind::AtomMap(..) |
+      LispKind::StrMap(..) |
+      LispKind::Vector(..) |
       LispKind::Goal(..) => false,
       LispKind::Atom(..) |
       LispKind::MVar(..) |
@@ -394,6 +396,14 @@ impl<'a> Pretty<'a> {
         let doc = self.append_doc(self.lparen, self.append_doc(doc, self.rparen));
         self.alloc(Doc::Group(self.alloc(Doc::Nest(2, doc))))
       }
+      LispKind::Vector(es) => {
+        let mut doc = s!("vector!");
+        for e in es {
+          doc = self.append_doc(doc, self.append_doc(Self::line(), self.pp_lisp(e)));
+        }
+        let doc = self.append_doc(self.lparen, self.append_doc(doc, self.rparen));
+        self.alloc(Doc::Group(self.alloc(Doc::Nest(2, doc))))
+      }
       _ => self.text(format!("{}", self.fe.to(e))),
     })
   }
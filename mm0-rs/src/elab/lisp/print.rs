@@ -129,6 +129,22 @@ impl EnvDisplay for LispVal {
   fn fmt(&self, fe: FormatEnv<'_>, f: &mut fmt::Formatter<'_>) -> fmt::Result { self.0.fmt(fe, f) }
 }
 
+/// Print a string literal, with escapes for special characters.
+fn string(s: &[u8], f: &mut fmt::Formatter<'_>) -> fmt::Result {
+  write!(f, "\"")?;
+  for &c in s {
+    match c {
+      b'\\' => write!(f, "\\\\")?,
+      b'\n' => write!(f, "\\n")?,
+      b'\r' => write!(f, "\\r")?,
+      b'\"' => write!(f, "\\\"")?,
+      0x20..=0x7e => write!(f, "{}", c as char)?,
+      _ => write!(f, "\\x{c:02x}")?,
+    }
+  }
+  write!(f, "\"")
+}
+
 impl EnvDisplay for LispKind {
   #[allow(clippy::match_overlapping_arm)]
   fn fmt(&self, fe: FormatEnv<'_>, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -140,20 +156,7 @@ impl EnvDisplay for LispKind {
       LispKind::List(es) => list(es, None, true, fe, f),
       LispKind::Annot(_, e) => e.fmt(fe, f),
       LispKind::Number(n) => n.fmt(f),
-      LispKind::String(s) => {
-        write!(f, "\"")?;
-        for &c in &**s {
-          match c {
-            b'\\' => write!(f, "\\\\")?,
-            b'\n' => write!(f, "\\n")?,
-            b'\r' => write!(f, "\\r")?,
-            b'\"' => write!(f, "\\\"")?,
-            0x20..=0x7e => write!(f, "{}", c as char)?,
-            _ => write!(f, "\\x{c:02x}")?,
-          }
-        }
-        write!(f, "\"")
-      }
+      LispKind::String(s) => string(s, f),
       LispKind::Bool(true) => "#t".fmt(f),
       LispKind::Bool(false) => "#f".fmt(f),
       LispKind::Syntax(s) => s.fmt(f),
@@ -175,11 +178,26 @@ impl EnvDisplay for LispKind {
       LispKind::Proc(Proc::ProofThunk(x, _)) => write!(f, "#<proof of {}>", fe.to(x)),
       LispKind::Proc(Proc::MergeMap(_)) => write!(f, "#<merge-map>"),
       LispKind::Proc(Proc::Dyn(c)) => EnvDisplay::fmt(&**c.borrow(), fe, f),
+      LispKind::Proc(Proc::Macro(_)) => write!(f, "#<macro>"),
       LispKind::AtomMap(m) => {
         write!(f, "(atom-map!")?;
         for (a, v) in m {write!(f, " [{} {}]", fe.data[*a].name, fe.to(v))?}
         write!(f, ")")
       }
+      LispKind::StrMap(m) => {
+        write!(f, "(str-map!")?;
+        for (k, v) in m {
+          write!(f, " [")?;
+          string(k, f)?;
+          write!(f, " {}]", fe.to(v))?
+        }
+        write!(f, ")")
+      }
+      LispKind::Vector(es) => {
+        write!(f, "(vector!")?;
+        for e in es {write!(f, " {}", fe.to(e))?}
+        write!(f, ")")
+      }
       LispKind::Ref(m) if m.too_many_readers() => write!(f, "#<ref>"),
       LispKind::Ref(m) => m.get(|e| e.fmt(fe, f)),
       &LispKind::MVar(n, _) => write!(f, "?{}", alphanumber(n)),
@@ -0,0 +1,136 @@
+//! The elaboration profiler, enabled by `mm0-rs compile --profile`.
+//!
+//! When profiling is enabled, each [`Elaborator`](super::Elaborator) keeps a [`Profiler`]
+//! holding a stack of frames, one for each declaration being elaborated, lisp procedure call
+//! in progress, and run of the `refine` engine. When a frame exits its time is recorded, both
+//! by name (for the summary report) and by the full stack (for flamegraph tools). When an
+//! elaboration finishes, its data is merged into a global [`ProfileData`] which can be
+//! retrieved with [`take_profile`].
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::Duration;
+use instant::Instant;
+use crate::MutexExt;
+
+/// The accumulated statistics of one profiler entry.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Stat {
+  /// The number of times the entry was run.
+  pub calls: u64,
+  /// The total time spent in the entry, including subframes.
+  /// (Recursive calls are only counted once.)
+  pub total: Duration,
+  /// The time spent in the entry itself, not counting subframes.
+  pub self_time: Duration,
+}
+
+/// The accumulated profiling data.
+#[derive(Debug, Default)]
+pub struct ProfileData {
+  /// The self time of each stack of frames, keyed by the frame names separated by `;`.
+  pub folded: HashMap<String, Duration>,
+  /// The statistics for each frame name.
+  pub stats: HashMap<String, Stat>,
+}
+
+impl ProfileData {
+  fn merge(&mut self, other: ProfileData) {
+    for (k, t) in other.folded { *self.folded.entry(k).or_default() += t }
+    for (k, s) in other.stats {
+      let stat = self.stats.entry(k).or_default();
+      stat.calls += s.calls;
+      stat.total += s.total;
+      stat.self_time += s.self_time;
+    }
+  }
+
+  /// Write the data in the "folded stacks" format used by flamegraph tools such as
+  /// `flamegraph.pl` and `inferno`: one line per stack, with the self time in microseconds.
+  pub fn write_folded(&self, mut w: impl Write) -> io::Result<()> {
+    let mut folded = self.folded.iter().collect::<Vec<_>>();
+    folded.sort_unstable();
+    for (stack, t) in folded { writeln!(w, "{stack} {}", t.as_micros())? }
+    Ok(())
+  }
+
+  /// Write a summary of the `n` entries with the largest self time.
+  pub fn write_report(&self, mut w: impl Write, n: usize) -> io::Result<()> {
+    let mut stats = self.stats.iter().collect::<Vec<_>>();
+    stats.sort_unstable_by(|a, b| b.1.self_time.cmp(&a.1.self_time).then_with(|| a.0.cmp(b.0)));
+    let ms = |d: Duration| format!("{}.{:03}", d.as_millis(), d.as_micros() % 1000);
+    writeln!(w, "{:>11} {:>11} {:>8}  name", "self (ms)", "total (ms)", "calls")?;
+    for (name, stat) in stats.into_iter().take(n) {
+      writeln!(w, "{:>11} {:>11} {:>8}  {name}",
+        ms(stat.self_time), ms(stat.total), stat.calls)?
+    }
+    Ok(())
+  }
+}
+
+/// The profiling data of all completed elaborations.
+static PROFILE: Mutex<Option<ProfileData>> = Mutex::new(None);
+
+/// Take the profiling data accumulated by all elaborations so far.
+pub fn take_profile() -> ProfileData { PROFILE.ulock().take().unwrap_or_default() }
+
+#[derive(Debug)]
+struct Frame {
+  name: String,
+  start: Instant,
+  /// The time spent in subframes of this frame.
+  children: Duration,
+}
+
+/// The profiler for one elaboration.
+#[derive(Debug)]
+pub(crate) struct Profiler {
+  /// The name of the file being elaborated, which is used as the root of all stacks.
+  root: String,
+  stack: Vec<Frame>,
+  data: ProfileData,
+}
+
+impl Profiler {
+  pub(crate) fn new(root: String) -> Self {
+    Self { root, stack: vec![], data: ProfileData::default() }
+  }
+
+  /// The number of frames in progress.
+  pub(crate) fn depth(&self) -> usize { self.stack.len() }
+
+  /// Start a new frame.
+  pub(crate) fn enter(&mut self, name: String) {
+    self.stack.push(Frame { name, start: Instant::now(), children: Duration::ZERO })
+  }
+
+  /// Finish the most recent frame, and record its time.
+  pub(crate) fn exit(&mut self) {
+    let Some(frame) = self.stack.pop() else { return };
+    let elapsed = frame.start.elapsed();
+    let self_time = elapsed.saturating_sub(frame.children);
+    if let Some(parent) = self.stack.last_mut() { parent.children += elapsed }
+    let mut key = self.root.clone();
+    for f in &self.stack { key.push(';'); key.push_str(&f.name) }
+    key.push(';');
+    key.push_str(&frame.name);
+    *self.data.folded.entry(key).or_default() += self_time;
+    let recursive = self.stack.iter().any(|f| f.name == frame.name);
+    let stat = self.data.stats.entry(frame.name).or_default();
+    stat.calls += 1;
+    stat.self_time += self_time;
+    if !recursive { stat.total += elapsed }
+  }
+
+  /// Finish frames until there are only `depth` frames left.
+  pub(crate) fn exit_to(&mut self, depth: usize) {
+    while self.stack.len() > depth { self.exit() }
+  }
+
+  /// Finish all frames, and merge the data into the global profile.
+  pub(crate) fn finish(mut self) {
+    self.exit_to(0);
+    PROFILE.ulock().get_or_insert_with(Default::default).merge(self.data)
+  }
+}
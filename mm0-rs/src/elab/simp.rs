@@ -0,0 +1,549 @@
+//! The `simp` tactic, a native rewriting engine for MM1 proofs.
+//!
+//! `(simp cfg e)` rewrites the expression `e` to normal form using a set of equality
+//! theorems, and returns the normal form together with a proof pre-expression, suitable
+//! for passing to `refine`, of the equality between `e` and the normal form.
+//!
+//! MM0 has no built in notion of equality, so the configuration `cfg` names the equality
+//! relations to use, and the reflexivity, transitivity and congruence theorems for these
+//! relations are discovered from the environment by the shape of their statements:
+//!
+//! * reflexivity: `R a a`
+//! * transitivity: `R a b > R b c > R a c`
+//! * congruence: `R1 a1 b1 > ... > Rn an bn > R (f a1 ... an) (f b1 ... bn)`, where
+//!   some of the arguments to `f` may instead be the same variable on both sides.
+//!
+//! If the configuration names an implication term `imp`, then theorems stated as
+//! `A -> B` are also accepted in place of `A > B` (the generated proof will then rely on
+//! `refine-extra-args` to apply the theorem to its antecedents).
+
+use std::collections::HashMap;
+use num::{BigInt, ToPrimitive};
+use crate::{AtomId, ExprNode, SortId, Span, TermId, TermKind};
+use super::{Elaborator, Environment};
+use super::lisp::{InferTarget, LispKind, LispVal, Uncons, eval::SResult};
+
+/// The default value of the `max-steps` option of `simp`.
+const MAX_STEPS: usize = 10000;
+
+/// A theorem statement or definition body, where [`Var`](Pat::Var) refers
+/// to the arguments of the theorem or definition.
+#[derive(Debug)]
+enum Pat {
+  /// The `i`th argument.
+  Var(usize),
+  /// A dummy variable. These are not supported by `simp`.
+  Dummy,
+  /// A term application.
+  App(TermId, Box<[Pat]>),
+}
+
+impl Pat {
+  fn new(env: &Environment, heap: &[ExprNode], store: &[ExprNode], nargs: usize, e: &ExprNode) -> Pat {
+    match *e {
+      ExprNode::Ref(i) if i < nargs => Pat::Var(i),
+      ExprNode::Ref(i) => Pat::new(env, heap, store, nargs, &heap[i]),
+      ExprNode::Dummy(..) => Pat::Dummy,
+      ExprNode::App(t, p) => Pat::App(t, env.terms[t].unpack_app(&store[p..]).iter()
+        .map(|e| Pat::new(env, heap, store, nargs, e)).collect()),
+    }
+  }
+
+  /// If this is `R a b` where `a` and `b` are variables, returns `(a, b)`.
+  fn rel_vars(&self, r: TermId) -> Option<(usize, usize)> {
+    match *self {
+      Pat::App(t, ref args) if t == r => match **args {
+        [Pat::Var(a), Pat::Var(b)] => Some((a, b)),
+        _ => None
+      },
+      _ => None
+    }
+  }
+
+  /// Mark the variables in this pattern in `vars`. Returns false if the pattern
+  /// contains a dummy variable.
+  fn vars(&self, vars: &mut [bool]) -> bool {
+    match self {
+      &Pat::Var(i) => {vars[i] = true; true}
+      Pat::Dummy => false,
+      Pat::App(_, args) => args.iter().all(|p| p.vars(vars)),
+    }
+  }
+}
+
+/// Split off the antecedents of `e` along the implication `imp`, adding them to `hyps`.
+fn split_imp(imp: Option<TermId>, mut e: Pat, hyps: &mut Vec<Pat>) -> Pat {
+  while let Pat::App(t, args) = &mut e {
+    if Some(*t) != imp { break }
+    let [a, b] = &mut **args else { break };
+    hyps.push(std::mem::replace(a, Pat::Dummy));
+    e = std::mem::replace(b, Pat::Dummy);
+  }
+  e
+}
+
+/// A theorem application, where the `i`th argument to the theorem is taken from
+/// `slots[i]` of the values passed to [`apply`](Self::apply).
+#[derive(Debug)]
+struct Template {
+  thm: AtomId,
+  slots: Box<[usize]>,
+}
+
+impl Template {
+  /// Construct the proof pre-expression `(! thm args.. hyps..)`.
+  fn apply(&self, vals: &[LispVal], hyps: Vec<LispVal>) -> LispVal {
+    let mut args = vec![LispVal::atom(AtomId::BANG), LispVal::atom(self.thm)];
+    args.extend(self.slots.iter().map(|&i| vals[i].clone()));
+    args.extend(hyps);
+    LispVal::list(args)
+  }
+}
+
+/// An equality relation, together with its reflexivity and transitivity theorems.
+#[derive(Debug)]
+struct Rel {
+  term: AtomId,
+  refl: Template,
+  trans: Template,
+}
+
+/// A congruence theorem for a term constructor.
+#[derive(Debug)]
+struct Cong {
+  /// The theorem, where slots `2*i` and `2*i+1` are the `i`th argument
+  /// on the left and right hand side respectively.
+  tmpl: Template,
+  /// The argument positions that can be rewritten, in the order of the hypotheses.
+  hyps: Box<[usize]>,
+}
+
+/// A rewrite rule `lhs = rhs`.
+#[derive(Debug)]
+struct Lemma {
+  /// The theorem, which takes its arguments in order.
+  tmpl: Template,
+  /// For each argument of the theorem, true if it is a bound variable.
+  bound: Box<[bool]>,
+  lhs: Pat,
+  rhs: Pat,
+}
+
+/// The rewriting configuration, built from the `cfg` argument of `simp`.
+#[derive(Debug, Default)]
+struct SimpSet {
+  rels: HashMap<SortId, Rel>,
+  congs: HashMap<TermId, Cong>,
+  lemmas: HashMap<TermId, Vec<Lemma>>,
+  unfold: HashMap<TermId, Pat>,
+}
+
+
+/// A node in the hash-consed expression [`Arena`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Node {
+  /// A variable.
+  Var(AtomId),
+  /// An expression that is not a variable or term application, such as a metavariable.
+  /// These are only equal to themselves.
+  Opaque(usize),
+  /// A term application.
+  App(TermId, Box<[usize]>),
+}
+
+/// A hash-consed expression store, in which syntactically equal expressions
+/// (other than [`Opaque`](Node::Opaque) nodes) have the same index.
+#[derive(Debug, Default)]
+struct Arena {
+  nodes: Vec<(Node, LispVal)>,
+  intern: HashMap<Node, usize>,
+}
+
+impl Arena {
+  fn push(&mut self, node: Node, val: LispVal) -> usize {
+    let n = self.nodes.len();
+    if !matches!(node, Node::Opaque(_)) { self.intern.insert(node.clone(), n); }
+    self.nodes.push((node, val));
+    n
+  }
+
+  fn val(&self, n: usize) -> LispVal { self.nodes[n].1.clone() }
+
+  fn app(&mut self, env: &Environment, t: TermId, args: Box<[usize]>) -> usize {
+    let node = Node::App(t, args);
+    if let Some(&n) = self.intern.get(&node) { return n }
+    let Node::App(_, args) = &node else { unreachable!() };
+    let val = std::iter::once(LispVal::atom(env.terms[t].atom))
+      .chain(args.iter().map(|&a| self.val(a))).collect::<Vec<_>>();
+    self.push(node, LispVal::list(val))
+  }
+
+  /// Add an elaborated expression to the arena.
+  fn intern(&mut self, env: &Environment, e: &LispVal) -> usize {
+    let e = e.unwrapped_arc();
+    match &*e {
+      &LispKind::Atom(a) => {
+        let node = Node::Var(a);
+        if let Some(&n) = self.intern.get(&node) { return n }
+        return self.push(node, e.clone())
+      }
+      LispKind::List(es) => if let Some((head, args)) = es.split_first() {
+        if let Some(t) = head.as_atom().and_then(|a| env.term(a)) {
+          if env.terms[t].args.len() == args.len() {
+            let args = args.iter().map(|a| self.intern(env, a)).collect();
+            return self.app(env, t, args)
+          }
+        }
+      },
+      _ => {}
+    }
+    self.push(Node::Opaque(self.nodes.len()), e)
+  }
+
+  /// Match the pattern `p` against node `n`, extending the substitution `subst`.
+  fn matches(&self, p: &Pat, n: usize, subst: &mut [Option<usize>]) -> bool {
+    match *p {
+      Pat::Var(i) => match subst[i] {
+        Some(m) => m == n,
+        None => { subst[i] = Some(n); true }
+      },
+      Pat::Dummy => false,
+      Pat::App(t, ref ps) => match &self.nodes[n].0 {
+        Node::App(t2, args) if *t2 == t =>
+          ps.iter().zip(&**args).all(|(p, &a)| self.matches(p, a, subst)),
+        _ => false
+      }
+    }
+  }
+
+  /// Instantiate the pattern `p`, which must not contain dummy variables,
+  /// with the substitution `subst`.
+  fn inst(&mut self, env: &Environment, p: &Pat, subst: &[usize]) -> usize {
+    match *p {
+      Pat::Var(i) => subst[i],
+      Pat::Dummy => unreachable!("dummy variables are rejected when building the simp set"),
+      Pat::App(t, ref ps) => {
+        let args = ps.iter().map(|p| self.inst(env, p, subst)).collect();
+        self.app(env, t, args)
+      }
+    }
+  }
+}
+
+/// The state of a call to `simp`.
+#[derive(Debug)]
+struct Simp<'a> {
+  env: &'a Environment,
+  set: SimpSet,
+  arena: Arena,
+  /// The normal form of each node that has been simplified, together with a proof
+  /// of equality, or `None` if the node is already in normal form.
+  cache: HashMap<usize, (usize, Option<LispVal>)>,
+  steps: usize,
+  max_steps: usize,
+}
+
+impl Simp<'_> {
+  /// A proof of `R n n`, where `R` is the relation on sort `s`.
+  fn refl(&self, s: SortId, n: usize) -> LispVal {
+    self.set.rels[&s].refl.apply(&[self.arena.val(n)], vec![])
+  }
+
+  /// Given proofs `p: R a b` and `q: R b c`, where `None` means reflexivity,
+  /// returns a proof of `R a c`.
+  fn trans(&self, s: SortId, abc: [usize; 3], p: Option<LispVal>, q: Option<LispVal>) -> Option<LispVal> {
+    match (p, q) {
+      (None, r) | (r, None) => r,
+      (Some(p), Some(q)) =>
+        Some(self.set.rels[&s].trans.apply(&abc.map(|n| self.arena.val(n)), vec![p, q])),
+    }
+  }
+
+  /// Rewrite `n` to normal form, returning the normal form and a proof of equality.
+  fn simp(&mut self, n: usize) -> SResult<(usize, Option<LispVal>)> {
+    if let Some(res) = self.cache.get(&n) { return Ok(res.clone()) }
+    let (mut cur, mut pf) = self.congr(n)?;
+    while let Some((s, next, p)) = self.rewrite(cur) {
+      self.steps += 1;
+      if self.steps > self.max_steps {
+        return Err(format!("exceeded the maximum of {} rewrite steps", self.max_steps))
+      }
+      let (next2, q) = self.congr(next)?;
+      pf = self.trans(s, [n, cur, next], pf, Some(p));
+      pf = self.trans(s, [n, next, next2], pf, q);
+      cur = next2;
+    }
+    self.cache.insert(cur, (cur, None));
+    self.cache.insert(n, (cur, pf.clone()));
+    Ok((cur, pf))
+  }
+
+  /// Simplify the arguments of `n` using the congruence theorem for its head term.
+  fn congr(&mut self, n: usize) -> SResult<(usize, Option<LispVal>)> {
+    let Node::App(t, ref args) = self.arena.nodes[n].0 else { return Ok((n, None)) };
+    let Some(cong) = self.set.congs.get(&t) else { return Ok((n, None)) };
+    let (args, hyps) = (args.clone(), cong.hyps.clone());
+    let mut new_args = args.clone();
+    let mut proofs = Vec::with_capacity(hyps.len());
+    let mut changed = false;
+    for &i in &*hyps {
+      let (a, p) = self.simp(args[i])?;
+      new_args[i] = a;
+      proofs.push(match p {
+        Some(p) => { changed = true; p }
+        None => self.refl(self.env.terms[t].args[i].1.sort(), a),
+      });
+    }
+    if !changed { return Ok((n, None)) }
+    let vals = args.iter().zip(&*new_args)
+      .flat_map(|(&a, &b)| [self.arena.val(a), self.arena.val(b)]).collect::<Vec<_>>();
+    let p = self.set.congs[&t].tmpl.apply(&vals, proofs);
+    Ok((self.arena.app(self.env, t, new_args), Some(p)))
+  }
+
+  /// Rewrite `n` at the root, using the first applicable lemma or else by unfolding the
+  /// head definition. Returns the sort of `n`, the result, and a proof of equality.
+  fn rewrite(&mut self, n: usize) -> Option<(SortId, usize, LispVal)> {
+    let Node::App(t, _) = self.arena.nodes[n].0 else { return None };
+    let s = self.env.terms[t].ret.0;
+    let rel = self.set.rels.get(&s)?;
+    for lemma in self.set.lemmas.get(&t).into_iter().flatten() {
+      let mut subst = vec![None; lemma.bound.len()];
+      if !self.arena.matches(&lemma.lhs, n, &mut subst) { continue }
+      let Some(subst) = subst.into_iter().zip(&*lemma.bound)
+        .map(|(m, &bd)| m.filter(|&m| !bd || matches!(self.arena.nodes[m].0, Node::Var(_))))
+        .collect::<Option<Vec<_>>>() else { continue };
+      let vals = subst.iter().map(|&m| self.arena.val(m)).collect::<Vec<_>>();
+      let rhs = self.arena.inst(self.env, &lemma.rhs, &subst);
+      return Some((s, rhs, lemma.tmpl.apply(&vals, vec![])))
+    }
+    let body = self.set.unfold.get(&t)?;
+    let Node::App(_, args) = &self.arena.nodes[n].0 else { unreachable!() };
+    let args = args.clone();
+    let rhs = self.arena.inst(self.env, body, &args);
+    // `refine` will prove `R n rhs` from `R rhs rhs` by unfolding the definition
+    let ty = LispVal::list(vec![LispVal::atom(rel.term), self.arena.val(n), self.arena.val(rhs)]);
+    let p = rel.refl.apply(&[self.arena.val(rhs)], vec![]);
+    Some((s, rhs, LispVal::list(vec![LispVal::atom(AtomId::COLON), p, ty])))
+  }
+}
+
+/// Get the head term of the expression `e` in a theorem statement.
+fn head_term(heap: &[ExprNode], nargs: usize, e: &ExprNode) -> Option<TermId> {
+  match *e {
+    ExprNode::Ref(i) if i >= nargs => head_term(heap, nargs, &heap[i]),
+    ExprNode::App(t, _) => Some(t),
+    _ => None
+  }
+}
+
+/// If a theorem with `nargs` arguments, hypotheses `hyps` and conclusion
+/// `R (f us) (f vs)` is a congruence theorem for `f`, returns the congruence data.
+/// `rels` gives the relation on each sort.
+#[allow(clippy::too_many_arguments)]
+fn congruence(env: &Environment, rels: &HashMap<SortId, TermId>,
+  thm: AtomId, nargs: usize, hyps: &[Pat], f: TermId, us: &[Pat], vs: &[Pat],
+) -> Option<Cong> {
+  let td = &env.terms[f];
+  // slots[v] = (i, right) if theorem variable `v` is the `i`th argument on the right or left
+  let mut slots = vec![None; nargs];
+  let mut free = vec![false; us.len()];
+  for (i, (u, v)) in us.iter().zip(vs).enumerate() {
+    let (&Pat::Var(u), &Pat::Var(v)) = (u, v) else { return None };
+    if slots[u].replace((i, false)).is_some() { return None }
+    if u != v {
+      if slots[v].replace((i, true)).is_some() { return None }
+      free[i] = true;
+    }
+  }
+  let mut pos = Vec::with_capacity(hyps.len());
+  for h in hyps {
+    let Pat::App(rel, _) = *h else { return None };
+    let (lhs, rhs) = h.rel_vars(rel)?;
+    let (i, false) = slots[lhs]? else { return None };
+    if slots[rhs]? != (i, true) || !std::mem::take(&mut free[i]) ||
+      rels.get(&td.args[i].1.sort()) != Some(&rel) { return None }
+    pos.push(i);
+  }
+  if pos.is_empty() || free.contains(&true) { return None }
+  let slots = slots.into_iter()
+    .map(|s| s.map(|(i, right)| 2 * i + usize::from(right)))
+    .collect::<Option<_>>()?;
+  Some(Cong { tmpl: Template { thm, slots }, hyps: pos.into() })
+}
+
+impl Elaborator {
+  /// Get the list of atoms in a `simp` configuration entry, which is either a list of
+  /// atoms or an atom map (in which case the keys with truthy values are used).
+  fn simp_atoms(&self, e: &LispVal) -> SResult<Vec<AtomId>> {
+    let keys = e.unwrapped(|e| if let LispKind::AtomMap(m) = e {
+      Some(m.iter().filter(|(_, v)| v.truthy()).map(|(&a, _)| a).collect::<Vec<_>>())
+    } else { None });
+    if let Some(mut keys) = keys {
+      keys.sort_unstable();
+      return Ok(keys)
+    }
+    let mut u = Uncons::from(e.clone());
+    let mut out = vec![];
+    for a in &mut u {
+      out.push(a.as_atom().ok_or_else(|| format!("expected an atom, got {}", self.print(&a)))?)
+    }
+    if !u.is_empty() { return Err(format!("expected a list, got {}", self.print(e))) }
+    Ok(out)
+  }
+
+  fn simp_term(&self, a: AtomId) -> SResult<TermId> {
+    self.term(a).ok_or_else(|| format!("unknown term '{}'", self.data[a].name))
+  }
+
+  /// Build the [`SimpSet`] for the configuration entries `rels`, `imp`, `lemmas`
+  /// and `unfold`.
+  fn simp_set(&self,
+    rels: Option<LispVal>, imp: Option<LispVal>, lemmas: Option<LispVal>, unfold: Option<LispVal>,
+  ) -> SResult<SimpSet> {
+    let mut rel_of = HashMap::new();
+    let mut rel_sort = HashMap::new();
+    for r in self.simp_atoms(&rels.ok_or("missing 'rels' entry")?)? {
+      let t = self.simp_term(r)?;
+      let s = match *self.terms[t].args {
+        [(_, ty1), (_, ty2)] if ty1.sort() == ty2.sort() => ty1.sort(),
+        _ => return Err(format!("'{}' is not a binary relation", self.data[r].name))
+      };
+      if rel_of.insert(s, t).is_some() {
+        return Err(format!("more than one relation given for sort '{}'", self.sorts[s].name))
+      }
+      rel_sort.insert(t, s);
+    }
+    let imp = match imp.and_then(|e| e.as_atom()) {
+      Some(a) => Some(self.simp_term(a)?),
+      None => None,
+    };
+
+    let mut refl = HashMap::new();
+    let mut trans = HashMap::new();
+    let mut congs = HashMap::<_, Cong>::new();
+    for (_, td) in self.thms.enum_iter() {
+      let nargs = td.args.len();
+      match head_term(&td.heap, nargs, &td.ret) {
+        Some(t) if Some(t) == imp || rel_sort.contains_key(&t) => {}
+        _ => continue
+      }
+      let pat = |e| Pat::new(&self.env, &td.heap, &td.store, nargs, e);
+      let mut hyps = td.hyps.iter().map(|(_, h)| pat(h)).collect::<Vec<_>>();
+      let Pat::App(r, sides) = split_imp(imp, pat(&td.ret), &mut hyps) else { continue };
+      let Some(&s) = rel_sort.get(&r) else { continue };
+      match (&*hyps, &*sides) {
+        ([], &[Pat::Var(a), Pat::Var(b)]) if a == b && nargs == 1 => {
+          refl.entry(s).or_insert_with(|| Template { thm: td.atom, slots: Box::new([0]) });
+        }
+        ([h1, h2], &[Pat::Var(a), Pat::Var(c)]) if nargs == 3 => {
+          if let (Some((a1, b)), Some((b1, c1))) = (h1.rel_vars(r), h2.rel_vars(r)) {
+            if a1 == a && b1 == b && c1 == c && a != b && b != c && a != c {
+              let mut slots = [0; 3];
+              (slots[a], slots[b], slots[c]) = (0, 1, 2);
+              trans.entry(s).or_insert_with(|| Template { thm: td.atom, slots: Box::new(slots) });
+            }
+          }
+        }
+        (_, [Pat::App(f, us), Pat::App(g, vs)]) if f == g && rel_of.get(&self.terms[*f].ret.0) == Some(&r) => {
+          if let Some(cong) = congruence(&self.env, &rel_of, td.atom, nargs, &hyps, *f, us, vs) {
+            match congs.get(f) {
+              Some(old) if old.hyps.len() >= cong.hyps.len() => {}
+              _ => { congs.insert(*f, cong); }
+            }
+          }
+        }
+        _ => {}
+      }
+    }
+
+    let mut set = SimpSet { congs, ..SimpSet::default() };
+    for (s, t) in rel_of {
+      let name = &self.data[self.terms[t].atom].name;
+      set.rels.insert(s, Rel {
+        term: self.terms[t].atom,
+        refl: refl.remove(&s).ok_or_else(|| format!("no reflexivity theorem found for '{name}'"))?,
+        trans: trans.remove(&s).ok_or_else(|| format!("no transitivity theorem found for '{name}'"))?,
+      });
+    }
+
+    for x in lemmas.map_or(Ok(vec![]), |e| self.simp_atoms(&e))? {
+      let name = &self.data[x].name;
+      let th = self.thm(x).ok_or_else(|| format!("unknown theorem '{name}'"))?;
+      let td = &self.thms[th];
+      let nargs = td.args.len();
+      let pat = |e| Pat::new(&self.env, &td.heap, &td.store, nargs, e);
+      let mut hyps = td.hyps.iter().map(|(_, h)| pat(h)).collect::<Vec<_>>();
+      let concl = split_imp(imp, pat(&td.ret), &mut hyps);
+      if !hyps.is_empty() { return Err(format!("'{name}' has hypotheses")) }
+      let (lhs, rhs) = match concl {
+        Pat::App(r, sides) if rel_sort.contains_key(&r) => {
+          let mut it = sides.into_vec().into_iter();
+          (it.next(), it.next())
+        }
+        _ => (None, None)
+      };
+      let (Some(lhs), Some(rhs)) = (lhs, rhs) else { return Err(format!("'{name}' is not an equation")) };
+      let Pat::App(f, _) = lhs else {
+        return Err(format!("the left hand side of '{name}' is a variable"))
+      };
+      let mut vars = vec![false; nargs];
+      if !lhs.vars(&mut vars) || !rhs.vars(&mut vec![false; nargs]) {
+        return Err(format!("'{name}' contains dummy variables"))
+      }
+      if vars.contains(&false) {
+        return Err(format!("the left hand side of '{name}' does not contain all the variables"))
+      }
+      set.lemmas.entry(f).or_default().push(Lemma {
+        tmpl: Template { thm: x, slots: (0..nargs).collect() },
+        bound: td.args.iter().map(|(_, ty)| ty.bound()).collect(),
+        lhs, rhs,
+      });
+    }
+
+    for x in unfold.map_or(Ok(vec![]), |e| self.simp_atoms(&e))? {
+      let name = &self.data[x].name;
+      let t = self.simp_term(x)?;
+      let td = &self.terms[t];
+      let TermKind::Def(Some(e)) = &td.kind else {
+        return Err(format!("'{name}' is not a definition"))
+      };
+      let nargs = td.args.len();
+      let body = Pat::new(&self.env, &e.heap, &e.store, nargs, e.head());
+      if !body.vars(&mut vec![false; nargs]) {
+        return Err(format!("cannot unfold '{name}', which has dummy variables"))
+      }
+      set.unfold.insert(t, body);
+    }
+    Ok(set)
+  }
+
+  /// Implementation of the `(simp cfg e)` builtin. Returns `(e2 p)` where `e2` is the
+  /// normal form of `e` and `p` is a proof pre-expression for `R e e2`.
+  pub fn simp(&mut self, sp: Span, cfg: &LispVal, e: &LispVal) -> SResult<LispVal> {
+    let keys = [&b"rels"[..], b"imp", b"lemmas", b"unfold", b"max-steps"].map(|k| self.get_atom(k));
+    let [rels, imp, lemmas, unfold, max_steps] = cfg.unwrapped(|c| match c {
+      LispKind::AtomMap(m) => Ok(keys.map(|k| m.get(&k).filter(|v| v.is_def()).cloned())),
+      _ => Err(format!("expected an atom map, got {}", self.print(c)))
+    })?;
+    let max_steps = match max_steps {
+      Some(n) => n.as_int(BigInt::to_usize).flatten().ok_or("expected a number for 'max-steps'")?,
+      None => MAX_STEPS,
+    };
+    let set = self.simp_set(rels, imp, lemmas, unfold)?;
+    let sort = match self.infer_target(sp, e).map_err(|e| e.kind.msg())? {
+      InferTarget::Bound(s) | InferTarget::Reg(s) => self.data[s].sort,
+      InferTarget::Unknown | InferTarget::Provable => None,
+    }.ok_or("cannot infer the sort of the expression")?;
+    if !set.rels.contains_key(&sort) {
+      return Err(format!("no relation given for sort '{}'", self.sorts[sort].name))
+    }
+    let mut simp = Simp {
+      env: &self.env, set, arena: Arena::default(), cache: HashMap::new(), steps: 0, max_steps
+    };
+    let n = simp.arena.intern(&self.env, e);
+    let (n2, p) = simp.simp(n)?;
+    let p = p.unwrap_or_else(|| simp.refl(sort, n2));
+    Ok(LispVal::list(vec![simp.arena.val(n2), p]))
+  }
+}
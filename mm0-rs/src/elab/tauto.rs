@@ -0,0 +1,608 @@
+//! The `tauto` tactic, a proof search procedure for propositional and first-order logic.
+//!
+//! `(tauto cfg e)` proves the formula `e` using a classical tableau: it assumes `~e` and
+//! decomposes the assumptions until every branch contains a contradiction. All the rules
+//! are invertible, so no backtracking is needed and the search fails as soon as a
+//! branch cannot be closed. Quantifiers are instantiated only at their own bound
+//! variable, and an existential assumption `E. x a` is eliminated only when `x` is not
+//! free in the remaining assumptions, so the first-order part is incomplete.
+//!
+//! The proof is reconstructed in deduction form, as a proof of `G -> F.` where `G` is the
+//! conjunction of the assumptions on the current branch, using a fixed set of library
+//! theorems. The connectives and theorems are looked up by name; the defaults are the
+//! names used in `peano.mm1`, and the configuration `cfg` can map any of them to a
+//! different name. The theorems are applied to their hypotheses only, leaving the
+//! variables to unification.
+
+use std::collections::HashMap;
+use std::time::Instant;
+use num::{BigInt, ToPrimitive};
+use crate::AtomId;
+use super::Elaborator;
+use super::local_context::InferSort;
+use super::lisp::{LispKind, LispVal, Uncons, eval::SResult};
+
+/// Declare a struct of the atoms used by a tactic. Each field is named after the key
+/// that overrides it in the configuration atom map, which is also its default value.
+macro_rules! make_names {
+  ($(#[$sattr:meta])* struct $name:ident { $($(#[$attr:meta])* $x:ident,)* }) => {
+    $(#[$sattr])* struct $name { $($(#[$attr])* $x: AtomId,)* }
+
+    impl $name {
+      fn new(elab: &mut Elaborator, m: &HashMap<AtomId, LispVal>) -> SResult<$name> {
+        Ok($name {$($x: {
+          let key = elab.get_atom(stringify!($x).as_bytes());
+          match m.get(&key).filter(|v| v.is_def()) {
+            None => key,
+            Some(v) => v.as_atom().ok_or_else(||
+              format!("expected an atom for '{}', got {}", stringify!($x), elab.print(v)))?,
+          }
+        },)*})
+      }
+    }
+  }
+}
+
+/// The default value of the `max-steps` option of `tauto`.
+const MAX_STEPS: usize = 100_000;
+
+make_names! {
+  /// The term and theorem names used by `tauto`.
+  #[derive(Clone, Copy)]
+  struct Names {
+    // Connectives
+    /// `im: wff > wff > wff`, written `a -> b`
+    im,
+    /// `not: wff > wff`, written `~a`
+    not,
+    /// `an: wff > wff > wff`, written `a /\ b`
+    an,
+    /// `or: wff > wff > wff`, written `a \/ b`
+    or,
+    /// `iff: wff > wff > wff`, written `a <-> b`
+    iff,
+    /// `tru: wff`, written `T.`
+    tru,
+    /// `fal: wff`, written `F.`
+    fal,
+    /// `al {x: nat} (a: wff x): wff`, written `A. x a`
+    al,
+    /// `ex {x: nat} (a: wff x): wff`, written `E. x a`
+    ex,
+
+    // Theorems
+    /// `a -> a`
+    id,
+    /// `a /\ b -> b`
+    anr,
+    /// `(h: a -> c): a /\ b -> c`
+    anwl,
+    /// `(h: a /\ b -> c): a -> b -> c`
+    exp,
+    /// `(h1: a -> b) (h2: a -> b -> c): a -> c`
+    mpd,
+    /// `(h1: b -> c) (h2: a -> b): a -> c`
+    syl,
+    /// `(h: b): a -> b`
+    a1i,
+    /// `(h: a -> b): a -> c -> b`
+    a1d,
+    /// `a -> b -> a`
+    ax_1,
+    /// `(h1: a -> b) (h2: ~a -> b): b`
+    cases,
+    /// `(h1: a -> b -> c) (h2: a -> ~b -> c): a -> c`
+    casesd,
+    /// `~a -> a -> b`
+    absurd,
+    /// `F. -> a`
+    efal,
+    /// `~~a -> a`
+    dne,
+    /// `(h: a -> ~b -> c): a -> ~c -> b`
+    con1d,
+    /// `(h1: a -> ~b) (h2: a -> c -> b): a -> ~c`
+    mtd,
+    /// `(h: a -> b /\ c): a -> b`
+    anld,
+    /// `(h: a -> b /\ c): a -> c`
+    anrd,
+    /// `(h1: a -> b) (h2: a -> c): a -> b /\ c`
+    iand,
+    /// `a -> a \/ b`
+    orl,
+    /// `b -> a \/ b`
+    orr,
+    /// `(h1: a -> b -> d) (h2: a -> c -> d): a -> b \/ c -> d`
+    eord,
+    /// `(h: a -> (b <-> c)): a -> b -> c`
+    bi1d,
+    /// `(h: a -> (b <-> c)): a -> c -> b`
+    bi2d,
+    /// `(h1: a -> b -> c) (h2: a -> c -> b): a -> (b <-> c)`
+    ibid,
+    /// `(h1: a -> (c <-> b)) (h2: a -> b): a -> c`
+    mpbird,
+    /// `A. x a -> a`
+    eal,
+    /// `E. x ~a <-> ~(A. x a)`
+    exnal,
+    /// `A. x ~a <-> ~(E. x a)`
+    alnex,
+    /// `(h: a -> b): E. x a -> b`, where `x` does not occur in `b`
+    eex,
+    /// `(h1: F/ x a) (h2: F/ x c) (h3: a -> b -> c): a -> E. x b -> c`
+    eexdh,
+    /// `F/ x a`, where `x` does not occur in `a`
+    nfv,
+    /// `F/ x A. x a`
+    nfal1,
+    /// `F/ x E. x a`
+    nfex1,
+    /// `(h: F/ x a): F/ x A. y a`
+    nfal,
+    /// `(h: F/ x a): F/ x E. y a`
+    nfex,
+    /// `(h: F/ x a): F/ x ~a`
+    nfnot,
+    /// `(h1: F/ x a) (h2: F/ x b): F/ x a -> b`
+    nfim,
+    /// `(h1: F/ x a) (h2: F/ x b): F/ x a /\ b`
+    nfan,
+    /// `(h1: F/ x a) (h2: F/ x b): F/ x a \/ b`
+    nfor,
+    /// `(h1: F/ x a) (h2: F/ x b): F/ x a <-> b`
+    nfbi,
+  }
+}
+
+/// A hash-consed expression.
+#[allow(variant_size_differences)]
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Node {
+  /// A variable.
+  Var(AtomId),
+  /// A term application.
+  App(AtomId, Box<[usize]>),
+}
+
+/// The shape of a formula, according to its head connective.
+#[derive(Clone, Copy)]
+enum Form {
+  Imp(usize, usize),
+  Not(usize),
+  And(usize, usize),
+  Or(usize, usize),
+  Iff(usize, usize),
+  True,
+  False,
+  All(AtomId, usize),
+  Ex(AtomId, usize),
+  Atom,
+}
+
+/// A non-branching rule, which adds a subformula of the assumption (or its negation).
+#[derive(Clone, Copy)]
+enum Alpha {
+  /// `a /\ b`: `a`
+  AndL(usize),
+  /// `a /\ b`: `b`
+  AndR(usize),
+  /// `A. x a`: `a`
+  All(usize),
+  /// `~~a`: `a`
+  NotNot(usize),
+  /// `~(a \/ b)`: `~a`
+  NotOrL(usize),
+  /// `~(a \/ b)`: `~b`
+  NotOrR(usize),
+  /// `~(a -> b)`: `a`
+  NotImpL(usize),
+  /// `~(a -> b)`: `~b`
+  NotImpR(usize),
+  /// `~(A. x a)`: `E. x ~a`
+  NotAll(AtomId, usize),
+  /// `~(E. x a)`: `A. x ~a`
+  NotEx(AtomId, usize),
+}
+
+/// A branching rule, which splits on whether `a` holds for some subformula `a`
+/// of the assumption.
+#[derive(Clone, Copy)]
+enum Beta {
+  /// `a -> b`: `b` if `a`
+  Imp(usize),
+  /// `a <-> b`: `b` if `a`, `~b` if `~a`
+  Iff(usize),
+  /// `~(a /\ b)`: `~b` if `a`
+  NotAnd(usize),
+  /// `~(a <-> b)`: `~b` if `a`, `b` if `~a`
+  NotIff(usize),
+}
+
+/// An assumption on the current branch.
+#[derive(Clone, Copy)]
+struct Entry {
+  form: usize,
+  /// The number of rule applications already done for this assumption.
+  done: u8,
+}
+
+struct Tauto<'a> {
+  elab: &'a Elaborator,
+  n: Names,
+  nodes: Vec<Node>,
+  /// The bound variables occurring in each node (including those bound by a quantifier
+  /// inside the node), or `None` if they are not known.
+  fvs: Vec<Option<Box<[AtomId]>>>,
+  map: HashMap<Node, usize>,
+  steps: usize,
+  max_steps: usize,
+  deadline: Option<Instant>,
+}
+
+#[allow(clippy::many_single_char_names)]
+impl Tauto<'_> {
+  fn node(&mut self, node: Node, fv: Option<Box<[AtomId]>>) -> usize {
+    if let Some(&n) = self.map.get(&node) { return n }
+    let n = self.nodes.len();
+    self.nodes.push(node.clone());
+    self.fvs.push(fv);
+    self.map.insert(node, n);
+    n
+  }
+
+  /// Construct the term application `t args`.
+  fn app(&mut self, t: AtomId, args: Box<[usize]>) -> SResult<usize> {
+    let name = &self.elab.data[t].name;
+    let tid = self.elab.term(t).ok_or_else(|| format!("unknown term '{name}'"))?;
+    let td = &self.elab.terms[tid];
+    if td.args.len() != args.len() {
+      return Err(format!("term '{name}' expects {} arguments, got {}", td.args.len(), args.len()))
+    }
+    let mut fv = Some(vec![]);
+    for &a in &*args {
+      match (&mut fv, &self.fvs[a]) {
+        (Some(out), Some(vs)) => for &x in &**vs {
+          if !out.contains(&x) { out.push(x) }
+        },
+        (fv, _) => *fv = None,
+      }
+    }
+    Ok(self.node(Node::App(t, args), fv.map(Vec::into_boxed_slice)))
+  }
+
+  fn intern(&mut self, e: &LispVal) -> SResult<usize> {
+    if let Some(a) = e.as_atom() {
+      let fv = match self.elab.lc.vars.get(&a) {
+        Some((_, InferSort::Bound {..})) => Some(Box::new([a]) as Box<[_]>),
+        Some((_, InferSort::Reg {deps, ..})) => Some(deps.clone()),
+        _ if self.elab.term(a).is_some() => return self.app(a, Box::new([])),
+        _ => None,
+      };
+      return Ok(self.node(Node::Var(a), fv))
+    }
+    let mut u = Uncons::from(e.clone());
+    let t = u.next().and_then(|t| t.as_atom());
+    let args = (&mut u).map(|e| self.intern(&e)).collect::<SResult<Box<[_]>>>()?;
+    match t {
+      Some(t) if u.is_empty() => self.app(t, args),
+      _ => Err(format!("expected an expression, got {}", self.elab.print(e)))
+    }
+  }
+
+  fn form(&self, n: usize) -> Form {
+    let Node::App(t, ref args) = self.nodes[n] else { return Form::Atom };
+    let n = &self.n;
+    match **args {
+      [a, b] if t == n.im => Form::Imp(a, b),
+      [a] if t == n.not => Form::Not(a),
+      [a, b] if t == n.an => Form::And(a, b),
+      [a, b] if t == n.or => Form::Or(a, b),
+      [a, b] if t == n.iff => Form::Iff(a, b),
+      [] if t == n.tru => Form::True,
+      [] if t == n.fal => Form::False,
+      [x, a] if t == n.al || t == n.ex => match self.nodes[x] {
+        Node::Var(x) if t == n.al => Form::All(x, a),
+        Node::Var(x) => Form::Ex(x, a),
+        Node::App(..) => Form::Atom,
+      },
+      _ => Form::Atom,
+    }
+  }
+
+  fn not(&mut self, a: usize) -> SResult<usize> { self.app(self.n.not, Box::new([a])) }
+
+  /// The theorem `a` applied to `hyps`.
+  fn thm<const N: usize>(&self, a: AtomId, hyps: [LispVal; N]) -> SResult<LispVal> {
+    if self.elab.thm(a).is_none() { return Err(format!("unknown theorem '{}'", self.elab.data[a].name)) }
+    let mut args = vec![LispVal::atom(a)];
+    args.extend(hyps);
+    Ok(LispVal::list(args))
+  }
+
+  /// A proof of `G -> a`, where `a` is the `i`th of the `n` assumptions in `G`.
+  fn hyp(&self, i: usize, n: usize) -> SResult<LispVal> {
+    if n == 1 { self.thm(self.n.id, []) }
+    else if i + 1 == n { self.thm(self.n.anr, []) }
+    else { self.thm(self.n.anwl, [self.hyp(i, n - 1)?]) }
+  }
+
+  fn tick(&mut self) -> SResult<()> {
+    self.steps += 1;
+    if self.steps > self.max_steps { return Err("step limit exceeded".into()) }
+    if self.steps % 64 == 0 && self.deadline.is_some_and(|t| t < Instant::now()) {
+      return Err("timeout".into())
+    }
+    Ok(())
+  }
+
+  fn find(ctx: &[Entry], a: usize) -> Option<usize> { ctx.iter().position(|e| e.form == a) }
+
+  /// Given `p: G -> a`, add `a` to the assumptions and refute the result.
+  fn add(&mut self, mut ctx: Vec<Entry>, a: usize, p: LispVal) -> SResult<LispVal> {
+    if Self::find(&ctx, a).is_some() { return self.refute(ctx) }
+    ctx.push(Entry { form: a, done: 0 });
+    let q = self.refute(ctx)?;
+    self.thm(self.n.mpd, [p, self.thm(self.n.exp, [q])?])
+  }
+
+  /// Apply the next non-branching rule to the `i`th assumption, if there is one,
+  /// returning the new assumption and its proof. If `gamma` is set, this only
+  /// instantiates universal assumptions, and otherwise it does everything else.
+  fn alpha(&mut self, ctx: &mut [Entry], i: usize, gamma: bool) -> SResult<Option<(usize, LispVal)>> {
+    let Entry { form, done } = ctx[i];
+    let rule = match (self.form(form), done) {
+      (Form::All(_, a), 0) if gamma => Alpha::All(a),
+      _ if gamma => return Ok(None),
+      (Form::And(a, _), 0) => Alpha::AndL(a),
+      (Form::And(_, b), 1) => Alpha::AndR(b),
+      (Form::Not(a), _) => match (self.form(a), done) {
+        (Form::Not(a), 0) => Alpha::NotNot(a),
+        (Form::Or(a, _), 0) => Alpha::NotOrL(a),
+        (Form::Or(_, b), 1) => Alpha::NotOrR(b),
+        (Form::Imp(a, _), 0) => Alpha::NotImpL(a),
+        (Form::Imp(_, b), 1) => Alpha::NotImpR(b),
+        (Form::All(x, a), 0) => Alpha::NotAll(x, a),
+        (Form::Ex(x, a), 0) => Alpha::NotEx(x, a),
+        _ => return Ok(None)
+      },
+      _ => return Ok(None)
+    };
+    ctx[i].done += 1;
+    let h = self.hyp(i, ctx.len())?;
+    let n = self.n;
+    let a1i = |this: &Self, th| this.thm(n.a1i, [this.thm(th, [])?]);
+    Ok(Some(match rule {
+      Alpha::AndL(a) => (a, self.thm(n.anld, [h])?),
+      Alpha::AndR(b) => (b, self.thm(n.anrd, [h])?),
+      Alpha::All(a) => (a, self.thm(n.syl, [self.thm(n.eal, [])?, h])?),
+      Alpha::NotNot(a) => (a, self.thm(n.syl, [self.thm(n.dne, [])?, h])?),
+      Alpha::NotOrL(a) => (self.not(a)?, self.thm(n.mtd, [h, a1i(self, n.orl)?])?),
+      Alpha::NotOrR(b) => (self.not(b)?, self.thm(n.mtd, [h, a1i(self, n.orr)?])?),
+      Alpha::NotImpL(a) => (a, self.thm(n.mpd, [h, self.thm(n.con1d, [a1i(self, n.absurd)?])?])?),
+      Alpha::NotImpR(b) => (self.not(b)?, self.thm(n.mtd, [h, a1i(self, n.ax_1)?])?),
+      Alpha::NotAll(x, a) => {
+        let (na, x) = (self.not(a)?, self.node(Node::Var(x), Some(Box::new([x]))));
+        (self.app(n.ex, Box::new([x, na]))?, self.thm(n.mpbird, [a1i(self, n.exnal)?, h])?)
+      }
+      Alpha::NotEx(x, a) => {
+        let (na, x) = (self.not(a)?, self.node(Node::Var(x), Some(Box::new([x]))));
+        (self.app(n.al, Box::new([x, na]))?, self.thm(n.mpbird, [a1i(self, n.alnex)?, h])?)
+      }
+    }))
+  }
+
+  /// Returns true if all the rules for this assumption have been applied, so that it
+  /// can be dropped without losing anything.
+  fn spent(&self, e: Entry) -> bool {
+    match self.form(e.form) {
+      Form::And(..) => e.done >= 2,
+      Form::Not(a) => match self.form(a) {
+        Form::Or(..) | Form::Imp(..) => e.done >= 2,
+        _ => e.done != 0,
+      },
+      Form::All(..) => false,
+      _ => e.done != 0,
+    }
+  }
+
+  /// A proof of `F/ x a`, if `x` is not free in `a`.
+  fn nf(&self, x: AtomId, a: usize) -> SResult<Option<LispVal>> {
+    if self.fvs[a].as_ref().is_some_and(|fv| !fv.contains(&x)) {
+      return Ok(Some(self.thm(self.n.nfv, [])?))
+    }
+    let n = &self.n;
+    let un = |th, a| Ok(match self.nf(x, a)? { Some(h) => Some(self.thm(th, [h])?), None => None });
+    let bin = |th, a, b| Ok(match (self.nf(x, a)?, self.nf(x, b)?) {
+      (Some(h1), Some(h2)) => Some(self.thm(th, [h1, h2])?),
+      _ => None,
+    });
+    match self.form(a) {
+      Form::All(y, _) if x == y => Ok(Some(self.thm(n.nfal1, [])?)),
+      Form::Ex(y, _) if x == y => Ok(Some(self.thm(n.nfex1, [])?)),
+      Form::All(_, a) => un(n.nfal, a),
+      Form::Ex(_, a) => un(n.nfex, a),
+      Form::Not(a) => un(n.nfnot, a),
+      Form::Imp(a, b) => bin(n.nfim, a, b),
+      Form::And(a, b) => bin(n.nfan, a, b),
+      Form::Or(a, b) => bin(n.nfor, a, b),
+      Form::Iff(a, b) => bin(n.nfbi, a, b),
+      Form::True | Form::False | Form::Atom => Ok(None),
+    }
+  }
+
+  /// Eliminate the existential assumption `E. x a` at `i`. The new context consists
+  /// of `a` and the assumptions which are not yet spent, and `x` may not be free in them.
+  fn delta(&mut self, ctx: &[Entry], i: usize, x: AtomId, a: usize) -> SResult<Option<LispVal>> {
+    let keep = (0..ctx.len()).filter(|&j| j != i && !self.spent(ctx[j])).collect::<Vec<_>>();
+    let n = self.n;
+    // a proof of `F/ x G'`, where `G'` is the conjunction of the kept assumptions
+    let mut nf = None;
+    for &j in &keep {
+      let Some(h) = self.nf(x, ctx[j].form)? else { return Ok(None) };
+      nf = Some(match nf { None => h, Some(h0) => self.thm(n.nfan, [h0, h])? })
+    }
+    let h = self.hyp(i, ctx.len())?;
+    let mut ctx2 = keep.iter().map(|&j| ctx[j]).collect::<Vec<_>>();
+    ctx2.push(Entry { form: a, done: 0 });
+    let q = self.refute(ctx2)?;
+    let Some(nf) = nf else { return Ok(Some(self.thm(n.syl, [self.thm(n.eex, [q])?, h])?)) };
+    // a proof of `G -> G'`
+    let mut p = None;
+    for &j in &keep {
+      let h = self.hyp(j, ctx.len())?;
+      p = Some(match p { None => h, Some(p) => self.thm(n.iand, [p, h])? })
+    }
+    let p = p.ok_or("impossible")?;
+    let q = self.thm(n.eexdh, [nf, self.thm(n.nfv, [])?, self.thm(n.exp, [q])?])?;
+    Ok(Some(self.thm(n.mpd, [h, self.thm(n.syl, [q, p])?])?))
+  }
+
+  /// Apply a branching rule to the assumption at `i`, by cases on `a`.
+  fn beta(&mut self, mut ctx: Vec<Entry>, i: usize, a: usize, rule: Beta) -> SResult<LispVal> {
+    ctx[i].done = 1;
+    let na = self.not(a)?;
+    if let Some(j) = Self::find(&ctx, a) { return self.beta_pos(ctx, i, j, rule) }
+    if let Some(j) = Self::find(&ctx, na) { return self.beta_neg(ctx, i, j, rule) }
+    let n = ctx.len();
+    let mut ctx2 = ctx.clone();
+    ctx.push(Entry { form: a, done: 0 });
+    let q1 = self.beta_pos(ctx, i, n, rule)?;
+    ctx2.push(Entry { form: na, done: 0 });
+    let q2 = self.beta_neg(ctx2, i, n, rule)?;
+    self.thm(self.n.casesd, [self.thm(self.n.exp, [q1])?, self.thm(self.n.exp, [q2])?])
+  }
+
+  /// The case of [`beta`](Self::beta) where `a` is the `j`th assumption.
+  fn beta_pos(&mut self, ctx: Vec<Entry>, i: usize, j: usize, rule: Beta) -> SResult<LispVal> {
+    let len = ctx.len();
+    let (h, ha) = (self.hyp(i, len)?, self.hyp(j, len)?);
+    let n = self.n;
+    match rule {
+      Beta::Imp(b) => { let p = self.thm(n.mpd, [ha, h])?; self.add(ctx, b, p) }
+      Beta::Iff(b) => { let p = self.thm(n.mpd, [ha, self.thm(n.bi1d, [h])?])?; self.add(ctx, b, p) }
+      Beta::NotAnd(b) => {
+        // G /\ b -> a /\ b
+        let p = self.thm(n.iand, [self.hyp(j, len + 1)?, self.hyp(len, len + 1)?])?;
+        let p = self.thm(n.mtd, [h, self.thm(n.exp, [p])?])?;
+        let nb = self.not(b)?;
+        self.add(ctx, nb, p)
+      }
+      Beta::NotIff(b) => {
+        // G /\ b -> (a <-> b)
+        let p1 = self.thm(n.a1d, [self.hyp(len, len + 1)?])?;
+        let p2 = self.thm(n.a1d, [self.hyp(j, len + 1)?])?;
+        let p = self.thm(n.mtd, [h, self.thm(n.exp, [self.thm(n.ibid, [p1, p2])?])?])?;
+        let nb = self.not(b)?;
+        self.add(ctx, nb, p)
+      }
+    }
+  }
+
+  /// The case of [`beta`](Self::beta) where `~a` is the `j`th assumption.
+  fn beta_neg(&mut self, ctx: Vec<Entry>, i: usize, j: usize, rule: Beta) -> SResult<LispVal> {
+    let len = ctx.len();
+    let h = self.hyp(i, len)?;
+    let n = self.n;
+    match rule {
+      Beta::Imp(_) | Beta::NotAnd(_) => self.refute(ctx),
+      Beta::Iff(b) => {
+        let p = self.thm(n.mtd, [self.hyp(j, len)?, self.thm(n.bi2d, [h])?])?;
+        let nb = self.not(b)?;
+        self.add(ctx, nb, p)
+      }
+      Beta::NotIff(b) => {
+        // G /\ ~b -> (a <-> b)
+        let p1 = self.thm(n.syl, [self.thm(n.absurd, [])?, self.hyp(j, len + 1)?])?;
+        let p2 = self.thm(n.syl, [self.thm(n.absurd, [])?, self.hyp(len, len + 1)?])?;
+        let p = self.thm(n.con1d, [self.thm(n.exp, [self.thm(n.ibid, [p1, p2])?])?])?;
+        let p = self.thm(n.mpd, [h, p])?;
+        self.add(ctx, b, p)
+      }
+    }
+  }
+
+  /// Returns a proof of `G -> F.`, where `G` is the conjunction of the assumptions `ctx`.
+  fn refute(&mut self, mut ctx: Vec<Entry>) -> SResult<LispVal> {
+    self.tick()?;
+    let len = ctx.len();
+    let pos = ctx.iter().enumerate().map(|(i, e)| (e.form, i)).collect::<HashMap<_, _>>();
+    for (i, e) in ctx.iter().enumerate() {
+      match self.form(e.form) {
+        Form::False => return self.hyp(i, len),
+        Form::Not(a) => match self.form(a) {
+          Form::True => return self.hyp(i, len),
+          _ => if let Some(&j) = pos.get(&a) {
+            let p = self.thm(self.n.syl, [self.thm(self.n.absurd, [])?, self.hyp(i, len)?])?;
+            return self.thm(self.n.mpd, [self.hyp(j, len)?, p])
+          }
+        }
+        _ => {}
+      }
+    }
+    for i in 0..len {
+      if let Some((a, p)) = self.alpha(&mut ctx, i, false)? { return self.add(ctx, a, p) }
+    }
+    // Existentials are eliminated before universals are instantiated, since the
+    // instances may contain the bound variable
+    for i in 0..len {
+      if let (Form::Ex(x, a), 0) = (self.form(ctx[i].form), ctx[i].done) {
+        if let Some(p) = self.delta(&ctx, i, x, a)? { return Ok(p) }
+      }
+    }
+    for i in 0..len {
+      if ctx[i].done != 0 { continue }
+      match self.form(ctx[i].form) {
+        Form::Or(a, b) => {
+          let h = self.hyp(i, len)?;
+          ctx[i].done = 1;
+          let (mut ctx1, mut ctx2) = (ctx.clone(), ctx);
+          ctx1.push(Entry { form: a, done: 0 });
+          let q1 = self.refute(ctx1)?;
+          ctx2.push(Entry { form: b, done: 0 });
+          let q2 = self.refute(ctx2)?;
+          let p = self.thm(self.n.eord, [self.thm(self.n.exp, [q1])?, self.thm(self.n.exp, [q2])?])?;
+          return self.thm(self.n.mpd, [h, p])
+        }
+        Form::Imp(a, b) => return self.beta(ctx, i, a, Beta::Imp(b)),
+        Form::Iff(a, b) => return self.beta(ctx, i, a, Beta::Iff(b)),
+        Form::Not(c) => match self.form(c) {
+          Form::And(a, b) => return self.beta(ctx, i, a, Beta::NotAnd(b)),
+          Form::Iff(a, b) => return self.beta(ctx, i, a, Beta::NotIff(b)),
+          _ => {}
+        },
+        _ => {}
+      }
+    }
+    for i in 0..len {
+      if let Some((a, p)) = self.alpha(&mut ctx, i, true)? { return self.add(ctx, a, p) }
+    }
+    Err("no proof found".into())
+  }
+}
+
+impl Elaborator {
+  /// Implementation of the `(tauto cfg e)` builtin. Returns a proof pre-expression for
+  /// the formula `e`.
+  pub fn tauto(&mut self, cfg: &LispVal, e: &LispVal) -> SResult<LispVal> {
+    let map = cfg.unwrapped(|c| match c {
+      LispKind::AtomMap(map) => Ok(map.clone()),
+      _ => Err(format!("expected an atom map, got {}", self.print(c)))
+    })?;
+    let names = Names::new(self, &map)?;
+    let max_steps = match map.get(&self.get_atom(b"max-steps")).filter(|v| v.is_def()) {
+      Some(n) => n.as_int(BigInt::to_usize).flatten().ok_or("expected a number for 'max-steps'")?,
+      None => MAX_STEPS,
+    };
+    let mut tauto = Tauto {
+      elab: self, n: names, nodes: vec![], fvs: vec![], map: HashMap::new(),
+      steps: 0, max_steps, deadline: self.cur_timeout,
+    };
+    let goal = tauto.intern(e)?;
+    let ngoal = tauto.not(goal)?;
+    let q = tauto.refute(vec![Entry { form: ngoal, done: 0 }])?;
+    let n = &tauto.n;
+    tauto.thm(n.cases, [tauto.thm(n.id, [])?, tauto.thm(n.syl, [tauto.thm(n.efal, [])?, q])?])
+  }
+}
@@ -12,6 +12,7 @@
 //!     compile    Compile MM1 files into MMB
 //!     help       Prints this message or the help of the given subcommand(s)
 //!     join       Join MM1/MM0 files with imports by concatenation
+//!     mmc        Compile MMC programs into an executable and an MMB proof
 //!     server     MM1 LSP server
 //! ```
 //!
@@ -66,6 +67,8 @@ pub use mm0_deepsize::deep_size_0;
 
 #[cfg(feature = "server")]
 #[macro_use] pub mod server;
+#[cfg(feature = "server")]
+pub mod debugger;
 pub mod compiler;
 pub mod joiner;
 pub mod elab;
@@ -99,12 +102,14 @@ pub use mm0b_parser::MAX_BOUND_VARS;
 
 static CHECK_PROOFS: AtomicBool = AtomicBool::new(true);
 static CHECK_PARENS: AtomicBool = AtomicBool::new(false);
+static PROFILE: AtomicBool = AtomicBool::new(false);
 
 pub(crate) fn get_options() -> ElabOptions {
   ElabOptions {
     check_proofs: CHECK_PROOFS.load(Ordering::Relaxed),
     check_parens: CHECK_PARENS.load(Ordering::Relaxed),
     unused_vars: true,
+    profile: PROFILE.load(Ordering::Relaxed),
   }
 }
 
@@ -115,3 +120,7 @@ pub fn set_check_proofs(b: bool) { CHECK_PROOFS.store(b, Ordering::Relaxed) }
 /// Set the initial parenthesis warn behavior at the start of an MM1 file
 /// before a `(warn-unnecessary-parens)` command is found.
 pub fn set_check_parens(b: bool) { CHECK_PARENS.store(b, Ordering::Relaxed) }
+
+/// Enable the profiler, which records the time spent in each declaration and lisp
+/// procedure. The results can be retrieved using [`take_profile`](elab::profile::take_profile).
+pub fn set_profile(b: bool) { PROFILE.store(b, Ordering::Relaxed) }
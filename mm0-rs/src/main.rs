@@ -9,8 +9,12 @@ enum Cli {
   Compile(mm0_rs::compiler::Args),
   Join(mm0_rs::joiner::Args),
   Doc(mm0_rs::doc::Args),
+  #[cfg(feature = "mmc")]
+  Mmc(mm0_rs::mmc::Args),
   #[cfg(feature = "server")]
   Server(mm0_rs::server::Args),
+  #[cfg(feature = "server")]
+  Debug(mm0_rs::debugger::Args),
 }
 
 fn main() -> std::io::Result<()> {
@@ -18,10 +22,13 @@ fn main() -> std::io::Result<()> {
     Cli::Compile(args) => {
       if args.no_proofs { mm0_rs::set_check_proofs(false) }
       if args.check_parens { mm0_rs::set_check_parens(true) }
+      if args.profile.is_some() { mm0_rs::set_profile(true) }
       args.main()
     }
     Cli::Join(args) => args.main(),
     Cli::Doc(args) => args.main(),
+    #[cfg(feature = "mmc")]
+    Cli::Mmc(args) => args.main(),
     #[cfg(feature = "server")]
     Cli::Server(args) => {
       if args.no_proofs { mm0_rs::set_check_proofs(false) }
@@ -29,5 +36,11 @@ fn main() -> std::io::Result<()> {
       args.main();
       Ok(())
     }
+    #[cfg(feature = "server")]
+    Cli::Debug(args) => {
+      if args.no_proofs { mm0_rs::set_check_proofs(false) }
+      if args.check_parens { mm0_rs::set_check_parens(true) }
+      args.main()
+    }
   }
 }
@@ -8,7 +8,7 @@ use zerocopy::{AsBytes, U32, U64};
 use crate::{
   Type, SortId, AtomId, AtomVec, TermKind, ThmKind,
   TermVec, ExprNode, ProofNode, StmtTrace, DeclKey, Modifiers,
-  FrozenEnv, FileRef, LinedString, ErrorLevel};
+  FrozenEnv, FileRef, LinedString, ErrorLevel, OutputString};
 
 #[allow(clippy::wildcard_imports)]
 use mm0b_parser::{ProofCmd, UnifyCmd, cmd::*, write_cmd_bytes};
@@ -653,6 +653,16 @@ impl<'a, W: Write + Seek> Exporter<'a, W> {
             }
           }
         }
+        StmtTrace::InputString(ref is) => {
+          let OutputString {ref heap, ref store, exprs, ..} = **is;
+          let mut reorder = Reorder::new(0, heap.len(), |i| i);
+          for e in &store[store.len() - exprs..] {
+            write_expr_proof(self.env, vec, heap, store, &mut reorder, &mut None, e, false)?;
+          }
+          vec.write_u8(0)?;
+          write_cmd_bytes(self, STMT_INPUT_STRING, vec)?;
+          vec.clear();
+        }
         StmtTrace::Global(_) |
         StmtTrace::OutputString(_) => {}
       }
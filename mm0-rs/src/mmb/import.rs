@@ -3,7 +3,7 @@
 
 use std::rc::Rc;
 use crate::{Environment, Modifiers, AtomId, TermId,
-    Type, Term, Thm, TermKind, ThmKind, ExprNode, Expr, Proof};
+    Type, Term, Thm, TermKind, ThmKind, ExprNode, Expr, Proof, StmtTrace, OutputString};
 use crate::elab::proof::{IDedup, ProofKind, ProofHash, build};
 use crate::{FileRef, FileSpan, SliceExt};
 use mm0b_parser::{NumdStmtCmd, UnifyCmd, ProofCmd, BasicMmbFile,
@@ -369,6 +369,38 @@ fn parse_proof(
   Ok(Proof {heap, hyps, store: store.into()})
 }
 
+/// Parse a proof stream containing only expression constructors, as in an `input string`
+/// statement. Returns the heap and store, and the number of expressions that were left on the
+/// stack, which are stored at the end of the store.
+fn parse_exprs(
+  file: &BasicMmbFile<'_>, it: &mut ProofIter<'_>,
+) -> Result<(Box<[ExprNode]>, Vec<ExprNode>, usize)> {
+  use ParseError::StrError;
+  let (mut heap, mut store, mut stack) = (vec![], vec![], vec![]);
+  let mut pos = it.pos;
+  while let Some(e) = it.next() {
+    match e? {
+      ProofCmd::Term {tid, save} => {
+        let nargs = file.term(tid).ok_or(StrError("unknown term", pos))?.args().len();
+        let args = stack.len().checked_sub(nargs).ok_or(StrError("stack underflow", pos))?;
+        let r = ExprNode::App(tid, store.len());
+        store.extend(stack.drain(args..));
+        stack.push(if save { heap.push(r); ExprNode::Ref(heap.len() - 1) } else { r })
+      }
+      ProofCmd::Ref(i) => {
+        let i = usize::try_from(i).expect("impossible");
+        if i >= heap.len() { return Err(StrError("reference out of range", pos)) }
+        stack.push(ExprNode::Ref(i))
+      }
+      _ => return Err(StrError("expected an expression", pos)),
+    }
+    pos = it.pos;
+  }
+  let exprs = stack.len();
+  store.extend(stack);
+  Ok((heap.into(), store, exprs))
+}
+
 fn parse(fref: &FileRef, buf: &[u8], env: &mut Environment) -> Result<()> {
   use ParseError::StrError;
   let file = BasicMmbFile::parse(buf)?;
@@ -461,6 +493,12 @@ fn parse(fref: &FileRef, buf: &[u8], env: &mut Environment) -> Result<()> {
           vis, heap, store: store.into(), hyps: hyps.into(), ret,
         }).map_err(|_| StrError("double add term", start))?;
       }
+      NumdStmtCmd::InputString => {
+        let (heap, store, exprs) = parse_exprs(&file, &mut pf)?;
+        let span = FileSpan {file: fref.clone(), span: (start..pf.pos).into()};
+        env.stmts.push(StmtTrace::InputString(
+          Box::new(OutputString {span, heap, store: store.into(), exprs})));
+      }
     }
     start = it.pos;
   }
@@ -0,0 +1,135 @@
+//! The `mm0-rs mmc` command, which compiles a program in the C-like MMC surface syntax
+//! (see [`syntax`](super::syntax)) to an executable together with an MMB proof file.
+
+use std::{fs, io, path::Path, sync::Arc};
+use mm1_parser::ast::Ast;
+use crate::{Elaborator, ElabError, elab::Result};
+use crate::compiler::{elab_for_result, exit_on_error, export_mmb, load_file, report_errors, set_quiet};
+use super::{Compiler, syntax};
+
+/// Compile MMC programs into an executable and an MMB proof
+#[derive(clap::Args, Debug)]
+pub struct Args {
+  /// Hide diagnostic messages
+  #[clap(short, long)]
+  pub quiet: bool,
+  /// Don't add debugging data to the .mmb file
+  #[clap(short, long)]
+  pub strip: bool,
+  /// Add a symbol table and line number information to the executable
+  #[clap(short, long)]
+  pub debug: bool,
+  /// The target architecture. Proofs are only generated for x86-64
+  #[clap(long, arg_enum, default_value_t = Target::X86_64)]
+  pub target: Target,
+  /// The name of the program definition in the proof (default: the output file name)
+  #[clap(long)]
+  pub name: Option<String>,
+  /// Sets the output executable (default: the input file without its extension).
+  /// The proof is written next to it, with extension .mmb
+  #[clap(short, long, value_name = "FILE")]
+  pub output: Option<String>,
+  /// Sets the input file (.mmc)
+  pub input: String,
+}
+
+/// The architecture to compile for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ArgEnum)]
+pub enum Target {
+  /// 64-bit x86 Linux.
+  X86_64,
+  /// 64-bit ARM Linux. No proof is produced for this target.
+  Arm64,
+}
+
+/// Turn a file name into an MM0 identifier, for the default `--name`.
+fn ident_from(s: &str) -> String {
+  let mut out: String = s.chars()
+    .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' }).collect();
+  if !out.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') { out.insert(0, '_') }
+  out
+}
+
+/// Parse and typecheck the program, and generate the ELF file and its correctness proof.
+/// If the proof cannot be generated this is an error, and nothing is written.
+fn build(elab: &mut Elaborator, start: usize, name: &str, args: &Args) -> Result<Vec<u8>> {
+  let mut compiler = Compiler::new(elab);
+  let sp = compiler.add_source(elab, start)?;
+  if args.target == Target::Arm64 { return compiler.to_arm64_str(sp) }
+  let debug = args.debug;
+  let elf = if debug { compiler.to_debug_str(elab, sp)? } else { compiler.to_str(sp)? };
+  let atom = elab.get_atom(name.as_bytes());
+  compiler.finish(elab, sp, atom)?;
+  let valid = elab.get_atom(format!("{name}_valid").as_bytes());
+  if elab.data[valid].decl.is_none() {
+    elab.report(ElabError::warn(sp, format!("the correctness theorem {name}_valid \
+      is not produced yet, so the .mmb file only contains the program `{name}` \
+      and the lemmas about its procedures")))
+  }
+  Ok(elf)
+}
+
+impl Args {
+  /// Main entry point for `mm0-rs mmc` subcommand.
+  ///
+  /// # Arguments
+  ///
+  /// `mm0-rs mmc <prog.mmc> [-o prog]`, where:
+  ///
+  /// - `prog.mmc` is the program to compile. It starts with a list of `import "file.mm1";`
+  ///   lines, which are elaborated to provide the MMC compiler theory and any definitions
+  ///   used in the program's specifications.
+  /// - `prog` is the executable to generate. The proof that it meets its specification is
+  ///   written to `prog.mmb`, unless `--target arm64` is given. If the proof cannot be
+  ///   generated, the command fails without writing either file.
+  pub fn main(self) -> io::Result<()> {
+    set_quiet(self.quiet);
+    if self.debug && self.target == Target::Arm64 {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "--debug is not supported for arm64"))
+    }
+    let (path, text) = load_file(fs::canonicalize(&self.input)?.into())?;
+    let Some(source) = text.try_ascii().cloned() else {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, "input is not an ASCII text file"))
+    };
+    let (imports, start) = match syntax::parse_imports(source.as_bytes()) {
+      Ok(r) => r,
+      Err(e) => { report_errors(&path, &text, &[e]); std::process::exit(1) }
+    };
+    let mut envs = vec![];
+    for (sp, file) in imports {
+      let file = String::from_utf8_lossy(&file);
+      let p = path.path().parent().map_or_else(|| Path::new(&*file).to_owned(), |p| p.join(&*file));
+      let env = match fs::canonicalize(&p) {
+        Ok(p) => elab_for_result(p.into())?.1,
+        Err(e) => { report_errors(&path, &text, &[ElabError::new_e(sp, e)]); None }
+      };
+      envs.push((sp, env.unwrap_or_else(|| std::process::exit(1))));
+    }
+    let output = self.output.clone().unwrap_or_else(|| {
+      let input = Path::new(&self.input);
+      input.with_extension("").to_string_lossy().into_owned()
+    });
+    let name = self.name.clone().unwrap_or_else(|| ident_from(
+      &Path::new(&output).file_stem().map_or_else(|| "main".into(), |s| s.to_string_lossy())));
+    let ast = Arc::new(Ast { source: source.clone(), ..Ast::default() });
+    let (elf, mut errors, env) = Elaborator::with_elab(ast, path.clone(), |elab| {
+      for (sp, env) in &envs { elab.import_env(*sp, env) }
+      build(elab, start, &name, &self)
+    });
+    let elf = elf.map_err(|e| errors.push(e)).ok();
+    if !errors.is_empty() { report_errors(&path, &text, &errors) }
+    if let Some(elf) = elf {
+      fs::write(&output, elf)?;
+      #[cfg(unix)] {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&output, fs::Permissions::from_mode(0o755))?;
+      }
+      if self.target == Target::X86_64 {
+        let w = io::BufWriter::new(fs::File::create(format!("{output}.mmb"))?);
+        export_mmb(path, Some(&source), &env, !self.strip, w)?;
+      }
+    }
+    exit_on_error(false);
+    Ok(())
+  }
+}
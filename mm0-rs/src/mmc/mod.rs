@@ -7,16 +7,21 @@
 //!
 //! [`mmc.md`]: https://github.com/digama0/mm0/blob/master/mm0-rs/mmc.md
 
+mod driver;
 mod parser;
 mod proof;
+mod syntax;
 
-use std::{collections::HashMap, rc::Rc};
-use mmcc::{infer::TypeError, types::{IdxVec, LambdaId, hir, ty::CtxPrint}, LinkedCode, LinkerErr};
+use std::{cell::RefCell, collections::HashMap, hash::{Hash, Hasher}, rc::Rc};
+use mmcc::{infer::TypeError, types::{IdxVec, LambdaId, Spanned, VarId, ast, hir, ty::CtxPrint},
+  LinkedCode, LinkerErr, Symbol};
+use mmcc::interp::{Outcome, Value};
 use parser::{ItemIter, Parser, Keyword};
 use crate::{FileSpan, Span, AtomId, Remap, Remapper, Elaborator, ElabError,
-  elab::Result, LispVal, EnvDebug, FormatEnv, lisp::ProcSpec, LispProc, EnvDisplay};
+  elab::Result, LispKind, LispVal, Uncons, EnvDebug, FormatEnv, lisp::ProcSpec, LispProc, EnvDisplay};
 
 use self::parser::Mm0ExprNode;
+pub use driver::Args;
 
 struct PrintLambda<'a> {
   fe: FormatEnv<'a>,
@@ -56,6 +61,8 @@ impl mmcc::PrintLambda for PrintLambda<'_> {
 struct Config;
 struct ItemContext<'a> {
   elab: &'a Elaborator,
+  /// The source text of the item, if it is in the current file.
+  src: &'a [u8],
   lambdas: &'a IdxVec<LambdaId, Mm0ExprNode>,
   errors: &'a mut Vec<ElabError>,
 }
@@ -76,15 +83,21 @@ impl<'a> mmcc::ItemContext<Config> for ItemContext<'a> {
     PrintLambda { fe: self.elab.format_env(), lambdas: self.lambdas }
   }
 
+  fn hash_item(&self, h: &mut impl Hasher) {
+    self.src.hash(h);
+    format!("{:?}", self.lambdas).hash(h);
+  }
+
   fn emit_type_errors<'b>(&mut self, _: &mut Config,
     errs: Vec<hir::Spanned<'b, TypeError<'b>>>,
     pr: &impl mmcc::DisplayCtx<'b>,
   ) -> Result<()> {
-    self.errors.extend(errs.into_iter().map(|err| match err.k {
-      TypeError::ExpectedPure(sp) =>
-        ElabError::with_info(err.span, format!("{}", CtxPrint(pr, &err.k)).into(),
-          vec![(sp.clone(), "Needed for this operation".into())]),
-      _ => ElabError::new_e(err.span, format!("{}", CtxPrint(pr, &err.k))),
+    self.errors.extend(errs.into_iter().map(|err| {
+      let msg = format!("{}", CtxPrint(pr, &err.k));
+      let related = err.k.related();
+      if related.is_empty() { return ElabError::new_e(err.span, msg) }
+      ElabError::with_info(err.span, msg.into(), related.into_iter()
+        .map(|(sp, r)| (sp, format!("{}", CtxPrint(pr, &r)).into())).collect())
     }));
     Ok(())
   }
@@ -95,6 +108,9 @@ impl<'a> mmcc::ItemContext<Config> for ItemContext<'a> {
 struct CompilerInner {
   inner: mmcc::Compiler<Config>,
   code: Option<Box<mmcc::LinkedCode>>,
+  /// The last generated proof, shared between copies of the compiler like the compiler's own
+  /// cache of compiled procedures.
+  proofs: Rc<RefCell<proof::ProofCache>>,
 }
 
 impl CompilerInner {
@@ -108,18 +124,22 @@ impl CompilerInner {
     if self.inner.has_type_errors() {
       return Err(ElabError::new_e(sp, "Compilation failed due to previous errors"))
     }
-    let code = self.inner.finish().map_err(|err| match err {
-      LinkerErr::LowerErr(mmcc::LowerErr::GhostVarUsed(v)) =>
-        ElabError::new_e(&v.span, "Ghost variable used in computationally relevant position"),
-      LinkerErr::LowerErr(mmcc::LowerErr::EntryUnreachable(sp)) =>
-        ElabError::new_e(&sp, "Function has an unconditional infinite loop"),
-      LinkerErr::LowerErr(mmcc::LowerErr::InfiniteOp(sp)) =>
-        ElabError::new_e(&sp, "Function has a computationally relevant infinite size operation"),
-    })?;
+    let code = self.inner.finish().map_err(linker_err)?;
     Ok(self.code.get_or_insert(code))
   }
 }
 
+fn linker_err(err: LinkerErr) -> ElabError {
+  match err {
+    LinkerErr::LowerErr(mmcc::LowerErr::GhostVarUsed(v)) =>
+      ElabError::new_e(&v.span, "Ghost variable used in computationally relevant position"),
+    LinkerErr::LowerErr(mmcc::LowerErr::EntryUnreachable(sp)) =>
+      ElabError::new_e(&sp, "Function has an unconditional infinite loop"),
+    LinkerErr::LowerErr(mmcc::LowerErr::InfiniteOp(sp)) =>
+      ElabError::new_e(&sp, "Function has a computationally relevant infinite size operation"),
+  }
+}
+
 /// The MMC compiler, which contains local state for the functions that have been
 /// loaded and typechecked thus far.
 #[derive(Clone)]
@@ -157,6 +177,18 @@ impl Remap for Compiler {
   }
 }
 
+/// Typecheck a parsed item and add it to the compiler state, reporting the type errors.
+fn add_item(
+  compiler: &mut mmcc::Compiler<Config>, elab: &mut Elaborator, item: &ast::Item,
+  var_names: IdxVec<VarId, Spanned<Symbol>>, lambdas: &IdxVec<LambdaId, Mm0ExprNode>,
+) -> Result<()> {
+  let mut errors = vec![];
+  let src = if item.span.file == elab.path { &elab.ast.source[item.span.span] } else { &[] };
+  compiler.add(item, var_names, ItemContext { elab, src, lambdas, errors: &mut errors })?;
+  for e in errors { elab.report(e) }
+  Ok(())
+}
+
 impl Compiler {
   /// Construct a new compiler object.
   pub fn new(elab: &mut Elaborator) -> Self {
@@ -188,18 +220,34 @@ impl Compiler {
           Ok(None) => break,
         };
         let (var_names, lambdas) = p.finish();
-        let mut errors = vec![];
-        compiler.inner.add(&item, var_names, ItemContext {
-          elab,
-          lambdas: &lambdas,
-          errors: &mut errors
-        })?;
-        for e in errors { elab.report(e) }
+        add_item(&mut compiler.inner, elab, &item, var_names, &lambdas)?
       }
     }
     Ok(())
   }
 
+  /// Add the items of an MMC source file in the surface syntax (see [`syntax`]), starting at
+  /// position `start` of the current file, to the compiler state. Each item is typechecked
+  /// before the next one is parsed. Returns the span of the name of `main`, or of the first
+  /// item if there is no `main`, for reporting errors about the whole program.
+  pub(crate) fn add_source(&mut self, elab: &mut Elaborator, start: usize) -> Result<Span> {
+    let compiler = Rc::make_mut(&mut self.inner);
+    compiler.code = None;
+    let source = elab.ast.source.clone();
+    let mut lex = syntax::Lexer::new(source.as_bytes(), start)?;
+    let (mut first, mut main) = (None, None);
+    while let Some((item, var_names, lambdas)) =
+      syntax::parse_item(elab, &mut compiler.inner, &mut lex)?
+    {
+      first.get_or_insert(item.span.span);
+      if let ast::ItemKind::Proc { name, .. } = &item.k {
+        if name.k.as_str() == "main" { main = Some(name.span.span) }
+      }
+      add_item(&mut compiler.inner, elab, &item, var_names, &lambdas)?
+    }
+    Ok(main.or(first).unwrap_or_else(|| start.into()))
+  }
+
   /// Get the compiled ELF file as a byte string.
   pub fn to_str(&mut self, sp: Span) -> Result<Vec<u8>> {
     let compiler = Rc::make_mut(&mut self.inner);
@@ -209,11 +257,93 @@ impl Compiler {
     Ok(out)
   }
 
+  /// Get the compiled ELF file as a byte string, with a symbol table and line number information
+  /// appended after the loaded segment. Only spans in the current file are given line numbers.
+  pub fn to_debug_str(&mut self, elab: &Elaborator, sp: Span) -> Result<Vec<u8>> {
+    let compiler = Rc::make_mut(&mut self.inner);
+    let code = compiler.linked_code(sp)?;
+    let mut out = Vec::new();
+    code.write_elf_debug(&mut out, |fsp| (fsp.file == elab.path)
+      .then(|| elab.ast.source.to_pos(fsp.span.start).line + 1)
+    ).expect("IO error in string write");
+    Ok(out)
+  }
+
+  /// Get the program compiled for 64-bit ARM Linux, as an ELF file. Proofs are not available for
+  /// this target, so this consumes the functions that have been added, like
+  /// [`finish`](Self::finish), without producing any definitions.
+  pub fn to_arm64_str(&mut self, sp: Span) -> Result<Vec<u8>> {
+    let compiler = Rc::make_mut(&mut self.inner);
+    if compiler.inner.has_type_errors() {
+      return Err(ElabError::new_e(sp, "Compilation failed due to previous errors"))
+    }
+    let code = compiler.inner.finish_arm64().map_err(linker_err)?;
+    let mut out = Vec::new();
+    code.write_elf(&mut out).expect("IO error in string write");
+    Ok(out)
+  }
+
+  /// Get an annotated assembly listing of the compiled program.
+  pub fn to_asm(&mut self, sp: Span) -> Result<Vec<u8>> {
+    let compiler = Rc::make_mut(&mut self.inner);
+    let code = compiler.linked_code(sp)?;
+    let mut out = Vec::new();
+    code.write_asm(&mut out).expect("IO error in string write");
+    Ok(out)
+  }
+
+  /// Run the function `f` in the MIR interpreter on the given arguments, with `input` as
+  /// the contents of standard input. Returns the list `(result output)`, where `result` is the
+  /// return value (or a list of them, if there is not exactly one), or `'exit` or `'fail`
+  /// if the program exited or failed an assertion, and `output` is the string written to standard
+  /// output. This has to be called before [`finish`](Self::finish), which consumes the functions.
+  pub fn eval(&self,
+    elab: &mut Elaborator, sp: Span, input: &[u8], f: AtomId, args: impl Iterator<Item=LispVal>
+  ) -> Result<LispVal> {
+    fn to_value(e: &LispVal) -> Option<Value> {
+      if e.is_list() {
+        return Uncons::new(e.clone()).map(|e| to_value(&e)).collect::<Option<_>>().map(Value::List)
+      }
+      e.unwrapped(|e| match e {
+        LispKind::Number(n) => Some(Value::Int(n.clone())),
+        &LispKind::Bool(b) => Some(Value::Bool(b)),
+        LispKind::Undef => Some(Value::Uninit),
+        _ => None,
+      })
+    }
+    fn to_lisp(v: Value) -> LispVal {
+      match v {
+        Value::Unit => LispVal::nil(),
+        Value::Uninit => LispVal::undef(),
+        Value::Bool(b) => LispVal::bool(b),
+        Value::Int(n) => LispVal::number(n),
+        Value::List(vs) => LispVal::list(vs.into_iter().map(to_lisp).collect::<Vec<_>>()),
+        Value::Ptr(p) => LispVal::number(p.addr().into()),
+      }
+    }
+    let args = args.map(|e| to_value(&e).ok_or_else(|| ElabError::new_e(sp,
+      format!("mmc-eval: cannot convert {} to a value", elab.print(&e)))))
+      .collect::<Result<_>>()?;
+    let f_sym = mmcc::intern(elab.data[f].name.as_str());
+    let (out, output) = self.inner.inner.eval(f_sym, args, input).map_err(|e| ElabError::new_e(sp,
+      format!("mmc-eval: {}: {e}", elab.data[f].name)))?;
+    let result = match out {
+      Outcome::Return(mut vals) if vals.len() == 1 => to_lisp(vals.pop().expect("nonempty")),
+      Outcome::Return(vals) => to_lisp(Value::List(vals)),
+      Outcome::Exit => LispVal::atom(elab.get_atom(b"exit")),
+      Outcome::Fail => LispVal::atom(elab.get_atom(b"fail")),
+    };
+    Ok(LispVal::list(vec![result, LispVal::string(output.into())]))
+  }
+
   /// Once we are done adding functions, this function performs final linking to produce an executable.
   pub fn finish(&mut self, elab: &mut Elaborator, sp: Span, name: AtomId) -> Result<()> {
     let compiler = Rc::make_mut(&mut self.inner);
+    let proofs = compiler.proofs.clone();
     let code = compiler.linked_code(sp)?;
-    proof::render_proof(&self.predef, elab, sp, name, &code.proof())
+    let res = proof::render_proof(&self.predef, elab, sp, name, &code.proof(),
+      &mut proofs.borrow_mut());
+    res
   }
 }
 
@@ -234,6 +364,22 @@ impl LispProc for Compiler {
         self.add(elab, sp, it)?;
         Ok(LispVal::string(self.to_str(sp)?.into()))
       }
+      Some(Keyword::DebugString) => {
+        self.add(elab, sp, it)?;
+        Ok(LispVal::string(self.to_debug_str(elab, sp)?.into()))
+      }
+      Some(Keyword::Asm) => {
+        self.add(elab, sp, it)?;
+        Ok(LispVal::string(self.to_asm(sp)?.into()))
+      }
+      Some(Keyword::Eval) => {
+        let input = it.next().and_then(|e| e.unwrapped(|e|
+          if let LispKind::String(s) = e { Some(s.clone()) } else { None }));
+        let (Some(input), Some(f)) = (input, it.next().and_then(|e| e.as_atom())) else {
+          return Err(ElabError::new_e(sp, "mmc-eval: syntax error"))
+        };
+        self.eval(elab, sp, &input, f, it)
+      }
       Some(Keyword::Finish) => {
         let name = it.next().and_then(|e| e.as_atom()).ok_or_else(||
           ElabError::new_e(sp, "mmc-finish: syntax error"))?;
@@ -81,22 +81,28 @@ make_keywords! {
   Arrow: "=>",
   ArrowL: "<-",
   ArrowR: "->",
+  Asm: "->asm",
   Begin: "begin",
   Colon: ":",
   ColonEq: ":=",
   Const: "const",
+  DebugString: "->debug-string",
   Else: "else",
   Entail: "entail",
+  Eval: "eval",
   Func: "func",
   Finish: "finish",
+  Fold: "fold",
   Ghost: "ghost",
   Global: "global",
   Implicit: "implicit",
   Intrinsic: "intrinsic",
   If: "if",
+  Inline: "inline",
   Le: "<=",
   Lt: "<",
   Main: "main",
+  Map: "map",
   Match: "match",
   Mut: "mut",
   Or: "or",
@@ -200,6 +206,115 @@ impl From<PArgAttr> for ArgAttr {
   }
 }
 
+/// A `mut` function argument, which may be paired with an `out` return.
+struct OutVal {
+  input: u32,
+  name: Spanned<Symbol>,
+  used: bool,
+}
+
+/// The signature of a function or procedure during parsing, which matches the `mut` arguments
+/// with the `out` returns.
+#[derive(Default)]
+pub(crate) struct ProcSig {
+  outmap: Vec<OutVal>,
+  args: Vec<Arg>,
+  rets: Vec<(FileSpan, PArgAttr, ArgKind)>,
+}
+
+impl ProcSig {
+  /// Add a function argument.
+  pub(crate) fn push_arg(&mut self, span: FileSpan, attr: PArgAttr, pat: ArgKind) -> Result<()> {
+    if attr.out.is_some() {
+      return Err(ElabError::new_e(&span, "'out' not permitted on function arguments"))
+    }
+    if attr.mut_ {
+      if let Some((_, name, _)) = pat.var().as_single_name() {
+        if self.outmap.iter().any(|p| p.name.k == name) {
+          return Err(ElabError::new_e(&span, "'mut' variables cannot shadow"))
+        }
+        self.outmap.push(OutVal {
+          name: Spanned { span: span.clone(), k: name },
+          used: false,
+          input: self.args.len().try_into().expect("too many arguments"),
+        });
+      } else { return Err(ElabError::new_e(&span, "cannot use tuple pattern with 'mut'")) }
+    }
+    self.args.push(Spanned {span, k: (attr.into(), pat)});
+    Ok(())
+  }
+
+  /// Add a function return. The returns should be added in a new scope, after the arguments.
+  pub(crate) fn push_ret(&mut self, span: FileSpan, mut attr: PArgAttr, pat: ArgKind) -> Result<()> {
+    if let Some(name) = &mut attr.out {
+      if *name == Symbol::UNDER {
+        if let Some(v) = pat.var().as_single_name() {*name = v.1}
+      }
+      if let Some(OutVal {used, ..}) = self.outmap.iter_mut().find(|p| p.name.k == *name) {
+        if std::mem::replace(used, true) {
+          return Err(ElabError::new_e(&span, "two 'out' arguments to one 'mut'"))
+        }
+      } else {
+        return Err(ElabError::new_e(&span,
+          "'out' does not reference a 'mut' in the function arguments"))
+      }
+    }
+    self.rets.push((span, attr, pat));
+    Ok(())
+  }
+
+  /// Finish the signature, returning the arguments, the `out` returns and the regular returns.
+  /// A `mut` argument without a matching `out` gets an implicit `out` return with the same name.
+  #[allow(clippy::type_complexity)]
+  pub(crate) fn finish(self, ba: &mut BuildAst
+  ) -> Result<(Box<[Arg]>, Box<[OutArg]>, Box<[TuplePattern]>)> {
+    let ProcSig {outmap, args, rets: rets1} = self;
+    let mut outs = outmap.iter().filter(|val| !val.used).map(|&OutVal { input, ref name, .. }| {
+      OutArg { input, var: ba.push_fresh(name.clone()), name: name.clone(), ty: None }
+    }).collect::<Vec<_>>();
+    let mut rets = vec![];
+    for (span, attr, pat) in rets1 {
+      if attr.mut_ {
+        return Err(ElabError::new_e(&span, "'mut' not permitted on function returns"))
+      }
+      match pat {
+        ArgKind::Let(..) =>
+          return Err(ElabError::new_e(&span, "assignment not permitted here")),
+        ArgKind::Lam(mut pat) => if let Some(name) = attr.out {
+          if !rets.is_empty() {
+            return Err(ElabError::new_e(&span,
+              "out parameters must precede regular function returns"))
+          }
+          let mut ty = None;
+          let mut sp = span.clone();
+          outs.push(loop {
+            match pat {
+              TuplePatternKind::Name(_, name2, var) => {
+                let &OutVal {input, name: Spanned {ref span, ..}, ..} =
+                  outmap.iter().find(|p| p.name.k == name).expect("checked");
+                break OutArg { input, name: Spanned { span: span.clone(), k: name2 }, var, ty }
+              }
+              TuplePatternKind::Typed(pat2, ty2) => {
+                if ty.replace(ty2).is_some() {
+                  return Err(ElabError::new_e(&span,
+                    "double type ascription not permitted here"))
+                }
+                sp = pat2.span.clone();
+                pat = pat2.k;
+              }
+              TuplePatternKind::Tuple(_) => return Err(ElabError::new_e(&sp,
+                "tuple pattern not permitted in 'out' returns"))
+            }
+          })
+        } else {
+          rets.push(Spanned {span, k: pat})
+        }
+      }
+    }
+    Ok((args.into(), outs.into(), rets.into()))
+  }
+}
+
 /// A parsed label expression `((begin (lab x y)) body)`.
 #[derive(Debug)]
 #[cfg_attr(feature = "memory", derive(DeepSizeOf))]
@@ -220,6 +335,7 @@ enum ItemGroup {
   Global(Uncons),
   Const(Uncons),
   Intrinsic(Uncons),
+  Inline(Uncons),
 }
 
 #[derive(Debug)]
@@ -229,6 +345,7 @@ enum ItemIterInner {
   Global(Uncons),
   Const(Uncons),
   Intrinsic(Box<ItemIter>),
+  Inline(Box<ItemIter>),
 }
 
 /// An iterator over items. This is not a proper iterator in the sense of implementing `Iterator`,
@@ -238,13 +355,14 @@ enum ItemIterInner {
 pub(crate) struct ItemIter {
   group: ItemIterInner,
   u: Uncons,
-  intrinsic: bool
+  intrinsic: bool,
+  inline: bool,
 }
 
 impl ItemIter {
   /// Construct a new iterator from an `I: Iterator<Item=LispVal>`.
   #[must_use] pub(crate) fn new(e: LispVal) -> Self {
-    Self { group: ItemIterInner::New, u: Uncons::new(e), intrinsic: false }
+    Self { group: ItemIterInner::New, u: Uncons::new(e), intrinsic: false, inline: false }
   }
 }
 
@@ -271,7 +389,7 @@ pub(crate) struct Parser<'a, C> {
   Spanned {span: try_get_fspan(fsp, e), k}
 }
 
-fn get_intrinsic<T>(sp: &FileSpan, name: Symbol, f: impl FnOnce(Symbol) -> Option<T>) -> Result<T> {
+pub(crate) fn get_intrinsic<T>(sp: &FileSpan, name: Symbol, f: impl FnOnce(Symbol) -> Option<T>) -> Result<T> {
   f(name).ok_or_else(|| ElabError::new_e(sp, "unknown intrinsic"))
 }
 
@@ -338,13 +456,6 @@ impl<'a, C> Parser<'a, C> {
     Some((self.as_keyword(&u.next()?)?, u))
   }
 
-  fn with_ctx<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
-    let sc = self.ba.scope();
-    let res = f(self);
-    sc.close(&mut self.ba);
-    res
-  }
-
   /// Same as `as_symbol`, but does not require mutable access (and does not update the cache).
   fn as_symbol_ref(&self, a: AtomId) -> Symbol {
     self.symbols.get(&a).map_or_else(|| intern(self.fe.env.data[a].name.as_str()), |a| *a)
@@ -791,6 +902,57 @@ impl<'a, C> Parser<'a, C> {
           ))})?;
           mk.finish(&mut self.ba, var, cond, hyp, body)
         }
+        Some((kw @ (Keyword::Map | Keyword::Fold), u)) => {
+          let name = if kw == Keyword::Map {"map"} else {"fold"};
+          let err = |msg: &str| ElabError::new_e(&span, format!("{name}: {msg}"));
+          let mut args = u.collect::<Vec<_>>();
+          let body = args.pop().ok_or_else(|| err("expected a body"))?;
+          let mut args = args.into_iter().peekable();
+          // An optional leading index binder `i` or `{h : i}`
+          let mut idx = None;
+          let mut hyp = None;
+          if let Some(e) = args.peek() {
+            if let Some(a) = e.as_atom() {
+              idx = Some(spanned(&span, e, self.as_symbol(a)));
+            } else if let Some((Keyword::Colon, mut u)) = self.head_keyword(e) {
+              let (Some(h), Some(i), true) = (u.next(), u.next(), u.is_empty()) else {
+                return Err(err("expected {h : i}"))
+              };
+              let (Some(ha), Some(ia)) = (h.as_atom(), i.as_atom()) else {
+                return Err(err("expected {h : i}"))
+              };
+              hyp = Some(spanned(&span, &h, self.as_symbol(ha)));
+              idx = Some(spanned(&span, &i, self.as_symbol(ia)));
+            }
+            if idx.is_some() { args.next(); }
+          }
+          // The bindings `{x := a}`, where the first one is the accumulator for `fold`
+          let mut binds = vec![];
+          for e in args {
+            let e_span = try_get_fspan(&span, &e);
+            let bad = || ElabError::new_e(&e_span, format!("{name}: expected {{x := a}}"));
+            let Some((Keyword::ColonEq, mut u)) = self.head_keyword(&e) else { return Err(bad()) };
+            let (Some(x), Some(a), true) = (u.next(), u.next(), u.is_empty()) else {
+              return Err(bad())
+            };
+            let xa = x.as_atom().ok_or_else(bad)?;
+            let x = spanned(&span, &x, self.as_symbol(xa));
+            binds.push((x, self.parse_expr(&span, a)?));
+          }
+          let mut binds = binds.into_iter();
+          let acc = if kw == Keyword::Fold {
+            Some(binds.next().ok_or_else(|| err("expected an accumulator {acc := init}"))?)
+          } else { None };
+          if binds.len() == 0 { return Err(err("expected at least one array {x := a}")) }
+          self.with_ctx(|this| -> Result<_> {
+            let hyp = hyp.map(|h| this.ba.push_fresh_span(h));
+            let idx = idx.map(|i| this.ba.push_fresh_span(i));
+            let acc = acc.map(|(x, init)| (this.ba.push_fresh_span(x), Box::new(init)));
+            let arrays = binds.map(|(x, a)| (this.ba.push_fresh_span(x), a)).collect();
+            let body = Box::new(this.parse_expr(&span, body)?);
+            Ok(ExprKind::MapFold(Box::new(ast::MapFold {idx, hyp, acc, arrays, body})))
+          })?
+        }
         Some((Keyword::Begin, u)) => ExprKind::Block(self.parse_block(&span, u)?),
         Some((Keyword::Entail, u)) => {
           let mut args = u.collect::<Vec<_>>();
@@ -913,19 +1075,11 @@ impl<'a, C> Parser<'a, C> {
     kind: &dyn Fn(Symbol) -> Result<ProcKind>,
     mut u: Uncons,
     intrinsic: bool,
+    inline: bool,
   ) -> Result<Item> {
-    struct OutVal {
-      input: u32,
-      name: Spanned<Symbol>,
-      used: bool,
-    }
     let Some(e) = u.next() else {
       return Err(ElabError::new_e(try_get_span(&span, &LispVal::from(u)), "func/proc: syntax error"))
     };
-    let mut outmap: Vec<OutVal> = vec![];
-    let mut args = vec![];
-    let mut outs = vec![];
-    let mut rets = vec![];
     let (name, header) = match &*e.unwrapped_arc() {
       &LispKind::Atom(a) => (spanned(&span, &e, self.as_symbol(a)), None),
       LispKind::List(_) | LispKind::DottedList(_, _) => {
@@ -942,7 +1096,8 @@ impl<'a, C> Parser<'a, C> {
       Some(get_intrinsic(&name.span, name.k, IntrinsicProc::from_symbol)?)
     } else { None };
     self.compiler.forward_declare_proc(&name.span, name.k)?;
-    if let Some(u) = header {
+    let mut sig = ProcSig::default();
+    let (args, outs, rets) = if let Some(u) = header {
       let mut u = u.peekable();
       while let Some((e, a)) =
         u.peek().and_then(|e| e.as_atom().filter(|&a| a != AtomId::COLON).map(|a| (e, a)))
@@ -953,95 +1108,18 @@ impl<'a, C> Parser<'a, C> {
       }
       for e in &mut u {
         if e.as_atom() == Some(AtomId::COLON) { break }
-        self.push_args_core(&span, Default::default(), e, &mut |span, attr, pat| {
-          if attr.out.is_some() {
-            return Err(ElabError::new_e(&span, "'out' not permitted on function arguments"))
-          }
-          if attr.mut_ {
-            if let Some((_, name, _)) = pat.var().as_single_name() {
-              if outmap.iter().any(|p| p.name.k == name) {
-                return Err(ElabError::new_e(&span, "'mut' variables cannot shadow"))
-              }
-              outmap.push(OutVal {
-                name: Spanned { span: span.clone(), k: name },
-                used: false,
-                input: args.len().try_into().expect("too many arguments"),
-              });
-            } else { return Err(ElabError::new_e(&span, "cannot use tuple pattern with 'mut'")) }
-          }
-          args.push(Spanned {span, k: (attr.into(), pat)});
-          Ok(())
-        })?
+        self.push_args_core(&span, Default::default(), e, &mut |span, attr, pat|
+          sig.push_arg(span, attr, pat))?
       }
       self.with_ctx(|this| {
-        let mut rets1 = vec![];
         for e in u {
-          this.push_args_core(&span, Default::default(), e, &mut |span, mut attr, pat| {
-            if let Some(name) = &mut attr.out {
-              if *name == Symbol::UNDER {
-                if let Some(v) = pat.var().as_single_name() {*name = v.1}
-              }
-              if let Some(OutVal {used, ..}) = outmap.iter_mut().find(|p| p.name.k == *name) {
-                if std::mem::replace(used, true) {
-                  return Err(ElabError::new_e(&span, "two 'out' arguments to one 'mut'"))
-                }
-              } else {
-                return Err(ElabError::new_e(&span,
-                  "'out' does not reference a 'mut' in the function arguments"))
-              }
-            }
-            rets1.push((span, attr, pat));
-            Ok(())
-          })?
-        }
-        outs.extend(outmap.iter().filter(|val| !val.used).map(|&OutVal { input, ref name, .. }| {
-          OutArg { input, var: this.ba.push_fresh(name.clone()), name: name.clone(), ty: None }
-        }));
-        for (span, attr, pat) in rets1 {
-          if attr.mut_ {
-            return Err(ElabError::new_e(&span, "'mut' not permitted on function returns"))
-          }
-          match pat {
-            ArgKind::Let(..) =>
-              return Err(ElabError::new_e(&span, "assignment not permitted here")),
-            ArgKind::Lam(mut pat) => if let Some(name) = attr.out {
-              if !rets.is_empty() {
-                return Err(ElabError::new_e(&span,
-                  "out parameters must precede regular function returns"))
-              }
-              let mut ty = None;
-              let mut sp = span.clone();
-              outs.push(loop {
-                match pat {
-                  TuplePatternKind::Name(_, name2, var) => {
-                    let &OutVal {input, name: Spanned {ref span, ..}, ..} =
-                      outmap.iter().find(|p| p.name.k == name).expect("checked");
-                    break OutArg { input, name: Spanned { span: span.clone(), k: name2 }, var, ty }
-                  }
-                  TuplePatternKind::Typed(pat2, ty2) => {
-                    if ty.replace(ty2).is_some() {
-                      return Err(ElabError::new_e(&span,
-                        "double type ascription not permitted here"))
-                    }
-                    sp = pat2.span.clone();
-                    pat = pat2.k;
-                  }
-                  TuplePatternKind::Tuple(_) => return Err(ElabError::new_e(&sp,
-                    "tuple pattern not permitted in 'out' returns"))
-                }
-              })
-            } else {
-              rets.push(Spanned {span, k: pat})
-            }
-          }
+          this.push_args_core(&span, Default::default(), e, &mut |span, attr, pat|
+            sig.push_ret(span, attr, pat))?
         }
-        Ok(())
-      })?;
-    }
+        sig.finish(&mut this.ba)
+      })?
+    } else { Default::default() };
     let tyargs = self.ba.num_tyvars();
-    let args = args.into();
-    let outs = outs.into();
-    let rets = rets.into();
     let variant = if let Some(e) = u.head() {
       if intrinsic.is_some() {
         return Err(ElabError::new_e(&span, "intrinsic: unexpected body"))
@@ -1050,7 +1128,7 @@ impl<'a, C> Parser<'a, C> {
     } else {None};
     let body = self.parse_block(&span, u)?;
     Ok(Spanned {span, k: ItemKind::Proc {
-      intrinsic, kind, name, tyargs, args, outs, rets, variant, body
+      intrinsic, inline, kind, name, tyargs, args, outs, rets, variant, body
     }})
   }
 
@@ -1078,19 +1156,22 @@ impl<'a, C> Parser<'a, C> {
 
   /// Parses the input lisp literal `e` into a list of top level items and appends them to `ast`.
   fn push_item_group(&mut self,
-    base: &FileSpan, e: &LispVal, intrinsic: bool,
+    base: &FileSpan, e: &LispVal, intrinsic: bool, inline: bool,
   ) -> Result<ItemGroup> {
     let span = try_get_fspan(base, e);
     Ok(match self.head_keyword(e) {
       Some((Keyword::Proc, u)) => {
         let f = |a| Ok(if a == Keyword::Main.as_symbol() {ProcKind::Main} else {ProcKind::Proc});
-        ItemGroup::Item(self.parse_proc(span, &f, u, intrinsic)?)
+        ItemGroup::Item(self.parse_proc(span, &f, u, intrinsic, inline)?)
       }
       Some((Keyword::Func, u)) => {
         let f = |_| Ok(ProcKind::Func);
-        ItemGroup::Item(self.parse_proc(span, &f, u, intrinsic)?)
+        ItemGroup::Item(self.parse_proc(span, &f, u, intrinsic, inline)?)
       }
+      _ if inline => return Err(ElabError::new_e(try_get_span(base, e),
+        "inline: expecting a func or proc declaration")),
       Some((Keyword::Intrinsic, u)) => ItemGroup::Intrinsic(u),
+      Some((Keyword::Inline, u)) => ItemGroup::Inline(u),
       Some((Keyword::Global, u)) => ItemGroup::Global(u),
       Some((Keyword::Const, u)) => ItemGroup::Const(u),
       Some((Keyword::Typedef, mut u)) =>
@@ -1123,17 +1204,19 @@ impl<'a, C> Parser<'a, C> {
 
   /// Extract the next item from the provided item iterator.
   pub(crate) fn parse_next_item(&mut self,
-    base: &FileSpan, ItemIter {group, u, intrinsic}: &mut ItemIter
+    base: &FileSpan, ItemIter {group, u, intrinsic, inline}: &mut ItemIter
   ) -> Result<Option<Item>> {
     self.with_ctx(|this| Ok(loop {
       match group {
         ItemIterInner::New => if let Some(e) = u.next() {
-          match this.push_item_group(base, &e, *intrinsic)? {
+          match this.push_item_group(base, &e, *intrinsic, *inline)? {
             ItemGroup::Item(it) => break Some(it),
             ItemGroup::Global(u2) => *group = ItemIterInner::Global(u2),
             ItemGroup::Const(u2) => *group = ItemIterInner::Const(u2),
             ItemGroup::Intrinsic(u) => *group = ItemIterInner::Intrinsic(
-              Box::new(ItemIter {group: ItemIterInner::New, u, intrinsic: true})),
+              Box::new(ItemIter {group: ItemIterInner::New, u, intrinsic: true, inline: *inline})),
+            ItemGroup::Inline(u) => *group = ItemIterInner::Inline(
+              Box::new(ItemIter {group: ItemIterInner::New, u, intrinsic: *intrinsic, inline: true})),
           }
         } else {
           break None
@@ -1148,7 +1231,8 @@ impl<'a, C> Parser<'a, C> {
         } else {
           *group = ItemIterInner::New
         }
-        ItemIterInner::Intrinsic(iter) => if let Some(item) = this.parse_next_item(base, iter)? {
+        ItemIterInner::Intrinsic(iter) |
+        ItemIterInner::Inline(iter) => if let Some(item) = this.parse_next_item(base, iter)? {
           break Some(item)
         } else {
           *group = ItemIterInner::New
@@ -1247,92 +1331,23 @@ impl<'a, C> Parser<'a, C> {
   fn parse_ty(&mut self, base: &FileSpan, e: &LispVal) -> Result<Type> {
     let span = try_get_fspan(base, e);
     let mut u = Uncons::new(e.clone());
-    let (head, mut args) = match u.next() {
+    let (head, args) = match u.next() {
       None if u.is_empty() => return Ok(Spanned {span, k: TypeKind::Unit}),
       None => (u.into(), vec![]),
       Some(head) => (head, u.collect()),
     };
 
     macro_rules! ty {($ty:expr) => {Box::new(self.parse_ty(&span, $ty)?)}}
-    macro_rules! tys {($args:expr) => {
-      $args.iter().map(|ty| self.parse_ty(&span, ty)).collect::<Result<_>>()?}}
     macro_rules! expr {($e:expr) => {Box::new(self.parse_expr(&span, $e.clone())?)}}
-    macro_rules! exprs {($args:expr) => {
-      $args.iter().map(|e| self.parse_expr(&span, e.clone())).collect::<Result<_>>()?}}
     let k = if let Some(name) = head.as_atom() {
       if name == AtomId::UNDER {
         return Ok(spanned(base, e, TypeKind::Infer))
       }
       let name = self.as_symbol(name);
-      match self.compiler.names.get(&name) {
-        Some(&Entity::Prim(Prim {ty: Some(prim), ..})) => match (prim, &*args) {
-          (PrimType::Array, [ty, n]) => TypeKind::Array(ty!(ty), expr!(n)),
-          (PrimType::Bool, []) => TypeKind::Bool,
-          (PrimType::I8, []) => TypeKind::Int(Size::S8),
-          (PrimType::I16, []) => TypeKind::Int(Size::S16),
-          (PrimType::I32, []) => TypeKind::Int(Size::S32),
-          (PrimType::I64, []) => TypeKind::Int(Size::S64),
-          (PrimType::Int, []) => TypeKind::Int(Size::Inf),
-          (PrimType::U8, []) => TypeKind::UInt(Size::S8),
-          (PrimType::U16, []) => TypeKind::UInt(Size::S16),
-          (PrimType::U32, []) => TypeKind::UInt(Size::S32),
-          (PrimType::U64, []) => TypeKind::UInt(Size::S64),
-          (PrimType::Nat, []) => TypeKind::UInt(Size::Inf),
-          (PrimType::Input, []) => TypeKind::Input,
-          (PrimType::Output, []) => TypeKind::Output,
-          (PrimType::Own, [ty]) => TypeKind::Own(ty!(ty)),
-          (PrimType::Ref, [ty]) => TypeKind::Ref(None, ty!(ty)),
-          (PrimType::RefSn, [e]) => TypeKind::RefSn(expr!(e.clone())),
-          (PrimType::Shr, [ty]) => TypeKind::Shr(None, ty!(ty)),
-          (PrimType::Sn, [e]) => TypeKind::Sn(expr!(e.clone())),
-          (PrimType::List | PrimType::Star, _) => TypeKind::List(tys!(args)),
-          (PrimType::Struct, _) => {
-            let mut out = vec![];
-            for e in args { self.push_args(base, false, e, &mut out)? }
-            TypeKind::Struct(out.into())
-          },
-          (PrimType::And, _) => TypeKind::And(tys!(args)),
-          (PrimType::Or, _) => TypeKind::Or(tys!(args)),
-          (PrimType::Moved, [ty]) => TypeKind::Moved(ty!(ty)),
-          (PrimType::Ghost, [ty]) => TypeKind::Ghost(ty!(ty)),
-          (PrimType::Uninit, [ty]) => TypeKind::Uninit(ty!(ty)),
-          (PrimType::All, args1) if !args1.is_empty() => self.with_ctx(|this| -> Result<_> {
-            let last = args.pop().expect("nonempty");
-            let args = args.into_iter().map(|e| {
-              this.push_tuple_pattern(base, false, e)
-            }).collect::<Result<_>>()?;
-            Ok(TypeKind::All(args, Box::new(this.parse_ty(&span, &last)?)))
-          })?,
-          (PrimType::Ex, args1) if !args1.is_empty() => self.with_ctx(|this| -> Result<_> {
-            let last = args.pop().expect("nonempty");
-            let args = args.into_iter().map(|e| {
-              this.push_tuple_pattern(base, false, e)
-            }).collect::<Result<_>>()?;
-            let ty = Box::new(this.parse_ty(&span, &last)?);
-            let v = this.ba.fresh_var(Spanned { span: ty.span.clone(), k: Symbol::UNDER });
-            Ok(TypeKind::ex(span.clone(), args, v, ty))
-          })?,
-          (PrimType::Imp, [e1, e2]) => TypeKind::Imp(ty!(e1), ty!(e2)),
-          (PrimType::Wand, [e1, e2]) => TypeKind::Wand(ty!(e1), ty!(e2)),
-          (PrimType::HasTy, [e, ty]) => TypeKind::HasTy(expr!(e), ty!(ty)),
-          _ => return Err(ElabError::new_e(&span, "unexpected number of arguments"))
-        },
-        Some(&Entity::Prim(p)) if p.op.is_some() => TypeKind::Pure(expr!(e)),
-        Some(Entity::Type(ty)) => if let Some(&TypeTy {tyargs, args: ref tgt, ..}) = ty.k.ty() {
-          let n = tyargs as usize;
-          let nargs = tgt.iter().filter(|&a| matches!(a.1, global::ArgKind::Lam(_))).count();
-          if args.len() != n + nargs {
-            return Err(ElabError::new_e(try_get_span(base, &head), "unexpected number of arguments"))
-          }
-          TypeKind::User(name, tys!(args[..n]), exprs!(args[n..]))
-        } else {
-          TypeKind::Error
-        },
-        Some(_) => return Err(ElabError::new_e(try_get_span(base, &head), "expected a type")),
-        None if args.is_empty() => TypeKind::Var(self.ba.get_tyvar(name).ok_or_else(||
-          ElabError::new_e(&span, format!("unknown type variable '{name}'")))?),
-        None => return Err(ElabError::new_e(try_get_span(base, &head),
-          format!("unknown type constructor '{name}'"))),
+      let head_span = try_get_fspan(base, &head);
+      match self.parse_ty_app(&span, &head_span, name, args)? {
+        Some(k) => k,
+        None => TypeKind::Pure(expr!(e)),
       }
     } else {
       match self.as_keyword(&head) {
@@ -1349,28 +1364,293 @@ impl<'a, C> Parser<'a, C> {
     Ok(Spanned {span, k})
   }
 
+  fn parse_pure_args(&mut self, base: &FileSpan, mut args: Vec<LispVal>
+  ) -> Result<(Vec<(AtomId, Expr)>, LispVal)> {
+    if let Some(last) = args.pop() {
+      Ok((args.into_iter().map(|e| {
+        let span = try_get_fspan(base, &e);
+        if let Some((Keyword::ColonEq, mut u)) = self.head_keyword(&e) {
+          if let (Some(lhs), Some(rhs), true) = (u.next(), u.next(), u.is_empty()) {
+            return Ok((
+              lhs.as_atom().ok_or_else(||
+                ElabError::new_e(&try_get_fspan(&span, &lhs), "pure: expected an atom"))?,
+              self.parse_expr(&span, rhs)?))
+          }
+        }
+        Err(ElabError::new_e(&span, "'pure' syntax error"))
+      }).collect::<Result<_>>()?, last))
+    } else { Err(ElabError::new_e(base, "expected 1 argument")) }
+  }
+
+  /// Parse an MM0 expression. This is a sort of hybrid of MMC and MM0 syntax because it is MM0 syntax
+  /// in the term constructors with variables drawn from the MMC context. For example,
+  /// `(begin {x := 1} {y := 2} (pure $ x + x = y $))` will work, where `+` and `=` are the MM0 term constructors
+  /// `add` and `eq`, while `x` and `y` are program variables in the MMC context. (TODO: MMC antiquotation?)
+  fn parse_mm0_expr(&mut self, base: &FileSpan, args: Vec<LispVal>,
+  ) -> Result<Mm0Expr<Expr>> {
+    struct Mm0<'a, C> {
+      subst: Vec<Expr>,
+      base: &'a FileSpan,
+      vars: HashMap<AtomId, u32>,
+      dummies: Vec<AtomId>,
+      p: &'a Parser<'a, C>
+    }
+    impl<C> Mm0<'_, C> {
+      fn list_opt(&mut self, e: &LispVal, head: AtomId, args: Option<Uncons>) -> Result<Option<Mm0ExprNode>> {
+        let tid = self.p.fe.env.term(head).ok_or_else(|| ElabError::new_e(try_get_span(self.base, e),
+          format!("term '{}' not declared", self.p.fe.to(&head))))?;
+        let term = &self.p.fe.env.terms[tid];
+        if args.as_ref().map_or(0, Uncons::len) != term.args.len() {
+          return Err(ElabError::new_e(try_get_span(self.base, e),
+            format!("expected {} arguments", term.args.len())));
+        }
+        Ok(if let Some(u) = args {
+          let mut cnst = true;
+          let mut vec = Vec::with_capacity(u.len());
+          let len = self.dummies.len();
+          for (e, (_, arg)) in u.zip(&*term.args) {
+            match *arg {
+              EType::Bound(_) => {
+                let a = e.as_atom().ok_or_else(||
+                  ElabError::new_e(try_get_span(self.base, &e), "expected an atom"))?;
+                self.dummies.push(a);
+                vec.push(Mm0ExprNode::Const(e))
+              }
+              EType::Reg(_, _) => {
+                let n = self.node(e)?;
+                cnst &= matches!(n, Mm0ExprNode::Const(_));
+                vec.push(n)
+              }
+            }
+          }
+          self.dummies.truncate(len);
+          if cnst {None} else {Some(Mm0ExprNode::Expr(tid, vec))}
+        } else {None})
+      }
+
+      fn node_opt(&mut self, e: &LispVal) -> Result<Option<Mm0ExprNode>> {
+        e.unwrapped(|r| Ok(if let LispKind::Atom(a) = *r {
+          if self.dummies.contains(&a) {return Ok(None)}
+          match self.vars.entry(a) {
+            Entry::Occupied(entry) => Some(Mm0ExprNode::Var(*entry.get())),
+            Entry::Vacant(entry) => {
+              let name = self.p.as_symbol_ref(a);
+              if let Some(v) = self.p.ba.get_var(name) {
+                let n = self.subst.len().try_into().expect("overflow");
+                entry.insert(n);
+                self.subst.push(Spanned {span: try_get_fspan(self.base, e), k: ExprKind::Var(v)});
+                Some(Mm0ExprNode::Var(n))
+              } else {
+                self.list_opt(e, a, None)?
+              }
+            }
+          }
+        } else {
+          let mut u = Uncons::from(e.clone());
+          let head = u.next().ok_or_else(|| ElabError::new_e(try_get_span(self.base, e),
+            format!("bad expression {}", self.p.fe.to(e))))?;
+          let a = head.as_atom().ok_or_else(|| ElabError::new_e(try_get_span(self.base, &head),
+            "expected an atom"))?;
+          self.list_opt(&head, a, Some(u))?
+        }))
+      }
+
+      #[allow(clippy::unnecessary_lazy_evaluations)]
+      fn node(&mut self, e: LispVal) -> Result<Mm0ExprNode> {
+        Ok(self.node_opt(&e)?.unwrap_or_else(|| Mm0ExprNode::Const(e)))
+      }
+    }
+
+    let (subst, e) = self.parse_pure_args(base, args)?;
+    let mut vars = HashMap::new();
+    let subst = subst.into_iter().enumerate().map(|(i, (a, e))| {
+      vars.insert(a, i.try_into().expect("overflow"));
+      e
+    }).collect();
+    let mut mm0 = Mm0 {
+      subst,
+      base,
+      vars,
+      dummies: vec![],
+      p: self,
+    };
+    let expr = mm0.node(e)?;
+    Ok(Mm0Expr {subst: mm0.subst, expr: self.lambdas.push(expr)})
+  }
+}
+
+impl<C> Resolve for Parser<'_, C> {
+  type Arg = LispVal;
+
+  fn ba(&mut self) -> &mut BuildAst { &mut self.ba }
+
+  fn names(&self) -> &HashMap<Symbol, Entity> { &self.compiler.names }
+
+  fn expr(&mut self, base: &FileSpan, e: &LispVal) -> Result<Expr> {
+    self.parse_expr(base, e.clone())
+  }
+
+  fn ty(&mut self, base: &FileSpan, e: &LispVal) -> Result<Type> { self.parse_ty(base, e) }
+
+  fn label_name(&mut self, e: &LispVal) -> Option<Symbol> { Some(self.as_symbol(e.as_atom()?)) }
+
+  fn tuple_pattern(&mut self, base: &FileSpan, e: LispVal) -> Result<TuplePattern> {
+    self.push_tuple_pattern(base, false, e)
+  }
+
+  fn push_fields(&mut self, base: &FileSpan, e: LispVal, out: &mut Vec<Arg>) -> Result<()> {
+    self.push_args(base, false, e, out)
+  }
+
+  fn mm0_expr(&mut self, base: &FileSpan, args: Vec<LispVal>) -> Result<Mm0Expr<Expr>> {
+    self.parse_mm0_expr(base, args)
+  }
+}
+
+/// The resolution of names in calls and type constructors, which is shared by the lisp parser
+/// and the C-like [`syntax`](super::syntax). An `Arg` is an argument which has not been parsed
+/// yet, because it is an expression or a type depending on what the head resolves to.
+pub(crate) trait Resolve: Sized {
+  /// An unparsed argument.
+  type Arg;
+
+  /// The AST builder, which contains the local context.
+  fn ba(&mut self) -> &mut BuildAst;
+
+  /// The global names.
+  fn names(&self) -> &HashMap<Symbol, Entity>;
+
+  /// Parse an argument as an expression.
+  fn expr(&mut self, base: &FileSpan, e: &Self::Arg) -> Result<Expr>;
+
+  /// Parse an argument as a type.
+  fn ty(&mut self, base: &FileSpan, e: &Self::Arg) -> Result<Type>;
+
+  /// Get the name of an argument, if it is a single name.
+  fn label_name(&mut self, e: &Self::Arg) -> Option<Symbol>;
+
+  /// Parse an argument as a tuple pattern, and push the names it binds.
+  fn tuple_pattern(&mut self, base: &FileSpan, e: Self::Arg) -> Result<TuplePattern>;
+
+  /// Parse an argument as a list of fields, like function arguments without `mut`.
+  fn push_fields(&mut self, base: &FileSpan, e: Self::Arg, out: &mut Vec<Arg>) -> Result<()>;
+
+  /// Parse the arguments of `pure` as an MM0 expression.
+  fn mm0_expr(&mut self, base: &FileSpan, args: Vec<Self::Arg>) -> Result<Mm0Expr<Expr>>;
+
+  /// Run `f` in a new scope, so that names bound in `f` are not visible afterwards.
+  fn with_ctx<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+    let sc = self.ba().scope();
+    let res = f(self);
+    sc.close(self.ba());
+    res
+  }
+
+  /// Parse the application of the type constructor `name` to `args`. Returns `None` if `name`
+  /// is a primitive operation rather than a type, so the type is a proposition `(pure e)`.
+  fn parse_ty_app(&mut self,
+    span: &FileSpan, head_span: &FileSpan, name: Symbol, mut args: Vec<Self::Arg>,
+  ) -> Result<Option<TypeKind>> {
+    macro_rules! ty {($ty:expr) => {Box::new(self.ty(span, $ty)?)}}
+    macro_rules! tys {($args:expr) => {
+      $args.iter().map(|ty| self.ty(span, ty)).collect::<Result<_>>()?}}
+    macro_rules! expr {($e:expr) => {Box::new(self.expr(span, $e)?)}}
+    macro_rules! exprs {($args:expr) => {
+      $args.iter().map(|e| self.expr(span, e)).collect::<Result<_>>()?}}
+    Ok(Some(match self.names().get(&name) {
+      Some(&Entity::Prim(Prim {ty: Some(prim), ..})) => match (prim, &*args) {
+        (PrimType::Array, [ty, n]) => TypeKind::Array(ty!(ty), expr!(n)),
+        (PrimType::Bool, []) => TypeKind::Bool,
+        (PrimType::I8, []) => TypeKind::Int(Size::S8),
+        (PrimType::I16, []) => TypeKind::Int(Size::S16),
+        (PrimType::I32, []) => TypeKind::Int(Size::S32),
+        (PrimType::I64, []) => TypeKind::Int(Size::S64),
+        (PrimType::Int, []) => TypeKind::Int(Size::Inf),
+        (PrimType::U8, []) => TypeKind::UInt(Size::S8),
+        (PrimType::U16, []) => TypeKind::UInt(Size::S16),
+        (PrimType::U32, []) => TypeKind::UInt(Size::S32),
+        (PrimType::U64, []) => TypeKind::UInt(Size::S64),
+        (PrimType::Nat, []) => TypeKind::UInt(Size::Inf),
+        (PrimType::Input, []) => TypeKind::Input,
+        (PrimType::Output, []) => TypeKind::Output,
+        (PrimType::Own, [ty]) => TypeKind::Own(ty!(ty)),
+        (PrimType::Ref, [ty]) => TypeKind::Ref(None, ty!(ty)),
+        (PrimType::RefSn, [e]) => TypeKind::RefSn(expr!(e)),
+        (PrimType::Shr, [ty]) => TypeKind::Shr(None, ty!(ty)),
+        (PrimType::Sn, [e]) => TypeKind::Sn(expr!(e)),
+        (PrimType::List | PrimType::Star, _) => TypeKind::List(tys!(args)),
+        (PrimType::Struct, _) => {
+          let mut out = vec![];
+          for e in args { self.push_fields(span, e, &mut out)? }
+          TypeKind::Struct(out.into())
+        },
+        (PrimType::And, _) => TypeKind::And(tys!(args)),
+        (PrimType::Or, _) => TypeKind::Or(tys!(args)),
+        (PrimType::Moved, [ty]) => TypeKind::Moved(ty!(ty)),
+        (PrimType::Ghost, [ty]) => TypeKind::Ghost(ty!(ty)),
+        (PrimType::Uninit, [ty]) => TypeKind::Uninit(ty!(ty)),
+        (PrimType::All, args1) if !args1.is_empty() => self.with_ctx(|this| -> Result<_> {
+          let last = args.pop().expect("nonempty");
+          let args = args.into_iter().map(|e| {
+            this.tuple_pattern(span, e)
+          }).collect::<Result<_>>()?;
+          Ok(TypeKind::All(args, Box::new(this.ty(span, &last)?)))
+        })?,
+        (PrimType::Ex, args1) if !args1.is_empty() => self.with_ctx(|this| -> Result<_> {
+          let last = args.pop().expect("nonempty");
+          let args = args.into_iter().map(|e| {
+            this.tuple_pattern(span, e)
+          }).collect::<Result<_>>()?;
+          let ty = Box::new(this.ty(span, &last)?);
+          let v = this.ba().fresh_var(Spanned { span: ty.span.clone(), k: Symbol::UNDER });
+          Ok(TypeKind::ex(span.clone(), args, v, ty))
+        })?,
+        (PrimType::Imp, [e1, e2]) => TypeKind::Imp(ty!(e1), ty!(e2)),
+        (PrimType::Wand, [e1, e2]) => TypeKind::Wand(ty!(e1), ty!(e2)),
+        (PrimType::HasTy, [e, ty]) => TypeKind::HasTy(expr!(e), ty!(ty)),
+        _ => return Err(ElabError::new_e(span, "unexpected number of arguments"))
+      },
+      Some(&Entity::Prim(p)) if p.op.is_some() => return Ok(None),
+      Some(Entity::Type(ty)) => if let Some(&TypeTy {tyargs, args: ref tgt, ..}) = ty.k.ty() {
+        let n = tyargs as usize;
+        let nargs = tgt.iter().filter(|&a| matches!(a.1, global::ArgKind::Lam(_))).count();
+        if args.len() != n + nargs {
+          return Err(ElabError::new_e(head_span, "unexpected number of arguments"))
+        }
+        TypeKind::User(name, tys!(args[..n]), exprs!(args[n..]))
+      } else {
+        TypeKind::Error
+      },
+      Some(_) => return Err(ElabError::new_e(head_span, "expected a type")),
+      None if args.is_empty() => TypeKind::Var(self.ba().get_tyvar(name).ok_or_else(||
+        ElabError::new_e(span, format!("unknown type variable '{name}'")))?),
+      None => return Err(ElabError::new_e(head_span,
+        format!("unknown type constructor '{name}'"))),
+    }))
+  }
+
   /// Parse an expression that looks like a function call.
   fn parse_call(&mut self,
     span: FileSpan,
     fsp: FileSpan, f: Symbol,
-    args: Vec<LispVal>,
-    variant: Option<LispVal>,
+    args: Vec<Self::Arg>,
+    variant: Option<Self::Arg>,
   ) -> Result<Expr> {
     macro_rules! err {($($e:expr),*) => {
       return Err(ElabError::new_e(&span, format!($($e),*)))
     }}
-    macro_rules! ty {($ty:expr) => {Box::new(self.parse_ty(&span, $ty)?)}}
+    macro_rules! ty {($ty:expr) => {Box::new(self.ty(&span, $ty)?)}}
     macro_rules! tys {($args:expr) => {
-      $args.iter().map(|ty| self.parse_ty(&span, ty)).collect::<Result<_>>()?}}
-    macro_rules! expr {($e:expr) => {Box::new(self.parse_expr(&span, $e.clone())?)}}
+      $args.iter().map(|ty| self.ty(&span, ty)).collect::<Result<_>>()?}}
+    macro_rules! expr {($e:expr) => {Box::new(self.expr(&span, $e)?)}}
     macro_rules! exprs {($args:expr) => {
-      $args.iter().map(|e| self.parse_expr(&span, e.clone())).collect::<Result<_>>()?}}
-    macro_rules! variant {() => {if let Some(e) = variant {Some(expr!(e))} else {None}}}
-    if let Some(lab) = self.ba.get_label(f) {
+      $args.iter().map(|e| self.expr(&span, e)).collect::<Result<_>>()?}}
+    macro_rules! variant {() => {if let Some(e) = &variant {Some(expr!(e))} else {None}}}
+    if let Some(lab) = self.ba().get_label(f) {
       let k = ExprKind::Jump(lab, exprs!(args), variant!());
       return Ok(Spanned {span, k})
     }
-    let k = match self.compiler.names.get(&f) {
+    let k = match self.names().get(&f) {
       None => err!("unknown function '{}'", f),
       Some(Entity::Const(_)) => ExprKind::Const(f),
       Some(Entity::Global(_)) => return Err(ElabError::new_e(&span, format!(
@@ -1378,29 +1658,29 @@ impl<'a, C> Parser<'a, C> {
         A global with this name exists but must be imported into scope with\n  (global {f})\
         \nin the function signature."))),
       Some(Entity::Prim(Prim {op: Some(prim), ..})) => match (prim, &*args) {
-        (PrimOp::Add, _) => {let args = exprs!(args); return Ok(self.ba.mk_add(&span, args))}
-        (PrimOp::And, _) => {let args = exprs!(args); return Ok(self.ba.mk_and(&span, args))}
-        (PrimOp::Or, _) => {let args = exprs!(args); return Ok(self.ba.mk_or(&span, args))}
-        (PrimOp::BitAnd, _) => {let args = exprs!(args); return Ok(self.ba.mk_bit_and(&span, args))}
-        (PrimOp::BitNot, _) => {let args = exprs!(args); return Ok(self.ba.mk_bit_nor(&span, args))}
-        (PrimOp::BitOr, _) => {let args = exprs!(args); return Ok(self.ba.mk_bit_or(&span, args))}
-        (PrimOp::BitXor, _) => {let args = exprs!(args); return Ok(self.ba.mk_bit_xor(&span, args))}
+        (PrimOp::Add, _) => {let args = exprs!(args); return Ok(self.ba().mk_add(&span, args))}
+        (PrimOp::And, _) => {let args = exprs!(args); return Ok(self.ba().mk_and(&span, args))}
+        (PrimOp::Or, _) => {let args = exprs!(args); return Ok(self.ba().mk_or(&span, args))}
+        (PrimOp::BitAnd, _) => {let args = exprs!(args); return Ok(self.ba().mk_bit_and(&span, args))}
+        (PrimOp::BitNot, _) => {let args = exprs!(args); return Ok(self.ba().mk_bit_nor(&span, args))}
+        (PrimOp::BitOr, _) => {let args = exprs!(args); return Ok(self.ba().mk_bit_or(&span, args))}
+        (PrimOp::BitXor, _) => {let args = exprs!(args); return Ok(self.ba().mk_bit_xor(&span, args))}
         (PrimOp::Max | PrimOp::Min | PrimOp::Le | PrimOp::Lt | PrimOp::Eq | PrimOp::Ne, _)
         if args.is_empty() => err!("expected 2 arguments"),
-        (PrimOp::Max, _) => {let args = exprs!(args); return Ok(self.ba.mk_max(&span, args))}
-        (PrimOp::Min, _) => {let args = exprs!(args); return Ok(self.ba.mk_min(&span, args))}
+        (PrimOp::Max, _) => {let args = exprs!(args); return Ok(self.ba().mk_max(&span, args))}
+        (PrimOp::Min, _) => {let args = exprs!(args); return Ok(self.ba().mk_min(&span, args))}
         (PrimOp::MulDeref, [e]) => ExprKind::Deref(expr!(e)),
-        (PrimOp::MulDeref, _) => {let args = exprs!(args); return Ok(self.ba.mk_mul(&span, args))}
-        (PrimOp::Not, _) => {let args = exprs!(args); return Ok(self.ba.mk_nor(&span, args))}
+        (PrimOp::MulDeref, _) => {let args = exprs!(args); return Ok(self.ba().mk_mul(&span, args))}
+        (PrimOp::Not, _) => {let args = exprs!(args); return Ok(self.ba().mk_nor(&span, args))}
         (PrimOp::Le | PrimOp::Lt | PrimOp::Eq | PrimOp::Ne, _) if args.is_empty() =>
           err!("expected 2 arguments"),
-        (PrimOp::Le, _) => {let args = exprs!(args); return Ok(self.ba.mk_le(&span, args))}
-        (PrimOp::Lt, _) => {let args = exprs!(args); return Ok(self.ba.mk_lt(&span, args))}
-        (PrimOp::Eq, _) => {let args = exprs!(args); return Ok(self.ba.mk_eq(&span, args))}
-        (PrimOp::Ne, _) => {let args = exprs!(args); return Ok(self.ba.mk_ne(&span, args))}
+        (PrimOp::Le, _) => {let args = exprs!(args); return Ok(self.ba().mk_le(&span, args))}
+        (PrimOp::Lt, _) => {let args = exprs!(args); return Ok(self.ba().mk_lt(&span, args))}
+        (PrimOp::Eq, _) => {let args = exprs!(args); return Ok(self.ba().mk_eq(&span, args))}
+        (PrimOp::Ne, _) => {let args = exprs!(args); return Ok(self.ba().mk_ne(&span, args))}
         (PrimOp::List, _) => ExprKind::List(exprs!(args)),
         (PrimOp::Assert, _) =>
-          {let args = exprs!(args); ExprKind::Assert(Box::new(self.ba.mk_and(&span, args)))}
+          {let args = exprs!(args); ExprKind::Assert(Box::new(self.ba().mk_and(&span, args)))}
         (PrimOp::Index, args) => match args {
           [arr, idx] => ExprKind::Index(expr!(arr), expr!(idx), None),
           [arr, idx, pf] => ExprKind::Index(expr!(arr), expr!(idx), Some(expr!(pf))),
@@ -1410,7 +1690,7 @@ impl<'a, C> Parser<'a, C> {
         (PrimOp::Return, _) => ExprKind::Return(exprs!(args)),
         (PrimOp::Sub, []) => err!("expected 1 or more arguments"),
         (PrimOp::Sub, [e]) => ExprKind::Unop(Unop::Neg, expr!(e)),
-        (PrimOp::Sub, _) => {let args = exprs!(args); return Ok(self.ba.mk_sub(&span, args))}
+        (PrimOp::Sub, _) => {let args = exprs!(args); return Ok(self.ba().mk_sub(&span, args))}
         (PrimOp::Shl, [a, b]) => ExprKind::Binop(Binop::Shl, expr!(a), expr!(b)),
         (PrimOp::Shr, [a, b]) => ExprKind::Binop(Binop::Shr, expr!(a), expr!(b)),
         (PrimOp::Typed, [e, ty]) => ExprKind::Typed(expr!(e), ty!(ty)),
@@ -1441,7 +1721,7 @@ impl<'a, C> Parser<'a, C> {
         },
         (PrimOp::Uninit, []) => ExprKind::Uninit,
         (PrimOp::Uninit, _) => err!("expected 0 arguments"),
-        (PrimOp::Pure, _) => ExprKind::Mm0(self.parse_mm0_expr(&span, args)?),
+        (PrimOp::Pure, _) => ExprKind::Mm0(self.mm0_expr(&span, args)?),
         (PrimOp::Ref, [e]) => ExprKind::Ref(expr!(e)),
         (PrimOp::Borrow, [e]) => ExprKind::Borrow(expr!(e)),
         (PrimOp::TypeofBang, [e]) => ExprKind::Typeof(expr!(e)),
@@ -1457,21 +1737,21 @@ impl<'a, C> Parser<'a, C> {
         }),
         (PrimOp::Break, args1) => {
           let (lab, args) = match args1.first().and_then(|e| {
-            let a = self.as_symbol(e.as_atom()?); self.ba.get_label(a)
+            let a = self.label_name(e)?; self.ba().get_label(a)
           }) {
             Some(lab) => (lab, &args[1..]),
-            None => (self.ba.get_loop_label().ok_or_else(||
+            None => (self.ba().get_loop_label().ok_or_else(||
               ElabError::new_e(&span, "can't break, not in a loop"))?, &*args)
           };
-          self.ba.mark_label_break(lab.0);
+          self.ba().mark_label_break(lab.0);
           ExprKind::Break(lab.0, Box::new(Expr::list(&span, exprs!(args))))
         }
         (PrimOp::Continue, args1) => {
           let (lab, args) = match args1.first().and_then(|e| {
-            let a = self.as_symbol(e.as_atom()?); self.ba.get_label(a)
+            let a = self.label_name(e)?; self.ba().get_label(a)
           }) {
             Some(lab) => (lab, &args[1..]),
-            None => (self.ba.get_loop_label().ok_or_else(||
+            None => (self.ba().get_loop_label().ok_or_else(||
               ElabError::new_e(&span, "can't break, not in a loop"))?, &*args)
           };
           ExprKind::Jump(lab, exprs!(args), variant!())
@@ -1497,117 +1777,4 @@ impl<'a, C> Parser<'a, C> {
     Ok(Spanned {span, k})
   }
 
-  fn parse_pure_args(&mut self, base: &FileSpan, mut args: Vec<LispVal>
-  ) -> Result<(Vec<(AtomId, Expr)>, LispVal)> {
-    if let Some(last) = args.pop() {
-      Ok((args.into_iter().map(|e| {
-        let span = try_get_fspan(base, &e);
-        if let Some((Keyword::ColonEq, mut u)) = self.head_keyword(&e) {
-          if let (Some(lhs), Some(rhs), true) = (u.next(), u.next(), u.is_empty()) {
-            return Ok((
-              lhs.as_atom().ok_or_else(||
-                ElabError::new_e(&try_get_fspan(&span, &lhs), "pure: expected an atom"))?,
-              self.parse_expr(&span, rhs)?))
-          }
-        }
-        Err(ElabError::new_e(&span, "'pure' syntax error"))
-      }).collect::<Result<_>>()?, last))
-    } else { Err(ElabError::new_e(base, "expected 1 argument")) }
-  }
-
-  /// Parse an MM0 expression. This is a sort of hybrid of MMC and MM0 syntax because it is MM0 syntax
-  /// in the term constructors with variables drawn from the MMC context. For example,
-  /// `(begin {x := 1} {y := 2} (pure $ x + x = y $))` will work, where `+` and `=` are the MM0 term constructors
-  /// `add` and `eq`, while `x` and `y` are program variables in the MMC context. (TODO: MMC antiquotation?)
-  fn parse_mm0_expr(&mut self, base: &FileSpan, args: Vec<LispVal>,
-  ) -> Result<Mm0Expr<Expr>> {
-    struct Mm0<'a, C> {
-      subst: Vec<Expr>,
-      base: &'a FileSpan,
-      vars: HashMap<AtomId, u32>,
-      dummies: Vec<AtomId>,
-      p: &'a Parser<'a, C>
-    }
-    impl<C> Mm0<'_, C> {
-      fn list_opt(&mut self, e: &LispVal, head: AtomId, args: Option<Uncons>) -> Result<Option<Mm0ExprNode>> {
-        let tid = self.p.fe.env.term(head).ok_or_else(|| ElabError::new_e(try_get_span(self.base, e),
-          format!("term '{}' not declared", self.p.fe.to(&head))))?;
-        let term = &self.p.fe.env.terms[tid];
-        if args.as_ref().map_or(0, Uncons::len) != term.args.len() {
-          return Err(ElabError::new_e(try_get_span(self.base, e),
-            format!("expected {} arguments", term.args.len())));
-        }
-        Ok(if let Some(u) = args {
-          let mut cnst = true;
-          let mut vec = Vec::with_capacity(u.len());
-          let len = self.dummies.len();
-          for (e, (_, arg)) in u.zip(&*term.args) {
-            match *arg {
-              EType::Bound(_) => {
-                let a = e.as_atom().ok_or_else(||
-                  ElabError::new_e(try_get_span(self.base, &e), "expected an atom"))?;
-                self.dummies.push(a);
-                vec.push(Mm0ExprNode::Const(e))
-              }
-              EType::Reg(_, _) => {
-                let n = self.node(e)?;
-                cnst &= matches!(n, Mm0ExprNode::Const(_));
-                vec.push(n)
-              }
-            }
-          }
-          self.dummies.truncate(len);
-          if cnst {None} else {Some(Mm0ExprNode::Expr(tid, vec))}
-        } else {None})
-      }
-
-      fn node_opt(&mut self, e: &LispVal) -> Result<Option<Mm0ExprNode>> {
-        e.unwrapped(|r| Ok(if let LispKind::Atom(a) = *r {
-          if self.dummies.contains(&a) {return Ok(None)}
-          match self.vars.entry(a) {
-            Entry::Occupied(entry) => Some(Mm0ExprNode::Var(*entry.get())),
-            Entry::Vacant(entry) => {
-              let name = self.p.as_symbol_ref(a);
-              if let Some(v) = self.p.ba.get_var(name) {
-                let n = self.subst.len().try_into().expect("overflow");
-                entry.insert(n);
-                self.subst.push(Spanned {span: try_get_fspan(self.base, e), k: ExprKind::Var(v)});
-                Some(Mm0ExprNode::Var(n))
-              } else {
-                self.list_opt(e, a, None)?
-              }
-            }
-          }
-        } else {
-          let mut u = Uncons::from(e.clone());
-          let head = u.next().ok_or_else(|| ElabError::new_e(try_get_span(self.base, e),
-            format!("bad expression {}", self.p.fe.to(e))))?;
-          let a = head.as_atom().ok_or_else(|| ElabError::new_e(try_get_span(self.base, &head),
-            "expected an atom"))?;
-          self.list_opt(&head, a, Some(u))?
-        }))
-      }
-
-      #[allow(clippy::unnecessary_lazy_evaluations)]
-      fn node(&mut self, e: LispVal) -> Result<Mm0ExprNode> {
-        Ok(self.node_opt(&e)?.unwrap_or_else(|| Mm0ExprNode::Const(e)))
-      }
-    }
-
-    let (subst, e) = self.parse_pure_args(base, args)?;
-    let mut vars = HashMap::new();
-    let subst = subst.into_iter().enumerate().map(|(i, (a, e))| {
-      vars.insert(a, i.try_into().expect("overflow"));
-      e
-    }).collect();
-    let mut mm0 = Mm0 {
-      subst,
-      base,
-      vars,
-      dummies: vec![],
-      p: self,
-    };
-    let expr = mm0.node(e)?;
-    Ok(Mm0Expr {subst: mm0.subst, expr: self.lambdas.push(expr)})
-  }
 }
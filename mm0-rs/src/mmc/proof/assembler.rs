@@ -4,7 +4,8 @@ use mmcc::{types::{mir, classify::{self as cl, TraceIter}, vcode::{ArgAbi, ProcA
 use mmcc::{Symbol, TEXT_START, types::Size};
 use mmcc::arch::{ExtMode, OpcodeLayout, PInst, PRegMemImm, Unop};
 use mmcc::proof::{AssemblyItem, AssemblyItemIter, ElfProof, Inst, Proc, ProcId};
-use crate::{Elaborator, FileSpan, Modifiers, Span, TermId, ThmId, elab::Result, mmc::proof::Name};
+use crate::{Elaborator, ElabError, FileSpan, Modifiers, Span, TermId, ThmId, elab::Result,
+  mmc::proof::Name};
 
 use super::{Dedup, ExprDedup, Mangler, Predefs, ProofDedup, ProofId,
   norm_num::{HexCache, Num}, predefs::Rex};
@@ -1407,7 +1408,8 @@ impl<'a> BuildAssembly<'a> {
     let (c, filesz, a, h1) = self.bisect(iter.len(), &mut iter, zero, &mut |this, item, x| {
       match item {
         AssemblyItem::Proc(proc) => this.assemble_proc(&proc, x),
-        AssemblyItem::Const(_) => todo!(),
+        AssemblyItem::Const(_) => Err(ElabError::new_e(this.full,
+          "mmc-finish: global constants are not yet supported by the proof generator")),
       }
     })?;
     let memsz = self.hex.from_u64(&mut self.thm, proof.p_memsz());
@@ -1416,7 +1418,7 @@ impl<'a> BuildAssembly<'a> {
     let (end, h3) = self.hex.add(&mut self.thm, text_start, memsz);
     let (filesz, memsz) = (*filesz, *memsz);
     let h4 = HexCache::is_u64(&mut self.thm, *end);
-    let res = super::compiler::mk_result(&mut self.thm, proof);
+    let res = super::compiler::mk_result(&mut self.thm, proof, self.full)?;
     let th = thm!(self.thm, ((assembled (mkGCtx c filesz memsz res) a)) =>
       assembledI(a, res, c, *end, filesz, memsz, h1, h2, h3, h4));
 
@@ -1484,7 +1486,7 @@ impl<'a> BuildAssembly<'a> {
             .map_err(|e| e.into_elab_error(self.full))?;
           self.proc_asm.get_mut(&proc.id).expect("impossible").1 = asmd_thm;
         }
-        AssemblyItem::Const(_) => todo!(),
+        AssemblyItem::Const(_) => unreachable!("rejected by assemble"),
       }
       Ok(())
     } else {
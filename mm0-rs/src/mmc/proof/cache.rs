@@ -0,0 +1,248 @@
+//! Reuse of the generated correctness proof when a program is compiled again.
+//!
+//! Generating the proof is the slowest part of `mmc-finish`, and when a file is elaborated again
+//! (for example by the language server after an edit elsewhere in the file) the program is
+//! usually the same. The declarations added by [`render_proof`](super::render_proof) are stored
+//! together with the statements of the earlier declarations they refer to, and if the program is
+//! the same and these statements have not changed the next time, the stored declarations are
+//! added to the environment instead.
+
+use std::collections::BTreeSet;
+use mm0_util::{ArcString, AtomId, FileSpan, Modifiers, SortId, Span, TermId, ThmId};
+use crate::elab::Result;
+use crate::{DeclKey, Environment, ExprNode, ProofNode, Remap, Remapper, StmtTrace,
+  Term, TermKind, Thm, ThmKind, Type};
+
+/// A declaration added by the proof generator.
+enum Decl {
+  Term(Term),
+  Thm(Thm),
+}
+
+/// The statement of a term that the proof refers to. The proof only depends on the binders,
+/// the return sort and the definition, and not on the names of the variables or the spans.
+#[derive(PartialEq, Eq)]
+struct TermStmt {
+  atom: AtomId,
+  args: Box<[Type]>,
+  ret: (SortId, u64),
+  kind: TermKind,
+}
+
+impl TermStmt {
+  fn new(t: &Term) -> Self {
+    Self {
+      atom: t.atom,
+      args: t.args.iter().map(|a| a.1).collect(),
+      ret: t.ret,
+      kind: t.kind.clone(),
+    }
+  }
+
+  fn remap(&self, r: &mut Remapper) -> Self {
+    Self {
+      atom: self.atom.remap(r),
+      args: self.args.remap(r),
+      ret: (self.ret.0.remap(r), self.ret.1),
+      kind: self.kind.remap(r),
+    }
+  }
+}
+
+/// The statement of a theorem that the proof refers to. The proof of the theorem itself
+/// is not needed.
+#[derive(PartialEq, Eq)]
+struct ThmStmt {
+  atom: AtomId,
+  args: Box<[Type]>,
+  heap: Box<[ExprNode]>,
+  store: Box<[ExprNode]>,
+  hyps: Box<[ExprNode]>,
+  ret: ExprNode,
+}
+
+impl ThmStmt {
+  fn new(t: &Thm) -> Self {
+    Self {
+      atom: t.atom,
+      args: t.args.iter().map(|a| a.1).collect(),
+      heap: t.heap.clone(),
+      store: t.store.clone(),
+      hyps: t.hyps.iter().map(|h| h.1).collect(),
+      ret: t.ret,
+    }
+  }
+
+  fn remap(&self, r: &mut Remapper) -> Self {
+    Self {
+      atom: self.atom.remap(r),
+      args: self.args.remap(r),
+      heap: self.heap.remap(r),
+      store: self.store.remap(r),
+      hyps: self.hyps.remap(r),
+      ret: self.ret.remap(r),
+    }
+  }
+}
+
+/// The sorts, terms and theorems referred to by a list of declarations.
+#[derive(Default)]
+struct Refs {
+  sorts: BTreeSet<SortId>,
+  terms: BTreeSet<TermId>,
+  thms: BTreeSet<ThmId>,
+}
+
+impl Refs {
+  fn ty(&mut self, ty: Type) { self.sorts.insert(ty.sort()); }
+
+  fn expr(&mut self, e: &ExprNode) {
+    match *e {
+      ExprNode::Ref(_) => {}
+      ExprNode::Dummy(_, s) => { self.sorts.insert(s); }
+      ExprNode::App(t, _) => { self.terms.insert(t); }
+    }
+  }
+
+  fn proof(&mut self, p: &ProofNode) {
+    match *p {
+      ProofNode::Dummy(_, s) => { self.sorts.insert(s); }
+      ProofNode::Term(t, _) | ProofNode::Cong(t, _) | ProofNode::Unfold(t, _) => {
+        self.terms.insert(t);
+      }
+      ProofNode::Thm(t, _) => { self.thms.insert(t); }
+      ProofNode::Ref(_) | ProofNode::Hyp(..) | ProofNode::Conv(_) |
+      ProofNode::Refl(_) | ProofNode::Sym(_) => {}
+    }
+  }
+
+  fn decl(&mut self, decl: &Decl) {
+    match decl {
+      Decl::Term(t) => {
+        t.args.iter().for_each(|a| self.ty(a.1));
+        self.sorts.insert(t.ret.0);
+        if let TermKind::Def(Some(e)) = &t.kind {
+          e.heap.iter().chain(&*e.store).for_each(|e| self.expr(e))
+        }
+      }
+      Decl::Thm(t) => {
+        t.args.iter().for_each(|a| self.ty(a.1));
+        t.heap.iter().chain(&*t.store).chain(t.hyps.iter().map(|h| &h.1))
+          .chain(Some(&t.ret)).for_each(|e| self.expr(e));
+        if let ThmKind::Thm(Some(p)) = &t.kind {
+          p.heap.iter().chain(&*p.hyps).chain(&*p.store).for_each(|p| self.proof(p))
+        }
+      }
+    }
+  }
+}
+
+/// The result of a successful proof generation.
+struct CachedProof {
+  /// The key of the program, from [`ElfProof::key`](mmcc::proof::ElfProof::key).
+  code: u64,
+  /// The name of the module, which is used to name the generated declarations.
+  name: ArcString,
+  /// The names of the atoms at the end of proof generation. Atoms are created on demand, so the
+  /// same names can have different numbers when the proof is reused.
+  atoms: Box<[ArcString]>,
+  /// The number of sorts before proof generation.
+  num_sorts: usize,
+  /// The number of terms before proof generation. The generated declarations refer to the
+  /// earlier terms by number.
+  num_terms: usize,
+  /// The number of theorems before proof generation.
+  num_thms: usize,
+  /// The sorts referred to by the generated declarations, with their names and modifiers.
+  sorts: Box<[(SortId, AtomId, Modifiers)]>,
+  /// The earlier terms referred to by the generated declarations, with their statements.
+  term_deps: Box<[(TermId, TermStmt)]>,
+  /// The earlier theorems referred to by the generated declarations, with their statements.
+  thm_deps: Box<[(ThmId, ThmStmt)]>,
+  /// The generated declarations, in order.
+  decls: Box<[Decl]>,
+}
+
+impl CachedProof {
+  /// Check that the sorts, terms and theorems the proof refers to have the same statements
+  /// in `env` as when the proof was generated.
+  fn check_deps(&self, env: &Environment, r: &mut Remapper) -> bool {
+    self.sorts.iter().all(|&(s, a, mods)| env.sorts.get(s)
+      .is_some_and(|s| s.atom == a.remap(r) && s.mods == mods)) &&
+    self.term_deps.iter().all(|(t, stmt)| env.terms.get(*t)
+      .is_some_and(|t| TermStmt::new(t) == stmt.remap(r))) &&
+    self.thm_deps.iter().all(|(t, stmt)| env.thms.get(*t)
+      .is_some_and(|t| ThmStmt::new(t) == stmt.remap(r)))
+  }
+}
+
+/// The declarations generated by the last successful call to
+/// [`render_proof`](super::render_proof). Only the latest proof is kept.
+#[derive(Default)]
+pub(crate) struct ProofCache(Option<CachedProof>);
+#[cfg(feature = "memory")] mm0_deepsize::deep_size_0!(ProofCache);
+
+impl ProofCache {
+  /// If the stored proof is for the program with key `code` (from
+  /// [`ElfProof::key`](mmcc::proof::ElfProof::key)) in module `name`, and the declarations it
+  /// refers to are unchanged, add its declarations to `env`, located at `fsp`, and return true.
+  /// Returns false if the proof has to be generated.
+  pub(crate) fn replay(&self,
+    env: &mut Environment, code: u64, name: &ArcString, fsp: &FileSpan, full: Span
+  ) -> Result<bool> {
+    let Some(proof) = self.0.as_ref().filter(|p| p.code == code && p.name == *name)
+    else { return Ok(false) };
+    if proof.num_sorts > env.sorts.len() { return Ok(false) }
+    // Sorts, terms and theorems from before proof generation keep their numbers, and
+    // `check_deps` makes sure that the ones the proof uses still mean the same thing
+    #[allow(clippy::cast_possible_truncation)]
+    let mut r = Remapper {
+      sort: (0..proof.num_sorts).map(|i| SortId(i as u8)).collect(),
+      term: (0..proof.num_terms).map(|i| TermId(i as u32)).collect(),
+      thm: (0..proof.num_thms).map(|i| ThmId(i as u32)).collect(),
+      atom: proof.atoms.iter().map(|a| env.get_atom_arc(a.clone())).collect(),
+      ..Default::default()
+    };
+    if !proof.check_deps(env, &mut r) { return Ok(false) }
+    for decl in &*proof.decls {
+      match decl {
+        Decl::Term(t) => {
+          let t = Term { span: fsp.clone(), full, ..t.remap(&mut r) };
+          r.term.push(env.add_term(t).map_err(|e| e.into_elab_error(full))?);
+        }
+        Decl::Thm(t) => {
+          let t = Thm { span: fsp.clone(), full, ..t.remap(&mut r) };
+          r.thm.push(env.add_thm(t).map_err(|e| e.into_elab_error(full))?);
+        }
+      }
+    }
+    Ok(true)
+  }
+
+  /// Store the declarations that were added to `env` after its first `start` statements, as the
+  /// proof of the program with key `code` in module `name`.
+  pub(crate) fn insert(&mut self, env: &Environment, code: u64, name: ArcString, start: usize) {
+    let decls: Box<[Decl]> = env.stmts[start..].iter().filter_map(|s| match *s {
+      StmtTrace::Decl(a) => Some(match env.data[a].decl? {
+        DeclKey::Term(t) => Decl::Term(env.terms[t].clone()),
+        DeclKey::Thm(t) => Decl::Thm(env.thms[t].clone()),
+      }),
+      _ => None,
+    }).collect();
+    // The generated declarations are the last ones in `env`
+    let num_terms = env.terms.len() - decls.iter().filter(|d| matches!(d, Decl::Term(_))).count();
+    let num_thms = env.thms.len() - decls.iter().filter(|d| matches!(d, Decl::Thm(_))).count();
+    let num_sorts = env.sorts.len();
+    let mut refs = Refs::default();
+    decls.iter().for_each(|d| refs.decl(d));
+    let sorts = refs.sorts.into_iter().map(|s| (s, env.sorts[s].atom, env.sorts[s].mods)).collect();
+    let term_deps = refs.terms.into_iter().filter(|t| (t.0 as usize) < num_terms)
+      .map(|t| (t, TermStmt::new(&env.terms[t]))).collect();
+    let thm_deps = refs.thms.into_iter().filter(|t| (t.0 as usize) < num_thms)
+      .map(|t| (t, ThmStmt::new(&env.thms[t]))).collect();
+    let atoms = env.data.iter().map(|d| d.name.clone()).collect();
+    self.0 = Some(CachedProof {
+      code, name, atoms, num_sorts, num_terms, num_thms, sorts, term_deps, thm_deps, decls
+    })
+  }
+}
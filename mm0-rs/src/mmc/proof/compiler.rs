@@ -9,7 +9,7 @@ use std::marker::PhantomData;
 use mmcc::Idx;
 use mmcc::types::classify::TraceIter;
 use mmcc::types::mir::{Cfg, Contexts, CtxBufId, CtxId, ExprKind, Statement, Terminator, VarId,
-  LetKind, Ty, TyKind, RValue, Operand, Place, ConstKind, Constant};
+  LetKind, Ty, TyKind, RValue, Operand, Place, ConstKind, Constant, CastKind};
 use mmcc::types::vcode::{ProcAbi, ArgAbi};
 use mmcc::{Symbol, TEXT_START, types::{Size, IdxVec, classify as cl}};
 use mmcc::arch::{ExtMode, OpcodeLayout, PInst, PRegMemImm, Unop, RegMem};
@@ -17,13 +17,84 @@ use mmcc::proof::{AssemblyBlocks, AssemblyItem, AssemblyItemIter, BlockId, Block
   BlockProofTree, BlockTreeIter, ElfProof, Inst, InstIter, PReg, Proc, VBlockId, ProcId};
 use crate::LispVal;
 use crate::lisp::print::Print;
-use crate::{Elaborator, FileSpan, Modifiers, Span, TermId, ThmId, elab::Result, mmc::proof::Name};
+use crate::{Elaborator, ElabError, FileSpan, Modifiers, Span, TermId, ThmId, elab::Result,
+  mmc::proof::Name};
 
 use super::{Dedup, ExprDedup, Mangler, Predefs, ProofDedup, ProofId,
   norm_num::{HexCache, Num}, predefs::Rex};
 
-pub(super) fn mk_result<'a, D: Dedup<'a>>(de: &mut D, proof: &ElfProof<'_>) -> D::Id {
-  app!(de, (tyUnit)) // TODO
+/// Translates a MIR type to the corresponding `ty`, if it is supported by the proof generator.
+/// Currently only the propositional constants are supported.
+fn translate_ty<'a, D: Dedup<'a>>(de: &mut D, ty: &TyKind) -> Option<D::Id> {
+  Some(match ty {
+    TyKind::Unit => app!(de, (tyUnit)),
+    TyKind::True => app!(de, (tyTrue)),
+    TyKind::False => app!(de, (tyFalse)),
+    _ => return None,
+  })
+}
+
+/// Returns true if the operand is a proof by linear arithmetic ([`ConstKind::Linarith`]).
+fn is_linarith(o: &Operand) -> bool {
+  matches!(o.place(), Err(Constant { k: ConstKind::Linarith(_), .. }))
+}
+
+/// Returns true if any block of the procedure uses a proof by linear arithmetic.
+/// The certificates are not yet translated into MM0 proofs, so these procedures are
+/// rejected by [`compile_proof`] before any proof is built.
+fn uses_linarith(cfg: &Cfg) -> bool {
+  cfg.blocks().filter(|(_, bl)| !bl.is_dead()).any(|(_, bl)| {
+    bl.stmts.iter().any(|s| match s {
+      Statement::Assign(_, _, o, _) => is_linarith(o),
+      Statement::Let(_, _, _, rv) => match rv {
+        RValue::Use(o) | RValue::Unop(_, o) | RValue::Ghost(o) | RValue::Typeof(o) =>
+          is_linarith(o),
+        RValue::Binop(_, o1, o2) | RValue::Eq(_, _, o1, o2) => is_linarith(o1) || is_linarith(o2),
+        RValue::Cast(ck, o, _) => is_linarith(o) || match ck {
+          CastKind::Subtype(pf) | CastKind::Wand(Some(pf)) | CastKind::Mem(pf) => is_linarith(pf),
+          CastKind::Int | CastKind::Shr | CastKind::Wand(None) => false,
+        },
+        RValue::List(os) | RValue::Array(os) | RValue::Mm0(_, os) => os.iter().any(is_linarith),
+        RValue::Pun(..) | RValue::Borrow(_) => false,
+      },
+      _ => false,
+    }) || bl.term.as_ref().is_some_and(|t| match t {
+      Terminator::Jump(_, args, _) | Terminator::Return(_, args) =>
+        args.iter().any(|(_, _, o)| is_linarith(o)),
+      Terminator::Call { args, .. } => args.iter().any(|(_, o)| is_linarith(o)),
+      Terminator::Unreachable(o) | Terminator::Exit(o) |
+      Terminator::If(_, o, _) | Terminator::Assert(o, _, _) => is_linarith(o),
+      Terminator::Fail | Terminator::Jump1(_, _) | Terminator::Dead => false,
+    })
+  })
+}
+
+/// Describes the procedure for error messages.
+fn proc_desc(proc: &Proc<'_>) -> String {
+  proc.name().map_or_else(|| "the initializer".into(), |s| format!("procedure '{s}'"))
+}
+
+/// Constructs the exit proposition `T` of the global context, which must hold on any successful
+/// run of the program. This is the translation of the return type of `main`
+/// (see [`ElfProof::result_ty`]).
+pub(super) fn mk_result<'a, D: Dedup<'a>>(
+  de: &mut D, proof: &ElfProof<'_>, sp: Span,
+) -> Result<D::Id> {
+  let ty = proof.result_ty();
+  translate_ty(de, &ty).ok_or_else(|| ElabError::new_e(sp,
+    format!("mmc-finish: the return type of main is not supported by the proof generator: {ty:?}")))
+}
+
+/// Constructs the 8 byte little endian encoding of `val` as a `string`, as it appears in the
+/// ELF header.
+fn u64_string(de: &mut ProofDedup<'_>, hex: &HexCache, val: u64) -> ProofId {
+  let bytes = val.to_le_bytes();
+  let (&last, rest) = bytes.split_last().expect("impossible");
+  let mut s = app!(de, (s1 (ch {hex[last >> 4]} {hex[last & 15]})));
+  for &byte in rest.iter().rev() {
+    s = app!(de, (scons (ch {hex[byte >> 4]} {hex[byte & 15]}) s));
+  }
+  s
 }
 
 fn format_to_string(f: impl FnOnce(&mut String) -> std::fmt::Result) -> String {
@@ -50,18 +121,27 @@ struct Ctx {
 }
 
 impl Ctx {
-  fn new(de: &mut ProofDedup<'_>, hex: &HexCache, proc: &Proc<'_>, t_gctx: TermId) -> Ctx {
+  /// Constructs the procedure context. `abi` is `None` for the start procedure.
+  fn new(de: &mut ProofDedup<'_>,
+    hex: &HexCache, proc: &Proc<'_>, abi: Option<&ProcAbi>, t_gctx: TermId
+  ) -> Ctx {
     let ok0 = app!(de, (ok0));
     let asm0 = app!(de, (ASM0));
     let gctx = app!(de, ({t_gctx}));
-    let ret = app!(de, (ok0)); // TODO
-    let se = app!(de, (tru)); // TODO
+    let ret = match abi {
+      // The start procedure never returns (see `buildStart`)
+      Some(abi) if abi.reach => app!(de, (ok0)), // TODO: return ABI
+      _ => app!(de, (noRet)),
+    };
+    let se = if proc.side_effect() { app!(de, (tru)) } else { app!(de, (fal)) };
     let mut epi = app!(de, (epiRet));
-    for &reg in proc.saved_regs() {
-      epi = app!(de, epiPop[hex[reg.index()], epi]);
+    if abi.is_some() {
+      for &reg in proc.saved_regs() {
+        epi = app!(de, epiPop[hex[reg.index()], epi]);
+      }
     }
     let sp_max = hex.from_u32(de, proc.stack_size());
-    if sp_max.val != 0 { epi = app!(de, epiFree[*sp_max, epi]) }
+    if abi.is_some() && sp_max.val != 0 { epi = app!(de, epiFree[*sp_max, epi]) }
     let pctx1 = app!(de, mkPCtx1[ret, epi, se]);
     let pctx = app!(de, mkPCtx[gctx, pctx1]);
     let labs = app!(de, (labelGroup0));
@@ -482,9 +562,9 @@ trait NodeInsert<N: NodeKind> {
           let ctx = N::ctx_left(ctx, &ns.0, &ns.1);
           let (grew, th) = Self::insert(de, &mut ns.0, ctx, key, t);
           let rotate_right = grew && match bal {
-            Ordering::Less => { *bal = Ordering::Equal; false }
-            Ordering::Equal => { *bal = Ordering::Greater; false }
-            Ordering::Greater => true
+            Ordering::Less => true,
+            Ordering::Equal => { *bal = Ordering::Less; false }
+            Ordering::Greater => { *bal = Ordering::Equal; false }
           };
           if rotate_right {
             let MCtxNode::Node(bal1, _, ns1) = std::mem::take(&mut ns.0.0) else { unreachable!() };
@@ -520,15 +600,15 @@ trait NodeInsert<N: NodeKind> {
             }
           } else {
             let th = Self::node_lt(de, [a, b, et, ns.0.1, th]);
-            *n = N::node(de, &ns.0, &ns.1); (grew && *bal == Ordering::Greater, th)
+            *n = N::node(de, &ns.0, &ns.1); (grew && *bal == Ordering::Less, th)
           }
         } else {
           let ctx = N::ctx_right(ctx, &ns.0, &ns.1);
           let (grew, th) = Self::insert(de, &mut ns.1, ctx, key, t);
           let rotate_left = grew && match bal {
-            Ordering::Less => true,
-            Ordering::Equal => { *bal = Ordering::Less; false }
-            Ordering::Greater => { *bal = Ordering::Equal; false }
+            Ordering::Less => { *bal = Ordering::Equal; false }
+            Ordering::Equal => { *bal = Ordering::Greater; false }
+            Ordering::Greater => true
           };
           if rotate_left {
             let MCtxNode::Node(bal1, _, ns1) = std::mem::take(&mut ns.1.0) else { unreachable!() };
@@ -563,8 +643,8 @@ trait NodeInsert<N: NodeKind> {
               }
             }
           } else {
-            let th = Self::node_lt(de, [a, b, et, ns.0.1, th]);
-            *n = N::node(de, &ns.0, &ns.1); (grew && *bal == Ordering::Less, th)
+            let th = Self::node_gt(de, [a, b, et, ns.1.1, th]);
+            *n = N::node(de, &ns.0, &ns.1); (grew && *bal == Ordering::Greater, th)
           }
         }
       }
@@ -773,6 +853,8 @@ struct ProcProver<'a> {
   hex: HexCache,
   thm: ProofDedup<'a>,
   ctx: Ctx,
+  /// The span of the `mmc-finish` call, for error reporting
+  full: Span,
 }
 
 #[derive(Clone, Copy)]
@@ -849,19 +931,14 @@ impl<'a> ProcProver<'a> {
     });
     let mut l = tctx.mk(&mut self.thm);
     for (v, _, (e, ty)) in self.proc.cfg.ctxs.iter_range(base..bl.block().ctx) {
+      let ty = translate_ty(&mut self.thm, ty).unwrap_or_else(|| app!(self.thm, (ok0))); // TODO
       let (l2, th) = match e {
         Some(e) if match **e {
           ExprKind::Var(u) if u == v.k => false,
           ExprKind::Unit => false,
           _ => true,
-        } => {
-          let ty = app!(self.thm, (ok0)); // TODO
-          tctx.push_hyp(l, &mut self.thm, v.k, VarKind::Hyp, ty)
-        }
-        _ => {
-          let ty = app!(self.thm, (ok0)); // TODO
-          tctx.push_var(l, &mut self.thm, &self.hex, v.k, ty).1
-        }
+        } => tctx.push_hyp(l, &mut self.thm, v.k, VarKind::Hyp, ty),
+        _ => tctx.push_var(l, &mut self.thm, &self.hex, v.k, ty).1,
       };
       l = l2;
     }
@@ -884,35 +961,45 @@ impl<'a> ProcProver<'a> {
   /// * `(ip, |- okBlock bctx (suc ip) tctx)`
   /// * `(INVALID, |- okCode bctx tctx ASM0 ok0)` or
   /// for the given block, starting from the given `tctx`.
-  fn ok_block_opt(&mut self, (tctx, l1): P<&mut TCtx>, tgt: BlockId) -> (ProofId, ProofId) {
-    if let Some(vid) = self.proc.vblock_id(tgt) {
+  fn ok_block_opt(&mut self,
+    (tctx, l1): P<&mut TCtx>, tgt: BlockId
+  ) -> Result<(ProofId, ProofId)> {
+    Ok(if let Some(vid) = self.proc.vblock_id(tgt) {
       let (a, h1) = self.vblock_asm[&vid];
       app_match!(self.thm, let (asmAt ip code) = a);
-      let h2 = self.ok_stmts(self.proc.block(tgt), code, (tctx, l1));
+      let h2 = self.ok_stmts(self.proc.block(tgt), code, (tctx, l1))?;
       (ip, thm!(self.thm, ((okBlock {self.bctx} (suc ip) l1)) =>
         okBlockI(self.labs, code, ip, self.pctx, l1, h1, h2)))
     } else {
-      (ProofId::INVALID, self.ok_stmts(self.proc.block(tgt), self.asm0, (tctx, l1)))
-    }
+      (ProofId::INVALID, self.ok_stmts(self.proc.block(tgt), self.asm0, (tctx, l1))?)
+    })
   }
 
   /// Returns `(ip, |- okBlock bctx ip tctx)`
   /// for the given block, starting from the given `tctx`.
-  fn ok_block(&mut self, (tctx, l1): P<&mut TCtx>, tgt: BlockId) -> (ProofId, ProofId) {
-    let (ip, th) = self.ok_block_opt((tctx, l1), tgt);
-    if ip == ProofId::INVALID {
+  fn ok_block(&mut self, (tctx, l1): P<&mut TCtx>, tgt: BlockId) -> Result<(ProofId, ProofId)> {
+    let (ip, th) = self.ok_block_opt((tctx, l1), tgt)?;
+    Ok(if ip == ProofId::INVALID {
       let ip = app!(self.thm, (d0));
       (ip, thm!(self.thm, okBlock0(self.bctx, l1, th): okBlock[self.bctx, ip, l1]))
     } else {
       (app!(self.thm, (suc ip)), th)
-    }
+    })
+  }
+
+  /// The error for a part of the program that the proof generator does not support yet.
+  fn unsupported(&self, what: impl std::fmt::Display) -> ElabError {
+    ElabError::new_e(self.full, format!("mmc-finish: cannot prove {}: {what} \
+      is not yet supported by the proof generator", proc_desc(self.proc)))
   }
 
   /// Returns `|- okReadHyp tctx ty2` if `th: |- okReadHyp tctx ty`
-  fn read_hyp_coerce(&mut self, ty: ProofId, th: ProofId, ty2: ProofId) -> ProofId {
-    // TODO
-    assert!(ty == ty2, "failed to coerce {} to {}", self.pp(ty), self.pp(ty2));
-    th
+  fn read_hyp_coerce(&mut self, ty: ProofId, th: ProofId, ty2: ProofId) -> Result<ProofId> {
+    if ty != ty2 {
+      let what = format!("coercing {} to {}", self.pp(ty), self.pp(ty2));
+      return Err(self.unsupported(what))
+    }
+    Ok(th)
   }
 
   /// Returns `(ty, |- okReadHypVCtx vctx ty)`
@@ -929,9 +1016,9 @@ impl<'a> ProcProver<'a> {
   }
 
   /// Returns `(ty, |- okReadHypVCtx vctx ty)`
-  fn read_hyp_vctx_place(&mut self, vctx: &VCtx, p: &Place) -> (ProofId, ProofId) {
-    assert!(p.proj.is_empty()); // TODO
-    self.read_hyp_vctx_var(vctx, p.local)
+  fn read_hyp_vctx_place(&mut self, vctx: &VCtx, p: &Place) -> Result<(ProofId, ProofId)> {
+    if !p.proj.is_empty() { return Err(self.unsupported("reading a projection of a variable")) }
+    Ok(self.read_hyp_vctx_var(vctx, p.local))
   }
 
   /// Given `ty`, `|- okReadHypVCtx vctx ty`, returns `|- okReadHyp tctx ty`
@@ -941,26 +1028,32 @@ impl<'a> ProcProver<'a> {
   }
 
   /// Returns `(ty, |- okReadHyp tctx ty)`
-  fn read_hyp_operand(&mut self, (tctx, l1): P<&TCtx>, op: &Operand) -> (ProofId, ProofId) {
+  fn read_hyp_operand(&mut self,
+    (tctx, l1): P<&TCtx>, op: &Operand
+  ) -> Result<(ProofId, ProofId)> {
     match op.place() {
       Ok(p) => {
-        let (ty, th) = self.read_hyp_vctx_place(&tctx.vctx, p);
-        (ty, self.read_hyp_from_vctx(l1, ty, th))
+        let (ty, th) = self.read_hyp_vctx_place(&tctx.vctx, p)?;
+        Ok((ty, self.read_hyp_from_vctx(l1, ty, th)))
       }
-      Err(c) => match c.k {
-        ConstKind::Unit => {
-          let ty = app!(self.thm, (tyUnit));
-          (ty, thm!(self.thm, okReadHyp_unit(l1): okReadHyp[l1, ty]))
-        }
-        ConstKind::ITrue => todo!(),
-        ConstKind::Bool => todo!(),
-        ConstKind::Int => todo!(),
-        ConstKind::Uninit => todo!(),
-        ConstKind::Const(_) => todo!(),
-        ConstKind::Sizeof => todo!(),
-        ConstKind::Mm0Proof(_) => todo!(),
-        ConstKind::Contra(_, _) => todo!(),
-        ConstKind::As(_) => todo!(),
+      Err(c) => {
+        let what = match c.k {
+          ConstKind::Unit => {
+            let ty = app!(self.thm, (tyUnit));
+            return Ok((ty, thm!(self.thm, okReadHyp_unit(l1): okReadHyp[l1, ty])))
+          }
+          ConstKind::ITrue => "the constant `trivial`",
+          ConstKind::Bool => "a boolean constant",
+          ConstKind::Int => "an integer constant",
+          ConstKind::Uninit => "an uninitialized value",
+          ConstKind::Const(_) => "a named constant",
+          ConstKind::Sizeof => "`sizeof`",
+          ConstKind::Mm0Proof(_) => "an MM0 proof",
+          ConstKind::Linarith(_) => unreachable!("rejected by compile_proof"),
+          ConstKind::Contra(_, _) => "a proof by contradiction",
+          ConstKind::As(_) => "a cast",
+        };
+        Err(self.unsupported(what))
       }
     }
   }
@@ -968,12 +1061,13 @@ impl<'a> ProcProver<'a> {
   /// Returns `(args, mctx, |- accumArgs args vctx n)`
   fn accum_args(&mut self, vctx: &mut VCtx,
     bl_ctx: CtxId, abi: &[ArgAbi]
-  ) -> (ProofId, P<MCtx>, ProofId) {
+  ) -> Result<(ProofId, P<MCtx>, ProofId)> {
     let mut args = app!(self.thm, (arg0));
     let mut th = thm!(self.thm, accumArgs0(): (accumArgs args {vctx.e} {*vctx.nvars}));
     let mut mctx = MCtx::new(&mut self.thm);
     for ((v, _, (e, ty)), abi) in self.proc.cfg.ctxs.iter(..bl_ctx).zip(abi) {
       let (vctx1, n1) = (vctx.e, vctx.nvars);
+      let ty = translate_ty(&mut self.thm, ty).unwrap_or_else(|| app!(self.thm, (ok0))); // TODO
       let args2;
       th = match e {
         Some(e) if match **e {
@@ -981,7 +1075,6 @@ impl<'a> ProcProver<'a> {
           ExprKind::Unit => false,
           _ => true,
         } => {
-          let ty = app!(self.thm, (ok0)); // TODO
           let e = app!(self.thm, (vHyp ty));
           let h2 = vctx.push(&mut self.thm, v.k, VarKind::Hyp, e);
           args2 = app!(self.thm, (argS args (aHyp ty)));
@@ -998,11 +1091,9 @@ impl<'a> ProcProver<'a> {
               let t = app!(self.thm, REG[self.hex[r], var]);
               MCtx::push_reg::<NoProof>(&mut mctx, &mut self.thm, ((r, value), t));
             }
-            ArgAbi::Mem { .. } => todo!(),
-            ArgAbi::Boxed { .. } => todo!(),
-            ArgAbi::BoxedMem { .. } => todo!(),
+            ArgAbi::Mem { .. } | ArgAbi::Boxed { .. } | ArgAbi::BoxedMem { .. } =>
+              return Err(self.unsupported("an argument passed in memory")),
           }
-          let ty = app!(self.thm, (ok0)); // TODO
           let e = app!(self.thm, (vVar {*n1} ty));
           let h2 = vctx.push(&mut self.thm, v.k, VarKind::Var, e);
           let (n2, h3) = self.hex.suc(&mut self.thm, n1);
@@ -1014,7 +1105,7 @@ impl<'a> ProcProver<'a> {
       };
       args = args2;
     }
-    (args, mctx, th)
+    Ok((args, mctx, th))
   }
 
   /// Returns `(clob, |- accumClob clob mctx mctx2)`
@@ -1189,22 +1280,24 @@ impl<'a> ProcProver<'a> {
     ret: ProofId,
     clob: ProofId,
     rel: bool,
-  ) -> ProofId {
-    if !abi.args.is_empty() || !abi.rets.is_empty() { todo!() }
+  ) -> Result<ProofId> {
+    if !abi.args.is_empty() || !abi.rets.is_empty() {
+      return Err(self.unsupported("a call with arguments or return values"))
+    }
     let l1 = tctx.1;
     let l2 = tctx.1;
-    if rel {
+    Ok(if rel {
       let ret = app!(self.thm, applyCall[l1, args, ret, clob, l2]);
       thm!(self.thm, sorry(ret): ret) // TODO
     } else {
       let ret = app!(self.thm, applyCallG[l1, args, ret, l2]);
       thm!(self.thm, sorry(ret): ret) // TODO
-    }
+    })
   }
 
   /// Proves `|- okProc gctx start args ret clob se`,
   /// or `|- okStart gctx fs ms` for the start procedure
-  fn prove_proc(&mut self, root: VCtx) -> ProofId {
+  fn prove_proc(&mut self, root: VCtx) -> Result<ProofId> {
     let name = self.proc.name();
     let (asm, asmd_thm) = self.proc_asm[&self.proc.id];
     let (x, th) = self.thm.thm0(self.elab, asmd_thm);
@@ -1220,7 +1313,7 @@ impl<'a> ProcProver<'a> {
     if name.is_some() {
       let abi = self.elf_proof.proc_abi(self.proc.id.expect("not start"));
       let mut vctx = root;
-      let (args, mut mctx, h2) = self.accum_args(&mut vctx, bl.block().ctx, &abi.args);
+      let (args, mut mctx, h2) = self.accum_args(&mut vctx, bl.block().ctx, &abi.args)?;
       let (vctx1, sz1) = (vctx.e, *vctx.nvars);
       let mctx1 = mctx.1;
       let args2 = app!(self.thm, (mkArgs args mctx1));
@@ -1230,15 +1323,15 @@ impl<'a> ProcProver<'a> {
       let mctx3 = mctx.1;
       let tctx = &mut TCtx { vctx, mctx };
       let l2 = tctx.mk(&mut self.thm);
-      let h5 = self.ok_stmts(bl, code, (tctx, l2));
-      thm!(self.thm, (okProc[self.gctx, start, args2, self.ret, clob, self.se]) =>
+      let h5 = self.ok_stmts(bl, code, (tctx, l2))?;
+      Ok(thm!(self.thm, (okProc[self.gctx, start, args2, self.ret, clob, self.se]) =>
         okProcI(args, clob, code, self.epi, self.gctx, mctx1, mctx2, mctx3,
-          prol, self.ret, self.se, start, sz1, vctx1, h1, h2, h3, h4, h5))
+          prol, self.ret, self.se, start, sz1, vctx1, h1, h2, h3, h4, h5)))
     } else {
       let (fs, ms, (mut tctx, l1), h2) = self.build_start(bl, root);
-      let h3 = self.ok_stmts(bl, code, (&mut *tctx, l1));
-      thm!(self.thm, (okStart[self.gctx, *fs, *ms]) =>
-        okStartI(code, *fs, self.gctx, *ms, self.pctx, l1, h1, h2, h3))
+      let h3 = self.ok_stmts(bl, code, (&mut *tctx, l1))?;
+      Ok(thm!(self.thm, (okStart[self.gctx, *fs, *ms]) =>
+        okStartI(code, *fs, self.gctx, *ms, self.pctx, l1, h1, h2, h3)))
     }
   }
 }
@@ -1299,6 +1392,8 @@ struct BlockProofVisitor<'a, 'b> {
   arg_count: usize,
   /// Set at the end of the block
   out: ProofId,
+  /// The first error encountered. The rest of the block is skipped once this is set
+  err: Option<ElabError>,
 }
 
 impl<'a> Deref for BlockProofVisitor<'a, '_> {
@@ -1310,7 +1405,9 @@ impl<'a, 'b> DerefMut for BlockProofVisitor<'a, 'b> {
 }
 
 impl<'a> ProcProver<'a> {
-  fn ok_stmts(&mut self, bl: BlockProof<'a>, code: ProofId, tctx: P<&mut TCtx>) -> ProofId {
+  fn ok_stmts(&mut self,
+    bl: BlockProof<'a>, code: ProofId, tctx: P<&mut TCtx>
+  ) -> Result<ProofId> {
     // eprintln!("\n{:?}: {:?}", bl.id, bl.vblock().map(|bl| bl.insts));
     let n = bl.block().stmts.len();
     let mut visitor = BlockProofVisitor {
@@ -1326,15 +1423,28 @@ impl<'a> ProcProver<'a> {
       inst_state: InstState::None,
       arg_count: 0,
       out: ProofId::INVALID,
+      err: None,
     };
     if n != 0 { visitor.split() }
     bl.visit(&mut visitor);
+    if let Some(e) = visitor.err { return Err(e) }
     assert!(visitor.out != ProofId::INVALID, "{:?}", visitor.stack);
-    visitor.out
+    Ok(visitor.out)
   }
 }
 
 impl<'a, 'b> BlockProofVisitor<'a, 'b> {
+  /// Records the error `e`, if there is not already one.
+  fn fail(&mut self, e: ElabError) {
+    self.err.get_or_insert(e);
+  }
+
+  /// Records an error for a part of the program that is not yet supported.
+  fn fail_unsupported(&mut self, what: impl std::fmt::Display) {
+    let e = self.proc.unsupported(what);
+    self.fail(e)
+  }
+
   /// Assuming a `|- code1 +asm code2` proof obligation, pops this and
   /// adds two `|- code1`, `|- code2` proof obligations. (See `finish`)
   fn split(&mut self) {
@@ -1381,13 +1491,17 @@ impl<'a, 'b> BlockProofVisitor<'a, 'b> {
     inst: Option<&Inst<'a>>,
   ) {
     let proc = &mut *self.proc;
-    let proc_thm = *proc.proc_proof.get(&Some(f))
-      .unwrap_or_else(|| unimplemented!("recursive function"));
+    let Some(&proc_thm) = proc.proc_proof.get(&Some(f)) else {
+      return self.fail_unsupported("a recursive call")
+    };
     let (x, h1) = proc.thm.thm0(proc.elab, proc_thm);
     app_match!(proc.thm, let (okProc _ tgt args ret clob _) = x);
     let rel = inst.is_some();
     let l1 = self.tctx.1;
-    let h2 = proc.apply_call(&mut self.tctx, abi, args, ret, clob, rel);
+    let h2 = match proc.apply_call(&mut self.tctx, abi, args, ret, clob, rel) {
+      Ok(h2) => h2,
+      Err(e) => return self.fail(e),
+    };
     let l2 = self.tctx.1;
     let th = match (rel, se) {
       (true, true) => thm!(self.thm, (okCode[self.bctx, l1, self.code, l2]) =>
@@ -1408,7 +1522,10 @@ impl<'a, 'b> BlockProofVisitor<'a, 'b> {
   fn fallthrough(&mut self, tgt: BlockId) {
     let l1 = self.tctx.1;
     let bctx = self.bctx;
-    let (ip, mut th) = self.proc.ok_block_opt((self.tctx.0, l1), tgt);
+    let (ip, mut th) = match self.proc.ok_block_opt((self.tctx.0, l1), tgt) {
+      Ok(r) => r,
+      Err(e) => return self.fail(e),
+    };
     // eprintln!("returning to {:?}", self.block_id);
     if ip != ProofId::INVALID {
       th = thm!(self.thm, ok_jump(bctx, l1, ip, th): okCode[bctx, l1, self.code, self.ok0]);
@@ -1420,10 +1537,13 @@ impl<'a, 'b> BlockProofVisitor<'a, 'b> {
 
 impl<'a> cl::Visitor<'a> for BlockProofVisitor<'a, '_> {
   fn on_inst(&mut self, _: &TraceIter<'a>, spill: bool, inst: &Inst<'a>) {
+    if self.err.is_some() { return }
     if spill {
       match self.inst_state {
-        InstState::None => panic!("unexpected instruction: {:?}", inst.inst),
-        InstState::Skip => panic!("unexpected spill instruction"),
+        InstState::None => return self.fail_unsupported(
+          format_args!("the instruction {:?}", inst.inst)),
+        InstState::Skip => return self.fail_unsupported(
+          "a spill in the prologue or epilogue of a procedure"),
         _ => {}
       }
       self.split();
@@ -1431,7 +1551,8 @@ impl<'a> cl::Visitor<'a> for BlockProofVisitor<'a, '_> {
       self.finish(th);
     } else {
       match self.inst_state {
-        InstState::None => panic!("unexpected instruction: {:?}", inst.inst),
+        InstState::None =>
+          self.fail_unsupported(format_args!("the instruction {:?}", inst.inst)),
         InstState::StartSkip => self.inst_state = InstState::Skip,
         InstState::Skip => {}
         InstState::Call =>
@@ -1439,7 +1560,7 @@ impl<'a> cl::Visitor<'a> for BlockProofVisitor<'a, '_> {
             self.call(f, abi, args, reach, rets, se, Some(inst));
             self.inst_state = InstState::None
           } else { unreachable!() },
-        InstState::Move => todo!(),
+        InstState::Move => self.fail_unsupported("a copy between registers"),
         InstState::Fallthrough(tgt) => {
           self.fallthrough(tgt);
           self.inst_state = InstState::None
@@ -1449,6 +1570,7 @@ impl<'a> cl::Visitor<'a> for BlockProofVisitor<'a, '_> {
   }
 
   fn before_copy(&mut self, _: &TraceIter<'a>, cl: cl::Copy) {
+    if self.err.is_some() { return }
     if matches!(cl, cl::Copy::Two) { self.split() }
     self.inst_state = InstState::Move
   }
@@ -1467,6 +1589,7 @@ impl<'a> cl::Visitor<'a> for BlockProofVisitor<'a, '_> {
   fn before_epilogue(&mut self, _: &cl::TraceIter<'_>) { self.inst_state = InstState::StartSkip }
 
   fn before_stmt(&mut self, _: &TraceIter<'a>, stmt: &Statement, _: &cl::Statement) {
+    if self.err.is_some() { return }
     let mut n = self.stmts_in.pop().expect("underflow");
     while n > 1 {
       let m = n >> 1;
@@ -1474,29 +1597,24 @@ impl<'a> cl::Visitor<'a> for BlockProofVisitor<'a, '_> {
       self.split();
       n = m;
     }
-    self.stmt_state = match stmt {
-      Statement::Let(_, _, _, _) => todo!(),
-      Statement::Assign(_, _, _, _) => todo!(),
-      Statement::LabelGroup(_, _) => todo!(),
-      Statement::PopLabelGroup => todo!(),
-      Statement::DominatedBlock(_, _) => todo!(),
-    }
+    self.fail_unsupported(match stmt {
+      Statement::Let(_, _, _, _) => "a let statement",
+      Statement::Assign(_, _, _, _) => "an assignment",
+      Statement::LabelGroup(_, _) => "a label group",
+      Statement::PopLabelGroup => "the end of a label group",
+      Statement::DominatedBlock(_, _) => "a dominated block",
+    })
   }
 
-  fn after_stmt(&mut self, _: &TraceIter<'a>, stmt: &Statement, _: &cl::Statement) {
-    let th = match stmt {
-      Statement::Let(_, _, _, _) => todo!(),
-      Statement::Assign(_, _, _, _) => todo!(),
-      Statement::LabelGroup(_, _) => todo!(),
-      Statement::PopLabelGroup => todo!(),
-      Statement::DominatedBlock(_, _) => todo!(),
-    };
-    self.finish(th)
+  fn after_stmt(&mut self, _: &TraceIter<'a>, _: &Statement, _: &cl::Statement) {
+    // every statement is rejected by `before_stmt`
+    debug_assert!(self.err.is_some())
   }
 
   fn before_call_args(&mut self, _: &TraceIter<'a>,
     _: ProcId, _: &ProcAbi, args: &[(bool, Operand)]
   ) {
+    if self.err.is_some() { return }
     self.arg_count = args.len();
     if self.arg_count == 0 { self.finish_id() }
   }
@@ -1504,6 +1622,7 @@ impl<'a> cl::Visitor<'a> for BlockProofVisitor<'a, '_> {
   fn before_call_arg(&mut self, _: &TraceIter<'a>,
     _: bool, _: &'a Operand, _: &'a ArgAbi, _: &'a cl::Elem
   ) {
+    if self.err.is_some() { return }
     self.arg_count -= 1;
     if self.arg_count != 0 { self.split() }
   }
@@ -1511,7 +1630,7 @@ impl<'a> cl::Visitor<'a> for BlockProofVisitor<'a, '_> {
   fn after_call_arg(&mut self, _: &TraceIter<'a>,
     _: bool, _: &'a Operand, _: &'a ArgAbi, _: &'a cl::Elem
   ) {
-    self.finish(todo!());
+    self.fail_unsupported("passing an argument to a procedure")
   }
 
   fn after_call_args(&mut self, _: &TraceIter<'a>,
@@ -1521,30 +1640,34 @@ impl<'a> cl::Visitor<'a> for BlockProofVisitor<'a, '_> {
   fn before_call_retargs(&mut self, _: &TraceIter<'a>,
     _: ProcId, _: &'a ProcAbi, rets: &'a [(bool, VarId)],
   ) {
+    if self.err.is_some() { return }
     self.arg_count = rets.len();
     self.split();
     if self.arg_count == 0 { self.finish_id() }
   }
 
   fn before_call_retarg(&mut self, _: &TraceIter<'a>, _: cl::IntoMem, _: &'a ArgAbi) {
+    if self.err.is_some() { return }
     self.arg_count -= 1;
     if self.arg_count != 0 { self.split() }
   }
 
   fn after_call_retarg(&mut self, _: &TraceIter<'a>, _: cl::IntoMem, _: &'a ArgAbi) {
-    self.finish(todo!())
+    self.fail_unsupported("a return value passed in memory")
   }
 
   fn after_call_retargs(&mut self, _: &TraceIter<'a>,
     _: ProcId, _: &'a ProcAbi, _: &'a [(bool, VarId)],
-  ) { self.split() }
+  ) {
+    if self.err.is_none() { self.split() }
+  }
 
   fn before_call_ret(&mut self, _: &TraceIter<'a>, _: &'a ArgAbi, _: &'a cl::Elem) {
-    self.split()
+    if self.err.is_none() { self.split() }
   }
 
   fn after_call_ret(&mut self, _: &TraceIter<'a>, _: &'a ArgAbi, _: &'a cl::Elem) {
-    self.finish(todo!());
+    self.fail_unsupported("a return value of a procedure")
   }
 
   // override the main handling
@@ -1552,6 +1675,7 @@ impl<'a> cl::Visitor<'a> for BlockProofVisitor<'a, '_> {
     boxes: u8, retabi: &'a [ArgAbi], rets: &'a [(bool, VarId)], tgt: BlockId,
     it: &mut TraceIter<'a>,
   ) {
+    if self.err.is_some() { return }
     // eprintln!("before_call_rets");
     for (arg, &(vr, _)) in retabi.iter().zip(rets) {
       if !vr { continue }
@@ -1567,6 +1691,7 @@ impl<'a> cl::Visitor<'a> for BlockProofVisitor<'a, '_> {
     f: ProcId, abi: &'a ProcAbi, args: &'a [(bool, Operand)],
     reach: bool, rets: &'a [(bool, VarId)], se: bool, _: BlockId,
   ) {
+    if self.err.is_some() { return }
     self.stmt_state = StmtState::Call { f, abi, args, reach, rets, se };
     self.split();
   }
@@ -1581,14 +1706,13 @@ impl<'a> cl::Visitor<'a> for BlockProofVisitor<'a, '_> {
     _: &IdxVec<ProcId, ProcAbi>, _: Option<&[ArgAbi]>,
     term: &'a Terminator, _: &cl::Terminator
   ) {
+    if self.err.is_some() { return }
     match term {
-      Terminator::Jump(_, _, _) => todo!(),
-      Terminator::Jump1(_, _) => todo!(),
-      Terminator::Return(..) => {}
-      Terminator::Unreachable(_) => todo!(),
-      Terminator::If(_, _, _) => todo!(),
-      Terminator::Assert(_, _, _) => todo!(),
-      Terminator::Call { .. } => {}
+      Terminator::Jump(_, _, _) | Terminator::Jump1(_, _) => self.fail_unsupported("a jump"),
+      Terminator::Return(..) | Terminator::Call { .. } => {}
+      Terminator::Unreachable(_) => self.fail_unsupported("an unreachable block"),
+      Terminator::If(_, _, _) => self.fail_unsupported("a conditional branch"),
+      Terminator::Assert(_, _, _) => self.fail_unsupported("a runtime assertion"),
       Terminator::Fail |
       Terminator::Exit(_) => self.inst_state = InstState::StartSkip,
       Terminator::Dead => unreachable!(),
@@ -1599,10 +1723,12 @@ impl<'a> cl::Visitor<'a> for BlockProofVisitor<'a, '_> {
     _: &IdxVec<ProcId, ProcAbi>, _: Option<&[ArgAbi]>,
     term: &Terminator, cl: &cl::Terminator
   ) {
+    if self.err.is_some() { return }
     // let (l1, code) = self.stmt_in.take().expect("nesting fail");
     match term {
-      Terminator::Jump(_, _, _) => todo!(),
-      Terminator::Jump1(_, _) => todo!(),
+      Terminator::Jump(_, _, _) | Terminator::Jump1(_, _) | Terminator::Unreachable(_) |
+      Terminator::If(_, _, _) | Terminator::Assert(_, _, _) =>
+        unreachable!("rejected by before_terminator"),
       Terminator::Return(outs, args) => {
         assert!(!matches!(cl, cl::Terminator::Ghost), "ghost return not allowed, I think");
         self.inst_state = InstState::None;
@@ -1614,9 +1740,6 @@ impl<'a> cl::Visitor<'a> for BlockProofVisitor<'a, '_> {
           okEpilogue_E(proc.bctx, self.code, proc.epi, proc.ret, self.lhs_tctx, h1, h2, h3));
         self.finish(th)
       }
-      Terminator::Unreachable(_) => todo!(),
-      Terminator::If(_, _, _) => todo!(),
-      Terminator::Assert(_, _, _) => todo!(),
       Terminator::Fail => {
         let l1 = self.tctx.1;
         let th = thm!(self.thm, (okCode[self.bctx, l1, self.code, self.ok0]) =>
@@ -1628,8 +1751,11 @@ impl<'a> cl::Visitor<'a> for BlockProofVisitor<'a, '_> {
         let proc = &mut *self.proc;
         let (ty, h1) = proc.get_result();
         let l1 = self.tctx.1;
-        let (ty2, h2) = proc.read_hyp_operand((self.tctx.0, l1), op);
-        let h2 = proc.read_hyp_coerce(ty2, h2, ty);
+        let h2 = match proc.read_hyp_operand((self.tctx.0, l1), op)
+          .and_then(|(ty2, h2)| proc.read_hyp_coerce(ty2, h2, ty)) {
+          Ok(h2) => h2,
+          Err(e) => return self.fail(e),
+        };
         let th = thm!(proc.thm, (okCode[proc.bctx, l1, self.code, proc.ok0]) =>
           ok_exit(ty, proc.gctx, proc.labs, proc.pctx1, l1, h1, h2));
         self.finish(th)
@@ -1652,6 +1778,12 @@ pub(super) fn compile_proof(
 ) -> Result<()> {
   let mut proc_proof = HashMap::new();
   for proc in proof.proc_proofs() {
+    if uses_linarith(proc.cfg) {
+      return Err(ElabError::new_e(full, format!("mmc-finish: cannot prove {}: \
+        a proof by linear arithmetic (from a `map` or `fold`) is not yet supported \
+        by the proof generator",
+        proc_desc(&proc))))
+    }
     let mut thm = ProofDedup::new(pd, &[]);
     let hex = HexCache::new(&mut thm);
     let root = VCtx::root(&mut thm, &hex);
@@ -1663,12 +1795,13 @@ pub(super) fn compile_proof(
       vblock_asm: Default::default(),
       block_proof: Default::default(),
       elab,
-      ctx: Ctx::new(&mut thm, &hex, &proc, gctx),
+      ctx: Ctx::new(&mut thm, &hex, &proc, proc.id.map(|id| proof.proc_abi(id)), gctx),
       vctxs: vec![root()].into(),
       hex,
       thm,
+      full,
     };
-    let th = build.prove_proc(root());
+    let th = build.prove_proc(root())?;
     let (ok_thm, doc) = mangler.get_data(build.elab,
       proc.name().map_or(Name::StartOkThm, Name::ProcOkThm));
     let ok_thm = build.elab.env
@@ -1676,5 +1809,21 @@ pub(super) fn compile_proof(
       .map_err(|e| e.into_elab_error(full))?;
     proc_proof.insert(proc.id, ok_thm);
   }
+
+  // The program itself, `Foo := ELF_lit filesz memsz content`. The correctness theorem
+  // `Foo_valid: okProg Foo T` is not produced yet, because the `okProc` and `okStart` proofs
+  // above still rely on unproven lemmas.
+  let mut de = ExprDedup::new(pd, &[]);
+  let mut thm = ProofDedup::new(pd, &[]);
+  let hex = HexCache::new(&mut thm);
+  let u_gctx = thm.get_def0(&elab.env, gctx);
+  app_match!(thm, let (mkGCtx c _ _ _) = u_gctx);
+  let fss = u64_string(&mut thm, &hex, proof.p_filesz());
+  let mss = u64_string(&mut thm, &hex, proof.p_memsz());
+  let elf = app!(thm, (ELF_lit fss mss c));
+  let e = thm.to_expr(&mut de, elf);
+  let (prog, doc) = mangler.get_data(elab, Name::Program);
+  elab.env.add_term(de.build_def0(prog, Modifiers::empty(), span.clone(), full, Some(doc), e,
+    pd.string)).map_err(|e| e.into_elab_error(full))?;
   Ok(())
 }
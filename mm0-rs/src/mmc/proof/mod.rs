@@ -14,6 +14,7 @@ mod predefs;
 mod norm_num;
 mod assembler;
 mod compiler;
+mod cache;
 
 use std::collections::HashMap;
 use std::fmt::Display;
@@ -29,6 +30,7 @@ use crate::{ElabError, Elaborator, Environment, Expr, ExprNode,
 use crate::elab::proof::{self, IDedup, ProofKind};
 
 pub(crate) use predefs::Predefs;
+pub(crate) use cache::ProofCache;
 
 trait Dedup<'a>: std::ops::Deref<Target = &'a Predefs> {
   type Node;
@@ -317,6 +319,8 @@ enum Name {
   ProcOkThm(Symbol),
   /// `_start_ok: okStart foo_gctx <foo_start>`: the correctness proof for the `_start` entry point
   StartOkThm,
+  /// `foo: string`: the complete ELF file (not prefixed by `_mmc_`)
+  Program,
 }
 
 impl Display for Name {
@@ -332,6 +336,7 @@ impl Display for Name {
       Name::ProcAsmdThm(Some(proc)) => write!(f, "{proc}_asmd"),
       Name::ProcOkThm(proc) => write!(f, "{proc}_ok"),
       Name::StartOkThm => write!(f, "_start_ok"),
+      Name::Program => write!(f, "program"),
       Name::Content => write!(f, "content"),
       Name::GCtx => write!(f, "gctx"),
       Name::AsmdThm => write!(f, "asmd"),
@@ -353,7 +358,10 @@ impl Mangler {
     struct S<'a>(&'a str, Name);
     impl Display for S<'_> {
       fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "_mmc_{}_{}", self.0, self.1)
+        match self.1 {
+          Name::Program => write!(f, "{}", self.0),
+          _ => write!(f, "_mmc_{}_{}", self.0, self.1),
+        }
       }
     }
     S(self.module.as_str(), name)
@@ -434,26 +442,29 @@ impl Mangler {
         if the program jumps to location `start`, then the program will safely execute \
         and satisfy the global exit proposition (or fail).",
         ProcName(None), self.mangle(Name::GCtx)),
+
+      Name::Program => "The complete ELF file for the program, \
+        as a string literal `ELF_lit filesz memsz content`.".to_owned(),
     }
   }
 }
 
 pub(crate) fn render_proof(
   pd: &Predefs, elab: &mut Elaborator, sp: Span,
-  name: AtomId, proof: &ElfProof<'_>
+  name: AtomId, proof: &ElfProof<'_>, cache: &mut ProofCache
 ) -> Result<()> {
   let mangler = Mangler {
     module: elab.data[name].name.clone(),
   };
   let fsp = elab.fspan(sp);
+  if cache.replay(&mut elab.env, proof.key(), &mangler.module, &fsp, sp)? { return Ok(()) }
+  let start = elab.env.stmts.len();
   let mut proc_asm = HashMap::new();
   scope_ast_source(&elab.ast.source.clone(), || {
     let gctx = assembler::assemble_proof(elab, pd, &mut proc_asm, &mangler, proof, &fsp, sp)?;
-    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-      compiler::compile_proof(elab, pd, &proc_asm, &mangler, proof, &fsp, sp, gctx)
-      // Ok(())
-    })).unwrap_or_else(|e| Err(ElabError::new_e(sp, "panicked")))
+    compiler::compile_proof(elab, pd, &proc_asm, &mangler, proof, &fsp, sp, gctx)
   })?;
   // elab.report(ElabError::info(sp, format!("{:#?}", proof)));
+  cache.insert(&elab.env, proof.key(), mangler.module.clone(), start);
   Ok(())
 }
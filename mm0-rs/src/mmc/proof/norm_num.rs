@@ -1,7 +1,13 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use arrayvec::ArrayVec;
+use mm0_util::{AtomId, SortId, TermId};
 use mmcc::Idx;
+use num::{BigInt, ToPrimitive};
 
-use super::{Dedup, ProofDedup, ProofId, predefs::SplitBits};
+use crate::{DeclKey, Elaborator, LispKind, LispVal};
+use crate::elab::{lisp::{Uncons, eval::SResult}, proof::ProofHash};
+use super::{Dedup, Predefs, ProofDedup, ProofId, predefs::SplitBits};
 
 
 pub(super) struct HexCache<Id = ProofId> {
@@ -126,7 +132,6 @@ impl HexCache {
   }
 
   /// Returns `|- a < b` assuming `a < b`
-  #[allow(unused)]
   pub(super) fn lt(&self, de: &mut ProofDedup<'_>, x: Num, y: Num) -> ProofId {
     match (x.cases(de), y.cases(de)) {
       (NumKind::Hex(a, b), NumKind::Hex(c, d)) if a < c => {
@@ -148,14 +153,12 @@ impl HexCache {
   }
 
   /// Returns `|- a <= b` assuming `a <= b`
-  #[allow(unused)]
   pub(super) fn le(&self, de: &mut ProofDedup<'_>, x: Num, y: Num) -> ProofId {
     if x == y { thm!(de, leid(*x): {*x} <= {*y}) }
     else { thm!(de, ltlei(*x, *y, self.lt(de, x, y)): {*x} <= {*y}) }
   }
 
   /// Returns `|- a != b` assuming `a != b`
-  #[allow(unused)]
   pub(super) fn ne(&self, de: &mut ProofDedup<'_>, x: Num, y: Num) -> ProofId {
     if x < y { thm!(de, ltnei(*x, *y, self.lt(de, x, y)): {*x} != {*y}) }
     else { thm!(de, ltneri(*y, *x, self.lt(de, y, x)): {*x} != {*y}) }
@@ -229,6 +232,178 @@ impl HexCache {
     }
   }
 
+  /// Returns `(c, |- a * b = c)`
+  pub(super) fn mul(&self, de: &mut ProofDedup<'_>, x: Num, y: Num) -> (Num, ProofId) {
+    if x.val == 0 { return (x, thm!(de, mulx01(*y): ({*x} * {*y}) = {*x})) }
+    if y.val == 0 { return (y, thm!(de, mulx02(*x): ({*x} * {*y}) = {*y})) }
+    self.mul_nz(de, x, y)
+  }
+
+  /// Returns `(c, |- a * b = c)` assuming `a` and `b` are nonzero
+  fn mul_nz(&self, de: &mut ProofDedup<'_>, x: Num, y: Num) -> (Num, ProofId) {
+    if x.val == 1 { return (y, thm!(de, mulx11(*y): ({*x} * {*y}) = {*y})) }
+    if y.val == 1 { return (x, thm!(de, mulx12(*x): ({*x} * {*y}) = {*x})) }
+    match (x.cases(de), y.cases(de)) {
+      (_, NumKind::Hex(b, 0)) => {
+        let (c, p) = self.mul_nz(de, x, b);
+        let r = self.hex(de, c, 0);
+        (r, thm!(de, mul_b2(*x, *b, *c, p): ({*x} * {*y}) = {*r}))
+      }
+      (NumKind::Hex(a, 0), _) => {
+        let (c, p) = self.mul_nz(de, a, y);
+        let r = self.hex(de, c, 0);
+        (r, thm!(de, mul_b1(*a, *y, *c, p): ({*x} * {*y}) = {*r}))
+      }
+      (_, NumKind::Hex(b, c)) => {
+        let (d, p1) = self.mul_nz(de, x, b);
+        let ec = self.h2n(de, c);
+        let (e, p2) = self.mul(de, x, ec);
+        match e.cases(de) {
+          NumKind::Hex(e, f) => {
+            let (g, p3) = self.add(de, d, e);
+            let r = self.hex(de, g, f);
+            (r, thm!(de, mul_x2x(*x, *b, self[c], *d, *e, self[f], *g, p1, p2, p3):
+              ({*x} * {*y}) = {*r}))
+          }
+          NumKind::H2n(f) => {
+            let r = self.hex(de, d, f);
+            (r, thm!(de, mul_x20(*x, *b, self[c], *d, self[f], p1, p2): ({*x} * {*y}) = {*r}))
+          }
+        }
+      }
+      (NumKind::Hex(a, b), NumKind::H2n(_)) => {
+        let (d, p1) = self.mul_nz(de, a, y);
+        let eb = self.h2n(de, b);
+        let (e, p2) = self.mul(de, eb, y);
+        match e.cases(de) {
+          NumKind::Hex(e, f) => {
+            let (g, p3) = self.add(de, d, e);
+            let r = self.hex(de, g, f);
+            (r, thm!(de, mul_x1x(*a, self[b], *y, *d, *e, self[f], *g, p1, p2, p3):
+              ({*x} * {*y}) = {*r}))
+          }
+          NumKind::H2n(f) => {
+            let r = self.hex(de, d, f);
+            (r, thm!(de, mul_x10(*a, self[b], *y, *d, self[f], p1, p2): ({*x} * {*y}) = {*r}))
+          }
+        }
+      }
+      (NumKind::H2n(a), NumKind::H2n(b)) => {
+        let r = self.from_u8(de, a * b);
+        (r, thm!(de, decmuln[a][b](): ({*x} * {*y}) = {*r}))
+      }
+    }
+  }
+
+  /// Returns `i` if `e` is the digit `x[i]`
+  fn digit(de: &ProofDedup<'_>, e: ProofId) -> Option<u8> {
+    (0..16).find(|&i| de.is_app_of(e, de.xn[usize::from(i)]).is_some())
+  }
+
+  /// Returns `|- a = a2` given an optional proof from [`eval`](Self::eval)
+  fn eq_or_refl(de: &mut ProofDedup<'_>, a: ProofId, pa: Option<ProofId>) -> ProofId {
+    pa.unwrap_or_else(|| thm!(de, eqid(a): a = a))
+  }
+
+  /// Evaluates an expression built from numerals, the constants `d0, ..., d16`, `suc`, `+`
+  /// and `*`. Returns `(n, Some(|- e = n))`, or `(n, None)` if `e` is already the numeral `n`.
+  /// Fails with the subexpression that is not of this form or whose value does not fit in
+  /// a `u64`.
+  pub(super) fn eval(&self, de: &mut ProofDedup<'_>, e: ProofId
+  ) -> Result<(Num, Option<ProofId>), ProofId> {
+    if let Some(i) = (0..=16_u8).find(|&i| de.is_app_of(e, de.dn[usize::from(i)]).is_some()) {
+      let r = self.from_u8(de, i);
+      let p = if i < 16 {
+        let h = thm!(de, h2nn[i](): {*r} = e);
+        thm!(de, eqcomi(*r, e, h): e = {*r})
+      } else {
+        thm!(de, h2n10(): e = {*r})
+      };
+      return Ok((r, Some(p)))
+    }
+    app_match!(de, e => {
+      (h2n c) => match Self::digit(de, c) {
+        Some(i) => Ok((Num::new(i.into(), e), None)),
+        None => Err(e),
+      },
+      (hex a c) => {
+        let i = Self::digit(de, c).ok_or(e)?;
+        let (a2, pa) = self.eval(de, a)?;
+        if a2.val == 0 {
+          let r = self.h2n(de, i);
+          Ok((r, Some(match pa {
+            Some(pa) => thm!(de, hexeql0(a, c, pa): e = {*r}),
+            None => thm!(de, hex01(c): e = {*r}),
+          })))
+        } else {
+          if a2.val >> 60 != 0 { return Err(e) }
+          let r = self.hex(de, a2, i);
+          Ok((r, pa.map(|pa| thm!(de, hexeql(a, *a2, c, pa): e = {*r}))))
+        }
+      }
+      (suc (add a b)) => {
+        let ((a2, pa), (b2, pb)) = (self.eval(de, a)?, self.eval(de, b)?);
+        a2.val.checked_add(b2.val).and_then(|n| n.checked_add(1)).ok_or(e)?;
+        let (c, pc) = self.adc(de, true, a2, b2);
+        if pa.is_none() && pb.is_none() { return Ok((c, Some(pc))) }
+        let (pa, pb) = (Self::eq_or_refl(de, a, pa), Self::eq_or_refl(de, b, pb));
+        Ok((c, Some(thm!(de, adceql(a, *a2, b, *b2, *c, pa, pb, pc): e = {*c}))))
+      }
+      (suc a) => {
+        let (a2, pa) = self.eval(de, a)?;
+        if a2.val == u64::MAX { return Err(e) }
+        let (b, pb) = self.suc(de, a2);
+        Ok((b, Some(match pa {
+          Some(pa) => thm!(de, suceql(a, *a2, *b, pa, pb): e = {*b}),
+          None => pb,
+        })))
+      }
+      (add a b) => {
+        let ((a2, pa), (b2, pb)) = (self.eval(de, a)?, self.eval(de, b)?);
+        a2.val.checked_add(b2.val).ok_or(e)?;
+        let (c, pc) = self.add(de, a2, b2);
+        if pa.is_none() && pb.is_none() { return Ok((c, Some(pc))) }
+        let (pa, pb) = (Self::eq_or_refl(de, a, pa), Self::eq_or_refl(de, b, pb));
+        Ok((c, Some(thm!(de, addeql(a, *a2, b, *b2, *c, pa, pb, pc): e = {*c}))))
+      }
+      (mul a b) => {
+        let ((a2, pa), (b2, pb)) = (self.eval(de, a)?, self.eval(de, b)?);
+        a2.val.checked_mul(b2.val).ok_or(e)?;
+        let (c, pc) = self.mul(de, a2, b2);
+        if pa.is_none() && pb.is_none() { return Ok((c, Some(pc))) }
+        let (pa, pb) = (Self::eq_or_refl(de, a, pa), Self::eq_or_refl(de, b, pb));
+        Ok((c, Some(thm!(de, muleql(a, *a2, b, *b2, *c, pa, pb, pc): e = {*c}))))
+      }
+      _ => Err(e),
+    })
+  }
+
+  /// Returns a proof of `R a b` where `R` is one of `eq`, `lt`, `le` or `ne`, or `None` if it
+  /// is false. Fails like [`eval`](Self::eval).
+  pub(super) fn prove(&self, de: &mut ProofDedup<'_>, r: TermId, a: ProofId, b: ProofId
+  ) -> Result<Option<ProofId>, ProofId> {
+    let ((a2, pa), (b2, pb)) = (self.eval(de, a)?, self.eval(de, b)?);
+    if r == de.eq {
+      if a2 != b2 { return Ok(None) }
+      return Ok(Some(match (pa, pb) {
+        (Some(pa), Some(pb)) => thm!(de, eqtr4i(a, *a2, b, pa, pb): a = b),
+        (Some(pa), None) => pa,
+        (None, Some(pb)) => thm!(de, eqcomi(b, a, pb): a = b),
+        (None, None) => thm!(de, eqid(a): a = b),
+      }))
+    }
+    let (p, eql) = match a2.cmp(&b2) {
+      Ordering::Less if r == de.lt => (self.lt(de, a2, b2), de.lteql),
+      Ordering::Less | Ordering::Equal if r == de.le => (self.le(de, a2, b2), de.leeql),
+      Ordering::Less | Ordering::Greater if r == de.ne => (self.ne(de, a2, b2), de.neeql),
+      _ => return Ok(None),
+    };
+    if pa.is_none() && pb.is_none() { return Ok(Some(p)) }
+    let (pa, pb) = (Self::eq_or_refl(de, a, pa), Self::eq_or_refl(de, b, pb));
+    let res = de.app(r, &[a, b]);
+    Ok(Some(de.thm(eql, &[a, *a2, b, *b2, pa, pb, p], res)))
+  }
+
   pub(super) fn is_u64(de: &mut ProofDedup<'_>, a: ProofId) -> ProofId {
     let mut args = vec![];
     let mut x = a;
@@ -305,3 +480,121 @@ norm_split_bits! {
   fn split_bits_121, fn unsplit_bits_121(Sb121: 1, 2, 1);
   fn split_bits_1111, fn unsplit_bits_1111(Sb1111: 1, 1, 1, 1);
 }
+
+/// The predefs used by [`HexCache::prove`] and [`HexCache::eval`], which `norm-num` requires.
+const NORM_NUM_PREDEFS: &[&str] = &[
+  "eq", "ne", "lt", "le", "suc", "add", "mul", "dn", "xn", "h2n", "hex",
+  "h2nn", "h2n10", "hex01", "hexeql", "hexeql0",
+  "decsucx", "decsucxf", "decsucn", "decltn", "decltx1", "decltx2", "declt0x",
+  "decaddn", "decadcn", "add_xx0", "add_xx1", "add_0x0", "add_0x1", "add_x00", "add_x01",
+  "adc_xx0", "adc_xx1", "adc_0x0", "adc_0x1", "adc_x00", "adc_x01",
+  "decmuln", "mulx01", "mulx02", "mulx11", "mulx12",
+  "mul_b1", "mul_b2", "mul_x1x", "mul_x10", "mul_x2x", "mul_x20",
+  "suceql", "addeql", "adceql", "muleql", "lteql", "leeql", "neeql",
+  "ltlei", "ltnei", "ltneri", "leid", "eqid", "eqcomi", "eqtr4i",
+];
+
+/// The state of the `norm-num` tactic.
+struct NormNum<'a, 'b> {
+  elab: &'a mut Elaborator,
+  de: ProofDedup<'b>,
+  hex: HexCache,
+  /// The sort of `h2n x0`
+  nat: SortId,
+  /// The sort of `x0`
+  digit: SortId,
+}
+
+impl NormNum<'_, '_> {
+  /// Convert a lisp expression to an expression. A digit in a position where a number is
+  /// expected is coerced with `h2n`, and a lisp number is converted to a numeral.
+  fn expr(&mut self, e: &LispVal, sort: SortId) -> SResult<ProofId> {
+    if let Some(n) = e.as_int(BigInt::to_u64) {
+      let n = n.ok_or_else(|| format!("number out of range: {}", self.elab.print(e)))?;
+      return Ok(*self.hex.from_u64(&mut self.de, n))
+    }
+    let err = |this: &Self| format!("expected a closed term, got {}", this.elab.print(e));
+    let (a, args) = match e.as_atom() {
+      Some(a) => (a, vec![]),
+      None => {
+        let mut u = Uncons::from(e.clone());
+        let Some(a) = u.next().and_then(|a| a.as_atom()) else { return Err(err(self)) };
+        let args = (&mut u).collect::<Vec<_>>();
+        if !u.is_empty() { return Err(err(self)) }
+        (a, args)
+      }
+    };
+    let Some(DeclKey::Term(t)) = self.elab.data[a].decl else { return Err(err(self)) };
+    let td = &self.elab.terms[t];
+    if td.args.len() != args.len() { return Err(err(self)) }
+    let ret = td.ret.0;
+    let sorts = td.args.iter().map(|(_, ty)| ty.sort()).collect::<Vec<_>>();
+    let args = args.iter().zip(sorts).map(|(e, s)| self.expr(e, s)).collect::<SResult<Vec<_>>>()?;
+    let e = self.de.app(t, &args);
+    Ok(if ret == self.digit && sort == self.nat { app!(self.de, (h2n e)) } else { e })
+  }
+
+  /// Convert a proof to a proof pre-expression, in which the theorems are applied to all
+  /// their arguments explicitly.
+  fn proof(&mut self, p: ProofId) -> LispVal {
+    let ProofHash::Thm(t, ref args, _) = *self.de.get(p) else { unreachable!("not a theorem") };
+    let args = args.clone();
+    let td = &self.elab.thms[t];
+    let nargs = td.args.len();
+    let mut out = vec![LispVal::atom(AtomId::BANG), LispVal::atom(td.atom)];
+    for (i, &a) in args.iter().enumerate() {
+      let a = ProofId::from_usize(a);
+      out.push(if i < nargs { self.de.to_lisp(self.elab, a) } else { self.proof(a) })
+    }
+    LispVal::list(out)
+  }
+
+  fn print(&mut self, e: ProofId) -> String {
+    let e = self.de.to_lisp(self.elab, e);
+    self.elab.print(&e).to_string()
+  }
+}
+
+impl Elaborator {
+  /// Implementation of the `(norm-num cfg e)` builtin. If `e` is a goal `R a b` where `R`
+  /// is one of `eq`, `lt`, `le` or `ne`, returns a proof for it; otherwise `e` is
+  /// evaluated, returning `(e2 p)` where `e2` is the normal form of `e` and `p` is a
+  /// proof of `e = e2`.
+  pub fn norm_num(&mut self, cfg: &LispVal, e: &LispVal) -> SResult<LispVal> {
+    let map = cfg.unwrapped(|c| match c {
+      LispKind::AtomMap(map) => Ok(map.clone()),
+      _ => Err(format!("expected an atom map, got {}", self.print(c)))
+    })?;
+    let mut renames = HashMap::new();
+    for (&k, v) in map.iter().filter(|(_, v)| v.is_def()) {
+      let v = v.as_atom().ok_or_else(|| format!("expected an atom for '{}', got {}",
+        self.data[k].name, self.print(v)))?;
+      renames.insert(self.data[k].name.clone(), v);
+    }
+    let (pd, missing) = Predefs::with_names(self, |s| renames.get(s).copied());
+    if let Some((_, name)) = missing.iter().find(|(x, _)| NORM_NUM_PREDEFS.contains(x)) {
+      return Err(format!("{name} not found"))
+    }
+    let (nat, digit) = (self.terms[pd.h2n].ret.0, self.terms[pd.xn[0]].ret.0);
+    let mut de = ProofDedup::new(&pd, &[]);
+    let hex = HexCache::new(&mut de);
+    let mut nn = NormNum { elab: self, de, hex, nat, digit };
+    let e = nn.expr(e, nat)?;
+    let res = if let Some((r, &[a, b])) = [pd.eq, pd.lt, pd.le, pd.ne].into_iter()
+      .find_map(|r| Some((r, nn.de.is_app_of(e, r)?)))
+    {
+      let (a, b) = (nn.de.do_from_usize(a), nn.de.do_from_usize(b));
+      match nn.hex.prove(&mut nn.de, r, a, b) {
+        Ok(Some(p)) => Ok(nn.proof(p)),
+        Ok(None) => return Err(format!("goal is false: {}", nn.print(e))),
+        Err(e) => Err(e),
+      }
+    } else {
+      nn.hex.eval(&mut nn.de, e).map(|(n, p)| {
+        let p = HexCache::eq_or_refl(&mut nn.de, e, p);
+        LispVal::list([nn.de.to_lisp(nn.elab, *n), nn.proof(p)])
+      })
+    };
+    res.map_err(|e| format!("cannot evaluate: {}", nn.print(e)))
+  }
+}
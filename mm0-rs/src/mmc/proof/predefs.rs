@@ -1,6 +1,6 @@
-use mm0_util::{SortId, TermId, ThmId};
+use mm0_util::{AtomId, SortId, TermId, ThmId};
 
-use crate::{DeclKey, Environment};
+use crate::{AtomData, DeclKey, Environment};
 
 fn mk_array<A, const N: usize>(mut f: impl FnMut(usize) -> A) -> [A; N] {
   let mut i = 0_usize;
@@ -15,44 +15,63 @@ fn mk_remap<'a, A, const N: usize>(
   [(); N].map(|_| { let a = f(i, &arr[i]); i += 1; a })
 }
 
-fn get_sort(env: &Environment, s: &[u8]) -> SortId {
-  if_chain! {
-    if let Some(&a) = env.atoms.get(s);
-    if let Some(sort) = env.data[a].sort;
-    then { sort }
-    else { panic!("sort {} not found", std::str::from_utf8(s).expect("utf8")) }
-  }
+/// Looks up the names of the predefs in an environment, for [`Predefs::with_names`].
+struct Lookup<'a, F> {
+  env: &'a mut Environment,
+  rename: F,
+  missing: Vec<(&'static str, String)>,
 }
-fn get_term(env: &Environment, s: &[u8]) -> TermId {
-  if_chain! {
-    if let Some(&a) = env.atoms.get(s);
-    if let Some(DeclKey::Term(t)) = env.data[a].decl;
-    then { t }
-    else { panic!("term {} not found", std::str::from_utf8(s).expect("utf8")) }
+
+impl<F: FnMut(&[u8]) -> Option<AtomId>> Lookup<'_, F> {
+  fn atom(&mut self, s: &[u8]) -> AtomId {
+    match (self.rename)(s) { Some(a) => a, None => self.env.get_atom(s) }
   }
-}
-fn get_thm(env: &Environment, s: &[u8]) -> ThmId {
-  if_chain! {
-    if let Some(&a) = env.atoms.get(s);
-    if let Some(DeclKey::Thm(t)) = env.data[a].decl;
-    then { t }
-    else { panic!("thm {} not found", std::str::from_utf8(s).expect("utf8")) }
+
+  fn get<T>(&mut self, field: &'static str, kind: &str, s: &[u8], invalid: T,
+    f: impl FnOnce(&AtomData) -> Option<T>
+  ) -> T {
+    let a = (self.rename)(s).or_else(|| self.env.atoms.get(s).copied());
+    if let Some(t) = a.and_then(|a| f(&self.env.data[a])) { return t }
+    let name = match a {
+      Some(a) => self.env.data[a].name.to_string(),
+      None => String::from_utf8_lossy(s).into(),
+    };
+    self.missing.push((field, format!("{kind} {name}")));
+    invalid
+  }
+
+  fn sort(&mut self, field: &'static str, s: &[u8]) -> SortId {
+    self.get(field, "sort", s, SortId::INVALID, |d| d.sort)
+  }
+
+  fn term(&mut self, field: &'static str, s: &[u8]) -> TermId {
+    self.get(field, "term", s, TermId::INVALID, |d| match d.decl {
+      Some(DeclKey::Term(t)) => Some(t),
+      _ => None,
+    })
+  }
+
+  fn thm(&mut self, field: &'static str, s: &[u8]) -> ThmId {
+    self.get(field, "thm", s, ThmId::INVALID, |d| match d.decl {
+      Some(DeclKey::Thm(t)) => Some(t),
+      _ => None,
+    })
   }
 }
 
 macro_rules! make_predefs {
   (@ty $ty:tt $n:expr, $($ns:expr,)*) => {[make_predefs!(@ty $ty $($ns,)*); $n]};
   (@ty $ty:ident) => {$ty};
-  (@new $ty:tt $env:expr, ($i:ident, $($is:ident,)*) $cond:tt $e:expr) => {
-    mk_array(|$i| make_predefs!(@new $ty $env, ($($is,)*) $cond $e))
+  (@new $ty:tt $l:expr, $x:ident, ($i:ident, $($is:ident,)*) $cond:tt $e:expr) => {
+    mk_array(|$i| make_predefs!(@new $ty $l, $x, ($($is,)*) $cond $e))
   };
-  (@new $ty:ident $env:expr, () ($cond:expr) $e:expr) => {
-    if $cond { make_predefs!(@new $ty $env, () () $e) } else { $ty::INVALID }
+  (@new $ty:ident $l:expr, $x:ident, () ($cond:expr) $e:expr) => {
+    if $cond { make_predefs!(@new $ty $l, $x, () () $e) } else { $ty::INVALID }
   };
-  (@new AtomId $env:expr, () () $e:expr) => { $env.get_atom($e) };
-  (@new SortId $env:expr, () () $e:expr) => { get_sort($env, $e) };
-  (@new TermId $env:expr, () () $e:expr) => { get_term($env, $e) };
-  (@new ThmId $env:expr, () () $e:expr) => { get_thm($env, $e) };
+  (@new AtomId $l:expr, $x:ident, () () $e:expr) => { $l.atom($e) };
+  (@new SortId $l:expr, $x:ident, () () $e:expr) => { $l.sort(stringify!($x), $e) };
+  (@new TermId $l:expr, $x:ident, () () $e:expr) => { $l.term(stringify!($x), $e) };
+  (@new ThmId $l:expr, $x:ident, () () $e:expr) => { $l.thm(stringify!($x), $e) };
   (@remap $ty:tt $self:expr, $r:expr, ($i:ident, $($is:ident,)*) $cond:tt) => {
     mk_remap($self, |$i, this| make_predefs!(@remap $ty this, $r, ($($is,)*) $cond))
   };
@@ -84,8 +103,23 @@ macro_rules! make_predefs {
     impl Predefs {
       /// Construct a `Predefs` from an environment.
       pub(crate) fn new(env: &mut crate::Environment) -> Self {
+        let (pd, missing) = Self::with_names(env, |_| None);
+        if let Some((_, name)) = missing.first() { panic!("{name} not found") }
+        pd
+      }
+
+      /// Construct a `Predefs` from an environment, where `rename` can replace the name that
+      /// a predef is looked up under. The predefs that are not found are set to `INVALID`, and
+      /// are returned as pairs of the field name and a description of what is missing.
+      pub(crate) fn with_names(env: &mut crate::Environment,
+        rename: impl FnMut(&[u8]) -> Option<AtomId>,
+      ) -> (Self, Vec<(&'static str, String)>) {
+        let mut l = Lookup { env, rename, missing: vec![] };
         #[allow(clippy::string_lit_as_bytes)]
-        Self { $($x: make_predefs!(@new $ty env, ($($i,)*) ($($cond)?) $e.as_bytes())),* }
+        let pd = Self {
+          $($x: make_predefs!(@new $ty l, $x, ($($i,)*) ($($cond)?) $e.as_bytes())),*
+        };
+        (pd, l.missing)
       }
     }
   };
@@ -187,6 +221,50 @@ make_predefs! {
   adc_x00: ThmId => "adc_x00";
   adc_x01: ThmId => "adc_x01";
 
+  /// `decmul[0..=f][0..=f]: x[a] * x[b] = hex(a*b)`
+  decmuln[a: 16][b: 16]: ThmId => format!("decmul{a:x}{b:x}");
+
+  // Theorems to compute `a * b = c`
+  /// `mulx01: $ x0 * a = x0 $`
+  mulx01: ThmId => "mulx01";
+  /// `mulx02: $ a * x0 = x0 $`
+  mulx02: ThmId => "mulx02";
+  /// `mulx11: $ x1 * a = a $`
+  mulx11: ThmId => "mulx11";
+  /// `mulx12: $ a * x1 = a $`
+  mulx12: ThmId => "mulx12";
+  mul_b1: ThmId => "mul_b1";
+  mul_b2: ThmId => "mul_b2";
+  mul_x1x: ThmId => "mul_x1x";
+  mul_x10: ThmId => "mul_x10";
+  mul_x2x: ThmId => "mul_x2x";
+  mul_x20: ThmId => "mul_x20";
+
+  // Theorems to evaluate subterms, and to prove `a = b`
+  /// `h2n10: $ 16 = x1 :x x0 $`
+  h2n10: ThmId => "h2n10";
+  /// `hex01: $ x0 :x a = a $`
+  hex01: ThmId => "hex01";
+  /// `hexeql (ha: $ a = a2 $): $ a :x b = a2 :x b $`
+  hexeql: ThmId => "hexeql";
+  /// `hexeql0 (ha: $ a = x0 $): $ a :x b = b $`
+  hexeql0: ThmId => "hexeql0";
+  /// `suceql (ha: $ a = a2 $) (h: $ suc a2 = b $): $ suc a = b $`
+  suceql: ThmId => "suceql";
+  addeql: ThmId => "addeql";
+  adceql: ThmId => "adceql";
+  muleql: ThmId => "muleql";
+  /// `lteql (ha: $ a = a2 $) (hb: $ b = b2 $) (h: $ a2 < b2 $): $ a < b $`
+  lteql: ThmId => "lteql";
+  leeql: ThmId => "leeql";
+  neeql: ThmId => "neeql";
+  /// `eqid: $ a = a $`
+  eqid: ThmId => "eqid";
+  /// `eqcomi (h: $ a = b $): $ b = a $`
+  eqcomi: ThmId => "eqcomi";
+  /// `eqtr4i (h1: $ a = b $) (h2: $ c = b $): $ a = c $`
+  eqtr4i: ThmId => "eqtr4i";
+
   /// `bit: nat > nat > nat`
   bit: TermId => "bit";
   xbit[n: 16][i: 4]: ThmId => format!("xbit{n:x}{i:x}");
@@ -501,6 +579,9 @@ make_predefs! {
   parseAssert: ThmId => "parseAssert";
 
   tyUnit: TermId => "tyUnit";
+  tyTrue: TermId => "tyTrue";
+  tyFalse: TermId => "tyFalse";
+  noRet: TermId => "noRet";
 
   eVar: TermId => "eVar";
 
@@ -613,6 +694,9 @@ make_predefs! {
   okStart: TermId => "okStart";
   okStartI: ThmId => "okStartI";
 
+  /// `ELF_lit (fs ms c: string): string`
+  ELF_lit: TermId => "ELF_lit";
+
   okBlock: TermId => "okBlock";
   okBlock_weak: ThmId => "okBlock_weak";
   okBlockI: ThmId => "okBlockI";
@@ -0,0 +1,1437 @@
+//! A C-like surface syntax for MMC, used by the `mm0-rs mmc` command.
+//!
+//! The items of a `.mmc` file are parsed directly into the MMC [`ast`], resolving names using
+//! the same rules as the lisp [`parser`](super::parser), which provides the resolution of calls
+//! and type constructors through the [`Resolve`] trait. Each item is typechecked before the next
+//! one is parsed, so that later items can use the names declared by earlier ones.
+//! See [`mmc.md`] for a description of the syntax.
+//!
+//! [`mmc.md`]: https://github.com/digama0/mm0/blob/master/mm0-rs/mmc.md
+
+use std::{collections::HashMap, mem, ops::{Deref, DerefMut}};
+use num::BigInt;
+use mm0_util::BoxError;
+use mm1_parser::ast::Formula;
+use mmcc::{Symbol, intern};
+use mmcc::build_ast::{BadBinding, BuildAst, BuildMatch, Incomplete, Pattern, PatternBuilder,
+  Renames, UnreachablePattern};
+use mmcc::types::{Binop, FieldName, IdxVec, LambdaId, Mm0Expr, Spanned, Unop, VarId};
+use mmcc::types::entity::{Entity, Prim, IntrinsicConst, IntrinsicGlobal, IntrinsicProc,
+  IntrinsicType};
+#[allow(clippy::wildcard_imports)] use mmcc::types::ast::{self, *};
+use crate::{AtomId, Elaborator, ElabError, FileSpan, LispVal, Span, Type as EType, elab::Result};
+use crate::elab::math_parser::{QExpr, QExprKind};
+use super::Config;
+use super::parser::{Keyword, Mm0ExprNode, PArgAttr, ProcSig, Resolve, get_intrinsic};
+
+/// The kind of a [`Token`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Tok {
+  /// An identifier or keyword. The text is in the span of the token.
+  Ident,
+  /// A number or character literal.
+  Num(BigInt),
+  /// A string literal, with escapes already processed.
+  Str(Vec<u8>),
+  /// A math formula `$ e $`. The span includes the delimiters.
+  Formula,
+  /// A punctuation token, one of [`PUNCT`].
+  Punct(&'static str),
+  /// The end of the file.
+  Eof,
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+  span: Span,
+  k: Tok,
+}
+
+/// The punctuation tokens. Longer tokens come first, so that the first match is the longest.
+const PUNCT: &[&str] = &[
+  "=>", "==", "!=", "<=", ">=", "<<", ">>", "&&", "||", "->", "<-",
+  "(", ")", "[", "]", "{", "}", ",", ";", ":", "=", "<", ">",
+  "+", "-", "*", "&", "|", "^", "!", "~", ".", "?",
+];
+
+/// Words that cannot be used as names.
+const RESERVED: &[&str] = &[
+  "as", "break", "const", "continue", "else", "false", "fold", "func", "global", "if", "import",
+  "inline", "intrinsic", "label", "let", "map", "match", "proc", "return", "struct", "true",
+  "typedef", "variant", "while", "with",
+];
+
+/// The binary operators, with their precedence (higher binds tighter).
+const BINOPS: &[(&str, u8)] = &[
+  ("||", 1), ("&&", 2),
+  ("==", 3), ("!=", 3), ("<", 3), ("<=", 3), (">", 3), (">=", 3),
+  ("|", 4), ("^", 5), ("&", 6), ("<<", 7), (">>", 7),
+  ("+", 8), ("-", 8), ("*", 9),
+];
+
+/// The precedence of bitwise or, the loosest operator allowed in a `variant` clause
+/// (because it is followed by an optional `< bound`).
+const PREC_BOR: u8 = 4;
+
+fn escape(src: &[u8], idx: usize) -> Result<(u8, usize)> {
+  let err = || ElabError::new_e(Span::from(idx..idx + 2), "invalid escape sequence");
+  Ok(match src.get(idx + 1) {
+    Some(b'n') => (b'\n', idx + 2),
+    Some(b't') => (b'\t', idx + 2),
+    Some(b'r') => (b'\r', idx + 2),
+    Some(b'0') => (0, idx + 2),
+    Some(&c @ (b'\\' | b'\'' | b'"')) => (c, idx + 2),
+    Some(b'x') => {
+      let s = src.get(idx + 2..idx + 4).ok_or_else(err)?;
+      let s = std::str::from_utf8(s).map_err(|_| err())?;
+      (u8::from_str_radix(s, 16).map_err(|_| err())?, idx + 4)
+    }
+    _ => return Err(err()),
+  })
+}
+
+/// Read the token starting at or after `idx`, skipping whitespace and comments.
+fn lex(src: &[u8], mut idx: usize) -> Result<Token> {
+  loop {
+    match src.get(idx) {
+      Some(c) if c.is_ascii_whitespace() => idx += 1,
+      Some(b'/') if src.get(idx + 1) == Some(&b'/') =>
+        while src.get(idx).is_some_and(|&c| c != b'\n') { idx += 1 },
+      Some(b'/') if src.get(idx + 1) == Some(&b'*') => {
+        let start = idx;
+        idx += 2;
+        loop {
+          match src.get(idx) {
+            None => return Err(ElabError::new_e(Span::from(start..idx), "unclosed comment")),
+            Some(b'*') if src.get(idx + 1) == Some(&b'/') => { idx += 2; break }
+            _ => idx += 1,
+          }
+        }
+      }
+      _ => break
+    }
+  }
+  let start = idx;
+  let Some(&c) = src.get(idx) else {
+    return Ok(Token { span: (idx..idx).into(), k: Tok::Eof })
+  };
+  let err = |idx: usize, msg| ElabError::new_e(Span::from(start..idx), msg);
+  let k = match c {
+    b'a'..=b'z' | b'A'..=b'Z' | b'_' => {
+      while src.get(idx).is_some_and(|&c| c.is_ascii_alphanumeric() || c == b'_') { idx += 1 }
+      Tok::Ident
+    }
+    b'0'..=b'9' => {
+      let radix = if c == b'0' && matches!(src.get(idx + 1), Some(b'x' | b'X')) {
+        idx += 2;
+        16
+      } else { 10 };
+      let digits = idx;
+      while src.get(idx).is_some_and(|&c| (c as char).is_digit(radix)) { idx += 1 }
+      if src.get(idx).is_some_and(|&c| c.is_ascii_alphanumeric() || c == b'_') {
+        return Err(err(idx + 1, "invalid number literal"))
+      }
+      Tok::Num(BigInt::parse_bytes(&src[digits..idx], radix)
+        .ok_or_else(|| err(idx, "invalid number literal"))?)
+    }
+    b'"' => {
+      idx += 1;
+      let mut s = vec![];
+      loop {
+        match src.get(idx) {
+          None | Some(b'\n') => return Err(err(idx, "unclosed string literal")),
+          Some(b'"') => { idx += 1; break }
+          Some(b'\\') => { let (c, i) = escape(src, idx)?; s.push(c); idx = i }
+          Some(&c) => { s.push(c); idx += 1 }
+        }
+      }
+      Tok::Str(s)
+    }
+    b'\'' => {
+      let c = match src.get(idx + 1) {
+        Some(b'\\') => { let (c, i) = escape(src, idx + 1)?; idx = i; c }
+        Some(&c) if c != b'\'' && c != b'\n' => { idx += 2; c }
+        _ => return Err(err(idx + 1, "invalid character literal")),
+      };
+      if src.get(idx) != Some(&b'\'') { return Err(err(idx, "unclosed character literal")) }
+      idx += 1;
+      Tok::Num(c.into())
+    }
+    b'$' => {
+      idx += 1;
+      while src.get(idx).is_some_and(|&c| c != b'$') { idx += 1 }
+      if idx == src.len() { return Err(err(idx, "unclosed math formula")) }
+      idx += 1;
+      Tok::Formula
+    }
+    _ => {
+      let p = PUNCT.iter().find(|p| src[idx..].starts_with(p.as_bytes()))
+        .ok_or_else(|| err(idx + 1, "unexpected character"))?;
+      idx += p.len();
+      Tok::Punct(p)
+    }
+  };
+  Ok(Token { span: (start..idx).into(), k })
+}
+
+/// A token stream over the source, with one token of lookahead.
+#[derive(Clone)]
+pub(crate) struct Lexer<'a> {
+  src: &'a [u8],
+  /// The current (not yet consumed) token.
+  tok: Token,
+  /// The end of the last consumed token.
+  last_end: usize,
+}
+
+impl<'a> Lexer<'a> {
+  pub(crate) fn new(src: &'a [u8], idx: usize) -> Result<Self> {
+    Ok(Self { src, tok: lex(src, idx)?, last_end: idx })
+  }
+
+  fn text(&self, sp: Span) -> &'a [u8] { &self.src[sp.start..sp.end] }
+
+  fn bump(&mut self) -> Result<Token> {
+    let next = lex(self.src, self.tok.span.end)?;
+    self.last_end = self.tok.span.end;
+    Ok(mem::replace(&mut self.tok, next))
+  }
+
+  fn err(&self, msg: impl Into<BoxError>) -> ElabError { ElabError::new_e(self.tok.span, msg) }
+
+  fn is(&self, p: &str) -> bool { matches!(self.tok.k, Tok::Punct(q) if q == p) }
+
+  fn is_kw(&self, kw: &str) -> bool {
+    self.tok.k == Tok::Ident && self.text(self.tok.span) == kw.as_bytes()
+  }
+
+  fn eat(&mut self, p: &str) -> Result<bool> {
+    Ok(if self.is(p) { self.bump()?; true } else { false })
+  }
+
+  fn eat_kw(&mut self, kw: &str) -> Result<bool> {
+    Ok(if self.is_kw(kw) { self.bump()?; true } else { false })
+  }
+
+  fn expect(&mut self, p: &str) -> Result<Span> {
+    if self.is(p) { Ok(self.bump()?.span) } else { Err(self.err(format!("expected '{p}'"))) }
+  }
+
+  /// True if the current token is an identifier followed by `:`.
+  fn at_name_colon(&self) -> Result<bool> {
+    Ok(self.tok.k == Tok::Ident && lex(self.src, self.tok.span.end)?.k == Tok::Punct(":"))
+  }
+
+  /// Skip to the first token at bracket depth zero which satisfies `stop`, or which is an
+  /// unmatched closing bracket.
+  fn skip_until(&mut self, stop: impl Fn(&Self) -> bool) -> Result<()> {
+    let mut depth = 0_u32;
+    loop {
+      match self.tok.k {
+        Tok::Eof => return Ok(()),
+        _ if depth == 0 && stop(self) => return Ok(()),
+        Tok::Punct("(" | "[" | "{") => depth += 1,
+        Tok::Punct(")" | "]" | "}") if depth == 0 => return Ok(()),
+        Tok::Punct(")" | "]" | "}") => depth -= 1,
+        _ => {}
+      }
+      self.bump()?;
+    }
+  }
+
+  /// Skip a bracketed group, starting at the opening bracket `open`.
+  fn skip_group(&mut self, open: &str, close: &str) -> Result<()> {
+    self.expect(open)?;
+    self.skip_until(|_| false)?;
+    self.expect(close)?;
+    Ok(())
+  }
+
+  /// Skip a binding pattern, returning the spans of the names it binds (other than `_`).
+  /// Type ascriptions inside a tuple pattern are skipped as well.
+  fn skip_pattern(&mut self, names: &mut Vec<Span>) -> Result<()> {
+    if self.is_kw("ghost") {
+      self.bump()?;
+      self.skip_pattern(names)
+    } else if self.eat("(")? {
+      while !self.is(")") {
+        self.skip_pattern(names)?;
+        self.skip_until(|l| l.is(","))?;
+        if !self.eat(",")? { break }
+      }
+      self.expect(")")?;
+      Ok(())
+    } else {
+      let sp = self.name()?;
+      if self.text(sp) != b"_" { names.push(sp) }
+      Ok(())
+    }
+  }
+
+  /// Consume a name (a non-reserved identifier).
+  fn name(&mut self) -> Result<Span> {
+    if self.tok.k == Tok::Ident {
+      let s = self.text(self.tok.span);
+      if !RESERVED.iter().any(|r| r.as_bytes() == s) { return Ok(self.bump()?.span) }
+    }
+    Err(self.err("expected a name"))
+  }
+}
+
+/// An `import "file";` line: the span of the statement and the file name.
+pub(crate) type Import = (Span, Vec<u8>);
+
+/// Parse the `import "file";` lines at the start of a `.mmc` file. Returns the list of
+/// imports (with the span of each import statement) and the position of the first item.
+pub(crate) fn parse_imports(src: &[u8]) -> Result<(Vec<Import>, usize)> {
+  let mut lex = Lexer::new(src, 0)?;
+  let mut imports = vec![];
+  while lex.is_kw("import") {
+    let start = lex.bump()?.span.start;
+    let Tok::Str(s) = lex.tok.k.clone() else { return Err(lex.err("expected a file name")) };
+    lex.bump()?;
+    lex.expect(";")?;
+    imports.push(((start..lex.last_end).into(), s));
+  }
+  Ok((imports, lex.tok.span.start))
+}
+
+/// A parsed item, with the names of its variables and the MM0 expressions in it.
+pub(crate) type ParsedItem = (Item, IdxVec<VarId, Spanned<Symbol>>, IdxVec<LambdaId, Mm0ExprNode>);
+
+/// Parse the next item of a `.mmc` file, or return `None` at the end of the file. The
+/// elaborator is used to resolve math formulas, so the imports should already be loaded, and
+/// the names are resolved in `compiler`, so the previous items should already be added to it.
+pub(crate) fn parse_item(
+  elab: &mut Elaborator, compiler: &mut mmcc::Compiler<Config>, lex: &mut Lexer<'_>,
+) -> Result<Option<ParsedItem>> {
+  if lex.tok.k == Tok::Eof { return Ok(None) }
+  let mut p = Parser {
+    lex: lex.clone(), elab, compiler,
+    ba: BuildAst::default(),
+    lambdas: IdxVec::default(),
+    in_arm: false,
+  };
+  let item = p.item(false, false)?;
+  *lex = p.lex;
+  Ok(Some((item, p.ba.var_names, p.lambdas)))
+}
+
+/// A label declared in a block, whose header and body are parsed after all the labels in its
+/// group have been declared, so that they can jump to each other.
+struct PLabel {
+  span: FileSpan,
+  name: Symbol,
+  /// The position of the argument list, if any.
+  args: Option<usize>,
+  /// The position of the body.
+  body: usize,
+}
+
+/// The state for converting a math formula to an MM0 expression.
+#[derive(Default)]
+struct Mm0 {
+  /// The program variables used in the formula.
+  subst: Vec<Expr>,
+  /// The index of each program variable in `subst`.
+  vars: HashMap<AtomId, u32>,
+  /// The bound variables in scope, which are not program variables.
+  dummies: Vec<AtomId>,
+}
+
+/// Push a struct field or a non-`mut` argument, as in the lisp `push_args`.
+fn push_field(out: &mut Vec<Arg>, span: FileSpan, attr: PArgAttr, pat: ArgKind) -> Result<()> {
+  if attr.out.is_some() { return Err(ElabError::new_e(&span, "'out' not expected here")) }
+  if attr.mut_ { return Err(ElabError::new_e(&span, "'mut' not expected here")) }
+  out.push(Spanned {span, k: (attr.into(), pat)});
+  Ok(())
+}
+
+struct Parser<'a, 'b> {
+  lex: Lexer<'a>,
+  elab: &'b mut Elaborator,
+  compiler: &'b mut mmcc::Compiler<Config>,
+  ba: BuildAst,
+  lambdas: IdxVec<LambdaId, Mm0ExprNode>,
+  /// True if we are directly inside a `match` arm, where `,` ends the arm rather
+  /// than separating the values of a `return`.
+  in_arm: bool,
+}
+
+impl<'a> Deref for Parser<'a, '_> {
+  type Target = Lexer<'a>;
+  fn deref(&self) -> &Lexer<'a> { &self.lex }
+}
+impl<'a> DerefMut for Parser<'a, '_> {
+  fn deref_mut(&mut self) -> &mut Lexer<'a> { &mut self.lex }
+}
+
+impl Resolve for Parser<'_, '_> {
+  /// The position of the argument in the source.
+  type Arg = usize;
+
+  fn ba(&mut self) -> &mut BuildAst { &mut self.ba }
+
+  fn names(&self) -> &HashMap<Symbol, Entity> { &self.compiler.names }
+
+  fn expr(&mut self, _: &FileSpan, &pos: &usize) -> Result<Expr> {
+    self.at(pos, |this| this.nested(Self::expr))
+  }
+
+  fn ty(&mut self, _: &FileSpan, &pos: &usize) -> Result<Type> {
+    self.at(pos, |this| this.nested(|this| this.ty_prec(true)))
+  }
+
+  fn label_name(&mut self, &pos: &usize) -> Option<Symbol> {
+    self.at(pos, |this| {
+      let sp = this.name()?;
+      Ok(this.symbol(sp))
+    }).ok()
+  }
+
+  fn tuple_pattern(&mut self, _: &FileSpan, pos: usize) -> Result<TuplePattern> {
+    self.at(pos, |this| this.nested(|this| this.typed_pattern(false)))
+  }
+
+  fn push_fields(&mut self, _: &FileSpan, pos: usize, out: &mut Vec<Arg>) -> Result<()> {
+    self.at(pos, |this| this.nested(|this|
+      this.param(false, &mut |span, attr, pat| push_field(out, span, attr, pat))))
+  }
+
+  fn mm0_expr(&mut self, base: &FileSpan, args: Vec<usize>) -> Result<Mm0Expr<Expr>> {
+    match *args {
+      [pos] => self.at(pos, |this| if this.tok.k == Tok::Formula {
+        this.formula()
+      } else {
+        Err(this.err("expected a math formula"))
+      }),
+      _ => Err(ElabError::new_e(base, "expected 1 argument")),
+    }
+  }
+}
+
+impl Parser<'_, '_> {
+  /// The span from `start` to the end of the last consumed token.
+  fn fspan(&self, start: usize) -> FileSpan { self.elab.fspan((start..self.last_end).into()) }
+
+  fn symbol(&self, sp: Span) -> Symbol { intern(&String::from_utf8_lossy(self.text(sp))) }
+
+  fn atom(&mut self, s: &[u8], sp: Span) -> LispVal {
+    LispVal::atom(self.elab.get_atom(s)).span(self.elab.fspan(sp))
+  }
+
+  /// Consume a name, returning it with its span.
+  fn name_sym(&mut self) -> Result<Spanned<Symbol>> {
+    let sp = self.name()?;
+    Ok(Spanned {span: self.elab.fspan(sp), k: self.symbol(sp)})
+  }
+
+  /// Run `f` on the source starting at position `pos`, and then return to the current position.
+  /// The thing parsed by `f` must end at a `,` or a closing bracket.
+  fn at<T>(&mut self, pos: usize, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+    self.at_raw(pos, |this| f(this).and_then(|r| match this.tok.k {
+      Tok::Punct("," | ")" | "]" | "}" | "=" | ";") => Ok(r),
+      _ => Err(this.err("expected ',' or ')'")),
+    }))
+  }
+
+  /// Run `f` on the source starting at position `pos`, and then return to the current position.
+  fn at_raw<T>(&mut self, pos: usize, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+    let lex = Lexer::new(self.src, pos)?;
+    let old = mem::replace(&mut self.lex, lex);
+    let r = f(self);
+    self.lex = old;
+    r
+  }
+
+  /// Run `f` inside a bracket, where commas are not special even in a `match` arm.
+  fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+    let old = mem::replace(&mut self.in_arm, false);
+    let r = f(self);
+    self.in_arm = old;
+    r
+  }
+
+  /// Parse `e (, e)* ,?` up to (and including) the closing token `close`.
+  fn comma_list<T>(&mut self, close: &str,
+    mut f: impl FnMut(&mut Self) -> Result<T>
+  ) -> Result<Vec<T>> {
+    self.nested(|this| {
+      let mut es = vec![];
+      while !this.is(close) {
+        es.push(f(this)?);
+        if !this.eat(",")? { break }
+      }
+      this.expect(close)?;
+      Ok(es)
+    })
+  }
+
+  /// Parse an item. `intrinsic` and `inline` are set if the item is preceded by these keywords.
+  fn item(&mut self, intrinsic: bool, inline: bool) -> Result<Item> {
+    let start = self.tok.span.start;
+    if self.eat_kw("intrinsic")? { return self.item(true, inline) }
+    if self.eat_kw("inline")? { return self.item(intrinsic, true) }
+    if self.is_kw("proc") || self.is_kw("func") { return self.proc(start, intrinsic, inline) }
+    if inline { return Err(self.err("inline: expecting a func or proc declaration")) }
+    if self.is_kw("const") || self.is_kw("global") { return self.decl(start, intrinsic) }
+    let is_struct = self.is_kw("struct");
+    if !is_struct && !self.is_kw("typedef") {
+      return Err(self.err("expected an item (proc, func, const, global, typedef or struct)"))
+    }
+    self.bump()?;
+    let name = self.name_sym()?;
+    let intrinsic = if intrinsic {
+      Some(get_intrinsic(&name.span, name.k, IntrinsicType::from_symbol)?)
+    } else { None };
+    self.tyargs()?;
+    let mut args = vec![];
+    if self.eat("(")? {
+      self.comma_list(")", |this|
+        this.param(false, &mut |span, attr, pat| push_field(&mut args, span, attr, pat)))?;
+    }
+    let tyargs = self.ba.num_tyvars();
+    let val = if is_struct {
+      let fields_start = self.tok.span.start;
+      self.expect("{")?;
+      let mut fields = vec![];
+      self.comma_list("}", |this|
+        this.param(false, &mut |span, attr, pat| push_field(&mut fields, span, attr, pat)))?;
+      Spanned {span: self.fspan(fields_start), k: TypeKind::Struct(fields.into())}
+    } else {
+      self.expect("=")?;
+      let ty = self.ty()?;
+      self.expect(";")?;
+      ty
+    };
+    let span = self.fspan(start);
+    Ok(Spanned {span, k: ItemKind::Typedef {intrinsic, name, tyargs, args: args.into(), val}})
+  }
+
+  /// Parse `const pat = e;` or `global pat = e;`.
+  fn decl(&mut self, start: usize, intrinsic: bool) -> Result<Item> {
+    let is_const = self.bump()?.span;
+    let is_const = self.text(is_const) == b"const";
+    let pat = self.tok.span.start;
+    let mut names = vec![];
+    self.skip_pattern(&mut names)?;
+    for sp in names {
+      let (span, name) = (self.elab.fspan(sp), self.symbol(sp));
+      if is_const {
+        self.compiler.forward_declare_const(&span, name)?
+      } else {
+        self.compiler.forward_declare_global(&span, name)?
+      }
+    }
+    self.skip_until(|l| l.is("="))?;
+    self.expect("=")?;
+    let rhs = self.expr()?;
+    self.expect(";")?;
+    let span = self.fspan(start);
+    let lhs = self.at(pat, |this| this.typed_pattern(false))?;
+    macro_rules! get_intrinsic {($f:ident) => {
+      if intrinsic {
+        if let Some((_, name, _)) = lhs.k.as_single_name() {
+          Some(get_intrinsic(&lhs.span, name, $f::from_symbol)?)
+        } else {
+          return Err(ElabError::new_e(&lhs.span, "pattern matching is not allowed in intrinsics"))
+        }
+      } else { None }
+    }}
+    let k = if is_const {
+      ItemKind::Const(get_intrinsic!(IntrinsicConst), lhs, rhs)
+    } else {
+      ItemKind::Global(get_intrinsic!(IntrinsicGlobal), lhs, rhs)
+    };
+    Ok(Spanned {span, k})
+  }
+
+  /// Parse the type arguments `<T, ...>` of a declaration, if present.
+  fn tyargs(&mut self) -> Result<()> {
+    if self.eat("<")? {
+      loop {
+        let name = self.name_sym()?;
+        self.ba.push_tyvar(&name);
+        if !self.eat(",")? { break }
+      }
+      self.expect(">")?;
+    }
+    Ok(())
+  }
+
+  /// Parse `proc name<T, ...>(params) -> rets variant e { body }`.
+  fn proc(&mut self, start: usize, intrinsic: bool, inline: bool) -> Result<Item> {
+    let func = self.is_kw("func");
+    self.bump()?;
+    let name = self.name_sym()?;
+    let kind = if func { ProcKind::Func }
+      else if name.k == Keyword::Main.as_symbol() { ProcKind::Main }
+      else { ProcKind::Proc };
+    let intrinsic = if intrinsic {
+      Some(get_intrinsic(&name.span, name.k, IntrinsicProc::from_symbol)?)
+    } else { None };
+    self.compiler.forward_declare_proc(&name.span, name.k)?;
+    self.tyargs()?;
+    let mut sig = ProcSig::default();
+    self.expect("(")?;
+    self.comma_list(")", |this|
+      this.param(false, &mut |span, attr, pat| sig.push_arg(span, attr, pat)))?;
+    let (args, outs, rets) = self.with_ctx(|this| {
+      if this.eat("->")? {
+        let mut push = |span, attr, pat| sig.push_ret(span, attr, pat);
+        if this.eat("(")? {
+          this.comma_list(")", |this| this.param(true, &mut push))?;
+        } else {
+          this.param(true, &mut push)?
+        }
+      }
+      sig.finish(&mut this.ba)
+    })?;
+    let tyargs = self.ba.num_tyvars();
+    if intrinsic.is_some() && !self.is(";") {
+      return Err(self.err("intrinsic: unexpected body"))
+    }
+    let variant = if self.is_kw("variant") { Some(self.variant()?) } else { None };
+    let body = if self.eat(";")? { Block {stmts: vec![], expr: None} } else { self.block()? };
+    Ok(Spanned {span: self.fspan(start), k: ItemKind::Proc {
+      intrinsic, inline, kind, name, tyargs, args, outs, rets, variant, body
+    }})
+  }
+
+  /// Parse a function argument, return, or struct field: `attrs... x: T` or `attrs... T`,
+  /// where the attributes are `ghost`, `mut`, `implicit`, `global`, and (if `ret` is true)
+  /// `out` or `out(name)`. The result is passed to `push`.
+  fn param(&mut self, ret: bool,
+    push: &mut impl FnMut(FileSpan, PArgAttr, ArgKind) -> Result<()>
+  ) -> Result<()> {
+    let start = self.tok.span.start;
+    let mut attr = PArgAttr::default();
+    let mut ghost = false;
+    loop {
+      if self.eat_kw("ghost")? { ghost = true }
+      else if self.eat_kw("mut")? { attr.mut_ = true }
+      else if self.eat_kw("implicit")? { attr.implicit = true }
+      else if self.eat_kw("global")? { attr.global = true }
+      else if ret && self.eat_kw("out")? {
+        attr.out = Some(if self.eat("(")? {
+          let name = self.name_sym()?.k;
+          self.expect(")")?;
+          name
+        } else { Symbol::UNDER })
+      } else { break }
+    }
+    let pat = if self.at_name_colon()? {
+      self.typed_pattern(ghost)?.k
+    } else if attr.global && self.tok.k == Tok::Ident &&
+      matches!(lex(self.src, self.tok.span.end)?.k, Tok::Punct("," | ")"))
+    {
+      let name = self.name_sym()?;
+      let v = self.ba.fresh_var(name.clone());
+      TuplePatternKind::Name(false, name.k, v)
+    } else {
+      let ty = self.ty()?;
+      let span = self.fspan(start);
+      let v = self.ba.fresh_var(Spanned { span: span.clone(), k: Symbol::UNDER });
+      let under = Box::new(Spanned {span, k: TuplePatternKind::Name(true, Symbol::UNDER, v)});
+      TuplePatternKind::Typed(under, Box::new(ty))
+    };
+    push(self.fspan(start), attr, ArgKind::Lam(pat))
+  }
+
+  /// Parse a pattern with an optional type ascription, `pat: T`. The type is parsed before
+  /// the names in the pattern are bound.
+  fn typed_pattern(&mut self, ghost: bool) -> Result<TuplePattern> {
+    let start = self.tok.span.start;
+    self.skip_pattern(&mut vec![])?;
+    if !self.eat(":")? { return self.at_raw(start, |this| this.pattern(ghost)) }
+    let ty = self.ty()?;
+    let pat = self.at_raw(start, |this| this.pattern(ghost))?;
+    let span = self.fspan(start);
+    Ok(Spanned {span, k: TuplePatternKind::Typed(Box::new(pat), Box::new(ty))})
+  }
+
+  /// Parse a binding pattern: a name, `_`, `ghost pat`, or a tuple `(pat, ...)`.
+  fn pattern(&mut self, ghost: bool) -> Result<TuplePattern> {
+    let start = self.tok.span.start;
+    if self.eat_kw("ghost")? { return self.pattern(true) }
+    let k = if self.eat("(")? {
+      let pats = self.comma_list(")", |this| {
+        let pat = this.typed_pattern(ghost)?;
+        let v = pat.k.as_single_name().map_or_else(|| {
+          this.ba.fresh_var(Spanned { span: pat.span.clone(), k: Symbol::UNDER })
+        }, |(_, _, v)| v);
+        Ok((v, pat))
+      })?;
+      TuplePatternKind::Tuple(pats.into())
+    } else {
+      let name = self.name_sym()?;
+      let v = self.ba.push_fresh(name.clone());
+      TuplePatternKind::Name(ghost || name.k == Symbol::UNDER, name.k, v)
+    };
+    Ok(Spanned {span: self.fspan(start), k})
+  }
+
+  /// Parse a type. Types share the expression grammar (so that `own(T)` and `sn(x + 1)`
+  /// parse the same way), but are read at the level of a unary expression, so that
+  /// `x: T = e` is not ambiguous.
+  fn ty(&mut self) -> Result<Type> { self.ty_prec(false) }
+
+  /// True if the current token is the name of a type constructor or a type variable.
+  fn is_type_name(&self) -> bool {
+    if self.tok.k != Tok::Ident { return false }
+    let name = self.symbol(self.tok.span);
+    match self.compiler.names.get(&name) {
+      Some(Entity::Prim(Prim {ty, ..})) => ty.is_some(),
+      Some(Entity::Type(_)) => true,
+      Some(_) => false,
+      None => self.ba.get_tyvar(name).is_some(),
+    }
+  }
+
+  /// Parse a type. A type which is not a type constructor or one of the special forms is a
+  /// proposition: an expression (a binary expression if `full` is true, otherwise a unary
+  /// expression) which is read as `(pure e)`.
+  fn ty_prec(&mut self, full: bool) -> Result<Type> {
+    let start = self.tok.span.start;
+    let k = match self.tok.k {
+      Tok::Punct("(") => {
+        self.bump()?;
+        if !self.eat(")")? {
+          let ty = self.nested(|this| this.ty_prec(true))?;
+          self.expect(")")?;
+          return Ok(ty)
+        }
+        TypeKind::Unit
+      }
+      Tok::Punct("[") => self.nested(|this| {
+        this.bump()?;
+        let mut tys = vec![];
+        if !this.is("]") {
+          let ty = this.ty_prec(true)?;
+          if this.eat(";")? {
+            let n = this.expr()?;
+            this.expect("]")?;
+            return Ok(TypeKind::Array(Box::new(ty), Box::new(n)))
+          }
+          tys.push(ty);
+          if this.eat(",")? { tys.extend(this.comma_list("]", |this| this.ty_prec(true))?); return Ok(TypeKind::List(tys.into())) }
+        }
+        this.expect("]")?;
+        Ok(TypeKind::List(tys.into()))
+      })?,
+      Tok::Punct("&") => {
+        let amp = self.bump()?.span;
+        // `&sn x` is the type of pointers to `x`
+        if self.is_kw("sn") && self.tok.span.start == amp.end {
+          self.bump()?;
+          TypeKind::RefSn(Box::new(self.with_ctx(Self::unary)?))
+        } else {
+          TypeKind::Shr(None, Box::new(self.ty()?))
+        }
+      }
+      Tok::Punct("?") => { self.bump()?; TypeKind::Uninit(Box::new(self.ty()?)) }
+      Tok::Ident if self.is_kw("_") => { self.bump()?; TypeKind::Infer }
+      Tok::Ident if self.is_kw("if") => {
+        self.bump()?;
+        let c = self.expr()?;
+        self.expect("{")?;
+        let t = self.nested(|this| this.ty_prec(true))?;
+        self.expect("}")?;
+        if !self.eat_kw("else")? { return Err(self.err("expected 'else'")) }
+        let e = if self.is_kw("if") { self.ty()? } else {
+          self.expect("{")?;
+          let e = self.nested(|this| this.ty_prec(true))?;
+          self.expect("}")?;
+          e
+        };
+        TypeKind::If(Box::new(c), Box::new(t), Box::new(e))
+      }
+      Tok::Ident if self.is_kw("match") => return self.parse_match(Self::ty),
+      Tok::Ident if self.is_type_name() => {
+        let sp = self.bump()?.span;
+        let (name, head_span) = (self.symbol(sp), self.elab.fspan(sp));
+        let args = if self.is("(") { self.call_args()?.0 } else { vec![] };
+        let span = self.fspan(start);
+        self.parse_ty_app(&span, &head_span, name, args)?
+          .ok_or_else(|| ElabError::new_e(&head_span, "expected a type"))?
+      }
+      _ => TypeKind::Pure(Box::new(if full { self.expr()? } else { self.with_ctx(Self::unary)? })),
+    };
+    Ok(Spanned {span: self.fspan(start), k})
+  }
+
+  /// Parse `variant e`, `variant e < bound` or `variant e <= bound`.
+  fn variant(&mut self) -> Result<Box<Variant>> {
+    let start = self.bump()?.span.start;
+    let e = self.with_ctx(|this| this.binary(PREC_BOR))?;
+    let vt = if self.eat("<")? {
+      VariantType::UpLt(self.with_ctx(|this| this.binary(PREC_BOR))?)
+    } else if self.eat("<=")? {
+      VariantType::UpLe(self.with_ctx(|this| this.binary(PREC_BOR))?)
+    } else {
+      VariantType::Down
+    };
+    Ok(Box::new(Spanned {span: self.fspan(start), k: (e, vt)}))
+  }
+
+  /// Parse the arguments `(a, b, variant v)` of a call, returning the positions of the
+  /// arguments and the variant. They are parsed later, as expressions or types depending on
+  /// the function.
+  fn call_args(&mut self) -> Result<(Vec<usize>, Option<usize>)> {
+    self.expect("(")?;
+    let mut args = vec![];
+    let mut variant = None;
+    while !self.is(")") {
+      if self.eat_kw("variant")? {
+        if variant.replace(self.tok.span.start).is_some() {
+          return Err(self.err("call: two variants"))
+        }
+      } else {
+        args.push(self.tok.span.start)
+      }
+      self.skip_until(|l| l.is(","))?;
+      if !self.eat(",")? { break }
+    }
+    self.expect(")")?;
+    Ok((args, variant))
+  }
+
+  /// Parse a block `{ stmts }`. If the last statement is an expression followed by `;`,
+  /// the block has value `()`.
+  fn block(&mut self) -> Result<Block> {
+    self.nested(|this| this.with_ctx(|this| {
+      let start = this.expect("{")?.start;
+      let mut stmts = vec![];
+      let mut jumps = vec![];
+      let mut unit = false;
+      while !this.eat("}")? {
+        if this.is_kw("label") {
+          let lab = this.label()?;
+          if jumps.iter().any(|l: &PLabel| l.name == lab.name) { this.push_jumps(&mut jumps, &mut stmts)? }
+          jumps.push(lab);
+          continue
+        }
+        this.push_jumps(&mut jumps, &mut stmts)?;
+        let (stmt, semi) = this.stmt()?;
+        stmts.push(stmt);
+        unit = semi;
+      }
+      if let Some(lab) = jumps.first() {
+        return Err(ElabError::new_e(&lab.span, "a labeled block is a statement, not an expression"))
+      }
+      let expr = if unit {
+        Some(Box::new(Spanned {span: this.fspan(start), k: ExprKind::Unit}))
+      } else if let Some(Spanned {k: StmtKind::Expr(_), ..}) = stmts.last() {
+        let Some(Spanned {span, k: StmtKind::Expr(expr)}) = stmts.pop() else { unreachable!() };
+        Some(Box::new(Spanned {span, k: expr}))
+      } else {
+        None
+      };
+      Ok(Block {stmts, expr})
+    }))
+  }
+
+  /// Parse a block as an expression.
+  fn block_expr(&mut self) -> Result<Expr> {
+    let start = self.tok.span.start;
+    let k = ExprKind::Block(self.block()?);
+    Ok(Spanned {span: self.fspan(start), k})
+  }
+
+  /// Parse the header of `label l(x: T) { .. }`, skipping the arguments and the body.
+  fn label(&mut self) -> Result<PLabel> {
+    let start = self.bump()?.span.start;
+    let name = self.name_sym()?.k;
+    let args = if self.is("(") {
+      let pos = self.tok.span.start;
+      self.skip_group("(", ")")?;
+      Some(pos)
+    } else { None };
+    let body = self.tok.span.start;
+    self.skip_group("{", "}")?;
+    Ok(PLabel {span: self.fspan(start), name, args, body})
+  }
+
+  /// Declare a group of labels and parse their arguments and bodies.
+  fn push_jumps(&mut self, jumps: &mut Vec<PLabel>, stmts: &mut Vec<Stmt>) -> Result<()> {
+    let Some(first) = jumps.first() else { return Ok(()) };
+    let span = first.span.clone();
+    let group = self.ba.push_label_group(span.clone(), jumps.iter().map(|j| j.name));
+    let labels = mem::take(jumps).into_iter().map(|PLabel {span, args: pos, body, ..}| {
+      self.with_ctx(|this| {
+        let mut args = vec![];
+        if let Some(pos) = pos {
+          this.at_raw(pos, |this| {
+            this.expect("(")?;
+            this.comma_list(")", |this|
+              this.param(false, &mut |span, attr, pat| push_field(&mut args, span, attr, pat)))
+          })?;
+        }
+        let body = this.at_raw(body, Self::block)?;
+        Ok(Label {args: args.into(), variant: None, body: Spanned {span, k: body}})
+      })
+    }).collect::<Result<_>>()?;
+    stmts.push(Spanned {span, k: StmtKind::Label(group, labels)});
+    Ok(())
+  }
+
+  /// Parse a statement. Returns the statement and whether it is an expression (with
+  /// a value that should be discarded) terminated by a `;`.
+  fn stmt(&mut self) -> Result<(Stmt, bool)> {
+    let start = self.tok.span.start;
+    if self.eat_kw("let")? {
+      // The pattern is bound after the value is parsed
+      let pat = self.tok.span.start;
+      self.skip_until(|l| l.is("="))?;
+      self.expect("=")?;
+      let rhs = self.expr()?;
+      let Renames {old, new} = self.renames()?;
+      let span = self.fspan(start);
+      self.expect(";")?;
+      self.ba.apply_rename(&old)?;
+      let lhs = self.at(pat, |this| this.typed_pattern(false))?;
+      self.ba.apply_rename(&new)?;
+      return Ok((Spanned {span, k: StmtKind::Let {lhs, rhs}}, false))
+    }
+    let diverges = ["return", "break", "continue", "unreachable"].iter().any(|kw| self.is_kw(kw));
+    let block_like = ["if", "while", "match", "map", "fold"].iter().any(|kw| self.is_kw(kw)) || self.is("{");
+    // As in Rust, a block-like statement ends at its closing brace, so that
+    // `while c { .. } (*p)[0] = x;` is not read as a call
+    if block_like {
+      let e = self.primary()?;
+      return Ok((e.map_into(StmtKind::Expr), self.eat(";")?))
+    }
+    let e = self.expr()?;
+    if self.eat("=")? {
+      let lhs = Box::new(e);
+      let rhs = Box::new(self.expr()?);
+      let with = self.renames()?;
+      let oldmap = self.ba.mk_oldmap(&lhs, with)?;
+      let span = self.fspan(start);
+      self.expect(";")?;
+      return Ok((Spanned {span, k: StmtKind::Expr(ExprKind::Assign {lhs, rhs, oldmap})}, false))
+    }
+    let stmt = e.map_into(StmtKind::Expr);
+    if self.eat(";")? { return Ok((stmt, !diverges)) }
+    if self.is("}") { return Ok((stmt, false)) }
+    Err(self.err("expected ';'"))
+  }
+
+  /// Parse an optional `with a -> a', b' <- b, c` clause after a `let` or assignment.
+  fn renames(&mut self) -> Result<Renames> {
+    let mut with = Renames::default();
+    if !self.eat_kw("with")? { return Ok(with) }
+    loop {
+      let a = self.name_sym()?;
+      if self.eat("->")? {
+        let b = self.name_sym()?;
+        with.old.push((a, b))
+      } else if self.eat("<-")? {
+        let b = self.name_sym()?;
+        with.new.push((b, a))
+      } else {
+        with.new.push((a.clone(), a))
+      }
+      if !self.eat(",")? { break }
+    }
+    Ok(with)
+  }
+
+  /// Parse an expression in a new scope.
+  fn expr(&mut self) -> Result<Expr> { self.with_ctx(|this| this.binary(1)) }
+
+  /// Parse a binary expression whose operators all have precedence at least `min`.
+  fn binary(&mut self, min: u8) -> Result<Expr> {
+    let start = self.tok.span.start;
+    let mut lhs = self.cast()?;
+    while let Tok::Punct(p) = self.tok.k {
+      let Some(&(op, prec)) = BINOPS.iter().find(|o| o.0 == p && o.1 >= min) else { break };
+      self.bump()?;
+      let rhs = self.binary(prec + 1)?;
+      let span = self.fspan(start);
+      let ba = &mut self.ba;
+      lhs = match op {
+        "||" => ba.mk_or(&span, vec![lhs, rhs]),
+        "&&" => ba.mk_and(&span, vec![lhs, rhs]),
+        "==" => ba.mk_eq(&span, vec![lhs, rhs]),
+        "!=" => ba.mk_ne(&span, vec![lhs, rhs]),
+        "<" => ba.mk_lt(&span, vec![lhs, rhs]),
+        "<=" => ba.mk_le(&span, vec![lhs, rhs]),
+        // `a > b` is `b < a`
+        ">" => ba.mk_lt(&span, vec![rhs, lhs]),
+        ">=" => ba.mk_le(&span, vec![rhs, lhs]),
+        "|" => ba.mk_bit_or(&span, vec![lhs, rhs]),
+        "^" => ba.mk_bit_xor(&span, vec![lhs, rhs]),
+        "&" => ba.mk_bit_and(&span, vec![lhs, rhs]),
+        "<<" => Spanned {span, k: ExprKind::Binop(Binop::Shl, Box::new(lhs), Box::new(rhs))},
+        ">>" => Spanned {span, k: ExprKind::Binop(Binop::Shr, Box::new(lhs), Box::new(rhs))},
+        "+" => ba.mk_add(&span, vec![lhs, rhs]),
+        "-" => ba.mk_sub(&span, vec![lhs, rhs]),
+        "*" => ba.mk_mul(&span, vec![lhs, rhs]),
+        _ => unreachable!(),
+      };
+    }
+    Ok(lhs)
+  }
+
+  /// Parse `e as T as T'...`.
+  fn cast(&mut self) -> Result<Expr> {
+    let start = self.tok.span.start;
+    let mut e = self.unary()?;
+    while self.eat_kw("as")? {
+      let ty = self.ty()?;
+      e = Spanned {span: self.fspan(start), k: ExprKind::As(Box::new(e), Box::new(ty))};
+    }
+    Ok(e)
+  }
+
+  /// Parse the prefix operators `-e`, `!e`, `~e`, `*e` and `&e`.
+  fn unary(&mut self) -> Result<Expr> {
+    let start = self.tok.span.start;
+    let Tok::Punct(op @ ("-" | "!" | "~" | "*" | "&")) = self.tok.k else { return self.postfix() };
+    self.bump()?;
+    let e = self.unary()?;
+    let span = self.fspan(start);
+    Ok(match op {
+      "!" => self.ba.mk_nor(&span, vec![e]),
+      "~" => self.ba.mk_bit_nor(&span, vec![e]),
+      _ => Spanned {span, k: match op {
+        "-" => ExprKind::Unop(Unop::Neg, Box::new(e)),
+        "*" => ExprKind::Deref(Box::new(e)),
+        _ => ExprKind::Borrow(Box::new(e)),
+      }},
+    })
+  }
+
+  /// Parse indexing `a[i]` and `a[i, h]`, and field access `x.f`.
+  fn postfix(&mut self) -> Result<Expr> {
+    let start = self.tok.span.start;
+    let mut e = self.primary()?;
+    loop {
+      let k = if self.is("(") {
+        return Err(self.err("only variables can be called like functions"))
+      } else if self.eat("[")? {
+        let mut es = self.comma_list("]", Self::expr)?.into_iter();
+        match (es.next(), es.next(), es.next()) {
+          (Some(i), None, _) => ExprKind::Index(Box::new(e), Box::new(i), None),
+          (Some(i), Some(h), None) => ExprKind::Index(Box::new(e), Box::new(i), Some(Box::new(h))),
+          _ => return Err(ElabError::new_e(&self.fspan(start), "expected 1 or 2 indices")),
+        }
+      } else if self.eat(".")? {
+        let sp = self.tok.span;
+        let field = match self.tok.k {
+          Tok::Ident => FieldName::Named(self.name_sym()?.k),
+          Tok::Num(ref n) => {
+            let n = n.try_into().map_err(|_| self.err("field access: index out of range"))?;
+            self.bump()?;
+            FieldName::Number(n)
+          }
+          _ => return Err(self.err("expected a field name")),
+        };
+        ExprKind::Proj(Box::new(e), Spanned {span: self.elab.fspan(sp), k: field})
+      } else {
+        return Ok(e)
+      };
+      e = Spanned {span: self.fspan(start), k};
+    }
+  }
+
+  /// Parse the arguments of `return`, `break` and `continue`, which are separated by commas.
+  fn jump_args(&mut self) -> Result<Vec<Expr>> {
+    let mut es = vec![];
+    if !matches!(self.tok.k, Tok::Punct(";" | "}" | ")" | "]" | ",") | Tok::Eof) {
+      loop {
+        es.push(self.expr()?);
+        if self.in_arm || !self.eat(",")? { break }
+      }
+    }
+    Ok(es)
+  }
+
+  fn primary(&mut self) -> Result<Expr> {
+    let start = self.tok.span.start;
+    let sp = self.tok.span;
+    let k = match self.tok.k.clone() {
+      Tok::Num(n) => { self.bump()?; ExprKind::Int(n) }
+      Tok::Str(s) => {
+        self.bump()?;
+        let fsp = self.elab.fspan(sp);
+        ExprKind::List(s.into_iter()
+          .map(|c| Spanned {span: fsp.clone(), k: ExprKind::Int(c.into())}).collect())
+      }
+      Tok::Formula => ExprKind::Mm0(self.formula()?),
+      Tok::Ident => {
+        if self.eat_kw("true")? { return Ok(Spanned {span: self.fspan(start), k: ExprKind::Bool(true)}) }
+        if self.eat_kw("false")? { return Ok(Spanned {span: self.fspan(start), k: ExprKind::Bool(false)}) }
+        if self.is_kw("if") { return self.if_expr() }
+        if self.is_kw("while") { return self.while_expr() }
+        if self.is_kw("map") || self.is_kw("fold") { return self.map_fold_expr() }
+        if self.is_kw("match") { return self.parse_match(Self::expr) }
+        if self.eat_kw("return")? { ExprKind::Return(self.jump_args()?) }
+        else if self.is_kw("break") || self.is_kw("continue") {
+          let brk = self.is_kw("break");
+          self.bump()?;
+          // An optional label, followed by the arguments
+          let lab = match self.tok.k {
+            Tok::Ident => self.ba.get_label(self.symbol(self.tok.span)),
+            _ => None,
+          };
+          let lab = if let Some(lab) = lab { self.bump()?; self.eat(",")?; lab } else {
+            self.ba.get_loop_label().ok_or_else(||
+              ElabError::new_e(&self.fspan(start), "can't break, not in a loop"))?
+          };
+          let args = self.jump_args()?;
+          let span = self.fspan(start);
+          if brk {
+            self.ba.mark_label_break(lab.0);
+            ExprKind::Break(lab.0, Box::new(Expr::list(&span, args)))
+          } else {
+            ExprKind::Jump(lab, args, None)
+          }
+        } else if self.eat_kw("_")? {
+          ExprKind::Infer(true)
+        } else {
+          if RESERVED.iter().any(|r| r.as_bytes() == self.text(sp)) {
+            return Err(self.err("expected an expression"))
+          }
+          self.bump()?;
+          let (f, fsp) = (self.symbol(sp), self.elab.fspan(sp));
+          if self.is("(") {
+            let (args, variant) = self.call_args()?;
+            return self.parse_call(self.fspan(start), fsp, f, args, variant)
+          }
+          if let Some(v) = self.ba.get_var(f) { ExprKind::Var(v) } else {
+            return self.parse_call(fsp.clone(), fsp.clone(), f, vec![], None).map_err(|_|
+              ElabError::new_e(&fsp, format!("unknown variable '{f}'")))
+          }
+        }
+      }
+      Tok::Punct("(") => self.nested(|this| {
+        this.bump()?;
+        if this.eat(")")? { return Ok(ExprKind::Unit) }
+        let e = this.expr()?;
+        if this.eat(":")? {
+          let ty = this.ty()?;
+          this.expect(")")?;
+          return Ok(ExprKind::Typed(Box::new(e), Box::new(ty)))
+        }
+        this.expect(")")?;
+        Ok(e.k)
+      })?,
+      Tok::Punct("[") => { self.bump()?; ExprKind::List(self.comma_list("]", Self::expr)?) }
+      Tok::Punct("{") => return self.block_expr(),
+      _ => return Err(self.err("expected an expression")),
+    };
+    Ok(Spanned {span: self.fspan(start), k})
+  }
+
+  /// Parse a condition `c` or `h: c` for `if` and `while`.
+  fn cond(&mut self) -> Result<(Option<Spanned<Symbol>>, Expr)> {
+    let hyp = if self.at_name_colon()? {
+      let h = self.name_sym()?;
+      self.bump()?;
+      Some(h)
+    } else { None };
+    Ok((hyp, self.expr()?))
+  }
+
+  /// Parse `if c { .. } else if c2 { .. } else { .. }`.
+  fn if_expr(&mut self) -> Result<Expr> {
+    let start = self.bump()?.span.start;
+    let (hyp, cond) = self.cond()?;
+    let cond = Box::new(cond);
+    let (hyp, then) = match hyp {
+      Some(h) if h.k != Symbol::UNDER => {
+        let (h1, then) = self.with_ctx(|this| -> Result<_> {
+          let h1 = this.ba.push_fresh_span(h.clone());
+          Ok((h1, this.block_expr()?))
+        })?;
+        (Some([h1, self.ba.push_fresh_span(h)]), then)
+      }
+      _ => (None, self.block_expr()?),
+    };
+    let els = if self.eat_kw("else")? {
+      if self.is_kw("if") { self.if_expr()? } else { self.block_expr()? }
+    } else {
+      Spanned {span: self.fspan(start), k: ExprKind::Unit}
+    };
+    let k = ExprKind::If {ik: IfKind::If, hyp, cond, then: Box::new(then), els: Box::new(els)};
+    Ok(Spanned {span: self.fspan(start), k})
+  }
+
+  /// Parse `while c variant v mut x, y { .. }`.
+  fn while_expr(&mut self) -> Result<Expr> {
+    let start = self.bump()?.span.start;
+    // The condition is parsed after the loop is set up
+    let cond_pos = self.tok.span.start;
+    self.skip_until(|l| l.is("{") || l.is_kw("variant") || l.is_kw("mut"))?;
+    let var = if self.is_kw("variant") { Some(self.variant()?) } else { None };
+    let mut muts = vec![];
+    if self.eat_kw("mut")? {
+      loop {
+        let name = self.name_sym()?;
+        let v = self.ba.get_var(name.k).ok_or_else(||
+          ElabError::new_e(&name.span, format!("unknown variable '{}'", name.k)))?;
+        if muts.contains(&v) { return Err(ElabError::new_e(&name.span, "duplicate mut")) }
+        muts.push(v);
+        if !self.eat(",")? { break }
+      }
+    }
+    let span = {
+      let mut lex = self.lex.clone();
+      lex.skip_group("{", "}")?;
+      self.elab.fspan((start..lex.last_end).into())
+    };
+    let mk = self.ba.build_while(span.clone(), muts.into());
+    let (hyp, cond) = self.at_raw(cond_pos, Self::cond)?;
+    let (hyp, body) = self.with_ctx(|this| -> Result<_> { Ok((
+      hyp.map(|h| this.ba.push_fresh_span(h)),
+      Box::new(this.block()?),
+    ))})?;
+    let k = mk.finish(&mut self.ba, var, Box::new(cond), hyp, body);
+    Ok(Spanned {span, k})
+  }
+
+  /// Parse `map h: i, x in a, y in b { .. }` or `fold i, acc = init, x in a { .. }`, where the
+  /// index `i` (or `h: i`) is optional.
+  fn map_fold_expr(&mut self) -> Result<Expr> {
+    let fold = self.is_kw("fold");
+    let start = self.bump()?.span.start;
+    let name = if fold {"fold"} else {"map"};
+    // An optional leading index binder `i` or `h: i`
+    let mut idx = None;
+    let mut hyp = None;
+    if self.at_name_colon()? {
+      hyp = Some(self.name_sym()?);
+      self.bump()?;
+      idx = Some(self.name_sym()?);
+      self.expect(",")?;
+    } else if self.tok.k == Tok::Ident && matches!(lex(self.src, self.tok.span.end)?.k, Tok::Punct(",")) {
+      idx = Some(self.name_sym()?);
+      self.bump()?;
+    }
+    // The bindings `x in a`, where the first one is the accumulator `acc = init` for `fold`
+    let mut binds = vec![];
+    loop {
+      let x = self.name_sym()?;
+      if !self.eat("=")? && !self.eat_kw("in")? { return Err(self.err("expected '=' or 'in'")) }
+      binds.push((x, self.expr()?));
+      if !self.eat(",")? { break }
+    }
+    let mut binds = binds.into_iter();
+    let err = |this: &Self, msg: &str| ElabError::new_e(&this.fspan(start), format!("{name}: {msg}"));
+    let acc = if fold {
+      Some(binds.next().ok_or_else(|| err(self, "expected an accumulator `acc = init`"))?)
+    } else { None };
+    if binds.len() == 0 { return Err(err(self, "expected at least one array `x in a`")) }
+    let k = self.with_ctx(|this| -> Result<_> {
+      let hyp = hyp.map(|h| this.ba.push_fresh_span(h));
+      let idx = idx.map(|i| this.ba.push_fresh_span(i));
+      let acc = acc.map(|(x, init)| (this.ba.push_fresh_span(x), Box::new(init)));
+      let arrays = binds.map(|(x, a)| (this.ba.push_fresh_span(x), a)).collect();
+      let body = Box::new(this.block_expr()?);
+      Ok(ExprKind::MapFold(Box::new(ast::MapFold {idx, hyp, acc, arrays, body})))
+    })?;
+    Ok(Spanned {span: self.fspan(start), k})
+  }
+
+  /// Parse `match e { pat => e, pat if guard => e, ... }`, where the right hand sides are
+  /// parsed by `f`.
+  fn parse_match<T: BuildMatch>(&mut self,
+    mut f: impl FnMut(&mut Self) -> Result<Spanned<T>>
+  ) -> Result<Spanned<T>> {
+    let start = self.bump()?.span.start;
+    let c = self.expr()?;
+    let span = {
+      let mut lex = self.lex.clone();
+      lex.skip_group("{", "}")?;
+      self.elab.fspan((start..lex.last_end).into())
+    };
+    let mut mb = self.ba.build_match(span, c);
+    self.expect("{")?;
+    self.nested(|this| this.with_ctx(|this| {
+      while !this.eat("}")? {
+        let arm_start = this.tok.span.start;
+        let mut lex = this.lex.clone();
+        lex.skip_until(|l| l.is("=>") || l.is_kw("if"))?;
+        let guard = lex.is_kw("if");
+        lex.skip_until(|l| l.is("=>"))?;
+        let span = this.elab.fspan((arm_start..lex.last_end).into());
+        let mut pb = mb.branch(&span, &mut this.ba).map_err(|UnreachablePattern|
+          ElabError::new_e(&span, "unreachable pattern"))?;
+        if guard { pb.with(&span) }
+        let mut pat = this.match_pattern(&mut pb)?;
+        if guard {
+          this.bump()?;
+          let e = this.expr()?;
+          pat = pat.with(&span, e);
+        }
+        this.expect("=>")?;
+        let old = mem::replace(&mut this.in_arm, true);
+        let rhs = this.with_ctx(|this| {
+          pb.prepare_rhs(&mut this.ba);
+          f(this)
+        });
+        this.in_arm = old;
+        pb.finish(&span, pat, rhs?, &mut mb);
+        if !this.eat(",")? && !this.is("}") && !matches!(this.src.get(this.last_end - 1), Some(b'}')) {
+          return Err(this.err("expected ',' or '}'"))
+        }
+      }
+      mb.finish().map_err(|Incomplete(c)| ElabError::new_e(&c.span, "unreachable pattern"))
+    }))
+  }
+
+  /// The span from the current token to the end of the match pattern, which ends at one of the
+  /// tokens `stop`, `=>` or `if`.
+  fn pattern_span(&self, stop: &str) -> Result<FileSpan> {
+    let mut lex = self.lex.clone();
+    lex.skip_until(|l| l.is(stop) || l.is("=>") || l.is_kw("if"))?;
+    Ok(self.elab.fspan((self.tok.span.start..lex.last_end).into()))
+  }
+
+  /// Parse a match pattern: `p | p`, `h: p`, a number, a constant, a variable or `_`.
+  fn match_pattern<T: BuildMatch>(&mut self, pb: &mut PatternBuilder<T>) -> Result<Pattern> {
+    let span = self.pattern_span(")")?;
+    let mut lex = self.lex.clone();
+    lex.skip_until(|l| l.is("|") || l.is("=>") || l.is_kw("if"))?;
+    if !lex.is("|") { return self.match_pattern1(pb) }
+    let mut orb = pb.or(&span);
+    loop {
+      orb.send(pb);
+      orb.recv(self.match_pattern1(pb)?);
+      if !self.eat("|")? { break }
+    }
+    Ok(orb.finish())
+  }
+
+  fn match_pattern1<T: BuildMatch>(&mut self, pb: &mut PatternBuilder<T>) -> Result<Pattern> {
+    let start = self.tok.span.start;
+    if self.at_name_colon()? {
+      let span = self.pattern_span("|")?;
+      let h = self.name_sym()?;
+      self.bump()?;
+      pb.hyped(&span, h.clone(), &mut self.ba).map_err(|BadBinding|
+        ElabError::new_e(&h.span, "can't bind variables in this context"))?;
+      return Ok(self.match_pattern1(pb)?.hyped(&span))
+    }
+    if self.eat("(")? {
+      let p = self.nested(|this| this.match_pattern(pb))?;
+      self.expect(")")?;
+      return Ok(p)
+    }
+    let neg = self.eat("-")?;
+    match self.tok.k.clone() {
+      Tok::Num(n) => {
+        self.bump()?;
+        let span = self.fspan(start);
+        Ok(pb.const_(&span, Spanned {span: span.clone(), k: ExprKind::Int(if neg { -n } else { n })}))
+      }
+      _ if neg => Err(self.err("expected a number")),
+      _ => {
+        let name = self.name_sym()?;
+        if name.k == Symbol::UNDER { return Ok(pb.ignore()) }
+        if matches!(self.compiler.names.get(&name.k), Some(Entity::Const(_))) {
+          Ok(pb.const_(&name.span, Spanned {span: name.span.clone(), k: ExprKind::Const(name.k)}))
+        } else {
+          pb.var(name.k, &mut self.ba).map_err(|BadBinding|
+            ElabError::new_e(&name.span, "can't bind variables in this context"))
+        }
+      }
+    }
+  }
+
+  /// Parse a math formula `$ e $` as an MM0 expression. The program variables in scope can be
+  /// used in the formula; the other names are the term constructors and bound variables of
+  /// the imported theories.
+  fn formula(&mut self) -> Result<Mm0Expr<Expr>> {
+    let sp = self.bump()?.span;
+    let q = self.elab.parse_formula(Formula(sp))?;
+    let mut mm0 = Mm0::default();
+    let expr = self.mm0_node(&mut mm0, &q)?;
+    Ok(Mm0Expr {subst: mm0.subst, expr: self.lambdas.push(expr)})
+  }
+
+  fn mm0_node(&mut self, mm0: &mut Mm0, e: &QExpr) -> Result<Mm0ExprNode> {
+    Ok(match self.mm0_node_opt(mm0, e)? {
+      Some(n) => n,
+      None => Mm0ExprNode::Const(self.qexpr(e)?),
+    })
+  }
+
+  /// Convert a math expression to an MM0 expression node, returning `None` if it is a constant
+  /// (does not contain any program variables).
+  fn mm0_node_opt(&mut self, mm0: &mut Mm0, e: &QExpr) -> Result<Option<Mm0ExprNode>> {
+    match e.k {
+      QExprKind::IdentApp(sp, ref es) => {
+        let a = { let s = self.text(sp); self.elab.get_atom(s) };
+        if es.is_empty() {
+          if mm0.dummies.contains(&a) { return Ok(None) }
+          if let Some(&n) = mm0.vars.get(&a) { return Ok(Some(Mm0ExprNode::Var(n))) }
+          if let Some(v) = self.ba.get_var(self.symbol(sp)) {
+            let n = mm0.subst.len().try_into().expect("overflow");
+            mm0.vars.insert(a, n);
+            mm0.subst.push(Spanned {span: self.elab.fspan(sp), k: ExprKind::Var(v)});
+            return Ok(Some(Mm0ExprNode::Var(n)))
+          }
+        }
+        let t = self.elab.term(a).ok_or_else(|| ElabError::new_e(sp,
+          format!("term '{}' not declared", self.elab.data[a].name)))?;
+        self.mm0_app(mm0, e.span, t, es)
+      }
+      QExprKind::App(_, t, ref es) => self.mm0_app(mm0, e.span, t, es),
+      QExprKind::Unquote(_) =>
+        Err(ElabError::new_e(e.span, "antiquotation is not supported in .mmc files")),
+    }
+  }
+
+  fn mm0_app(&mut self,
+    mm0: &mut Mm0, sp: Span, t: mm0_util::TermId, es: &[QExpr]
+  ) -> Result<Option<Mm0ExprNode>> {
+    let nargs = self.elab.terms[t].args.len();
+    if es.len() != nargs {
+      return Err(ElabError::new_e(sp, format!("expected {nargs} arguments")))
+    }
+    let mut cnst = true;
+    let mut args = Vec::with_capacity(nargs);
+    let len = mm0.dummies.len();
+    for (i, e) in es.iter().enumerate() {
+      if let EType::Bound(_) = self.elab.terms[t].args[i].1 {
+        let QExprKind::IdentApp(x, ref xs) = e.k else {
+          return Err(ElabError::new_e(e.span, "expected a variable"))
+        };
+        if !xs.is_empty() { return Err(ElabError::new_e(e.span, "expected a variable")) }
+        let x = { let s = self.text(x); self.elab.get_atom(s) };
+        mm0.dummies.push(x);
+        args.push(Mm0ExprNode::Const(self.qexpr(e)?))
+      } else {
+        let n = self.mm0_node(mm0, e)?;
+        cnst &= matches!(n, Mm0ExprNode::Const(_));
+        args.push(n)
+      }
+    }
+    mm0.dummies.truncate(len);
+    Ok(if cnst { None } else { Some(Mm0ExprNode::Expr(t, args)) })
+  }
+
+  /// Convert a parsed math formula to the lisp representation, as in a quotation `'$ e $`.
+  fn qexpr(&mut self, e: &QExpr) -> Result<LispVal> {
+    let fsp = self.elab.fspan(e.span);
+    Ok(match e.k {
+      QExprKind::IdentApp(sp, ref es) => {
+        let head = self.atom(self.text(sp), sp);
+        if es.is_empty() { return Ok(head) }
+        let mut args = vec![head];
+        for e in &**es { args.push(self.qexpr(e)?) }
+        LispVal::list(args).span(fsp)
+      }
+      QExprKind::App(sp, t, ref es) => {
+        let mut args = vec![LispVal::atom(self.elab.terms[t].atom).span(self.elab.fspan(sp))];
+        for e in &**es { args.push(self.qexpr(e)?) }
+        LispVal::list(args).span(fsp)
+      }
+      QExprKind::Unquote(_) =>
+        return Err(ElabError::new_e(e.span, "antiquotation is not supported in .mmc files")),
+    })
+  }
+}
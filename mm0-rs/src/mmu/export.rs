@@ -392,7 +392,8 @@ impl FrozenEnv {
           }
         }
         StmtTrace::Global(_) => {}
-        StmtTrace::OutputString(_) => writeln!(w, "(output string)\n")?
+        StmtTrace::OutputString(_) => writeln!(w, "(output string)\n")?,
+        StmtTrace::InputString(_) => writeln!(w, "(input string)\n")?,
       }
     }
     Ok(())
@@ -24,9 +24,9 @@ use mm1_parser::{Ast, parse};
 use crate::mmb::import::elab as mmb_elab;
 use crate::mmu::import::elab as mmu_elab;
 use crate::compiler::FileContents;
-use crate::{ObjectKind, DeclKey, StmtTrace, AtomId, SortId, TermId, ThmId, LinedString, FrozenEnv,
+use crate::{ObjectKind, DeclKey, StmtTrace, AtomId, SortId, TermId, ThmId, LinedString, Environment, FrozenEnv,
   FrozenLispKind, FrozenAtomData};
-use crate::elab::{ElabResult, ElaborateBuilder, GoalListener,
+use crate::elab::{ElabResult, ElaborateBuilder, FileReader, GoalListener,
   local_context::InferSort, proof::Subst,
   lisp::{print::FormatEnv, pretty::Pretty, Syntax, LispKind, Proc, BuiltinProc},
   spans::Spans};
@@ -207,6 +207,7 @@ async fn elaborate(path: FileRef, start: Option<Position>,
   let source = text.clone();
 
   let mut deps = Vec::new();
+  let reads = Arc::new(Mutex::new(Vec::new()));
   let (ast, (cyc, toks, errors, env)) = if path.has_extension("mmb") {
     let (error, env) = mmb_elab(&path, &text);
     let errors = if let Err(e) = error { vec![e] } else { vec![] };
@@ -215,6 +216,10 @@ async fn elaborate(path: FileRef, start: Option<Position>,
     let (error, env) = mmu_elab(&path, &text);
     let errors = if let Err(e) = error { vec![e] } else { vec![] };
     (None, (None, vec![], errors, FrozenEnv::new(env)))
+  } else if !path.has_extension("mm0") && !path.has_extension("mm1") {
+    // A data file, read by `read-file`. There is nothing to elaborate, but going through
+    // the cache lets edits to it propagate to the files that read it.
+    (None, (None, vec![], vec![], FrozenEnv::new(Environment::new())))
   } else {
     let (idx, ast) = parse(text.ascii().clone(), old_ast);
     let ast = Arc::new(ast);
@@ -253,10 +258,31 @@ async fn elaborate(path: FileRef, start: Option<Position>,
             }
           })
         }),
+      debug_hook: None,
+      file_reader: Some(FileReader::new({
+        let (path, rd, reads) = (path.clone(), rd.clone(), reads.clone());
+        move |p| {
+          let (p, dep) = SERVER.vfs.get_or_insert(p.clone())?;
+          let (version, text) = dep.text.ulock().clone();
+          let text = text.try_ascii().ok_or_else(||
+            io::Error::new(io::ErrorKind::InvalidData, "expected a text file"))?;
+          if matches!(dep.parsed.try_lock().as_deref(), Some(None)) {
+            let (send, _) = channel();
+            Job::ElaborateDep(p.clone(), path.clone(), Some((send, rd.clone()))).spawn();
+          }
+          let mut hasher = DefaultHasher::new();
+          version.hash(&mut hasher);
+          reads.ulock().push((p, hasher.finish()));
+          Ok(ArcString::from(text.as_bytes()))
+        }
+      })),
     }.elab();
     (Some(ast.clone()), elab.await)
   };
   for tok in toks {tok.hash(&mut hasher)}
+  for (p, tok) in reads.ulock().drain(..) {
+    if !deps.contains(&p) { deps.push(p); tok.hash(&mut hasher) }
+  }
   let hash = hasher.finish();
   let is_canceled = cancel.load(Ordering::SeqCst);
   log!("elabbed {:?}{}", path, if is_canceled {" (canceled)"} else {""});
@@ -1054,7 +1080,9 @@ async fn document_symbol(path: FileRef) -> Result<Option<DocumentSymbolResponse>
                 FrozenLispKind::Syntax(_) => SymbolKind::EVENT,
                 FrozenLispKind::Undef => return None,
                 FrozenLispKind::Proc(_) => SymbolKind::FUNCTION,
+                FrozenLispKind::Vector(_) => SymbolKind::ARRAY,
                 FrozenLispKind::AtomMap(_) |
+                FrozenLispKind::StrMap(_) |
                 FrozenLispKind::Annot(_, _) |
                 FrozenLispKind::Ref(_) => SymbolKind::OBJECT,
               }))() {
@@ -1064,7 +1092,7 @@ async fn document_symbol(path: FileRef) -> Result<Option<DocumentSymbolResponse>
           }
         }
       }
-      StmtTrace::OutputString(_) => {}
+      StmtTrace::OutputString(_) | StmtTrace::InputString(_) => {}
     }
   }
   Ok(Some(DocumentSymbolResponse::Nested(res)))
@@ -1106,6 +1134,8 @@ fn make_completion_item(path: &FileRef, fe: FormatEnv<'_>, ad: &FrozenAtomData,
       }
     }),
     TraceKind::Global => {
+      // private definitions of imported modules cannot be referred to by name
+      if ad.name().contains(&b'#') { return None }
       let e = ad.lisp().as_ref()?;
       // Safety: We only use the expression for printing and don't Rc::clone it
       Some(done!(format!("{}", fe.to(unsafe { e.thaw() })), match *e.unwrap() {
@@ -1119,6 +1149,8 @@ fn make_completion_item(path: &FileRef, fe: FormatEnv<'_>, ad: &FrozenAtomData,
         FrozenLispKind::String(_) |
         FrozenLispKind::Bool(_) |
         FrozenLispKind::AtomMap(_) |
+        FrozenLispKind::StrMap(_) |
+        FrozenLispKind::Vector(_) |
         FrozenLispKind::Annot(_, _) |
         FrozenLispKind::Ref(_) => CompletionItemKind::VALUE,
         FrozenLispKind::Syntax(_) => CompletionItemKind::EVENT,
@@ -1,4 +1,5 @@
-import { commands, window, workspace, ExtensionContext, TextDocument, EndOfLine } from 'vscode';
+import { commands, debug, window, workspace, ExtensionContext, TextDocument, EndOfLine,
+	DebugAdapterDescriptor, DebugAdapterExecutable } from 'vscode';
 
 import {
 	LanguageClient,
@@ -36,6 +37,13 @@ function startClient() {
 	client.start();
 }
 
+// The lisp debugger is the `mm0-rs debug` subcommand, speaking DAP over stdio
+function createDebugAdapter(): DebugAdapterDescriptor {
+	let config = workspace.getConfiguration('metamath-zero');
+	let mm0Path: string = config.get('executablePath') || 'mm0-rs';
+	return new DebugAdapterExecutable(mm0Path, ['debug']);
+}
+
 export function activate(context: ExtensionContext) {
 	startClient();
 
@@ -56,7 +64,9 @@ export function activate(context: ExtensionContext) {
 		commands.registerCommand('metamath-zero.shutdownServer',
 		  () => client.stop().then(() => {}, () => {})),
 		commands.registerCommand('metamath-zero.restartServer',
-			() => client.stop().then(startClient, startClient))
+			() => client.stop().then(startClient, startClient)),
+		debug.registerDebugAdapterDescriptorFactory('metamath-zero',
+			{ createDebugAdapterDescriptor: createDebugAdapter })
 	);
 }
 